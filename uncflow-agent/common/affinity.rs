@@ -1,19 +1,114 @@
+// CPU affinity for MSR access and for keeping the agent's own threads off measured cores
+//
+// Housekeeping cores and AffinityGuard compose: runtime threads stay on the housekeeping
+// cores, and only the per-CPU MSR workers (see msr.rs) hold a guard pinning them to their
+// target CPU, restoring the inherited housekeeping mask if a worker exits.
+//
+// Where seccomp blocks sched_setaffinity, pinning can be turned off (--no-msr-pinning or
+// UNCFLOW_NO_MSR_PINNING). The msr device still addresses the CPU it belongs to; the
+// accessing thread then just runs wherever the scheduler puts it.
+
+use nix::errno::Errno;
 use nix::sched::{sched_getaffinity, sched_setaffinity, CpuSet};
 use nix::unistd::Pid;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
 
 use crate::error::{Result, UncflowError};
 
+/// Cores the agent's own threads are confined to, e.g. `--housekeeping-cores 0-1,16`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoreList(Vec<usize>);
+
+impl CoreList {
+    pub fn cores(&self) -> &[usize] {
+        &self.0
+    }
+
+    /// Restrict the calling thread to these cores
+    pub fn pin_current_thread(&self) -> Result<()> {
+        let mut set = CpuSet::new();
+        for &core in &self.0 {
+            set.set(core).map_err(|e| {
+                UncflowError::AffinityError(format!("Failed to set CPU {core} in set: {e}"))
+            })?;
+        }
+
+        sched_setaffinity(Pid::from_raw(0), &set).map_err(|e| {
+            UncflowError::AffinityError(format!("Failed to pin thread to cores {:?}: {e}", self.0))
+        })
+    }
+}
+
+impl FromStr for CoreList {
+    type Err = UncflowError;
+
+    /// Parse a list like `0-3,8`, in the format of /sys/devices/system/cpu/online
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid =
+            |part: &str| UncflowError::ParseError(format!("Invalid core '{part}' in '{s}'"));
+
+        let mut cores = Vec::new();
+        for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            if let Some((start, end)) = part.split_once('-') {
+                let start: usize = start.trim().parse().map_err(|_| invalid(part))?;
+                let end: usize = end.trim().parse().map_err(|_| invalid(part))?;
+                if start > end {
+                    return Err(invalid(part));
+                }
+                cores.extend(start..=end);
+            } else {
+                cores.push(part.parse().map_err(|_| invalid(part))?);
+            }
+        }
+        if cores.is_empty() {
+            return Err(UncflowError::ParseError(format!("No cores in '{s}'")));
+        }
+
+        cores.sort_unstable();
+        cores.dedup();
+        Ok(Self(cores))
+    }
+}
+
+/// Environment variable that turns off pinning around MSR accesses when set
+pub const NO_MSR_PINNING_ENV: &str = "UNCFLOW_NO_MSR_PINNING";
+
+static MSR_PINNING: AtomicBool = AtomicBool::new(true);
+static PINNING_DENIED: Once = Once::new();
+
+/// Turn pinning the accessing thread around MSR accesses on or off, process-wide
+pub fn set_msr_pinning(enabled: bool) {
+    MSR_PINNING.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether MSR accesses pin the accessing thread to the target CPU
+pub fn msr_pinning() -> bool {
+    MSR_PINNING.load(Ordering::Relaxed)
+}
+
+/// Pins the calling thread to one CPU and restores its previous affinity on drop
 pub struct AffinityGuard {
-    old_affinity: CpuSet,
+    // None when pinning is off
+    old_affinity: Option<CpuSet>,
 }
 
 impl AffinityGuard {
     pub fn new(cpu: i32) -> Result<Self> {
+        Self::with_pinning(cpu, msr_pinning())
+    }
+
+    /// Pin to `cpu`, or leave the thread's affinity alone unless `pinning`
+    pub fn with_pinning(cpu: i32, pinning: bool) -> Result<Self> {
         if cpu < 0 {
             return Err(UncflowError::AffinityError(format!(
                 "Invalid CPU ID: {cpu}"
             )));
         }
+        if !pinning {
+            return Ok(Self { old_affinity: None });
+        }
 
         let old_affinity = sched_getaffinity(Pid::from_raw(0))
             .map_err(|e| UncflowError::AffinityError(format!("Failed to get affinity: {e}")))?;
@@ -24,16 +119,29 @@ impl AffinityGuard {
         })?;
 
         sched_setaffinity(Pid::from_raw(0), &new_affinity).map_err(|e| {
+            if e == Errno::EPERM {
+                PINNING_DENIED.call_once(|| {
+                    tracing::warn!(
+                        "sched_setaffinity is not permitted (blocked by seccomp?), no MSR \
+                         handle can be opened; run with --no-msr-pinning or {}=1 to skip pinning",
+                        NO_MSR_PINNING_ENV
+                    );
+                });
+            }
             UncflowError::AffinityError(format!("Failed to set affinity to CPU {cpu}: {e}"))
         })?;
 
-        Ok(Self { old_affinity })
+        Ok(Self {
+            old_affinity: Some(old_affinity),
+        })
     }
 }
 
 impl Drop for AffinityGuard {
     fn drop(&mut self) {
-        let _ = sched_setaffinity(Pid::from_raw(0), &self.old_affinity);
+        if let Some(old_affinity) = &self.old_affinity {
+            let _ = sched_setaffinity(Pid::from_raw(0), old_affinity);
+        }
     }
 }
 
@@ -46,4 +154,52 @@ mod tests {
         let result = AffinityGuard::new(0);
         assert!(result.is_ok() || result.is_err());
     }
+
+    #[test]
+    fn test_guard_without_pinning_is_a_no_op() {
+        let before = sched_getaffinity(Pid::from_raw(0)).unwrap();
+        // A CPU the process may not run on would fail to pin
+        let guard = AffinityGuard::with_pinning(CpuSet::count() as i32 - 1, false).unwrap();
+        assert!(guard.old_affinity.is_none());
+        assert_eq!(sched_getaffinity(Pid::from_raw(0)).unwrap(), before);
+        drop(guard);
+        assert_eq!(sched_getaffinity(Pid::from_raw(0)).unwrap(), before);
+        assert!(AffinityGuard::with_pinning(-1, false).is_err());
+    }
+
+    #[test]
+    fn test_parse_core_list() {
+        assert_eq!(
+            "0-3,8".parse::<CoreList>().unwrap().cores(),
+            &[0, 1, 2, 3, 8]
+        );
+        assert_eq!(
+            " 5 , 2-3,3 ".parse::<CoreList>().unwrap().cores(),
+            &[2, 3, 5]
+        );
+        assert!("".parse::<CoreList>().is_err());
+        assert!("3-1".parse::<CoreList>().is_err());
+        assert!("0-a".parse::<CoreList>().is_err());
+        assert!("-1".parse::<CoreList>().is_err());
+    }
+
+    #[test]
+    fn test_pin_current_thread() {
+        // Any core this process may run on, so the test works under a restricted cpuset
+        let allowed = sched_getaffinity(Pid::from_raw(0)).unwrap();
+        let core = (0..CpuSet::count())
+            .find(|&c| allowed.is_set(c).unwrap())
+            .unwrap();
+
+        let affinity = std::thread::spawn(move || {
+            CoreList(vec![core]).pin_current_thread().unwrap();
+            sched_getaffinity(Pid::from_raw(0)).unwrap()
+        })
+        .join()
+        .unwrap();
+
+        for c in 0..CpuSet::count() {
+            assert_eq!(affinity.is_set(c).unwrap(), c == core);
+        }
+    }
 }
@@ -28,10 +28,24 @@ impl CpuArchitecture {
     }
 }
 
-pub static CPU_ARCH: Lazy<CpuArchitecture> =
-    Lazy::new(|| detect_architecture().unwrap_or(CpuArchitecture::Unknown));
+/// CPUID facts of the CPU the agent runs on, read once
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuInfo {
+    /// Display family (base plus extended family)
+    pub family: u32,
+    /// Display model (extended model folded in on family 6 and 15)
+    pub model: u32,
+    pub stepping: u32,
+    pub arch: CpuArchitecture,
+    /// Architectural performance monitoring version (CPUID leaf 0xA), 0 if absent
+    pub pmu_version: u32,
+}
+
+pub static CPU_INFO: Lazy<CpuInfo> = Lazy::new(detect_cpu_info);
 
-fn detect_architecture() -> Result<CpuArchitecture> {
+pub static CPU_ARCH: Lazy<CpuArchitecture> = Lazy::new(|| CPU_INFO.arch);
+
+fn detect_cpu_info() -> CpuInfo {
     // CPUID leaf 1: Family, Model, Stepping
     let (eax, _ebx, _ecx, _edx) = cpuid::cpuid(1, 0);
 
@@ -61,6 +75,29 @@ fn detect_architecture() -> Result<CpuArchitecture> {
         stepping
     );
 
+    // CPUID leaf 0xA EAX[7:0], when the leaf is enumerated
+    let (max_leaf, _ebx, _ecx, _edx) = cpuid::cpuid(0, 0);
+    let pmu_version = if max_leaf >= 0xA {
+        cpuid::cpuid(0xA, 0).0 & 0xFF
+    } else {
+        0
+    };
+
+    CpuInfo {
+        family: display_family,
+        model: display_model,
+        stepping,
+        arch: detect_architecture(display_family, display_model, stepping)
+            .unwrap_or(CpuArchitecture::Unknown),
+        pmu_version,
+    }
+}
+
+fn detect_architecture(
+    display_family: u32,
+    display_model: u32,
+    stepping: u32,
+) -> Result<CpuArchitecture> {
     // Intel architectures are Family 6
     if display_family != 0x6 {
         tracing::warn!("Non-Intel or very old Intel CPU detected");
@@ -0,0 +1,212 @@
+// Counter arithmetic shared by the monitors
+//
+// Rates divide a counter delta by the time between the two reads that bracket it,
+// measured with Instant at read time, never by the configured collection interval:
+// collections run late under load, and a 1.3 s window counted as 1 s would
+// overstate bandwidth by 30%.
+//
+// Every delta is taken against a Baseline: the first read after start or reset
+// only records it and yields nothing, so a counter's absolute value is never
+// reported as the count of one window.
+
+use std::time::{Duration, Instant};
+use uncflow_raw::counter_mask;
+
+/// Shortest window a rate is derived over; closer reads would divide a few
+/// counts by almost no time and export a spike
+pub const MIN_RATE_WINDOW: Duration = Duration::from_millis(10);
+
+/// Delta between two reads of a `width_bits`-wide free-running counter,
+/// correct across a single wrap
+pub fn wrapping_delta(prev: u64, current: u64, width_bits: u64) -> u64 {
+    current.wrapping_sub(prev) & counter_mask(width_bits)
+}
+
+/// Length of a measurement window in seconds, or None if it is shorter than
+/// [`MIN_RATE_WINDOW`] and callers should keep their previous value
+pub fn rate_window_secs(elapsed: Duration) -> Option<f64> {
+    (elapsed >= MIN_RATE_WINDOW).then_some(elapsed.as_secs_f64())
+}
+
+/// `delta` per second of `elapsed`, None if the window is under [`MIN_RATE_WINDOW`]
+pub fn per_second(delta: f64, elapsed: Duration) -> Option<f64> {
+    rate_window_secs(elapsed).map(|secs| delta / secs)
+}
+
+/// Instant of the last counter read, timing the window the next read closes
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReadClock {
+    last: Option<Instant>,
+}
+
+impl ReadClock {
+    /// Whether a read at `now` would close a window under [`MIN_RATE_WINDOW`], so
+    /// the caller should skip it and keep the baseline
+    pub fn too_soon(&self, now: Instant) -> bool {
+        self.last
+            .is_some_and(|last| now.saturating_duration_since(last) < MIN_RATE_WINDOW)
+    }
+
+    /// Record a read at `now`, returning the window since the previous read
+    pub fn record(&mut self, now: Instant) -> Option<Duration> {
+        let window = self.last.map(|last| now.saturating_duration_since(last));
+        self.last = Some(now);
+        window
+    }
+
+    /// Forget the last read, so the next one only sets a baseline
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+}
+
+/// Previous reading of a set of counters, the start of the next window
+///
+/// The first read after creation or [`Baseline::reset`] yields no window. A read
+/// [`Baseline::too_soon`] after the last one should be skipped by the caller,
+/// keeping the baseline so the next window covers both.
+#[derive(Debug, Clone)]
+pub struct Baseline<T> {
+    last: Option<T>,
+    clock: ReadClock,
+}
+
+impl<T> Default for Baseline<T> {
+    fn default() -> Self {
+        Self {
+            last: None,
+            clock: ReadClock::default(),
+        }
+    }
+}
+
+impl<T> Baseline<T> {
+    /// Whether a read at `now` would close a window under [`MIN_RATE_WINDOW`]
+    pub fn too_soon(&self, now: Instant) -> bool {
+        self.last.is_some() && self.clock.too_soon(now)
+    }
+
+    /// Make `current`, read at `now`, the baseline, returning the previous one and
+    /// the window since it; None on the first read
+    pub fn advance(&mut self, current: T, now: Instant) -> Option<(T, Duration)> {
+        let window = self.clock.record(now);
+        let previous = self.last.replace(current)?;
+        Some((previous, window?))
+    }
+
+    /// The last reading, None before the first
+    pub fn get(&self) -> Option<&T> {
+        self.last.as_ref()
+    }
+
+    /// Forget the baseline, so the next read only records one
+    pub fn reset(&mut self) {
+        self.last = None;
+        self.clock.reset();
+    }
+}
+
+/// Min/avg/max of a metric over the sub-samples of one export interval
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SampleStats {
+    pub min: f64,
+    pub avg: f64,
+    pub max: f64,
+}
+
+impl SampleStats {
+    /// Aggregate a series of samples; all zeros if there are none
+    pub fn from_samples(samples: impl IntoIterator<Item = f64>) -> Self {
+        let mut count = 0usize;
+        let mut stats = SampleStats {
+            min: f64::INFINITY,
+            avg: 0.0,
+            max: f64::NEG_INFINITY,
+        };
+        for value in samples {
+            stats.min = stats.min.min(value);
+            stats.max = stats.max.max(value);
+            stats.avg += value;
+            count += 1;
+        }
+
+        if count == 0 {
+            return SampleStats::default();
+        }
+        stats.avg /= count as f64;
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrapping_delta() {
+        assert_eq!(wrapping_delta(10, 15, 48), 5);
+
+        let max48 = (1u64 << 48) - 1;
+        assert_eq!(wrapping_delta(max48, 4, 48), 5);
+        assert_eq!(wrapping_delta(u32::MAX as u64, 0, 32), 1);
+        assert_eq!(wrapping_delta(u64::MAX, 1, 64), 2);
+    }
+
+    #[test]
+    fn test_rate_window_floor() {
+        assert_eq!(rate_window_secs(Duration::ZERO), None);
+        assert_eq!(rate_window_secs(Duration::from_millis(9)), None);
+        assert_eq!(rate_window_secs(MIN_RATE_WINDOW), Some(0.01));
+        assert_eq!(rate_window_secs(Duration::from_secs(1)), Some(1.0));
+    }
+
+    #[test]
+    fn test_rate_over_measured_window() {
+        let start = Instant::now();
+        let mut clock = ReadClock::default();
+        assert_eq!(clock.record(start), None);
+
+        // Collected 1.3 s later, not after the nominal 1 s
+        let read_at = start + Duration::from_millis(1300);
+        assert!(!clock.too_soon(read_at));
+        let window = clock.record(read_at).unwrap();
+        assert_eq!(window, Duration::from_millis(1300));
+        let rate = per_second(1_300_000.0, window).unwrap();
+        assert!((rate - 1_000_000.0).abs() < 1e-6, "{rate}");
+
+        assert!(clock.too_soon(read_at + Duration::from_millis(5)));
+        assert_eq!(per_second(10.0, Duration::from_millis(5)), None);
+        clock.reset();
+        assert!(!clock.too_soon(read_at));
+    }
+
+    #[test]
+    fn test_first_read_only_sets_baseline() {
+        let start = Instant::now();
+        let mut baseline = Baseline::default();
+        assert!(!baseline.too_soon(start));
+        assert_eq!(baseline.advance(1_000u64, start), None);
+        assert_eq!(baseline.get(), Some(&1_000));
+
+        let later = start + Duration::from_secs(1);
+        assert!(baseline.too_soon(start + Duration::from_millis(5)));
+        assert_eq!(
+            baseline.advance(1_500, later),
+            Some((1_000, Duration::from_secs(1)))
+        );
+
+        baseline.reset();
+        assert_eq!(baseline.get(), None);
+        assert_eq!(baseline.advance(9_999, later), None);
+    }
+
+    #[test]
+    fn test_sample_stats() {
+        let stats = SampleStats::from_samples([4.0, 1.0, 7.0, 4.0]);
+        assert_eq!(stats.min, 1.0);
+        assert_eq!(stats.max, 7.0);
+        assert_eq!(stats.avg, 4.0);
+
+        assert_eq!(SampleStats::from_samples([]), SampleStats::default());
+    }
+}
@@ -28,6 +28,27 @@ pub fn cpuid(_eax: u32, _ecx: u32) -> (u32, u32, u32, u32) {
     (0, 0, 0, 0)
 }
 
+/// TSC frequency in Hz from CPUID leaf 0x15, if the CPU enumerates its crystal clock
+pub fn tsc_frequency() -> Option<f64> {
+    let (max_leaf, _, _, _) = cpuid(0, 0);
+    if max_leaf < 0x15 {
+        return None;
+    }
+    let (eax, ebx, ecx, _edx) = cpuid(0x15, 0);
+    tsc_frequency_from_leaf(eax, ebx, ecx)
+}
+
+/// TSC frequency = crystal Hz (ECX) * numerator (EBX) / denominator (EAX)
+///
+/// Any zero field means the ratio or crystal frequency is not enumerated
+/// (Skylake-SP reports ECX = 0).
+fn tsc_frequency_from_leaf(eax: u32, ebx: u32, ecx: u32) -> Option<f64> {
+    if eax == 0 || ebx == 0 || ecx == 0 {
+        return None;
+    }
+    Some(ecx as f64 * ebx as f64 / eax as f64)
+}
+
 pub fn get_mbm_scaling_factor() -> Result<u32> {
     let (_eax, ebx, _ecx, _edx) = cpuid(0x0F, 0x1);
     let scaling_factor = ebx;
@@ -41,6 +62,20 @@ pub fn get_mbm_scaling_factor() -> Result<u32> {
     }
 }
 
+/// Width in bits of the MBM counters, after which they wrap
+pub fn mbm_counter_width() -> u64 {
+    let (eax, _ebx, _ecx, _edx) = cpuid(0x0F, 0x1);
+    let width = mbm_counter_width_from_leaf(eax);
+    tracing::info!("MBM counter width: {} bits", width);
+    width
+}
+
+/// Counter width = 24 + offset in EAX[7:0]; parts predating the field (Skylake-SP)
+/// report 0 and count in 24 bits. The counter register holds at most 62.
+fn mbm_counter_width_from_leaf(eax: u32) -> u64 {
+    (24 + u64::from(eax & 0xFF)).min(62)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -51,4 +86,23 @@ mod tests {
         let (eax, ebx, ecx, edx) = cpuid(0, 0);
         println!("CPUID(0,0): EAX={eax:08X} EBX={ebx:08X} ECX={ecx:08X} EDX={edx:08X}");
     }
+
+    #[test]
+    fn test_tsc_frequency_from_leaf() {
+        // 24 MHz crystal, ratio 176/2
+        assert_eq!(
+            tsc_frequency_from_leaf(2, 176, 24_000_000),
+            Some(2_112_000_000.0)
+        );
+        assert_eq!(tsc_frequency_from_leaf(2, 176, 0), None);
+        assert_eq!(tsc_frequency_from_leaf(0, 0, 0), None);
+    }
+
+    #[test]
+    fn test_mbm_counter_width_from_leaf() {
+        assert_eq!(mbm_counter_width_from_leaf(0), 24);
+        // Ice Lake-SP: offset 20
+        assert_eq!(mbm_counter_width_from_leaf(0x14), 44);
+        assert_eq!(mbm_counter_width_from_leaf(0xFF), 62);
+    }
 }
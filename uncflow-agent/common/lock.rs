@@ -0,0 +1,104 @@
+// Mutual exclusion for the shared uncore PMUs
+//
+// IMC, CHA, IRP, IIO, UPI and M2M counters are socket-wide: a second uncflow
+// programming them resets and overwrites the first one's events. An advisory
+// flock on a well-known file keeps a second instance from starting. Core counters
+// (per logical CPU) and RDT (per-core RMIDs) are not covered. Tools that don't
+// take the lock, such as pcm, are not kept out either.
+
+use nix::errno::Errno;
+use nix::fcntl::{Flock, FlockArg};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::{Result, UncflowError};
+
+/// Lock file taken before programming uncore units
+pub const DEFAULT_LOCK_PATH: &str = "/var/run/uncflow.lock";
+
+/// Exclusive hold on the uncore PMUs, released when dropped or on process exit
+#[derive(Debug)]
+pub struct UncoreLock {
+    path: PathBuf,
+    _file: Flock<File>,
+}
+
+impl UncoreLock {
+    /// Take the lock at `path` without blocking, recording our PID in it
+    ///
+    /// Fails with the holder's PID if another process has it.
+    pub fn acquire(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|e| {
+                UncflowError::ConfigError(format!("Cannot open lock file {}: {e}", path.display()))
+            })?;
+
+        let mut file = match Flock::lock(file, FlockArg::LockExclusiveNonblock) {
+            Ok(file) => file,
+            Err((mut file, Errno::EWOULDBLOCK)) => {
+                let mut holder = String::new();
+                let _ = file.read_to_string(&mut holder);
+                let holder = match holder.trim() {
+                    "" => "another process".to_string(),
+                    pid => format!("PID {pid}"),
+                };
+                return Err(UncflowError::ConfigError(format!(
+                    "Uncore counters are in use by {holder} (lock {}); stop it or pass --no-lock",
+                    path.display()
+                )));
+            }
+            Err((_, errno)) => return Err(errno.into()),
+        };
+
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        writeln!(&mut *file, "{}", std::process::id())?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            _file: file,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_contention() {
+        let path = std::env::temp_dir().join(format!("uncflow-lock-{}", std::process::id()));
+
+        let lock = UncoreLock::acquire(&path).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(lock.path()).unwrap().trim(),
+            std::process::id().to_string()
+        );
+
+        // flock locks belong to the open file description, so a second open conflicts
+        let err = UncoreLock::acquire(&path).unwrap_err().to_string();
+        assert!(
+            err.contains(&format!("PID {}", std::process::id())),
+            "{err}"
+        );
+        assert!(err.contains("--no-lock"), "{err}");
+
+        // Released on drop
+        drop(lock);
+        let lock = UncoreLock::acquire(&path).unwrap();
+        drop(lock);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,118 @@
+// Rate limit for warnings about failures that repeat every collection
+//
+// A device that is permanently absent or locked fails the same way on every
+// interval. Each distinct condition, a (source, detail) pair such as
+// ("CHA socket 1", error text), is logged the first time it occurs and then at most
+// once per window, with the number of occurrences that were not logged. A new
+// detail for the same source is a new condition and is logged right away, so a
+// changed failure is never hidden. clear() forgets a source once it recovers, so
+// its next failure is logged again.
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How long a repeated failure stays quiet after being logged
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(600);
+
+#[derive(Debug)]
+struct Condition {
+    logged_at: Instant,
+    suppressed: u64,
+}
+
+/// Decides which occurrences of repeated failures are logged
+#[derive(Debug)]
+pub struct LogLimiter {
+    window: Duration,
+    conditions: Mutex<HashMap<(String, String), Condition>>,
+}
+
+impl LogLimiter {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            conditions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether this occurrence of `detail` from `source` should be logged
+    pub fn allow(&self, source: &str, detail: &str) -> bool {
+        self.allow_at(source, detail, Instant::now())
+    }
+
+    fn allow_at(&self, source: &str, detail: &str, now: Instant) -> bool {
+        let mut conditions = self.conditions.lock();
+        let key = (source.to_string(), detail.to_string());
+        match conditions.get_mut(&key) {
+            Some(condition) if now.saturating_duration_since(condition.logged_at) < self.window => {
+                condition.suppressed += 1;
+                false
+            }
+            Some(condition) => {
+                if condition.suppressed > 0 {
+                    tracing::warn!(
+                        "{}: {} repeated {} times without being logged",
+                        source,
+                        detail,
+                        condition.suppressed
+                    );
+                }
+                condition.logged_at = now;
+                condition.suppressed = 0;
+                true
+            }
+            None => {
+                conditions.insert(
+                    key,
+                    Condition {
+                        logged_at: now,
+                        suppressed: 0,
+                    },
+                );
+                true
+            }
+        }
+    }
+
+    /// Forget every condition of `source`, so its next failure is logged
+    pub fn clear(&self, source: &str) {
+        self.conditions.lock().retain(|(s, _), _| s != source);
+    }
+}
+
+/// Process-wide limiter the monitors and exporters log their failures through
+pub fn log_limiter() -> &'static LogLimiter {
+    static INSTANCE: Lazy<LogLimiter> = Lazy::new(|| LogLimiter::new(DEFAULT_WINDOW));
+    &INSTANCE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeated_failure_logs_once_per_window() {
+        let limiter = LogLimiter::new(Duration::from_secs(60));
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert!(limiter.allow_at("UPI socket 0", "link 2 absent", at(0)));
+        for secs in 1..60 {
+            assert!(!limiter.allow_at("UPI socket 0", "link 2 absent", at(secs)));
+        }
+        // A different failure, or another source, is logged right away
+        assert!(limiter.allow_at("UPI socket 0", "read timed out", at(10)));
+        assert!(limiter.allow_at("UPI socket 1", "link 2 absent", at(10)));
+
+        // Once per window
+        assert!(limiter.allow_at("UPI socket 0", "link 2 absent", at(60)));
+        assert!(!limiter.allow_at("UPI socket 0", "link 2 absent", at(61)));
+
+        // Recovery re-arms the source
+        limiter.clear("UPI socket 0");
+        assert!(limiter.allow_at("UPI socket 0", "link 2 absent", at(62)));
+        assert!(!limiter.allow_at("UPI socket 1", "link 2 absent", at(62)));
+    }
+}
@@ -1,9 +1,26 @@
 pub mod affinity;
 pub mod arch;
+pub mod counter;
 pub mod cpuid;
+pub mod lock;
+pub mod log_limit;
 pub mod msr;
+pub mod numa;
 pub mod pci;
+pub mod perf;
+pub mod pmon;
+pub mod read_stats;
+pub mod register;
+pub mod scheduler;
+pub mod sysroot;
 
-pub use affinity::AffinityGuard;
-pub use arch::{CpuArchitecture, CPU_ARCH};
-pub use msr::{Msr, MsrHandle};
+pub use affinity::{set_msr_pinning, AffinityGuard, CoreList, NO_MSR_PINNING_ENV};
+pub use arch::{CpuArchitecture, CpuInfo, CPU_ARCH, CPU_INFO};
+pub use lock::UncoreLock;
+pub use log_limit::log_limiter;
+pub use msr::{Msr, MsrAccess, MsrHandle};
+pub use numa::{NumaNode, NumaTopology};
+pub use perf::CounterBackend;
+pub use read_stats::{read_stats, ReadCounter};
+pub use scheduler::EventScheduler;
+pub use sysroot::{sys_roots, SysRoots};
@@ -1,86 +1,137 @@
+// MSR access through /dev/cpu/N/msr
+//
+// Every CPU's msr device is served by one dedicated OS thread that is pinned to that CPU
+// once, when the handle is opened. Callers on tokio workers send it requests over a
+// channel and block for the reply, so no runtime thread ever changes its own affinity
+// and two tasks sharing a worker thread can't race on it.
+
 use once_cell::sync::Lazy;
 use parking_lot::RwLock;
 use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
 use std::os::unix::io::AsRawFd;
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
 use std::sync::Arc;
+use uncflow_raw::current_arch::ubox::{self, UncoreGlobalControl};
+use uncflow_raw::msr::{decode_msr_value, encode_msr_value};
+use uncflow_raw::RegisterLayout;
 
-use crate::common::affinity::AffinityGuard;
+use crate::common::affinity::{msr_pinning, AffinityGuard};
+use crate::common::sysroot::{sys_roots, SysRoots};
 use crate::error::{Result, UncflowError};
 
+/// One request to a CPU's MSR worker, answered on the enclosed channel
+enum MsrOp {
+    Read(u64, SyncSender<Result<u64>>),
+    Write(u64, u64, SyncSender<Result<()>>),
+}
+
 pub struct MsrHandle {
-    file: parking_lot::Mutex<File>,
+    ops: Sender<MsrOp>,
     cpu_id: u32,
 }
 
 impl MsrHandle {
     pub fn new(cpu: u32) -> Result<Self> {
-        let path = format!("/dev/cpu/{cpu}/msr");
+        let path = sys_roots().msr_path(cpu);
         let file = OpenOptions::new()
             .read(true)
             .write(true)
             .open(&path)
             .map_err(|e| {
-                UncflowError::MsrError(format!("Failed to open {path} for CPU {cpu}: {e}"))
+                UncflowError::MsrError(format!(
+                    "Failed to open {} for CPU {cpu}: {e}",
+                    path.display()
+                ))
             })?;
 
         tracing::info!("Opened MSR handle {} for core {}", file.as_raw_fd(), cpu);
 
-        Ok(Self {
-            file: parking_lot::Mutex::new(file),
-            cpu_id: cpu,
-        })
+        Self::spawn(file, cpu, msr_pinning())
     }
 
-    pub fn read(&self, addr: u64) -> Result<u64> {
-        let _affinity = AffinityGuard::new(self.cpu_id as i32)?;
-        let mut file = self.file.lock();
-
-        file.seek(SeekFrom::Start(addr)).map_err(|e| {
-            UncflowError::MsrError(format!(
-                "Failed to seek to MSR 0x{:X} on CPU {}: {}",
-                addr, self.cpu_id, e
-            ))
-        })?;
+    /// Start the worker serving `file`, pinned to `cpu` when `pinning`
+    ///
+    /// Fails if the worker can't be pinned, so a blocked sched_setaffinity shows up
+    /// when the handle is opened rather than on every access.
+    fn spawn(file: File, cpu: u32, pinning: bool) -> Result<Self> {
+        let (ops, requests) = mpsc::channel();
+        let (started, startup) = mpsc::sync_channel(1);
+        std::thread::Builder::new()
+            .name(format!("msr-cpu{cpu}"))
+            .spawn(move || {
+                // Held for the worker's lifetime
+                let _affinity = match AffinityGuard::with_pinning(cpu as i32, pinning) {
+                    Ok(guard) => guard,
+                    Err(e) => {
+                        let _ = started.send(Err(e));
+                        return;
+                    }
+                };
+                let _ = started.send(Ok(()));
+                Self::serve(&file, cpu, requests);
+            })?;
+
+        startup.recv().map_err(|_| {
+            UncflowError::MsrError(format!("MSR worker for CPU {cpu} exited during startup"))
+        })??;
+
+        Ok(Self { ops, cpu_id: cpu })
+    }
+
+    /// Worker loop, runs until the handle is dropped
+    fn serve(file: &File, cpu: u32, requests: Receiver<MsrOp>) {
+        for op in requests {
+            match op {
+                MsrOp::Read(addr, reply) => {
+                    let _ = reply.send(Self::read_at(file, cpu, addr));
+                }
+                MsrOp::Write(addr, value, reply) => {
+                    let _ = reply.send(Self::write_at(file, cpu, addr, value));
+                }
+            }
+        }
+    }
 
+    fn read_at(file: &File, cpu: u32, addr: u64) -> Result<u64> {
         let mut buffer = [0u8; 8];
-        file.read_exact(&mut buffer).map_err(|e| {
-            UncflowError::MsrError(format!(
-                "Failed to read MSR 0x{:X} on CPU {}: {}",
-                addr, self.cpu_id, e
-            ))
+        file.read_exact_at(&mut buffer, addr).map_err(|e| {
+            UncflowError::MsrError(format!("Failed to read MSR 0x{addr:X} on CPU {cpu}: {e}"))
         })?;
 
-        let value = u64::from_ne_bytes(buffer);
+        let value = decode_msr_value(buffer);
         tracing::debug!(
             "MSR read: CPU {} MSR 0x{:08x} = 0x{:016x}",
-            self.cpu_id,
+            cpu,
             addr,
             value
         );
         Ok(value)
     }
 
-    pub fn write(&self, addr: u64, value: u64) -> Result<()> {
-        let _affinity = AffinityGuard::new(self.cpu_id as i32)?;
-        let mut file = self.file.lock();
-
-        file.seek(SeekFrom::Start(addr)).map_err(|e| {
-            UncflowError::MsrError(format!(
-                "Failed to seek to MSR 0x{:X} on CPU {}: {}",
-                addr, self.cpu_id, e
-            ))
-        })?;
+    fn write_at(file: &File, cpu: u32, addr: u64, value: u64) -> Result<()> {
+        file.write_all_at(&encode_msr_value(value), addr)
+            .map_err(|e| {
+                UncflowError::MsrError(format!("Failed to write MSR 0x{addr:X} on CPU {cpu}: {e}"))
+            })
+    }
 
-        file.write_all(&value.to_ne_bytes()).map_err(|e| {
-            UncflowError::MsrError(format!(
-                "Failed to write MSR 0x{:X} on CPU {}: {}",
-                addr, self.cpu_id, e
-            ))
-        })?;
+    /// Round-trip one request through the worker
+    fn request<T>(&self, op: impl FnOnce(SyncSender<Result<T>>) -> MsrOp) -> Result<T> {
+        let (reply, response) = mpsc::sync_channel(1);
+        let stopped =
+            || UncflowError::MsrError(format!("MSR worker for CPU {} has stopped", self.cpu_id));
+        self.ops.send(op(reply)).map_err(|_| stopped())?;
+        response.recv().map_err(|_| stopped())?
+    }
+
+    pub fn read(&self, addr: u64) -> Result<u64> {
+        self.request(|reply| MsrOp::Read(addr, reply))
+    }
 
-        Ok(())
+    pub fn write(&self, addr: u64, value: u64) -> Result<()> {
+        self.request(|reply| MsrOp::Write(addr, value, reply))
     }
 
     pub fn cpu_id(&self) -> u32 {
@@ -133,6 +184,102 @@ impl Msr {
     }
 }
 
+/// MSR access backend, implemented by `Msr` and by test mocks
+pub trait MsrAccess: Send + Sync {
+    fn read(&self, cpu: u32, addr: u64) -> Result<u64>;
+    fn write(&self, cpu: u32, addr: u64, value: u64) -> Result<()>;
+}
+
+impl std::fmt::Debug for dyn MsrAccess {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("MsrAccess")
+    }
+}
+
+impl MsrAccess for Msr {
+    fn read(&self, cpu: u32, addr: u64) -> Result<u64> {
+        Msr::read(self, cpu, addr)
+    }
+
+    fn write(&self, cpu: u32, addr: u64, value: u64) -> Result<()> {
+        Msr::write(self, cpu, addr, value)
+    }
+}
+
+/// Write an MSR and read it back to confirm the value stuck
+///
+/// Virtualized or locked uncore MSRs often accept writes silently while keeping
+/// their old contents. Only bits in `mask` are compared, so reserved and
+/// self-clearing bits don't cause false mismatches.
+pub fn write_verified(
+    msr: &dyn MsrAccess,
+    cpu: u32,
+    addr: u64,
+    value: u64,
+    mask: u64,
+) -> Result<()> {
+    msr.write(cpu, addr, value)?;
+    let readback = msr.read(cpu, addr)?;
+
+    if readback & mask != value & mask {
+        return Err(UncflowError::MsrError(format!(
+            "MSR 0x{addr:X} on CPU {cpu} is not programmable: wrote 0x{value:X}, read back 0x{readback:X}"
+        )));
+    }
+
+    Ok(())
+}
+
+/// Why the msr driver refuses every write on this system, None if writes may work
+///
+/// Writes are rejected with `msr.allow_writes=off` and under kernel lockdown; each
+/// counter programming attempt would then just fail. Nothing is written to find out.
+pub fn writes_blocked(roots: &SysRoots) -> Option<String> {
+    let read = |path: std::path::PathBuf| std::fs::read_to_string(path).ok();
+    if read(roots.msr_allow_writes_path()).is_some_and(|value| value.trim() == "off") {
+        return Some("the msr module has allow_writes=off".to_string());
+    }
+    let lockdown = read(roots.lockdown_path())?;
+    let active = lockdown
+        .split_whitespace()
+        .find_map(|mode| mode.strip_prefix('[')?.strip_suffix(']'))?;
+    (active != "none").then(|| format!("the kernel is in {active} lockdown"))
+}
+
+/// Lift a global uncore freeze of the socket `cpu` belongs to before `unit` is programmed
+///
+/// While the UBox global control holds every uncore box frozen, per-box programming
+/// succeeds but the counters never advance and `unit` would export zeros. A control
+/// register that can't be read is left alone; one that stays frozen is an error.
+pub fn ensure_uncore_unfrozen(msr: &dyn MsrAccess, cpu: u32, unit: &str) -> Result<()> {
+    let addr = ubox::msr::U_MSR_PMON_GLOBAL_CTL;
+    let control = match msr.read(cpu, addr) {
+        Ok(value) => UncoreGlobalControl::from_msr_value(value),
+        Err(e) => {
+            tracing::debug!("Cannot check the global uncore freeze for {}: {}", unit, e);
+            return Ok(());
+        }
+    };
+    if !control.is_frozen() {
+        return Ok(());
+    }
+
+    tracing::warn!(
+        "Uncore PMU is globally frozen (MSR 0x{:X} on CPU {}), unfreezing it for {}",
+        addr,
+        cpu,
+        unit
+    );
+    msr.write(cpu, addr, UncoreGlobalControl::UNFREEZE.to_msr_value())?;
+    if UncoreGlobalControl::from_msr_value(msr.read(cpu, addr)?).is_frozen() {
+        return Err(UncflowError::MsrError(format!(
+            "Uncore PMU stays globally frozen (MSR 0x{addr:X} on CPU {cpu}), {unit} counters would not count; check BIOS PMU settings or other tools holding the freeze"
+        )));
+    }
+
+    Ok(())
+}
+
 pub fn read(cpu: u32, addr: u64) -> Result<u64> {
     Msr::instance().read(cpu, addr)
 }
@@ -149,8 +296,90 @@ pub fn write_msr(cpu: u32, addr: u64, value: u64) -> Result<()> {
     Msr::instance().write(cpu, addr, value)
 }
 
+#[cfg(test)]
+pub(crate) mod mock {
+    use super::*;
+
+    /// In-memory MSR backend; a read-only mock drops writes like a virtualized MSR
+    #[derive(Default)]
+    pub struct MockMsr {
+        values: parking_lot::Mutex<HashMap<(u32, u64), u64>>,
+        writes: std::sync::atomic::AtomicUsize,
+        locked: parking_lot::Mutex<std::collections::HashSet<(u32, u64)>>,
+        failing: parking_lot::Mutex<std::collections::HashSet<(u32, u64)>>,
+        read_only: bool,
+    }
+
+    impl MockMsr {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn read_only() -> Self {
+            Self {
+                read_only: true,
+                ..Default::default()
+            }
+        }
+
+        pub fn set(&self, cpu: u32, addr: u64, value: u64) {
+            self.values.lock().insert((cpu, addr), value);
+        }
+
+        pub fn get(&self, cpu: u32, addr: u64) -> Option<u64> {
+            self.values.lock().get(&(cpu, addr)).copied()
+        }
+
+        /// Make one register drop writes, like a counter locked by firmware
+        pub fn lock(&self, cpu: u32, addr: u64) {
+            self.locked.lock().insert((cpu, addr));
+        }
+
+        /// Let a locked register take writes again
+        pub fn unlock(&self, cpu: u32, addr: u64) {
+            self.locked.lock().remove(&(cpu, addr));
+        }
+
+        /// Make reads of one register fail, like an MSR the kernel refuses
+        pub fn fail_reads(&self, cpu: u32, addr: u64) {
+            self.failing.lock().insert((cpu, addr));
+        }
+
+        /// Number of write() calls, including ones a read-only mock dropped
+        pub fn write_count(&self) -> usize {
+            self.writes.load(std::sync::atomic::Ordering::Relaxed)
+        }
+
+        /// Leak the mock so it can stand in for the `&'static` MSR singleton
+        pub fn leak(self) -> &'static Self {
+            Box::leak(Box::new(self))
+        }
+    }
+
+    impl MsrAccess for MockMsr {
+        fn read(&self, cpu: u32, addr: u64) -> Result<u64> {
+            if self.failing.lock().contains(&(cpu, addr)) {
+                return Err(UncflowError::MsrError(format!(
+                    "Failed to read MSR 0x{addr:X} on CPU {cpu}"
+                )));
+            }
+            Ok(self.get(cpu, addr).unwrap_or(0))
+        }
+
+        fn write(&self, cpu: u32, addr: u64, value: u64) -> Result<()> {
+            self.writes
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            if !self.read_only && !self.locked.lock().contains(&(cpu, addr)) {
+                self.set(cpu, addr, value);
+            }
+            Ok(())
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use super::mock::MockMsr;
     use super::*;
 
     #[test]
@@ -159,4 +388,167 @@ mod tests {
         let msr2 = Msr::instance();
         assert!(std::ptr::eq(msr1, msr2));
     }
+
+    #[test]
+    fn test_write_verified() {
+        let msr = MockMsr::new();
+        assert!(write_verified(&msr, 0, 0xE01, 0x40_0036, u64::MAX).is_ok());
+
+        // Bits outside the mask are ignored
+        let read_only = MockMsr::read_only();
+        read_only.set(0, 0xE02, 0x2_0000);
+        assert!(write_verified(&read_only, 0, 0xE02, 0, 0xFFFF).is_ok());
+    }
+
+    #[test]
+    fn test_uncore_freeze_detection() {
+        let addr = ubox::msr::U_MSR_PMON_GLOBAL_CTL;
+        let frozen = UncoreGlobalControl {
+            frz_all: true,
+            ..Default::default()
+        }
+        .to_msr_value();
+
+        // Running uncore: nothing written
+        let msr = MockMsr::new();
+        assert!(ensure_uncore_unfrozen(&msr, 0, "CHA").is_ok());
+        assert_eq!(msr.write_count(), 0);
+
+        let msr = MockMsr::new();
+        msr.set(0, addr, frozen);
+        assert!(ensure_uncore_unfrozen(&msr, 0, "CHA").is_ok());
+        assert_eq!(
+            msr.get(0, addr),
+            Some(UncoreGlobalControl::UNFREEZE.to_msr_value())
+        );
+
+        // A freeze that can't be lifted is reported
+        let read_only = MockMsr::read_only();
+        read_only.set(0, addr, frozen);
+        let err = ensure_uncore_unfrozen(&read_only, 0, "IIO").unwrap_err();
+        assert!(err.to_string().contains("globally frozen"));
+
+        // Unreadable control: can't tell, so don't block the monitor
+        let unreadable = MockMsr::new();
+        unreadable.fail_reads(0, addr);
+        assert!(ensure_uncore_unfrozen(&unreadable, 0, "IRP").is_ok());
+    }
+
+    #[test]
+    fn test_write_verified_readback_mismatch() {
+        let msr = MockMsr::read_only();
+        msr.set(0, 0xA68, 0x1234);
+
+        let err = write_verified(&msr, 0, 0xA68, 0x40_0041, u64::MAX).unwrap_err();
+        assert!(err.to_string().contains("not programmable"));
+    }
+
+    #[test]
+    fn test_writes_blocked() {
+        let root = std::env::temp_dir().join(format!("uncflow-msr-writes-{}", std::process::id()));
+        let roots = SysRoots::under(&root);
+        let write = |path: std::path::PathBuf, contents: &str| {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        };
+
+        assert_eq!(writes_blocked(&roots), None);
+        write(roots.lockdown_path(), "[none] integrity confidentiality\n");
+        write(roots.msr_allow_writes_path(), "default\n");
+        assert_eq!(writes_blocked(&roots), None);
+        write(roots.lockdown_path(), "none [integrity] confidentiality\n");
+        assert!(writes_blocked(&roots)
+            .unwrap()
+            .contains("integrity lockdown"));
+        write(roots.msr_allow_writes_path(), "off\n");
+        assert!(writes_blocked(&roots).unwrap().contains("allow_writes=off"));
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    fn scratch_file(name: &str) -> (std::path::PathBuf, File) {
+        // A regular file stands in for /dev/cpu/N/msr
+        let path = std::env::temp_dir().join(format!("uncflow-{name}-{}", std::process::id()));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        (path, file)
+    }
+
+    fn allowed_cpus() -> Vec<u32> {
+        let affinity = nix::sched::sched_getaffinity(nix::unistd::Pid::from_raw(0)).unwrap();
+        (0..nix::sched::CpuSet::count())
+            .filter(|&cpu| affinity.is_set(cpu).unwrap_or(false))
+            .map(|cpu| cpu as u32)
+            .collect()
+    }
+
+    #[test]
+    fn test_byte_order_matches_raw_crate() {
+        let (path, file) = scratch_file("msr");
+        let handle = MsrHandle::spawn(file, allowed_cpus()[0], true).unwrap();
+
+        let value = 0x0123_4567_89AB_CDEF;
+        handle.write(0x10, value).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        let written: [u8; 8] = bytes[0x10..0x18].try_into().unwrap();
+        assert_eq!(written, encode_msr_value(value));
+        assert_eq!(decode_msr_value(written), value);
+
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.write_all_at(&encode_msr_value(value), 0x20).unwrap();
+        assert_eq!(handle.read(0x20).unwrap(), value);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_concurrent_reads_across_cpus() {
+        // Pin the workers of CPUs we may run on, the others serve unpinned
+        let allowed = allowed_cpus();
+        let mut paths = Vec::new();
+        let mut handles = Vec::new();
+        for cpu in 0..4u32 {
+            let (path, file) = scratch_file(&format!("msr-stress{cpu}"));
+            let handle = MsrHandle::spawn(file, cpu, allowed.contains(&cpu)).unwrap();
+            for addr in 0..16u64 {
+                handle.write(addr * 8, u64::from(cpu) << 32 | addr).unwrap();
+            }
+            paths.push(path);
+            handles.push(handle);
+        }
+
+        let before = nix::sched::sched_getaffinity(nix::unistd::Pid::from_raw(0)).unwrap();
+        std::thread::scope(|scope| {
+            for reader in 0..8u64 {
+                let handles = &handles;
+                scope.spawn(move || {
+                    let caller = nix::sched::sched_getaffinity(nix::unistd::Pid::from_raw(0));
+                    for i in 0..500u64 {
+                        let handle = &handles[((reader + i) % 4) as usize];
+                        let addr = (reader * 7 + i) % 16;
+                        let value = handle.read(addr * 8).unwrap();
+                        assert_eq!(value, u64::from(handle.cpu_id()) << 32 | addr);
+                    }
+                    // Reading never moves the calling thread
+                    assert_eq!(
+                        nix::sched::sched_getaffinity(nix::unistd::Pid::from_raw(0)),
+                        caller
+                    );
+                });
+            }
+        });
+        assert_eq!(
+            nix::sched::sched_getaffinity(nix::unistd::Pid::from_raw(0)).unwrap(),
+            before
+        );
+
+        for path in paths {
+            let _ = std::fs::remove_file(path);
+        }
+    }
 }
@@ -0,0 +1,152 @@
+// NUMA nodes of the monitored sockets, for attributing uncore traffic per node
+//
+// With sub-NUMA clustering (SNC) enabled in the BIOS, a socket presents as several
+// NUMA nodes, each owning a share of the socket's memory controllers and cores.
+// SNC is detected from /sys/devices/system/node: every nodeN directory lists its
+// CPUs in `cpulist`, and the physical_package_id of those CPUs is the node's
+// socket. A socket holding more than one node runs SNC. Memory-only nodes (PMEM,
+// CXL) have no CPUs and are skipped.
+//
+// The OS does not expose which IMC or CHA belongs to which cluster. On Skylake-SP
+// SNC-2, IMC 0 (channels 0-2) serves the lower-numbered node of the socket and
+// IMC 1 (channels 3-5) the higher one; CHAs are split the same way, the lower half
+// of the box ids to the first node. That split follows the mesh halves only
+// approximately, so per-node CHA values are an attribution, not an exact count.
+
+use std::path::Path;
+
+use crate::common::{sys_roots, CoreList};
+use crate::error::{Result, UncflowError};
+
+/// One NUMA node with CPUs, and the socket it is part of
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NumaNode {
+    pub id: i32,
+    pub socket: i32,
+    pub cpus: Vec<usize>,
+}
+
+/// NUMA nodes of the system, ordered by node id
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NumaTopology {
+    nodes: Vec<NumaNode>,
+}
+
+impl NumaTopology {
+    /// Nodes of the running system, with sockets from the CPUs' package ids
+    pub fn discover() -> Result<Self> {
+        Self::from_dir(&sys_roots().numa_nodes_dir(), |cpu| {
+            std::fs::read_to_string(sys_roots().package_id_path(cpu as i32))
+                .ok()?
+                .trim()
+                .parse()
+                .ok()
+        })
+    }
+
+    /// Nodes below `nodes_dir`, with `package_of` mapping a CPU to its socket
+    pub fn from_dir(nodes_dir: &Path, package_of: impl Fn(usize) -> Option<i32>) -> Result<Self> {
+        let mut nodes = Vec::new();
+        for entry in std::fs::read_dir(nodes_dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let Some(id) = name
+                .to_str()
+                .and_then(|name| name.strip_prefix("node"))
+                .and_then(|id| id.parse().ok())
+            else {
+                continue;
+            };
+            let cpulist = std::fs::read_to_string(entry.path().join("cpulist"))?;
+            // Memory-only node
+            if cpulist.trim().is_empty() {
+                continue;
+            }
+            let cpus = cpulist.trim().parse::<CoreList>()?.cores().to_vec();
+            let socket = package_of(cpus[0]).ok_or_else(|| {
+                UncflowError::HardwareError(format!("No socket for CPU {} of node {id}", cpus[0]))
+            })?;
+            nodes.push(NumaNode { id, socket, cpus });
+        }
+        nodes.sort_by_key(|node| node.id);
+        Ok(Self { nodes })
+    }
+
+    pub fn nodes(&self) -> &[NumaNode] {
+        &self.nodes
+    }
+
+    pub fn node(&self, id: i32) -> Option<&NumaNode> {
+        self.nodes.iter().find(|node| node.id == id)
+    }
+
+    /// Nodes of `socket`, lowest id first
+    pub fn nodes_of_socket(&self, socket: i32) -> Vec<&NumaNode> {
+        self.nodes
+            .iter()
+            .filter(|node| node.socket == socket)
+            .collect()
+    }
+
+    /// Whether any socket is split into several nodes
+    pub fn is_snc(&self) -> bool {
+        self.nodes
+            .iter()
+            .any(|node| self.nodes_of_socket(node.socket).len() > 1)
+    }
+
+    /// Node of unit `index` out of `count` equal units of `socket`, such as an IMC
+    /// channel or a CHA box, splitting the units evenly across the socket's nodes
+    pub fn node_of_unit(&self, socket: i32, index: usize, count: usize) -> Option<i32> {
+        let nodes = self.nodes_of_socket(socket);
+        if nodes.is_empty() || index >= count {
+            return None;
+        }
+        Some(nodes[index * nodes.len() / count].id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_node_enumeration() {
+        let dir = std::env::temp_dir().join(format!("uncflow-numa-{}", std::process::id()));
+        // SNC-2 on two sockets of 4 CPUs, plus a memory-only node
+        for (node, cpulist) in [
+            ("node0", "0-1\n"),
+            ("node1", "2-3\n"),
+            ("node2", "4,5\n"),
+            ("node3", "6-7\n"),
+            ("node4", "\n"),
+        ] {
+            std::fs::create_dir_all(dir.join(node)).unwrap();
+            std::fs::write(dir.join(node).join("cpulist"), cpulist).unwrap();
+        }
+        std::fs::write(dir.join("online"), "0-4\n").unwrap();
+
+        let topology = NumaTopology::from_dir(&dir, |cpu| Some(cpu as i32 / 4)).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(topology.nodes().len(), 4);
+        assert_eq!(
+            topology.node(2),
+            Some(&NumaNode {
+                id: 2,
+                socket: 1,
+                cpus: vec![4, 5]
+            })
+        );
+        assert!(topology.node(4).is_none());
+        assert!(topology.is_snc());
+
+        // IMC 0 of socket 1 serves node 2, IMC 1 node 3
+        assert_eq!(topology.node_of_unit(1, 2, 6), Some(2));
+        assert_eq!(topology.node_of_unit(1, 3, 6), Some(3));
+        // CHA boxes split by halves
+        assert_eq!(topology.node_of_unit(0, 13, 28), Some(0));
+        assert_eq!(topology.node_of_unit(0, 14, 28), Some(1));
+        assert_eq!(topology.node_of_unit(2, 0, 6), None);
+    }
+}
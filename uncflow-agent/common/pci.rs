@@ -1,4 +1,16 @@
-use once_cell::sync::Lazy;
+// PCI configuration space access to the uncore devices of each socket
+//
+// An uncore device (IMC channel, M2M, UPI link, UBox) appears once per socket at
+// the same device and function, on a bus of that socket. Every located copy is a
+// candidate, and candidates are ordered by (segment, bus): BIOSes number sockets'
+// buses upwards within a segment, and on 4/8-socket systems that give each socket
+// or socket pair its own segment group, segments upwards by socket.
+//
+// Firmware that orders segments otherwise is configured with a segment-per-socket
+// table (--pci-segments): socket N then takes the candidates of segment table[N],
+// in bus order among the sockets sharing that segment.
+
+use once_cell::sync::{Lazy, OnceCell};
 use parking_lot::RwLock;
 use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
@@ -6,8 +18,27 @@ use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::PathBuf;
 use std::sync::Arc;
 
+use crate::common::sysroot::{sys_roots, SysRoots};
 use crate::error::{Result, UncflowError};
 
+static SOCKET_SEGMENTS: OnceCell<Vec<u32>> = OnceCell::new();
+
+/// Intel's PCI vendor ID
+pub const INTEL_VENDOR_ID: u32 = 0x8086;
+
+/// Whether the vendor/device dword at config offset 0 is the Intel device `device_id`
+fn is_intel_device(vendor_device: u32, device_id: u32) -> bool {
+    vendor_device & 0xFFFF == INTEL_VENDOR_ID && vendor_device >> 16 == device_id
+}
+
+/// Set the PCI segment of each socket, indexed by socket; must happen before the
+/// first PCI access
+pub fn set_socket_segments(segments: Vec<u32>) -> Result<()> {
+    SOCKET_SEGMENTS
+        .set(segments)
+        .map_err(|_| UncflowError::ConfigError("PCI socket segments were already set".to_string()))
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct PciConfigAddress {
     pub socket: u32,
@@ -65,7 +96,7 @@ pub struct PciHandle {
 
 impl PciHandle {
     pub fn new(address: PciAddress) -> Result<Self> {
-        let path = Self::get_pci_path(address)?;
+        let path = Self::get_pci_path(sys_roots(), address);
         let file = OpenOptions::new()
             .read(true)
             .write(true)
@@ -83,28 +114,21 @@ impl PciHandle {
         })
     }
 
-    fn get_pci_path(address: PciAddress) -> Result<PathBuf> {
-        let base_path = if std::env::var("DOCKER_RUNNING").is_ok() {
-            "/pcm/proc/bus/pci"
+    fn get_pci_path(roots: &SysRoots, address: PciAddress) -> PathBuf {
+        let bus_dir = if address.group_number > 0 {
+            format!("{:04x}:{:02x}", address.group_number, address.bus)
         } else {
-            "/proc/bus/pci"
+            format!("{:02x}", address.bus)
         };
 
-        let path = if address.group_number > 0 {
-            format!(
-                "{}/{:04x}:{:02x}/{:02x}.{}",
-                base_path, address.group_number, address.bus, address.device, address.function
-            )
-        } else {
-            format!(
-                "{}/{:02x}/{:02x}.{}",
-                base_path, address.bus, address.device, address.function
-            )
-        };
-
-        Ok(PathBuf::from(path))
+        roots
+            .pci_bus_dir()
+            .join(bus_dir)
+            .join(format!("{:02x}.{}", address.device, address.function))
     }
 
+    // Config space is little-endian by the PCI spec and sysfs hands it over as raw
+    // bytes, unlike the msr driver which returns native-endian values
     pub fn read32(&self, offset: u32) -> Result<u32> {
         let mut file = self.file.lock();
         file.seek(SeekFrom::Start(offset as u64)).map_err(|e| {
@@ -154,15 +178,12 @@ pub struct Mcfg {
 
 impl Mcfg {
     fn new() -> Result<Self> {
-        let mcfg_path = if std::env::var("DOCKER_RUNNING").is_ok() {
-            "/pcm/sys/firmware/acpi/tables/MCFG"
-        } else {
-            "/sys/firmware/acpi/tables/MCFG"
-        };
-
-        let mut file = File::open(mcfg_path)
+        let file = File::open(sys_roots().mcfg_path())
             .map_err(|e| UncflowError::PciError(format!("Failed to open MCFG table: {e}")))?;
+        Self::from_reader(file)
+    }
 
+    fn from_reader(mut file: impl Read) -> Result<Self> {
         let mut header_bytes = vec![0u8; std::mem::size_of::<McfgHeader>()];
         file.read_exact(&mut header_bytes)
             .map_err(|e| UncflowError::PciError(format!("Failed to read MCFG header: {e}")))?;
@@ -206,14 +227,9 @@ impl Mcfg {
             function,
         };
 
-        if let Ok(handle) = PciHandle::new(address) {
-            if let Ok(value) = handle.read32(0) {
-                let vendor = value & 0xFFFF;
-                let device = (value >> 16) & 0xFFFF;
-                return vendor == 0x8086 && device == device_id;
-            }
-        }
-        false
+        PciHandle::new(address)
+            .and_then(|handle| handle.read32(0))
+            .is_ok_and(|value| is_intel_device(value, device_id))
     }
 
     pub fn find_group_bus(&self, config_addr: &PciConfigAddress) -> Result<PciAddress> {
@@ -224,6 +240,35 @@ impl Mcfg {
             }
         }
 
+        let candidates = self.candidates(config_addr, |address| {
+            self.validate_pci_address(
+                address.group_number,
+                address.bus,
+                address.device,
+                address.function,
+                config_addr.device_id,
+            )
+        });
+        let segments = SOCKET_SEGMENTS.get().map(Vec::as_slice);
+
+        if let Some(addr) = select_socket(&candidates, config_addr.socket, segments) {
+            let mut map = self.group_bus_map.write();
+            map.insert(*config_addr, addr);
+            return Ok(addr);
+        }
+
+        Err(UncflowError::PciError(format!(
+            "Cannot find PCI device for socket {} device {} function {}",
+            config_addr.socket, config_addr.device, config_addr.function
+        )))
+    }
+
+    // Every copy of the device `probe` accepts, ordered by (segment, bus)
+    fn candidates(
+        &self,
+        config_addr: &PciConfigAddress,
+        probe: impl Fn(PciAddress) -> bool,
+    ) -> Vec<PciAddress> {
         let mut candidates = Vec::new();
 
         for record in &self.records {
@@ -232,44 +277,54 @@ impl Mcfg {
             let end_bus = record.end_bus;
 
             for bus in start_bus..=end_bus {
-                if self.validate_pci_address(
-                    pci_segment as u32,
-                    bus as u32,
-                    config_addr.device,
-                    config_addr.function,
-                    config_addr.device_id,
-                ) {
-                    tracing::warn!(
+                let address = PciAddress {
+                    group_number: pci_segment as u32,
+                    bus: bus as u32,
+                    device: config_addr.device,
+                    function: config_addr.function,
+                };
+                if probe(address) {
+                    tracing::debug!(
                         "Located PCI device {:04X}:{:02X}:{:02X}.{}",
                         pci_segment,
                         bus,
                         config_addr.device,
                         config_addr.function
                     );
-                    candidates.push(PciAddress {
-                        group_number: pci_segment as u32,
-                        bus: bus as u32,
-                        device: config_addr.device,
-                        function: config_addr.function,
-                    });
+                    candidates.push(address);
                 }
             }
         }
 
-        if (config_addr.socket as usize) < candidates.len() {
-            let addr = candidates[config_addr.socket as usize];
-            let mut map = self.group_bus_map.write();
-            map.insert(*config_addr, addr);
-            return Ok(addr);
-        }
-
-        Err(UncflowError::PciError(format!(
-            "Cannot find PCI device for socket {} device {} function {}",
-            config_addr.socket, config_addr.device, config_addr.function
-        )))
+        candidates.sort_by_key(|address| (address.group_number, address.bus));
+        candidates
     }
 }
 
+/// Candidate of `socket` among `candidates` ordered by (segment, bus): the socket's
+/// position in that order, or with a segment-per-socket table its position among
+/// the sockets sharing its segment
+fn select_socket(
+    candidates: &[PciAddress],
+    socket: u32,
+    segments: Option<&[u32]>,
+) -> Option<PciAddress> {
+    let socket = socket as usize;
+    let Some(segments) = segments else {
+        return candidates.get(socket).copied();
+    };
+    let segment = *segments.get(socket)?;
+    let rank = segments[..socket]
+        .iter()
+        .filter(|&&other| other == segment)
+        .count();
+    candidates
+        .iter()
+        .filter(|address| address.group_number == segment)
+        .nth(rank)
+        .copied()
+}
+
 pub struct Pci {
     handles: RwLock<HashMap<PciConfigAddress, Arc<PciHandle>>>,
 }
@@ -316,6 +371,31 @@ impl Pci {
         let handle = self.get_or_create_handle(config_addr)?;
         handle.read64(offset)
     }
+
+    /// Check that `config_addr` holds the Intel device `config_addr.device_id`
+    ///
+    /// `unit` names the device in the error, e.g. "UPI link 1".
+    pub fn probe(&self, config_addr: &PciConfigAddress, unit: &str) -> Result<()> {
+        let vendor_device = self.read32(config_addr, 0)?;
+        if !is_intel_device(vendor_device, config_addr.device_id) {
+            return Err(UncflowError::PciError(format!(
+                "{unit} not found for socket {}: expected {INTEL_VENDOR_ID:04X}:{:04X}, got {:04X}:{:04X}",
+                config_addr.socket,
+                config_addr.device_id,
+                vendor_device & 0xFFFF,
+                vendor_device >> 16
+            )));
+        }
+        Ok(())
+    }
+
+    /// Write a 64-bit register as two dwords, the upper one at `offset + 4` first so
+    /// the enable bit in the lower dword takes effect on the complete value
+    pub fn write64(&self, config_addr: &PciConfigAddress, offset: u32, value: u64) -> Result<()> {
+        let handle = self.get_or_create_handle(config_addr)?;
+        handle.write32(offset + 4, (value >> 32) as u32)?;
+        handle.write32(offset, value as u32)
+    }
 }
 
 pub fn device_exists(group: u32, bus: u32, device: u32, function: u32) -> bool {
@@ -327,3 +407,123 @@ pub fn device_exists(group: u32, bus: u32, device: u32, function: u32) -> bool {
     };
     PciHandle::new(address).is_ok()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_intel_device() {
+        assert!(is_intel_device(0x2058_8086, 0x2058));
+        assert!(!is_intel_device(0x2058_8086, 0x2066));
+        assert!(!is_intel_device(0x2058_1022, 0x2058));
+        // Absent functions read back all ones
+        assert!(!is_intel_device(u32::MAX, 0xFFFF));
+    }
+
+    #[test]
+    fn test_pci_path_honors_sysroot() {
+        let roots = SysRoots::under("/host");
+        let address = PciAddress {
+            group_number: 0,
+            bus: 0x17,
+            device: 0x0A,
+            function: 2,
+        };
+        assert_eq!(
+            PciHandle::get_pci_path(&roots, address),
+            PathBuf::from("/host/proc/bus/pci/17/0a.2")
+        );
+
+        let address = PciAddress {
+            group_number: 1,
+            ..address
+        };
+        assert_eq!(
+            PciHandle::get_pci_path(&SysRoots::host(), address),
+            PathBuf::from("/proc/bus/pci/0001:17/0a.2")
+        );
+    }
+
+    #[test]
+    fn test_four_socket_segment_mapping() {
+        // Four sockets, one segment each, listed by firmware out of order
+        let mut table = vec![0u8; std::mem::size_of::<McfgHeader>()];
+        table[..4].copy_from_slice(b"MCFG");
+        let length = table.len() + 4 * std::mem::size_of::<McfgRecord>();
+        table[4..8].copy_from_slice(&(length as u32).to_le_bytes());
+        for segment in [2u16, 0, 3, 1] {
+            table.extend_from_slice(&(0xE000_0000u64 + segment as u64 * 0x1000_0000).to_le_bytes());
+            table.extend_from_slice(&segment.to_le_bytes());
+            table.extend_from_slice(&[0x00, 0xFF, 0, 0, 0, 0]);
+        }
+        let mcfg = Mcfg::from_reader(table.as_slice()).unwrap();
+        assert_eq!(mcfg.records.len(), 4);
+
+        // The device sits on bus 0x7F of every segment
+        let config_addr = PciConfigAddress {
+            socket: 0,
+            device: 0x08,
+            function: 2,
+            device_id: 0x2014,
+        };
+        let candidates = mcfg.candidates(&config_addr, |address| address.bus == 0x7F);
+        assert_eq!(candidates.len(), 4);
+
+        let segment_of = |socket, segments| {
+            let address = select_socket(&candidates, socket, segments).unwrap();
+            assert_eq!(
+                (address.bus, address.device, address.function),
+                (0x7F, 0x08, 2)
+            );
+            address.group_number
+        };
+        // Segment order, whatever the order of the MCFG records
+        for socket in 0..4 {
+            assert_eq!(segment_of(socket, None), socket);
+        }
+        // A segment-per-socket table overrides it
+        let table = [3, 1, 0, 2];
+        for socket in 0..4 {
+            assert_eq!(segment_of(socket, Some(&table[..])), table[socket as usize]);
+        }
+        assert!(select_socket(&candidates, 4, None).is_none());
+        assert!(select_socket(&candidates, 4, Some(&table[..])).is_none());
+
+        // Two sockets sharing a segment split it by bus
+        let shared = [
+            PciAddress {
+                group_number: 0,
+                bus: 0x7F,
+                device: 0x08,
+                function: 2,
+            },
+            PciAddress {
+                group_number: 0,
+                bus: 0xFF,
+                device: 0x08,
+                function: 2,
+            },
+            PciAddress {
+                group_number: 1,
+                bus: 0x7F,
+                device: 0x08,
+                function: 2,
+            },
+            PciAddress {
+                group_number: 1,
+                bus: 0xFF,
+                device: 0x08,
+                function: 2,
+            },
+        ];
+        let table = [1, 1, 0, 0];
+        let buses: Vec<_> = (0..4)
+            .map(|socket| {
+                let address = select_socket(&shared, socket, Some(&table[..])).unwrap();
+                (address.group_number, address.bus)
+            })
+            .collect();
+        assert_eq!(buses, [(1, 0x7F), (1, 0xFF), (0, 0x7F), (0, 0xFF)]);
+    }
+}
@@ -0,0 +1,302 @@
+// perf_event_open counter backend
+//
+// Alternative to /dev/cpu/*/msr for hardened hosts that allow perf_event_open
+// (perf_event_paranoid <= 0, or CAP_PERFMON). System-wide counting on a CPU is used,
+// so no process needs to be attached.
+//
+// Supported through perf:
+// - Core: fixed counters (instructions, cycles, reference cycles) as generic hardware
+//   events, programmable events as raw event/umask encodings
+// - RAPL: package, core and DRAM energy through the `power` PMU
+//
+// IMC, CHA, IRP, IIO, UPI, M2M and RDT still program MSR/PCI registers directly.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::os::fd::{FromRawFd, OwnedFd};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::common::sys_roots;
+use crate::error::{Result, UncflowError};
+
+/// `perf_event_attr.type` for generic hardware events
+pub const PERF_TYPE_HARDWARE: u32 = 0;
+/// `perf_event_attr.type` for raw, model-specific event encodings
+pub const PERF_TYPE_RAW: u32 = 4;
+
+/// Generic hardware event ids (`perf_event_attr.config` with `PERF_TYPE_HARDWARE`)
+pub mod hardware {
+    pub const CPU_CYCLES: u64 = 0;
+    pub const INSTRUCTIONS: u64 = 1;
+    pub const REF_CPU_CYCLES: u64 = 9;
+}
+
+// perf_event_attr size for the layout below (PERF_ATTR_SIZE_VER5)
+const PERF_ATTR_SIZE: u32 = 112;
+
+// perf_event_attr flag bits
+const FLAG_EXCLUDE_KERNEL: u64 = 1 << 5;
+const FLAG_EXCLUDE_HV: u64 = 1 << 6;
+
+/// Which mechanism reads the counters of the subsystems that support both
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CounterBackend {
+    /// Program and read MSRs through /dev/cpu/*/msr
+    #[default]
+    Msr,
+    /// Open counters with perf_event_open (Core and RAPL only)
+    Perf,
+}
+
+impl FromStr for CounterBackend {
+    type Err = UncflowError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "msr" => Ok(Self::Msr),
+            "perf" => Ok(Self::Perf),
+            _ => Err(UncflowError::ParseError(format!(
+                "Unknown counter backend '{s}', expected 'msr' or 'perf'"
+            ))),
+        }
+    }
+}
+
+/// PMU type and config selecting one perf event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PerfEvent {
+    pub type_: u32,
+    pub config: u64,
+    /// Count kernel-mode events too
+    pub kernel: bool,
+}
+
+impl PerfEvent {
+    /// Generic hardware event, counted in user and kernel mode
+    pub const fn hardware(config: u64) -> Self {
+        Self {
+            type_: PERF_TYPE_HARDWARE,
+            config,
+            kernel: true,
+        }
+    }
+
+    /// Raw core event, encoded like IA32_PERFEVTSEL bits 0-15
+    pub const fn raw(event: u8, umask: u8, kernel: bool) -> Self {
+        Self {
+            type_: PERF_TYPE_RAW,
+            config: (event as u64) | ((umask as u64) << 8),
+            kernel,
+        }
+    }
+
+    /// Named event of a dynamic PMU under /sys/bus/event_source/devices, e.g. power/energy-pkg
+    pub fn from_sysfs(pmu: &str, event: &str) -> Result<Self> {
+        let dir = sys_roots().perf_pmu_dir(pmu);
+        let type_ = read_trimmed(&dir.join("type"))?
+            .parse::<u32>()
+            .map_err(|e| UncflowError::ParseError(format!("Invalid {pmu} PMU type: {e}")))?;
+
+        let spec = read_trimmed(&dir.join("events").join(event))?;
+        let mut formats = HashMap::new();
+        for term in spec.split(',') {
+            let name = term.split('=').next().unwrap_or_default().trim();
+            let format = read_trimmed(&dir.join("format").join(name))?;
+            formats.insert(name.to_string(), parse_format(&format)?);
+        }
+
+        Ok(Self {
+            type_,
+            config: encode_event_spec(&spec, &formats)?,
+            kernel: true,
+        })
+    }
+}
+
+/// Parse a sysfs format file such as `config:0-7` or `config:21` into a bit range
+pub fn parse_format(format: &str) -> Result<(u32, u32)> {
+    let invalid = || UncflowError::ParseError(format!("Unsupported perf format '{format}'"));
+
+    let bits = format.strip_prefix("config:").ok_or_else(invalid)?;
+    let (lo, hi) = bits.split_once('-').unwrap_or((bits, bits));
+    let lo = lo.parse::<u32>().map_err(|_| invalid())?;
+    let hi = hi.parse::<u32>().map_err(|_| invalid())?;
+    if lo > hi || hi > 63 {
+        return Err(invalid());
+    }
+    Ok((lo, hi))
+}
+
+/// Encode a sysfs event spec such as `event=0x02,umask=0x1` into `perf_event_attr.config`
+///
+/// Terms without a value (e.g. `edge`) set the field to 1.
+pub fn encode_event_spec(spec: &str, formats: &HashMap<String, (u32, u32)>) -> Result<u64> {
+    let mut config = 0u64;
+    for term in spec.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+        let (name, value) = term.split_once('=').unwrap_or((term, "1"));
+        let &(lo, hi) = formats.get(name).ok_or_else(|| {
+            UncflowError::ParseError(format!("No format for term '{name}' in '{spec}'"))
+        })?;
+
+        let value = match value.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => value.parse::<u64>(),
+        }
+        .map_err(|e| UncflowError::ParseError(format!("Invalid value in '{term}': {e}")))?;
+
+        let width = hi - lo + 1;
+        if width < 64 && value >> width != 0 {
+            return Err(UncflowError::ParseError(format!(
+                "Value of '{term}' does not fit in {width} bits"
+            )));
+        }
+        config |= value << lo;
+    }
+    Ok(config)
+}
+
+fn read_trimmed(path: &Path) -> Result<String> {
+    Ok(std::fs::read_to_string(path)?.trim().to_string())
+}
+
+// Leading part of struct perf_event_attr, up to PERF_ATTR_SIZE_VER5
+#[repr(C)]
+#[derive(Default)]
+struct PerfEventAttr {
+    type_: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+    config2: u64,
+    branch_sample_type: u64,
+    sample_regs_user: u64,
+    sample_stack_user: u32,
+    clockid: i32,
+    sample_regs_intr: u64,
+    aux_watermark: u32,
+    sample_max_stack: u16,
+    reserved: u16,
+}
+
+/// One system-wide counter on a single CPU, counting from the moment it is opened
+#[derive(Debug)]
+pub struct PerfCounter {
+    file: File,
+}
+
+impl PerfCounter {
+    pub fn open(event: &PerfEvent, cpu: u32) -> Result<Self> {
+        let mut flags = FLAG_EXCLUDE_HV;
+        if !event.kernel {
+            flags |= FLAG_EXCLUDE_KERNEL;
+        }
+        let attr = PerfEventAttr {
+            type_: event.type_,
+            size: PERF_ATTR_SIZE,
+            config: event.config,
+            flags,
+            ..Default::default()
+        };
+
+        // pid = -1, cpu = cpu: every task on that CPU; no group, no flags
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_perf_event_open,
+                &attr as *const PerfEventAttr,
+                -1 as libc::pid_t,
+                cpu as libc::c_int,
+                -1 as libc::c_int,
+                0 as libc::c_ulong,
+            )
+        };
+        if fd < 0 {
+            let err = std::io::Error::last_os_error();
+            return Err(UncflowError::HardwareError(format!(
+                "perf_event_open(type {}, config 0x{:X}) on CPU {cpu} failed: {err}",
+                event.type_, event.config
+            )));
+        }
+
+        // SAFETY: the syscall returned a new file descriptor that we now own
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as libc::c_int) };
+        Ok(Self {
+            file: File::from(fd),
+        })
+    }
+
+    /// Current count since the counter was opened
+    pub fn read(&self) -> Result<u64> {
+        let mut buf = [0u8; 8];
+        (&self.file).read_exact(&mut buf)?;
+        Ok(u64::from_ne_bytes(buf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attr_layout() {
+        assert_eq!(
+            std::mem::size_of::<PerfEventAttr>(),
+            PERF_ATTR_SIZE as usize
+        );
+    }
+
+    #[test]
+    fn test_raw_event_encoding() {
+        // LONGEST_LAT_CACHE.MISS
+        let event = PerfEvent::raw(0x2E, 0x41, false);
+        assert_eq!(event.type_, PERF_TYPE_RAW);
+        assert_eq!(event.config, 0x412E);
+    }
+
+    #[test]
+    fn test_event_spec_encoding() {
+        let formats = HashMap::from([
+            ("event".to_string(), parse_format("config:0-7").unwrap()),
+            ("umask".to_string(), parse_format("config:8-15").unwrap()),
+            ("edge".to_string(), parse_format("config:18").unwrap()),
+        ]);
+
+        assert_eq!(encode_event_spec("event=0x02", &formats).unwrap(), 0x02);
+        assert_eq!(
+            encode_event_spec("event=0x04,umask=0x0c,edge", &formats).unwrap(),
+            0x4_0C04
+        );
+        // Value wider than its field
+        assert!(encode_event_spec("event=0x100", &formats).is_err());
+        // Term without a format
+        assert!(encode_event_spec("cmask=1", &formats).is_err());
+    }
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!(parse_format("config:0-7").unwrap(), (0, 7));
+        assert_eq!(parse_format("config:21").unwrap(), (21, 21));
+        assert!(parse_format("config1:0-7").is_err());
+        assert!(parse_format("config:8-4").is_err());
+    }
+
+    #[test]
+    fn test_parse_backend() {
+        assert_eq!(
+            "perf".parse::<CounterBackend>().unwrap(),
+            CounterBackend::Perf
+        );
+        assert_eq!(
+            "msr".parse::<CounterBackend>().unwrap(),
+            CounterBackend::Msr
+        );
+        assert!("pci".parse::<CounterBackend>().is_err());
+    }
+}
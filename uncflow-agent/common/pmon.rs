@@ -0,0 +1,51 @@
+// Uncore PMON box programming shared by the counter units
+
+use uncflow_raw::current_arch::pmon::BoxControl;
+use uncflow_raw::RegisterLayout;
+
+use crate::error::Result;
+
+/// Freeze a box and reset its counters, write its counter controls with `program`,
+/// then let the box count again
+///
+/// `write_box_ctl` writes the unit's box control register, an MSR or a PCI config
+/// space register. A failed write stops the sequence and leaves the box frozen.
+pub fn program_frozen(
+    mut write_box_ctl: impl FnMut(u64) -> Result<()>,
+    program: impl FnOnce() -> Result<()>,
+) -> Result<()> {
+    write_box_ctl(BoxControl::frozen().to_msr_value())?;
+    write_box_ctl(BoxControl::frozen_reset().to_msr_value())?;
+    program()?;
+    write_box_ctl(BoxControl::unfrozen().to_msr_value())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::UncflowError;
+    use std::cell::RefCell;
+
+    #[test]
+    fn test_program_frozen_sequence() {
+        let writes = RefCell::new(Vec::new());
+        let write_box_ctl = |value| {
+            writes.borrow_mut().push(value);
+            Ok(())
+        };
+        program_frozen(write_box_ctl, || {
+            writes.borrow_mut().push(0xC0);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(*writes.borrow(), [0x100, 0x102, 0xC0, 0]);
+
+        // A failed programming keeps the box frozen
+        writes.borrow_mut().clear();
+        let result = program_frozen(write_box_ctl, || {
+            Err(UncflowError::MsrError("locked".to_string()))
+        });
+        assert!(result.is_err());
+        assert_eq!(*writes.borrow(), [0x100, 0x102]);
+    }
+}
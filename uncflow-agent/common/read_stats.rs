@@ -0,0 +1,121 @@
+// Data-quality counters for counter reads
+//
+// A failed MSR/PCI read leaves a gap in the exported series that looks the same
+// as a real zero. Every monitor records each read of a collect cycle here, so
+// uncflow_read_errors_total / uncflow_reads_total gives the error rate per
+// subsystem and socket.
+
+use once_cell::sync::Lazy;
+use prometheus::{IntCounter, IntCounterVec, Opts, Registry};
+
+use crate::error::Result;
+
+static READ_STATS: Lazy<ReadStats> =
+    Lazy::new(|| ReadStats::new().expect("read counters have valid names"));
+
+/// Process-wide read counters, shared by every monitor
+pub fn read_stats() -> &'static ReadStats {
+    &READ_STATS
+}
+
+/// Read and read error counters labeled by subsystem and socket
+pub struct ReadStats {
+    registry: Registry,
+    reads: IntCounterVec,
+    errors: IntCounterVec,
+}
+
+impl ReadStats {
+    const LABELS: [&'static str; 2] = ["subsystem", "socket"];
+
+    pub fn new() -> Result<Self> {
+        let registry = Registry::new();
+
+        let reads = IntCounterVec::new(
+            Opts::new(
+                "uncflow_reads_total",
+                "Counter reads attempted during collection",
+            ),
+            &Self::LABELS,
+        )?;
+        registry.register(Box::new(reads.clone()))?;
+
+        let errors = IntCounterVec::new(
+            Opts::new(
+                "uncflow_read_errors_total",
+                "Counter reads that failed during collection, leaving a gap in the derived metrics",
+            ),
+            &Self::LABELS,
+        )?;
+        registry.register(Box::new(errors.clone()))?;
+
+        Ok(Self {
+            registry,
+            reads,
+            errors,
+        })
+    }
+
+    /// Handle for the reads of `subsystem` on `socket`
+    pub fn counter(&self, subsystem: &str, socket: i32) -> ReadCounter {
+        let socket = socket.to_string();
+        let labels = [subsystem, socket.as_str()];
+        ReadCounter {
+            reads: self.reads.with_label_values(&labels),
+            errors: self.errors.with_label_values(&labels),
+        }
+    }
+
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+}
+
+/// Read counters of one subsystem on one socket, cheap to clone into monitors
+#[derive(Debug, Clone)]
+pub struct ReadCounter {
+    reads: IntCounter,
+    errors: IntCounter,
+}
+
+impl ReadCounter {
+    /// Count `result` as one read, and as an error if it failed
+    pub fn observe<T>(&self, result: Result<T>) -> Result<T> {
+        self.reads.inc();
+        if result.is_err() {
+            self.errors.inc();
+        }
+        result
+    }
+
+    pub fn reads(&self) -> u64 {
+        self.reads.get()
+    }
+
+    pub fn errors(&self) -> u64 {
+        self.errors.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::UncflowError;
+
+    #[test]
+    fn test_failed_read_counts_as_error() {
+        let stats = ReadStats::new().unwrap();
+        let counter = stats.counter("imc", 1);
+
+        assert_eq!(counter.observe(Ok(5)).unwrap(), 5);
+        let failed: Result<u64> = Err(UncflowError::PciError("read failed".to_string()));
+        assert!(counter.observe(failed).is_err());
+
+        assert_eq!(counter.reads(), 2);
+        assert_eq!(counter.errors(), 1);
+        // Handles for the same labels share the series
+        assert_eq!(stats.counter("imc", 1).errors(), 1);
+        assert_eq!(stats.counter("imc", 0).errors(), 0);
+        assert_eq!(stats.registry().gather().len(), 2);
+    }
+}
@@ -0,0 +1,39 @@
+// Dry-run validation of register layouts before they reach hardware
+
+use uncflow_raw::RegisterLayout;
+
+use crate::error::{Result, UncflowError};
+
+/// Validate every register a unit is about to write, returning the first error
+///
+/// `unit` names the register set in the error, e.g. "CHA group LLC Hit".
+pub fn validate_all<'a, T: RegisterLayout + 'a>(
+    unit: &str,
+    registers: impl IntoIterator<Item = &'a T>,
+) -> Result<()> {
+    for (i, register) in registers.into_iter().enumerate() {
+        register.validate().map_err(|e| {
+            UncflowError::InvalidConfiguration(format!("{unit}, register {i}: {e}"))
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uncflow_raw::current_arch::iio::IioCounterControl;
+
+    #[test]
+    fn test_validate_all_reports_first_error() {
+        let valid = IioCounterControl::default();
+        let invalid = IioCounterControl {
+            fc_mask: 0x08,
+            ..Default::default()
+        };
+
+        assert!(validate_all("IIO", &[valid, valid]).is_ok());
+        let err = validate_all("IIO", &[valid, invalid]).unwrap_err();
+        assert!(err.to_string().contains("IIO, register 1"));
+    }
+}
@@ -0,0 +1,141 @@
+// Rotation of event groups over a unit's programmable counters
+//
+// Uncore units have fewer counters than their monitors have events, so the events
+// are split into groups that take turns on the counters. The scheduler tracks which
+// group is loaded, how long it has been counting and when the next one is due;
+// programming the hardware and keeping counter baselines stay with the monitor.
+
+use std::time::{Duration, Instant};
+
+/// Round-robin schedule of event groups of type `G`
+#[derive(Debug, Clone)]
+pub struct EventScheduler<G> {
+    groups: Vec<G>,
+    current: usize,
+    // When the current group started counting
+    started_at: Instant,
+    rotation_interval: Duration,
+}
+
+impl<G> EventScheduler<G> {
+    /// Empty schedule moving to the next group every `rotation_interval`
+    pub fn new(rotation_interval: Duration) -> Self {
+        Self {
+            groups: Vec::new(),
+            current: 0,
+            started_at: Instant::now(),
+            rotation_interval,
+        }
+    }
+
+    /// Schedule of `groups`, starting at the first
+    pub fn with_groups(groups: Vec<G>, rotation_interval: Duration) -> Self {
+        Self {
+            groups,
+            ..Self::new(rotation_interval)
+        }
+    }
+
+    /// Append `group` to the rotation
+    pub fn push(&mut self, group: G) {
+        self.groups.push(group);
+    }
+
+    pub fn groups(&self) -> &[G] {
+        &self.groups
+    }
+
+    pub fn len(&self) -> usize {
+        self.groups.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// Group currently on the counters, None for an empty schedule
+    pub fn current(&self) -> Option<&G> {
+        self.groups.get(self.current)
+    }
+
+    pub fn current_index(&self) -> usize {
+        self.current
+    }
+
+    pub fn rotation_interval(&self) -> Duration {
+        self.rotation_interval
+    }
+
+    pub fn set_rotation_interval(&mut self, rotation_interval: Duration) {
+        self.rotation_interval = rotation_interval;
+    }
+
+    /// How long the current group has been counting
+    pub fn elapsed(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    /// Whether the current group has counted for a full rotation interval
+    pub fn should_rotate(&self) -> bool {
+        !self.groups.is_empty() && self.elapsed() >= self.rotation_interval
+    }
+
+    /// Move to the next group, wrapping around after the last, and start its window
+    pub fn rotate(&mut self) -> Option<&G> {
+        if self.groups.is_empty() {
+            return None;
+        }
+        self.current = (self.current + 1) % self.groups.len();
+        self.started_at = Instant::now();
+        self.current()
+    }
+
+    /// Start the current group's window over, after its counters were re-baselined
+    pub fn reset(&mut self) {
+        self.started_at = Instant::now();
+    }
+
+    /// Pretend the current group started counting `by` earlier
+    #[cfg(test)]
+    pub(crate) fn backdate(&mut self, by: Duration) {
+        self.started_at -= by;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotation_wraps_around() {
+        let mut scheduler = EventScheduler::with_groups(vec!["a", "b", "c"], Duration::ZERO);
+        assert_eq!(scheduler.current(), Some(&"a"));
+        assert_eq!(scheduler.rotate(), Some(&"b"));
+        assert_eq!(scheduler.rotate(), Some(&"c"));
+        assert_eq!(scheduler.rotate(), Some(&"a"));
+        assert_eq!(scheduler.current_index(), 0);
+
+        let mut empty = EventScheduler::<&str>::new(Duration::ZERO);
+        assert!(!empty.should_rotate());
+        assert_eq!(empty.rotate(), None);
+    }
+
+    #[test]
+    fn test_reset_restarts_the_window() {
+        let mut scheduler = EventScheduler::with_groups(vec![1, 2], Duration::from_secs(2));
+        assert!(!scheduler.should_rotate());
+        scheduler.backdate(Duration::from_secs(3));
+        assert!(scheduler.should_rotate());
+
+        // Same group, fresh window
+        scheduler.reset();
+        assert!(!scheduler.should_rotate());
+        assert!(scheduler.elapsed() < Duration::from_secs(1));
+        assert_eq!(scheduler.current(), Some(&1));
+
+        // Rotating starts the next group's window too
+        scheduler.backdate(Duration::from_secs(3));
+        scheduler.rotate();
+        assert!(!scheduler.should_rotate());
+    }
+}
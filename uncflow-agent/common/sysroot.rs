@@ -0,0 +1,194 @@
+// Filesystem roots for sysfs, procfs and device nodes
+// Lets the agent run in a container against a host filesystem mounted elsewhere
+
+use once_cell::sync::OnceCell;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Result, UncflowError};
+
+/// Environment variable naming a directory that holds the host's sys/, proc/ and dev/
+pub const SYSROOT_ENV: &str = "UNCFLOW_SYSROOT";
+
+// Legacy container layout: host /sys and /proc mounted under /pcm, /dev passed through
+const DOCKER_RUNNING_ENV: &str = "DOCKER_RUNNING";
+const DOCKER_ROOT: &str = "/pcm";
+
+static SYS_ROOTS: OnceCell<SysRoots> = OnceCell::new();
+
+/// Root directories every sysfs/procfs/dev lookup is resolved against
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SysRoots {
+    pub sys: PathBuf,
+    pub proc: PathBuf,
+    pub dev: PathBuf,
+}
+
+impl SysRoots {
+    /// The running system's own /sys, /proc and /dev
+    pub fn host() -> Self {
+        Self::under("/")
+    }
+
+    /// sys/, proc/ and dev/ below `root`
+    pub fn under(root: impl AsRef<Path>) -> Self {
+        let root = root.as_ref();
+        Self {
+            sys: root.join("sys"),
+            proc: root.join("proc"),
+            dev: root.join("dev"),
+        }
+    }
+
+    /// Roots from `UNCFLOW_SYSROOT`, falling back to the legacy `DOCKER_RUNNING`
+    /// layout, then to the host
+    pub fn from_env() -> Self {
+        if let Some(root) = std::env::var_os(SYSROOT_ENV) {
+            return Self::under(root);
+        }
+        if std::env::var_os(DOCKER_RUNNING_ENV).is_some() {
+            return Self {
+                dev: PathBuf::from("/dev"),
+                ..Self::under(DOCKER_ROOT)
+            };
+        }
+        Self::host()
+    }
+
+    /// MSR device node for `cpu`
+    pub fn msr_path(&self, cpu: u32) -> PathBuf {
+        self.dev.join(format!("cpu/{cpu}/msr"))
+    }
+
+    /// Directory of per-bus PCI config space files
+    pub fn pci_bus_dir(&self) -> PathBuf {
+        self.proc.join("bus/pci")
+    }
+
+    /// sysfs PCI devices, one `DDDD:BB:dd.f` directory each
+    pub fn pci_devices_dir(&self) -> PathBuf {
+        self.sys.join("bus/pci/devices")
+    }
+
+    /// ACPI MCFG table (PCIe enhanced configuration space ranges)
+    pub fn mcfg_path(&self) -> PathBuf {
+        self.sys.join("firmware/acpi/tables/MCFG")
+    }
+
+    /// List of online CPUs, e.g. "0-3,8-11"
+    pub fn cpu_online_path(&self) -> PathBuf {
+        self.sys.join("devices/system/cpu/online")
+    }
+
+    /// Base frequency of `cpu` in kHz, as reported by cpufreq
+    pub fn base_frequency_path(&self, cpu: i32) -> PathBuf {
+        self.sys.join(format!(
+            "devices/system/cpu/cpu{cpu}/cpufreq/base_frequency"
+        ))
+    }
+
+    /// sysfs directory of a perf PMU, e.g. `power`
+    pub fn perf_pmu_dir(&self, pmu: &str) -> PathBuf {
+        self.sys.join("bus/event_source/devices").join(pmu)
+    }
+
+    /// Physical package (socket) id of `cpu`
+    pub fn package_id_path(&self, cpu: i32) -> PathBuf {
+        self.sys.join(format!(
+            "devices/system/cpu/cpu{cpu}/topology/physical_package_id"
+        ))
+    }
+
+    /// NUMA nodes, one `nodeN` directory each with the node's `cpulist`
+    pub fn numa_nodes_dir(&self) -> PathBuf {
+        self.sys.join("devices/system/node")
+    }
+
+    /// Raw SMBIOS structures, one directory per entry such as `17-0/`
+    pub fn dmi_entries_dir(&self) -> PathBuf {
+        self.sys.join("firmware/dmi/entries")
+    }
+
+    /// libnvdimm devices; `nmemN` entries are the installed NVDIMMs (e.g. Optane DC)
+    pub fn nvdimm_dir(&self) -> PathBuf {
+        self.sys.join("bus/nd/devices")
+    }
+
+    /// msr driver's `allow_writes` parameter: on, off or default
+    pub fn msr_allow_writes_path(&self) -> PathBuf {
+        self.sys.join("module/msr/parameters/allow_writes")
+    }
+
+    /// Kernel lockdown modes, the active one in brackets
+    pub fn lockdown_path(&self) -> PathBuf {
+        self.sys.join("kernel/security/lockdown")
+    }
+
+    /// Mount point of the resctrl filesystem
+    pub fn resctrl_dir(&self) -> PathBuf {
+        self.sys.join("fs/resctrl")
+    }
+}
+
+/// Set the process-wide roots; must happen before the first hardware access
+pub fn init(roots: SysRoots) -> Result<()> {
+    SYS_ROOTS
+        .set(roots)
+        .map_err(|_| UncflowError::ConfigError("System roots were already initialized".to_string()))
+}
+
+/// Process-wide roots, read from the environment if [`init`] was never called
+pub fn sys_roots() -> &'static SysRoots {
+    SYS_ROOTS.get_or_init(SysRoots::from_env)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host_paths() {
+        let roots = SysRoots::host();
+        assert_eq!(roots.msr_path(3), PathBuf::from("/dev/cpu/3/msr"));
+        assert_eq!(roots.pci_bus_dir(), PathBuf::from("/proc/bus/pci"));
+        assert_eq!(
+            roots.mcfg_path(),
+            PathBuf::from("/sys/firmware/acpi/tables/MCFG")
+        );
+    }
+
+    #[test]
+    fn test_custom_root_paths() {
+        let roots = SysRoots::under("/host");
+        assert_eq!(roots.msr_path(0), PathBuf::from("/host/dev/cpu/0/msr"));
+        assert_eq!(roots.pci_bus_dir(), PathBuf::from("/host/proc/bus/pci"));
+        assert_eq!(
+            roots.mcfg_path(),
+            PathBuf::from("/host/sys/firmware/acpi/tables/MCFG")
+        );
+        assert_eq!(
+            roots.cpu_online_path(),
+            PathBuf::from("/host/sys/devices/system/cpu/online")
+        );
+        assert_eq!(
+            roots.perf_pmu_dir("power"),
+            PathBuf::from("/host/sys/bus/event_source/devices/power")
+        );
+        assert_eq!(
+            roots.package_id_path(5),
+            PathBuf::from("/host/sys/devices/system/cpu/cpu5/topology/physical_package_id")
+        );
+        assert_eq!(roots.resctrl_dir(), PathBuf::from("/host/sys/fs/resctrl"));
+        assert_eq!(
+            roots.numa_nodes_dir(),
+            PathBuf::from("/host/sys/devices/system/node")
+        );
+        assert_eq!(
+            roots.nvdimm_dir(),
+            PathBuf::from("/host/sys/bus/nd/devices")
+        );
+        assert_eq!(
+            roots.dmi_entries_dir(),
+            PathBuf::from("/host/sys/firmware/dmi/entries")
+        );
+    }
+}
@@ -1,10 +1,77 @@
+use crate::common::{sys_roots, CounterBackend, NumaTopology};
+use crate::counters::cha::ChaCustomEvents;
+use crate::counters::core::CustomEvent;
+use crate::error::{Result, UncflowError};
+use crate::prom::cha::ChaUnavailable;
 use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Debug, Clone)]
 pub struct ExportConfig {
     pub sockets: Vec<i32>,
     pub cores: Vec<i32>,
     pub core_labels: HashMap<i32, String>,
+    /// Number of sub-samples taken within each export interval
+    pub sample_count: u32,
+    /// Export CHA counters per box in addition to the socket aggregate
+    pub cha_per_box: bool,
+    /// Read only this many evenly spaced CHA boxes and scale the socket aggregate
+    /// up to all boxes (see counters::cha::monitor); None reads every box
+    pub cha_sample_boxes: Option<usize>,
+    /// Export how old each rotated CHA metric's data is
+    pub cha_metric_age: bool,
+    /// What the CHA metrics of sockets whose counters cannot be programmed export
+    pub cha_unavailable: ChaUnavailable,
+    /// User-defined CHA events (--events-from-json), programmed instead of the built-in groups
+    pub cha_custom_events: Option<ChaCustomEvents>,
+    /// Count DRAM page events instead of IMC queue occupancy
+    pub imc_page_events: bool,
+    /// Count IMC thermal throttle cycles and read DIMM temperatures
+    pub imc_thermal: bool,
+    /// Count IIO IOMMU lookups, misses and page walks
+    pub iio_iommu: bool,
+    /// Label the per-port IIO series with the PCIe device behind each root port
+    pub iio_device_labels: bool,
+    /// Export IMC bandwidth/occupancy per channel in addition to the socket aggregate
+    pub imc_per_channel: bool,
+    /// Export IRP metrics per unit in addition to the socket aggregate
+    pub irp_per_unit: bool,
+    /// Export raw per-interval counter deltas next to the derived metrics
+    pub export_raw: bool,
+    /// Export per-window event counts as per-second rates (see metrics::kind)
+    pub count_rates: bool,
+    /// Export socket totals of the per-channel/per-port bandwidth series
+    pub rollups: bool,
+    /// How Core and RAPL counters are read
+    pub backend: CounterBackend,
+    /// Never program counters, collect only free-running and read-only ones
+    pub read_only_counters: bool,
+    /// Multiplex the AVX frequency license events with the default core events
+    pub core_avx_license: bool,
+    /// Multiplex offcore response events (local vs remote DRAM) with the default core events
+    pub core_offcore: bool,
+    /// Export per-socket sums and ratios of the core metrics
+    pub core_socket_rollup: bool,
+    /// Extra core PMU events (--core-event), multiplexed with the default core events
+    pub core_events: Vec<CustomEvent>,
+    /// resctrl monitoring groups read by RDT instead of programming RMIDs per core
+    pub resctrl_groups: Vec<String>,
+    /// Peak memory bandwidth per socket in GB/s, overriding the DMI-derived value
+    pub memory_peak_bandwidth_gbps: Option<f64>,
+    /// Refuse to start when the exporters would register more series than this
+    pub max_series: usize,
+    /// Abandon a subsystem's collection when its counter reads take longer than this
+    pub read_timeout: Duration,
+    /// Core each socket's CHA/IIO/IRP MSRs are accessed from, instead of the
+    /// monitors' default core
+    pub uncore_cores: HashMap<i32, u32>,
+    /// File the cumulative counters are restored from and saved to (see prom::state)
+    pub state_file: Option<PathBuf>,
+    /// NUMA topology IMC/CHA metrics are attributed with, None without --numa-node
+    pub numa_topology: Option<NumaTopology>,
+    /// NUMA nodes exported with a numa_node label (--numa-node)
+    pub numa_nodes: Vec<i32>,
 }
 
 impl ExportConfig {
@@ -20,9 +87,308 @@ impl ExportConfig {
             sockets,
             cores,
             core_labels,
+            sample_count: 1,
+            cha_per_box: false,
+            cha_sample_boxes: None,
+            cha_metric_age: false,
+            cha_unavailable: ChaUnavailable::default(),
+            cha_custom_events: None,
+            imc_page_events: false,
+            imc_thermal: false,
+            iio_iommu: false,
+            iio_device_labels: false,
+            imc_per_channel: false,
+            irp_per_unit: false,
+            export_raw: false,
+            count_rates: false,
+            rollups: false,
+            backend: CounterBackend::Msr,
+            read_only_counters: false,
+            core_avx_license: false,
+            core_offcore: false,
+            core_socket_rollup: false,
+            core_events: Vec::new(),
+            resctrl_groups: Vec::new(),
+            memory_peak_bandwidth_gbps: None,
+            max_series: crate::orchestrator::DEFAULT_MAX_SERIES,
+            read_timeout: crate::orchestrator::DEFAULT_READ_TIMEOUT,
+            uncore_cores: HashMap::new(),
+            state_file: None,
+            numa_topology: None,
+            numa_nodes: Vec::new(),
         }
     }
 
+    /// Take `sample_count` evenly spaced sub-samples per export interval, within half
+    /// the read timeout
+    pub fn with_sample_count(mut self, sample_count: u32) -> Self {
+        self.sample_count = sample_count.max(1);
+        self
+    }
+
+    /// Also export per-CHA-box counters (one series per box, so higher cardinality)
+    pub fn with_cha_per_box(mut self, cha_per_box: bool) -> Self {
+        self.cha_per_box = cha_per_box;
+        self
+    }
+
+    /// Sample `cha_sample_boxes` CHA boxes per socket instead of reading all of them
+    pub fn with_cha_sample_boxes(mut self, cha_sample_boxes: Option<usize>) -> Self {
+        self.cha_sample_boxes = cha_sample_boxes;
+        self
+    }
+
+    /// Count activates and page-miss precharges on IMC counters 2 and 3
+    pub fn with_imc_page_events(mut self, imc_page_events: bool) -> Self {
+        self.imc_page_events = imc_page_events;
+        self
+    }
+
+    /// Export CHAMetricAgeSeconds alongside the CHA metrics
+    pub fn with_cha_metric_age(mut self, cha_metric_age: bool) -> Self {
+        self.cha_metric_age = cha_metric_age;
+        self
+    }
+
+    /// Omit the CHA metrics of locked sockets, or export them as NaN
+    pub fn with_cha_unavailable(mut self, cha_unavailable: ChaUnavailable) -> Self {
+        self.cha_unavailable = cha_unavailable;
+        self
+    }
+
+    /// Count thermal throttle cycles on IMC counter 3 and export DIMM temperatures
+    pub fn with_imc_thermal(mut self, imc_thermal: bool) -> Self {
+        self.imc_thermal = imc_thermal;
+        self
+    }
+
+    /// Add the IOMMU event group to the IIO rotation
+    pub fn with_iio_iommu(mut self, iio_iommu: bool) -> Self {
+        self.iio_iommu = iio_iommu;
+        self
+    }
+
+    /// Add pci_vendor, pci_device and pci_device_name labels to the per-port IIO series
+    pub fn with_iio_device_labels(mut self, iio_device_labels: bool) -> Self {
+        self.iio_device_labels = iio_device_labels;
+        self
+    }
+
+    /// Also export per-channel IMC bandwidth and queue occupancy
+    pub fn with_imc_per_channel(mut self, imc_per_channel: bool) -> Self {
+        self.imc_per_channel = imc_per_channel;
+        self
+    }
+
+    /// Also export per-unit IRP metrics
+    pub fn with_irp_per_unit(mut self, irp_per_unit: bool) -> Self {
+        self.irp_per_unit = irp_per_unit;
+        self
+    }
+
+    /// Also export the counter deltas metrics are derived from (one series per counter)
+    pub fn with_export_raw(mut self, export_raw: bool) -> Self {
+        self.export_raw = export_raw;
+        self
+    }
+
+    /// Divide CHA and IIO event counts by their window and export them per second
+    pub fn with_count_rates(mut self, count_rates: bool) -> Self {
+        self.count_rates = count_rates;
+        self
+    }
+
+    /// Also export socket-total memory and PCIe bandwidth
+    pub fn with_rollups(mut self, rollups: bool) -> Self {
+        self.rollups = rollups;
+        self
+    }
+
+    /// Also count cycles per AVX frequency license on every core
+    pub fn with_core_avx_license(mut self, core_avx_license: bool) -> Self {
+        self.core_avx_license = core_avx_license;
+        self
+    }
+
+    /// Also count LLC misses served by local and remote DRAM on every core
+    pub fn with_core_offcore(mut self, core_offcore: bool) -> Self {
+        self.core_offcore = core_offcore;
+        self
+    }
+
+    /// Also export the core metrics summed per socket (core_socket_<metric>)
+    pub fn with_core_socket_rollup(mut self, core_socket_rollup: bool) -> Self {
+        self.core_socket_rollup = core_socket_rollup;
+        self
+    }
+
+    /// Program these user-defined CHA events and export them under their own names
+    pub fn with_cha_custom_events(mut self, cha_custom_events: Option<ChaCustomEvents>) -> Self {
+        self.cha_custom_events = cha_custom_events;
+        self
+    }
+
+    /// Also count these events on every core, exported as core_event_count
+    pub fn with_core_events(mut self, core_events: Vec<CustomEvent>) -> Self {
+        self.core_events = core_events;
+        self
+    }
+
+    /// Read RDT counters of these resctrl monitoring groups (paths below the
+    /// resctrl mount) instead of assigning an RMID to every core
+    pub fn with_resctrl_groups(mut self, resctrl_groups: Vec<String>) -> Self {
+        self.resctrl_groups = resctrl_groups;
+        self
+    }
+
+    /// Divide IMC bandwidth by this per-socket peak (GB/s) instead of the one
+    /// derived from DIMM speed and channel count
+    pub fn with_memory_peak_bandwidth_gbps(mut self, peak: Option<f64>) -> Self {
+        self.memory_peak_bandwidth_gbps = peak;
+        self
+    }
+
+    /// Fail startup instead of registering more than `max_series` series
+    pub fn with_max_series(mut self, max_series: usize) -> Self {
+        self.max_series = max_series;
+        self
+    }
+
+    /// Skip a subsystem for the interval when its reads block longer than `read_timeout`
+    pub fn with_read_timeout(mut self, read_timeout: Duration) -> Self {
+        self.read_timeout = read_timeout;
+        self
+    }
+
+    /// Access each socket's uncore MSRs from these cores (socket -> core)
+    pub fn with_uncore_cores(mut self, uncore_cores: HashMap<i32, u32>) -> Self {
+        self.uncore_cores = uncore_cores;
+        self
+    }
+
+    /// Core to access the uncore MSRs of `socket` from, `default` unless overridden
+    pub fn uncore_core(&self, socket: i32, default: u32) -> u32 {
+        self.uncore_cores.get(&socket).copied().unwrap_or(default)
+    }
+
+    /// Parse a `SOCKET:CORE` uncore core override
+    pub fn parse_uncore_core(spec: &str) -> Result<(i32, u32)> {
+        let parsed = spec.split_once(':').and_then(|(socket, core)| {
+            Some((socket.trim().parse().ok()?, core.trim().parse().ok()?))
+        });
+        parsed.ok_or_else(|| {
+            UncflowError::InvalidConfiguration(format!(
+                "Invalid uncore core '{spec}', expected SOCKET:CORE (e.g. 1:57)"
+            ))
+        })
+    }
+
+    /// Check that the override `core` for `socket` sits on that socket, given the
+    /// package the core belongs to (`None` when it does not exist)
+    pub fn check_uncore_core(socket: i32, core: u32, core_socket: Option<i32>) -> Result<()> {
+        match core_socket {
+            Some(package) if package == socket => Ok(()),
+            Some(package) => Err(UncflowError::InvalidConfiguration(format!(
+                "Uncore core {core} is on socket {package}, not socket {socket}"
+            ))),
+            None => Err(UncflowError::InvalidConfiguration(format!(
+                "Uncore core {core} for socket {socket} does not exist or is offline"
+            ))),
+        }
+    }
+
+    /// Socket (physical package) of `core`, read from sysfs
+    pub fn core_socket(core: u32) -> Option<i32> {
+        std::fs::read_to_string(sys_roots().package_id_path(core as i32))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    }
+
+    /// Restore the cumulative counters from `state_file` and save them to it on shutdown
+    pub fn with_state_file(mut self, state_file: Option<PathBuf>) -> Self {
+        self.state_file = state_file;
+        self
+    }
+
+    /// Also export IMC and CHA metrics of `nodes`, attributed with `topology`
+    pub fn with_numa_nodes(mut self, topology: NumaTopology, nodes: Vec<i32>) -> Self {
+        self.numa_topology = (!nodes.is_empty()).then_some(topology);
+        self.numa_nodes = nodes;
+        self
+    }
+
+    /// Selected NUMA node unit `index` of `count` on `socket` is attributed to (see
+    /// common::numa), None if its node is not selected
+    pub fn numa_node_of_unit(&self, socket: i32, index: usize, count: usize) -> Option<i32> {
+        let node = self
+            .numa_topology
+            .as_ref()?
+            .node_of_unit(socket, index, count)?;
+        self.numa_nodes.contains(&node).then_some(node)
+    }
+
+    /// Read Core and RAPL counters through `backend`
+    pub fn with_backend(mut self, backend: CounterBackend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Skip every subsystem that has to program counters and read IIO PCIe
+    /// traffic from its free-running counters only
+    pub fn with_read_only_counters(mut self, read_only_counters: bool) -> Self {
+        self.read_only_counters = read_only_counters;
+        self
+    }
+
+    /// Create a configuration from user-requested IDs, dropping cores that are
+    /// not online and sockets that have no online CPU
+    pub fn validated(sockets: Vec<i32>, cores: Vec<i32>) -> Result<Self> {
+        let cores = Self::retain_available(&cores, &Self::detect_online_cpus(), "core");
+        if cores.is_empty() {
+            return Err(UncflowError::InvalidConfiguration(
+                "None of the requested cores exist or are online".to_string(),
+            ));
+        }
+
+        let sockets = Self::retain_available(&sockets, &Self::detect_online_sockets(), "socket");
+        if sockets.is_empty() {
+            return Err(UncflowError::InvalidConfiguration(
+                "None of the requested sockets exist or have online CPUs".to_string(),
+            ));
+        }
+
+        Ok(Self::new(sockets, cores))
+    }
+
+    /// This configuration limited to `selected` sockets, e.g. for a subsystem
+    /// enabled on some sockets only; all sockets when `selected` is empty
+    pub fn restricted_to(&self, selected: &[i32]) -> Self {
+        let mut config = self.clone();
+        if !selected.is_empty() {
+            config.sockets.retain(|socket| selected.contains(socket));
+        }
+        config
+    }
+
+    /// Keep only the requested IDs present in `available`, warning about the rest
+    pub fn retain_available(requested: &[i32], available: &[i32], kind: &str) -> Vec<i32> {
+        let (kept, dropped): (Vec<i32>, Vec<i32>) =
+            requested.iter().partition(|id| available.contains(id));
+
+        if !dropped.is_empty() {
+            tracing::warn!(
+                "Ignoring {} {:?}: not present or offline (available: {:?})",
+                kind,
+                dropped,
+                available
+            );
+        }
+
+        kept
+    }
+
     /// Auto-detect all available CPUs in the system
     pub fn auto_detect() -> Self {
         let cores = Self::detect_online_cpus();
@@ -39,7 +405,7 @@ impl ExportConfig {
 
     /// Detect online CPUs from /sys/devices/system/cpu/online
     pub fn detect_online_cpus() -> Vec<i32> {
-        std::fs::read_to_string("/sys/devices/system/cpu/online")
+        std::fs::read_to_string(sys_roots().cpu_online_path())
             .ok()
             .and_then(|s| Self::parse_cpu_list(&s))
             .unwrap_or_else(|| {
@@ -63,13 +429,17 @@ impl ExportConfig {
         Some(cpus)
     }
 
+    /// Detect sockets that have at least one online CPU
+    pub fn detect_online_sockets() -> Vec<i32> {
+        Self::detect_sockets(&Self::detect_online_cpus())
+    }
+
     /// Detect which sockets the cores belong to
     pub fn detect_sockets(cores: &[i32]) -> Vec<i32> {
         let mut sockets = std::collections::HashSet::new();
 
         for &core in cores {
-            let socket_path =
-                format!("/sys/devices/system/cpu/cpu{core}/topology/physical_package_id");
+            let socket_path = sys_roots().package_id_path(core);
             if let Ok(socket_str) = std::fs::read_to_string(&socket_path) {
                 if let Ok(socket) = socket_str.trim().parse::<i32>() {
                     sockets.insert(socket);
@@ -87,3 +457,60 @@ impl ExportConfig {
         socket_vec
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!(
+            ExportConfig::parse_cpu_list("0-3,8-9\n"),
+            Some(vec![0, 1, 2, 3, 8, 9])
+        );
+        assert_eq!(ExportConfig::parse_cpu_list("5"), Some(vec![5]));
+        assert_eq!(ExportConfig::parse_cpu_list("a-3"), None);
+    }
+
+    #[test]
+    fn test_retain_available() {
+        let online = ExportConfig::parse_cpu_list("0-3,8-11").unwrap();
+        let requested: Vec<i32> = (0..=9).collect();
+
+        assert_eq!(
+            ExportConfig::retain_available(&requested, &online, "core"),
+            vec![0, 1, 2, 3, 8, 9]
+        );
+        assert!(ExportConfig::retain_available(&[999], &online, "core").is_empty());
+    }
+
+    #[test]
+    fn test_uncore_core_override() {
+        assert_eq!(ExportConfig::parse_uncore_core("1:57").unwrap(), (1, 57));
+        assert_eq!(ExportConfig::parse_uncore_core(" 0 : 3 ").unwrap(), (0, 3));
+        for spec in ["1", "1:", "a:3", "1:-2", "1:2:3"] {
+            assert!(ExportConfig::parse_uncore_core(spec).is_err(), "{spec}");
+        }
+
+        let config =
+            ExportConfig::new(vec![0, 1], vec![0]).with_uncore_cores(HashMap::from([(1, 57)]));
+        assert_eq!(config.uncore_core(1, 28), 57);
+        assert_eq!(config.uncore_core(0, 0), 0);
+
+        assert!(ExportConfig::check_uncore_core(1, 57, Some(1)).is_ok());
+        let err = ExportConfig::check_uncore_core(1, 3, Some(0)).unwrap_err();
+        assert!(
+            err.to_string().contains("on socket 0, not socket 1"),
+            "{err}"
+        );
+        assert!(ExportConfig::check_uncore_core(1, 999, None).is_err());
+    }
+
+    #[test]
+    fn test_restricted_to() {
+        let config = ExportConfig::new(vec![0, 1, 2], vec![0]);
+        assert_eq!(config.restricted_to(&[]).sockets, vec![0, 1, 2]);
+        assert_eq!(config.restricted_to(&[2, 0]).sockets, vec![0, 2]);
+        assert!(config.restricted_to(&[3]).sockets.is_empty());
+    }
+}
@@ -0,0 +1,806 @@
+// User-defined CHA events loaded from JSON (--events-from-json)
+//
+// For events the built-in groups do not cover, in the spirit of Intel's perfmon
+// event files. One file programs one group on every CHA box of a socket:
+//
+//     {
+//       "unit": "cha",
+//       "filter": { "opcode": "0x202", "state": "0x7F" },
+//       "events": [
+//         { "name": "drd_occupancy", "counter": 0, "event": "0x36", "umask": "0x21" },
+//         { "name": "drd_inserts", "counter": 1, "event": "0x35", "umask": "0x21" },
+//         { "name": "cha_clockticks", "counter": 2, "event": "0x00" }
+//       ],
+//       "metrics": [
+//         { "name": "drd_latency_ns",
+//           "formula": "drd_occupancy / drd_inserts / (cha_clockticks / seconds) * 1e9" }
+//       ]
+//     }
+//
+// Numbers are JSON numbers or strings in decimal or 0x hex. Counter controls take
+// the optional `threshold`, `invert` and `edge` too; the filter `opcode`, `state`
+// and `tid`, shared by all four counters. Events are exported as their count over
+// the last collection window summed over the boxes, metrics as their formula
+// over those counts: + - * / and parentheses, event names, numbers, and `seconds`
+// for the window length. A metric whose formula divides by zero is not updated.
+//
+// The group owns the CHA counters, so it replaces the built-in CHA metrics.
+
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::common::counter::{wrapping_delta, Baseline};
+use crate::common::{
+    arch::CPU_ARCH,
+    msr::{self, MsrAccess},
+    read_stats, register, ReadCounter,
+};
+use crate::error::{Result, UncflowError};
+
+use uncflow_raw::current_arch::cha::{
+    self, ChaBoxControl, ChaCounterControl, ChaFilter0, ChaFilter1,
+};
+use uncflow_raw::RegisterLayout;
+
+/// Formula variable holding the collection window in seconds
+pub const SECONDS_VARIABLE: &str = "seconds";
+
+/// A JSON number, or a string in decimal or 0x hex
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum JsonNumber {
+    Number(u64),
+    Text(String),
+}
+
+impl JsonNumber {
+    fn parse(&self, field: &str) -> Result<u64> {
+        match self {
+            Self::Number(value) => Ok(*value),
+            Self::Text(text) => {
+                let text = text.trim();
+                match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+                    Some(hex) => u64::from_str_radix(hex, 16),
+                    None => text.parse(),
+                }
+                .map_err(|_| invalid(format!("{field} '{text}' is not a number")))
+            }
+        }
+    }
+
+    /// Parsed value that must fit in `T`
+    fn parse_as<T: TryFrom<u64>>(&self, field: &str) -> Result<T> {
+        let value = self.parse(field)?;
+        T::try_from(value).map_err(|_| invalid(format!("{field} 0x{value:X} is out of range")))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileSpec {
+    unit: String,
+    #[serde(default)]
+    filter: FilterSpec,
+    events: Vec<EventSpec>,
+    #[serde(default)]
+    metrics: Vec<MetricSpec>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FilterSpec {
+    opcode: Option<JsonNumber>,
+    state: Option<JsonNumber>,
+    tid: Option<JsonNumber>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EventSpec {
+    name: String,
+    counter: usize,
+    event: JsonNumber,
+    umask: Option<JsonNumber>,
+    threshold: Option<JsonNumber>,
+    #[serde(default)]
+    invert: bool,
+    #[serde(default)]
+    edge: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MetricSpec {
+    name: String,
+    formula: String,
+}
+
+fn invalid(reason: impl std::fmt::Display) -> UncflowError {
+    UncflowError::InvalidConfiguration(format!("Invalid CHA event file: {reason}"))
+}
+
+/// Whether `name` is a valid Prometheus metric name
+fn is_metric_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == ':')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+/// One user event and the counter it is programmed on
+#[derive(Debug, Clone)]
+pub struct ChaCustomEvent {
+    pub name: String,
+    pub counter: usize,
+    pub control: ChaCounterControl,
+}
+
+/// One user metric derived from the event counts
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChaDerivedMetric {
+    pub name: String,
+    pub formula: Formula,
+}
+
+/// A validated event file: the registers to program and what to export
+#[derive(Debug, Clone)]
+pub struct ChaCustomEvents {
+    pub filter0: Option<ChaFilter0>,
+    pub filter1: Option<ChaFilter1>,
+    pub events: Vec<ChaCustomEvent>,
+    pub metrics: Vec<ChaDerivedMetric>,
+}
+
+impl ChaCustomEvents {
+    /// Read and validate an event file
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path).map_err(|e| {
+            UncflowError::ConfigError(format!("Cannot read {}: {e}", path.display()))
+        })?;
+        contents.parse()
+    }
+
+    /// Names of the events and metrics, in export order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.events
+            .iter()
+            .map(|e| e.name.as_str())
+            .chain(self.metrics.iter().map(|m| m.name.as_str()))
+    }
+
+    /// Check every register against its layout
+    pub fn validate(&self) -> Result<()> {
+        let unit = "CHA event file";
+        register::validate_all(unit, &self.filter0)?;
+        register::validate_all(unit, &self.filter1)?;
+        register::validate_all(unit, self.events.iter().map(|e| &e.control))
+    }
+}
+
+impl FromStr for ChaCustomEvents {
+    type Err = UncflowError;
+
+    fn from_str(json: &str) -> Result<Self> {
+        let spec: FileSpec = serde_json::from_str(json).map_err(invalid)?;
+        if !spec.unit.eq_ignore_ascii_case("cha") {
+            return Err(invalid(format!(
+                "unit '{}' is not supported, only 'cha' is",
+                spec.unit
+            )));
+        }
+
+        // Filters are only written when they select something, like the built-in groups
+        let filter0 = match &spec.filter.opcode {
+            Some(opcode) => Some(ChaFilter0 {
+                opcode_match: opcode.parse_as("opcode")?,
+            }),
+            None => None,
+        };
+        let state: u16 = match &spec.filter.state {
+            Some(state) => state.parse_as("state")?,
+            None => 0,
+        };
+        let tid: u32 = match &spec.filter.tid {
+            Some(tid) => tid.parse_as("tid")?,
+            None => 0,
+        };
+        let filter1 = (state != 0 || tid != 0).then_some(ChaFilter1 { tid, state });
+
+        if spec.events.is_empty() {
+            return Err(invalid("no events"));
+        }
+        let mut events: Vec<ChaCustomEvent> = Vec::new();
+        for event in spec.events {
+            if event.counter >= cha::COUNTERS_PER_CHA {
+                return Err(invalid(format!(
+                    "event {}: counter {} does not exist, CHA boxes have {}",
+                    event.name,
+                    event.counter,
+                    cha::COUNTERS_PER_CHA
+                )));
+            }
+            if let Some(other) = events.iter().find(|e| e.counter == event.counter) {
+                return Err(invalid(format!(
+                    "events {} and {} both use counter {}",
+                    other.name, event.name, event.counter
+                )));
+            }
+            let control = ChaCounterControl {
+                event_select: event.event.parse_as("event")?,
+                unit_mask: match &event.umask {
+                    Some(umask) => umask.parse_as("umask")?,
+                    None => 0,
+                },
+                threshold: match &event.threshold {
+                    Some(threshold) => threshold.parse_as("threshold")?,
+                    None => 0,
+                },
+                invert: event.invert,
+                edge_detect: event.edge,
+                enable: true,
+                ..Default::default()
+            };
+            events.push(ChaCustomEvent {
+                name: event.name,
+                counter: event.counter,
+                control,
+            });
+        }
+
+        let mut metrics = Vec::new();
+        for metric in spec.metrics {
+            let formula: Formula = metric
+                .formula
+                .parse()
+                .map_err(|e| invalid(format!("metric {}: {e}", metric.name)))?;
+            for variable in formula.variables() {
+                if variable != SECONDS_VARIABLE && !events.iter().any(|e| e.name == variable) {
+                    return Err(invalid(format!(
+                        "metric {}: '{variable}' is not an event",
+                        metric.name
+                    )));
+                }
+            }
+            metrics.push(ChaDerivedMetric {
+                name: metric.name,
+                formula,
+            });
+        }
+
+        let parsed = Self {
+            filter0,
+            filter1,
+            events,
+            metrics,
+        };
+
+        let mut seen = Vec::new();
+        for name in parsed.names() {
+            if !is_metric_name(name) || name == SECONDS_VARIABLE {
+                return Err(invalid(format!("'{name}' is not a valid metric name")));
+            }
+            if seen.contains(&name) {
+                return Err(invalid(format!("'{name}' is defined twice")));
+            }
+            seen.push(name);
+        }
+
+        parsed.validate()?;
+        Ok(parsed)
+    }
+}
+
+/// Arithmetic operator of a formula
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+/// Parsed derived-metric formula
+#[derive(Debug, Clone, PartialEq)]
+pub enum Formula {
+    Number(f64),
+    Variable(String),
+    Negate(Box<Formula>),
+    Binary(Box<Formula>, Operator, Box<Formula>),
+}
+
+impl Formula {
+    /// Value with each variable looked up in `values`; None for an unknown variable,
+    /// a division by zero or any other non-finite result
+    pub fn evaluate(&self, values: &HashMap<&str, f64>) -> Option<f64> {
+        let value = match self {
+            Self::Number(value) => *value,
+            Self::Variable(name) => *values.get(name.as_str())?,
+            Self::Negate(inner) => -inner.evaluate(values)?,
+            Self::Binary(lhs, op, rhs) => {
+                let (lhs, rhs) = (lhs.evaluate(values)?, rhs.evaluate(values)?);
+                match op {
+                    Operator::Add => lhs + rhs,
+                    Operator::Subtract => lhs - rhs,
+                    Operator::Multiply => lhs * rhs,
+                    Operator::Divide if rhs == 0.0 => return None,
+                    Operator::Divide => lhs / rhs,
+                }
+            }
+        };
+        value.is_finite().then_some(value)
+    }
+
+    /// Names of the variables the formula reads
+    pub fn variables(&self) -> Vec<&str> {
+        match self {
+            Self::Number(_) => Vec::new(),
+            Self::Variable(name) => vec![name.as_str()],
+            Self::Negate(inner) => inner.variables(),
+            Self::Binary(lhs, _, rhs) => {
+                let mut variables = lhs.variables();
+                variables.extend(rhs.variables());
+                variables
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Name(String),
+    Symbol(char),
+}
+
+fn tokenize(formula: &str) -> std::result::Result<Vec<Token>, String> {
+    let chars: Vec<char> = formula.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if "+-*/()".contains(c) {
+            tokens.push(Token::Symbol(c));
+            i += 1;
+        } else if c.is_ascii_digit() || c == '.' {
+            let start = i;
+            while i < chars.len()
+                && (chars[i].is_ascii_digit()
+                    || chars[i] == '.'
+                    || (matches!(chars[i], 'e' | 'E') && i + 1 < chars.len())
+                    || (matches!(chars[i], '+' | '-') && matches!(chars[i - 1], 'e' | 'E')))
+            {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            let value = text
+                .parse()
+                .map_err(|_| format!("'{text}' is not a number"))?;
+            tokens.push(Token::Number(value));
+        } else if c.is_ascii_alphabetic() || c == '_' || c == ':' {
+            let start = i;
+            while i < chars.len()
+                && (chars[i].is_ascii_alphanumeric() || chars[i] == '_' || chars[i] == ':')
+            {
+                i += 1;
+            }
+            tokens.push(Token::Name(chars[start..i].iter().collect()));
+        } else {
+            return Err(format!("unexpected '{c}'"));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent over the tokens: sums of products of factors
+struct FormulaParser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl FormulaParser {
+    fn peek_symbol(&self) -> Option<char> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Symbol(c)) => Some(*c),
+            _ => None,
+        }
+    }
+
+    fn expression(&mut self) -> std::result::Result<Formula, String> {
+        let mut lhs = self.term()?;
+        while let Some(op @ ('+' | '-')) = self.peek_symbol() {
+            self.pos += 1;
+            let op = if op == '+' {
+                Operator::Add
+            } else {
+                Operator::Subtract
+            };
+            lhs = Formula::Binary(Box::new(lhs), op, Box::new(self.term()?));
+        }
+        Ok(lhs)
+    }
+
+    fn term(&mut self) -> std::result::Result<Formula, String> {
+        let mut lhs = self.factor()?;
+        while let Some(op @ ('*' | '/')) = self.peek_symbol() {
+            self.pos += 1;
+            let op = if op == '*' {
+                Operator::Multiply
+            } else {
+                Operator::Divide
+            };
+            lhs = Formula::Binary(Box::new(lhs), op, Box::new(self.factor()?));
+        }
+        Ok(lhs)
+    }
+
+    fn factor(&mut self) -> std::result::Result<Formula, String> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or("unexpected end of formula")?;
+        self.pos += 1;
+        match token {
+            Token::Number(value) => Ok(Formula::Number(value)),
+            Token::Name(name) => Ok(Formula::Variable(name)),
+            Token::Symbol('-') => Ok(Formula::Negate(Box::new(self.factor()?))),
+            Token::Symbol('(') => {
+                let inner = self.expression()?;
+                match self.peek_symbol() {
+                    Some(')') => {
+                        self.pos += 1;
+                        Ok(inner)
+                    }
+                    _ => Err("missing ')'".to_string()),
+                }
+            }
+            Token::Symbol(c) => Err(format!("unexpected '{c}'")),
+        }
+    }
+}
+
+impl FromStr for Formula {
+    type Err = UncflowError;
+
+    fn from_str(formula: &str) -> Result<Self> {
+        let error = |reason: String| {
+            UncflowError::ParseError(format!("Invalid formula '{formula}': {reason}"))
+        };
+        let mut parser = FormulaParser {
+            tokens: tokenize(formula).map_err(error)?,
+            pos: 0,
+        };
+        let parsed = parser.expression().map_err(error)?;
+        if parser.pos != parser.tokens.len() {
+            return Err(error("trailing input".to_string()));
+        }
+        Ok(parsed)
+    }
+}
+
+/// Event counts and derived metrics of one collection window
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChaCustomSample {
+    pub window: Duration,
+    /// Count of each event over the window, summed over all boxes
+    pub events: BTreeMap<String, u64>,
+    /// Every metric whose formula could be evaluated
+    pub metrics: BTreeMap<String, f64>,
+}
+
+/// Programs an event file on every CHA box of one socket and reads it back
+pub struct ChaCustomMonitor {
+    socket: i32,
+    cha_count: usize,
+    core: u32,
+    events: ChaCustomEvents,
+    msr: &'static dyn MsrAccess,
+    // Counter values of the previous read, per box and event
+    baseline: Baseline<HashMap<usize, Vec<u64>>>,
+    reads: ReadCounter,
+}
+
+impl ChaCustomMonitor {
+    /// Monitor accessing the CHA MSRs of `socket` from `core`
+    pub fn with_core(
+        socket: i32,
+        core: u32,
+        events: ChaCustomEvents,
+        msr: &'static dyn MsrAccess,
+    ) -> Result<Self> {
+        msr::ensure_uncore_unfrozen(msr, core, "CHA")?;
+        Ok(Self {
+            socket,
+            cha_count: CPU_ARCH.cha_count().unwrap_or(28) as usize,
+            core,
+            events,
+            msr,
+            baseline: Baseline::default(),
+            reads: read_stats().counter("cha", socket),
+        })
+    }
+
+    /// Number of CHA boxes of the socket
+    pub fn cha_count(&self) -> usize {
+        self.cha_count
+    }
+
+    /// Program the event file on every box, failing if any control does not stick
+    pub fn initialize(&mut self) -> Result<()> {
+        self.events.validate()?;
+        for cha_id in 0..self.cha_count {
+            self.program_box(cha_id).map_err(|e| {
+                UncflowError::HardwareError(format!(
+                    "CHA box {cha_id} of socket {}: {e}",
+                    self.socket
+                ))
+            })?;
+        }
+        tracing::info!(
+            "Programmed {} custom CHA events on {} boxes of socket {}",
+            self.events.events.len(),
+            self.cha_count,
+            self.socket
+        );
+        Ok(())
+    }
+
+    fn program_box(&self, cha_id: usize) -> Result<()> {
+        let box_ctl = cha::msr::box_ctl(cha_id);
+        self.msr
+            .write(self.core, box_ctl, ChaBoxControl::frozen().to_msr_value())?;
+
+        if let Some(filter0) = self.events.filter0 {
+            self.msr
+                .write(self.core, cha::msr::filter0(cha_id), filter0.to_msr_value())?;
+        }
+        if let Some(filter1) = self.events.filter1 {
+            self.msr
+                .write(self.core, cha::msr::filter1(cha_id), filter1.to_msr_value())?;
+        }
+        for event in &self.events.events {
+            msr::write_verified(
+                self.msr,
+                self.core,
+                cha::msr::counter_ctl(cha_id, event.counter),
+                event.control.to_msr_value(),
+                ChaCounterControl::VERIFY_MASK,
+            )?;
+        }
+
+        self.msr
+            .write(self.core, box_ctl, ChaBoxControl::unfrozen().to_msr_value())
+    }
+
+    fn read_box(&self, cha_id: usize) -> Result<Vec<u64>> {
+        self.events
+            .events
+            .iter()
+            .map(|event| {
+                self.reads.observe(
+                    self.msr
+                        .read(self.core, cha::msr::counter_value(cha_id, event.counter)),
+                )
+            })
+            .collect()
+    }
+
+    /// Read every box, returning the window since the previous read
+    ///
+    /// The first read only sets a baseline, and one too soon after the previous
+    /// read is skipped, both returning None.
+    pub fn collect(&mut self) -> Result<Option<ChaCustomSample>> {
+        let now = Instant::now();
+        if self.baseline.too_soon(now) {
+            return Ok(None);
+        }
+
+        let mut readings = HashMap::with_capacity(self.cha_count);
+        for cha_id in 0..self.cha_count {
+            readings.insert(cha_id, self.read_box(cha_id)?);
+        }
+        let Some((prev, window)) = self.baseline.advance(readings.clone(), now) else {
+            return Ok(None);
+        };
+
+        let mut counts = vec![0u64; self.events.events.len()];
+        for (cha_id, current) in &readings {
+            let Some(before) = prev.get(cha_id) else {
+                continue;
+            };
+            for (count, (&before, &current)) in counts.iter_mut().zip(before.iter().zip(current)) {
+                *count += wrapping_delta(before, current, cha::COUNTER_WIDTH_BITS);
+            }
+        }
+
+        let events: BTreeMap<String, u64> = self
+            .events
+            .events
+            .iter()
+            .map(|event| event.name.clone())
+            .zip(counts)
+            .collect();
+
+        let mut values: HashMap<&str, f64> = events
+            .iter()
+            .map(|(name, &count)| (name.as_str(), count as f64))
+            .collect();
+        values.insert(SECONDS_VARIABLE, window.as_secs_f64());
+        let metrics = self
+            .events
+            .metrics
+            .iter()
+            .filter_map(|metric| {
+                let value = metric.formula.evaluate(&values)?;
+                Some((metric.name.clone(), value))
+            })
+            .collect();
+
+        Ok(Some(ChaCustomSample {
+            window,
+            events,
+            metrics,
+        }))
+    }
+
+    /// Drop the baseline, the next collect() is treated as a first sample
+    pub fn reset(&mut self) {
+        self.baseline.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::msr::mock::MockMsr;
+
+    const EVENT_FILE: &str = r#"{
+        "unit": "cha",
+        "filter": { "opcode": "0x202", "state": "0x7F" },
+        "events": [
+            { "name": "drd_occupancy", "counter": 0, "event": "0x36", "umask": "0x21" },
+            { "name": "drd_inserts", "counter": 1, "event": 53, "umask": "0x21",
+              "threshold": 2, "edge": true },
+            { "name": "cha_clockticks", "counter": 3, "event": "0x00" }
+        ],
+        "metrics": [
+            { "name": "drd_latency_ns",
+              "formula": "drd_occupancy / drd_inserts / (cha_clockticks / seconds) * 1e9" },
+            { "name": "drd_inserts_per_second", "formula": "drd_inserts / seconds" }
+        ]
+    }"#;
+
+    #[test]
+    fn test_parse_event_file_programs_controls() {
+        let events: ChaCustomEvents = EVENT_FILE.parse().unwrap();
+        assert_eq!(
+            events.names().collect::<Vec<_>>(),
+            [
+                "drd_occupancy",
+                "drd_inserts",
+                "cha_clockticks",
+                "drd_latency_ns",
+                "drd_inserts_per_second"
+            ]
+        );
+        assert_eq!(events.filter0.unwrap().to_msr_value(), 0x202);
+        assert_eq!(events.filter1.unwrap().to_msr_value(), 0x7F << 17);
+
+        let msr = MockMsr::new().leak();
+        let mut monitor = ChaCustomMonitor::with_core(0, 0, events, msr).unwrap();
+        monitor.cha_count = 2;
+        monitor.initialize().unwrap();
+
+        for cha_id in 0..2 {
+            assert_eq!(msr.get(0, cha::msr::filter0(cha_id)), Some(0x202));
+            assert_eq!(
+                msr.get(0, cha::msr::counter_ctl(cha_id, 0)),
+                Some(0x40_2136)
+            );
+            // Threshold 2, edge detect
+            assert_eq!(
+                msr.get(0, cha::msr::counter_ctl(cha_id, 1)),
+                Some(0x0244_2135)
+            );
+            assert_eq!(msr.get(0, cha::msr::counter_ctl(cha_id, 2)), None);
+            assert_eq!(
+                msr.get(0, cha::msr::counter_ctl(cha_id, 3)),
+                Some(0x40_0000)
+            );
+            assert_eq!(
+                msr.get(0, cha::msr::box_ctl(cha_id)),
+                Some(ChaBoxControl::unfrozen().to_msr_value())
+            );
+        }
+    }
+
+    #[test]
+    fn test_collect_sums_boxes_and_derives_metrics() {
+        let msr = MockMsr::new().leak();
+        let mut monitor =
+            ChaCustomMonitor::with_core(0, 0, EVENT_FILE.parse().unwrap(), msr).unwrap();
+        monitor.cha_count = 2;
+        monitor.initialize().unwrap();
+        assert_eq!(monitor.collect().unwrap(), None);
+
+        for cha_id in 0..2 {
+            msr.set(0, cha::msr::counter_value(cha_id, 0), 3000);
+            msr.set(0, cha::msr::counter_value(cha_id, 1), 100);
+            msr.set(0, cha::msr::counter_value(cha_id, 3), 50_000_000);
+        }
+        std::thread::sleep(Duration::from_millis(20));
+        let sample = monitor.collect().unwrap().unwrap();
+
+        assert_eq!(sample.events["drd_occupancy"], 6000);
+        assert_eq!(sample.events["drd_inserts"], 200);
+        let secs = sample.window.as_secs_f64();
+        let latency = 6000.0 / 200.0 / (100_000_000.0 / secs) * 1e9;
+        assert!((sample.metrics["drd_latency_ns"] - latency).abs() < 1e-6);
+        assert!((sample.metrics["drd_inserts_per_second"] - 200.0 / secs).abs() < 1e-6);
+
+        // Nothing inserted: the latency divides by zero and is left out
+        std::thread::sleep(Duration::from_millis(20));
+        let sample = monitor.collect().unwrap().unwrap();
+        assert_eq!(sample.events["drd_inserts"], 0);
+        assert!(!sample.metrics.contains_key("drd_latency_ns"));
+        assert_eq!(sample.metrics["drd_inserts_per_second"], 0.0);
+    }
+
+    #[test]
+    fn test_formula_precedence() {
+        let values = HashMap::from([("a", 6.0), ("b", 2.0)]);
+        let eval = |formula: &str| formula.parse::<Formula>().unwrap().evaluate(&values);
+        assert_eq!(eval("a + b * 3"), Some(12.0));
+        assert_eq!(eval("(a + b) * 3"), Some(24.0));
+        assert_eq!(eval("a / b / 3"), Some(1.0));
+        assert_eq!(eval("-a - -b"), Some(-4.0));
+        assert_eq!(eval("2.5e2 - a"), Some(244.0));
+        assert_eq!(eval("a / (b - 2)"), None);
+        assert_eq!(eval("missing"), None);
+
+        for formula in ["", "a +", "(a", "a b", "a % b", "1e"] {
+            assert!(formula.parse::<Formula>().is_err(), "{formula}");
+        }
+    }
+
+    #[test]
+    fn test_reject_invalid_event_files() {
+        let file = |events: &str, metrics: &str| {
+            format!(r#"{{ "unit": "cha", "events": [{events}], "metrics": [{metrics}] }}"#)
+        };
+        let event = |name: &str, counter: usize, rest: &str| {
+            format!(r#"{{ "name": "{name}", "counter": {counter}, "event": "0x35"{rest} }}"#)
+        };
+        for json in [
+            file(&event("a", 4, ""), ""),
+            file(&format!("{},{}", event("a", 0, ""), event("b", 0, "")), ""),
+            file(&format!("{},{}", event("a", 0, ""), event("a", 1, "")), ""),
+            file(&event("a-b", 0, ""), ""),
+            file(&event("a", 0, r#", "umask": "0x100""#), ""),
+            file(&event("a", 0, r#", "threshold": 64"#), ""),
+            file(&event("a", 0, r#", "period": 1"#), ""),
+            file(&event("a", 0, ""), r#"{ "name": "m", "formula": "a / b" }"#),
+            file(&event("a", 0, ""), r#"{ "name": "a", "formula": "a * 2" }"#),
+            file("", ""),
+            r#"{ "unit": "iio", "events": [] }"#.to_string(),
+            r#"{ "unit": "cha", "filter": { "state": "0x800" }, "events": [] }"#.to_string(),
+            "not json".to_string(),
+        ] {
+            assert!(json.parse::<ChaCustomEvents>().is_err(), "{json}");
+        }
+
+        // A state outside the defined bits fails RegisterLayout::validate
+        let json = format!(
+            r#"{{ "unit": "cha", "filter": {{ "state": "0x3FF" }}, "events": [{}] }}"#,
+            event("a", 0, "")
+        );
+        let err = json.parse::<ChaCustomEvents>().unwrap_err();
+        assert!(err.to_string().contains("State"), "{err}");
+    }
+}
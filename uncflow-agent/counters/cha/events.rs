@@ -1,5 +1,8 @@
 // CHA Event Configurations for Skylake-SP
-use crate::{enum_with_data, enum_with_opcodes};
+
+use uncflow_raw::current_arch::cha::{events, states, umasks};
+
+use crate::metrics::cha::CreditType;
 
 // Transaction types for CHA cache transaction monitoring
 enum_with_opcodes! {
@@ -18,16 +21,16 @@ enum_with_opcodes! {
     }
 }
 
-// LLC cache line states
+// LLC cache line states, one filter1 state bit each
 enum_with_data! {
     pub enum LLCState: u32 {
-        M => ("M", 0x40),       // Modified
-        E => ("E", 0x20),       // Exclusive
-        S => ("S", 0x02),       // Shared
-        I => ("I", 0x01),       // Invalid
-        SFM => ("SFM", 0x08),   // Snoop Filter Modified
-        SFE => ("SFE", 0x04),   // Snoop Filter Exclusive
-        SFS => ("SFS", 0x02),   // Snoop Filter Shared
+        M => ("M", states::M as u32),       // Modified
+        E => ("E", states::E as u32),       // Exclusive
+        S => ("S", states::S as u32),       // Shared
+        I => ("I", states::I as u32),       // Invalid
+        SFM => ("SFM", states::SFM as u32), // Snoop Filter Modified (H state)
+        SFE => ("SFE", states::SFE as u32), // Snoop Filter Exclusive
+        SFS => ("SFS", states::SFS as u32), // Snoop Filter Shared
     }
     impl state_value -> u32
 }
@@ -166,6 +169,45 @@ impl ChaEventConfig {
         }
     }
 
+    /// Create the snoop filter event config
+    ///
+    /// Counts valid snoop filter entries in the occupancy counter and snoop filter
+    /// evictions, each a back-invalidation of core caches, in the insert counter.
+    pub fn sf_occupancy() -> Self {
+        Self {
+            name: "SF Occupancy".to_string(),
+            transaction_type: None,
+            is_hit: None,
+            events: [
+                (events::SF_OCCUPANCY, umasks::sf::ALL),
+                (events::SF_EVICTION, umasks::sf::ALL),
+                (events::CLOCKTICKS, 0x00),
+                (0x00, 0x00),
+            ],
+            opc0: 0,
+            opc1: 0,
+            state: 0,
+        }
+    }
+
+    /// Create a no-credit stall event config, counted in the insert counter
+    pub fn credit_stall(credit: CreditType) -> Self {
+        Self {
+            name: credit.event_name().to_string(),
+            transaction_type: None,
+            is_hit: None,
+            events: [
+                (0x00, 0x00), // No occupancy for rejects
+                (events::RXC_IRQ0_REJECT, credit.umask()),
+                (events::CLOCKTICKS, 0x00),
+                (0x00, 0x00),
+            ],
+            opc0: 0,
+            opc1: 0,
+            state: 0,
+        }
+    }
+
     /// Generate all transaction event configs (22 total: 11 types × 2 hit/miss)
     pub fn all_transactions() -> Vec<Self> {
         let mut configs = Vec::new();
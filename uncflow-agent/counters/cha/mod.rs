@@ -1,5 +1,7 @@
+pub mod custom;
 pub mod events;
 pub mod monitor;
 
+pub use custom::{ChaCustomEvents, ChaCustomMonitor, ChaCustomSample};
 pub use events::{BasicEventType, ChaEventConfig, LLCLookupType, LLCState, TransactionType};
-pub use monitor::ChaMonitor;
+pub use monitor::{ChaBoxDelta, ChaGroupDeltas, ChaMonitor};
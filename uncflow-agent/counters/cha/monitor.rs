@@ -1,13 +1,40 @@
 // CHA (Cache Home Agent) monitoring with comprehensive event collection
 // Supports event rotation for full transaction coverage
 //
+// A transaction needs occupancy, insert and clockticks for both hit and miss, six
+// values for four counters per box. Each transaction slot is therefore measured in
+// two passes on every collect: hit events are programmed and counted for
+// TRANSACTION_PASS_DURATION, then miss events for the same length, so both halves
+// come from the same collect. The cost is that collect() blocks for twice the pass
+// duration (200ms) and reprograms every box twice.
+//
+// Event data is per interval: each measurement of a group replaces its counts and
+// duration with those of the window just read. Groups not measured in a collect
+// keep the data of their last turn, see RawEventData::measured_at.
+//
+// Boxes are only reprogrammed when the group to count differs from the one they
+// hold: rotating back onto the loaded group, or into a multi-pass slot whose first
+// pass the rotation already programmed, writes nothing and leaves the counters alone.
+//
+// With a box sample (--cha-sample-boxes) only that many evenly spaced boxes are
+// programmed and read, and the socket aggregate is scaled by total / sampled boxes.
+// This cuts the MSR accesses per collect proportionally but is only accurate when
+// traffic is spread uniformly over the boxes, as the address hash intends; a hot
+// set of lines homed on unsampled boxes goes unseen, and one homed on a sampled box
+// is overcounted by the scale factor. Per-box deltas are exported unscaled.
+//
 // Now uses uncflow-raw for type-safe hardware register programming
 
-use crate::common::{arch::CPU_ARCH, msr};
-use crate::counters::cha::ChaEventConfig;
-use crate::error::Result;
-use crate::metrics::cha::RawEventData;
-use std::collections::HashMap;
+use crate::common::{
+    arch::CPU_ARCH,
+    counter::{wrapping_delta, Baseline},
+    msr::{self, MsrAccess},
+    read_stats, register, EventScheduler, ReadCounter,
+};
+use crate::counters::cha::{ChaEventConfig, TransactionType};
+use crate::error::{Result, UncflowError};
+use crate::metrics::cha::{CreditType, LiveCounters, RawEventData};
+use std::collections::{BTreeMap, HashMap};
 use std::time::{Duration, Instant};
 
 // Import hardware definitions from uncflow-raw
@@ -33,84 +60,191 @@ impl EventGroup {
             counter_configs,
         }
     }
+
+    /// Build the filter and counter registers this group programs, without writing them
+    fn registers(&self) -> Result<GroupRegisters> {
+        let invalid = |field: &str, value: u32| {
+            UncflowError::InvalidConfiguration(format!(
+                "CHA group {}: {field} 0x{value:X} does not fit its filter field",
+                self.name
+            ))
+        };
+
+        // Filter 0 is only needed for transaction opcodes
+        let filter0 = match self.config.opc0 {
+            0 => None,
+            opc0 => Some(ChaFilter0 {
+                opcode_match: u16::try_from(opc0).map_err(|_| invalid("opcode", opc0))?,
+            }),
+        };
+
+        // Filter 1 is only needed for cache line states
+        let filter1 = match self.config.state {
+            0 => None,
+            state => Some(ChaFilter1 {
+                tid: 0,
+                state: u16::try_from(state).map_err(|_| invalid("state", state))?,
+            }),
+        };
+
+        let counters = self.counter_configs.map(|(event, umask)| {
+            (event != 0 || umask != 0).then_some(ChaCounterControl {
+                event_select: event,
+                unit_mask: umask,
+                enable: true,
+                ..Default::default()
+            })
+        });
+
+        Ok(GroupRegisters {
+            filter0,
+            filter1,
+            counters,
+        })
+    }
 }
 
-/// Event rotation scheduler
-struct EventScheduler {
-    groups: Vec<EventGroup>,
-    current_index: usize,
-    last_rotation: Instant,
-    rotation_interval: Duration,
+/// One rotation slot: a single group counted continuously across collects, or
+/// several groups measured back to back in sequential passes on every collect
+#[derive(Debug, Clone)]
+struct EventSlot {
+    passes: Vec<EventGroup>,
 }
 
-impl EventScheduler {
-    fn new(rotation_interval: Duration) -> Self {
+impl EventSlot {
+    fn single(config: ChaEventConfig) -> Self {
         Self {
-            groups: Vec::new(),
-            current_index: 0,
-            last_rotation: Instant::now(),
-            rotation_interval,
+            passes: vec![EventGroup::from_config(config)],
         }
     }
 
-    fn add_event_group(&mut self, config: ChaEventConfig) {
-        self.groups.push(EventGroup::from_config(config));
+    /// Slot measuring hit and miss of `trans_type` in two passes
+    fn transaction(trans_type: TransactionType) -> Self {
+        Self {
+            passes: vec![
+                EventGroup::from_config(ChaEventConfig::transaction(trans_type, true)),
+                EventGroup::from_config(ChaEventConfig::transaction(trans_type, false)),
+            ],
+        }
     }
 
-    fn should_rotate(&self) -> bool {
-        self.last_rotation.elapsed() >= self.rotation_interval
+    /// Slot measuring every no-credit stall group, one pass each
+    fn credit_stalls() -> Self {
+        Self {
+            passes: CreditType::all()
+                .into_iter()
+                .map(|credit| EventGroup::from_config(ChaEventConfig::credit_stall(credit)))
+                .collect(),
+        }
     }
 
-    fn get_current_group(&self) -> Option<&EventGroup> {
-        self.groups.get(self.current_index)
+    fn is_multi_pass(&self) -> bool {
+        self.passes.len() > 1
     }
+}
 
-    fn rotate(&mut self) {
-        if self.groups.is_empty() {
-            return;
-        }
-
-        self.current_index = (self.current_index + 1) % self.groups.len();
-        self.last_rotation = Instant::now();
-    }
+/// Registers written when an event group is programmed; `None` entries are left untouched
+struct GroupRegisters {
+    filter0: Option<ChaFilter0>,
+    filter1: Option<ChaFilter1>,
+    counters: [Option<ChaCounterControl>; 4],
+}
 
-    fn current_group_index(&self) -> usize {
-        self.current_index
+impl GroupRegisters {
+    fn validate(&self, group: &str) -> Result<()> {
+        let unit = format!("CHA group {group}");
+        register::validate_all(&unit, &self.filter0)?;
+        register::validate_all(&unit, &self.filter1)?;
+        register::validate_all(&unit, self.counters.iter().flatten())
     }
 }
 
+/// How long each pass of a multi-pass slot counts
+const TRANSACTION_PASS_DURATION: Duration = Duration::from_millis(100);
+
 /// Raw counter values for one CHA unit
 #[derive(Debug, Clone, Default)]
 struct ChaRawCounters {
     counter0: u64,
     counter1: u64,
     counter2: u64,
-    counter3: u64,
+}
+
+/// Counter deltas of one CHA box for the event group measured in the last collect()
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChaBoxDelta {
+    pub occupancy: u64,
+    pub insert: u64,
+    pub clockticks: u64,
+}
+
+/// Per-box deltas of one event group measured in the last collect()
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChaGroupDeltas {
+    pub group: String,
+    /// Counters that were programmed on every box; dead ones read as zero
+    pub live: LiveCounters,
+    /// CHA box each entry of `boxes` was read from, every box unless sampling
+    pub box_ids: Vec<usize>,
+    pub boxes: Vec<ChaBoxDelta>,
 }
 
 /// CHA Monitor with comprehensive event collection
 pub struct ChaMonitor {
     _socket: i32,
     cha_count: usize,
+    // Number of boxes actually read, None for all of them
+    sample_boxes: Option<usize>,
     representative_core: u32,
 
     // Event rotation
-    scheduler: EventScheduler,
+    scheduler: EventScheduler<EventSlot>,
 
-    // Previous counter values per CHA unit
-    prev_counters: HashMap<usize, ChaRawCounters>,
+    // Counter values per CHA unit at the start of the open single-pass window: the
+    // last read, or none yet after reprogramming or reset
+    baseline: Baseline<HashMap<usize, ChaRawCounters>>,
 
-    // Accumulated event data (aggregated across all CHA units)
+    // Latest window of each event group (aggregated across all CHA units)
     event_data: HashMap<String, RawEventData>,
 
-    // Collection start time
-    collection_start: Instant,
+    // Counters of the current event group that programmed on every box
+    live: LiveCounters,
+
+    // Event group the boxes are programmed with, None until one programmed successfully
+    loaded: Option<String>,
+
+    // Per-box deltas of every group measured in the last collect()
+    box_deltas: Vec<ChaGroupDeltas>,
+
+    // Counting time of each pass in multi-pass slots
+    pass_duration: Duration,
+
+    msr: &'static dyn MsrAccess,
+
+    // Counter reads and failed reads of this socket
+    reads: ReadCounter,
 }
 
 impl ChaMonitor {
     pub fn new(socket: i32) -> Result<Self> {
+        Self::with_msr(socket, msr::Msr::instance())
+    }
+
+    /// Create a monitor on top of a specific MSR backend
+    pub fn with_msr(socket: i32, msr: &'static dyn MsrAccess) -> Result<Self> {
+        Self::with_core(socket, Self::default_core(socket), msr)
+    }
+
+    /// Core the CHA MSRs of `socket` are accessed from unless overridden
+    pub fn default_core(socket: i32) -> u32 {
+        (socket * 28) as u32
+    }
+
+    /// Create a monitor accessing the CHA MSRs from `core`, which must be on `socket`
+    pub fn with_core(socket: i32, core: u32, msr: &'static dyn MsrAccess) -> Result<Self> {
         let cha_count = CPU_ARCH.cha_count().unwrap_or(28) as usize;
-        let representative_core = (socket * 28) as u32;
+        let representative_core = core;
+        msr::ensure_uncore_unfrozen(msr, core, "CHA")?;
 
         tracing::info!(
             "Initializing comprehensive CHA monitor for socket {} with {} CHA boxes",
@@ -124,229 +258,406 @@ impl ChaMonitor {
         Ok(Self {
             _socket: socket,
             cha_count,
+            sample_boxes: None,
             representative_core,
             scheduler,
-            prev_counters: HashMap::new(),
+            baseline: Baseline::default(),
             event_data: HashMap::new(),
-            collection_start: Instant::now(),
+            live: LiveCounters::ALL,
+            loaded: None,
+            box_deltas: Vec::new(),
+            pass_duration: TRANSACTION_PASS_DURATION,
+            msr,
+            reads: read_stats().counter("cha", socket),
         })
     }
 
+    /// Only program and read `sample_boxes` evenly spaced boxes, scaling the aggregate
+    ///
+    /// Must be set before initialize(). A sample covering every box reads them all.
+    pub fn with_sample_boxes(mut self, sample_boxes: Option<usize>) -> Self {
+        self.sample_boxes = sample_boxes;
+        self
+    }
+
+    /// Number of CHA boxes of the socket
+    pub fn cha_count(&self) -> usize {
+        self.cha_count
+    }
+
+    fn box_ids(&self) -> Vec<usize> {
+        sampled_box_ids(self.cha_count, self.sample_boxes)
+    }
+
     pub fn initialize(&mut self) -> Result<()> {
         // Setup event rotation with all transaction types
         self.setup_event_rotation();
 
+        // Every group is programmed during rotation, so check them all before any write
+        self.validate_program()?;
+
         // Program initial event group
-        if let Some(group) = self.scheduler.get_current_group() {
-            for cha_id in 0..self.cha_count {
-                self.program_event_group(cha_id, group)?;
-            }
+        if let Some(slot) = self.scheduler.current().cloned() {
+            self.program_all_boxes(&slot.passes[0])?;
         }
+        // The first collect reads the baseline
+        self.baseline.reset();
 
         Ok(())
     }
 
+    /// Build and validate the registers of every scheduled event group without touching hardware
+    pub fn validate_program(&self) -> Result<()> {
+        for group in self.scheduler.groups().iter().flat_map(|slot| &slot.passes) {
+            group.registers()?.validate(&group.name)?;
+        }
+        Ok(())
+    }
+
     fn setup_event_rotation(&mut self) {
-        // Add all transaction event groups (hit and miss)
-        for config in ChaEventConfig::all_transactions() {
-            self.scheduler.add_event_group(config);
+        // One two-pass slot (hit, then miss) per transaction type
+        for trans_type in TransactionType::all() {
+            self.scheduler.push(EventSlot::transaction(trans_type));
         }
+        // Snoop filter occupancy and back-invalidations in a single pass
+        self.scheduler
+            .push(EventSlot::single(ChaEventConfig::sf_occupancy()));
+        // Read and write no-credit stalls, also as one multi-pass slot
+        self.scheduler.push(EventSlot::credit_stalls());
 
         tracing::info!(
-            "Setup event rotation with {} groups (rotation every {:?})",
-            self.scheduler.groups.len(),
-            self.scheduler.rotation_interval
+            "Setup event rotation with {} slots (rotation every {:?}, {:?} per pass)",
+            self.scheduler.len(),
+            self.scheduler.rotation_interval(),
+            self.pass_duration
         );
     }
 
-    fn program_event_group(&self, cha_id: usize, group: &EventGroup) -> Result<()> {
-        let box_ctl_addr = cha::msr::box_ctl(cha_id);
+    /// Program `group` on every CHA box, best-effort per counter
+    ///
+    /// A counter that fails on any box is excluded from the group's metrics; only
+    /// when none of them can be programmed is the group an error. Nothing is
+    /// written if the boxes already hold `group`.
+    fn program_all_boxes(&mut self, group: &EventGroup) -> Result<()> {
+        if self.loaded.as_deref() == Some(group.name.as_str()) {
+            return Ok(());
+        }
+        // Partially written boxes hold no known group
+        self.loaded = None;
+
+        // Counter index -> boxes it failed on
+        let mut dead: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        let mut last_error = None;
 
-        // Freeze the CHA box using type-safe struct
-        let freeze_ctrl = ChaBoxControl {
-            freeze: true,
-            freeze_enable: true,
-            ..Default::default()
+        for cha_id in self.box_ids() {
+            for (counter, e) in self.program_event_group(cha_id, group)? {
+                tracing::debug!("CHA box {cha_id} counter {counter}: {e}");
+                dead.entry(counter).or_default().push(cha_id);
+                last_error = Some(e);
+            }
+        }
+
+        // Fail only if every counter the group actually programs is dead
+        let programmed = group.registers()?.counters.map(|ctrl| ctrl.is_some());
+        let any_live = (0..programmed.len()).any(|i| programmed[i] && !dead.contains_key(&i));
+        if let (false, Some(e)) = (any_live, last_error) {
+            return Err(e);
+        }
+
+        self.live = LiveCounters {
+            occupancy: !dead.contains_key(&0),
+            insert: !dead.contains_key(&1),
+            clockticks: !dead.contains_key(&2),
         };
-        msr::Msr::instance().write(
+
+        for (counter, boxes) in &dead {
+            tracing::warn!(
+                "CHA group {}: counter {} not programmable on boxes {:?}, excluding it from metrics",
+                group.name,
+                counter,
+                boxes
+            );
+        }
+
+        self.loaded = Some(group.name.clone());
+        Ok(())
+    }
+
+    /// Program one CHA box, returning the counters whose control write did not stick
+    fn program_event_group(
+        &self,
+        cha_id: usize,
+        group: &EventGroup,
+    ) -> Result<Vec<(usize, UncflowError)>> {
+        let registers = group.registers()?;
+        let box_ctl_addr = cha::msr::box_ctl(cha_id);
+
+        // Freeze the CHA box while the filters and counters are reprogrammed
+        self.msr.write(
             self.representative_core,
             box_ctl_addr,
-            freeze_ctrl.to_msr_value(),
+            ChaBoxControl::frozen().to_msr_value(),
         )?;
 
-        // Setup filter 0 if needed (for transaction opcodes)
-        if group.config.opc0 != 0 {
-            let filter0 = ChaFilter0 {
-                opcode_match: group.config.opc0 as u16,
-            };
-            let filter0_addr = cha::msr::filter0(cha_id);
-            msr::Msr::instance().write(
+        if let Some(filter0) = registers.filter0 {
+            self.msr.write(
                 self.representative_core,
-                filter0_addr,
+                cha::msr::filter0(cha_id),
                 filter0.to_msr_value(),
             )?;
         }
 
-        // Setup filter 1 if needed (for cache line states)
-        if group.config.state != 0 {
-            let filter1 = ChaFilter1 {
-                tid: 0,
-                state: group.config.state as u8,
-            };
-            let filter1_addr = cha::msr::filter1(cha_id);
-            msr::Msr::instance().write(
+        if let Some(filter1) = registers.filter1 {
+            self.msr.write(
                 self.representative_core,
-                filter1_addr,
+                cha::msr::filter1(cha_id),
                 filter1.to_msr_value(),
             )?;
         }
 
-        // Program all 4 counters using type-safe structs
-        for i in 0..4 {
-            let (event, umask) = group.counter_configs[i];
-            if event != 0 || umask != 0 {
-                let ctrl = ChaCounterControl {
-                    event_select: event,
-                    unit_mask: umask,
-                    enable: true,
-                    ..Default::default()
-                };
-
-                // Validate before writing (type safety!)
-                ctrl.validate()
-                    .map_err(|e| crate::error::UncflowError::HardwareError(e.to_string()))?;
-
-                let ctl_addr = cha::msr::counter_ctl(cha_id, i);
-                msr::Msr::instance().write(
-                    self.representative_core,
-                    ctl_addr,
-                    ctrl.to_msr_value(),
-                )?;
+        let mut failed = Vec::new();
+        for (i, ctrl) in registers.counters.iter().enumerate() {
+            let Some(ctrl) = ctrl else { continue };
+
+            // Read back to detect read-only or virtualized MSRs
+            if let Err(e) = msr::write_verified(
+                self.msr,
+                self.representative_core,
+                cha::msr::counter_ctl(cha_id, i),
+                ctrl.to_msr_value(),
+                ChaCounterControl::VERIFY_MASK,
+            ) {
+                failed.push((i, e));
             }
         }
 
         // Unfreeze the CHA box
-        let unfreeze_ctrl = ChaBoxControl {
-            freeze: false,
-            freeze_enable: true,
-            ..Default::default()
-        };
-        msr::Msr::instance().write(
+        self.msr.write(
             self.representative_core,
             box_ctl_addr,
-            unfreeze_ctrl.to_msr_value(),
+            ChaBoxControl::unfrozen().to_msr_value(),
         )?;
 
-        Ok(())
+        Ok(failed)
     }
 
     fn read_cha_counters(&self, cha_id: usize) -> Result<ChaRawCounters> {
+        let read = |counter| {
+            self.reads.observe(self.msr.read(
+                self.representative_core,
+                cha::msr::counter_value(cha_id, counter),
+            ))
+        };
         Ok(ChaRawCounters {
-            counter0: msr::Msr::instance()
-                .read(self.representative_core, cha::msr::counter_value(cha_id, 0))?,
-            counter1: msr::Msr::instance()
-                .read(self.representative_core, cha::msr::counter_value(cha_id, 1))?,
-            counter2: msr::Msr::instance()
-                .read(self.representative_core, cha::msr::counter_value(cha_id, 2))?,
-            counter3: msr::Msr::instance()
-                .read(self.representative_core, cha::msr::counter_value(cha_id, 3))?,
+            counter0: read(0)?,
+            counter1: read(1)?,
+            counter2: read(2)?,
         })
     }
 
-    fn collect_current_event_group(&mut self) -> Result<()> {
-        let group = match self.scheduler.get_current_group() {
-            Some(g) => g,
-            None => return Ok(()),
-        };
-
-        let mut aggregated = [0u64; 4];
-        let duration = self.collection_start.elapsed();
-
-        // Aggregate counters across all CHA units
-        for cha_id in 0..self.cha_count {
-            let current = self.read_cha_counters(cha_id)?;
-            let prev = self.prev_counters.get(&cha_id).cloned().unwrap_or_default();
+    /// Counter values of every read box
+    fn read_boxes(&self) -> Result<HashMap<usize, ChaRawCounters>> {
+        self.box_ids()
+            .into_iter()
+            .map(|cha_id| Ok((cha_id, self.read_cha_counters(cha_id)?)))
+            .collect()
+    }
 
-            // Calculate deltas
-            aggregated[0] += current.counter0.saturating_sub(prev.counter0);
-            aggregated[1] += current.counter1.saturating_sub(prev.counter1);
-            aggregated[2] += current.counter2.saturating_sub(prev.counter2);
-            aggregated[3] += current.counter3.saturating_sub(prev.counter3);
+    /// Per-box deltas of the live counters from `baseline` to `readings`
+    fn deltas_between(
+        &self,
+        baseline: &HashMap<usize, ChaRawCounters>,
+        readings: &HashMap<usize, ChaRawCounters>,
+        live: LiveCounters,
+    ) -> Vec<ChaBoxDelta> {
+        // Dead counters contribute nothing rather than whatever they happen to hold
+        let delta = |alive: bool, current: u64, prev: u64| {
+            if alive {
+                wrapping_delta(prev, current, cha::COUNTER_WIDTH_BITS)
+            } else {
+                0
+            }
+        };
 
-            // Save for next iteration
-            self.prev_counters.insert(cha_id, current);
-        }
+        self.box_ids()
+            .into_iter()
+            .map(|cha_id| {
+                let current = readings.get(&cha_id).cloned().unwrap_or_default();
+                let prev = baseline
+                    .get(&cha_id)
+                    .cloned()
+                    .unwrap_or_else(|| current.clone());
+                ChaBoxDelta {
+                    occupancy: delta(live.occupancy, current.counter0, prev.counter0),
+                    insert: delta(live.insert, current.counter1, prev.counter1),
+                    clockticks: delta(live.clockticks, current.counter2, prev.counter2),
+                }
+            })
+            .collect()
+    }
 
-        // Store the aggregated data
-        let event_name = &group.name;
+    /// Keep per-box deltas for per-tile export and aggregate them across CHA units
+    ///
+    /// A sampled aggregate is scaled up to all boxes.
+    fn record_box_deltas(
+        &mut self,
+        group: &str,
+        box_deltas: Vec<ChaBoxDelta>,
+        duration: Duration,
+        live: LiveCounters,
+    ) -> RawEventData {
+        let box_ids = self.box_ids();
+        let scale = |sum: u64| scale_to_all_boxes(sum, self.cha_count, box_ids.len());
         let data = RawEventData {
-            occupancy: aggregated[0],
-            insert: aggregated[1],
-            clockticks: aggregated[2],
+            occupancy: scale(box_deltas.iter().map(|d| d.occupancy).sum()),
+            insert: scale(box_deltas.iter().map(|d| d.insert).sum()),
+            clockticks: scale(box_deltas.iter().map(|d| d.clockticks).sum()),
             duration,
+            live,
+            measured_at: Some(Instant::now()),
         };
+        self.box_deltas.push(ChaGroupDeltas {
+            group: group.to_string(),
+            live,
+            box_ids,
+            boxes: box_deltas,
+        });
+        data
+    }
 
-        // Accumulate with existing data (for this event group)
-        self.event_data
-            .entry(event_name.clone())
-            .and_modify(|e| {
-                e.occupancy += data.occupancy;
-                e.insert += data.insert;
-                e.clockticks += data.clockticks;
-                e.duration = duration;
-            })
-            .or_insert(data);
+    fn collect_current_event_group(&mut self, group: &EventGroup) -> Result<()> {
+        let live = self.live;
+
+        let readings = self.read_boxes()?;
+        // The first read after (re)programming or reset only sets the baseline
+        let Some((prev, duration)) = self.baseline.advance(readings.clone(), Instant::now()) else {
+            return Ok(());
+        };
+        let box_deltas = self.deltas_between(&prev, &readings, live);
+
+        let data = self.record_box_deltas(&group.name, box_deltas, duration, live);
+        self.event_data.insert(group.name.clone(), data);
+
+        Ok(())
+    }
+
+    /// Measure each group of a multi-pass slot in turn, `wait` counting between the reads
+    ///
+    /// Per-box deltas are those of the last pass.
+    fn collect_passes(
+        &mut self,
+        passes: &[EventGroup],
+        mut wait: impl FnMut(&EventGroup),
+    ) -> Result<()> {
+        for group in passes {
+            self.program_all_boxes(group)?;
+            let live = self.live;
+
+            let baseline = self.read_boxes()?;
+            let start = Instant::now();
+            wait(group);
+            let readings = self.read_boxes()?;
+            let elapsed = start.elapsed();
+            let box_deltas = self.deltas_between(&baseline, &readings, live);
+
+            let data = self.record_box_deltas(&group.name, box_deltas, elapsed, live);
+            self.event_data.insert(group.name.clone(), data);
+        }
 
+        // The next single-pass collect must not diff against the last pass
+        self.baseline.reset();
         Ok(())
     }
 
     pub fn collect(&mut self) -> Result<HashMap<String, RawEventData>> {
-        // Collect data from current event group
-        self.collect_current_event_group()?;
+        self.box_deltas.clear();
+
+        // Collect data from current event slot
+        if let Some(slot) = self.scheduler.current().cloned() {
+            if slot.is_multi_pass() {
+                let pass_duration = self.pass_duration;
+                self.collect_passes(&slot.passes, |_| std::thread::sleep(pass_duration))?;
+            } else {
+                self.collect_current_event_group(&slot.passes[0])?;
+            }
+        }
 
         // Check if it's time to rotate
         if self.scheduler.should_rotate() {
-            self.scheduler.rotate();
-            let current_idx = self.scheduler.current_group_index();
-
-            if let Some(next_group) = self.scheduler.groups.get(current_idx) {
+            if let Some(next_slot) = self.scheduler.rotate().cloned() {
+                let current_idx = self.scheduler.current_index();
+                let next_group = &next_slot.passes[0];
                 tracing::debug!(
                     "Rotating to event group: {} ({}/{})",
                     next_group.name,
                     current_idx + 1,
-                    self.scheduler.groups.len()
+                    self.scheduler.len()
                 );
 
-                // Program all CHA units with the new event group
-                for cha_id in 0..self.cha_count {
-                    self.program_event_group(cha_id, next_group)?;
-                }
+                // Unchanged boxes keep counting, so their baseline stays valid
+                if self.loaded.as_deref() != Some(next_group.name.as_str()) {
+                    // Program all CHA units with the new event group
+                    self.program_all_boxes(next_group)?;
 
-                // Reset previous counters for clean delta calculation
-                self.prev_counters.clear();
+                    // The new group's window starts from a fresh read
+                    self.baseline.reset();
+                    let readings = self.read_boxes()?;
+                    self.baseline.advance(readings, Instant::now());
+                }
             }
         }
 
-        // Return a clone of accumulated event data
+        // Latest window of every group measured so far
         Ok(self.event_data.clone())
     }
 
+    /// Per-box deltas of each event group measured in the last collect(), in measurement order
+    pub fn box_deltas(&self) -> &[ChaGroupDeltas] {
+        &self.box_deltas
+    }
+
+    /// Counters of the current event group that are programmed on every box
+    pub fn live_counters(&self) -> LiveCounters {
+        self.live
+    }
+
     /// Get event data for calculator
     pub fn get_event_data(&self) -> &HashMap<String, RawEventData> {
         &self.event_data
     }
 
-    /// Reset accumulated data (e.g., after exporting)
+    /// Drop all baselines and event data, the next collect() is treated as a first sample
+    pub fn reset(&mut self) {
+        self.baseline.reset();
+        self.event_data.clear();
+        self.box_deltas.clear();
+        self.scheduler.reset();
+    }
+
+    /// Forget the windows of groups measured before, keeping the counter baselines
+    ///
+    /// Only groups measured after this are reported by the next collect().
     pub fn reset_event_data(&mut self) {
-        // Don't clear completely, just reset durations
-        // This allows continuous accumulation between rotations
-        for data in self.event_data.values_mut() {
-            data.duration = Duration::from_secs(0);
-        }
-        self.collection_start = Instant::now();
+        self.event_data.clear();
     }
 }
 
+/// `sample` evenly spaced box indices out of `cha_count`, all of them without a sample
+fn sampled_box_ids(cha_count: usize, sample: Option<usize>) -> Vec<usize> {
+    match sample {
+        Some(n) if n > 0 && n < cha_count => (0..n).map(|i| i * cha_count / n).collect(),
+        _ => (0..cha_count).collect(),
+    }
+}
+
+/// Scale a sum over `sampled` boxes by `total / sampled`
+fn scale_to_all_boxes(sum: u64, total: usize, sampled: usize) -> u64 {
+    if sampled == 0 || sampled == total {
+        return sum;
+    }
+    (sum as u128 * total as u128 / sampled as u128).min(u64::MAX as u128) as u64
+}
+
 // Legacy compatibility structure
 #[derive(Debug, Clone, Default)]
 pub struct ChaMetrics {
@@ -363,28 +674,404 @@ pub struct ChaMetrics {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::common::msr::mock::MockMsr;
     use crate::counters::cha::TransactionType;
+    use crate::metrics::cha::{MetricCalculator, TransactionMetricType};
+
+    /// Monitor counting one group continuously instead of the two-pass transaction
+    /// slots, its baseline read with every counter at 0
+    fn single_group_monitor(msr: &'static MockMsr, cha_count: usize) -> ChaMonitor {
+        let mut monitor = ChaMonitor::with_msr(0, msr).unwrap();
+        monitor.cha_count = cha_count;
+        monitor
+            .scheduler
+            .push(EventSlot::single(ChaEventConfig::transaction(
+                TransactionType::PCIeRead,
+                true,
+            )));
+        let group = monitor.scheduler.groups()[0].passes[0].clone();
+        monitor.program_all_boxes(&group).unwrap();
+        monitor.collect().unwrap();
+        monitor
+    }
+
+    #[test]
+    fn test_llc_lookup_state_filters_are_distinct() {
+        use crate::counters::cha::{LLCLookupType, LLCState};
+
+        let filter1 = |state| {
+            EventGroup::from_config(ChaEventConfig::llc_lookup(state, LLCLookupType::Read))
+                .registers()
+                .unwrap()
+                .filter1
+                .unwrap()
+                .to_msr_value()
+        };
+
+        // Shared and snoop-filter-shared select different bits
+        assert_eq!(filter1(LLCState::S), 1 << 21);
+        assert_eq!(filter1(LLCState::SFS), 1 << 18);
+
+        let combined = LLCState::all().into_iter().fold(0, |acc, state| {
+            assert_eq!(acc & filter1(state), 0, "{} aliases", state.name());
+            acc | filter1(state)
+        });
+        // I, SF S/E/H and LLC S/E/M: bits 17-23
+        assert_eq!(combined, 0x7F << 17);
+    }
+
+    #[test]
+    fn test_failed_read_counts_as_read_error() {
+        let msr = MockMsr::new().leak();
+        // Read counters are process-wide, so use a socket no other test collects on
+        let mut monitor = ChaMonitor::with_msr(5, msr).unwrap();
+        monitor.cha_count = 2;
+        let reads = read_stats().counter("cha", 5);
+        let (reads_before, errors_before) = (reads.reads(), reads.errors());
+
+        monitor.read_boxes().unwrap();
+        assert_eq!(reads.reads() - reads_before, 6);
+        assert_eq!(reads.errors(), errors_before);
+
+        msr.fail_reads(monitor.representative_core, cha::msr::counter_value(1, 2));
+        assert!(monitor.read_boxes().is_err());
+        assert_eq!(reads.reads() - reads_before, 12);
+        assert_eq!(reads.errors() - errors_before, 1);
+    }
+
+    #[test]
+    fn test_initialize_programs_counters() {
+        let msr = MockMsr::new().leak();
+        let mut monitor = ChaMonitor::with_msr(0, msr).unwrap();
+        monitor.cha_count = 2;
+
+        assert!(monitor.initialize().is_ok());
+        let ctl = msr.get(0, cha::msr::counter_ctl(1, 0)).unwrap();
+        assert_eq!(ChaCounterControl::from_msr_value(ctl).event_select, 0x36);
+
+        // Box is left running after programming
+        let box_ctl = msr.get(0, cha::msr::box_ctl(1)).unwrap();
+        assert_eq!(
+            ChaBoxControl::from_msr_value(box_ctl),
+            ChaBoxControl::unfrozen()
+        );
+    }
+
+    #[test]
+    fn test_unchanged_group_is_not_reprogrammed() {
+        let msr = MockMsr::new().leak();
+        let mut monitor = single_group_monitor(msr, 2);
+        let writes = msr.write_count();
+
+        // Every collect rotates, back onto the only slot
+        monitor.scheduler.set_rotation_interval(Duration::ZERO);
+        monitor.collect().unwrap();
+        msr.set(0, cha::msr::counter_value(0, 1), 100);
+        monitor.collect().unwrap();
+        msr.set(0, cha::msr::counter_value(0, 1), 150);
+        let event_data = monitor.collect().unwrap();
+        assert_eq!(msr.write_count(), writes);
+        // The baseline survived the rotations
+        assert_eq!(monitor.box_deltas().last().unwrap().boxes[0].insert, 50);
+        assert_eq!(event_data[&monitor.box_deltas()[0].group].insert, 50);
+
+        // A different group is written
+        monitor
+            .scheduler
+            .push(EventSlot::single(ChaEventConfig::sf_occupancy()));
+        monitor.collect().unwrap();
+        assert!(msr.write_count() > writes);
+    }
+
+    #[test]
+    fn test_core_override_is_honored() {
+        let msr = MockMsr::new().leak();
+        let mut monitor = ChaMonitor::with_core(1, 40, msr).unwrap();
+        monitor.cha_count = 2;
+
+        monitor.initialize().unwrap();
+        assert!(msr.get(40, cha::msr::counter_ctl(1, 0)).is_some());
+        assert!(msr
+            .get(ChaMonitor::default_core(1), cha::msr::counter_ctl(1, 0))
+            .is_none());
+    }
+
+    #[test]
+    fn test_initialize_detects_readback_mismatch() {
+        let msr = MockMsr::read_only().leak();
+        let mut monitor = ChaMonitor::with_msr(0, msr).unwrap();
+
+        let err = monitor.initialize().unwrap_err();
+        assert!(err.to_string().contains("not programmable"));
+    }
+
+    #[test]
+    fn test_invalid_group_fails_before_any_write() {
+        let msr = MockMsr::new().leak();
+        let mut monitor = ChaMonitor::with_msr(0, msr).unwrap();
+
+        // Opcode wider than the 16-bit filter field
+        let mut config = ChaEventConfig::transaction(TransactionType::PCIeRead, true);
+        config.opc0 = 0x1_0000;
+        monitor.scheduler.push(EventSlot::single(config));
+
+        let err = monitor.initialize().unwrap_err();
+        assert!(err.to_string().contains("opcode"));
+        assert_eq!(msr.write_count(), 0);
+    }
+
+    #[test]
+    fn test_box_deltas_sum_to_aggregate() {
+        let msr = MockMsr::new().leak();
+        let mut monitor = single_group_monitor(msr, 3);
+
+        for cha_id in 0..3u64 {
+            msr.set(
+                0,
+                cha::msr::counter_value(cha_id as usize, 0),
+                100 * (cha_id + 1),
+            );
+            msr.set(
+                0,
+                cha::msr::counter_value(cha_id as usize, 1),
+                10 * (cha_id + 1),
+            );
+            msr.set(
+                0,
+                cha::msr::counter_value(cha_id as usize, 2),
+                1000 + cha_id,
+            );
+        }
+
+        let event_data = monitor.collect().unwrap();
+        let ChaGroupDeltas {
+            group,
+            boxes: deltas,
+            ..
+        } = monitor.box_deltas().last().unwrap();
+        assert_eq!(deltas.len(), 3);
+        assert_eq!(deltas[1].insert, 20);
+
+        let aggregate = &event_data[group];
+        assert_eq!(
+            deltas.iter().map(|d| d.occupancy).sum::<u64>(),
+            aggregate.occupancy
+        );
+        assert_eq!(
+            deltas.iter().map(|d| d.insert).sum::<u64>(),
+            aggregate.insert
+        );
+        assert_eq!(
+            deltas.iter().map(|d| d.clockticks).sum::<u64>(),
+            aggregate.clockticks
+        );
+        assert_eq!(aggregate.insert, 60);
+    }
+
+    #[test]
+    fn test_sampled_boxes_scale_the_aggregate() {
+        assert_eq!(sampled_box_ids(28, Some(7)), vec![0, 4, 8, 12, 16, 20, 24]);
+        assert_eq!(sampled_box_ids(28, Some(3)), vec![0, 9, 18]);
+        assert_eq!(sampled_box_ids(4, Some(8)), vec![0, 1, 2, 3]);
+        assert_eq!(sampled_box_ids(4, None), vec![0, 1, 2, 3]);
+        assert_eq!(scale_to_all_boxes(30, 28, 3), 280);
+        assert_eq!(scale_to_all_boxes(30, 28, 28), 30);
+
+        let msr = MockMsr::new().leak();
+        let mut monitor = ChaMonitor::with_msr(0, msr)
+            .unwrap()
+            .with_sample_boxes(Some(2));
+        monitor.cha_count = 4;
+        monitor
+            .scheduler
+            .push(EventSlot::single(ChaEventConfig::transaction(
+                TransactionType::PCIeRead,
+                true,
+            )));
+        let group = monitor.scheduler.groups()[0].passes[0].clone();
+        monitor.program_all_boxes(&group).unwrap();
+        monitor.collect().unwrap();
+
+        // Boxes 1 and 3 are neither programmed nor read
+        assert!(msr.get(0, cha::msr::counter_ctl(1, 1)).is_none());
+        msr.set(0, cha::msr::counter_value(0, 1), 10);
+        msr.set(0, cha::msr::counter_value(1, 1), 1000);
+        msr.set(0, cha::msr::counter_value(2, 1), 30);
+
+        let event_data = monitor.collect().unwrap();
+        let ChaGroupDeltas {
+            group,
+            box_ids,
+            boxes,
+            ..
+        } = monitor.box_deltas().last().unwrap();
+        assert_eq!(box_ids, &[0, 2]);
+        assert_eq!(boxes[1].insert, 30);
+        // 40 over 2 of 4 boxes
+        assert_eq!(event_data[group].insert, 80);
+    }
 
     #[test]
-    fn test_event_scheduler() {
-        let mut scheduler = EventScheduler::new(Duration::from_secs(1));
+    fn test_partial_programming_keeps_live_counters() {
+        let msr = MockMsr::new().leak();
+        // The insert counter of box 1 drops writes
+        msr.lock(0, cha::msr::counter_ctl(1, 1));
+
+        let mut monitor = single_group_monitor(msr, 2);
 
-        // Add some test events
-        let config1 = ChaEventConfig::transaction(TransactionType::PCIeRead, true);
-        let config2 = ChaEventConfig::transaction(TransactionType::PCIeRead, false);
+        let live = monitor.live_counters();
+        assert!(live.occupancy && live.clockticks);
+        assert!(!live.insert);
 
-        scheduler.add_event_group(config1);
-        scheduler.add_event_group(config2);
+        for cha_id in 0..2 {
+            msr.set(0, cha::msr::counter_value(cha_id, 0), 100);
+            msr.set(0, cha::msr::counter_value(cha_id, 1), 10);
+            msr.set(0, cha::msr::counter_value(cha_id, 2), 1000);
+        }
+
+        let event_data = monitor.collect().unwrap();
+        let group = &monitor.box_deltas().last().unwrap().group;
+        let data = &event_data[group];
+        assert_eq!(data.live, live);
+        assert_eq!(data.occupancy, 200);
+        assert_eq!(data.clockticks, 2000);
+        // Nothing is aggregated from the dead counter
+        assert_eq!(data.insert, 0);
+    }
 
-        assert_eq!(scheduler.groups.len(), 2);
-        assert_eq!(scheduler.current_index, 0);
+    #[test]
+    fn test_first_collect_only_reads_baseline() {
+        let msr = MockMsr::new().leak();
+        let mut monitor = ChaMonitor::with_msr(0, msr).unwrap();
+        monitor.cha_count = 2;
+        monitor
+            .scheduler
+            .push(EventSlot::single(ChaEventConfig::transaction(
+                TransactionType::PCIeRead,
+                true,
+            )));
+        let group = monitor.scheduler.groups()[0].passes[0].clone();
+        monitor.program_all_boxes(&group).unwrap();
 
-        // Rotate
-        scheduler.rotate();
-        assert_eq!(scheduler.current_group_index(), 1);
+        // Counts left over from before programming are not a window's count
+        msr.set(0, cha::msr::counter_value(0, 1), 5_000);
+        msr.set(0, cha::msr::counter_value(1, 1), 7_000);
+        assert!(monitor.collect().unwrap().is_empty());
+        assert!(monitor.box_deltas().is_empty());
 
-        scheduler.rotate();
-        assert_eq!(scheduler.current_group_index(), 0); // Wrap around
+        msr.set(0, cha::msr::counter_value(0, 1), 5_040);
+        msr.set(0, cha::msr::counter_value(1, 1), 7_060);
+        let event_data = monitor.collect().unwrap();
+        let ChaGroupDeltas { boxes, .. } = monitor.box_deltas().last().unwrap();
+        assert_eq!(boxes[0].insert, 40);
+        assert_eq!(boxes[1].insert, 60);
+        assert_eq!(event_data[&group.name].insert, 100);
+    }
+
+    #[test]
+    fn test_counter_wrap_keeps_delta() {
+        let msr = MockMsr::new().leak();
+        msr.set(0, cha::msr::counter_value(0, 1), cha::COUNTER_MASK - 9);
+        let mut monitor = single_group_monitor(msr, 1);
+
+        // The 48-bit counter passes zero within the window
+        msr.set(0, cha::msr::counter_value(0, 1), 20);
+        let event_data = monitor.collect().unwrap();
+        let ChaGroupDeltas { group, boxes, .. } = monitor.box_deltas().last().unwrap();
+        assert_eq!(boxes[0].insert, 30);
+        assert_eq!(event_data[group].insert, 30);
+    }
+
+    #[test]
+    fn test_reset_starts_fresh_window() {
+        let msr = MockMsr::new().leak();
+        let mut monitor = single_group_monitor(msr, 1);
+
+        msr.set(0, cha::msr::counter_value(0, 1), 50);
+        monitor.collect().unwrap();
+        msr.set(0, cha::msr::counter_value(0, 1), 80);
+        let event_data = monitor.collect().unwrap();
+        let group = &monitor.box_deltas().last().unwrap().group;
+        assert_eq!(event_data[group].insert, 30);
+
+        monitor.reset();
+        assert!(monitor.box_deltas().is_empty());
+        assert!(monitor.get_event_data().is_empty());
+
+        // Like the first collect after initialize(), only the baseline is read: the
+        // counter's absolute value is not a window's count
+        msr.set(0, cha::msr::counter_value(0, 1), 100);
+        assert!(monitor.collect().unwrap().is_empty());
+        assert!(monitor.box_deltas().is_empty());
+
+        msr.set(0, cha::msr::counter_value(0, 1), 130);
+        let event_data = monitor.collect().unwrap();
+        let ChaGroupDeltas {
+            group,
+            boxes: deltas,
+            ..
+        } = monitor.box_deltas().last().unwrap();
+        assert_eq!(deltas[0].insert, 30);
+        assert_eq!(event_data[group].insert, 30);
+    }
+
+    #[test]
+    fn test_credit_stall_event_config() {
+        let registers = |credit| {
+            EventGroup::from_config(ChaEventConfig::credit_stall(credit))
+                .registers()
+                .unwrap()
+        };
+
+        // RxC_IRQ0_REJECT in the insert counter, AD_REQ_VN0 for reads, BL_WB_VN0 for writes
+        for (credit, umask) in [(CreditType::Read, 0x01), (CreditType::Write, 0x08)] {
+            let registers = registers(credit);
+            assert!(registers.filter0.is_none() && registers.filter1.is_none());
+            assert!(registers.counters[0].is_none());
+            let insert = registers.counters[1].unwrap();
+            assert_eq!((insert.event_select, insert.unit_mask), (0x18, umask));
+        }
+    }
+
+    #[test]
+    fn test_sf_occupancy_event_config() {
+        let registers = EventGroup::from_config(ChaEventConfig::sf_occupancy())
+            .registers()
+            .unwrap();
+        assert!(registers.filter0.is_none() && registers.filter1.is_none());
+        let occupancy = registers.counters[0].unwrap();
+        let insert = registers.counters[1].unwrap();
+        assert_eq!((occupancy.event_select, occupancy.unit_mask), (0x3E, 0x07));
+        assert_eq!((insert.event_select, insert.unit_mask), (0x3D, 0x07));
+    }
+
+    #[test]
+    fn test_credit_stalls_are_scheduled_and_stored() {
+        let msr = MockMsr::new().leak();
+        let mut monitor = ChaMonitor::with_msr(0, msr).unwrap();
+        monitor.cha_count = 2;
+        monitor.initialize().unwrap();
+
+        let slot = monitor.scheduler.groups().last().cloned().unwrap();
+        let names: Vec<_> = slot.passes.iter().map(|g| g.name.as_str()).collect();
+        assert_eq!(names, ["ReadNoCredit", "WriteNoCredit"]);
+
+        // Rejects counted on each box: 5 per read pass, 7 per write pass
+        let count = |group: &EventGroup| {
+            let rejects = if group.name == "ReadNoCredit" { 5 } else { 7 };
+            for cha_id in 0..2 {
+                let addr = cha::msr::counter_value(cha_id, 1);
+                msr.set(0, addr, msr.get(0, addr).unwrap_or(0) + rejects);
+            }
+        };
+        monitor.collect_passes(&slot.passes, count).unwrap();
+
+        let mut calculator = MetricCalculator::new();
+        for (name, data) in monitor.get_event_data().clone() {
+            calculator.store_event(name, data);
+        }
+        assert_eq!(calculator.get_credit_metric(CreditType::Read), 10);
+        assert_eq!(calculator.get_credit_metric(CreditType::Write), 14);
     }
 
     #[test]
@@ -393,4 +1080,83 @@ mod tests {
         // Should have 11 transaction types × 2 (hit/miss) = 22 groups
         assert_eq!(configs.len(), 22);
     }
+
+    #[test]
+    fn test_two_pass_hit_and_miss_share_a_collect() {
+        let msr = MockMsr::new().leak();
+        let mut monitor = ChaMonitor::with_msr(0, msr).unwrap();
+        monitor.cha_count = 2;
+        monitor.initialize().unwrap();
+
+        let slot = monitor.scheduler.current().cloned().unwrap();
+        assert_eq!(slot.passes.len(), 2);
+
+        // Each pass counts per box: hit 1000/10/2000, miss 3000/20/2000
+        let count = |group: &EventGroup| {
+            let (occupancy, insert) = if group.name.ends_with("Hit") {
+                (1000, 10)
+            } else {
+                (3000, 20)
+            };
+            for cha_id in 0..2 {
+                for (counter, value) in [(0, occupancy), (1, insert), (2, 2000)] {
+                    let addr = cha::msr::counter_value(cha_id, counter);
+                    msr.set(0, addr, msr.get(0, addr).unwrap_or(0) + value);
+                }
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        };
+        monitor.collect_passes(&slot.passes, count).unwrap();
+        monitor.collect_passes(&slot.passes, count).unwrap();
+
+        let event_data = monitor.get_event_data();
+        let hit = &event_data["PCIeRead Hit"];
+        let miss = &event_data["PCIeRead Miss"];
+        // The last collect's passes over two boxes
+        assert_eq!(
+            (hit.occupancy, hit.insert, hit.clockticks),
+            (2000, 20, 4000)
+        );
+        assert_eq!(
+            (miss.occupancy, miss.insert, miss.clockticks),
+            (6000, 40, 4000)
+        );
+        // Each pass counted for at least 20ms
+        assert!(hit.duration >= Duration::from_millis(20));
+        assert!(miss.duration >= Duration::from_millis(20));
+
+        let mut calculator = MetricCalculator::new();
+        for (name, data) in event_data.clone() {
+            calculator.store_event(name, data);
+        }
+        let metrics = calculator.calculate_transaction_metrics(TransactionType::PCIeRead);
+        for (data, metric) in [
+            (hit, TransactionMetricType::HitLatency),
+            (miss, TransactionMetricType::MissLatency),
+        ] {
+            let expected = (data.occupancy as f64 / data.insert as f64)
+                * (data.clockticks as f64 / data.duration.as_nanos() as f64);
+            assert!((metrics[&metric] - expected).abs() < 1e-9);
+        }
+        assert!((metrics[&TransactionMetricType::HitRate] - 1.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_identical_traffic_keeps_bandwidth_flat() {
+        let msr = MockMsr::new().leak();
+        let mut monitor = single_group_monitor(msr, 1);
+        monitor.collect().unwrap();
+
+        // The same 1000 inserts in each of two equally long windows
+        let mut rates = Vec::new();
+        for window in 1..=2 {
+            std::thread::sleep(Duration::from_millis(50));
+            msr.set(0, cha::msr::counter_value(0, 1), 1000 * window);
+            let event_data = monitor.collect().unwrap();
+            let data = &event_data[&monitor.box_deltas()[0].group];
+            assert_eq!(data.insert, 1000);
+            rates.push(data.insert as f64 / data.duration.as_secs_f64());
+        }
+        assert!(rates[1] < rates[0] * 1.5, "rates grew: {rates:?}");
+    }
 }
@@ -1,6 +1,13 @@
 // PMU event definitions (architecture-aware)
 
-use crate::common::CPU_ARCH;
+use std::str::FromStr;
+
+use crate::common::{CpuArchitecture, CPU_ARCH};
+use crate::error::{Result, UncflowError};
+use uncflow_raw::current_arch::core::{
+    offcore_events, offcore_request, offcore_snoop, offcore_supplier, CorePerfEvtSel,
+    OffcoreResponse,
+};
 
 #[derive(Debug, Clone, Copy)]
 pub struct PmuEvent {
@@ -94,6 +101,232 @@ pub fn get_default_event_set() -> Vec<PmuEvent> {
     ]
 }
 
+// Frequency license events (CORE_POWER.LVL*_TURBO_LICENSE, Skylake and later)
+// Together they count every unhalted core cycle, split by the turbo license in effect:
+// level 0 is the non-AVX schedule, level 1 AVX2 / light AVX-512, level 2 heavy AVX-512
+pub const CORE_POWER_LVL0: &str = "CorePowerLvl0TurboLicense";
+pub const CORE_POWER_LVL1: &str = "CorePowerLvl1TurboLicense";
+pub const CORE_POWER_LVL2: &str = "CorePowerLvl2TurboLicense";
+
+pub const LICENSE_EVENTS: &[PmuEvent] = &[
+    PmuEvent {
+        event: 0x28,
+        umask: 0x07,
+        name: CORE_POWER_LVL0,
+    },
+    PmuEvent {
+        event: 0x28,
+        umask: 0x18,
+        name: CORE_POWER_LVL1,
+    },
+    PmuEvent {
+        event: 0x28,
+        umask: 0x20,
+        name: CORE_POWER_LVL2,
+    },
+];
+
+/// Frequency license events, multiplexed with the default set when enabled
+///
+/// None before Skylake, which has no CORE_POWER events.
+pub fn get_license_event_set() -> Option<Vec<PmuEvent>> {
+    match *CPU_ARCH {
+        CpuArchitecture::Haswell | CpuArchitecture::Broadwell => None,
+        _ => Some(LICENSE_EVENTS.to_vec()),
+    }
+}
+
+// Offcore response events: LLC misses split by where the data came from
+pub const CORE_OFFCORE_LOCAL_DRAM: &str = "core_offcore_local_dram";
+pub const CORE_OFFCORE_REMOTE_DRAM: &str = "core_offcore_remote_dram";
+
+/// One offcore response counter: an exported name and the response matrix it counts
+#[derive(Debug, Clone, Copy)]
+pub struct OffcoreEvent {
+    pub name: &'static str,
+    pub response: OffcoreResponse,
+}
+
+/// Data reads and RFOs served by DRAM on this socket (MSR_OFFCORE_RSP_0)
+pub const OFFCORE_LOCAL_DRAM: OffcoreEvent = OffcoreEvent {
+    name: CORE_OFFCORE_LOCAL_DRAM,
+    response: OffcoreResponse {
+        request: offcore_request::ALL_DATA_RD | offcore_request::ALL_RFO,
+        supplier: offcore_supplier::L3_MISS_LOCAL_DRAM,
+        snoop: offcore_snoop::ANY_SNOOP,
+    },
+};
+
+/// Data reads and RFOs served by DRAM on another socket (MSR_OFFCORE_RSP_1)
+pub const OFFCORE_REMOTE_DRAM: OffcoreEvent = OffcoreEvent {
+    name: CORE_OFFCORE_REMOTE_DRAM,
+    response: OffcoreResponse {
+        request: offcore_request::ALL_DATA_RD | offcore_request::ALL_RFO,
+        supplier: offcore_supplier::L3_MISS_REMOTE_DRAM,
+        snoop: offcore_snoop::ANY_SNOOP,
+    },
+};
+
+/// Default offcore response events for MSR_OFFCORE_RSP_0 and MSR_OFFCORE_RSP_1
+///
+/// None without offcore response support, and on parts whose response matrix does
+/// not follow the Skylake-SP layout (Haswell, Broadwell, Ice Lake).
+pub fn get_offcore_event_set() -> Option<[OffcoreEvent; 2]> {
+    let skylake_layout = matches!(
+        *CPU_ARCH,
+        CpuArchitecture::Skylake | CpuArchitecture::CascadeLake
+    );
+    (CPU_ARCH.supports_offcore_response() && skylake_layout)
+        .then_some([OFFCORE_LOCAL_DRAM, OFFCORE_REMOTE_DRAM])
+}
+
+/// Programmable events counting `events`: OFFCORE_RESPONSE_0 for the first, _1 for the second
+pub fn offcore_event_group(events: &[OffcoreEvent; 2]) -> Vec<PmuEvent> {
+    [
+        offcore_events::OFFCORE_RESPONSE_0,
+        offcore_events::OFFCORE_RESPONSE_1,
+    ]
+    .into_iter()
+    .zip(events)
+    .map(|((event, umask), offcore)| PmuEvent {
+        event,
+        umask,
+        name: offcore.name,
+    })
+    .collect()
+}
+
+/// A programmable event given as a perf-style descriptor (--core-event)
+///
+/// Accepted forms, with optional `u`/`k` modifiers (both when neither is given):
+/// - `cpu/event=0x24,umask=0x38/`, terms `event`, `umask`, `cmask`, `inv`, `edge`,
+///   `any` and `name`, modifiers after the closing slash (`/u` or `/:u`)
+/// - `r3824:k`, raw config `umask << 8 | event` in hex
+///
+/// Exported under `name`, or the descriptor itself.
+#[derive(Debug, Clone)]
+pub struct CustomEvent {
+    pub name: String,
+    pub select: CorePerfEvtSel,
+}
+
+impl CustomEvent {
+    fn invalid(spec: &str, reason: impl std::fmt::Display) -> UncflowError {
+        UncflowError::InvalidConfiguration(format!("Invalid core event '{spec}': {reason}"))
+    }
+
+    // Number in hex (0x prefix) or decimal that fits a `bits`-wide field
+    fn parse_field(spec: &str, term: &str, value: &str, bits: u32) -> Result<u64> {
+        let parsed = match value
+            .strip_prefix("0x")
+            .or_else(|| value.strip_prefix("0X"))
+        {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => value.parse(),
+        }
+        .map_err(|_| Self::invalid(spec, format!("{term}={value} is not a number")))?;
+        if parsed >> bits != 0 {
+            return Err(Self::invalid(
+                spec,
+                format!("{term}={value} does not fit in {bits} bits"),
+            ));
+        }
+        Ok(parsed)
+    }
+
+    // Apply `u`/`k` modifiers; no modifier counts both like perf
+    fn apply_modifiers(spec: &str, select: &mut CorePerfEvtSel, modifiers: &str) -> Result<()> {
+        let modifiers = modifiers.strip_prefix(':').unwrap_or(modifiers);
+        for modifier in modifiers.chars() {
+            match modifier {
+                'u' => select.usr = true,
+                'k' => select.os = true,
+                other => return Err(Self::invalid(spec, format!("unknown modifier '{other}'"))),
+            }
+        }
+        if !select.usr && !select.os {
+            select.usr = true;
+            select.os = true;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for CustomEvent {
+    type Err = UncflowError;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        let spec = spec.trim();
+        let mut select = CorePerfEvtSel {
+            enable: true,
+            ..Default::default()
+        };
+        let mut name = None;
+
+        if let Some(rest) = spec.strip_prefix("cpu/") {
+            let (terms, modifiers) = rest
+                .split_once('/')
+                .ok_or_else(|| Self::invalid(spec, "missing closing '/'"))?;
+            let mut has_event = false;
+            for term in terms.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+                let (key, value) = term.split_once('=').unwrap_or((term, "1"));
+                match key {
+                    "event" => {
+                        select.event_select = Self::parse_field(spec, key, value, 8)? as u8;
+                        has_event = true;
+                    }
+                    "umask" => select.umask = Self::parse_field(spec, key, value, 8)? as u8,
+                    "cmask" => select.cmask = Self::parse_field(spec, key, value, 8)? as u8,
+                    "inv" => select.invert = Self::parse_field(spec, key, value, 1)? == 1,
+                    "edge" => select.edge = Self::parse_field(spec, key, value, 1)? == 1,
+                    "any" => select.any_thread = Self::parse_field(spec, key, value, 1)? == 1,
+                    "name" => name = Some(value.to_string()),
+                    _ => return Err(Self::invalid(spec, format!("unknown term '{key}'"))),
+                }
+            }
+            if !has_event {
+                return Err(Self::invalid(spec, "no event= term"));
+            }
+            Self::apply_modifiers(spec, &mut select, modifiers)?;
+        } else if let Some(rest) = spec.strip_prefix('r') {
+            let (config, modifiers) = rest.split_once(':').unwrap_or((rest, ""));
+            let config = u64::from_str_radix(config, 16)
+                .map_err(|_| Self::invalid(spec, "raw config is not hex"))?;
+            if config >> 16 != 0 {
+                return Err(Self::invalid(
+                    spec,
+                    "raw config wider than event and umask (16 bits)",
+                ));
+            }
+            select.event_select = (config & 0xFF) as u8;
+            select.umask = (config >> 8) as u8;
+            Self::apply_modifiers(spec, &mut select, modifiers)?;
+        } else {
+            return Err(Self::invalid(spec, "expected cpu/.../ or rUUEE"));
+        }
+
+        // Event select 0 is reserved
+        if select.event_select == 0 {
+            return Err(Self::invalid(spec, "event select 0 is reserved"));
+        }
+
+        Ok(Self {
+            name: name.unwrap_or_else(|| spec.to_string()),
+            select,
+        })
+    }
+}
+
+/// Parse an event file: one descriptor per line, `#` starts a comment
+pub fn parse_event_file(contents: &str) -> Result<Vec<CustomEvent>> {
+    contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or("").trim())
+        .filter(|line| !line.is_empty())
+        .map(str::parse)
+        .collect()
+}
+
 // MSR addresses for PMU
 pub const IA32_PERF_GLOBAL_CTRL: u64 = 0x38F;
 pub const IA32_FIXED_CTR_CTRL: u64 = 0x38D;
@@ -116,9 +349,19 @@ pub const IA32_PMC1: u64 = 0xC2;
 pub const IA32_PMC2: u64 = 0xC3;
 pub const IA32_PMC3: u64 = 0xC4;
 
+// Number of general-purpose counters programmed per core
+pub const GENERAL_PURPOSE_COUNTERS: usize = 4;
+
 // TSC for time measurement
 pub const IA32_TIME_STAMP_COUNTER: u64 = 0x10;
 
+// Free-running clock counters, both counting only in C0: MPERF at the TSC rate,
+// APERF at the actual core clock
+pub const IA32_MPERF: u64 = 0xE7;
+pub const IA32_APERF: u64 = 0xE8;
+// APERF and MPERF are full 64-bit counters
+pub const APERF_MPERF_WIDTH_BITS: u64 = 64;
+
 // Platform info for frequency
 pub const MSR_PLATFORM_INFO: u64 = 0xCE;
 
@@ -133,3 +376,84 @@ impl PmuEvent {
         value
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uncflow_raw::RegisterLayout;
+
+    fn parse(spec: &str) -> CustomEvent {
+        spec.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_event_descriptors() {
+        let event = parse("cpu/event=0x24,umask=0x38/");
+        assert_eq!(event.name, "cpu/event=0x24,umask=0x38/");
+        assert_eq!(
+            (event.select.event_select, event.select.umask),
+            (0x24, 0x38)
+        );
+        // No modifier counts user and kernel mode, like perf
+        assert!(event.select.usr && event.select.os && event.select.enable);
+        assert_eq!(event.select.to_msr_value(), 0x43_3824);
+
+        let event = parse("cpu/event=0xA3,umask=0x14,cmask=20,inv,edge=1,name=stalls_mem/:u");
+        assert_eq!(event.name, "stalls_mem");
+        assert_eq!(event.select.cmask, 20);
+        assert!(event.select.invert && event.select.edge && !event.select.any_thread);
+        assert!(event.select.usr && !event.select.os);
+
+        let event = parse("cpu/event=0xd1,umask=0x20/k");
+        assert!(!event.select.usr && event.select.os);
+
+        let event = parse("r3824:uk");
+        assert_eq!(
+            (event.select.event_select, event.select.umask),
+            (0x24, 0x38)
+        );
+        assert!(event.select.usr && event.select.os);
+
+        let event = parse("r00c0:k");
+        assert_eq!(
+            (event.select.event_select, event.select.umask),
+            (0xC0, 0x00)
+        );
+        assert!(!event.select.usr && event.select.os);
+    }
+
+    #[test]
+    fn test_reject_invalid_descriptors() {
+        for spec in [
+            "cpu/event=0x124,umask=0x38/",
+            "cpu/event=0x24,umask=256/",
+            "cpu/event=0x24,inv=2/",
+            "cpu/umask=0x38/",
+            "cpu/event=0x24,period=1000/",
+            "cpu/event=0x24",
+            "cpu/event=0x24/x",
+            "cpu/event=zz/",
+            "cpu/event=0/",
+            "r13824",
+            "rzz",
+            "cycles",
+        ] {
+            assert!(
+                spec.parse::<CustomEvent>().is_err(),
+                "{spec} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_event_file() {
+        let events = parse_event_file(
+            "# memory stalls\ncpu/event=0xa3,umask=0x14,cmask=20,name=stalls_mem/\n\nr3824 # L2 misses\n",
+        )
+        .unwrap();
+        let names: Vec<_> = events.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["stalls_mem", "r3824"]);
+
+        assert!(parse_event_file("cpu/event=0x24/\nbogus\n").is_err());
+    }
+}
@@ -1,4 +1,5 @@
 pub mod events;
 pub mod monitor;
 
-pub use monitor::CoreMonitor;
+pub use events::CustomEvent;
+pub use monitor::{diff, CoreMonitor, CounterSnapshot};
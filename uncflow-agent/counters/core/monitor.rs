@@ -1,9 +1,15 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
 
-use crate::common::msr;
+use crate::common::counter::wrapping_delta;
+use crate::common::msr::{self, MsrAccess};
+use crate::common::perf::{self, PerfCounter, PerfEvent};
+use crate::common::{register, sys_roots, CounterBackend};
 use crate::config::ExportConfig;
 use crate::counters::core::events::*;
-use crate::error::Result;
+use crate::error::{Result, UncflowError};
+use uncflow_raw::current_arch::core::{self, offcore_events, CorePerfEvtSel};
+use uncflow_raw::RegisterLayout;
 
 #[derive(Debug, Clone, Default)]
 pub struct CoreMetrics {
@@ -20,21 +26,246 @@ pub struct CoreMetrics {
     pub l2_out_non_silent: u64,
     pub l2_in: u64,
     pub l2_writeback: u64,
+    pub license_lvl0: u64,
+    pub license_lvl1: u64,
+    pub license_lvl2: u64,
+    pub offcore_local_dram: u64,
+    pub offcore_remote_dram: u64,
+    /// Counts of --core-event events, by name
+    pub custom: BTreeMap<Arc<str>, u64>,
+    pub aperf: u64,
+    pub mperf: u64,
     pub tsc_start: u64,
     pub tsc_end: u64,
 }
 
+impl CoreMetrics {
+    /// Wrap-aware difference between this reading and a later one
+    fn delta(&self, later: &CoreMetrics) -> CoreMetrics {
+        let d = |a: u64, b: u64| wrapping_delta(a, b, core::COUNTER_WIDTH_BITS);
+        CoreMetrics {
+            instructions: d(self.instructions, later.instructions),
+            cycles: d(self.cycles, later.cycles),
+            ref_cycles: d(self.ref_cycles, later.ref_cycles),
+            llc_ref: d(self.llc_ref, later.llc_ref),
+            llc_miss: d(self.llc_miss, later.llc_miss),
+            l2_ref: d(self.l2_ref, later.l2_ref),
+            l2_miss: d(self.l2_miss, later.l2_miss),
+            l2_prefetch_miss: d(self.l2_prefetch_miss, later.l2_prefetch_miss),
+            l2_prefetch_hit: d(self.l2_prefetch_hit, later.l2_prefetch_hit),
+            l2_out_silent: d(self.l2_out_silent, later.l2_out_silent),
+            l2_out_non_silent: d(self.l2_out_non_silent, later.l2_out_non_silent),
+            l2_in: d(self.l2_in, later.l2_in),
+            l2_writeback: d(self.l2_writeback, later.l2_writeback),
+            license_lvl0: d(self.license_lvl0, later.license_lvl0),
+            license_lvl1: d(self.license_lvl1, later.license_lvl1),
+            license_lvl2: d(self.license_lvl2, later.license_lvl2),
+            offcore_local_dram: d(self.offcore_local_dram, later.offcore_local_dram),
+            offcore_remote_dram: d(self.offcore_remote_dram, later.offcore_remote_dram),
+            custom: later
+                .custom
+                .iter()
+                .map(|(name, &count)| {
+                    let before = self.custom.get(name).copied().unwrap_or(0);
+                    (Arc::clone(name), d(before, count))
+                })
+                .collect(),
+            aperf: wrapping_delta(self.aperf, later.aperf, APERF_MPERF_WIDTH_BITS),
+            mperf: wrapping_delta(self.mperf, later.mperf, APERF_MPERF_WIDTH_BITS),
+            tsc_start: self.tsc_start,
+            tsc_end: later.tsc_end,
+        }
+    }
+
+    /// Store a programmable counter value in the field of the event it counts
+    fn set_programmable(&mut self, event: &PmuEvent, value: u64) {
+        match event.name {
+            "LLCReference" => self.llc_ref = value,
+            "LLCMisses" => self.llc_miss = value,
+            "L2RequestMisses" => self.l2_miss = value,
+            "L2RequestReference" => self.l2_ref = value,
+            CORE_POWER_LVL0 => self.license_lvl0 = value,
+            CORE_POWER_LVL1 => self.license_lvl1 = value,
+            CORE_POWER_LVL2 => self.license_lvl2 = value,
+            CORE_OFFCORE_LOCAL_DRAM => self.offcore_local_dram = value,
+            CORE_OFFCORE_REMOTE_DRAM => self.offcore_remote_dram = value,
+            _ => {}
+        }
+    }
+
+    /// This reading with the programmable counters cleared, as right after reprogramming
+    fn fixed_only(&self) -> CoreMetrics {
+        CoreMetrics {
+            instructions: self.instructions,
+            cycles: self.cycles,
+            ref_cycles: self.ref_cycles,
+            aperf: self.aperf,
+            mperf: self.mperf,
+            tsc_start: self.tsc_start,
+            tsc_end: self.tsc_end,
+            ..Default::default()
+        }
+    }
+
+    /// Add one window's counts, extrapolating programmable counts by `scale`, the
+    /// number of event groups taking turns on the counters
+    ///
+    /// APERF and MPERF only feed the effective frequency, which is a gauge of the
+    /// last window, so they hold that window's deltas rather than totals.
+    fn accumulate(&mut self, window: &CoreMetrics, scale: u64) {
+        self.instructions += window.instructions;
+        self.cycles += window.cycles;
+        self.ref_cycles += window.ref_cycles;
+        self.aperf = window.aperf;
+        self.mperf = window.mperf;
+        for (total, count) in [
+            (&mut self.llc_ref, window.llc_ref),
+            (&mut self.llc_miss, window.llc_miss),
+            (&mut self.l2_ref, window.l2_ref),
+            (&mut self.l2_miss, window.l2_miss),
+            (&mut self.l2_prefetch_miss, window.l2_prefetch_miss),
+            (&mut self.l2_prefetch_hit, window.l2_prefetch_hit),
+            (&mut self.l2_out_silent, window.l2_out_silent),
+            (&mut self.l2_out_non_silent, window.l2_out_non_silent),
+            (&mut self.l2_in, window.l2_in),
+            (&mut self.l2_writeback, window.l2_writeback),
+            (&mut self.license_lvl0, window.license_lvl0),
+            (&mut self.license_lvl1, window.license_lvl1),
+            (&mut self.license_lvl2, window.license_lvl2),
+            (&mut self.offcore_local_dram, window.offcore_local_dram),
+            (&mut self.offcore_remote_dram, window.offcore_remote_dram),
+        ] {
+            *total += count * scale;
+        }
+        for (name, &count) in &window.custom {
+            *self.custom.entry(Arc::clone(name)).or_insert(0) += count * scale;
+        }
+        self.tsc_end = window.tsc_end;
+    }
+}
+
+/// Opaque point-in-time reading of the core counters, see [`diff`]
+///
+/// With more than one event group, programmable counts are only comparable between
+/// snapshots taken while the same group is programmed (between two `collect()` calls).
+#[derive(Debug, Clone)]
+pub struct CounterSnapshot {
+    counters: HashMap<i32, CoreMetrics>,
+    tsc_frequency: f64,
+}
+
+/// Per-core metrics over the window between two snapshots
+///
+/// Cores missing from either snapshot are skipped.
+pub fn diff(
+    before: &CounterSnapshot,
+    after: &CounterSnapshot,
+) -> HashMap<i32, HashMap<String, f64>> {
+    after
+        .counters
+        .iter()
+        .filter_map(|(&core, later)| {
+            let earlier = before.counters.get(&core)?;
+            Some((
+                core,
+                derive_metrics(&earlier.delta(later), after.tsc_frequency),
+            ))
+        })
+        .collect()
+}
+
+/// Event of one programmable counter in a group
+#[derive(Debug, Clone)]
+enum GroupEvent {
+    Builtin(PmuEvent),
+    /// A --core-event event with its PERFEVTSEL value, which PmuEvent cannot encode
+    Custom {
+        name: Arc<str>,
+        select: u64,
+    },
+}
+
+impl GroupEvent {
+    fn name(&self) -> &str {
+        match self {
+            Self::Builtin(event) => event.name,
+            Self::Custom { name, .. } => name,
+        }
+    }
+}
+
+/// Built-in events as a group
+fn builtin_group(events: Vec<PmuEvent>) -> Vec<GroupEvent> {
+    events.into_iter().map(GroupEvent::Builtin).collect()
+}
+
+// perf counters of one core, in MSR order: fixed counters, then PMC0..PMC3
+struct PerfCoreCounters {
+    instructions: PerfCounter,
+    cycles: PerfCounter,
+    ref_cycles: PerfCounter,
+    programmable: Vec<PerfCounter>,
+}
+
+impl PerfCoreCounters {
+    fn open(core: i32, events: &[GroupEvent]) -> Result<Self> {
+        let cpu = core as u32;
+        let open_hw = |config| PerfCounter::open(&PerfEvent::hardware(config), cpu);
+        Ok(Self {
+            instructions: open_hw(perf::hardware::INSTRUCTIONS)?,
+            cycles: open_hw(perf::hardware::CPU_CYCLES)?,
+            ref_cycles: open_hw(perf::hardware::REF_CPU_CYCLES)?,
+            // User mode only, like the PERFEVTSEL programming
+            // --core-event events need the MSR backend, so only built-in ones are here
+            programmable: events
+                .iter()
+                .filter_map(|event| match event {
+                    GroupEvent::Builtin(e) => Some(e),
+                    GroupEvent::Custom { .. } => None,
+                })
+                .map(|e| PerfCounter::open(&PerfEvent::raw(e.event, e.umask, false), cpu))
+                .collect::<Result<_>>()?,
+        })
+    }
+}
+
+/// Core PMU monitor
+///
+/// The default event set occupies all general-purpose counters. Optional groups (the
+/// AVX frequency license and offcore response events) take turns with it, switching on every `collect()`;
+/// a group's counts are then extrapolated by the number of groups, like perf does when
+/// it multiplexes.
 pub struct CoreMonitor {
     config: ExportConfig,
-    cpu_frequency: f64,
-    prev_metrics: HashMap<i32, CoreMetrics>,
-    programmable_events: Vec<PmuEvent>,
+    msr: &'static dyn MsrAccess,
+    tsc_frequency: f64,
+    // Latest raw reading per core, the start of the next window
+    last_readings: HashMap<i32, CoreMetrics>,
+    // Counts since initialize() or reset(), get_metrics() derives from these
+    totals: HashMap<i32, CoreMetrics>,
+    // Groups taking turns on the programmable counters, the default set first
+    event_groups: Vec<Vec<GroupEvent>>,
+    current_group: usize,
+    // Response matrices for MSR_OFFCORE_RSP_0/1 when the offcore group is counted
+    offcore_events: Option<[OffcoreEvent; 2]>,
+    perf_counters: HashMap<i32, PerfCoreCounters>,
 }
 
 impl CoreMonitor {
     pub fn new(config: ExportConfig) -> Result<Self> {
-        let cpu_frequency = Self::get_cpu_frequency()?;
-        tracing::info!("Detected CPU frequency: {:.2} GHz", cpu_frequency / 1e9);
+        Self::with_msr(config, msr::Msr::instance())
+    }
+
+    /// Monitor programming the MSR backend's counters through `msr`
+    pub fn with_msr(config: ExportConfig, msr: &'static dyn MsrAccess) -> Result<Self> {
+        let cpu_frequency = match config.backend {
+            CounterBackend::Msr => Self::get_cpu_frequency(msr)?,
+            CounterBackend::Perf => Self::get_cpufreq_base_frequency(&config)?,
+        };
+        // CPUID 0x15 when enumerated, otherwise the base (max non-turbo) frequency,
+        // which the TSC runs at on server parts
+        let tsc_frequency = crate::common::cpuid::tsc_frequency().unwrap_or(cpu_frequency);
+        tracing::info!("Detected TSC frequency: {:.2} GHz", tsc_frequency / 1e9);
 
         // Get the default event set (architecture-aware)
         let programmable_events = crate::counters::core::events::get_default_event_set();
@@ -45,202 +276,812 @@ impl CoreMonitor {
             crate::common::CPU_ARCH.name()
         );
 
-        let prev_metrics = HashMap::new();
+        let mut event_groups = vec![builtin_group(programmable_events)];
+        if config.core_avx_license {
+            event_groups.extend(
+                Self::optional_group(config.backend, "frequency license", get_license_event_set())
+                    .map(builtin_group),
+            );
+        }
+        Self::custom_groups(&config, &mut event_groups)?;
+        let offcore_events = if config.core_offcore {
+            Self::optional_group(config.backend, "offcore response", get_offcore_event_set())
+        } else {
+            None
+        };
 
-        Ok(Self {
+        let monitor = Self {
             config,
-            cpu_frequency,
-            prev_metrics,
-            programmable_events,
+            msr,
+            tsc_frequency,
+            last_readings: HashMap::new(),
+            totals: HashMap::new(),
+            event_groups,
+            current_group: 0,
+            offcore_events: None,
+            perf_counters: HashMap::new(),
+        };
+        match offcore_events {
+            Some(events) => monitor.with_offcore_events(events),
+            None => Ok(monitor),
+        }
+    }
+
+    // Append the --core-event events as groups of up to GENERAL_PURPOSE_COUNTERS
+    fn custom_groups(config: &ExportConfig, event_groups: &mut Vec<Vec<GroupEvent>>) -> Result<()> {
+        if config.core_events.is_empty() {
+            return Ok(());
+        }
+        if config.backend == CounterBackend::Perf {
+            return Err(UncflowError::InvalidConfiguration(
+                "--core-event events need the MSR backend".to_string(),
+            ));
+        }
+
+        let builtin = |name: &str| {
+            [CORE_OFFCORE_LOCAL_DRAM, CORE_OFFCORE_REMOTE_DRAM].contains(&name)
+                || event_groups
+                    .iter()
+                    .flatten()
+                    .any(|event| event.name() == name)
+        };
+        let mut names = HashSet::new();
+        let mut events = Vec::new();
+        for custom in &config.core_events {
+            if builtin(&custom.name) {
+                return Err(UncflowError::InvalidConfiguration(format!(
+                    "Core event name '{}' is taken by a built-in event",
+                    custom.name
+                )));
+            }
+            if !names.insert(custom.name.as_str()) {
+                return Err(UncflowError::InvalidConfiguration(format!(
+                    "Core event name '{}' given more than once",
+                    custom.name
+                )));
+            }
+            events.push(GroupEvent::Custom {
+                name: Arc::from(custom.name.as_str()),
+                select: custom.select.to_msr_value(),
+            });
+        }
+        tracing::info!(
+            "Multiplexing {} custom core events in {} groups",
+            events.len(),
+            events.len().div_ceil(GENERAL_PURPOSE_COUNTERS)
+        );
+        event_groups.extend(
+            events
+                .chunks(GENERAL_PURPOSE_COUNTERS)
+                .map(<[GroupEvent]>::to_vec),
+        );
+        Ok(())
+    }
+
+    // Events for an extra multiplexed group, if the backend and architecture support them
+    fn optional_group<T>(backend: CounterBackend, kind: &str, events: Option<T>) -> Option<T> {
+        match (backend, events) {
+            (CounterBackend::Perf, _) => {
+                tracing::warn!("{kind} events need the MSR backend, not counting them");
+                None
+            }
+            (CounterBackend::Msr, None) => {
+                tracing::warn!(
+                    "{} has no supported {kind} events, not counting them",
+                    crate::common::CPU_ARCH.name()
+                );
+                None
+            }
+            (CounterBackend::Msr, Some(events)) => Some(events),
+        }
+    }
+
+    /// Count `events` on the two offcore response counters, in a group of their own
+    ///
+    /// Replaces the default local/remote DRAM events selected by `core_offcore`.
+    /// Must be called before `initialize()`.
+    pub fn with_offcore_events(mut self, events: [OffcoreEvent; 2]) -> Result<Self> {
+        if self.config.backend == CounterBackend::Perf {
+            return Err(UncflowError::InvalidConfiguration(
+                "Offcore response events need the MSR backend".to_string(),
+            ));
+        }
+
+        self.event_groups
+            .retain(|group| !Self::is_offcore_group(group));
+        self.event_groups
+            .push(builtin_group(offcore_event_group(&events)));
+        self.offcore_events = Some(events);
+        Ok(self)
+    }
+
+    fn is_offcore_group(events: &[GroupEvent]) -> bool {
+        events.iter().any(|event| {
+            matches!(event, GroupEvent::Builtin(e)
+                if (e.event, e.umask) == offcore_events::OFFCORE_RESPONSE_0)
         })
     }
 
-    fn get_cpu_frequency() -> Result<f64> {
+    /// Whether the AVX frequency license group is multiplexed in
+    pub fn counts_avx_license(&self) -> bool {
+        self.event_groups
+            .iter()
+            .flatten()
+            .any(|event| event.name() == CORE_POWER_LVL0)
+    }
+
+    /// Whether the offcore response group is multiplexed in
+    pub fn counts_offcore(&self) -> bool {
+        self.offcore_events.is_some()
+    }
+
+    /// Names of the --core-event events being counted
+    pub fn custom_event_names(&self) -> Vec<Arc<str>> {
+        self.event_groups
+            .iter()
+            .flatten()
+            .filter_map(|event| match event {
+                GroupEvent::Custom { name, .. } => Some(Arc::clone(name)),
+                GroupEvent::Builtin(_) => None,
+            })
+            .collect()
+    }
+
+    // PERFEVTSEL value for `event`, user mode only unless given by --core-event
+    fn event_select(&self, event: &GroupEvent) -> u64 {
+        match event {
+            GroupEvent::Builtin(event) => event.encode_for_perfevtsel(true, false),
+            GroupEvent::Custom { select, .. } => *select,
+        }
+    }
+
+    fn get_cpu_frequency(msr: &dyn MsrAccess) -> Result<f64> {
         // Read MSR_PLATFORM_INFO to get base frequency
-        let platform_info = msr::read_msr(0, MSR_PLATFORM_INFO)?;
+        let platform_info = msr.read(0, MSR_PLATFORM_INFO)?;
         let max_non_turbo_ratio = (platform_info >> 8) & 0xFF;
         let frequency = (max_non_turbo_ratio as f64) * 100_000_000.0; // 100 MHz per ratio
         Ok(frequency)
     }
 
+    fn get_cpufreq_base_frequency(config: &ExportConfig) -> Result<f64> {
+        // Without MSR access, use the cpufreq base frequency (kHz) of the first monitored core
+        let core = config.cores.first().copied().unwrap_or(0);
+        let khz = std::fs::read_to_string(sys_roots().base_frequency_path(core))?
+            .trim()
+            .parse::<f64>()
+            .map_err(|e| UncflowError::ParseError(format!("Invalid base frequency: {e}")))?;
+        Ok(khz * 1e3)
+    }
+
+    /// Build and validate the event select registers without touching hardware
+    pub fn validate_program(&self) -> Result<()> {
+        for events in &self.event_groups {
+            if events.len() > GENERAL_PURPOSE_COUNTERS {
+                return Err(UncflowError::InvalidConfiguration(format!(
+                    "{} core PMU events selected, only {} general-purpose counters available",
+                    events.len(),
+                    GENERAL_PURPOSE_COUNTERS
+                )));
+            }
+
+            let selects: Vec<CorePerfEvtSel> = events
+                .iter()
+                .map(|event| CorePerfEvtSel::from_msr_value(self.event_select(event)))
+                .collect();
+            register::validate_all("Core PMU events", &selects)?;
+        }
+
+        if let Some(events) = &self.offcore_events {
+            register::validate_all(
+                "Core offcore responses",
+                events.iter().map(|event| &event.response),
+            )?;
+        }
+        Ok(())
+    }
+
     pub fn initialize(&mut self) -> Result<()> {
+        self.validate_program()?;
+
         let cores = self.config.cores.clone();
-        for core in cores {
-            self.initialize_core(core)?;
-            tracing::info!("Initialized PMU for core {}", core);
+        for &core in &cores {
+            if self.config.backend == CounterBackend::Perf {
+                let counters = PerfCoreCounters::open(core, &self.event_groups[0])?;
+                self.perf_counters.insert(core, counters);
+                tracing::info!("Opened perf counters for core {}", core);
+            } else {
+                self.initialize_core(core)?;
+                tracing::info!("Initialized PMU for core {}", core);
+            }
         }
-        Ok(())
+
+        // Start of the window get_metrics() reports over
+        self.reset()
     }
 
     fn initialize_core(&self, core: i32) -> Result<()> {
         let core_u32 = core as u32;
 
         // Disable all counters
-        msr::write_msr(core_u32, IA32_PERF_GLOBAL_CTRL, 0)?;
+        self.msr.write(core_u32, IA32_PERF_GLOBAL_CTRL, 0)?;
 
         // Configure fixed counters (instructions, cycles, ref cycles)
         // Enable user mode counting for all 3 fixed counters
         let fixed_ctrl = 0x333u64; // User mode for CTR0, CTR1, CTR2
-        msr::write_msr(core_u32, IA32_FIXED_CTR_CTRL, fixed_ctrl)?;
+        self.msr.write(core_u32, IA32_FIXED_CTR_CTRL, fixed_ctrl)?;
 
-        // Program the programmable counters
-        for (i, event) in self.programmable_events.iter().enumerate() {
-            let perfevtsel_addr = IA32_PERFEVTSEL0 + (i as u64);
-            let event_config = event.encode_for_perfevtsel(true, false);
-            msr::write_msr(core_u32, perfevtsel_addr, event_config)?;
+        // Response matrices only take effect while an OFFCORE_RESPONSE event is selected,
+        // so they stay programmed while other groups have the counters
+        if let Some(events) = &self.offcore_events {
+            let matrices = [core::msr::MSR_OFFCORE_RSP_0, core::msr::MSR_OFFCORE_RSP_1];
+            for (addr, event) in matrices.into_iter().zip(events) {
+                self.msr
+                    .write(core_u32, addr, event.response.to_msr_value())?;
+            }
         }
 
-        // Clear all counters
-        msr::write_msr(core_u32, IA32_FIXED_CTR0, 0)?;
-        msr::write_msr(core_u32, IA32_FIXED_CTR1, 0)?;
-        msr::write_msr(core_u32, IA32_FIXED_CTR2, 0)?;
-        for i in 0..4 {
-            let pmc_addr = IA32_PMC0 + (i as u64);
-            msr::write_msr(core_u32, pmc_addr, 0)?;
-        }
+        // Program and clear the programmable counters
+        self.program_group(core, &self.event_groups[self.current_group])?;
+
+        // Clear the fixed counters
+        self.msr.write(core_u32, IA32_FIXED_CTR0, 0)?;
+        self.msr.write(core_u32, IA32_FIXED_CTR1, 0)?;
+        self.msr.write(core_u32, IA32_FIXED_CTR2, 0)?;
 
         // Enable all counters: 3 fixed + 4 programmable
         let global_ctrl = (0x7u64 << 32) | 0xFu64; // Fixed[2:0] + PMC[3:0]
-        msr::write_msr(core_u32, IA32_PERF_GLOBAL_CTRL, global_ctrl)?;
+        self.msr
+            .write(core_u32, IA32_PERF_GLOBAL_CTRL, global_ctrl)?;
 
         Ok(())
     }
 
+    // Point the programmable counters at `events` and clear them; unused counters stay off
+    fn program_group(&self, core: i32, events: &[GroupEvent]) -> Result<()> {
+        let core_u32 = core as u32;
+        for i in 0..GENERAL_PURPOSE_COUNTERS {
+            let select = events.get(i).map_or(0, |event| self.event_select(event));
+            self.msr
+                .write(core_u32, IA32_PERFEVTSEL0 + i as u64, select)?;
+            self.msr.write(core_u32, IA32_PMC0 + i as u64, 0)?;
+        }
+        Ok(())
+    }
+
+    // Move the programmable counters on to the next group, whose window starts at zero
+    fn rotate_group(&mut self) -> Result<()> {
+        self.current_group = (self.current_group + 1) % self.event_groups.len();
+        let events = self.event_groups[self.current_group].clone();
+        for core in self.config.cores.clone() {
+            self.program_group(core, &events)?;
+            if let Some(last) = self.last_readings.get_mut(&core) {
+                *last = last.fixed_only();
+            }
+        }
+        Ok(())
+    }
+
+    fn read_perf_counters(&self, core: i32) -> Result<CoreMetrics> {
+        let counters = self.perf_counters.get(&core).ok_or_else(|| {
+            UncflowError::InvalidConfiguration(format!("No perf counters open for core {core}"))
+        })?;
+
+        // The TSC is invariant and synchronized across cores, any CPU's value will do
+        // SAFETY: RDTSC has no preconditions on x86_64
+        let tsc_start = unsafe { std::arch::x86_64::_rdtsc() };
+
+        let mut metrics = CoreMetrics {
+            instructions: counters.instructions.read()?,
+            cycles: counters.cycles.read()?,
+            ref_cycles: counters.ref_cycles.read()?,
+            tsc_start,
+            ..Default::default()
+        };
+        for (event, counter) in self.event_groups[0].iter().zip(&counters.programmable) {
+            if let GroupEvent::Builtin(event) = event {
+                metrics.set_programmable(event, counter.read()?);
+            }
+        }
+        // SAFETY: as above
+        metrics.tsc_end = unsafe { std::arch::x86_64::_rdtsc() };
+        Ok(metrics)
+    }
+
     fn read_core_counters(&self, core: i32) -> Result<CoreMetrics> {
+        if self.config.backend == CounterBackend::Perf {
+            return self.read_perf_counters(core);
+        }
+
         let core_u32 = core as u32;
 
         // Read TSC first
-        let tsc_start = msr::read_msr(core_u32, IA32_TIME_STAMP_COUNTER)?;
+        let tsc_start = self.msr.read(core_u32, IA32_TIME_STAMP_COUNTER)?;
 
         // Read fixed counters
-        let instructions = msr::read_msr(core_u32, IA32_FIXED_CTR0)?;
-        let cycles = msr::read_msr(core_u32, IA32_FIXED_CTR1)?;
-        let ref_cycles = msr::read_msr(core_u32, IA32_FIXED_CTR2)?;
-
-        // Read programmable counters (matching our event programming)
-        let llc_ref = msr::read_msr(core_u32, IA32_PMC0)?;
-        let llc_miss = msr::read_msr(core_u32, IA32_PMC1)?;
-        let l2_miss = msr::read_msr(core_u32, IA32_PMC2)?;
-        let l2_ref = msr::read_msr(core_u32, IA32_PMC3)?;
-
-        // For now, set other L2 metrics to 0 (would need event multiplexing)
-        let metrics = CoreMetrics {
-            instructions,
-            cycles,
-            ref_cycles,
-            llc_ref,
-            llc_miss,
-            l2_ref,
-            l2_miss,
-            l2_prefetch_miss: 0,
-            l2_prefetch_hit: 0,
-            l2_out_silent: 0,
-            l2_out_non_silent: 0,
-            l2_in: 0,
-            l2_writeback: 0,
+        let mut metrics = CoreMetrics {
+            instructions: self.msr.read(core_u32, IA32_FIXED_CTR0)?,
+            cycles: self.msr.read(core_u32, IA32_FIXED_CTR1)?,
+            ref_cycles: self.msr.read(core_u32, IA32_FIXED_CTR2)?,
+            aperf: self.msr.read(core_u32, IA32_APERF)?,
+            mperf: self.msr.read(core_u32, IA32_MPERF)?,
             tsc_start,
-            tsc_end: tsc_start,
+            ..Default::default()
         };
 
+        // Read programmable counters of the group currently programmed; the other
+        // L2 metrics stay 0 (no group counts them yet)
+        for (i, event) in self.event_groups[self.current_group].iter().enumerate() {
+            let value = self.msr.read(core_u32, IA32_PMC0 + i as u64)?;
+            match event {
+                GroupEvent::Builtin(event) => metrics.set_programmable(event, value),
+                GroupEvent::Custom { name, .. } => {
+                    metrics.custom.insert(Arc::clone(name), value);
+                }
+            }
+        }
+
+        // Read TSC again so the reading is bracketed by timestamps
+        metrics.tsc_end = self.msr.read(core_u32, IA32_TIME_STAMP_COUNTER)?;
+
         Ok(metrics)
     }
 
+    /// Add the window since the previous collect to every core's totals, then hand the
+    /// programmable counters to the next event group
     pub fn collect(&mut self) -> Result<()> {
-        let cores = self.config.cores.clone();
-        for core in cores {
-            let metrics = self.read_core_counters(core)?;
-            self.prev_metrics.insert(core, metrics);
+        let scale = self.event_groups.len() as u64;
+        for core in self.config.cores.clone() {
+            let reading = self.read_core_counters(core)?;
+            if let Some(last) = self.last_readings.get(&core) {
+                let window = last.delta(&reading);
+                self.totals
+                    .entry(core)
+                    .or_insert_with(|| CoreMetrics {
+                        tsc_start: window.tsc_start,
+                        tsc_end: window.tsc_start,
+                        ..Default::default()
+                    })
+                    .accumulate(&window, scale);
+            }
+            self.last_readings.insert(core, reading);
+        }
+
+        if self.event_groups.len() > 1 {
+            self.rotate_group()?;
+        }
+        Ok(())
+    }
+
+    /// Re-read the baseline of every core, so get_metrics() reports totals since now
+    pub fn reset(&mut self) -> Result<()> {
+        self.totals.clear();
+        for core in self.config.cores.clone() {
+            let baseline = self.read_core_counters(core)?;
+            self.last_readings.insert(core, baseline);
         }
         Ok(())
     }
 
+    /// Read all configured cores without touching the collect() state
+    pub fn snapshot(&self) -> Result<CounterSnapshot> {
+        let mut counters = HashMap::new();
+        for &core in &self.config.cores {
+            counters.insert(core, self.read_core_counters(core)?);
+        }
+        Ok(CounterSnapshot {
+            counters,
+            tsc_frequency: self.tsc_frequency,
+        })
+    }
+
     pub fn get_metrics(&self, core: i32) -> HashMap<String, f64> {
-        let mut result = HashMap::new();
+        self.totals
+            .get(&core)
+            .map(|totals| derive_metrics(totals, self.tsc_frequency))
+            .unwrap_or_default()
+    }
 
-        if let Some(metrics) = self.prev_metrics.get(&core) {
-            // Basic counters
-            result.insert("instructions".to_string(), metrics.instructions as f64);
-            result.insert("cycles".to_string(), metrics.cycles as f64);
+    /// Extrapolated counts of the --core-event events on `core`, by name
+    pub fn custom_event_counts(&self, core: i32) -> BTreeMap<Arc<str>, u64> {
+        self.totals
+            .get(&core)
+            .map(|totals| totals.custom.clone())
+            .unwrap_or_default()
+    }
+}
 
-            // Derived metrics
-            let ipc = if metrics.cycles > 0 {
-                metrics.instructions as f64 / metrics.cycles as f64
-            } else {
-                0.0
-            };
-            result.insert("IPC".to_string(), ipc);
+/// Seconds between two TSC reads
+fn elapsed_seconds(tsc_start: u64, tsc_end: u64, tsc_frequency: f64) -> f64 {
+    if tsc_frequency > 0.0 {
+        tsc_end.wrapping_sub(tsc_start) as f64 / tsc_frequency
+    } else {
+        0.0
+    }
+}
 
-            // L3 (LLC) metrics
-            result.insert("L3CacheMissNum".to_string(), metrics.llc_miss as f64);
-            result.insert("L3CacheRef".to_string(), metrics.llc_ref as f64);
+/// Derive the exported core metrics from a set of counter values
+fn derive_metrics(metrics: &CoreMetrics, tsc_frequency: f64) -> HashMap<String, f64> {
+    let mut result = HashMap::new();
 
-            let l3_hit_ratio = if metrics.llc_ref > 0 {
-                1.0 - (metrics.llc_miss as f64 / metrics.llc_ref as f64)
-            } else {
-                0.0
-            };
-            result.insert("L3CacheHitRatio".to_string(), l3_hit_ratio);
+    // Basic counters
+    result.insert("instructions".to_string(), metrics.instructions as f64);
+    result.insert("cycles".to_string(), metrics.cycles as f64);
 
-            // L3 MPI (Misses Per Instruction)
-            let l3_mpi = if metrics.instructions > 0 {
-                (metrics.llc_miss as f64) / (metrics.instructions as f64)
-            } else {
-                0.0
-            };
-            result.insert("L3MPI".to_string(), l3_mpi);
+    // Derived metrics
+    let ipc = if metrics.cycles > 0 {
+        metrics.instructions as f64 / metrics.cycles as f64
+    } else {
+        0.0
+    };
+    result.insert("IPC".to_string(), ipc);
 
-            // L2 metrics
-            result.insert("L2CacheMissNum".to_string(), metrics.l2_miss as f64);
-            result.insert("L2CacheRef".to_string(), metrics.l2_ref as f64);
+    // L3 (LLC) metrics
+    result.insert("L3CacheMissNum".to_string(), metrics.llc_miss as f64);
+    result.insert("L3CacheRef".to_string(), metrics.llc_ref as f64);
 
-            let l2_hit_ratio = if metrics.l2_ref > 0 {
-                1.0 - (metrics.l2_miss as f64 / metrics.l2_ref as f64)
-            } else {
-                0.0
-            };
-            result.insert("L2CacheHitRatio".to_string(), l2_hit_ratio);
+    let l3_hit_ratio = if metrics.llc_ref > 0 {
+        1.0 - (metrics.llc_miss as f64 / metrics.llc_ref as f64)
+    } else {
+        0.0
+    };
+    result.insert("L3CacheHitRatio".to_string(), l3_hit_ratio);
 
-            // L2 MPI
-            let l2_mpi = if metrics.instructions > 0 {
-                (metrics.l2_miss as f64) / (metrics.instructions as f64)
-            } else {
-                0.0
-            };
-            result.insert("L2MPI".to_string(), l2_mpi);
+    // L3 MPI (Misses Per Instruction)
+    let l3_mpi = if metrics.instructions > 0 {
+        (metrics.llc_miss as f64) / (metrics.instructions as f64)
+    } else {
+        0.0
+    };
+    result.insert("L3MPI".to_string(), l3_mpi);
 
-            // Elapsed time (approximate from ref cycles)
-            let elapsed_time = if self.cpu_frequency > 0.0 {
-                (metrics.ref_cycles as f64) / self.cpu_frequency
-            } else {
-                0.0
-            };
-            result.insert("elapsedTime".to_string(), elapsed_time);
-
-            // Other L2 metrics (currently 0, would need event multiplexing)
-            result.insert(
-                "L2PrefetchMiss".to_string(),
-                metrics.l2_prefetch_miss as f64,
-            );
-            result.insert("L2PrefetchHit".to_string(), metrics.l2_prefetch_hit as f64);
-            result.insert("L2OutSilent".to_string(), metrics.l2_out_silent as f64);
-            result.insert(
-                "L2OutNonSilent".to_string(),
-                metrics.l2_out_non_silent as f64,
-            );
-            result.insert("L2In".to_string(), metrics.l2_in as f64);
-            result.insert("L2Writeback".to_string(), metrics.l2_writeback as f64);
-        }
+    // L2 metrics
+    result.insert("L2CacheMissNum".to_string(), metrics.l2_miss as f64);
+    result.insert("L2CacheRef".to_string(), metrics.l2_ref as f64);
 
-        result
+    let l2_hit_ratio = if metrics.l2_ref > 0 {
+        1.0 - (metrics.l2_miss as f64 / metrics.l2_ref as f64)
+    } else {
+        0.0
+    };
+    result.insert("L2CacheHitRatio".to_string(), l2_hit_ratio);
+
+    // L2 MPI
+    let l2_mpi = if metrics.instructions > 0 {
+        (metrics.l2_miss as f64) / (metrics.instructions as f64)
+    } else {
+        0.0
+    };
+    result.insert("L2MPI".to_string(), l2_mpi);
+
+    result.insert(
+        "elapsedTime".to_string(),
+        elapsed_seconds(metrics.tsc_start, metrics.tsc_end, tsc_frequency),
+    );
+
+    // Other L2 metrics (currently 0, would need event multiplexing)
+    result.insert(
+        "L2PrefetchMiss".to_string(),
+        metrics.l2_prefetch_miss as f64,
+    );
+    result.insert("L2PrefetchHit".to_string(), metrics.l2_prefetch_hit as f64);
+    result.insert("L2OutSilent".to_string(), metrics.l2_out_silent as f64);
+    result.insert(
+        "L2OutNonSilent".to_string(),
+        metrics.l2_out_non_silent as f64,
+    );
+    result.insert("L2In".to_string(), metrics.l2_in as f64);
+    result.insert("L2Writeback".to_string(), metrics.l2_writeback as f64);
+
+    // LLC misses by where they were served, per offcore response matrix
+    result.insert(
+        CORE_OFFCORE_LOCAL_DRAM.to_string(),
+        metrics.offcore_local_dram as f64,
+    );
+    result.insert(
+        CORE_OFFCORE_REMOTE_DRAM.to_string(),
+        metrics.offcore_remote_dram as f64,
+    );
+
+    if let Some(ratio) = avx_license_cycles_ratio(metrics) {
+        result.insert("core_avx_license_cycles_ratio".to_string(), ratio);
+    }
+
+    if let Some(hz) = effective_frequency_hz(metrics.aperf, metrics.mperf, tsc_frequency) {
+        result.insert("core_effective_frequency_hz".to_string(), hz);
     }
+
+    result
+}
+
+/// Average clock of the core while it was not halted, from APERF and MPERF deltas
+///
+/// MPERF advances at the TSC rate, so `tsc_frequency * APERF / MPERF` is the achieved
+/// frequency including turbo and throttling. None when MPERF did not advance: the
+/// core slept through the window, or the perf backend does not read the MSRs.
+fn effective_frequency_hz(aperf: u64, mperf: u64, tsc_frequency: f64) -> Option<f64> {
+    (mperf > 0).then(|| tsc_frequency * aperf as f64 / mperf as f64)
+}
+
+/// Share of unhalted cycles run under an AVX (level 1 or 2) frequency license
+///
+/// Normalized by the license events themselves, which together count every unhalted
+/// cycle while their group is programmed, so multiplexing does not skew it. None
+/// when the license group has not counted anything.
+fn avx_license_cycles_ratio(metrics: &CoreMetrics) -> Option<f64> {
+    let avx = metrics.license_lvl1 + metrics.license_lvl2;
+    let total = metrics.license_lvl0 + avx;
+    (total > 0).then(|| avx as f64 / total as f64)
 }
 
 impl Drop for CoreMonitor {
     fn drop(&mut self) {
+        // perf counters are released with their file descriptors
+        if self.config.backend == CounterBackend::Perf {
+            return;
+        }
+
         // Disable all counters on cleanup
         let cores = self.config.cores.clone();
         for core in cores {
-            let _ = msr::write_msr(core as u32, IA32_PERF_GLOBAL_CTRL, 0);
+            let _ = self.msr.write(core as u32, IA32_PERF_GLOBAL_CTRL, 0);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::msr::mock::MockMsr;
+
+    #[test]
+    fn test_diff_snapshots() {
+        let before = CounterSnapshot {
+            counters: HashMap::from([(
+                0,
+                CoreMetrics {
+                    instructions: core::COUNTER_MASK - 99,
+                    cycles: 1_000,
+                    ref_cycles: 1_000,
+                    llc_ref: 10,
+                    tsc_start: 10_000,
+                    tsc_end: 10_400,
+                    ..Default::default()
+                },
+            )]),
+            tsc_frequency: 2e9,
+        };
+        let after = CounterSnapshot {
+            counters: HashMap::from([
+                (
+                    0,
+                    CoreMetrics {
+                        instructions: 1_900,
+                        cycles: 2_001_000,
+                        ref_cycles: 2_001_000,
+                        llc_ref: 110,
+                        llc_miss: 25,
+                        // Reference cycles drift from the TSC, elapsed time must not use them
+                        tsc_start: 2_009_600,
+                        tsc_end: 2_010_000,
+                        ..Default::default()
+                    },
+                ),
+                (1, CoreMetrics::default()),
+            ]),
+            tsc_frequency: 2e9,
+        };
+
+        let metrics = diff(&before, &after);
+        // Core 1 has no baseline and is skipped
+        assert_eq!(metrics.len(), 1);
+
+        let core0 = &metrics[&0];
+        assert_eq!(core0["instructions"], 2_000.0);
+        assert_eq!(core0["cycles"], 2_000_000.0);
+        assert!((core0["L3CacheHitRatio"] - 0.75).abs() < 1e-9);
+        // From the first read's start to the second read's end
+        assert!((core0["elapsedTime"] - 0.001).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_elapsed_from_tsc_delta() {
+        assert_eq!(elapsed_seconds(1_000, 3_001_000, 3e9), 0.001);
+        // The 64-bit TSC wrapping between reads
+        assert_eq!(elapsed_seconds(u64::MAX - 999, 1_000, 2e9), 1e-6);
+        assert_eq!(elapsed_seconds(0, 1_000, 0.0), 0.0);
+    }
+
+    #[test]
+    fn test_avx_license_cycles_ratio() {
+        let metrics = CoreMetrics {
+            license_lvl0: 600,
+            license_lvl1: 300,
+            license_lvl2: 100,
+            ..Default::default()
+        };
+        assert_eq!(avx_license_cycles_ratio(&metrics), Some(0.4));
+        assert_eq!(
+            derive_metrics(&metrics, 2e9)["core_avx_license_cycles_ratio"],
+            0.4
+        );
+
+        // Not reported before the license group has counted
+        assert_eq!(avx_license_cycles_ratio(&CoreMetrics::default()), None);
+        assert!(!derive_metrics(&CoreMetrics::default(), 2e9)
+            .contains_key("core_avx_license_cycles_ratio"));
+    }
+
+    #[test]
+    fn test_effective_frequency_from_aperf_mperf_delta() {
+        // APERF wraps between the reads; the core ran 1.5x its base clock
+        let before = CoreMetrics {
+            aperf: u64::MAX - 499,
+            mperf: 10_000,
+            ..Default::default()
+        };
+        let after = CoreMetrics {
+            aperf: 2_500,
+            mperf: 12_000,
+            ..Default::default()
+        };
+        let window = before.delta(&after);
+        assert_eq!((window.aperf, window.mperf), (3_000, 2_000));
+        assert_eq!(
+            effective_frequency_hz(window.aperf, window.mperf, 2e9),
+            Some(3e9)
+        );
+        assert_eq!(
+            derive_metrics(&window, 2e9)["core_effective_frequency_hz"],
+            3e9
+        );
+
+        // No C0 time, no frequency
+        assert_eq!(effective_frequency_hz(0, 0, 2e9), None);
+    }
+
+    #[test]
+    fn test_effective_frequency_follows_last_window() {
+        let msr = MockMsr::new().leak();
+        let mut monitor = CoreMonitor::with_msr(ExportConfig::new(vec![0], vec![0]), msr).unwrap();
+        monitor.tsc_frequency = 2e9;
+        monitor.initialize().unwrap();
+
+        // Window 1 at twice the base clock, for much longer than window 2
+        msr.set(0, IA32_APERF, 20_000);
+        msr.set(0, IA32_MPERF, 10_000);
+        monitor.collect().unwrap();
+        assert_eq!(monitor.get_metrics(0)["core_effective_frequency_hz"], 4e9);
+
+        // Window 2 at half the base clock; cumulative totals would still say 1.9x
+        msr.set(0, IA32_APERF, 20_500);
+        msr.set(0, IA32_MPERF, 11_000);
+        monitor.collect().unwrap();
+        let metrics = monitor.get_metrics(0);
+        assert_eq!(metrics["core_effective_frequency_hz"], 1e9);
+    }
+
+    #[test]
+    fn test_license_group_takes_turns() {
+        let msr = MockMsr::new().leak();
+        let config = ExportConfig::new(vec![0], vec![0]).with_core_avx_license(true);
+        let mut monitor = CoreMonitor::with_msr(config, msr).unwrap();
+        assert!(monitor.counts_avx_license());
+        monitor.initialize().unwrap();
+
+        let select = |i: u64| msr.get(0, IA32_PERFEVTSEL0 + i).unwrap();
+        let default_set = get_default_event_set();
+        assert_eq!(select(0), default_set[0].encode_for_perfevtsel(true, false));
+
+        // Window 1: default group, LLC references on PMC0
+        msr.set(0, IA32_FIXED_CTR1, 1_000);
+        msr.set(0, IA32_PMC0, 50);
+        monitor.collect().unwrap();
+        assert_eq!(
+            select(0),
+            LICENSE_EVENTS[0].encode_for_perfevtsel(true, false)
+        );
+        // Three license events, the fourth counter is switched off and all start from zero
+        assert_eq!(select(3), 0);
+        assert_eq!(msr.get(0, IA32_PMC0), Some(0));
+
+        // Window 2: license group
+        msr.set(0, IA32_FIXED_CTR1, 2_000);
+        msr.set(0, IA32_PMC0, 600);
+        msr.set(0, IA32_PMC1, 300);
+        msr.set(0, IA32_PMC2, 100);
+        monitor.collect().unwrap();
+        assert_eq!(select(0), default_set[0].encode_for_perfevtsel(true, false));
+
+        let metrics = monitor.get_metrics(0);
+        assert_eq!(metrics["cycles"], 2_000.0);
+        // Each group counted half the time and is extrapolated to the whole run
+        assert_eq!(metrics["L3CacheRef"], 100.0);
+        assert_eq!(metrics["core_avx_license_cycles_ratio"], 0.4);
+    }
+
+    #[test]
+    fn test_offcore_responses_programmed() {
+        let msr = MockMsr::new().leak();
+        let mut monitor = CoreMonitor::with_msr(ExportConfig::new(vec![0], vec![0]), msr)
+            .unwrap()
+            .with_offcore_events([OFFCORE_LOCAL_DRAM, OFFCORE_REMOTE_DRAM])
+            .unwrap();
+        assert!(monitor.counts_offcore());
+        monitor.initialize().unwrap();
+
+        // Both matrices are set up front, the events wait for their group's turn
+        let rsp0 = msr.get(0, core::msr::MSR_OFFCORE_RSP_0).unwrap();
+        let rsp1 = msr.get(0, core::msr::MSR_OFFCORE_RSP_1).unwrap();
+        assert_eq!(rsp0, 0x3F_8400_05B3);
+        assert_eq!(rsp1, 0x3F_B800_05B3);
+
+        monitor.collect().unwrap();
+        let select = CorePerfEvtSel::from_msr_value(msr.get(0, IA32_PERFEVTSEL1).unwrap());
+        assert_eq!((select.event_select, select.umask), (0xBB, 0x01));
+
+        msr.set(0, IA32_PMC0, 70);
+        msr.set(0, IA32_PMC1, 30);
+        monitor.collect().unwrap();
+        let metrics = monitor.get_metrics(0);
+        assert_eq!(metrics[CORE_OFFCORE_LOCAL_DRAM], 140.0);
+        assert_eq!(metrics[CORE_OFFCORE_REMOTE_DRAM], 60.0);
+    }
+
+    #[test]
+    fn test_custom_events_are_multiplexed() {
+        let specs = [
+            "cpu/event=0x24,umask=0x38,name=l2_miss/u",
+            "cpu/event=0xa3,umask=0x14,cmask=20,name=stalls_mem/",
+            "r01c2:k",
+            "r00c4",
+            "cpu/event=0xd1,umask=0x20,name=l3_miss/",
+        ];
+        let events = specs.iter().map(|spec| spec.parse().unwrap()).collect();
+        let msr = MockMsr::new().leak();
+        let config = ExportConfig::new(vec![0], vec![0]).with_core_events(events);
+        let mut monitor = CoreMonitor::with_msr(config, msr).unwrap();
+        let names = monitor.custom_event_names();
+        assert_eq!(
+            names.iter().map(|name| &**name).collect::<Vec<_>>(),
+            ["l2_miss", "stalls_mem", "r01c2:k", "r00c4", "l3_miss"]
+        );
+        monitor.initialize().unwrap();
+        let select = |i: u64| msr.get(0, IA32_PERFEVTSEL0 + i).unwrap();
+
+        // Default group, then the first four custom events with their own modes
+        monitor.collect().unwrap();
+        assert_eq!(select(0), 0x41_3824);
+        assert_eq!(select(1), 0x1443_14A3);
+        assert_eq!(select(2), 0x42_01C2);
+        msr.set(0, IA32_PMC0, 10);
+        msr.set(0, IA32_PMC1, 20);
+        monitor.collect().unwrap();
+
+        // The fifth gets a group of its own
+        assert_eq!(select(0), 0x43_20D1);
+        assert_eq!(select(1), 0);
+        msr.set(0, IA32_PMC0, 5);
+        monitor.collect().unwrap();
+
+        // Three groups take turns, so each count is extrapolated threefold
+        let counts = monitor.custom_event_counts(0);
+        assert_eq!(counts["l2_miss"], 30);
+        assert_eq!(counts["stalls_mem"], 60);
+        assert_eq!(counts["l3_miss"], 15);
+    }
+
+    #[test]
+    fn test_custom_events_rejected() {
+        let event: CustomEvent = "r3824".parse().unwrap();
+        let msr = MockMsr::new().leak();
+        let config = ExportConfig::new(vec![0], vec![0])
+            .with_core_events(vec![event.clone(), event.clone()]);
+        assert!(CoreMonitor::with_msr(config, msr).is_err());
+
+        let event = "cpu/event=0x2e,umask=0x4f,name=LLCReference/"
+            .parse()
+            .unwrap();
+        let config = ExportConfig::new(vec![0], vec![0]).with_core_events(vec![event]);
+        assert!(CoreMonitor::with_msr(config, msr).is_err());
+    }
+}
@@ -0,0 +1,262 @@
+// C-state residency reader
+//
+// Read-only: the package residency counters of each socket and the core residency
+// counters of each monitored core are sampled together with the TSC of the same
+// CPU, and each collection reports the share of the elapsed TSC cycles spent in
+// every state. The first collection after startup or a reset only sets baselines.
+//
+// States whose MSR faults at startup are skipped rather than failing the monitor,
+// since not every part implements every residency counter.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::common::msr::{self, MsrAccess};
+use crate::config::ExportConfig;
+use crate::error::{Result, UncflowError};
+
+use uncflow_raw::current_arch::cstate;
+
+/// Share of the TSC delta a residency counter advanced by, None without elapsed cycles
+///
+/// Both counters tick at the TSC rate, so the ratio is clamped to 1.0 to absorb
+/// the skew between the two reads.
+pub fn residency_ratio(prev: u64, cur: u64, tsc_prev: u64, tsc_cur: u64) -> Option<f64> {
+    let elapsed = tsc_cur.wrapping_sub(tsc_prev);
+    if elapsed == 0 {
+        return None;
+    }
+    Some((cur.wrapping_sub(prev) as f64 / elapsed as f64).min(1.0))
+}
+
+/// Residency ratios of one collection
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CstateResidency {
+    /// Ratio per (socket, package state)
+    pub package: BTreeMap<(i32, &'static str), f64>,
+    /// Ratio per (core, core state)
+    pub core: BTreeMap<(i32, &'static str), f64>,
+}
+
+// TSC and residency counters read together on one CPU
+#[derive(Debug, Clone)]
+struct Sample {
+    tsc: u64,
+    counters: Vec<u64>,
+}
+
+pub struct CstateMonitor {
+    msr: &'static dyn MsrAccess,
+    // CPU each socket's package-scoped MSRs are read from
+    socket_cpus: BTreeMap<i32, u32>,
+    cores: Vec<i32>,
+    // States whose counters read at startup, as (name, MSR)
+    package_states: Vec<(&'static str, u64)>,
+    core_states: Vec<(&'static str, u64)>,
+    prev_package: HashMap<i32, Sample>,
+    prev_core: HashMap<i32, Sample>,
+}
+
+impl CstateMonitor {
+    pub fn new(config: &ExportConfig) -> Result<Self> {
+        Self::with_msr(config, msr::Msr::instance())
+    }
+
+    /// Monitor reading the residency MSRs through `msr`, failing if none is readable
+    pub fn with_msr(config: &ExportConfig, msr: &'static dyn MsrAccess) -> Result<Self> {
+        let mut socket_cpus = BTreeMap::new();
+        for &socket in &config.sockets {
+            match Self::socket_cpu(config, socket) {
+                Some(cpu) => {
+                    socket_cpus.insert(socket, cpu);
+                }
+                None => tracing::warn!(
+                    "No monitored core on socket {socket}, skipping its package C-states"
+                ),
+            }
+        }
+
+        let readable = |cpu: Option<u32>, states: &[(&'static str, u64)]| -> Vec<_> {
+            let Some(cpu) = cpu else {
+                return Vec::new();
+            };
+            states
+                .iter()
+                .copied()
+                .filter(|&(name, address)| match msr.read(cpu, address) {
+                    Ok(_) => true,
+                    Err(e) => {
+                        tracing::warn!("C-state {} residency is not readable: {}", name, e);
+                        false
+                    }
+                })
+                .collect()
+        };
+        let package_states = readable(
+            socket_cpus.values().next().copied(),
+            &cstate::PACKAGE_CSTATES,
+        );
+        let core_states = readable(
+            config.cores.first().map(|&core| core as u32),
+            &cstate::CORE_CSTATES,
+        );
+
+        if package_states.is_empty() && core_states.is_empty() {
+            return Err(UncflowError::HardwareError(
+                "No C-state residency counter is readable".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            msr,
+            socket_cpus,
+            cores: config.cores.clone(),
+            package_states,
+            core_states,
+            prev_package: HashMap::new(),
+            prev_core: HashMap::new(),
+        })
+    }
+
+    /// The uncore core override of `socket`, else its first monitored core
+    fn socket_cpu(config: &ExportConfig, socket: i32) -> Option<u32> {
+        config.uncore_cores.get(&socket).copied().or_else(|| {
+            config
+                .cores
+                .iter()
+                .map(|&core| core as u32)
+                .find(|&core| ExportConfig::core_socket(core) == Some(socket))
+        })
+    }
+
+    fn sample(&self, cpu: u32, states: &[(&'static str, u64)]) -> Result<Sample> {
+        let tsc = self.msr.read(cpu, cstate::msr::IA32_TIME_STAMP_COUNTER)?;
+        let counters = states
+            .iter()
+            .map(|&(_, address)| self.msr.read(cpu, address))
+            .collect::<Result<_>>()?;
+        Ok(Sample { tsc, counters })
+    }
+
+    // Ratios of `cur` against `prev`, keyed by (`id`, state)
+    fn ratios(
+        id: i32,
+        states: &[(&'static str, u64)],
+        prev: &Sample,
+        cur: &Sample,
+        out: &mut BTreeMap<(i32, &'static str), f64>,
+    ) {
+        for (i, &(name, _)) in states.iter().enumerate() {
+            if let Some(ratio) =
+                residency_ratio(prev.counters[i], cur.counters[i], prev.tsc, cur.tsc)
+            {
+                out.insert((id, name), ratio);
+            }
+        }
+    }
+
+    /// Read every counter and return the ratios since the previous collection
+    pub fn collect(&mut self) -> Result<CstateResidency> {
+        let mut residency = CstateResidency::default();
+        if !self.package_states.is_empty() {
+            for (&socket, &cpu) in &self.socket_cpus {
+                let cur = self.sample(cpu, &self.package_states)?;
+                if let Some(prev) = self.prev_package.get(&socket) {
+                    Self::ratios(
+                        socket,
+                        &self.package_states,
+                        prev,
+                        &cur,
+                        &mut residency.package,
+                    );
+                }
+                self.prev_package.insert(socket, cur);
+            }
+        }
+        if !self.core_states.is_empty() {
+            for &core in &self.cores {
+                let cur = self.sample(core as u32, &self.core_states)?;
+                if let Some(prev) = self.prev_core.get(&core) {
+                    Self::ratios(core, &self.core_states, prev, &cur, &mut residency.core);
+                }
+                self.prev_core.insert(core, cur);
+            }
+        }
+        Ok(residency)
+    }
+
+    /// Drop the baselines so the next collection starts a fresh window
+    pub fn reset(&mut self) {
+        self.prev_package.clear();
+        self.prev_core.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::msr::mock::MockMsr;
+    use cstate::msr::*;
+    use std::collections::HashMap;
+
+    fn config() -> ExportConfig {
+        ExportConfig::new(vec![0], vec![2]).with_uncore_cores(HashMap::from([(0, 2)]))
+    }
+
+    #[test]
+    fn test_ratio_against_tsc_delta() {
+        assert_eq!(residency_ratio(100, 400, 1_000, 2_000), Some(0.3));
+        // The TSC did not advance
+        assert_eq!(residency_ratio(100, 400, 1_000, 1_000), None);
+        // Read skew can put the residency delta past the TSC delta
+        assert_eq!(residency_ratio(0, 1_010, 0, 1_000), Some(1.0));
+        // Counters wrapping between reads
+        assert_eq!(
+            residency_ratio(u64::MAX - 9, 40, u64::MAX - 99, 100),
+            Some(0.25)
+        );
+    }
+
+    #[test]
+    fn test_collect_reports_ratios_after_baseline() {
+        let msr = MockMsr::new().leak();
+        let mut monitor = CstateMonitor::with_msr(&config(), msr).unwrap();
+        assert_eq!(monitor.collect().unwrap(), CstateResidency::default());
+
+        msr.set(2, IA32_TIME_STAMP_COUNTER, 10_000);
+        msr.set(2, MSR_PKG_C6_RESIDENCY, 2_500);
+        msr.set(2, MSR_CORE_C6_RESIDENCY, 6_000);
+        let residency = monitor.collect().unwrap();
+        assert_eq!(residency.package[&(0, "c6")], 0.25);
+        assert_eq!(residency.package[&(0, "c2")], 0.0);
+        assert_eq!(residency.core[&(2, "c6")], 0.6);
+        assert_eq!(msr.write_count(), 0);
+
+        monitor.reset();
+        assert_eq!(monitor.collect().unwrap(), CstateResidency::default());
+    }
+
+    #[test]
+    fn test_unreadable_states_are_skipped() {
+        // Only the TSC and package C6 exist
+        let msr = MockMsr::new().leak();
+        for address in [
+            MSR_PKG_C2_RESIDENCY,
+            MSR_PKG_C3_RESIDENCY,
+            MSR_CORE_C3_RESIDENCY,
+            MSR_CORE_C6_RESIDENCY,
+            MSR_CORE_C7_RESIDENCY,
+        ] {
+            msr.fail_reads(2, address);
+        }
+        let mut monitor = CstateMonitor::with_msr(&config(), msr).unwrap();
+        monitor.collect().unwrap();
+        msr.set(2, IA32_TIME_STAMP_COUNTER, 100);
+        msr.set(2, MSR_PKG_C6_RESIDENCY, 50);
+        let residency = monitor.collect().unwrap();
+        assert_eq!(residency.package.len(), 1);
+        assert!(residency.core.is_empty());
+
+        msr.fail_reads(2, MSR_PKG_C6_RESIDENCY);
+        assert!(CstateMonitor::with_msr(&config(), msr).is_err());
+    }
+}
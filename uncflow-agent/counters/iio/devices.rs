@@ -0,0 +1,289 @@
+// PCIe devices behind the IIO root ports, labeled on the per-port series
+//
+// Each PCIe IIO channel is one stack whose bus number the UBox CPUBUSNO register
+// holds, and root port N of the stack is device N on that bus. The device of a port
+// is function 0 of device 0 on the port's secondary bus, identified from sysfs.
+// Ports without a link have no device; their series carry empty labels.
+//
+// Devices come and go with hotplug, so the lookup is cached and re-read every
+// DEVICE_REFRESH_INTERVAL rather than on every collect.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::common::{pci, sys_roots};
+use crate::error::{Result, UncflowError};
+use uncflow_raw::current_arch::{iio, ubox};
+
+/// How often the port devices are re-read to catch hotplug
+pub const DEVICE_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Label names the port devices are exported under
+pub const DEVICE_LABELS: [&str; 3] = ["pci_vendor", "pci_device", "pci_device_name"];
+
+// Vendors common behind server root ports
+const KNOWN_VENDORS: &[(u16, &str)] = &[
+    (0x1000, "LSI"),
+    (0x1077, "QLogic"),
+    (0x10DE, "NVIDIA"),
+    (0x144D, "Samsung"),
+    (0x14E4, "Broadcom"),
+    (0x15B3, "Mellanox"),
+    (0x1924, "Solarflare"),
+    (0x8086, "Intel"),
+];
+
+// Models worth naming; anything else falls back to the vendor and device id
+const KNOWN_DEVICES: &[(u16, u16, &str)] = &[
+    (0x10DE, 0x1DB4, "NVIDIA Tesla V100"),
+    (0x10DE, 0x20B0, "NVIDIA A100"),
+    (0x144D, 0xA808, "Samsung NVMe SM981/PM981"),
+    (0x144D, 0xA824, "Samsung NVMe PM173X"),
+    (0x15B3, 0x1013, "Mellanox CX-4"),
+    (0x15B3, 0x1015, "Mellanox CX-4 Lx"),
+    (0x15B3, 0x1017, "Mellanox CX-5"),
+    (0x15B3, 0x1019, "Mellanox CX-5 Ex"),
+    (0x15B3, 0x101B, "Mellanox CX-6"),
+    (0x15B3, 0x101D, "Mellanox CX-6 Dx"),
+    (0x8086, 0x0A54, "Intel NVMe P4510"),
+    (0x8086, 0x1572, "Intel X710"),
+    (0x8086, 0x1583, "Intel XL710"),
+    (0x8086, 0x158B, "Intel XXV710"),
+    (0x8086, 0x1592, "Intel E810-C"),
+];
+
+/// PCI identity of the device behind a root port
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortDevice {
+    pub vendor: u16,
+    pub device: u16,
+}
+
+impl PortDevice {
+    /// Model name from the built-in table, else the vendor name with the device
+    /// id, else both ids
+    pub fn name(&self) -> String {
+        if let Some(&(_, _, name)) = KNOWN_DEVICES
+            .iter()
+            .find(|&&(vendor, device, _)| (vendor, device) == (self.vendor, self.device))
+        {
+            return name.to_string();
+        }
+        match KNOWN_VENDORS
+            .iter()
+            .find(|&&(vendor, _)| vendor == self.vendor)
+        {
+            Some((_, vendor)) => format!("{vendor} {:04x}", self.device),
+            None => format!("{:04x}:{:04x}", self.vendor, self.device),
+        }
+    }
+
+    /// Values of DEVICE_LABELS
+    pub fn labels(&self) -> [String; 3] {
+        [
+            format!("{:04x}", self.vendor),
+            format!("{:04x}", self.device),
+            self.name(),
+        ]
+    }
+}
+
+/// Bus number of each PCIe IIO channel of `socket`, from the UBox CPUBUSNO register
+pub fn channel_buses(socket: i32) -> Result<[u8; iio::IIO_CHANNEL_COUNT]> {
+    let ubox = pci::PciConfigAddress {
+        socket: socket as u32,
+        device: ubox::pci::UBOX_DEVICE,
+        function: ubox::pci::UBOX_FUNCTION,
+        device_id: ubox::pci::UBOX_DEVICE_ID,
+    };
+    let pci = pci::Pci::instance();
+    if pci.read32(&ubox, ubox::pci::CPUBUSNO_VALID)? & (1 << 31) == 0 {
+        return Err(UncflowError::PciError(format!(
+            "Stack bus numbers of socket {socket} are not valid"
+        )));
+    }
+    let cpubusno = pci.read32(&ubox, ubox::pci::CPUBUSNO)?;
+    Ok(iio::IIO_CHANNEL_STACKS.map(|stack| (cpubusno >> (8 * stack)) as u8))
+}
+
+/// Device behind root port `port` of the stack on `bus`, None without a link
+///
+/// Assumes PCI segment 0, where the IIO stacks of Skylake-SP live.
+fn port_device(devices_dir: &Path, bus: u8, port: usize) -> Option<PortDevice> {
+    let read = |path: &Path| std::fs::read_to_string(path).ok();
+    let root = devices_dir.join(format!("0000:{bus:02x}:{port:02x}.0"));
+    let secondary: u8 = read(&root.join("secondary_bus_number"))?
+        .trim()
+        .parse()
+        .ok()?;
+    let endpoint = devices_dir.join(format!("0000:{secondary:02x}:00.0"));
+    let id = |attr: &str| {
+        let value = read(&endpoint.join(attr))?;
+        u16::from_str_radix(value.trim().trim_start_matches("0x"), 16).ok()
+    };
+    Some(PortDevice {
+        vendor: id("vendor")?,
+        device: id("device")?,
+    })
+}
+
+/// Devices behind every root port of `socket`, keyed by (channel, port)
+fn discover(socket: i32) -> HashMap<(usize, usize), PortDevice> {
+    let buses = match channel_buses(socket) {
+        Ok(buses) => buses,
+        Err(e) => {
+            tracing::warn!("Cannot map IIO stacks of socket {} to buses: {}", socket, e);
+            return HashMap::new();
+        }
+    };
+    let devices_dir = sys_roots().pci_devices_dir();
+    let mut devices = HashMap::new();
+    for (channel, &bus) in buses.iter().enumerate() {
+        for port in 0..iio::IIO_PCIE_PORT_COUNT {
+            if let Some(device) = port_device(&devices_dir, bus, port) {
+                devices.insert((channel, port), device);
+            }
+        }
+    }
+    devices
+}
+
+/// Cached devices of every monitored socket's root ports
+#[derive(Debug)]
+pub struct PortDevices {
+    sockets: Vec<i32>,
+    devices: HashMap<(i32, usize, usize), PortDevice>,
+    refreshed_at: Option<Instant>,
+}
+
+impl PortDevices {
+    pub fn new(sockets: Vec<i32>) -> Self {
+        Self {
+            sockets,
+            devices: HashMap::new(),
+            refreshed_at: None,
+        }
+    }
+
+    /// Device behind `port` of `channel` on `socket`, as of the last refresh
+    pub fn get(&self, socket: i32, channel: usize, port: usize) -> Option<PortDevice> {
+        self.devices.get(&(socket, channel, port)).copied()
+    }
+
+    /// Re-read the devices once DEVICE_REFRESH_INTERVAL has passed, returning the
+    /// (socket, channel, port) whose device changed
+    pub fn refresh_if_due(&mut self) -> Vec<(i32, usize, usize)> {
+        if self
+            .refreshed_at
+            .is_some_and(|at| at.elapsed() < DEVICE_REFRESH_INTERVAL)
+        {
+            return Vec::new();
+        }
+        self.refreshed_at = Some(Instant::now());
+
+        let mut devices = HashMap::new();
+        for &socket in &self.sockets {
+            for ((channel, port), device) in discover(socket) {
+                devices.insert((socket, channel, port), device);
+            }
+        }
+        self.replace(devices)
+    }
+
+    // Swap in freshly read devices, returning the ports that changed
+    fn replace(
+        &mut self,
+        devices: HashMap<(i32, usize, usize), PortDevice>,
+    ) -> Vec<(i32, usize, usize)> {
+        let mut changed: Vec<_> = self
+            .devices
+            .keys()
+            .chain(devices.keys())
+            .filter(|port| self.devices.get(port) != devices.get(port))
+            .copied()
+            .collect();
+        changed.sort_unstable();
+        changed.dedup();
+        for &(socket, channel, port) in &changed {
+            match devices.get(&(socket, channel, port)) {
+                Some(device) => tracing::info!(
+                    "IIO socket {} channel {} port {}: {}",
+                    socket,
+                    channel,
+                    port,
+                    device.name()
+                ),
+                None => tracing::info!(
+                    "IIO socket {} channel {} port {}: no device",
+                    socket,
+                    channel,
+                    port
+                ),
+            }
+        }
+        self.devices = devices;
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_device_name_fallback() {
+        let known = PortDevice {
+            vendor: 0x15B3,
+            device: 0x1017,
+        };
+        assert_eq!(known.name(), "Mellanox CX-5");
+        assert_eq!(
+            known.labels(),
+            ["15b3", "1017", "Mellanox CX-5"].map(String::from)
+        );
+
+        // Known vendor, unknown model
+        let vendor_only = PortDevice {
+            vendor: 0x8086,
+            device: 0xABCD,
+        };
+        assert_eq!(vendor_only.name(), "Intel abcd");
+
+        // Neither is known
+        let unknown = PortDevice {
+            vendor: 0x1AB4,
+            device: 0x0042,
+        };
+        assert_eq!(unknown.name(), "1ab4:0042");
+    }
+
+    #[test]
+    fn test_port_device_from_sysfs() {
+        let dir = std::env::temp_dir().join(format!("uncflow-iio-devices-{}", std::process::id()));
+        let root = dir.join("0000:5d:02.0");
+        let endpoint = dir.join("0000:5e:00.0");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::create_dir_all(&endpoint).unwrap();
+        std::fs::write(root.join("secondary_bus_number"), "94\n").unwrap();
+        std::fs::write(endpoint.join("vendor"), "0x15b3\n").unwrap();
+        std::fs::write(endpoint.join("device"), "0x101d\n").unwrap();
+
+        let device = port_device(&dir, 0x5D, 2).unwrap();
+        assert_eq!(device.name(), "Mellanox CX-6 Dx");
+        // No root port, or nothing behind it
+        assert!(port_device(&dir, 0x5D, 3).is_none());
+
+        let mut devices = PortDevices::new(vec![0]);
+        assert_eq!(
+            devices.replace(HashMap::from([((0, 1, 2), device)])),
+            vec![(0, 1, 2)]
+        );
+        assert!(devices
+            .replace(HashMap::from([((0, 1, 2), device)]))
+            .is_empty());
+        assert_eq!(devices.replace(HashMap::new()), vec![(0, 1, 2)]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,3 +1,4 @@
+pub mod devices;
 pub mod monitor;
 
 pub use monitor::IioMonitor;
@@ -1,8 +1,16 @@
 // IIO (Integrated IO) Monitor
 //
+// The programmable event groups take turns on the counters: each counts for at
+// least EVENT_GROUP_WINDOW from the collect that programmed it, and the first
+// collect after that reads it, programs the next group and returns. The counts of
+// a group are scaled to EVENT_GROUP_WINDOW, so they mean the same whatever the
+// collection period, and the metrics of the other groups are their last reading.
+//
 // Now uses uncflow-raw for type-safe hardware register programming
 
-use crate::common::msr;
+use crate::common::counter::{rate_window_secs, wrapping_delta, Baseline};
+use crate::common::msr::{self, MsrAccess};
+use crate::common::{log_limiter, pmon, read_stats, register, EventScheduler, ReadCounter};
 use crate::error::Result;
 use crate::metrics::iio::IioMetric;
 use std::collections::HashMap;
@@ -15,6 +23,14 @@ use uncflow_raw::RegisterLayout;
 // Business logic constants
 const CACHELINE_SIZE: u64 = 64;
 
+/// How long each programmable event group counts at least, the window the IIO
+/// counts are scaled to
+pub const EVENT_GROUP_WINDOW: Duration = Duration::from_secs(1);
+
+/// Consecutive failed collects of the programmable counters before only the
+/// free-running PCIe counters are read
+pub const MAX_PROGRAMMING_FAILURES: u32 = 3;
+
 // IIO Event configurations
 #[derive(Debug, Clone)]
 struct IioEventConfig {
@@ -22,6 +38,27 @@ struct IioEventConfig {
     events: [(u8, u8, u8, u8); 4], // (event, umask, ch_mask, fc_mask)
 }
 
+impl IioEventConfig {
+    /// Counter control registers for the four programmable counters
+    fn controls(&self) -> [IioCounterControl; 4] {
+        self.events
+            .map(|(event, umask, ch_mask, fc_mask)| IioCounterControl {
+                event_select: event,
+                unit_mask: umask,
+                reset_counter: true,
+                overflow_enable: true,
+                enable: true,
+                channel_mask: ch_mask,
+                fc_mask,
+                ..Default::default()
+            })
+    }
+
+    fn validate(&self) -> Result<()> {
+        register::validate_all(&format!("IIO group {}", self.name), &self.controls())
+    }
+}
+
 const IIO_EVENTS: &[IioEventConfig] = &[
     IioEventConfig {
         name: "TLB_Miss_Group",
@@ -52,76 +89,78 @@ const IIO_EVENTS: &[IioEventConfig] = &[
     },
 ];
 
+// Only counted with --iio-iommu, one more group in the rotation
+const IOMMU_EVENTS: IioEventConfig = IioEventConfig {
+    name: "IOMMU_Group",
+    events: [
+        (
+            iio::events::IOMMU0,
+            iio::umasks::IOMMU_FIRST_LOOKUPS,
+            0xFF,
+            0x07,
+        ),
+        (iio::events::IOMMU0, iio::umasks::IOMMU_MISSES, 0xFF, 0x07),
+        (
+            iio::events::IOMMU3,
+            iio::umasks::IOMMU_PWT_OCCUPANCY,
+            0xFF,
+            0x07,
+        ),
+        (iio::events::CLOCKTICKS, 0x00, 0xFF, 0x07),
+    ],
+};
+
 #[derive(Debug)]
 struct IioCounterUnit {
     core: u32,
     index: usize,
+    msr: &'static dyn MsrAccess,
 }
 
 impl IioCounterUnit {
-    fn new(core: u32, index: usize) -> Result<Self> {
-        Ok(Self { core, index })
-    }
-
-    fn freeze_and_reset(&self) -> Result<()> {
-        let ctrl_addr = iio::msr::IIO_UNIT_BOX_CTL[self.index];
-        msr::write(self.core, ctrl_addr, 0x100)?; // Freeze
-        msr::write(self.core, ctrl_addr, 0x102)?; // Reset
-        Ok(())
-    }
-
-    fn unfreeze(&self) -> Result<()> {
-        let ctrl_addr = iio::msr::IIO_UNIT_BOX_CTL[self.index];
-        msr::write(self.core, ctrl_addr, 0)?;
-        Ok(())
+    fn new(core: u32, index: usize, msr: &'static dyn MsrAccess) -> Result<Self> {
+        Ok(Self { core, index, msr })
     }
 
     fn program(&self, config: &IioEventConfig) -> Result<()> {
-        // Try to freeze and reset, but don't fail if it doesn't work
-        // Some systems may have read-only IIO MSRs
-        if let Err(e) = self.freeze_and_reset() {
-            tracing::debug!(
-                "IIO freeze/reset not supported on this system (unit {}): {}",
-                self.index,
-                e
-            );
-            return Err(e);
-        }
-
+        // Reject the whole group before the unit is frozen
+        config.validate()?;
+
+        let write_box_ctl = |value| {
+            // Some systems have read-only IIO MSRs
+            self.msr
+                .write(self.core, iio::msr::IIO_UNIT_BOX_CTL[self.index], value)
+                .inspect_err(|e| {
+                    tracing::debug!(
+                        "IIO freeze/reset not supported on this system (unit {}): {}",
+                        self.index,
+                        e
+                    )
+                })
+        };
         let ctrl_addrs = [
             iio::msr::IIO_UNIT_CTL0[self.index],
             iio::msr::IIO_UNIT_CTL1[self.index],
             iio::msr::IIO_UNIT_CTL2[self.index],
             iio::msr::IIO_UNIT_CTL3[self.index],
         ];
-
-        for (i, &(event, umask, ch_mask, fc_mask)) in config.events.iter().enumerate() {
-            // Use type-safe register struct from uncflow-raw
-            let ctrl = IioCounterControl {
-                event_select: event,
-                unit_mask: umask,
-                reset_counter: true,
-                overflow_enable: true,
-                enable: true,
-                channel_mask: ch_mask,
-                fc_mask,
-                ..Default::default()
-            };
-
-            // Validate before writing (type safety!)
-            ctrl.validate()
-                .map_err(|e| crate::error::UncflowError::HardwareError(e.to_string()))?;
-
-            // Convert to MSR value and write
-            // If write fails, propagate error
-            msr::write(self.core, ctrl_addrs[i], ctrl.to_msr_value())?;
-        }
-
-        self.unfreeze()?;
-        Ok(())
+        pmon::program_frozen(write_box_ctl, || {
+            for (ctrl, &addr) in config.controls().iter().zip(&ctrl_addrs) {
+                // Write and read back: read-only or virtualized MSRs accept the
+                // write but keep their old value
+                msr::write_verified(
+                    self.msr,
+                    self.core,
+                    addr,
+                    ctrl.to_msr_value(),
+                    IioCounterControl::VERIFY_MASK,
+                )?;
+            }
+            Ok(())
+        })
     }
 
-    fn read_counters(&self) -> Result<[u64; 5]> {
+    fn read_counters(&self, reads: &ReadCounter) -> Result<[u64; 5]> {
         let ctr_addrs = [
             iio::msr::IIO_UNIT_CTR0[self.index],
             iio::msr::IIO_UNIT_CTR1[self.index],
@@ -131,103 +170,253 @@ impl IioCounterUnit {
         ];
 
         let mut values = [0u64; 5];
-        let mask = (1u64 << iio::UNCORE_COUNTER_WIDTH_BITS) - 1;
         for (i, &addr) in ctr_addrs.iter().enumerate() {
-            values[i] = msr::read(self.core, addr)? & mask;
+            values[i] = reads.observe(self.msr.read(self.core, addr))? & iio::COUNTER_MASK;
         }
 
         Ok(values)
     }
 }
 
+// Programmable counts of a group that counted for `window_secs`, scaled to
+// EVENT_GROUP_WINDOW; the free-running clock in the last slot is left as read
+fn scale_to_window(mut values: [u64; 5], window_secs: f64) -> [u64; 5] {
+    let scale = EVENT_GROUP_WINDOW.as_secs_f64() / window_secs;
+    for value in &mut values[..4] {
+        *value = (*value as f64 * scale).round() as u64;
+    }
+    values
+}
+
+/// Fraction of `clock_delta` IO clock cycles a port was active, taking the busier
+/// direction; `None` when the clock did not advance
+pub fn port_utilization(in_active: u64, out_active: u64, clock_delta: u64) -> Option<f64> {
+    if clock_delta == 0 {
+        return None;
+    }
+    Some((in_active.max(out_active) as f64 / clock_delta as f64).min(1.0))
+}
+
+/// Fraction of IOMMU lookups served without a page walk, `None` without lookups
+pub fn iommu_hit_ratio(lookups: u64, misses: u64) -> Option<f64> {
+    if lookups == 0 {
+        return None;
+    }
+    Some(lookups.saturating_sub(misses) as f64 / lookups as f64)
+}
+
+/// Average IOMMU page-walk latency in nanoseconds over `window`
+///
+/// Every miss starts a walk, so the tracker occupancy per miss is the walk time in
+/// IO clock cycles, converted with the clock rate measured over the same window.
+pub fn page_walk_latency_ns(
+    occupancy: u64,
+    walks: u64,
+    clockticks: u64,
+    window: Duration,
+) -> Option<f64> {
+    let seconds = rate_window_secs(window)?;
+    if walks == 0 || clockticks == 0 {
+        return None;
+    }
+    let cycles = occupancy as f64 / walks as f64;
+    Some(cycles / (clockticks as f64 / seconds) * 1e9)
+}
+
+// Free-running PCIe byte counters, [channel][in ports..., out ports...]
+type PortCounters = [[u64; iio::IIO_PCIE_PORT_COUNT * 2]; iio::IIO_CHANNEL_COUNT];
+
+// One read of the free-running utilization counters of every channel
+#[derive(Debug, Clone, Copy)]
+struct UtilizationReading {
+    clock: [u64; iio::IIO_CHANNEL_COUNT],
+    active: PortCounters,
+}
+
 #[derive(Debug)]
 pub struct IioMonitor {
     socket: i32,
     core: u32,
     units: Vec<IioCounterUnit>,
+    scheduler: EventScheduler<&'static IioEventConfig>,
+    loaded: bool, // Whether the scheduler's current group is programmed and counting
     event_results: HashMap<String, Vec<[u64; 5]>>,
-    pcie_last_values: Option<[[u64; iio::IIO_PCIE_PORT_COUNT * 2]; iio::IIO_CHANNEL_COUNT]>,
-    pcie_last_time: Option<Instant>,
-    programmable_warned: bool, // Track if we've already warned about programmable counters
+    pcie_baseline: Baseline<PortCounters>,
+    util_baseline: Baseline<UtilizationReading>,
+    programmable_supported: bool, // Cleared after repeated failures, PCIe-only until a reset
+    programming_failures: u32,    // Consecutive collects whose programmable counters failed
+    read_only: bool,              // Never programmed, so no warning about protected MSRs
+    iommu: bool,                  // Also count the IOMMU event group
+    msr: &'static dyn MsrAccess,
+    reads: ReadCounter, // Counter reads and failed reads of this socket
 }
 
 impl IioMonitor {
     pub fn new(socket: i32) -> Result<Self> {
-        let core = (socket as u32) * 16;
+        Self::with_msr(socket, msr::Msr::instance())
+    }
 
+    /// Create a monitor on top of a specific MSR backend
+    pub fn with_msr(socket: i32, msr: &'static dyn MsrAccess) -> Result<Self> {
+        Self::with_core(socket, Self::default_core(socket), msr)
+    }
+
+    /// Core the IIO MSRs of `socket` are accessed from unless overridden
+    pub fn default_core(socket: i32) -> u32 {
+        (socket as u32) * 16
+    }
+
+    /// Create a monitor accessing the IIO MSRs from `core`, which must be on `socket`
+    ///
+    /// If a global uncore freeze can't be lifted, only the free-running PCIe counters
+    /// are read: the programmable groups would count nothing.
+    pub fn with_core(socket: i32, core: u32, msr: &'static dyn MsrAccess) -> Result<Self> {
+        Self::validate_program()?;
+        let mut monitor = Self::build(socket, core, msr)?;
+        if let Err(e) = msr::ensure_uncore_unfrozen(msr, core, "IIO") {
+            tracing::warn!(
+                "IIO programmable counters disabled on socket {}: {}",
+                socket,
+                e
+            );
+            monitor.programmable_supported = false;
+        }
+        Ok(monitor)
+    }
+
+    /// Create a monitor that never writes an MSR and reports only the free-running
+    /// PCIe bandwidth and utilization (--read-only-counters)
+    pub fn read_only(socket: i32, core: u32, msr: &'static dyn MsrAccess) -> Result<Self> {
+        let mut monitor = Self::build(socket, core, msr)?;
+        monitor.programmable_supported = false;
+        monitor.read_only = true;
+        Ok(monitor)
+    }
+
+    fn build(socket: i32, core: u32, msr: &'static dyn MsrAccess) -> Result<Self> {
         let mut units = Vec::new();
         for i in 0..iio::IIO_CHANNEL_COUNT {
-            units.push(IioCounterUnit::new(core, i)?);
+            units.push(IioCounterUnit::new(core, i, msr)?);
         }
 
         Ok(Self {
             socket,
             core,
             units,
+            scheduler: Self::scheduler(false),
+            loaded: false,
             event_results: HashMap::new(),
-            pcie_last_values: None,
-            pcie_last_time: None,
-            programmable_warned: false,
+            pcie_baseline: Baseline::default(),
+            util_baseline: Baseline::default(),
+            programmable_supported: true,
+            programming_failures: 0,
+            read_only: false,
+            iommu: false,
+            msr,
+            reads: read_stats().counter("iio", socket),
         })
     }
 
+    /// Also count IOMMU lookups, misses and page walks, one more event group window
+    pub fn with_iommu(mut self, iommu: bool) -> Self {
+        self.iommu = iommu;
+        self.scheduler = Self::scheduler(iommu);
+        self
+    }
+
+    pub fn has_iommu(&self) -> bool {
+        self.iommu
+    }
+
+    /// Build and validate the counter controls of every event group without touching hardware
+    pub fn validate_program() -> Result<()> {
+        IIO_EVENTS
+            .iter()
+            .chain([&IOMMU_EVENTS])
+            .try_for_each(IioEventConfig::validate)
+    }
+
+    fn scheduler(iommu: bool) -> EventScheduler<&'static IioEventConfig> {
+        EventScheduler::with_groups(
+            IIO_EVENTS
+                .iter()
+                .chain(iommu.then_some(&IOMMU_EVENTS))
+                .collect(),
+            EVENT_GROUP_WINDOW,
+        )
+    }
+
     pub fn collect_metrics(&mut self) -> Result<HashMap<IioMetric, f64>> {
         let mut metrics = HashMap::new();
 
-        // Try to collect programmable counter metrics
-        // If this fails (MSR writes not supported), we'll only collect PCIe bandwidth
-        let programmable_supported = self.try_collect_programmable_metrics(&mut metrics);
+        // Try to collect programmable counter metrics. A failure reprograms the
+        // current group on the next collect; after MAX_PROGRAMMING_FAILURES in a row
+        // (MSR writes not supported) we only collect PCIe bandwidth
+        if self.programmable_supported {
+            if self.try_collect_programmable_metrics(&mut metrics) {
+                self.programming_failures = 0;
+            } else {
+                self.loaded = false;
+                self.programming_failures += 1;
+                self.programmable_supported = self.programming_failures < MAX_PROGRAMMING_FAILURES;
+            }
+        }
 
-        if !programmable_supported && !self.programmable_warned {
+        if !self.programmable_supported
+            && !self.read_only
+            && log_limiter().allow(&format!("IIO socket {}", self.socket), "not programmable")
+        {
             tracing::warn!(
                 "IIO programmable counters not available on socket {} (MSR writes protected). \
                  Only PCIe bandwidth metrics will be reported.",
                 self.socket
             );
-            self.programmable_warned = true; // Only warn once
         }
 
         // Collect PCIe free-running counter metrics (these are always read-only)
-        self.collect_pcie_bandwidth(&mut metrics)?;
+        self.collect_pcie_bandwidth(&mut metrics, Instant::now())?;
+        self.collect_pcie_utilization(&mut metrics)?;
 
         Ok(metrics)
     }
 
     fn try_collect_programmable_metrics(&mut self, metrics: &mut HashMap<IioMetric, f64>) -> bool {
-        // Try to collect programmable counter metrics
-        for event_config in IIO_EVENTS {
-            // Try to program all units for this event
-            let mut program_failed = false;
-            for unit in &self.units {
-                if let Err(e) = unit.program(event_config) {
-                    tracing::debug!("Failed to program IIO unit: {}", e);
-                    program_failed = true;
-                    break;
-                }
-            }
+        let Some(&group) = self.scheduler.current() else {
+            return true;
+        };
 
-            if program_failed {
-                // MSR writes not supported - return false
+        if !self.loaded {
+            // First collect or after a reset: start counting the current group
+            if !self.program_units(group) {
                 return false;
             }
-
-            // Sleep to collect data
-            std::thread::sleep(Duration::from_secs(1));
+            self.scheduler.reset();
+            self.loaded = true;
+        } else if self.scheduler.should_rotate() {
+            let Some(window) = rate_window_secs(self.scheduler.elapsed()) else {
+                return true;
+            };
 
             // Read counters
             let mut all_values = Vec::new();
             for unit in &self.units {
-                match unit.read_counters() {
-                    Ok(values) => all_values.push(values),
+                match unit.read_counters(&self.reads) {
+                    Ok(values) => all_values.push(scale_to_window(values, window)),
                     Err(e) => {
                         tracing::debug!("Failed to read IIO counters: {}", e);
                         return false;
                     }
                 }
             }
-
             self.event_results
-                .insert(event_config.name.to_string(), all_values);
+                .insert(group.name.to_string(), all_values);
+
+            let Some(&next) = self.scheduler.rotate() else {
+                return true;
+            };
+            if !self.program_units(next) {
+                return false;
+            }
         }
 
         // Calculate metrics from programmable counters
@@ -239,6 +428,17 @@ impl IioMonitor {
         true
     }
 
+    // Program every unit with `group`, false once MSR writes turn out not to be supported
+    fn program_units(&self, group: &IioEventConfig) -> bool {
+        for unit in &self.units {
+            if let Err(e) = unit.program(group) {
+                tracing::debug!("Failed to program IIO unit: {}", e);
+                return false;
+            }
+        }
+        true
+    }
+
     fn calculate_programmable_metrics(&self, metrics: &mut HashMap<IioMetric, f64>) -> Result<()> {
         // TLB Miss Group
         if let Some(values) = self.event_results.get("TLB_Miss_Group") {
@@ -280,11 +480,39 @@ impl IioMonitor {
             }
         }
 
+        // IOMMU Group
+        if let Some(values) = self.event_results.get("IOMMU_Group") {
+            let lookups: u64 = values.iter().map(|v| v[0]).sum();
+            let misses: u64 = values.iter().map(|v| v[1]).sum();
+            let occupancy: u64 = values.iter().map(|v| v[2]).sum();
+            // Every stack runs off the same IO clock
+            let clockticks = values.iter().map(|v| v[3]).max().unwrap_or(0);
+
+            if let Some(ratio) = iommu_hit_ratio(lookups, misses) {
+                metrics.insert(IioMetric::IOMMUCacheHitRatio, ratio);
+            }
+            if let Some(latency) =
+                page_walk_latency_ns(occupancy, misses, clockticks, EVENT_GROUP_WINDOW)
+            {
+                metrics.insert(IioMetric::IOMMUPageWalkLatency, latency);
+            }
+        }
+
         Ok(())
     }
 
-    fn collect_pcie_bandwidth(&mut self, metrics: &mut HashMap<IioMetric, f64>) -> Result<()> {
-        let mut current_values = [[0u64; iio::IIO_PCIE_PORT_COUNT * 2]; iio::IIO_CHANNEL_COUNT];
+    // Bandwidth over the window since the last read, none on the first read
+    fn collect_pcie_bandwidth(
+        &mut self,
+        metrics: &mut HashMap<IioMetric, f64>,
+        now: Instant,
+    ) -> Result<()> {
+        // Too soon after the last read: keep the baselines so the next window covers both
+        if self.pcie_baseline.too_soon(now) {
+            return Ok(());
+        }
+        let mut current_values: PortCounters =
+            [[0; iio::IIO_PCIE_PORT_COUNT * 2]; iio::IIO_CHANNEL_COUNT];
 
         // Read all PCIe counters
         #[allow(clippy::needless_range_loop)]
@@ -293,56 +521,431 @@ impl IioMonitor {
                 let in_addr = iio::msr::IIO_PCIE_BANDWIDTH_IN[ch][port];
                 let out_addr = iio::msr::IIO_PCIE_BANDWIDTH_OUT[ch][port];
 
-                let in_val =
-                    msr::read(self.core, in_addr)? & ((1u64 << iio::IIO_COUNTER_WIDTH_BITS) - 1);
-                let out_val =
-                    msr::read(self.core, out_addr)? & ((1u64 << iio::IIO_COUNTER_WIDTH_BITS) - 1);
+                let in_val = self.read_free_running(in_addr)?;
+                let out_val = self.read_free_running(out_addr)?;
 
                 current_values[ch][port] = in_val;
                 current_values[ch][port + iio::IIO_PCIE_PORT_COUNT] = out_val;
             }
         }
 
-        let current_time = Instant::now();
-
-        // Calculate bandwidth if we have previous values
-        if let (Some(last_values), Some(last_time)) = (&self.pcie_last_values, self.pcie_last_time)
-        {
-            let elapsed = current_time.duration_since(last_time).as_secs_f64();
-
+        if let Some((last_values, window)) = self.pcie_baseline.advance(current_values, now) {
+            let elapsed = window.as_secs_f64();
             for ch in 0..iio::IIO_CHANNEL_COUNT {
                 for port in 0..iio::IIO_PCIE_PORT_COUNT {
                     // IN bandwidth
-                    let in_delta = if current_values[ch][port] >= last_values[ch][port] {
-                        current_values[ch][port] - last_values[ch][port]
-                    } else {
-                        (1u64 << iio::IIO_COUNTER_WIDTH_BITS) - last_values[ch][port]
-                            + current_values[ch][port]
-                    };
+                    let in_delta = wrapping_delta(
+                        last_values[ch][port],
+                        current_values[ch][port],
+                        iio::IIO_COUNTER_WIDTH_BITS,
+                    );
                     let in_bandwidth = (in_delta as f64 * CACHELINE_SIZE as f64) / elapsed / 1e9;
                     metrics.insert(IioMetric::PCIeInBandwidth(ch, port), in_bandwidth);
 
                     // OUT bandwidth
                     let out_idx = port + iio::IIO_PCIE_PORT_COUNT;
-                    let out_delta = if current_values[ch][out_idx] >= last_values[ch][out_idx] {
-                        current_values[ch][out_idx] - last_values[ch][out_idx]
-                    } else {
-                        (1u64 << iio::IIO_COUNTER_WIDTH_BITS) - last_values[ch][out_idx]
-                            + current_values[ch][out_idx]
-                    };
+                    let out_delta = wrapping_delta(
+                        last_values[ch][out_idx],
+                        current_values[ch][out_idx],
+                        iio::IIO_COUNTER_WIDTH_BITS,
+                    );
                     let out_bandwidth = (out_delta as f64 * CACHELINE_SIZE as f64) / elapsed / 1e9;
                     metrics.insert(IioMetric::PCIeOutBandwidth(ch, port), out_bandwidth);
                 }
             }
         }
+        Ok(())
+    }
+
+    fn read_free_running(&self, addr: u64) -> Result<u64> {
+        Ok(self.reads.observe(self.msr.read(self.core, addr))? & iio::IIO_COUNTER_MASK)
+    }
 
-        self.pcie_last_values = Some(current_values);
-        self.pcie_last_time = Some(current_time);
+    fn read_utilization(&self) -> Result<UtilizationReading> {
+        let read = |addr| self.read_free_running(addr);
 
+        let mut reading = UtilizationReading {
+            clock: [0; iio::IIO_CHANNEL_COUNT],
+            active: [[0; iio::IIO_PCIE_PORT_COUNT * 2]; iio::IIO_CHANNEL_COUNT],
+        };
+        for ch in 0..iio::IIO_CHANNEL_COUNT {
+            reading.clock[ch] = read(iio::msr::IIO_UNIT_CLK[ch])?;
+            for port in 0..iio::IIO_PCIE_PORT_COUNT {
+                reading.active[ch][port] = read(iio::msr::IIO_PCIE_UTIL_IN[ch][port])?;
+                reading.active[ch][port + iio::IIO_PCIE_PORT_COUNT] =
+                    read(iio::msr::IIO_PCIE_UTIL_OUT[ch][port])?;
+            }
+        }
+        Ok(reading)
+    }
+
+    // Active cycles over IO clock cycles, so no wall-clock window is needed
+    fn collect_pcie_utilization(&mut self, metrics: &mut HashMap<IioMetric, f64>) -> Result<()> {
+        let current = self.read_utilization()?;
+
+        if let Some((last, _)) = self.util_baseline.advance(current, Instant::now()) {
+            let delta = |a: u64, b: u64| wrapping_delta(a, b, iio::IIO_COUNTER_WIDTH_BITS);
+            for ch in 0..iio::IIO_CHANNEL_COUNT {
+                let clock = delta(last.clock[ch], current.clock[ch]);
+                for port in 0..iio::IIO_PCIE_PORT_COUNT {
+                    let out_idx = port + iio::IIO_PCIE_PORT_COUNT;
+                    let utilization = port_utilization(
+                        delta(last.active[ch][port], current.active[ch][port]),
+                        delta(last.active[ch][out_idx], current.active[ch][out_idx]),
+                        clock,
+                    );
+                    if let Some(utilization) = utilization {
+                        metrics.insert(IioMetric::PCIeUtilization(ch, port), utilization);
+                    }
+                }
+            }
+        }
         Ok(())
     }
 
+    /// Drop the PCIe baselines and cached event results, the next collect is a first sample
+    ///
+    /// Programmable counters given up on after repeated failures are tried again.
+    pub fn reset(&mut self) {
+        // Reprogramming restarts the current group's counters from zero
+        self.loaded = false;
+        self.programmable_supported =
+            !self.read_only && msr::ensure_uncore_unfrozen(self.msr, self.core, "IIO").is_ok();
+        self.programming_failures = 0;
+        self.event_results.clear();
+        self.pcie_baseline.reset();
+        self.util_baseline.reset();
+    }
+
     pub fn socket(&self) -> i32 {
         self.socket
     }
+
+    /// Core the IIO MSRs are accessed from
+    pub fn core(&self) -> u32 {
+        self.core
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::counter::MIN_RATE_WINDOW;
+    use crate::common::msr::mock::MockMsr;
+    use uncflow_raw::current_arch::ubox;
+
+    #[test]
+    fn test_program_verifies_readback() {
+        let msr = MockMsr::new().leak();
+        let unit = IioCounterUnit::new(0, 0, msr).unwrap();
+
+        assert!(unit.program(&IIO_EVENTS[0]).is_ok());
+        let written = msr.get(0, iio::msr::IIO_UNIT_CTL0[0]).unwrap();
+        assert_eq!(
+            IioCounterControl::from_msr_value(written).event_select,
+            0x41
+        );
+    }
+
+    #[test]
+    fn test_invalid_config_fails_before_any_write() {
+        let msr = MockMsr::new().leak();
+        let unit = IioCounterUnit::new(0, 0, msr).unwrap();
+
+        // FC mask is a 3-bit field
+        let config = IioEventConfig {
+            name: "Invalid",
+            events: [(0x41, 0x20, 0xFF, 0x08); 4],
+        };
+
+        let err = unit.program(&config).unwrap_err();
+        assert!(err.to_string().contains("FC mask"));
+        assert_eq!(msr.write_count(), 0);
+        assert!(IioMonitor::validate_program().is_ok());
+    }
+
+    #[test]
+    fn test_read_only_mode_never_writes() {
+        let msr = MockMsr::new().leak();
+        // A frozen uncore would otherwise be unfrozen with a write
+        msr.set(0, ubox::msr::U_MSR_PMON_GLOBAL_CTL, 1 << 63);
+        let mut monitor = IioMonitor::read_only(0, 0, msr).unwrap();
+
+        for _ in 0..3 {
+            let metrics = monitor.collect_metrics().unwrap();
+            assert!(!metrics.contains_key(&IioMetric::IIOTLBMiss));
+        }
+        monitor.reset();
+        monitor.collect_metrics().unwrap();
+        assert_eq!(msr.write_count(), 0);
+    }
+
+    #[test]
+    fn test_first_collect_reads_baseline() {
+        let msr = MockMsr::new().leak();
+        let clock = iio::msr::IIO_UNIT_CLK[0];
+        let active = iio::msr::IIO_PCIE_UTIL_IN[0][0];
+        msr.set(0, clock, 9_000_000);
+        msr.set(0, active, 4_000_000);
+        msr.set(0, iio::msr::IIO_PCIE_BANDWIDTH_IN[0][0], 5_000_000);
+        let mut monitor = IioMonitor::read_only(0, 0, msr).unwrap();
+
+        // Counters far from zero at startup are not counted as one window
+        let metrics = monitor.collect_metrics().unwrap();
+        assert!(!metrics.contains_key(&IioMetric::PCIeInBandwidth(0, 0)));
+        assert!(!metrics.contains_key(&IioMetric::PCIeUtilization(0, 0)));
+
+        msr.set(0, clock, 9_000_000 + 1_000);
+        msr.set(0, active, 4_000_000 + 250);
+        let metrics = monitor.collect_metrics().unwrap();
+        assert_eq!(metrics[&IioMetric::PCIeUtilization(0, 0)], 0.25);
+    }
+
+    #[test]
+    fn test_readback_mismatch_falls_back_to_pcie() {
+        let msr = MockMsr::read_only().leak();
+        let mut monitor = IioMonitor::with_msr(0, msr).unwrap();
+
+        assert!(monitor.units[0].program(&IIO_EVENTS[0]).is_err());
+
+        // Programmable counters are dropped, PCIe bandwidth still collected
+        for _ in 0..MAX_PROGRAMMING_FAILURES {
+            monitor.collect_metrics().unwrap();
+        }
+        assert!(!monitor.programmable_supported);
+        std::thread::sleep(MIN_RATE_WINDOW);
+        let metrics = monitor.collect_metrics().unwrap();
+        assert!(metrics.contains_key(&IioMetric::PCIeInBandwidth(0, 0)));
+        assert!(!metrics.contains_key(&IioMetric::IIOTLBMiss));
+
+        // A reset tries the programmable counters again
+        monitor.reset();
+        assert!(monitor.programmable_supported);
+    }
+
+    #[test]
+    fn test_transient_programming_failure_is_retried() {
+        let msr = MockMsr::new().leak();
+        let ctl = iio::msr::IIO_UNIT_CTL0[0];
+        msr.lock(0, ctl);
+        let mut monitor = IioMonitor::with_msr(0, msr).unwrap();
+
+        // One failed programming attempt keeps the programmable counters
+        monitor.collect_metrics().unwrap();
+        assert!(monitor.programmable_supported);
+        assert!(!monitor.loaded);
+
+        // The next collect programs the group and counts normally
+        msr.unlock(0, ctl);
+        monitor.collect_metrics().unwrap();
+        assert!(monitor.loaded);
+        assert_eq!(monitor.programming_failures, 0);
+        msr.set(0, iio::msr::IIO_UNIT_CTR0[0], 1_000);
+        monitor.scheduler.backdate(EVENT_GROUP_WINDOW);
+        let metrics = monitor.collect_metrics().unwrap();
+        assert_eq!(metrics[&IioMetric::IIOTLBMiss], 1_000.0);
+    }
+
+    #[test]
+    fn test_iommu_ratios() {
+        assert_eq!(iommu_hit_ratio(1_000, 250), Some(0.75));
+        assert_eq!(iommu_hit_ratio(1_000, 0), Some(1.0));
+        // Misses counted a few cycles later than the lookups
+        assert_eq!(iommu_hit_ratio(100, 101), Some(0.0));
+        assert_eq!(iommu_hit_ratio(0, 0), None);
+
+        // 100 walks of 500 cycles each on a 500 MHz IO clock: 1 µs per walk
+        let second = Duration::from_secs(1);
+        assert_eq!(
+            page_walk_latency_ns(50_000, 100, 500_000_000, second),
+            Some(1_000.0)
+        );
+        assert_eq!(page_walk_latency_ns(50_000, 0, 500_000_000, second), None);
+        assert_eq!(page_walk_latency_ns(50_000, 100, 0, second), None);
+        assert_eq!(
+            page_walk_latency_ns(50_000, 100, 500_000_000, Duration::ZERO),
+            None
+        );
+    }
+
+    #[test]
+    fn test_groups_rotate_across_collects() {
+        let msr = MockMsr::new().leak();
+        let mut monitor = IioMonitor::with_msr(0, msr).unwrap().with_iommu(true);
+        assert_eq!(monitor.scheduler.len(), IIO_EVENTS.len() + 1);
+        let event_select = || {
+            IioCounterControl::from_msr_value(msr.get(0, iio::msr::IIO_UNIT_CTL0[0]).unwrap())
+                .event_select
+        };
+
+        // The first collect only programs the first group, without blocking
+        let start = Instant::now();
+        monitor.collect_metrics().unwrap();
+        assert!(start.elapsed() < EVENT_GROUP_WINDOW);
+        assert_eq!(event_select(), IIO_EVENTS[0].events[0].0);
+        assert!(monitor.event_results.is_empty());
+
+        // Not due yet: the group keeps counting
+        monitor.collect_metrics().unwrap();
+        assert_eq!(monitor.scheduler.current_index(), 0);
+
+        // Two windows of counting are scaled down to one
+        msr.set(0, iio::msr::IIO_UNIT_CTR0[0], 2_000);
+        monitor.scheduler.backdate(2 * EVENT_GROUP_WINDOW);
+        let metrics = monitor.collect_metrics().unwrap();
+        assert_eq!(metrics[&IioMetric::IIOTLBMiss], 1_000.0);
+        assert_eq!(monitor.scheduler.current_index(), 1);
+        assert_eq!(event_select(), IIO_EVENTS[1].events[0].0);
+
+        // Reset reprograms the current group instead of reading it
+        monitor.reset();
+        monitor.scheduler.backdate(2 * EVENT_GROUP_WINDOW);
+        monitor.collect_metrics().unwrap();
+        assert!(monitor.event_results.is_empty());
+        assert_eq!(monitor.scheduler.current_index(), 1);
+    }
+
+    #[test]
+    fn test_port_utilization() {
+        assert_eq!(port_utilization(250, 100, 1_000), Some(0.25));
+        assert_eq!(port_utilization(100, 500, 1_000), Some(0.5));
+        assert_eq!(port_utilization(0, 0, 1_000), Some(0.0));
+        // Counters sampled a few cycles apart can overshoot the clock slightly
+        assert_eq!(port_utilization(1_010, 0, 1_000), Some(1.0));
+        assert_eq!(port_utilization(10, 10, 0), None);
+    }
+
+    #[test]
+    fn test_pcie_utilization_from_free_running_counters() {
+        let msr = MockMsr::read_only().leak();
+        let mut monitor = IioMonitor::with_msr(0, msr).unwrap();
+        let clock = iio::msr::IIO_UNIT_CLK[1];
+        let util_in = iio::msr::IIO_PCIE_UTIL_IN[1][2];
+        let util_out = iio::msr::IIO_PCIE_UTIL_OUT[1][2];
+
+        // Counters start mid-way and the inbound one wraps during the window
+        let wrap = iio::IIO_COUNTER_MASK - 99;
+        msr.set(0, clock, 5_000);
+        msr.set(0, util_in, wrap);
+        msr.set(0, util_out, 700);
+
+        let mut metrics = HashMap::new();
+        monitor.collect_pcie_utilization(&mut metrics).unwrap();
+        assert!(metrics.is_empty());
+
+        msr.set(0, clock, 5_000 + 2_000);
+        msr.set(0, util_in, 500);
+        msr.set(0, util_out, 700 + 100);
+        monitor.collect_pcie_utilization(&mut metrics).unwrap();
+        // Inbound: 100 cycles to wrap + 500 after it, out of 2000
+        assert_eq!(metrics[&IioMetric::PCIeUtilization(1, 2)], 0.3);
+        // Channel 0 clock did not move, no ratio
+        assert!(!metrics.contains_key(&IioMetric::PCIeUtilization(0, 0)));
+    }
+
+    #[test]
+    fn test_stuck_freeze_keeps_pcie_counters() {
+        let msr = MockMsr::new().leak();
+        let global_ctl = ubox::msr::U_MSR_PMON_GLOBAL_CTL;
+        msr.set(0, global_ctl, 1 << 63);
+        msr.lock(0, global_ctl);
+
+        // The monitor is created with only the free-running counters
+        let mut monitor = IioMonitor::with_msr(0, msr).unwrap();
+        assert!(!monitor.programmable_supported);
+        monitor.collect_metrics().unwrap();
+        assert!(monitor.pcie_baseline.get().is_some());
+        assert!(!monitor.loaded);
+
+        // Still frozen: a reset does not bring the programmable groups back
+        monitor.reset();
+        assert!(!monitor.programmable_supported);
+        msr.unlock(0, global_ctl);
+        monitor.reset();
+        assert!(monitor.programmable_supported);
+    }
+
+    #[test]
+    fn test_core_override_is_honored() {
+        let msr = MockMsr::read_only().leak();
+        let mut monitor = IioMonitor::with_core(1, 20, msr).unwrap();
+        assert_eq!(monitor.core(), 20);
+
+        // Only the override core's counters are read
+        let clock = iio::msr::IIO_UNIT_CLK[0];
+        let util_in = iio::msr::IIO_PCIE_UTIL_IN[0][0];
+        msr.set(IioMonitor::default_core(1), clock, 1_000);
+        msr.set(20, clock, 1_000);
+        msr.set(20, util_in, 0);
+
+        let mut metrics = HashMap::new();
+        monitor.collect_pcie_utilization(&mut metrics).unwrap();
+        msr.set(IioMonitor::default_core(1), clock, 2_000);
+        msr.set(20, clock, 2_000);
+        msr.set(20, util_in, 250);
+        monitor.collect_pcie_utilization(&mut metrics).unwrap();
+        assert_eq!(metrics[&IioMetric::PCIeUtilization(0, 0)], 0.25);
+    }
+
+    #[test]
+    fn test_pcie_bandwidth_wraps_at_free_running_width() {
+        let msr = MockMsr::new().leak();
+        let mut monitor = IioMonitor::with_msr(0, msr).unwrap();
+        let in_addr = |port: usize| iio::msr::IIO_PCIE_BANDWIDTH_IN[0][port];
+        let out_addr = iio::msr::IIO_PCIE_BANDWIDTH_OUT[0][0];
+        let above_width = 1 << iio::IIO_COUNTER_WIDTH_BITS;
+        let delta = 1_000_000;
+
+        // Port 0 counts without wrapping, port 1 and the outbound counter wrap at
+        // the free-running width, port 2 has stray bits above it
+        msr.set(0, in_addr(0), 5_000);
+        msr.set(0, in_addr(1), iio::IIO_COUNTER_MASK - 499);
+        msr.set(0, in_addr(2), above_width | 5_000);
+        msr.set(0, out_addr, iio::IIO_COUNTER_MASK);
+
+        let start = Instant::now();
+        let mut metrics = HashMap::new();
+        monitor.collect_pcie_bandwidth(&mut metrics, start).unwrap();
+        assert!(metrics.is_empty());
+        assert_eq!(monitor.pcie_baseline.get().unwrap()[0][2], 5_000);
+
+        msr.set(0, in_addr(0), 5_000 + delta);
+        msr.set(0, in_addr(1), delta - 500);
+        msr.set(0, in_addr(2), (2 * above_width) | (5_000 + delta));
+        msr.set(0, out_addr, delta - 1);
+        let later = start + Duration::from_secs(1);
+        monitor.collect_pcie_bandwidth(&mut metrics, later).unwrap();
+
+        // One million cache lines in a second
+        let unwrapped = metrics[&IioMetric::PCIeInBandwidth(0, 0)];
+        assert_eq!(unwrapped, 0.064);
+        // A wider wrap would add 2^48 - 2^36 lines and dwarf the real delta
+        assert_eq!(metrics[&IioMetric::PCIeInBandwidth(0, 1)], unwrapped);
+        assert_eq!(metrics[&IioMetric::PCIeInBandwidth(0, 2)], unwrapped);
+        assert_eq!(metrics[&IioMetric::PCIeOutBandwidth(0, 0)], unwrapped);
+    }
+
+    #[test]
+    fn test_pcie_short_window_keeps_baseline() {
+        let msr = MockMsr::new().leak();
+        let mut monitor = IioMonitor::with_msr(0, msr).unwrap();
+        let in_addr = iio::msr::IIO_PCIE_BANDWIDTH_IN[0][0];
+
+        let start = Instant::now();
+        let mut metrics = HashMap::new();
+        monitor.collect_pcie_bandwidth(&mut metrics, start).unwrap();
+        assert!(metrics.is_empty());
+
+        // Back-to-back read: no rate from a few microseconds, baseline untouched
+        msr.set(0, in_addr, 1_000_000);
+        let soon = start + Duration::from_micros(5);
+        monitor.collect_pcie_bandwidth(&mut metrics, soon).unwrap();
+        assert!(metrics.is_empty());
+        assert_eq!(monitor.pcie_baseline.get().unwrap()[0][0], 0);
+
+        // Once the window is long enough, the delta spans both reads
+        let later = start + Duration::from_secs(1);
+        monitor.collect_pcie_bandwidth(&mut metrics, later).unwrap();
+        assert_eq!(metrics[&IioMetric::PCIeInBandwidth(0, 0)], 0.064);
+    }
 }
@@ -1,3 +1,7 @@
 pub mod monitor;
+pub mod peak;
 
-pub use monitor::ImcMonitor;
+pub use monitor::{
+    aggregate, diff, max_sample_count, sub_sample_period, CounterSnapshot, ImcChannelMetrics,
+    ImcMetrics, ImcMonitor, ImcWindowMetrics,
+};
@@ -1,26 +1,26 @@
 // IMC (Integrated Memory Controller) monitoring
 // Measures memory bandwidth and latency
 
-use crate::common::pci;
+use crate::common::counter::{
+    rate_window_secs, wrapping_delta, Baseline, SampleStats, MIN_RATE_WINDOW,
+};
+use crate::common::{pci, read_stats, sys_roots, CpuArchitecture, ReadCounter, CPU_ARCH};
 use crate::error::Result;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use uncflow_raw::current_arch::imc;
 
 // IMC performance counter MSR addresses (per channel)
 // Base addresses - channels are at offsets
 #[allow(dead_code)] // Reserved for future MSR-based implementation
 const IMC_UNIT_CTRL: u64 = 0x0F1; // Control register
-const IMC_CTR0: u64 = 0x0A0; // Counter 0
-const IMC_CTR1: u64 = 0x0A8; // Counter 1
-const IMC_CTR2: u64 = 0x0B0; // Counter 2
-const IMC_CTR3: u64 = 0x0B8; // Counter 3
-#[allow(dead_code)] // Reserved for future MSR-based implementation
-const IMC_CTL0: u64 = 0x0D8; // Control 0
-#[allow(dead_code)] // Reserved for future MSR-based implementation
-const IMC_CTL1: u64 = 0x0DC; // Control 1
-#[allow(dead_code)] // Reserved for future MSR-based implementation
-const IMC_CTL2: u64 = 0x0E0; // Control 2
-#[allow(dead_code)] // Reserved for future MSR-based implementation
-const IMC_CTL3: u64 = 0x0E4; // Control 3
+
+// Programmable counter each event is counted on. Control imc::pci::IMC_CTL[i]
+// programs counter imc::pci::IMC_CTR[i]; a mismatch would swap read and write bandwidth.
+const CAS_READ_COUNTER: usize = 0;
+const CAS_WRITE_COUNTER: usize = 1;
+const QUEUE_COUNTERS: [usize; 2] = [2, 3];
 
 // IMC PCI configuration (for accessing via PCI)
 // Skylake-SP has 6 channels with different device/function/ID combinations
@@ -51,6 +51,133 @@ const IMC_WPQ_OCCUPANCY: u8 = 0x81; // Write Pending Queue occupancy
 // Assuming 64-byte cache line and counters increment per transaction
 const CACHE_LINE_SIZE: u64 = 64;
 
+// Counter control enable bit
+const ENABLE_BIT: u32 = 1 << 22;
+
+/// Counter control value counting `event`/`umask`: [7:0] event, [15:8] umask, [22] enable
+pub fn counter_control(event: u8, umask: u8) -> u32 {
+    (event as u32) | ((umask as u32) << 8) | ENABLE_BIT
+}
+
+/// Control value of every programmable counter in `mode`, indexed like imc::pci::IMC_CTL
+fn channel_controls(mode: QueueCounterMode) -> [u32; imc::COUNTERS_PER_CHANNEL] {
+    let mut controls = [0; imc::COUNTERS_PER_CHANNEL];
+    controls[CAS_READ_COUNTER] = counter_control(IMC_CAS_COUNT_RD, IMC_CAS_COUNT_RD_UMASK);
+    controls[CAS_WRITE_COUNTER] = counter_control(IMC_CAS_COUNT_WR, IMC_CAS_COUNT_WR_UMASK);
+    let queue = queue_counter_controls(mode);
+    for (counter, control) in QUEUE_COUNTERS.into_iter().zip(queue) {
+        controls[counter] = control;
+    }
+    controls
+}
+
+/// What counters 2 and 3 count besides the CAS counters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueCounterMode {
+    /// RPQ/WPQ occupancy, for latency and queue metrics
+    Occupancy,
+    /// PMM read/write inserts, when Optane persistent memory is installed
+    Pmem,
+    /// Activates and page-miss precharges, for DRAM page hit/miss/conflict ratios
+    PageEvents,
+    /// RPQ occupancy and thermal throttle cycles, with --imc-thermal
+    Thermal,
+}
+
+/// Events of counters 2 and 3 in `mode`
+///
+/// Like pcm-memory, PMM traffic or page events take over the occupancy counters,
+/// so read/write latency and queue occupancy are not measured in those modes.
+/// Thermal throttling only takes the WPQ counter, so read latency is kept.
+pub fn queue_counter_controls(mode: QueueCounterMode) -> [u32; 2] {
+    match mode {
+        QueueCounterMode::Occupancy => [
+            counter_control(IMC_RPQ_OCCUPANCY, 0),
+            counter_control(IMC_WPQ_OCCUPANCY, 0),
+        ],
+        QueueCounterMode::Pmem => [
+            counter_control(imc::events::PMM_RPQ_INSERTS, imc::events::PMM_INSERTS_UMASK),
+            counter_control(imc::events::PMM_WPQ_INSERTS, imc::events::PMM_INSERTS_UMASK),
+        ],
+        QueueCounterMode::PageEvents => [
+            counter_control(imc::events::ACT_COUNT, imc::events::ACT_COUNT_ALL_UMASK),
+            counter_control(
+                imc::events::PRE_COUNT,
+                imc::events::PRE_COUNT_PAGE_MISS_UMASK,
+            ),
+        ],
+        QueueCounterMode::Thermal => [
+            counter_control(IMC_RPQ_OCCUPANCY, 0),
+            counter_control(
+                imc::events::POWER_THROTTLE_CYCLES,
+                imc::events::POWER_THROTTLE_CYCLES_ALL_RANKS_UMASK,
+            ),
+        ],
+    }
+}
+
+/// Temperature in degrees C of a DIMMTEMPSTAT value, None for an empty slot
+///
+/// Reads of an absent PCI function return all ones, and a slot without a DIMM (or
+/// without a thermal sensor) reports 0.
+pub fn dimm_temperature(dimmtempstat: u32) -> Option<f64> {
+    if dimmtempstat == u32::MAX {
+        return None;
+    }
+    match dimmtempstat & imc::pci::DIMM_TEMP_MASK {
+        0 => None,
+        celsius => Some(celsius as f64),
+    }
+}
+
+/// Temperatures keyed by (channel, DIMM slot) of the populated slots of `channels`
+///
+/// `read(channel, offset)` reads a channel's thermal function; channels whose reads
+/// fail are left out.
+pub fn read_dimm_temperatures(
+    channels: &[u32],
+    read: impl Fn(u32, u32) -> Result<u32>,
+) -> BTreeMap<(u32, u32), f64> {
+    let mut temperatures = BTreeMap::new();
+    for &channel in channels {
+        for (dimm, &offset) in imc::pci::DIMMTEMPSTAT.iter().enumerate() {
+            match read(channel, offset) {
+                Ok(value) => {
+                    if let Some(celsius) = dimm_temperature(value) {
+                        temperatures.insert((channel, dimm as u32), celsius);
+                    }
+                }
+                Err(e) => {
+                    tracing::debug!(
+                        "DIMM temperature of IMC channel {} unreadable: {}",
+                        channel,
+                        e
+                    );
+                    break;
+                }
+            }
+        }
+    }
+    temperatures
+}
+
+/// Whether PMM traffic can be counted: Optane DIMMs registered with libnvdimm under
+/// `nvdimm_dir` on a CPU whose IMC has the DDR-T events
+pub fn pmem_present(arch: CpuArchitecture, nvdimm_dir: &Path) -> bool {
+    if arch != CpuArchitecture::CascadeLake {
+        return false;
+    }
+    std::fs::read_dir(nvdimm_dir)
+        .map(|entries| {
+            entries.filter_map(|e| e.ok()).any(|e| {
+                e.file_name()
+                    .to_str()
+                    .is_some_and(|name| name.starts_with("nmem"))
+            })
+        })
+        .unwrap_or(false)
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct ImcCounters {
     pub read_count: u64,
@@ -58,18 +185,215 @@ pub struct ImcCounters {
     pub rpq_occupancy: u64,
     pub wpq_occupancy: u64,
     pub cycles: u64,
+    /// PMM media reads, counted instead of RPQ occupancy on PMEM systems
+    pub pmem_read_count: u64,
+    /// PMM media writes, counted instead of WPQ occupancy on PMEM systems
+    pub pmem_write_count: u64,
+    /// DRAM row activates, counted instead of RPQ occupancy with page events
+    pub activates: u64,
+    /// Precharges due to page conflicts, counted instead of WPQ occupancy with page events
+    pub page_miss_precharges: u64,
+    /// Thermally throttled DCLK cycles, counted instead of WPQ occupancy with --imc-thermal
+    pub throttle_cycles: u64,
+}
+
+impl ImcCounters {
+    /// Counter values with the names they are exported under as raw deltas
+    pub fn named(&self) -> [(&'static str, u64); 5] {
+        [
+            ("cas_read", self.read_count),
+            ("cas_write", self.write_count),
+            ("rpq_occupancy", self.rpq_occupancy),
+            ("wpq_occupancy", self.wpq_occupancy),
+            ("dclk", self.cycles),
+        ]
+    }
+
+    fn add(&mut self, other: &ImcCounters) {
+        self.read_count += other.read_count;
+        self.write_count += other.write_count;
+        self.rpq_occupancy += other.rpq_occupancy;
+        self.wpq_occupancy += other.wpq_occupancy;
+        self.cycles += other.cycles;
+        self.pmem_read_count += other.pmem_read_count;
+        self.pmem_write_count += other.pmem_write_count;
+        self.activates += other.activates;
+        self.page_miss_precharges += other.page_miss_precharges;
+        self.throttle_cycles += other.throttle_cycles;
+    }
+
+    fn delta(&self, later: &ImcCounters) -> ImcCounters {
+        let d = |a: u64, b: u64| wrapping_delta(a, b, imc::PCI_COUNTER_WIDTH_BITS);
+        ImcCounters {
+            read_count: d(self.read_count, later.read_count),
+            write_count: d(self.write_count, later.write_count),
+            rpq_occupancy: d(self.rpq_occupancy, later.rpq_occupancy),
+            wpq_occupancy: d(self.wpq_occupancy, later.wpq_occupancy),
+            cycles: d(self.cycles, later.cycles),
+            pmem_read_count: d(self.pmem_read_count, later.pmem_read_count),
+            pmem_write_count: d(self.pmem_write_count, later.pmem_write_count),
+            activates: d(self.activates, later.activates),
+            page_miss_precharges: d(self.page_miss_precharges, later.page_miss_precharges),
+            throttle_cycles: d(self.throttle_cycles, later.throttle_cycles),
+        }
+    }
+}
+
+/// Opaque point-in-time reading of the IMC channel counters, see [`diff`]
+#[derive(Debug, Clone)]
+pub struct CounterSnapshot {
+    counters: HashMap<u32, ImcCounters>,
+    taken_at: Instant,
+}
+
+/// Socket-wide IMC metrics over the window between two snapshots
+///
+/// Channels missing from either snapshot are skipped.
+pub fn diff(before: &CounterSnapshot, after: &CounterSnapshot) -> ImcMetrics {
+    let elapsed = after.taken_at.saturating_duration_since(before.taken_at);
+    let deltas: BTreeMap<u32, ImcCounters> = after
+        .counters
+        .iter()
+        .filter_map(|(&ch, later)| {
+            before
+                .counters
+                .get(&ch)
+                .map(|earlier| (ch, earlier.delta(later)))
+        })
+        .collect();
+    derive_metrics(&deltas, elapsed)
+}
+
+/// Derive socket-wide and per-channel metrics from per-channel counter deltas over `elapsed`
+fn derive_metrics(deltas: &BTreeMap<u32, ImcCounters>, elapsed: Duration) -> ImcMetrics {
+    let mut total_metrics = ImcMetrics::default();
+    let Some(elapsed_secs) = rate_window_secs(elapsed) else {
+        return total_metrics;
+    };
+    if deltas.is_empty() {
+        return total_metrics;
+    }
+    total_metrics.deltas = deltas.clone();
+
+    // CAS commands * cache line size, in bytes/sec
+    let bandwidth = |cas: u64| (cas as f64 * CACHE_LINE_SIZE as f64 / elapsed_secs) as u64;
+    total_metrics.channels = deltas
+        .iter()
+        .map(|(&ch, d)| {
+            let channel = ImcChannelMetrics {
+                read_bandwidth: bandwidth(d.read_count),
+                write_bandwidth: bandwidth(d.write_count),
+                rpq_occupancy: d.rpq_occupancy,
+                wpq_occupancy: d.wpq_occupancy,
+            };
+            (ch, channel)
+        })
+        .collect();
+
+    let sum = |f: fn(&ImcCounters) -> u64| deltas.values().map(f).sum::<u64>();
+    let reads = sum(|c| c.read_count);
+    let writes = sum(|c| c.write_count);
+    let rpq_occupancy = sum(|c| c.rpq_occupancy);
+    let wpq_occupancy = sum(|c| c.wpq_occupancy);
+    let total_cycles = sum(|c| c.cycles);
+    let num_channels = deltas.len() as u64;
+
+    total_metrics.pmem_read_bandwidth = bandwidth(sum(|c| c.pmem_read_count));
+    total_metrics.pmem_write_bandwidth = bandwidth(sum(|c| c.pmem_write_count));
+    total_metrics.thermal_throttle_cycles =
+        (sum(|c| c.throttle_cycles) as f64 / elapsed_secs) as u64;
+
+    if reads > 0 {
+        total_metrics.write_read_ratio = writes as f64 / reads as f64;
+    }
+
+    // Every CAS is a page hit unless its row had to be opened first (activate); a
+    // page conflict also had to close another row (page-miss precharge), otherwise
+    // the bank was idle (page miss)
+    let cas = reads + writes;
+    if cas > 0 {
+        let activates = sum(|c| c.activates);
+        let conflicts = sum(|c| c.page_miss_precharges);
+        total_metrics.page_hit_ratio = cas.saturating_sub(activates) as f64 / cas as f64;
+        total_metrics.page_miss_ratio = activates.saturating_sub(conflicts) as f64 / cas as f64;
+        total_metrics.page_conflict_ratio = conflicts.min(activates) as f64 / cas as f64;
+    }
+
+    // Socket bandwidth is the sum of the channels so the breakdown adds up exactly
+    total_metrics.read_bandwidth = total_metrics
+        .channels
+        .values()
+        .map(|c| c.read_bandwidth)
+        .sum();
+    total_metrics.write_bandwidth = total_metrics
+        .channels
+        .values()
+        .map(|c| c.write_bandwidth)
+        .sum();
+
+    // Occupancy (average across all channels)
+    total_metrics.rpq_occupancy = rpq_occupancy / num_channels;
+    total_metrics.wpq_occupancy = wpq_occupancy / num_channels;
+
+    // Calculate frequency from DCLK counter (average channel cycles / time in seconds)
+    total_metrics.frequency = total_cycles as f64 / num_channels as f64 / elapsed_secs / 1e9;
+
+    // Little's Law: average latency (cycles) = accumulated occupancy / requests,
+    // converted to nanoseconds with the measured DCLK frequency
+    if total_metrics.frequency > 0.0 {
+        if reads > 0 {
+            total_metrics.read_latency =
+                rpq_occupancy as f64 / reads as f64 / total_metrics.frequency;
+        }
+        if writes > 0 {
+            total_metrics.write_latency =
+                wpq_occupancy as f64 / writes as f64 / total_metrics.frequency;
+        }
+    }
+
+    // Calculate queue status ratios
+    if total_cycles > 0 {
+        total_metrics.rpq_non_empty = rpq_occupancy as f64 / total_cycles as f64;
+        total_metrics.wpq_non_empty = wpq_occupancy as f64 / total_cycles as f64;
+    }
+
+    // RPQ/WPQ Full - approximation based on high occupancy
+    total_metrics.rpq_full = if total_metrics.rpq_non_empty > 0.8 {
+        total_metrics.rpq_non_empty * 0.5
+    } else {
+        0.0
+    };
+
+    total_metrics.wpq_full = if total_metrics.wpq_non_empty > 0.8 {
+        total_metrics.wpq_non_empty * 0.5
+    } else {
+        0.0
+    };
+
+    total_metrics
 }
 
 pub struct ImcMonitor {
     socket: i32,
     channels: Vec<u32>, // IMC channel numbers
-    prev_counters: HashMap<u32, ImcCounters>,
+    baseline: Baseline<CounterSnapshot>,
+    // What counters 2 and 3 count
+    mode: QueueCounterMode,
+    // Counter reads and failed reads of this socket
+    reads: ReadCounter,
+    // Channels whose DIMM temperatures are read, with --imc-thermal
+    thermal_channels: Vec<u32>,
     #[allow(dead_code)] // Reserved for MSR vs PCI mode selection
     use_pci: bool, // Use PCI access instead of MSR
 }
 
 impl ImcMonitor {
     pub fn new(socket: i32) -> Result<Self> {
+        Self::with_page_events(socket, false)
+    }
+
+    /// Create a monitor counting DRAM page events on counters 2 and 3 if `page_events`
+    pub fn with_page_events(socket: i32, page_events: bool) -> Result<Self> {
         // Detect available IMC channels (typically 2-8 channels)
         let channels = Self::detect_channels(socket)?;
 
@@ -79,16 +403,103 @@ impl ImcMonitor {
             socket
         );
 
-        let prev_counters = HashMap::new();
+        let pmem = pmem_present(*CPU_ARCH, &sys_roots().nvdimm_dir());
+        // Asked for explicitly, so page events win over PMM traffic
+        let mode = if page_events {
+            if pmem {
+                tracing::warn!(
+                    "Counting DRAM page events on socket {}, PMM traffic is not measured",
+                    socket
+                );
+            }
+            QueueCounterMode::PageEvents
+        } else if pmem {
+            tracing::info!(
+                "Optane persistent memory present, counting PMM traffic on socket {} instead of IMC queue occupancy",
+                socket
+            );
+            QueueCounterMode::Pmem
+        } else {
+            QueueCounterMode::Occupancy
+        };
 
         Ok(Self {
             socket,
             channels,
-            prev_counters,
+            baseline: Baseline::default(),
+            mode,
+            reads: read_stats().counter("imc", socket),
+            thermal_channels: Vec::new(),
             use_pci: false, // Try MSR first, fallback to PCI if needed
         })
     }
 
+    /// Also count thermal throttle cycles and read DIMM temperatures if `thermal`
+    ///
+    /// Throttling is counted on the WPQ occupancy counter, so it is not counted when
+    /// PMM traffic or page events already use it. Must be called before `initialize()`.
+    pub fn with_thermal(mut self, thermal: bool) -> Self {
+        if !thermal {
+            return self;
+        }
+
+        match self.mode {
+            QueueCounterMode::Occupancy => self.mode = QueueCounterMode::Thermal,
+            QueueCounterMode::Thermal => {}
+            QueueCounterMode::Pmem | QueueCounterMode::PageEvents => tracing::warn!(
+                "IMC counters of socket {} are taken by {:?} events, not counting thermal throttling",
+                self.socket,
+                self.mode
+            ),
+        }
+
+        // Channels without a readable thermal function have no temperatures to export
+        self.thermal_channels = self
+            .channels
+            .iter()
+            .copied()
+            .filter(|&ch| {
+                Self::thermal_address(self.socket, ch).is_some_and(|addr| {
+                    pci::Pci::instance()
+                        .read32(&addr, 0)
+                        .is_ok_and(|id| id & 0xFFFF == 0x8086)
+                })
+            })
+            .collect();
+        if self.thermal_channels.is_empty() {
+            tracing::warn!(
+                "No IMC thermal registers found on socket {}, not exporting DIMM temperatures",
+                self.socket
+            );
+        }
+        self
+    }
+
+    // PCI address of a channel's thermal function (DIMM temperature CSRs)
+    fn thermal_address(socket: i32, channel: u32) -> Option<pci::PciConfigAddress> {
+        let &(device, function, device_id) =
+            imc::pci::IMC_THERMAL_CHANNELS.get(channel as usize)?;
+        Some(pci::PciConfigAddress {
+            socket: socket as u32,
+            device,
+            function,
+            device_id,
+        })
+    }
+
+    /// Temperatures of the populated DIMM slots, keyed by (channel, slot)
+    pub fn dimm_temperatures(&self) -> BTreeMap<(u32, u32), f64> {
+        read_dimm_temperatures(&self.thermal_channels, |channel, offset| {
+            let addr = Self::thermal_address(self.socket, channel).ok_or_else(|| {
+                crate::error::UncflowError::InvalidConfiguration(format!(
+                    "Invalid IMC channel index: {channel}"
+                ))
+            })?;
+            self.reads
+                .observe(pci::Pci::instance().read32(&addr, offset))
+        })
+    }
+
     fn detect_channels(socket: i32) -> Result<Vec<u32>> {
         // Skylake-SP has up to 6 memory channels
         let mut channels = Vec::new();
@@ -137,11 +548,33 @@ impl ImcMonitor {
         Ok(channels)
     }
 
+    /// Whether PMM (Optane) read/write traffic is counted
+    pub fn has_pmem(&self) -> bool {
+        self.mode == QueueCounterMode::Pmem
+    }
+
+    /// Whether DRAM activates and page-miss precharges are counted
+    pub fn has_page_events(&self) -> bool {
+        self.mode == QueueCounterMode::PageEvents
+    }
+
+    /// Whether thermal throttle cycles are counted
+    pub fn has_thermal(&self) -> bool {
+        self.mode == QueueCounterMode::Thermal
+    }
+
+    /// Number of detected IMC channels
+    pub fn channel_count(&self) -> usize {
+        self.channels.len()
+    }
+
     pub fn initialize(&mut self) -> Result<()> {
         // Initialize counters for each channel
         for &ch in &self.channels {
-            self::initialize_channel(self.socket, ch)?;
+            self::initialize_channel(self.socket, ch, self.mode)?;
         }
+        // The first collect after programming reads the baseline
+        self.baseline.reset();
         Ok(())
     }
 
@@ -163,128 +596,83 @@ impl ImcMonitor {
 
         // Read counters from PCI config space
         // Counters are typically at specific offsets
-        let read_count = pci::Pci::instance().read32(&pci_addr, IMC_CTR0 as u32)? as u64;
-        let write_count = pci::Pci::instance().read32(&pci_addr, IMC_CTR1 as u32)? as u64;
-        let rpq_occupancy = pci::Pci::instance().read32(&pci_addr, IMC_CTR2 as u32)? as u64;
-        let wpq_occupancy = pci::Pci::instance().read32(&pci_addr, IMC_CTR3 as u32)? as u64;
+        let read = |addr: &pci::PciConfigAddress, offset: u32| {
+            self.reads
+                .observe(pci::Pci::instance().read32(addr, offset))
+        };
+        let counter = |index: usize| read(&pci_addr, imc::pci::IMC_CTR[index]).map(u64::from);
+        let read_count = counter(CAS_READ_COUNTER)?;
+        let write_count = counter(CAS_WRITE_COUNTER)?;
+        let ctr2 = counter(QUEUE_COUNTERS[0])?;
+        let ctr3 = counter(QUEUE_COUNTERS[1])?;
+        let mut counters = ImcCounters::default();
+        match self.mode {
+            QueueCounterMode::Occupancy => {
+                counters.rpq_occupancy = ctr2;
+                counters.wpq_occupancy = ctr3;
+            }
+            QueueCounterMode::Pmem => {
+                counters.pmem_read_count = ctr2;
+                counters.pmem_write_count = ctr3;
+            }
+            QueueCounterMode::PageEvents => {
+                counters.activates = ctr2;
+                counters.page_miss_precharges = ctr3;
+            }
+            QueueCounterMode::Thermal => {
+                counters.rpq_occupancy = ctr2;
+                counters.throttle_cycles = ctr3;
+            }
+        }
 
-        // Read uncore clock counter (DCLK counter)
-        // This is a free-running counter that tracks memory controller clocks
-        const IMC_DCLK_CTR: u32 = 0x0A4; // DCLK counter offset
-        let cycles = pci::Pci::instance().read32(&pci_addr, IMC_DCLK_CTR)? as u64;
+        // Read the fixed DCLK counter, which tracks memory controller clocks once enabled
+        let cycles = read(&pci_addr, imc::pci::IMC_DCLK_CTR)? as u64;
 
         Ok(ImcCounters {
             read_count,
             write_count,
-            rpq_occupancy,
-            wpq_occupancy,
             cycles,
+            ..counters
         })
     }
 
-    pub fn collect(&mut self) -> Result<ImcMetrics> {
-        let mut total_metrics = ImcMetrics::default();
-
+    /// Read all channels without touching the collect() state
+    pub fn snapshot(&self) -> Result<CounterSnapshot> {
+        let mut counters = HashMap::new();
         for &channel in &self.channels {
-            let current = self.read_channel_counters(channel)?;
-            let prev = self
-                .prev_counters
-                .get(&channel)
-                .cloned()
-                .unwrap_or_default();
-
-            // Calculate deltas
-            let read_delta = current.read_count.saturating_sub(prev.read_count);
-            let write_delta = current.write_count.saturating_sub(prev.write_count);
-
-            // Convert to bandwidth (bytes/sec)
-            // CAS commands * cache line size
-            total_metrics.read_bandwidth += read_delta * CACHE_LINE_SIZE;
-            total_metrics.write_bandwidth += write_delta * CACHE_LINE_SIZE;
-
-            // Occupancy (average across all channels)
-            total_metrics.rpq_occupancy += current.rpq_occupancy;
-            total_metrics.wpq_occupancy += current.wpq_occupancy;
-
-            // Save for next iteration
-            self.prev_counters.insert(channel, current);
-        }
-
-        // Average occupancy across channels
-        let num_channels = self.channels.len() as u64;
-        if num_channels > 0 {
-            total_metrics.rpq_occupancy /= num_channels;
-            total_metrics.wpq_occupancy /= num_channels;
+            counters.insert(channel, self.read_channel_counters(channel)?);
         }
+        Ok(CounterSnapshot {
+            counters,
+            taken_at: Instant::now(),
+        })
+    }
 
-        // Calculate latency from occupancy and bandwidth using Little's Law
-        // Latency (ns) = (Average Queue Occupancy * Time) / Throughput
-        // We use the cycle counters to convert occupancy to time
-        let total_cycles: u64 = self
-            .channels
-            .iter()
-            .filter_map(|&ch| self.prev_counters.get(&ch).map(|c| c.cycles))
-            .sum();
+    /// Take a fresh baseline, the next collect() covers only the time since now
+    pub fn reset(&mut self) -> Result<()> {
+        self.baseline.reset();
+        let snapshot = self.snapshot()?;
+        let taken_at = snapshot.taken_at;
+        self.baseline.advance(snapshot, taken_at);
+        Ok(())
+    }
 
-        if total_cycles > 0 && total_metrics.read_bandwidth > 0 {
-            // Assuming 1 GHz uncore frequency (typical), cycles = nanoseconds
-            let time_ns = total_cycles as f64;
-            total_metrics.read_latency = (total_metrics.rpq_occupancy as f64 * time_ns)
-                / (total_metrics.read_bandwidth as f64);
-        } else {
-            total_metrics.read_latency = 0.0;
-        }
+    /// Metrics over the window since the previous collect
+    ///
+    /// The first collect only reads the baseline, and one too soon after the
+    /// previous collect is skipped, both returning None.
+    pub fn collect(&mut self) -> Result<Option<ImcMetrics>> {
+        let current = self.snapshot()?;
+        Ok(self.close_window(current))
+    }
 
-        if total_cycles > 0 && total_metrics.write_bandwidth > 0 {
-            let time_ns = total_cycles as f64;
-            total_metrics.write_latency = (total_metrics.wpq_occupancy as f64 * time_ns)
-                / (total_metrics.write_bandwidth as f64);
-        } else {
-            total_metrics.write_latency = 0.0;
+    fn close_window(&mut self, current: CounterSnapshot) -> Option<ImcMetrics> {
+        let taken_at = current.taken_at;
+        if self.baseline.too_soon(taken_at) {
+            return None;
         }
-
-        // Calculate frequency from DCLK counter (cycles / time in seconds)
-        let elapsed = self
-            .prev_counters
-            .values()
-            .next()
-            .map(|_| std::time::Duration::from_secs(1)) // Approximate 1 second
-            .unwrap_or(std::time::Duration::from_secs(1));
-        let elapsed_secs = elapsed.as_secs_f64();
-
-        total_metrics.frequency = if total_cycles > 0 && elapsed_secs > 0.0 {
-            (total_cycles as f64 / elapsed_secs) / 1e9 // Convert to GHz
-        } else {
-            0.0
-        };
-
-        // Calculate queue status ratios
-        total_metrics.rpq_non_empty = if total_cycles > 0 {
-            total_metrics.rpq_occupancy as f64 / total_cycles as f64
-        } else {
-            0.0
-        };
-
-        total_metrics.wpq_non_empty = if total_cycles > 0 {
-            total_metrics.wpq_occupancy as f64 / total_cycles as f64
-        } else {
-            0.0
-        };
-
-        // RPQ/WPQ Full - approximation based on high occupancy
-        total_metrics.rpq_full = if total_metrics.rpq_non_empty > 0.8 {
-            total_metrics.rpq_non_empty * 0.5
-        } else {
-            0.0
-        };
-
-        total_metrics.wpq_full = if total_metrics.wpq_non_empty > 0.8 {
-            total_metrics.wpq_non_empty * 0.5
-        } else {
-            0.0
-        };
-
-        Ok(total_metrics)
+        let (prev, _) = self.baseline.advance(current.clone(), taken_at)?;
+        Some(diff(&prev, &current))
     }
 }
 
@@ -301,9 +689,140 @@ pub struct ImcMetrics {
     pub wpq_non_empty: f64, // Ratio of cycles when WPQ is non-empty
     pub wpq_full: f64,      // Ratio of cycles when WPQ is full
     pub frequency: f64,     // IMC frequency in GHz
+    /// PMM media read bandwidth in bytes/sec, zero without PMEM
+    pub pmem_read_bandwidth: u64,
+    /// PMM media write bandwidth in bytes/sec, zero without PMEM
+    pub pmem_write_bandwidth: u64,
+    /// Write CAS over read CAS commands
+    pub write_read_ratio: f64,
+    /// Share of CAS commands to an already open row; only meaningful with page events
+    pub page_hit_ratio: f64,
+    /// Share of CAS commands to an idle bank, needing only an activate
+    pub page_miss_ratio: f64,
+    /// Share of CAS commands that first had to close another open row
+    pub page_conflict_ratio: f64,
+    /// Thermally throttled DCLK cycles per second, summed over channels (--imc-thermal)
+    pub thermal_throttle_cycles: u64,
+    /// Per-channel breakdown, keyed by IMC channel index
+    pub channels: BTreeMap<u32, ImcChannelMetrics>,
+    /// Per-channel counter deltas the metrics were derived from (summed by `aggregate`)
+    pub deltas: BTreeMap<u32, ImcCounters>,
+}
+
+/// Bandwidth and queue occupancy of a single IMC channel
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImcChannelMetrics {
+    pub read_bandwidth: u64,
+    pub write_bandwidth: u64,
+    pub rpq_occupancy: u64,
+    pub wpq_occupancy: u64,
+}
+
+/// IMC metrics aggregated over the sub-samples of one export interval
+#[derive(Debug, Clone, Default)]
+pub struct ImcWindowMetrics {
+    /// Per-field mean of the sub-samples
+    pub mean: ImcMetrics,
+    pub read_bandwidth: SampleStats,
+    pub write_bandwidth: SampleStats,
+}
+
+/// Spacing of the `sample_count` sub-samples of one collect
+///
+/// The sub-samples are spread evenly over `period`, but squeezed into half of
+/// `read_timeout`: the collect runs under that deadline, and the sleeps between
+/// sub-samples must leave room for the reads themselves.
+pub fn sub_sample_period(period: Duration, sample_count: u32, read_timeout: Duration) -> Duration {
+    period.min(read_timeout / 2) / sample_count.max(1)
+}
+
+/// Most sub-samples whose [`sub_sample_period`] is still a [`MIN_RATE_WINDOW`];
+/// closer ones would be dropped as too soon
+pub fn max_sample_count(period: Duration, read_timeout: Duration) -> u32 {
+    let span = period.min(read_timeout / 2).as_nanos() / MIN_RATE_WINDOW.as_nanos();
+    u32::try_from(span).unwrap_or(u32::MAX).max(1)
+}
+
+/// Aggregate consecutive `collect()` results into min/avg/max bandwidth
+pub fn aggregate(samples: &[ImcMetrics]) -> ImcWindowMetrics {
+    if samples.is_empty() {
+        return ImcWindowMetrics::default();
+    }
+
+    let n = samples.len() as f64;
+    let mean_f = |f: fn(&ImcMetrics) -> f64| samples.iter().map(f).sum::<f64>() / n;
+    let mean_u = |f: fn(&ImcMetrics) -> u64| (samples.iter().map(f).sum::<u64>() as f64 / n) as u64;
+
+    ImcWindowMetrics {
+        mean: ImcMetrics {
+            read_bandwidth: mean_u(|m| m.read_bandwidth),
+            write_bandwidth: mean_u(|m| m.write_bandwidth),
+            read_latency: mean_f(|m| m.read_latency),
+            write_latency: mean_f(|m| m.write_latency),
+            rpq_occupancy: mean_u(|m| m.rpq_occupancy),
+            wpq_occupancy: mean_u(|m| m.wpq_occupancy),
+            rpq_non_empty: mean_f(|m| m.rpq_non_empty),
+            rpq_full: mean_f(|m| m.rpq_full),
+            wpq_non_empty: mean_f(|m| m.wpq_non_empty),
+            wpq_full: mean_f(|m| m.wpq_full),
+            frequency: mean_f(|m| m.frequency),
+            pmem_read_bandwidth: mean_u(|m| m.pmem_read_bandwidth),
+            pmem_write_bandwidth: mean_u(|m| m.pmem_write_bandwidth),
+            write_read_ratio: mean_f(|m| m.write_read_ratio),
+            page_hit_ratio: mean_f(|m| m.page_hit_ratio),
+            page_miss_ratio: mean_f(|m| m.page_miss_ratio),
+            page_conflict_ratio: mean_f(|m| m.page_conflict_ratio),
+            thermal_throttle_cycles: mean_u(|m| m.thermal_throttle_cycles),
+            channels: mean_channels(samples),
+            deltas: sum_deltas(samples),
+        },
+        read_bandwidth: SampleStats::from_samples(samples.iter().map(|m| m.read_bandwidth as f64)),
+        write_bandwidth: SampleStats::from_samples(
+            samples.iter().map(|m| m.write_bandwidth as f64),
+        ),
+    }
+}
+
+// Per-channel counter deltas over the whole window
+fn sum_deltas(samples: &[ImcMetrics]) -> BTreeMap<u32, ImcCounters> {
+    let mut sums: BTreeMap<u32, ImcCounters> = BTreeMap::new();
+    for (&ch, d) in samples.iter().flat_map(|m| &m.deltas) {
+        sums.entry(ch).or_default().add(d);
+    }
+    sums
+}
+
+// Per-channel mean over the sub-samples a channel appears in
+fn mean_channels(samples: &[ImcMetrics]) -> BTreeMap<u32, ImcChannelMetrics> {
+    let mut sums: BTreeMap<u32, (ImcChannelMetrics, u64)> = BTreeMap::new();
+    for (&ch, c) in samples.iter().flat_map(|m| &m.channels) {
+        let (sum, n) = sums.entry(ch).or_default();
+        sum.read_bandwidth += c.read_bandwidth;
+        sum.write_bandwidth += c.write_bandwidth;
+        sum.rpq_occupancy += c.rpq_occupancy;
+        sum.wpq_occupancy += c.wpq_occupancy;
+        *n += 1;
+    }
+
+    sums.into_iter()
+        .map(|(ch, (sum, n))| {
+            let mean = ImcChannelMetrics {
+                read_bandwidth: sum.read_bandwidth / n,
+                write_bandwidth: sum.write_bandwidth / n,
+                rpq_occupancy: sum.rpq_occupancy / n,
+                wpq_occupancy: sum.wpq_occupancy / n,
+            };
+            (ch, mean)
+        })
+        .collect()
+}
+
+/// Whether a DCLK control read-back has counting enabled
+fn dclk_enabled(control: u32) -> bool {
+    control & imc::pci::DCLK_ENABLE_BIT != 0
 }
 
-fn initialize_channel(socket: i32, channel: u32) -> Result<()> {
+fn initialize_channel(socket: i32, channel: u32, mode: QueueCounterMode) -> Result<()> {
     // Program IMC performance counters via PCI config space
     if channel as usize >= IMC_CHANNELS.len() {
         return Ok(()); // Silently skip invalid channels
@@ -324,34 +843,462 @@ fn initialize_channel(socket: i32, channel: u32) -> Result<()> {
 
     pci::Pci::instance().write32(&pci_addr, IMC_BOX_CTL, FREEZE_BIT | RESET_BIT)?;
 
-    // Program counter 0: CAS commands (reads)
-    // Event select format: [7:0] event, [15:8] umask, [22] enable
-    const ENABLE_BIT: u32 = 1 << 22;
-    let ctl0_value =
-        (IMC_CAS_COUNT_RD as u32) | ((IMC_CAS_COUNT_RD_UMASK as u32) << 8) | ENABLE_BIT;
-    pci::Pci::instance().write32(&pci_addr, IMC_CTL0 as u32, ctl0_value)?;
-
-    // Program counter 1: CAS commands (writes)
-    let ctl1_value =
-        (IMC_CAS_COUNT_WR as u32) | ((IMC_CAS_COUNT_WR_UMASK as u32) << 8) | ENABLE_BIT;
-    pci::Pci::instance().write32(&pci_addr, IMC_CTL1 as u32, ctl1_value)?;
-
-    // Program counter 2: RPQ occupancy
-    let ctl2_value = (IMC_RPQ_OCCUPANCY as u32) | ENABLE_BIT;
-    pci::Pci::instance().write32(&pci_addr, IMC_CTL2 as u32, ctl2_value)?;
-
-    // Program counter 3: WPQ occupancy
-    let ctl3_value = (IMC_WPQ_OCCUPANCY as u32) | ENABLE_BIT;
-    pci::Pci::instance().write32(&pci_addr, IMC_CTL3 as u32, ctl3_value)?;
+    // Program CAS reads and writes, then RPQ/WPQ occupancy, PMM reads/writes or page events
+    for (control, value) in imc::pci::IMC_CTL.into_iter().zip(channel_controls(mode)) {
+        pci::Pci::instance().write32(&pci_addr, control, value)?;
+    }
 
-    // Enable DCLK counter
-    const IMC_DCLK_CTL: u32 = 0x0A4;
-    const DCLK_ENABLE_BIT: u32 = 1 << 22;
-    const DCLK_RESET_BIT: u32 = 1 << 19;
-    pci::Pci::instance().write32(&pci_addr, IMC_DCLK_CTL, DCLK_ENABLE_BIT | DCLK_RESET_BIT)?;
+    // Enable the fixed DCLK counter and check the enable stuck; without it the
+    // frequency and the latencies derived from it read zero, bandwidth is unaffected
+    let dclk_ctl = imc::pci::DCLK_ENABLE_BIT | imc::pci::DCLK_RESET_BIT;
+    pci::Pci::instance().write32(&pci_addr, imc::pci::IMC_DCLK_CTL, dclk_ctl)?;
+    let readback = pci::Pci::instance().read32(&pci_addr, imc::pci::IMC_DCLK_CTL)?;
+    if !dclk_enabled(readback) {
+        tracing::warn!(
+            "IMC channel {} on socket {}: DCLK counter not enabled (control read back 0x{:08X}), \
+             frequency and latency will read zero",
+            channel,
+            socket,
+            readback
+        );
+    }
 
     // Unfreeze counters
     pci::Pci::instance().write32(&pci_addr, IMC_BOX_CTL, 0)?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot_at(taken_at: Instant, counters: ImcCounters) -> CounterSnapshot {
+        CounterSnapshot {
+            counters: HashMap::from([(0, counters.clone()), (1, counters)]),
+            taken_at,
+        }
+    }
+
+    fn monitor() -> ImcMonitor {
+        ImcMonitor {
+            socket: 0,
+            channels: vec![0, 1],
+            baseline: Baseline::default(),
+            mode: QueueCounterMode::Occupancy,
+            reads: read_stats().counter("imc", 0),
+            thermal_channels: Vec::new(),
+            use_pci: false,
+        }
+    }
+
+    #[test]
+    fn test_first_collect_reads_baseline() {
+        let start = Instant::now();
+        let reads = |read_count| ImcCounters {
+            read_count,
+            ..Default::default()
+        };
+        let mut monitor = monitor();
+
+        // Counts since long before the monitor started are not one window's traffic
+        assert!(monitor
+            .close_window(snapshot_at(start, reads(5_000_000)))
+            .is_none());
+        let metrics = monitor
+            .close_window(snapshot_at(
+                start + Duration::from_secs(1),
+                reads(5_001_000),
+            ))
+            .unwrap();
+        // Two channels, 1000 reads each over one second
+        assert_eq!(metrics.read_bandwidth, 2 * 1_000 * CACHE_LINE_SIZE);
+    }
+
+    #[test]
+    fn test_diff_snapshots() {
+        let start = Instant::now();
+        let before = snapshot_at(
+            start,
+            ImcCounters {
+                read_count: 1_000,
+                write_count: 500,
+                rpq_occupancy: 0,
+                wpq_occupancy: 0,
+                cycles: 0,
+                ..Default::default()
+            },
+        );
+        let after = snapshot_at(
+            start + Duration::from_secs(2),
+            ImcCounters {
+                read_count: 1_000 + 2_000_000,
+                write_count: 500 + 1_000_000,
+                rpq_occupancy: 100_000_000,
+                wpq_occupancy: 50_000_000,
+                cycles: 2_000_000_000,
+                ..Default::default()
+            },
+        );
+
+        let metrics = diff(&before, &after);
+        // 2 channels * 2M reads * 64 B over 2 s
+        assert_eq!(metrics.read_bandwidth, 128_000_000);
+        assert_eq!(metrics.write_bandwidth, 64_000_000);
+        assert!((metrics.frequency - 1.0).abs() < 1e-9);
+        // 100M occupancy / 2M reads = 50 cycles at 1 GHz
+        assert!((metrics.read_latency - 50.0).abs() < 1e-9);
+        assert!((metrics.write_latency - 50.0).abs() < 1e-9);
+        assert!((metrics.rpq_non_empty - 0.05).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_diff_handles_counter_wrap() {
+        let start = Instant::now();
+        let before = snapshot_at(
+            start,
+            ImcCounters {
+                read_count: u32::MAX as u64 - 9,
+                ..Default::default()
+            },
+        );
+        let after = snapshot_at(
+            start + Duration::from_secs(1),
+            ImcCounters {
+                read_count: 10,
+                ..Default::default()
+            },
+        );
+
+        let metrics = diff(&before, &after);
+        assert_eq!(metrics.read_bandwidth, 2 * 20 * CACHE_LINE_SIZE);
+    }
+
+    #[test]
+    fn test_aggregate_sub_samples() {
+        let sample = |read_bandwidth, write_bandwidth, frequency| ImcMetrics {
+            read_bandwidth,
+            write_bandwidth,
+            frequency,
+            ..Default::default()
+        };
+        let window = aggregate(&[
+            sample(100, 10, 1.0),
+            sample(400, 30, 1.2),
+            sample(100, 20, 1.4),
+        ]);
+
+        assert_eq!(window.read_bandwidth.min, 100.0);
+        assert_eq!(window.read_bandwidth.avg, 200.0);
+        assert_eq!(window.read_bandwidth.max, 400.0);
+        assert_eq!(window.write_bandwidth.min, 10.0);
+        assert_eq!(window.write_bandwidth.max, 30.0);
+        assert_eq!(window.mean.read_bandwidth, 200);
+        assert_eq!(window.mean.write_bandwidth, 20);
+        assert!((window.mean.frequency - 1.2).abs() < 1e-9);
+
+        assert_eq!(aggregate(&[]).read_bandwidth, SampleStats::default());
+    }
+
+    #[test]
+    fn test_sub_samples_fit_the_read_timeout() {
+        let period = Duration::from_secs(1);
+        let timeout = Duration::from_secs(1);
+
+        // The sleeps of 100 sub-samples span half the timeout, not the whole period
+        let spacing = sub_sample_period(period, 100, timeout);
+        assert_eq!(spacing, Duration::from_millis(5));
+        assert!(spacing * 99 < timeout / 2);
+
+        // A generous timeout spreads them over the period
+        assert_eq!(
+            sub_sample_period(period, 4, Duration::from_secs(5)),
+            Duration::from_millis(250)
+        );
+        assert_eq!(sub_sample_period(period, 0, timeout), timeout / 2);
+
+        // 50 sub-samples of 10ms fill half a 1s timeout
+        assert_eq!(max_sample_count(period, timeout), 50);
+        assert_eq!(sub_sample_period(period, 50, timeout), MIN_RATE_WINDOW);
+        assert_eq!(max_sample_count(period, Duration::from_secs(5)), 100);
+        assert_eq!(max_sample_count(period, Duration::from_millis(10)), 1);
+    }
+
+    #[test]
+    fn test_channel_breakdown_sums_to_aggregate() {
+        let start = Instant::now();
+        let channel = |read_count, write_count, rpq_occupancy| ImcCounters {
+            read_count,
+            write_count,
+            rpq_occupancy,
+            cycles: 1_000_000_000,
+            ..Default::default()
+        };
+        let before = CounterSnapshot {
+            counters: (0..3).map(|ch| (ch, ImcCounters::default())).collect(),
+            taken_at: start,
+        };
+        let after = CounterSnapshot {
+            counters: HashMap::from([
+                (0, channel(1_000, 100, 3_000)),
+                (1, channel(5_000, 700, 9_000)),
+                (2, channel(3, 0, 0)),
+            ]),
+            taken_at: start + Duration::from_secs(1),
+        };
+
+        let metrics = diff(&before, &after);
+        assert_eq!(metrics.channels.len(), 3);
+        assert_eq!(metrics.channels[&1].read_bandwidth, 5_000 * CACHE_LINE_SIZE);
+        assert_eq!(metrics.channels[&2].write_bandwidth, 0);
+
+        let sum = |f: fn(&ImcChannelMetrics) -> u64| metrics.channels.values().map(f).sum::<u64>();
+        assert_eq!(metrics.read_bandwidth, sum(|c| c.read_bandwidth));
+        assert_eq!(metrics.write_bandwidth, sum(|c| c.write_bandwidth));
+        // Socket occupancy is the channel average
+        assert_eq!(metrics.rpq_occupancy, sum(|c| c.rpq_occupancy) / 3);
+
+        // Sub-sample aggregation keeps the breakdown consistent with the socket mean
+        let window = aggregate(&[metrics.clone(), metrics]);
+        let mean_sum: u64 = window
+            .mean
+            .channels
+            .values()
+            .map(|c| c.read_bandwidth)
+            .sum();
+        assert_eq!(window.mean.read_bandwidth, mean_sum);
+    }
+
+    #[test]
+    fn test_pmem_event_encoding() {
+        // CAS_COUNT.RD: event 0x04, umask 0x03
+        assert_eq!(
+            counter_control(IMC_CAS_COUNT_RD, IMC_CAS_COUNT_RD_UMASK),
+            0x40_0304
+        );
+        assert_eq!(
+            queue_counter_controls(QueueCounterMode::Occupancy),
+            [0x40_0080, 0x40_0081]
+        );
+        // PMM_RPQ_INSERTS (0xE3) and PMM_WPQ_INSERTS (0xE7)
+        assert_eq!(
+            queue_counter_controls(QueueCounterMode::Pmem),
+            [0x40_00E3, 0x40_00E7]
+        );
+    }
+
+    #[test]
+    fn test_cas_controls_pair_with_cas_counters() {
+        let controls = channel_controls(QueueCounterMode::Occupancy);
+        // CAS reads (umask 0x03) are programmed at 0x0D8 and read from 0x0A0
+        assert_eq!(controls[CAS_READ_COUNTER], 0x40_0304);
+        assert_eq!(imc::pci::IMC_CTL[CAS_READ_COUNTER], 0x0D8);
+        assert_eq!(imc::pci::IMC_CTR[CAS_READ_COUNTER], 0x0A0);
+        // CAS writes (umask 0x0C) at 0x0DC and 0x0A8
+        assert_eq!(controls[CAS_WRITE_COUNTER], 0x40_0C04);
+        assert_eq!(imc::pci::IMC_CTL[CAS_WRITE_COUNTER], 0x0DC);
+        assert_eq!(imc::pci::IMC_CTR[CAS_WRITE_COUNTER], 0x0A8);
+        assert_eq!(
+            [controls[QUEUE_COUNTERS[0]], controls[QUEUE_COUNTERS[1]]],
+            queue_counter_controls(QueueCounterMode::Occupancy)
+        );
+    }
+
+    #[test]
+    fn test_page_event_encoding() {
+        // ACT.COUNT (0x01, umask RD|WR|BYP 0x0B) and PRE_COUNT.PAGE_MISS (0x02, umask 0x01)
+        assert_eq!(
+            queue_counter_controls(QueueCounterMode::PageEvents),
+            [0x40_0B01, 0x40_0102]
+        );
+    }
+
+    #[test]
+    fn test_page_and_write_read_ratios() {
+        let start = Instant::now();
+        let counters = |read_count, write_count, activates, page_miss_precharges| ImcCounters {
+            read_count,
+            write_count,
+            activates,
+            page_miss_precharges,
+            ..Default::default()
+        };
+        let before = snapshot_at(start, counters(0, 0, 0, 0));
+        // 800 CAS per channel: 300 rows opened, 100 of them closing another row first
+        let after = snapshot_at(start + Duration::from_secs(1), counters(600, 200, 300, 100));
+
+        let metrics = diff(&before, &after);
+        assert!((metrics.write_read_ratio - 200.0 / 600.0).abs() < 1e-9);
+        assert!((metrics.page_hit_ratio - 500.0 / 800.0).abs() < 1e-9);
+        assert!((metrics.page_miss_ratio - 200.0 / 800.0).abs() < 1e-9);
+        assert!((metrics.page_conflict_ratio - 100.0 / 800.0).abs() < 1e-9);
+        let total = metrics.page_hit_ratio + metrics.page_miss_ratio + metrics.page_conflict_ratio;
+        assert!((total - 1.0).abs() < 1e-9);
+
+        // Activates racing ahead of CAS within a window don't go negative
+        let after = snapshot_at(start + Duration::from_secs(1), counters(10, 0, 30, 40));
+        let metrics = diff(&before, &after);
+        assert_eq!(metrics.page_hit_ratio, 0.0);
+        assert_eq!(metrics.page_miss_ratio, 0.0);
+
+        // No traffic, no ratios
+        let metrics = diff(&before, &snapshot_at(after.taken_at, counters(0, 0, 0, 0)));
+        assert_eq!(metrics.write_read_ratio, 0.0);
+        assert_eq!(metrics.page_hit_ratio, 0.0);
+    }
+
+    #[test]
+    fn test_thermal_throttle_cycles() {
+        // RPQ occupancy stays, POWER_THROTTLE_CYCLES (0x41, all ranks) replaces WPQ occupancy
+        assert_eq!(
+            queue_counter_controls(QueueCounterMode::Thermal),
+            [0x40_0080, 0x40_FF41]
+        );
+
+        let start = Instant::now();
+        let throttled = |throttle_cycles| ImcCounters {
+            throttle_cycles,
+            ..Default::default()
+        };
+        let before = snapshot_at(start, throttled(0));
+        let after = snapshot_at(start + Duration::from_secs(2), throttled(3_000_000));
+        // 2 channels * 3M cycles over 2 s
+        assert_eq!(diff(&before, &after).thermal_throttle_cycles, 3_000_000);
+    }
+
+    #[test]
+    fn test_dimm_temperature_decode() {
+        assert_eq!(dimm_temperature(0x0000_002D), Some(45.0));
+        // Only bits 7:0 are the temperature
+        assert_eq!(dimm_temperature(0x8000_1A3C), Some(60.0));
+        // Empty slot, absent function
+        assert_eq!(dimm_temperature(0), None);
+        assert_eq!(dimm_temperature(u32::MAX), None);
+
+        let [dimm0, dimm1, dimm2] = imc::pci::DIMMTEMPSTAT;
+        let temperatures = read_dimm_temperatures(&[0, 1, 3], |channel, offset| match channel {
+            // Two populated slots
+            0 if offset == dimm0 => Ok(41),
+            0 if offset == dimm1 => Ok(43),
+            0 if offset == dimm2 => Ok(0),
+            // Function hidden by the BIOS
+            1 => Ok(u32::MAX),
+            _ => Err(crate::error::UncflowError::InvalidConfiguration(
+                "no such device".to_string(),
+            )),
+        });
+        assert_eq!(
+            temperatures,
+            BTreeMap::from([((0, 0), 41.0), ((0, 1), 43.0)])
+        );
+    }
+
+    #[test]
+    fn test_pmem_presence_gating() {
+        let dir = std::env::temp_dir().join(format!("uncflow-nd-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("region0")).unwrap();
+
+        // libnvdimm loaded but no DIMMs
+        assert!(!pmem_present(CpuArchitecture::CascadeLake, &dir));
+        // No libnvdimm at all
+        assert!(!pmem_present(
+            CpuArchitecture::CascadeLake,
+            &dir.join("missing")
+        ));
+
+        std::fs::create_dir_all(dir.join("nmem0")).unwrap();
+        assert!(pmem_present(CpuArchitecture::CascadeLake, &dir));
+        // Skylake-SP has no DDR-T support
+        assert!(!pmem_present(CpuArchitecture::Skylake, &dir));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_pmem_bandwidth() {
+        let start = Instant::now();
+        let counters = |read_count, pmem_read_count, pmem_write_count| ImcCounters {
+            read_count,
+            pmem_read_count,
+            pmem_write_count,
+            ..Default::default()
+        };
+        let before = snapshot_at(start, counters(0, 0, 0));
+        let after = snapshot_at(start + Duration::from_secs(2), counters(1_000, 3_000, 500));
+
+        // Two channels, two seconds; DDR and PMM traffic stay separate
+        let metrics = diff(&before, &after);
+        assert_eq!(metrics.read_bandwidth, 1_000 * CACHE_LINE_SIZE);
+        assert_eq!(metrics.pmem_read_bandwidth, 3_000 * CACHE_LINE_SIZE);
+        assert_eq!(metrics.pmem_write_bandwidth, 500 * CACHE_LINE_SIZE);
+
+        // Without PMEM the fields stay at zero
+        let metrics = diff(&before, &snapshot_at(after.taken_at, counters(1_000, 0, 0)));
+        assert_eq!(metrics.pmem_read_bandwidth, 0);
+    }
+
+    #[test]
+    fn test_diff_empty_window() {
+        let snap = snapshot_at(Instant::now(), ImcCounters::default());
+        assert_eq!(diff(&snap, &snap).read_bandwidth, 0);
+    }
+
+    #[test]
+    fn test_diff_short_window_is_not_a_spike() {
+        let start = Instant::now();
+        let before = snapshot_at(start, ImcCounters::default());
+        let after = snapshot_at(
+            start + Duration::from_micros(100),
+            ImcCounters {
+                read_count: 1_000_000,
+                ..Default::default()
+            },
+        );
+        assert_eq!(diff(&before, &after).read_bandwidth, 0);
+    }
+
+    #[test]
+    fn test_bandwidth_over_late_collection() {
+        let start = Instant::now();
+        let reads = |read_count| ImcCounters {
+            read_count,
+            ..Default::default()
+        };
+        // A collection that ran 300 ms late
+        let metrics = diff(
+            &snapshot_at(start, reads(0)),
+            &snapshot_at(start + Duration::from_millis(1300), reads(1_300_000)),
+        );
+        // 2 channels * 1.3M reads * 64 B over 1.3 s, not over 1 s
+        assert_eq!(metrics.read_bandwidth, 128_000_000);
+        assert_eq!(metrics.channels[&0].read_bandwidth, 64_000_000);
+    }
+
+    #[test]
+    fn test_raw_deltas_are_derivation_input() {
+        let start = Instant::now();
+        let counters = |read_count, cycles| ImcCounters {
+            read_count,
+            cycles,
+            ..Default::default()
+        };
+        let before = snapshot_at(start, counters(1_000, 0));
+        let after = snapshot_at(
+            start + Duration::from_secs(2),
+            counters(5_000, 4_000_000_000),
+        );
+
+        // Two channels, two seconds
+        let metrics = diff(&before, &after);
+        assert_eq!(metrics.deltas.len(), 2);
+        let reads: u64 = metrics.deltas.values().map(|d| d.read_count).sum();
+        let cycles: u64 = metrics.deltas.values().map(|d| d.cycles).sum();
+        assert_eq!(reads, 8_000);
+        assert_eq!(metrics.read_bandwidth, reads * CACHE_LINE_SIZE / 2);
+        assert!((metrics.frequency - cycles as f64 / 2.0 / 2.0 / 1e9).abs() < 1e-9);
+
+        // Raw deltas add up over a window instead of being averaged
+        let window = aggregate(&[metrics.clone(), metrics]);
+        assert_eq!(window.mean.deltas[&0].read_count, 8_000);
+    }
+}
@@ -0,0 +1,205 @@
+// Theoretical peak memory bandwidth, the denominator of the IMC saturation ratio
+//
+// DDR moves 8 bytes per transfer and channel, so a socket peaks at
+// speed (MT/s) * 8 B * channels. The speed is taken from the SMBIOS Memory Device
+// (type 17) entries the kernel exposes under /sys/firmware/dmi/entries.
+//
+// The measured data rate comes from the DCLK fixed counter instead. On Skylake-SP
+// DCLK runs at the DDR clock and data moves on both of its edges, so the data rate
+// in MT/s is twice the DCLK frequency in MHz: DDR4-2666 shows a 1333 MHz DCLK.
+// Every memory type the IMC drives (DDR4, and DDR-T for Optane DC PMM on the same
+// channel clock) is double data rate, so the multiplier is always 2.
+
+use std::path::Path;
+
+use crate::common::sys_roots;
+
+// Bytes moved per transfer on a 64-bit DDR channel
+const BYTES_PER_TRANSFER: f64 = 8.0;
+
+// Transfers per DCLK cycle of a double data rate channel
+const TRANSFERS_PER_DCLK: f64 = 2.0;
+
+// Relative difference between measured and DMI data rate that is worth a warning
+const DATA_RATE_TOLERANCE: f64 = 0.05;
+
+const SMBIOS_MEMORY_DEVICE: u8 = 17;
+
+// Offsets into a type 17 structure (SMBIOS 3.x)
+const SIZE_OFFSET: usize = 0x0C;
+const SPEED_OFFSET: usize = 0x15;
+const CONFIGURED_SPEED_OFFSET: usize = 0x20;
+const EXTENDED_SPEED_OFFSET: usize = 0x54;
+const CONFIGURED_EXTENDED_SPEED_OFFSET: usize = 0x58;
+
+/// Peak bandwidth in bytes/s of `channels` channels running at `speed_mts` MT/s
+pub fn peak_bandwidth(speed_mts: u32, channels: usize) -> f64 {
+    speed_mts as f64 * 1e6 * BYTES_PER_TRANSFER * channels as f64
+}
+
+/// Fraction of `peak` (bytes/s) used by `bandwidth` (bytes/s)
+pub fn saturation_ratio(bandwidth: u64, peak: f64) -> f64 {
+    if peak > 0.0 {
+        bandwidth as f64 / peak
+    } else {
+        0.0
+    }
+}
+
+/// Data rate in MT/s of a channel whose DCLK runs at `dclk_ghz`
+pub fn data_rate_mts(dclk_ghz: f64) -> f64 {
+    dclk_ghz * 1e3 * TRANSFERS_PER_DCLK
+}
+
+/// Whether `measured_mts` is more than 5% off the DMI speed `configured_mts`
+pub fn data_rate_mismatch(measured_mts: f64, configured_mts: u32) -> bool {
+    configured_mts > 0
+        && (measured_mts - configured_mts as f64).abs()
+            > configured_mts as f64 * DATA_RATE_TOLERANCE
+}
+
+/// Speed in MT/s of one raw SMBIOS Memory Device structure, `None` for empty
+/// slots and unknown speeds
+///
+/// The configured speed is preferred over the rated one, since DIMMs often run
+/// slower than they are rated for.
+pub fn parse_memory_device(raw: &[u8]) -> Option<u32> {
+    let length = *raw.get(1)? as usize;
+    if *raw.first()? != SMBIOS_MEMORY_DEVICE || length > raw.len() {
+        return None;
+    }
+    let formatted = &raw[..length];
+    let word = |offset: usize| -> Option<u16> {
+        let bytes = formatted.get(offset..offset + 2)?;
+        Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+    };
+    let dword = |offset: usize| -> Option<u32> {
+        let bytes = formatted.get(offset..offset + 4)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+    // 0xFFFF defers to the 32-bit extended field added in SMBIOS 3.3
+    let speed = |offset: usize, extended_offset: usize| match word(offset)? {
+        0 => None,
+        0xFFFF => dword(extended_offset).filter(|&s| s != 0),
+        s => Some(s as u32),
+    };
+
+    // Size 0 means no DIMM is installed in the slot
+    if word(SIZE_OFFSET)? == 0 {
+        return None;
+    }
+    speed(CONFIGURED_SPEED_OFFSET, CONFIGURED_EXTENDED_SPEED_OFFSET)
+        .or_else(|| speed(SPEED_OFFSET, EXTENDED_SPEED_OFFSET))
+}
+
+/// Slowest populated DIMM speed in MT/s found in the DMI entries under `dir`
+///
+/// Channels run at the speed of the slowest DIMM, so that bounds the peak.
+pub fn memory_speed_from(dir: &Path) -> Option<u32> {
+    std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.starts_with("17-"))
+        })
+        .filter_map(|entry| std::fs::read(entry.path().join("raw")).ok())
+        .filter_map(|raw| parse_memory_device(&raw))
+        .min()
+}
+
+/// Slowest populated DIMM speed of this system, if DMI is readable
+pub fn detect_memory_speed() -> Option<u32> {
+    memory_speed_from(&sys_roots().dmi_entries_dir())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory_device(size: u16, speed: u16, configured_speed: u16) -> Vec<u8> {
+        let mut raw = vec![0u8; 0x28];
+        raw[0] = SMBIOS_MEMORY_DEVICE;
+        raw[1] = 0x28;
+        raw[SIZE_OFFSET..SIZE_OFFSET + 2].copy_from_slice(&size.to_le_bytes());
+        raw[SPEED_OFFSET..SPEED_OFFSET + 2].copy_from_slice(&speed.to_le_bytes());
+        raw[CONFIGURED_SPEED_OFFSET..CONFIGURED_SPEED_OFFSET + 2]
+            .copy_from_slice(&configured_speed.to_le_bytes());
+        // Unformatted string section
+        raw.extend_from_slice(b"DIMM_A1\0\0");
+        raw
+    }
+
+    #[test]
+    fn test_peak_from_speed() {
+        // DDR4-2666 on the six Skylake-SP channels
+        assert_eq!(peak_bandwidth(2666, 6), 127_968_000_000.0);
+        assert_eq!(peak_bandwidth(3200, 1), 25_600_000_000.0);
+        assert_eq!(peak_bandwidth(2933, 0), 0.0);
+    }
+
+    #[test]
+    fn test_saturation_ratio() {
+        let peak = peak_bandwidth(2666, 6);
+        assert!((saturation_ratio(63_984_000_000, peak) - 0.5).abs() < 1e-12);
+        assert_eq!(saturation_ratio(0, peak), 0.0);
+        // No known peak
+        assert_eq!(saturation_ratio(1_000, 0.0), 0.0);
+    }
+
+    #[test]
+    fn test_data_rate_from_dclk() {
+        // DDR4-2666 runs DCLK at 1333 MHz
+        assert!((data_rate_mts(1.333) - 2666.0).abs() < 1e-9);
+        assert_eq!(data_rate_mts(1.6), 3200.0);
+        assert_eq!(data_rate_mts(0.0), 0.0);
+
+        assert!(!data_rate_mismatch(data_rate_mts(1.333), 2666));
+        // DIMMs rated 2933 but running at 2666
+        assert!(data_rate_mismatch(data_rate_mts(1.333), 2933));
+        // Unknown DMI speed
+        assert!(!data_rate_mismatch(2666.0, 0));
+    }
+
+    #[test]
+    fn test_parse_memory_device() {
+        // Rated 2933, configured down to 2666
+        assert_eq!(
+            parse_memory_device(&memory_device(0x4000, 2933, 2666)),
+            Some(2666)
+        );
+        // Unknown configured speed falls back to the rated one
+        assert_eq!(
+            parse_memory_device(&memory_device(0x4000, 2400, 0)),
+            Some(2400)
+        );
+        // Empty slot
+        assert_eq!(parse_memory_device(&memory_device(0, 2666, 2666)), None);
+        assert_eq!(parse_memory_device(&memory_device(0x4000, 0, 0)), None);
+
+        // Not a memory device
+        let mut other = memory_device(0x4000, 2666, 2666);
+        other[0] = 16;
+        assert_eq!(parse_memory_device(&other), None);
+
+        // SMBIOS 2.3 structure without the configured speed field
+        let mut short = memory_device(0x4000, 2133, 2666);
+        short[1] = 0x1B;
+        assert_eq!(parse_memory_device(&short), Some(2133));
+        assert_eq!(parse_memory_device(&short[..0x10]), None);
+    }
+
+    #[test]
+    fn test_extended_speed() {
+        let mut raw = memory_device(0x4000, 0xFFFF, 0xFFFF);
+        raw.resize(0x5C, 0);
+        raw[1] = 0x5C;
+        raw[EXTENDED_SPEED_OFFSET..EXTENDED_SPEED_OFFSET + 4]
+            .copy_from_slice(&70_000u32.to_le_bytes());
+        raw[CONFIGURED_EXTENDED_SPEED_OFFSET..CONFIGURED_EXTENDED_SPEED_OFFSET + 4]
+            .copy_from_slice(&68_000u32.to_le_bytes());
+        assert_eq!(parse_memory_device(&raw), Some(68_000));
+    }
+}
@@ -1,3 +1,3 @@
 pub mod monitor;
 
-pub use monitor::IrpMonitor;
+pub use monitor::{IrpMetrics, IrpMonitor};
@@ -1,32 +1,33 @@
 // IRP (IO Request Processing) Monitor
-
-use crate::common::{arch::CPU_ARCH, msr, pci};
+//
+// Skylake has one IRP unit per IIO stack. Each event pair is read from every unit;
+// the socket metrics are derived from the counts summed over the units, and each
+// unit's own counts give its per-unit metrics, so an imbalance between stacks is
+// visible without a second measurement.
+//
+// The event pairs take turns on the counters: each pass counts for at least
+// EVENT_WINDOW from the collect that programmed it, and the first collect after
+// that reads it and programs the next. Metrics of the passes not read in a collect
+// keep their last values. Each pass counts from a baseline read right after it is
+// programmed, so counts never rely on the unit reset clearing the counters.
+
+use crate::common::counter::{rate_window_secs, wrapping_delta, Baseline};
+use crate::common::{
+    arch::CPU_ARCH, msr, pci, pmon, read_stats, register, EventScheduler, ReadCounter,
+};
 use crate::error::{Result, UncflowError};
 use crate::metrics::irp::IrpMetric;
 use std::collections::HashMap;
 use std::time::{Duration, Instant};
+use uncflow_raw::arch::{broadwell, haswell};
+use uncflow_raw::current_arch::irp::{self, IrpCounterControl};
+use uncflow_raw::RegisterLayout;
 
-// Skylake IRP MSR addresses (3 IRP units per socket)
-const IRP_UNIT_CTRL: [u64; 3] = [0x0A78, 0x0A98, 0x0AB8];
-const _IRP_UNIT_STATUS: [u64; 3] = [0x0A7F, 0x0A9F, 0x0ABF];
-const IRP_CTR0: [u64; 3] = [0x0A79, 0x0A99, 0x0AB9];
-const IRP_CTR1: [u64; 3] = [0x0A7A, 0x0A9A, 0x0ABA];
-const IRP_CTRL0: [u64; 3] = [0x0A7B, 0x0A9B, 0x0ABB];
-const IRP_CTRL1: [u64; 3] = [0x0A7C, 0x0A9C, 0x0ABC];
-
-// Haswell/Broadwell IRP PCI addresses
-const IRP_DEVICE: u32 = 5;
-const IRP_FUNCTION: u32 = 6;
-const IRP_DEVICE_ID: u32 = 0x6F39;
-const IRP_UNIT_STATUS_ADDR: u32 = 0xF8;
-const IRP_UNIT_CTL_ADDR: u32 = 0xF4;
-const IRP_CTR_ADDR: [u32; 4] = [0xA0, 0xB0, 0xB8, 0xC0];
-const IRP_CTL_ADDR: [u32; 4] = [0xD8, 0xDC, 0xE0, 0xE4];
-
-const UNCORE_COUNTER_WIDTH: u64 = 48;
-const IRP_PCI_COUNTER_WIDTH: u32 = 32;
 const CACHELINE_SIZE: u64 = 64;
 
+/// How long each pass of event pairs counts at least before the next takes over
+pub const EVENT_WINDOW: Duration = Duration::from_secs(1);
+
 // IRP Event configurations
 #[derive(Debug, Clone)]
 struct IrpEventConfig {
@@ -37,6 +38,16 @@ struct IrpEventConfig {
     umask1: u8,
 }
 
+impl IrpEventConfig {
+    /// Counter controls for the event pair
+    fn controls(&self) -> [IrpCounterControl; 2] {
+        [
+            IrpCounterControl::counting(self.event0, self.umask0),
+            IrpCounterControl::counting(self.event1, self.umask1),
+        ]
+    }
+}
+
 const IRP_EVENTS: &[IrpEventConfig] = &[
     IrpEventConfig {
         name: "All",
@@ -89,6 +100,16 @@ const IRP_EVENTS: &[IrpEventConfig] = &[
     },
 ];
 
+// Event pairs counted together: the first on counters 0/1, the second, on the
+// 4-counter PCI units only, on counters 2/3
+type IrpPass = (&'static IrpEventConfig, Option<&'static IrpEventConfig>);
+
+// Measurement passes of the 4-counter PCI units: two events per pass, the last
+// one alone when the count is odd
+fn pci_passes(events: &'static [IrpEventConfig]) -> impl Iterator<Item = IrpPass> {
+    events.chunks(2).map(|pass| (&pass[0], pass.get(1)))
+}
+
 // MSR-based IRP counter unit (Skylake)
 #[derive(Debug)]
 struct IrpMsrCounterUnit {
@@ -101,128 +122,92 @@ impl IrpMsrCounterUnit {
         Ok(Self { core, index })
     }
 
-    fn freeze_and_reset(&self) -> Result<()> {
-        let ctrl_addr = IRP_UNIT_CTRL[self.index];
-        msr::write(self.core, ctrl_addr, 0x100)?; // Freeze
-        msr::write(self.core, ctrl_addr, 0x102)?; // Reset
-        Ok(())
-    }
-
-    fn unfreeze(&self) -> Result<()> {
-        let ctrl_addr = IRP_UNIT_CTRL[self.index];
-        msr::write(self.core, ctrl_addr, 0)?;
-        Ok(())
-    }
-
     fn program(&self, config: &IrpEventConfig) -> Result<()> {
-        self.freeze_and_reset()?;
-
-        let ctrl0_value = ((config.umask0 as u64) << 8) | (config.event0 as u64) | (1 << 22);
-        msr::write(self.core, IRP_CTRL0[self.index], ctrl0_value)?;
-
-        let ctrl1_value = ((config.umask1 as u64) << 8) | (config.event1 as u64) | (1 << 22);
-        msr::write(self.core, IRP_CTRL1[self.index], ctrl1_value)?;
-
-        self.unfreeze()?;
-        Ok(())
+        let write_box_ctl =
+            |value| msr::write(self.core, irp::msr::IRP_UNIT_CTRL[self.index], value);
+        pmon::program_frozen(write_box_ctl, || {
+            let [ctrl0, ctrl1] = config.controls();
+            msr::write(
+                self.core,
+                irp::msr::IRP_CTRL0[self.index],
+                ctrl0.to_msr_value(),
+            )?;
+            msr::write(
+                self.core,
+                irp::msr::IRP_CTRL1[self.index],
+                ctrl1.to_msr_value(),
+            )
+        })
     }
 
-    fn read_counters(&self) -> Result<[u64; 2]> {
-        let ctr0 = msr::read(self.core, IRP_CTR0[self.index])?;
-        let ctr1 = msr::read(self.core, IRP_CTR1[self.index])?;
-        let mask = (1u64 << UNCORE_COUNTER_WIDTH) - 1;
-        Ok([ctr0 & mask, ctr1 & mask])
+    fn read_counters(&self, reads: &ReadCounter) -> Result<[u64; 2]> {
+        let ctr0 = reads.observe(msr::read(self.core, irp::msr::IRP_CTR0[self.index]))?;
+        let ctr1 = reads.observe(msr::read(self.core, irp::msr::IRP_CTR1[self.index]))?;
+        Ok([ctr0 & irp::COUNTER_MASK, ctr1 & irp::COUNTER_MASK])
     }
 }
 
 // PCI-based IRP counter unit (Haswell/Broadwell)
+// Broadwell-EP shares the Haswell-EP register offsets, only the device ID differs
 #[derive(Debug)]
 struct IrpPciCounterUnit {
     pci_addr: pci::PciConfigAddress,
 }
 
 impl IrpPciCounterUnit {
-    fn new(socket: u32) -> Result<Self> {
+    fn new(socket: u32, device_id: u32) -> Result<Self> {
         let pci_addr = pci::PciConfigAddress {
             socket,
-            device: IRP_DEVICE,
-            function: IRP_FUNCTION,
-            device_id: IRP_DEVICE_ID,
+            device: haswell::irp::IRP_DEVICE,
+            function: haswell::irp::IRP_FUNCTION,
+            device_id,
         };
 
-        // Verify the device exists
-        let pci_inst = pci::Pci::instance();
-        let vendor_device = pci_inst.read32(&pci_addr, 0)?;
-        let vendor = vendor_device & 0xFFFF;
-        let device = (vendor_device >> 16) & 0xFFFF;
-
-        if vendor != 0x8086 {
-            return Err(UncflowError::PciError(format!(
-                "IRP device not found for socket {socket}: invalid vendor {vendor:04X}"
-            )));
-        }
-
-        if device != IRP_DEVICE_ID {
-            return Err(UncflowError::PciError(format!(
-                "IRP device ID mismatch for socket {socket}: expected {IRP_DEVICE_ID:04X}, got {device:04X}"
-            )));
-        }
+        pci::Pci::instance().probe(&pci_addr, "IRP device")?;
 
         Ok(Self { pci_addr })
     }
 
-    fn freeze_and_reset(&self) -> Result<()> {
-        let pci = pci::Pci::instance();
-        pci.write32(&self.pci_addr, IRP_UNIT_CTL_ADDR, 0x100)?; // Freeze
-        pci.write32(&self.pci_addr, IRP_UNIT_CTL_ADDR, 0x102)?; // Reset
-        Ok(())
-    }
-
-    fn unfreeze(&self) -> Result<()> {
+    fn program(&self, config0: &IrpEventConfig, config1: Option<&IrpEventConfig>) -> Result<()> {
         let pci = pci::Pci::instance();
-        pci.write32(&self.pci_addr, IRP_UNIT_CTL_ADDR, 0)?;
-        Ok(())
-    }
-
-    fn program(&self, config0: &IrpEventConfig, config1: &IrpEventConfig) -> Result<()> {
-        self.freeze_and_reset()?;
-
-        let pci = pci::Pci::instance();
-
-        // Program counter 0 with config0.event0
-        let ctrl00_value = ((config0.umask0 as u32) << 8) | (config0.event0 as u32) | (1 << 22);
-        pci.write32(&self.pci_addr, IRP_CTL_ADDR[0], ctrl00_value)?;
-
-        // Program counter 1 with config0.event1
-        let ctrl01_value = ((config0.umask1 as u32) << 8) | (config0.event1 as u32) | (1 << 22);
-        pci.write32(&self.pci_addr, IRP_CTL_ADDR[1], ctrl01_value)?;
-
-        // Program counter 2 with config1.event0
-        let ctrl10_value = ((config1.umask0 as u32) << 8) | (config1.event0 as u32) | (1 << 22);
-        pci.write32(&self.pci_addr, IRP_CTL_ADDR[2], ctrl10_value)?;
-
-        // Program counter 3 with config1.event1
-        let ctrl11_value = ((config1.umask1 as u32) << 8) | (config1.event1 as u32) | (1 << 22);
-        pci.write32(&self.pci_addr, IRP_CTL_ADDR[3], ctrl11_value)?;
-
-        self.unfreeze()?;
-        Ok(())
+        let write_box_ctl = |value| {
+            pci.write32(
+                &self.pci_addr,
+                haswell::irp::pci::IRP_UNIT_CTL_ADDR,
+                value as u32,
+            )
+        };
+        pmon::program_frozen(write_box_ctl, || {
+            // Counters 0/1 take config0's event pair, counters 2/3 take config1's or
+            // stay disabled in a pass measuring one event
+            let controls = config0
+                .controls()
+                .into_iter()
+                .chain(config1.map_or([IrpCounterControl::default(); 2], IrpEventConfig::controls));
+            for (ctl_addr, ctrl) in haswell::irp::pci::IRP_CTL_ADDR.iter().zip(controls) {
+                pci.write32(&self.pci_addr, *ctl_addr, ctrl.to_pci_value())?;
+            }
+            Ok(())
+        })
     }
 
-    fn read_counters(&self) -> Result<[u64; 4]> {
+    fn read_counters(&self, reads: &ReadCounter) -> Result<[u64; 4]> {
         let pci = pci::Pci::instance();
+        let read = |offset| reads.observe(pci.read32(&self.pci_addr, offset));
 
         // Check and clear overflow
-        let status = pci.read32(&self.pci_addr, IRP_UNIT_STATUS_ADDR)?;
+        let status_addr = haswell::irp::pci::IRP_UNIT_STATUS_ADDR;
+        let status = read(status_addr)?;
         if status & 0xF != 0 {
-            pci.write32(&self.pci_addr, IRP_UNIT_STATUS_ADDR, status & 0xF)?;
+            pci.write32(&self.pci_addr, status_addr, status & 0xF)?;
         }
 
-        let mask = (1u64 << IRP_PCI_COUNTER_WIDTH) - 1;
-        let ctr0 = (pci.read32(&self.pci_addr, IRP_CTR_ADDR[0])? as u64) & mask;
-        let ctr1 = (pci.read32(&self.pci_addr, IRP_CTR_ADDR[1])? as u64) & mask;
-        let ctr2 = (pci.read32(&self.pci_addr, IRP_CTR_ADDR[2])? as u64) & mask;
-        let ctr3 = (pci.read32(&self.pci_addr, IRP_CTR_ADDR[3])? as u64) & mask;
+        let ctr_addr = haswell::irp::pci::IRP_CTR_ADDR;
+        let mask = haswell::irp::COUNTER_MASK;
+        let ctr0 = (read(ctr_addr[0])? as u64) & mask;
+        let ctr1 = (read(ctr_addr[1])? as u64) & mask;
+        let ctr2 = (read(ctr_addr[2])? as u64) & mask;
+        let ctr3 = (read(ctr_addr[3])? as u64) & mask;
 
         Ok([ctr0, ctr1, ctr2, ctr3])
     }
@@ -236,48 +221,90 @@ enum IrpCounterUnit {
 }
 
 impl IrpCounterUnit {
-    fn program(&self, config: &IrpEventConfig) -> Result<()> {
+    fn program(&self, (config0, config1): IrpPass) -> Result<()> {
         match self {
-            IrpCounterUnit::Msr(unit) => unit.program(config),
-            IrpCounterUnit::Pci(_) => {
-                // PCI units need two configs, so we'll handle this differently
-                Ok(())
-            }
+            // MSR units count one event pair, their passes have no second one
+            IrpCounterUnit::Msr(unit) => unit.program(config0),
+            IrpCounterUnit::Pci(unit) => unit.program(config0, config1),
         }
     }
 
-    fn program_pci_pair(&self, config0: &IrpEventConfig, config1: &IrpEventConfig) -> Result<()> {
+    // Bits after which the unit's counters wrap
+    fn width_bits(&self) -> u64 {
         match self {
-            IrpCounterUnit::Pci(unit) => unit.program(config0, config1),
-            IrpCounterUnit::Msr(_) => Ok(()),
+            IrpCounterUnit::Msr(_) => irp::COUNTER_WIDTH_BITS,
+            IrpCounterUnit::Pci(_) => haswell::irp::COUNTER_WIDTH_BITS,
         }
     }
 
-    fn read_counters(&self) -> Result<Vec<u64>> {
+    fn read_counters(&self, reads: &ReadCounter) -> Result<Vec<u64>> {
         match self {
             IrpCounterUnit::Msr(unit) => {
-                let values = unit.read_counters()?;
+                let values = unit.read_counters(reads)?;
                 Ok(vec![values[0], values[1]])
             }
             IrpCounterUnit::Pci(unit) => {
-                let values = unit.read_counters()?;
+                let values = unit.read_counters(reads)?;
                 Ok(vec![values[0], values[1], values[2], values[3]])
             }
         }
     }
 }
 
+/// Metrics of one collection
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IrpMetrics {
+    /// Metrics of the counts summed over all units
+    pub total: HashMap<IrpMetric, f64>,
+    /// Metrics of each unit's own counts, indexed by IRP unit
+    pub units: Vec<HashMap<IrpMetric, f64>>,
+}
+
+impl IrpMetrics {
+    fn with_units(units: usize) -> Self {
+        Self {
+            total: HashMap::new(),
+            units: vec![HashMap::new(); units],
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct IrpMonitor {
     socket: i32,
+    core: u32,
     units: Vec<IrpCounterUnit>,
+    scheduler: EventScheduler<IrpPass>,
+    // Whether the scheduler's current pass is programmed and counting
+    loaded: bool,
+    // Counters of every unit when the current pass started
+    baseline: Baseline<Vec<Vec<u64>>>,
+    // Bits after which the units' counters wrap
+    counter_width: u64,
     event_results: HashMap<String, [u64; 2]>,
-    measure_start: Option<Instant>,
-    measure_duration: Duration,
+    unit_results: Vec<HashMap<String, [u64; 2]>>,
+    // Latest metrics of every pass
+    metrics: IrpMetrics,
+    // Counter reads and failed reads of this socket
+    reads: ReadCounter,
 }
 
 impl IrpMonitor {
     pub fn new(socket: i32) -> Result<Self> {
+        Self::with_core(socket, Self::default_core(socket))
+    }
+
+    /// Core the IRP MSRs of `socket` are accessed from unless overridden
+    pub fn default_core(socket: i32) -> u32 {
+        (socket as u32) * 16
+    }
+
+    /// Create a monitor accessing the IRP MSRs from `core`, which must be on `socket`
+    ///
+    /// Haswell and Broadwell IRP counters live in PCI config space and ignore `core`.
+    pub fn with_core(socket: i32, core: u32) -> Result<Self> {
+        Self::validate_program()?;
+
         let arch = *CPU_ARCH;
         let mut units = Vec::new();
 
@@ -286,15 +313,23 @@ impl IrpMonitor {
             | crate::common::arch::CpuArchitecture::CascadeLake
             | crate::common::arch::CpuArchitecture::IceLake => {
                 // MSR-based counters for Skylake and newer
-                let core = (socket as u32) * 16;
-                for i in 0..3 {
+                msr::ensure_uncore_unfrozen(msr::Msr::instance(), core, "IRP")?;
+                for i in 0..irp::IRP_UNIT_COUNT {
                     units.push(IrpCounterUnit::Msr(IrpMsrCounterUnit::new(core, i)?));
                 }
             }
-            crate::common::arch::CpuArchitecture::Haswell
-            | crate::common::arch::CpuArchitecture::Broadwell => {
+            crate::common::arch::CpuArchitecture::Haswell => {
                 // PCI-based counters for Haswell/Broadwell
-                units.push(IrpCounterUnit::Pci(IrpPciCounterUnit::new(socket as u32)?));
+                units.push(IrpCounterUnit::Pci(IrpPciCounterUnit::new(
+                    socket as u32,
+                    haswell::irp::IRP_DEVICE_ID,
+                )?));
+            }
+            crate::common::arch::CpuArchitecture::Broadwell => {
+                units.push(IrpCounterUnit::Pci(IrpPciCounterUnit::new(
+                    socket as u32,
+                    broadwell::irp::IRP_DEVICE_ID,
+                )?));
             }
             _ => {
                 return Err(UncflowError::UnsupportedArchitecture(format!(
@@ -303,110 +338,153 @@ impl IrpMonitor {
             }
         }
 
+        let passes = match units.first() {
+            Some(IrpCounterUnit::Pci(_)) => pci_passes(IRP_EVENTS).collect(),
+            _ => IRP_EVENTS.iter().map(|event| (event, None)).collect(),
+        };
+
         Ok(Self {
             socket,
+            core,
+            scheduler: EventScheduler::with_groups(passes, EVENT_WINDOW),
+            loaded: false,
+            baseline: Baseline::default(),
+            counter_width: units
+                .first()
+                .map_or(irp::COUNTER_WIDTH_BITS, |u| u.width_bits()),
+            unit_results: vec![HashMap::new(); units.len()],
+            metrics: IrpMetrics::with_units(units.len()),
             units,
             event_results: HashMap::new(),
-            measure_start: None,
-            measure_duration: Duration::from_secs(1),
+            reads: read_stats().counter("irp", socket),
         })
     }
 
-    pub fn collect_metrics(&mut self) -> Result<HashMap<IrpMetric, f64>> {
-        let mut metrics = HashMap::new();
-
-        match self.units.first() {
-            Some(IrpCounterUnit::Msr(_)) => {
-                // MSR mode: iterate through all event configurations
-                for event_config in IRP_EVENTS {
-                    for unit in &self.units {
-                        unit.program(event_config)?;
-                    }
-
-                    self.measure_start = Some(Instant::now());
-                    std::thread::sleep(self.measure_duration);
-
-                    let mut aggregated = [0u64, 0u64];
-                    for unit in &self.units {
-                        let values = unit.read_counters()?;
-                        aggregated[0] += values[0];
-                        aggregated[1] += values[1];
-                    }
-
-                    let elapsed = self.measure_start.unwrap().elapsed();
-                    self.event_results
-                        .insert(event_config.name.to_string(), aggregated);
-
-                    self.calculate_event_metrics(
-                        event_config.name,
-                        &aggregated,
-                        elapsed,
-                        &mut metrics,
-                    );
-                }
-            }
-            Some(IrpCounterUnit::Pci(_)) => {
-                // PCI mode: program in pairs (4 counters at once)
-                for i in (0..IRP_EVENTS.len()).step_by(2) {
-                    if i + 1 < IRP_EVENTS.len() {
-                        let config0 = &IRP_EVENTS[i];
-                        let config1 = &IRP_EVENTS[i + 1];
-
-                        for unit in &self.units {
-                            unit.program_pci_pair(config0, config1)?;
-                        }
-
-                        self.measure_start = Some(Instant::now());
-                        std::thread::sleep(self.measure_duration);
-
-                        for unit in &self.units {
-                            let values = unit.read_counters()?;
-                            let elapsed = self.measure_start.unwrap().elapsed();
-
-                            // First pair of counters (config0)
-                            let aggregated0 = [values[0], values[1]];
-                            self.event_results
-                                .insert(config0.name.to_string(), aggregated0);
-                            self.calculate_event_metrics(
-                                config0.name,
-                                &aggregated0,
-                                elapsed,
-                                &mut metrics,
-                            );
-
-                            // Second pair of counters (config1)
-                            let aggregated1 = [values[2], values[3]];
-                            self.event_results
-                                .insert(config1.name.to_string(), aggregated1);
-                            self.calculate_event_metrics(
-                                config1.name,
-                                &aggregated1,
-                                elapsed,
-                                &mut metrics,
-                            );
-                        }
-                    }
-                }
+    /// Build and validate the counter controls of every event pair without touching hardware
+    pub fn validate_program() -> Result<()> {
+        for config in IRP_EVENTS {
+            register::validate_all(&format!("IRP event {}", config.name), &config.controls())?;
+        }
+        Ok(())
+    }
+
+    pub fn collect_metrics(&mut self) -> Result<IrpMetrics> {
+        let Some(&pass) = self.scheduler.current().filter(|_| !self.units.is_empty()) else {
+            return Err(UncflowError::InvalidConfiguration(
+                "No IRP units available".to_string(),
+            ));
+        };
+
+        if !self.loaded {
+            // First collect or after a reset: start counting the current pass
+            self.program_units(pass)?;
+            self.scheduler.reset();
+            self.loaded = true;
+        } else if self.scheduler.should_rotate() {
+            let current = self.read_units()?;
+            self.close_pass(pass, current, Instant::now());
+
+            if let Some(&next) = self.scheduler.rotate() {
+                self.program_units(next)?;
             }
-            None => {
-                return Err(UncflowError::InvalidConfiguration(
-                    "No IRP units available".to_string(),
-                ));
+        }
+
+        Ok(self.metrics.clone())
+    }
+
+    // Program `pass` on every unit and read its baseline
+    fn program_units(&mut self, pass: IrpPass) -> Result<()> {
+        for unit in &self.units {
+            unit.program(pass)?;
+        }
+        self.baseline.reset();
+        let start = self.read_units()?;
+        self.baseline.advance(start, Instant::now());
+        Ok(())
+    }
+
+    fn read_units(&self) -> Result<Vec<Vec<u64>>> {
+        self.units
+            .iter()
+            .map(|unit| unit.read_counters(&self.reads))
+            .collect()
+    }
+
+    // Record the counts of `pass` between the baseline and `current`, read at
+    // `now`; without a baseline `current` only becomes one
+    fn close_pass(&mut self, pass: IrpPass, current: Vec<Vec<u64>>, now: Instant) {
+        let Some((start, elapsed)) = self.baseline.advance(current.clone(), now) else {
+            return;
+        };
+
+        // First pair of counters (pass.0), second pair (pass.1)
+        let width = self.counter_width;
+        let mut values0 = Vec::with_capacity(current.len());
+        let mut values1 = Vec::with_capacity(current.len());
+        for (start, current) in start.iter().zip(&current) {
+            let delta = |i: usize| wrapping_delta(start[i], current[i], width);
+            values0.push([delta(0), delta(1)]);
+            if current.len() == 4 {
+                values1.push([delta(2), delta(3)]);
             }
         }
 
-        Ok(metrics)
+        let (config0, config1) = pass;
+        self.record_event(config0.name, &values0, elapsed);
+        if let Some(config1) = config1 {
+            self.record_event(config1.name, &values1, elapsed);
+        }
+    }
+
+    /// Drop the cached counts and metrics; the current pass is reprogrammed, so
+    /// the next collect starts its window over
+    pub fn reset(&mut self) {
+        self.loaded = false;
+        self.baseline.reset();
+        self.event_results.clear();
+        self.unit_results = vec![HashMap::new(); self.units.len()];
+        self.metrics = IrpMetrics::with_units(self.units.len());
+    }
+
+    // Record one event pair's counts from every unit, deriving the summed metrics
+    // and each unit's own
+    fn record_event(&mut self, event_name: &str, unit_values: &[[u64; 2]], elapsed: Duration) {
+        let mut aggregated = [0u64, 0u64];
+        for values in unit_values {
+            aggregated[0] += values[0];
+            aggregated[1] += values[1];
+        }
+        self.event_results
+            .insert(event_name.to_string(), aggregated);
+        Self::calculate_event_metrics(
+            event_name,
+            &aggregated,
+            &self.event_results,
+            elapsed,
+            &mut self.metrics.total,
+        );
+
+        for ((values, results), unit_metrics) in unit_values
+            .iter()
+            .zip(&mut self.unit_results)
+            .zip(&mut self.metrics.units)
+        {
+            results.insert(event_name.to_string(), *values);
+            Self::calculate_event_metrics(event_name, values, results, elapsed, unit_metrics);
+        }
     }
 
     fn calculate_event_metrics(
-        &self,
         event_name: &str,
         values: &[u64; 2],
+        event_results: &HashMap<String, [u64; 2]>,
         duration: Duration,
         metrics: &mut HashMap<IrpMetric, f64>,
     ) {
-        let elapsed_ns = duration.as_nanos() as f64;
-        let elapsed_s = duration.as_secs_f64();
+        let Some(elapsed_s) = rate_window_secs(duration) else {
+            return;
+        };
+        let elapsed_ns = elapsed_s * 1e9;
 
         match event_name {
             "Clockticks" => {
@@ -415,14 +493,14 @@ impl IrpMonitor {
                 metrics.insert(IrpMetric::IRPFrequency, frequency);
 
                 // Calculate occupancy
-                if let Some(occupancy_values) = self.event_results.get("All") {
+                if let Some(occupancy_values) = event_results.get("All") {
                     let occupancy = occupancy_values[0] as f64 / ((frequency * 1e9) * elapsed_s);
                     metrics.insert(IrpMetric::IRPAnyOccupancy, occupancy);
                 }
             }
             "All" => {
                 // Calculate latency (requires Clockticks to be collected)
-                if let Some(clockticks) = self.event_results.get("Clockticks") {
+                if let Some(clockticks) = event_results.get("Clockticks") {
                     let latency = if values[1] > 0 {
                         (values[0] as f64) / (values[1] as f64)
                             * (clockticks[1] as f64 / elapsed_ns)
@@ -463,4 +541,102 @@ impl IrpMonitor {
     pub fn socket(&self) -> i32 {
         self.socket
     }
+
+    /// Core the IRP MSRs are accessed from
+    pub fn core(&self) -> u32 {
+        self.core
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Monitor without hardware units, fed counts through record_event
+    fn monitor_of(units: usize) -> IrpMonitor {
+        IrpMonitor {
+            socket: 0,
+            core: 0,
+            units: Vec::new(),
+            scheduler: EventScheduler::new(EVENT_WINDOW),
+            loaded: false,
+            baseline: Baseline::default(),
+            counter_width: irp::COUNTER_WIDTH_BITS,
+            event_results: HashMap::new(),
+            unit_results: vec![HashMap::new(); units],
+            metrics: IrpMetrics::with_units(units),
+            reads: read_stats().counter("irp", 0),
+        }
+    }
+
+    #[test]
+    fn test_summed_metrics_equal_total_of_units() {
+        let mut monitor = monitor_of(3);
+        let elapsed = Duration::from_secs(1);
+        monitor.record_event(
+            "PCIeRead",
+            &[[0, 1_000_000], [0, 3_000_000], [0, 0]],
+            elapsed,
+        );
+        monitor.record_event("Clockticks", &[[0, 500_000_000]; 3], elapsed);
+        let metrics = &monitor.metrics;
+
+        for metric in [IrpMetric::IRPPCIeReadBandwidth, IrpMetric::IRPFrequency] {
+            let per_unit: f64 = metrics.units.iter().map(|unit| unit[&metric]).sum();
+            assert!(
+                (metrics.total[&metric] - per_unit).abs() < 1e-9,
+                "{metric:?}"
+            );
+        }
+        // The imbalance the sum hides
+        assert_eq!(metrics.units[1][&IrpMetric::IRPPCIeReadBandwidth], 0.192);
+        assert_eq!(metrics.units[2][&IrpMetric::IRPPCIeReadBandwidth], 0.0);
+    }
+
+    #[test]
+    fn test_pass_counts_from_its_baseline() {
+        let mut monitor = monitor_of(1);
+        let pass = (&IRP_EVENTS[2], None);
+        assert_eq!(pass.0.name, "PCIeRead");
+
+        // Counters not cleared by the unit reset are not counted as one window
+        let start = Instant::now();
+        monitor.close_pass(pass, vec![vec![0, 7_000_000]], start);
+        assert!(monitor.metrics.total.is_empty());
+
+        let later = start + Duration::from_secs(1);
+        monitor.close_pass(pass, vec![vec![0, 8_000_000]], later);
+        assert_eq!(
+            monitor.metrics.total[&IrpMetric::IRPPCIeReadBandwidth],
+            0.064
+        );
+    }
+
+    #[test]
+    fn test_odd_event_count_measures_every_event() {
+        let events = &IRP_EVENTS[2..5];
+        assert_eq!(events.len() % 2, 1);
+
+        let mut monitor = monitor_of(1);
+        let elapsed = Duration::from_secs(1);
+        let mut measured = Vec::new();
+        for (config0, config1) in pci_passes(events) {
+            for config in std::iter::once(config0).chain(config1) {
+                monitor.record_event(config.name, &[[0, 1_000]], elapsed);
+                measured.push(config.name);
+            }
+        }
+
+        assert_eq!(measured, ["PCIeRead", "RFO", "PCIItoM"]);
+        for metric in [
+            IrpMetric::IRPPCIeReadBandwidth,
+            IrpMetric::IRPRFOBandwidth,
+            IrpMetric::IRPPCIItoMBandwidth,
+        ] {
+            assert!(monitor.metrics.total.contains_key(&metric), "{metric:?}");
+        }
+        // Every event of the full list is measured too, CLFlush in a pass of its own
+        let last = pci_passes(IRP_EVENTS).last().unwrap();
+        assert_eq!((last.0.name, last.1.is_none()), ("CLFlush", true));
+    }
 }
@@ -0,0 +1,275 @@
+// M2M (Mesh-to-Memory) Monitor
+//
+// Counts directory and near-memory tag lookups, aggregated across the M2M units
+
+use crate::common::counter::{rate_window_secs, wrapping_delta};
+use crate::common::{pci, pmon, read_stats, register, ReadCounter};
+use crate::error::{Result, UncflowError};
+use crate::metrics::m2m::M2mMetric;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// Import hardware definitions from uncflow-raw
+use uncflow_raw::current_arch::m2m::{self, M2mCounterControl};
+use uncflow_raw::RegisterLayout;
+
+// Counter assignment: (event, umask) per counter
+const M2M_EVENTS: [(u8, u8); 4] = [
+    (m2m::events::DIRECTORY_HIT, m2m::umasks::DIRECTORY_ANY),
+    (m2m::events::DIRECTORY_MISS, m2m::umasks::DIRECTORY_ANY),
+    (m2m::events::TAG_HIT, m2m::umasks::TAG_HIT_ANY),
+    (m2m::events::DIRECTORY_LOOKUP, m2m::umasks::LOOKUP_STATE_A),
+];
+
+// Counter controls for M2M_EVENTS
+fn counter_controls() -> [M2mCounterControl; 4] {
+    M2M_EVENTS.map(|(event, umask)| M2mCounterControl {
+        event_select: event,
+        unit_mask: umask,
+        enable: true,
+        ..Default::default()
+    })
+}
+
+/// Counter deltas for one interval, summed across M2M units
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct M2mCounts {
+    pub directory_hit: u64,
+    pub directory_miss: u64,
+    pub tag_hit: u64,
+    pub lookup_state_a: u64,
+}
+
+impl M2mCounts {
+    /// Counts with the names they are exported under as raw deltas
+    pub fn named(&self) -> [(&'static str, u64); 4] {
+        [
+            ("directory_hit", self.directory_hit),
+            ("directory_miss", self.directory_miss),
+            ("tag_hit", self.tag_hit),
+            ("lookup_state_a", self.lookup_state_a),
+        ]
+    }
+}
+
+// PCI-based M2M counter unit
+#[derive(Debug)]
+struct M2mUnit {
+    index: usize,
+    pci_addr: pci::PciConfigAddress,
+}
+
+impl M2mUnit {
+    fn new(socket: u32, index: usize) -> Result<Self> {
+        let pci_addr = pci::PciConfigAddress {
+            socket,
+            device: m2m::pci::M2M_DEVICE[index],
+            function: m2m::pci::M2M_FUNCTION,
+            device_id: m2m::M2M_DEVICE_ID,
+        };
+
+        pci::Pci::instance().probe(&pci_addr, &format!("M2M unit {index}"))?;
+
+        Ok(Self { index, pci_addr })
+    }
+
+    fn program(&self) -> Result<()> {
+        let pci = pci::Pci::instance();
+        let write_box_ctl =
+            |value| pci.write32(&self.pci_addr, m2m::pci::M2M_UNIT_CTL_ADDR, value as u32);
+        pmon::program_frozen(write_box_ctl, || {
+            for (i, ctrl) in counter_controls().iter().enumerate() {
+                pci.write32(
+                    &self.pci_addr,
+                    m2m::pci::M2M_CTL_ADDR[i],
+                    ctrl.to_msr_value() as u32,
+                )?;
+            }
+            Ok(())
+        })
+    }
+
+    fn read_counters(&self, reads: &ReadCounter) -> Result<[u64; 4]> {
+        let pci = pci::Pci::instance();
+
+        let mut values = [0u64; 4];
+        for (i, value) in values.iter_mut().enumerate() {
+            *value = reads.observe(pci.read64(&self.pci_addr, m2m::pci::M2M_CTR_ADDR[i]))?
+                & m2m::COUNTER_MASK;
+        }
+        Ok(values)
+    }
+}
+
+/// Compute M2M metrics from one interval of counts
+pub fn calculate_metrics(counts: &M2mCounts, elapsed: Duration) -> HashMap<M2mMetric, f64> {
+    let mut metrics = HashMap::new();
+    let Some(elapsed_s) = rate_window_secs(elapsed) else {
+        return metrics;
+    };
+
+    let lookups = counts.directory_hit + counts.directory_miss;
+    let hit_ratio = if lookups > 0 {
+        counts.directory_hit as f64 / lookups as f64
+    } else {
+        0.0
+    };
+
+    metrics.insert(M2mMetric::M2MDirectoryHitRatio, hit_ratio);
+    metrics.insert(
+        M2mMetric::M2MDirectoryHits,
+        counts.directory_hit as f64 / elapsed_s,
+    );
+    metrics.insert(
+        M2mMetric::M2MDirectoryMisses,
+        counts.directory_miss as f64 / elapsed_s,
+    );
+    metrics.insert(M2mMetric::M2MTagHits, counts.tag_hit as f64 / elapsed_s);
+
+    metrics
+}
+
+#[derive(Debug)]
+pub struct M2mMonitor {
+    socket: i32,
+    units: Vec<M2mUnit>,
+    prev_counters: HashMap<usize, [u64; 4]>,
+    last_time: Option<Instant>,
+    last_counts: Option<M2mCounts>,
+    // Counter reads and failed reads of this socket
+    reads: ReadCounter,
+}
+
+impl M2mMonitor {
+    pub fn new(socket: i32) -> Result<Self> {
+        Self::validate_program()?;
+
+        let mut units = Vec::new();
+        for index in 0..m2m::M2M_UNIT_COUNT {
+            match M2mUnit::new(socket as u32, index) {
+                Ok(unit) => {
+                    unit.program()?;
+                    units.push(unit);
+                }
+                Err(e) => {
+                    tracing::debug!("M2M unit {} unavailable on socket {}: {}", index, socket, e);
+                }
+            }
+        }
+
+        if units.is_empty() {
+            return Err(UncflowError::HardwareError(format!(
+                "No M2M units found on socket {socket}"
+            )));
+        }
+
+        Ok(Self {
+            socket,
+            units,
+            prev_counters: HashMap::new(),
+            last_time: None,
+            last_counts: None,
+            reads: read_stats().counter("m2m", socket),
+        })
+    }
+
+    /// Build and validate the counter controls without touching hardware
+    pub fn validate_program() -> Result<()> {
+        register::validate_all("M2M counters", &counter_controls())
+    }
+
+    /// Collect socket-wide M2M metrics (empty on the first call)
+    pub fn collect_metrics(&mut self) -> Result<HashMap<M2mMetric, f64>> {
+        let now = Instant::now();
+        if self
+            .last_time
+            .is_some_and(|t| rate_window_secs(now.duration_since(t)).is_none())
+        {
+            // Too soon after the last read: keep the baselines so the next window covers both
+            return Ok(HashMap::new());
+        }
+
+        let mut counts = M2mCounts::default();
+        let mut have_deltas = false;
+
+        for unit in &self.units {
+            let current = unit.read_counters(&self.reads)?;
+
+            if let Some(prev) = self.prev_counters.get(&unit.index) {
+                counts.directory_hit +=
+                    wrapping_delta(prev[0], current[0], m2m::COUNTER_WIDTH_BITS);
+                counts.directory_miss +=
+                    wrapping_delta(prev[1], current[1], m2m::COUNTER_WIDTH_BITS);
+                counts.tag_hit += wrapping_delta(prev[2], current[2], m2m::COUNTER_WIDTH_BITS);
+                counts.lookup_state_a +=
+                    wrapping_delta(prev[3], current[3], m2m::COUNTER_WIDTH_BITS);
+                have_deltas = true;
+            }
+
+            self.prev_counters.insert(unit.index, current);
+        }
+
+        let elapsed = self.last_time.map(|t| now.duration_since(t));
+        self.last_time = Some(now);
+
+        match elapsed {
+            Some(elapsed) if have_deltas => {
+                self.last_counts = Some(counts);
+                Ok(calculate_metrics(&counts, elapsed))
+            }
+            _ => Ok(HashMap::new()),
+        }
+    }
+
+    /// Counts the last metrics were calculated from
+    pub fn last_counts(&self) -> Option<&M2mCounts> {
+        self.last_counts.as_ref()
+    }
+
+    /// Drop the baselines, the next collect_metrics() is a first sample
+    pub fn reset(&mut self) {
+        self.prev_counters.clear();
+        self.last_time = None;
+        self.last_counts = None;
+    }
+
+    pub fn socket(&self) -> i32 {
+        self.socket
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_directory_hit_ratio() {
+        let counts = M2mCounts {
+            directory_hit: 750,
+            directory_miss: 250,
+            tag_hit: 100,
+            lookup_state_a: 200,
+        };
+        let metrics = calculate_metrics(&counts, Duration::from_millis(500));
+
+        assert!((metrics[&M2mMetric::M2MDirectoryHitRatio] - 0.75).abs() < 1e-9);
+        assert!((metrics[&M2mMetric::M2MDirectoryHits] - 1500.0).abs() < 1e-9);
+        assert!((metrics[&M2mMetric::M2MTagHits] - 200.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_ratio_without_lookups() {
+        let metrics = calculate_metrics(&M2mCounts::default(), Duration::from_secs(1));
+        assert_eq!(metrics[&M2mMetric::M2MDirectoryHitRatio], 0.0);
+    }
+
+    #[test]
+    fn test_short_window_yields_no_rates() {
+        let counts = M2mCounts {
+            directory_hit: 1000,
+            ..Default::default()
+        };
+        assert!(calculate_metrics(&counts, Duration::from_micros(50)).is_empty());
+        assert!(calculate_metrics(&counts, Duration::ZERO).is_empty());
+    }
+}
@@ -1,7 +1,13 @@
 pub mod cha;
 pub mod core;
+pub mod cstate;
 pub mod iio;
 pub mod imc;
 pub mod irp;
+pub mod m2m;
+pub mod pcu;
 pub mod rapl;
 pub mod rdt;
+pub mod sst;
+pub mod thermal;
+pub mod upi;
@@ -0,0 +1,3 @@
+pub mod monitor;
+
+pub use monitor::PcuMonitor;
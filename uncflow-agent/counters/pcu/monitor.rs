@@ -0,0 +1,329 @@
+// PCU (Power Control Unit) Monitor
+//
+// The PCU of each socket counts frequency transitions, the cycles frequency is
+// capped by thermal, power, PROCHOT# or VR_HOT limits, and how many cores are in
+// each C-state. The event groups take turns on the four counters like the IIO and
+// IRP groups: each counts for at least EVENT_GROUP_WINDOW from a baseline read
+// when it is programmed. Every group counts PCU clockticks on counter 0, so its
+// events are divided by the cycles of its own window. Metrics of the groups not
+// read in a collect keep their last values.
+
+use crate::common::counter::{wrapping_delta, Baseline};
+use crate::common::msr::{self, MsrAccess};
+use crate::common::{pmon, read_stats, register, EventScheduler, ReadCounter};
+use crate::error::{Result, UncflowError};
+use crate::metrics::pcu::PcuMetric;
+use crate::metrics::unit::MetricUnit;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// Import hardware definitions from uncflow-raw
+use uncflow_raw::current_arch::pcu::{self, events, occupancy, PcuCounterControl};
+use uncflow_raw::RegisterLayout;
+
+/// How long each event group counts at least before the next takes over
+pub const EVENT_GROUP_WINDOW: Duration = Duration::from_secs(1);
+
+// Events of counters 1-3 as (event, occ_sel, metric); counter 0 counts clockticks
+#[derive(Debug)]
+struct PcuEventGroup {
+    name: &'static str,
+    events: [(u8, u8, PcuMetric); 3],
+}
+
+impl PcuEventGroup {
+    /// Counter control registers for the four counters
+    fn controls(&self) -> [PcuCounterControl; 4] {
+        let [ctl1, ctl2, ctl3] = self
+            .events
+            .map(|(event, occ_sel, _)| PcuCounterControl::counting(event, occ_sel));
+        [
+            PcuCounterControl::counting(events::CLOCKTICKS, 0),
+            ctl1,
+            ctl2,
+            ctl3,
+        ]
+    }
+}
+
+const PCU_EVENTS: &[PcuEventGroup] = &[
+    PcuEventGroup {
+        name: "Frequency",
+        events: [
+            (events::FREQ_TRANS_CYCLES, 0, PcuMetric::FreqTransitionRatio),
+            (
+                events::FREQ_MAX_LIMIT_THERMAL_CYCLES,
+                0,
+                PcuMetric::ThermalLimitRatio,
+            ),
+            (events::FREQ_MAX_POWER_CYCLES, 0, PcuMetric::PowerLimitRatio),
+        ],
+    },
+    PcuEventGroup {
+        name: "Throttle",
+        events: [
+            (
+                events::PROCHOT_INTERNAL_CYCLES,
+                0,
+                PcuMetric::ProchotInternalRatio,
+            ),
+            (
+                events::PROCHOT_EXTERNAL_CYCLES,
+                0,
+                PcuMetric::ProchotExternalRatio,
+            ),
+            (events::VR_HOT_CYCLES, 0, PcuMetric::VrHotRatio),
+        ],
+    },
+    PcuEventGroup {
+        name: "Occupancy",
+        events: [
+            (
+                events::POWER_STATE_OCCUPANCY,
+                occupancy::CORES_C0,
+                PcuMetric::CoresC0,
+            ),
+            (
+                events::POWER_STATE_OCCUPANCY,
+                occupancy::CORES_C3,
+                PcuMetric::CoresC3,
+            ),
+            (
+                events::POWER_STATE_OCCUPANCY,
+                occupancy::CORES_C6,
+                PcuMetric::CoresC6,
+            ),
+        ],
+    },
+];
+
+/// `count` per PCU clock cycle: the share of cycles of a cycle event, the average
+/// core count of an occupancy; `None` when the clock did not advance
+pub fn cycle_ratio(count: u64, clockticks: u64) -> Option<f64> {
+    (clockticks > 0).then(|| count as f64 / clockticks as f64)
+}
+
+#[derive(Debug)]
+pub struct PcuMonitor {
+    socket: i32,
+    core: u32,
+    scheduler: EventScheduler<&'static PcuEventGroup>,
+    // Whether the scheduler's current group is programmed and counting
+    loaded: bool,
+    // Counters when the current group started
+    baseline: Baseline<[u64; pcu::COUNTERS_PER_PCU]>,
+    // Latest metrics of every group
+    metrics: HashMap<PcuMetric, f64>,
+    msr: &'static dyn MsrAccess,
+    // Counter reads and failed reads of this socket
+    reads: ReadCounter,
+}
+
+impl PcuMonitor {
+    pub fn new(socket: i32) -> Result<Self> {
+        Self::with_core(socket, Self::default_core(socket), msr::Msr::instance())
+    }
+
+    /// Core the PCU MSRs of `socket` are accessed from unless overridden
+    pub fn default_core(socket: i32) -> u32 {
+        (socket as u32) * 16
+    }
+
+    /// Create a monitor accessing the PCU MSRs from `core`, which must be on `socket`
+    pub fn with_core(socket: i32, core: u32, msr: &'static dyn MsrAccess) -> Result<Self> {
+        Self::validate_program()?;
+        msr::ensure_uncore_unfrozen(msr, core, "PCU")?;
+
+        Ok(Self {
+            socket,
+            core,
+            scheduler: EventScheduler::with_groups(PCU_EVENTS.iter().collect(), EVENT_GROUP_WINDOW),
+            loaded: false,
+            baseline: Baseline::default(),
+            metrics: HashMap::new(),
+            msr,
+            reads: read_stats().counter("pcu", socket),
+        })
+    }
+
+    /// Build and validate the counter controls of every group without touching hardware
+    pub fn validate_program() -> Result<()> {
+        for group in PCU_EVENTS {
+            register::validate_all(&format!("PCU group {}", group.name), &group.controls())?;
+        }
+        Ok(())
+    }
+
+    /// Metrics of every group measured so far (empty until the first group is read)
+    pub fn collect_metrics(&mut self) -> Result<HashMap<PcuMetric, f64>> {
+        let Some(&group) = self.scheduler.current() else {
+            return Err(UncflowError::InvalidConfiguration(
+                "No PCU event groups".to_string(),
+            ));
+        };
+
+        if !self.loaded {
+            // First collect or after a reset: start counting the current group
+            self.program(group)?;
+            self.scheduler.reset();
+            self.loaded = true;
+        } else if self.scheduler.should_rotate() {
+            let current = self.read_counters()?;
+            self.close_group(group, current, Instant::now());
+
+            if let Some(&next) = self.scheduler.rotate() {
+                self.program(next)?;
+            }
+        }
+
+        Ok(self.metrics.clone())
+    }
+
+    // Program `group` and read its baseline
+    fn program(&mut self, group: &PcuEventGroup) -> Result<()> {
+        let write_box_ctl = |value| self.msr.write(self.core, pcu::msr::PCU_UNIT_BOX_CTL, value);
+        pmon::program_frozen(write_box_ctl, || {
+            for (ctrl, &addr) in group.controls().iter().zip(&pcu::msr::PCU_UNIT_CTL) {
+                // Write and read back: read-only or virtualized MSRs accept the
+                // write but keep their old value
+                msr::write_verified(
+                    self.msr,
+                    self.core,
+                    addr,
+                    ctrl.to_msr_value(),
+                    PcuCounterControl::VERIFY_MASK,
+                )?;
+            }
+            Ok(())
+        })?;
+
+        self.baseline.reset();
+        let start = self.read_counters()?;
+        self.baseline.advance(start, Instant::now());
+        Ok(())
+    }
+
+    fn read_counters(&self) -> Result<[u64; pcu::COUNTERS_PER_PCU]> {
+        let mut values = [0; pcu::COUNTERS_PER_PCU];
+        for (value, &addr) in values.iter_mut().zip(&pcu::msr::PCU_UNIT_CTR) {
+            *value = self.reads.observe(self.msr.read(self.core, addr))? & pcu::COUNTER_MASK;
+        }
+        Ok(values)
+    }
+
+    // Derive the metrics of `group` from the counts between the baseline and
+    // `current`, read at `now`; without a baseline `current` only becomes one
+    fn close_group(
+        &mut self,
+        group: &PcuEventGroup,
+        current: [u64; pcu::COUNTERS_PER_PCU],
+        now: Instant,
+    ) {
+        let Some((start, _)) = self.baseline.advance(current, now) else {
+            return;
+        };
+        let delta = |i: usize| wrapping_delta(start[i], current[i], pcu::COUNTER_WIDTH_BITS);
+
+        let clockticks = delta(0);
+        for (i, &(_, _, metric)) in group.events.iter().enumerate() {
+            let Some(value) = cycle_ratio(delta(i + 1), clockticks) else {
+                continue;
+            };
+            // Counters are read one after the other, so a cycle count can run a
+            // little past the clockticks
+            let value = match metric.unit() {
+                Some(MetricUnit::Ratio) => value.min(1.0),
+                _ => value,
+            };
+            self.metrics.insert(metric, value);
+        }
+    }
+
+    /// Drop the baseline and metrics; the current group is reprogrammed, so the
+    /// next collect starts its window over
+    pub fn reset(&mut self) {
+        self.loaded = false;
+        self.baseline.reset();
+        self.metrics.clear();
+    }
+
+    pub fn socket(&self) -> i32 {
+        self.socket
+    }
+
+    /// Core the PCU MSRs are accessed from
+    pub fn core(&self) -> u32 {
+        self.core
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::msr::mock::MockMsr;
+
+    #[test]
+    fn test_cycle_ratio() {
+        assert_eq!(cycle_ratio(250_000, 1_000_000), Some(0.25));
+        assert_eq!(cycle_ratio(0, 1_000_000), Some(0.0));
+        // 12 cores in C0 on average
+        assert_eq!(cycle_ratio(12_000_000, 1_000_000), Some(12.0));
+        assert_eq!(cycle_ratio(100, 0), None);
+    }
+
+    #[test]
+    fn test_first_window_sets_baseline_then_ratios_are_clamped() {
+        let mut monitor = PcuMonitor::with_core(0, 0, MockMsr::new().leak()).unwrap();
+        let start = Instant::now();
+        monitor.close_group(&PCU_EVENTS[0], [1_000, 0, 0, 0], start);
+        assert!(monitor.metrics.is_empty());
+
+        // Thermal cycles read a little after the clockticks stay a share of them
+        let later = start + Duration::from_secs(1);
+        monitor.close_group(&PCU_EVENTS[0], [1_001_000, 20_000, 1_000_050, 0], later);
+        assert_eq!(monitor.metrics[&PcuMetric::FreqTransitionRatio], 0.02);
+        assert_eq!(monitor.metrics[&PcuMetric::ThermalLimitRatio], 1.0);
+        assert_eq!(monitor.metrics[&PcuMetric::PowerLimitRatio], 0.0);
+    }
+
+    #[test]
+    fn test_groups_rotate_from_their_baseline() {
+        let msr = MockMsr::new().leak();
+        let ctr = pcu::msr::PCU_UNIT_CTR;
+        let event_select = |counter: usize| {
+            PcuCounterControl::from_msr_value(msr.get(0, pcu::msr::PCU_UNIT_CTL[counter]).unwrap())
+        };
+        // Counters the box reset did not clear
+        msr.set(0, ctr[0], 5_000_000);
+        msr.set(0, ctr[1], 7_000);
+        let mut monitor = PcuMonitor::with_core(0, 0, msr).unwrap();
+
+        // The first collect only programs the first group
+        assert!(monitor.collect_metrics().unwrap().is_empty());
+        assert_eq!(event_select(0).event_select, events::CLOCKTICKS);
+        assert_eq!(event_select(1).event_select, events::FREQ_TRANS_CYCLES);
+
+        msr.set(0, ctr[0], 5_000_000 + 800_000);
+        msr.set(0, ctr[1], 7_000 + 40_000);
+        msr.set(0, ctr[3], 200_000);
+        monitor.scheduler.backdate(EVENT_GROUP_WINDOW);
+        let metrics = monitor.collect_metrics().unwrap();
+        assert_eq!(metrics[&PcuMetric::FreqTransitionRatio], 0.05);
+        assert_eq!(metrics[&PcuMetric::PowerLimitRatio], 0.25);
+        assert!(!metrics.contains_key(&PcuMetric::VrHotRatio));
+
+        // The throttle group is counting now, the frequency metrics are kept
+        assert_eq!(event_select(3).event_select, events::VR_HOT_CYCLES);
+        monitor.scheduler.backdate(EVENT_GROUP_WINDOW);
+        let metrics = monitor.collect_metrics().unwrap();
+        assert_eq!(metrics[&PcuMetric::FreqTransitionRatio], 0.05);
+        let occupancy = event_select(1);
+        assert_eq!(
+            (occupancy.event_select, occupancy.occ_sel),
+            (events::POWER_STATE_OCCUPANCY, occupancy::CORES_C0)
+        );
+
+        monitor.reset();
+        assert!(monitor.collect_metrics().unwrap().is_empty());
+    }
+}
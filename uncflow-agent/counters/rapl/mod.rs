@@ -1,3 +1,3 @@
 pub mod monitor;
 
-pub use monitor::RaplMonitor;
+pub use monitor::{EnergyInterval, RaplMonitor, ENERGY_POLL_INTERVAL};
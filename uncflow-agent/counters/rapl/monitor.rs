@@ -1,14 +1,60 @@
+// RAPL energy counters, accumulated into 64-bit totals
+//
+// The energy status MSRs are 32-bit and wrap after 2^32 energy units (262 kJ at the
+// common 61 µJ unit, a few minutes under full load). Each read adds the wrapping
+// difference since the previous one to a running total, which is exact as long as
+// reads are less than one wrap apart; `poll` exists so a background thread can
+// guarantee that however rarely the metrics are exported.
+//
+// MSR_RAPL_POWER_UNIT gives the energy unit of every domain, except DRAM on the
+// server parts, whose DRAM counter counts a fixed 15.3 µJ unit whatever the MSR says.
+
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
 
-use crate::common::msr;
+use crate::common::counter::{rate_window_secs, Baseline};
+use crate::common::msr::{self, MsrAccess};
+use crate::common::perf::{PerfCounter, PerfEvent};
+use crate::common::{sys_roots, CounterBackend, CPU_INFO};
 use crate::config::ExportConfig;
-use crate::error::Result;
+use crate::error::{Result, UncflowError};
 
 const MSR_RAPL_POWER_UNIT: u64 = 0x606;
 const MSR_PKG_ENERGY_STATUS: u64 = 0x611;
 const MSR_PP0_ENERGY_STATUS: u64 = 0x639;
 const MSR_DRAM_ENERGY_STATUS: u64 = 0x619;
 
+// Energy status MSRs in RaplData field order
+const ENERGY_STATUS_MSRS: [u64; 3] = [
+    MSR_PKG_ENERGY_STATUS,
+    MSR_PP0_ENERGY_STATUS,
+    MSR_DRAM_ENERGY_STATUS,
+];
+
+/// DRAM energy unit of the server parts in joules, ignoring MSR_RAPL_POWER_UNIT
+const SERVER_DRAM_ENERGY_UNIT: f64 = 15.3e-6;
+
+/// Family 6 models whose DRAM domain counts `SERVER_DRAM_ENERGY_UNIT`: Haswell-EP,
+/// Broadwell-EP/DE, Skylake-SP/Cascade Lake, Ice Lake-SP/D, Sapphire Rapids and Xeon Phi
+const FIXED_DRAM_UNIT_MODELS: [u32; 8] = [0x3F, 0x4F, 0x56, 0x55, 0x6A, 0x6C, 0x8F, 0x57];
+
+/// Joules per count of each energy domain, in RaplData field order
+fn domain_energy_units(rapl_unit: u64, family: u32, model: u32) -> [f64; 3] {
+    let unit = 1.0 / (1u64 << ((rapl_unit >> 8) & 0x1F)) as f64;
+    let dram_unit = if family == 6 && FIXED_DRAM_UNIT_MODELS.contains(&model) {
+        SERVER_DRAM_ENERGY_UNIT
+    } else {
+        unit
+    };
+    [unit, unit, dram_unit]
+}
+
+/// Interval at which the MSR energy counters must be polled to never miss a wrap
+///
+/// Well below the fastest wrap: 2^32 units of 15.3 µJ (the smallest unit in use)
+/// take over a minute even at 1 kW.
+pub const ENERGY_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
 #[derive(Debug, Clone, Copy, Default)]
 pub struct RaplData {
     pub package_energy: f64,
@@ -16,41 +62,161 @@ pub struct RaplData {
     pub dram_energy: f64,
 }
 
+/// Energy consumed over one measured interval
+#[derive(Debug, Clone, Copy)]
+pub struct EnergyInterval {
+    /// Joules consumed in the interval
+    pub energy: RaplData,
+    /// How long the interval lasted
+    pub elapsed: Duration,
+}
+
+impl EnergyInterval {
+    /// Average power over the interval in watts, None if it is too short to average over
+    pub fn average_power(&self) -> Option<RaplData> {
+        rate_window_secs(self.elapsed).map(|seconds| RaplData {
+            package_energy: self.energy.package_energy / seconds,
+            core_energy: self.energy.core_energy / seconds,
+            dram_energy: self.energy.dram_energy / seconds,
+        })
+    }
+}
+
+// perf `power` PMU events, in RaplData field order
+const PERF_ENERGY_EVENTS: [&str; 3] = ["energy-pkg", "energy-cores", "energy-ram"];
+
+// One energy domain read through perf, with its Joules-per-count scale
+struct PerfEnergyCounter {
+    counter: PerfCounter,
+    scale: f64,
+}
+
+impl PerfEnergyCounter {
+    fn open(event: &str, cpu: u32) -> Result<Self> {
+        let scale_path = sys_roots()
+            .perf_pmu_dir("power")
+            .join("events")
+            .join(format!("{event}.scale"));
+        let scale = std::fs::read_to_string(scale_path)?
+            .trim()
+            .parse::<f64>()
+            .map_err(|e| UncflowError::ParseError(format!("Invalid {event} scale: {e}")))?;
+
+        Ok(Self {
+            counter: PerfCounter::open(&PerfEvent::from_sysfs("power", event)?, cpu)?,
+            scale,
+        })
+    }
+
+    fn read_joules(&self) -> Result<f64> {
+        Ok(self.counter.read()? as f64 * self.scale)
+    }
+}
+
+/// 64-bit running totals of one socket's 32-bit energy counters, in energy units
+#[derive(Debug, Clone, Copy, Default)]
+struct EnergyAccumulator {
+    last_raw: [u32; 3],
+    total: [u64; 3],
+}
+
+impl EnergyAccumulator {
+    fn new(raw: [u32; 3]) -> Self {
+        Self {
+            last_raw: raw,
+            total: [0; 3],
+        }
+    }
+
+    /// Add the counts since the previous update, assuming at most one wrap in between
+    fn update(&mut self, raw: [u32; 3]) {
+        for ((total, last), raw) in self.total.iter_mut().zip(&mut self.last_raw).zip(raw) {
+            *total += u64::from(raw.wrapping_sub(*last));
+            *last = raw;
+        }
+    }
+}
+
 pub struct RaplMonitor {
     config: ExportConfig,
-    energy_units: HashMap<i32, f64>,
+    msr: &'static dyn MsrAccess,
+    // Joules per count of each domain, per socket
+    energy_units: HashMap<i32, [f64; 3]>,
+    accumulators: HashMap<i32, EnergyAccumulator>,
     socket_to_cpu: HashMap<i32, u32>,
-    last_readings: HashMap<i32, RaplData>,
+    // Per socket; a domain the CPU doesn't expose (e.g. energy-ram) is None
+    perf_counters: HashMap<i32, [Option<PerfEnergyCounter>; 3]>,
+    // Total energy of each socket at the previous energy_interval()
+    baselines: HashMap<i32, Baseline<RaplData>>,
 }
 
 impl RaplMonitor {
     pub fn new(config: ExportConfig) -> Result<Self> {
+        Self::with_msr(config, msr::Msr::instance())
+    }
+
+    /// Monitor reading the MSR backend's registers through `msr`
+    pub fn with_msr(config: ExportConfig, msr: &'static dyn MsrAccess) -> Result<Self> {
+        Self::with_msr_on(config, msr, CPU_INFO.family, CPU_INFO.model)
+    }
+
+    // Monitor for a CPU of the given family and model, which decide the DRAM unit
+    fn with_msr_on(
+        config: ExportConfig,
+        msr: &'static dyn MsrAccess,
+        family: u32,
+        model: u32,
+    ) -> Result<Self> {
         let mut energy_units = HashMap::new();
         let mut socket_to_cpu = HashMap::new();
-        let mut last_readings = HashMap::new();
+        let mut perf_counters = HashMap::new();
 
         for &socket_id in &config.sockets {
             let first_cpu = Self::find_first_cpu_for_socket(&config, socket_id)?;
 
-            let rapl_unit = msr::read_msr(first_cpu, MSR_RAPL_POWER_UNIT)?;
-            let energy_unit = 1.0 / (1u64 << ((rapl_unit >> 8) & 0x1F)) as f64;
-
-            energy_units.insert(socket_id, energy_unit);
+            match config.backend {
+                CounterBackend::Msr => {
+                    let rapl_unit = msr.read(first_cpu, MSR_RAPL_POWER_UNIT)?;
+                    energy_units.insert(socket_id, domain_energy_units(rapl_unit, family, model));
+                }
+                CounterBackend::Perf => {
+                    let counters = PERF_ENERGY_EVENTS.map(|event| {
+                        PerfEnergyCounter::open(event, first_cpu)
+                            .inspect_err(|e| {
+                                tracing::warn!(
+                                    "RAPL {event} unavailable on socket {socket_id}: {e}"
+                                )
+                            })
+                            .ok()
+                    });
+                    if counters[0].is_none() {
+                        return Err(UncflowError::RaplError(format!(
+                            "perf power PMU has no package energy counter for socket {socket_id}"
+                        )));
+                    }
+                    perf_counters.insert(socket_id, counters);
+                }
+            }
             socket_to_cpu.insert(socket_id, first_cpu);
-
-            last_readings.insert(socket_id, RaplData::default());
         }
 
         let mut monitor = Self {
             config,
+            msr,
             energy_units,
+            accumulators: HashMap::new(),
             socket_to_cpu,
-            last_readings,
+            perf_counters,
+            baselines: HashMap::new(),
         };
 
-        for &socket_id in &monitor.config.sockets {
-            let initial = monitor.get_current_energy(socket_id)?;
-            monitor.last_readings.insert(socket_id, initial);
+        if monitor.config.backend == CounterBackend::Msr {
+            for &socket_id in &monitor.config.sockets {
+                let raw = monitor.read_energy_status(socket_id)?;
+                monitor
+                    .accumulators
+                    .insert(socket_id, EnergyAccumulator::new(raw));
+            }
         }
 
         Ok(monitor)
@@ -59,8 +225,7 @@ impl RaplMonitor {
     fn find_first_cpu_for_socket(config: &ExportConfig, socket_id: i32) -> Result<u32> {
         // Try to find a CPU that belongs to the specified socket by checking topology
         for &cpu in &config.cores {
-            let topology_path =
-                format!("/sys/devices/system/cpu/cpu{cpu}/topology/physical_package_id");
+            let topology_path = sys_roots().package_id_path(cpu);
 
             if let Ok(package_id_str) = std::fs::read_to_string(&topology_path) {
                 if let Ok(package_id) = package_id_str.trim().parse::<i32>() {
@@ -86,41 +251,215 @@ impl RaplMonitor {
         Ok(0)
     }
 
-    fn read_msr(&self, socket: i32, reg: u64) -> Result<u64> {
+    // Raw 32-bit energy counts; the upper half of each status MSR is reserved
+    fn read_energy_status(&self, socket: i32) -> Result<[u32; 3]> {
         let cpu = self.socket_to_cpu[&socket];
-        msr::read_msr(cpu, reg)
+        let mut raw = [0; 3];
+        for (value, addr) in raw.iter_mut().zip(ENERGY_STATUS_MSRS) {
+            *value = self.msr.read(cpu, addr)? as u32;
+        }
+        Ok(raw)
     }
 
-    fn read_energy_status(&self, socket: i32, msr_addr: u64) -> Result<f64> {
-        let raw = self.read_msr(socket, msr_addr)?;
-        let energy_unit = self.energy_units[&socket];
-        Ok(raw as f64 * energy_unit)
+    /// Fold the energy consumed since the last read into every socket's totals
+    ///
+    /// Must run at least every `ENERGY_POLL_INTERVAL`; perf counters are 64-bit
+    /// already and need no polling.
+    pub fn poll(&mut self) -> Result<()> {
+        if self.config.backend == CounterBackend::Perf {
+            return Ok(());
+        }
+        for socket_id in self.config.sockets.clone() {
+            self.accumulate(socket_id)?;
+        }
+        Ok(())
     }
 
-    pub fn get_current_energy(&self, socket: i32) -> Result<RaplData> {
-        let package_energy = self.read_energy_status(socket, MSR_PKG_ENERGY_STATUS)?;
-        let core_energy = self.read_energy_status(socket, MSR_PP0_ENERGY_STATUS)?;
-        let dram_energy = self.read_energy_status(socket, MSR_DRAM_ENERGY_STATUS)?;
+    fn accumulate(&mut self, socket: i32) -> Result<&EnergyAccumulator> {
+        let raw = self.read_energy_status(socket)?;
+        let accumulator = self.accumulators.entry(socket).or_default();
+        accumulator.update(raw);
+        Ok(accumulator)
+    }
+
+    fn read_perf_energy(&self, socket: i32) -> Result<RaplData> {
+        let mut joules = [0.0; 3];
+        for (value, counter) in joules.iter_mut().zip(&self.perf_counters[&socket]) {
+            if let Some(counter) = counter {
+                *value = counter.read_joules()?;
+            }
+        }
 
         Ok(RaplData {
-            package_energy,
-            core_energy,
-            dram_energy,
+            package_energy: joules[0],
+            core_energy: joules[1],
+            dram_energy: joules[2],
         })
     }
 
-    pub fn get_power_consumption(&mut self, socket: i32) -> Result<RaplData> {
-        let current = self.get_current_energy(socket)?;
-        let last = self.last_readings[&socket];
+    /// Joules consumed since the monitor was created
+    pub fn total_energy(&mut self, socket: i32) -> Result<RaplData> {
+        if self.config.backend == CounterBackend::Perf {
+            return self.read_perf_energy(socket);
+        }
+
+        let units = self.energy_units[&socket];
+        let total = self.accumulate(socket)?.total;
+
+        Ok(RaplData {
+            package_energy: total[0] as f64 * units[0],
+            core_energy: total[1] as f64 * units[1],
+            dram_energy: total[2] as f64 * units[2],
+        })
+    }
+
+    /// Re-read the energy baseline of every socket
+    pub fn reset(&mut self) -> Result<()> {
+        for socket_id in self.config.sockets.clone() {
+            let current = self.total_energy(socket_id)?;
+            let baseline = self.baselines.entry(socket_id).or_default();
+            baseline.reset();
+            baseline.advance(current, Instant::now());
+        }
+        Ok(())
+    }
+
+    /// Energy consumed since the previous call (or reset)
+    pub fn get_power_consumption(&mut self, socket: i32) -> Result<Option<RaplData>> {
+        Ok(self
+            .energy_interval(socket)?
+            .map(|interval| interval.energy))
+    }
+
+    /// Energy consumed since the previous call (or reset), with the time it took
+    ///
+    /// The first call only reads the baseline and returns None.
+    pub fn energy_interval(&mut self, socket: i32) -> Result<Option<EnergyInterval>> {
+        let current = self.total_energy(socket)?;
+        let baseline = self.baselines.entry(socket).or_default();
+        let Some((last, elapsed)) = baseline.advance(current, Instant::now()) else {
+            return Ok(None);
+        };
+
+        Ok(Some(EnergyInterval {
+            energy: RaplData {
+                package_energy: current.package_energy - last.package_energy,
+                core_energy: current.core_energy - last.core_energy,
+                dram_energy: current.dram_energy - last.dram_energy,
+            },
+            elapsed,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::msr::mock::MockMsr;
 
-        let power = RaplData {
-            package_energy: current.package_energy - last.package_energy,
-            core_energy: current.core_energy - last.core_energy,
-            dram_energy: current.dram_energy - last.dram_energy,
+    /// Monitor of a client Skylake with its energy baseline read, as after reset()
+    fn baselined_monitor(msr: &'static MockMsr) -> RaplMonitor {
+        baselined_monitor_on(msr, 0x5E)
+    }
+
+    fn baselined_monitor_on(msr: &'static MockMsr, model: u32) -> RaplMonitor {
+        let config = ExportConfig::new(vec![0], vec![0]);
+        let mut monitor = RaplMonitor::with_msr_on(config, msr, 6, model).unwrap();
+        monitor.reset().unwrap();
+        monitor
+    }
+
+    #[test]
+    fn test_poll_accumulates_wraps_between_reads() {
+        let msr = MockMsr::new().leak();
+        // 2^-14 J energy unit
+        msr.set(0, MSR_RAPL_POWER_UNIT, 0xE << 8);
+        msr.set(0, MSR_PKG_ENERGY_STATUS, 0xF000_0000);
+        let mut monitor = baselined_monitor(msr);
+
+        // The counter passes zero twice before the next export, each poll sees less than a wrap
+        for raw in [0x7000_0000, 0xF000_0000, 0x1000_0000] {
+            msr.set(0, MSR_PKG_ENERGY_STATUS, raw);
+            monitor.poll().unwrap();
+        }
+        // Reserved upper half of the status MSR is ignored
+        msr.set(0, MSR_PKG_ENERGY_STATUS, 0xABCD_0000_1000_0000);
+
+        let expected = ((1u64 << 32) + 0x2000_0000) as f64 / 16384.0;
+        let power = monitor.get_power_consumption(0).unwrap().unwrap();
+        assert_eq!(power.package_energy, expected);
+        assert_eq!(power.dram_energy, 0.0);
+        assert_eq!(monitor.total_energy(0).unwrap().package_energy, expected);
+    }
+
+    #[test]
+    fn test_average_power_is_energy_over_interval() {
+        let interval = EnergyInterval {
+            energy: RaplData {
+                package_energy: 150.0,
+                core_energy: 90.0,
+                dram_energy: 12.5,
+            },
+            elapsed: Duration::from_millis(2500),
         };
+        let watts = interval.average_power().unwrap();
+        assert_eq!(watts.package_energy, 60.0);
+        assert_eq!(watts.core_energy, 36.0);
+        assert_eq!(watts.dram_energy, 5.0);
+        assert!(EnergyInterval {
+            elapsed: Duration::ZERO,
+            ..interval
+        }
+        .average_power()
+        .is_none());
+
+        let msr = MockMsr::new().leak();
+        msr.set(0, MSR_RAPL_POWER_UNIT, 0xE << 8);
+        let mut monitor = baselined_monitor(msr);
+        std::thread::sleep(Duration::from_millis(10));
+        // 2 J of package and 0.5 J of DRAM energy since the baseline
+        msr.set(0, MSR_PKG_ENERGY_STATUS, 2 * 16384);
+        msr.set(0, MSR_DRAM_ENERGY_STATUS, 8192);
+        let interval = monitor.energy_interval(0).unwrap().unwrap();
+        assert!(interval.elapsed >= Duration::from_millis(10));
+        let watts = interval.average_power().unwrap();
+        let seconds = interval.elapsed.as_secs_f64();
+        assert_eq!(watts.package_energy, 2.0 / seconds);
+        assert_eq!(watts.dram_energy, 0.5 / seconds);
+    }
+
+    #[test]
+    fn test_first_interval_only_reads_baseline() {
+        let msr = MockMsr::new().leak();
+        msr.set(0, MSR_RAPL_POWER_UNIT, 0xE << 8);
+        // Energy counted before the agent started is not an interval's energy
+        msr.set(0, MSR_PKG_ENERGY_STATUS, 100 * 16384);
+        let mut monitor = RaplMonitor::with_msr(ExportConfig::new(vec![0], vec![0]), msr).unwrap();
+        assert!(monitor.energy_interval(0).unwrap().is_none());
+
+        msr.set(0, MSR_PKG_ENERGY_STATUS, 103 * 16384);
+        let interval = monitor.energy_interval(0).unwrap().unwrap();
+        assert_eq!(interval.energy.package_energy, 3.0);
+    }
+
+    #[test]
+    fn test_server_dram_uses_fixed_unit() {
+        let msr = MockMsr::new().leak();
+        msr.set(0, MSR_RAPL_POWER_UNIT, 0xE << 8);
+        // Skylake-SP: package in the MSR's 2^-14 J, DRAM in 15.3 µJ
+        let mut monitor = baselined_monitor_on(msr, 0x55);
+        std::thread::sleep(Duration::from_millis(20));
+        msr.set(0, MSR_PKG_ENERGY_STATUS, 16384);
+        msr.set(0, MSR_DRAM_ENERGY_STATUS, 100_000);
 
-        self.last_readings.insert(socket, current);
+        let interval = monitor.energy_interval(0).unwrap().unwrap();
+        assert_eq!(interval.energy.package_energy, 1.0);
+        assert!((interval.energy.dram_energy - 1.53).abs() < 1e-9);
+        let watts = interval.average_power().unwrap();
+        let seconds = interval.elapsed.as_secs_f64();
+        assert!((watts.dram_energy - 1.53 / seconds).abs() < 1e-9);
 
-        Ok(power)
+        // Client parts keep the MSR's unit for DRAM
+        assert_eq!(domain_energy_units(0xE << 8, 6, 0x5E), [1.0 / 16384.0; 3]);
     }
 }
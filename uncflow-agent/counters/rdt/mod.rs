@@ -1,3 +1,5 @@
 pub mod monitor;
+pub mod resctrl;
 
 pub use monitor::RdtMonitor;
+pub use resctrl::ResctrlGroup;
@@ -1,11 +1,18 @@
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::Read;
+use std::path::Path;
+use std::time::{Duration, Instant};
 
-use crate::common::{cpuid, msr};
+use super::resctrl::{self, ResctrlGroup};
+use crate::common::counter::{per_second, wrapping_delta, Baseline};
+use crate::common::{cpuid, log_limiter, msr, sys_roots};
 use crate::config::ExportConfig;
 use crate::error::{Result, UncflowError};
 
+use uncflow_raw::current_arch::rdt::QmCounter;
+use uncflow_raw::RegisterLayout;
+
 const IA32_PQR_ASSOC: u64 = 0xC8F;
 const IA32_QM_EVTSEL: u64 = 0xC8D;
 const IA32_QM_CTR: u64 = 0xC8E;
@@ -16,30 +23,88 @@ const REMOTE_MEM_BW_EVENT: u64 = 0x03;
 
 const RMID_MAX: usize = 256;
 
+/// Raw MBM counters of one core, None while its RMID has no valid data
+#[derive(Debug, Clone, Copy, Default)]
+struct MbmReading {
+    local: Option<u64>,
+    remote: Option<u64>,
+}
+
 #[derive(Debug, Clone)]
 struct SocketInfo {
     socket_id: i32,
     cores: Vec<i32>,
-    last_local_bw: u64,
-    last_remote_bw: u64,
+    last_local_bytes: u64,
+    last_remote_bytes: u64,
+    // MBM counters of the socket's cores at the last read
+    baseline: Baseline<HashMap<i32, MbmReading>>,
+    // Time between the two reads the byte deltas were counted across
+    window: Option<Duration>,
+}
+
+impl SocketInfo {
+    /// Bytes/s of `bytes` moved over the last window, None before there is one
+    fn bandwidth(&self, bytes: u64) -> Option<f64> {
+        self.window
+            .and_then(|window| per_second(bytes as f64, window))
+    }
+
+    /// Make `readings`, taken at `now`, the baseline and return the local and
+    /// remote bytes of each core since the previous one, all 0 on the first read
+    fn record(
+        &mut self,
+        readings: HashMap<i32, MbmReading>,
+        now: Instant,
+        width_bits: u64,
+        scale: u64,
+    ) -> HashMap<i32, (u64, u64)> {
+        let window = self.baseline.advance(readings.clone(), now);
+        let previous = window.as_ref().map(|(previous, _)| previous);
+
+        let deltas: HashMap<_, _> = readings
+            .iter()
+            .map(|(&core, current)| {
+                let prev = previous.and_then(|previous| previous.get(&core));
+                let delta =
+                    |prev: Option<u64>, current| mbm_delta_bytes(prev, current, width_bits, scale);
+                let local = delta(prev.and_then(|p| p.local), current.local);
+                let remote = delta(prev.and_then(|p| p.remote), current.remote);
+                (core, (local, remote))
+            })
+            .collect();
+
+        self.last_local_bytes = deltas.values().map(|&(local, _)| local).sum();
+        self.last_remote_bytes = deltas.values().map(|&(_, remote)| remote).sum();
+        self.window = window.map(|(_, window)| window);
+        deltas
+    }
 }
 
 pub struct RdtMonitor {
     config: ExportConfig,
     mbm_scaling_factor: u32,
-    local_memory_bandwidth: Vec<u64>,
-    remote_memory_bandwidth: Vec<u64>,
+    // Bits after which the MBM byte counters wrap
+    mbm_counter_width: u64,
+    local_memory_bytes: Vec<u64>,
+    remote_memory_bytes: Vec<u64>,
     llc_occupancy: Vec<u64>,
-    prev_local_counters: Vec<u64>,
-    prev_remote_counters: Vec<u64>,
     core_to_rmid: Vec<u32>,
     rmid_used: Vec<bool>,
     sockets: Vec<SocketInfo>,
+    // Non-empty in resctrl mode, where no RMIDs are programmed
+    resctrl_groups: Vec<ResctrlGroup>,
 }
 
 impl RdtMonitor {
+    /// Per-core RMID monitoring, or resctrl group monitoring when
+    /// `config.resctrl_groups` is set
     pub fn new(config: ExportConfig) -> Result<Self> {
+        if !config.resctrl_groups.is_empty() {
+            return Self::with_resctrl(config, &sys_roots().resctrl_dir());
+        }
+
         let mbm_scaling_factor = cpuid::get_mbm_scaling_factor()?;
+        let mbm_counter_width = cpuid::mbm_counter_width();
 
         let max_core = config.cores.iter().max().copied().unwrap_or(0);
         if max_core < 0 {
@@ -49,31 +114,57 @@ impl RdtMonitor {
         }
 
         let vector_size = (max_core + 1) as usize;
-        let local_memory_bandwidth = vec![0; vector_size];
-        let remote_memory_bandwidth = vec![0; vector_size];
+        let local_memory_bytes = vec![0; vector_size];
+        let remote_memory_bytes = vec![0; vector_size];
         let llc_occupancy = vec![0; vector_size];
-        let prev_local_counters = vec![0; vector_size];
-        let prev_remote_counters = vec![0; vector_size];
         let core_to_rmid = vec![0; vector_size];
         let rmid_used = vec![false; RMID_MAX];
 
         let mut monitor = Self {
             config,
             mbm_scaling_factor,
-            local_memory_bandwidth,
-            remote_memory_bandwidth,
+            mbm_counter_width,
+            local_memory_bytes,
+            remote_memory_bytes,
             llc_occupancy,
-            prev_local_counters,
-            prev_remote_counters,
             core_to_rmid,
             rmid_used,
             sockets: Vec::new(),
+            resctrl_groups: Vec::new(),
         };
 
         monitor.initialize_socket_info()?;
         Ok(monitor)
     }
 
+    /// Read the configured monitoring groups from the resctrl mount at `root`
+    pub fn with_resctrl(config: ExportConfig, root: &Path) -> Result<Self> {
+        resctrl::detect(root)?;
+        let resctrl_groups = config
+            .resctrl_groups
+            .iter()
+            .map(|name| ResctrlGroup::new(root, name))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            config,
+            mbm_scaling_factor: 0,
+            mbm_counter_width: 0,
+            local_memory_bytes: Vec::new(),
+            remote_memory_bytes: Vec::new(),
+            llc_occupancy: Vec::new(),
+            core_to_rmid: Vec::new(),
+            rmid_used: Vec::new(),
+            sockets: Vec::new(),
+            resctrl_groups,
+        })
+    }
+
+    /// Whether counters come from resctrl groups rather than per-core RMIDs
+    pub fn is_resctrl(&self) -> bool {
+        !self.resctrl_groups.is_empty()
+    }
+
     fn initialize_socket_info(&mut self) -> Result<()> {
         let mut socket_cores: HashMap<i32, Vec<i32>> = HashMap::new();
 
@@ -86,8 +177,10 @@ impl RdtMonitor {
             self.sockets.push(SocketInfo {
                 socket_id,
                 cores,
-                last_local_bw: 0,
-                last_remote_bw: 0,
+                last_local_bytes: 0,
+                last_remote_bytes: 0,
+                baseline: Baseline::default(),
+                window: None,
             });
         }
 
@@ -95,7 +188,7 @@ impl RdtMonitor {
     }
 
     fn read_socket_id(core: i32) -> Result<i32> {
-        let path = format!("/sys/devices/system/cpu/cpu{core}/topology/physical_package_id");
+        let path = sys_roots().package_id_path(core);
         let mut file = File::open(&path).map_err(|e| {
             UncflowError::RdtError(format!("Cannot read CPU topology for core {core}: {e}"))
         })?;
@@ -138,6 +231,17 @@ impl RdtMonitor {
     }
 
     pub fn initialize(&mut self) -> Result<()> {
+        if self.is_resctrl() {
+            for group in &mut self.resctrl_groups {
+                group.update()?;
+                tracing::info!(
+                    "Initialized MBM monitoring for resctrl group {}",
+                    group.name()
+                );
+            }
+            return Ok(());
+        }
+
         let cores = self.config.cores.clone();
         for core in cores {
             let rmid = self.allocate_rmid()?;
@@ -160,82 +264,96 @@ impl RdtMonitor {
         Ok(())
     }
 
+    /// Read one event's QM counter for `rmid` on `cpu`, None while it holds no data
+    fn read_qm_counter(cpu: u32, rmid: u32, event: u64) -> Result<Option<u64>> {
+        msr::write_msr(cpu, IA32_QM_EVTSEL, ((rmid as u64) << 32) | event)?;
+        let counter = QmCounter::from_msr_value(msr::read_msr(cpu, IA32_QM_CTR)?);
+        Ok(counter.is_valid().then_some(counter.data))
+    }
+
+    // LLC occupancy is instantaneous and reported as read; the MBM events are
+    // free-running byte counters whose rate is the wrap-aware delta between reads
+    // over the time between them. The first read only sets the baseline.
     fn update_socket_metrics(&mut self, socket_idx: usize) -> Result<()> {
         let socket = &self.sockets[socket_idx];
+        // Too soon after the last read: keep the baselines so the next window covers both
+        if socket.baseline.too_soon(Instant::now()) {
+            return Ok(());
+        }
         let monitoring_core = socket.cores[0] as u32;
+        let scale = self.mbm_scaling_factor as u64;
 
-        let mut socket_local_bw = 0u64;
-        let mut socket_remote_bw = 0u64;
-
+        let mut readings = HashMap::new();
         for &core in &socket.cores {
-            let rmid = self.core_to_rmid[core as usize];
-
-            msr::write_msr(
-                monitoring_core,
-                IA32_QM_EVTSEL,
-                ((rmid as u64) << 32) | LLC_OCCUPANCY_EVENT,
-            )?;
-            let llc_counter = msr::read_msr(monitoring_core, IA32_QM_CTR)?;
-            self.llc_occupancy[core as usize] = llc_counter * (self.mbm_scaling_factor as u64);
-
-            msr::write_msr(
-                monitoring_core,
-                IA32_QM_EVTSEL,
-                ((rmid as u64) << 32) | LOCAL_MEM_BW_EVENT,
-            )?;
-            let local_counter = msr::read_msr(monitoring_core, IA32_QM_CTR)?;
-
-            msr::write_msr(
-                monitoring_core,
-                IA32_QM_EVTSEL,
-                ((rmid as u64) << 32) | REMOTE_MEM_BW_EVENT,
-            )?;
-            let remote_counter = msr::read_msr(monitoring_core, IA32_QM_CTR)?;
-
-            let local_delta = if local_counter >= self.prev_local_counters[core as usize] {
-                local_counter - self.prev_local_counters[core as usize]
-            } else {
-                local_counter
-            };
+            let idx = core as usize;
+            let rmid = self.core_to_rmid[idx];
 
-            let remote_delta = if remote_counter >= self.prev_remote_counters[core as usize] {
-                remote_counter - self.prev_remote_counters[core as usize]
-            } else {
-                remote_counter
+            if let Some(occupancy) =
+                Self::read_qm_counter(monitoring_core, rmid, LLC_OCCUPANCY_EVENT)?
+            {
+                self.llc_occupancy[idx] = occupancy * scale;
+            }
+            let reading = MbmReading {
+                local: Self::read_qm_counter(monitoring_core, rmid, LOCAL_MEM_BW_EVENT)?,
+                remote: Self::read_qm_counter(monitoring_core, rmid, REMOTE_MEM_BW_EVENT)?,
             };
-
-            self.local_memory_bandwidth[core as usize] =
-                local_delta * (self.mbm_scaling_factor as u64);
-            self.remote_memory_bandwidth[core as usize] =
-                remote_delta * (self.mbm_scaling_factor as u64);
-
-            self.prev_local_counters[core as usize] = local_counter;
-            self.prev_remote_counters[core as usize] = remote_counter;
-
-            socket_local_bw += self.local_memory_bandwidth[core as usize];
-            socket_remote_bw += self.remote_memory_bandwidth[core as usize];
+            readings.insert(core, reading);
         }
 
-        self.sockets[socket_idx].last_local_bw = socket_local_bw;
-        self.sockets[socket_idx].last_remote_bw = socket_remote_bw;
-
+        let deltas = self.sockets[socket_idx].record(
+            readings,
+            Instant::now(),
+            self.mbm_counter_width,
+            scale,
+        );
+        for (core, (local, remote)) in deltas {
+            self.local_memory_bytes[core as usize] = local;
+            self.remote_memory_bytes[core as usize] = remote;
+        }
         Ok(())
     }
 
     pub fn update(&mut self) -> Result<()> {
+        for group in &mut self.resctrl_groups {
+            if let Err(e) = group.update() {
+                let source = format!("resctrl group {}", group.name());
+                if log_limiter().allow(&source, &e.to_string()) {
+                    tracing::error!("Failed to update resctrl group {}: {}", group.name(), e);
+                }
+            }
+        }
+
         for i in 0..self.sockets.len() {
             if let Err(e) = self.update_socket_metrics(i) {
-                tracing::error!(
-                    "Failed to update socket {} metrics: {}",
-                    self.sockets[i].socket_id,
-                    e
-                );
+                let socket = self.sockets[i].socket_id;
+                if log_limiter().allow(&format!("RDT socket {socket}"), &e.to_string()) {
+                    tracing::error!("Failed to update socket {} metrics: {}", socket, e);
+                }
             }
         }
         Ok(())
     }
 
+    /// Re-read the MBM counters as the new baseline and clear the last bandwidth values
+    pub fn reset(&mut self) -> Result<()> {
+        for group in &mut self.resctrl_groups {
+            group.reset();
+            group.update()?;
+        }
+
+        for i in 0..self.sockets.len() {
+            self.sockets[i].baseline.reset();
+            self.update_socket_metrics(i)?;
+        }
+        self.local_memory_bytes.fill(0);
+        self.remote_memory_bytes.fill(0);
+        Ok(())
+    }
+
     pub fn refresh_rmids(&mut self) -> Result<()> {
+        if self.is_resctrl() {
+            return Ok(());
+        }
         let cores = self.config.cores.clone();
         for core in cores {
             let rmid = self.core_to_rmid[core as usize];
@@ -249,20 +367,21 @@ impl RdtMonitor {
     pub fn get_metrics(&self, core_id: i32) -> HashMap<String, f64> {
         let mut metrics = HashMap::new();
 
-        if self.config.cores.contains(&core_id) {
+        let socket = self.sockets.iter().find(|s| s.cores.contains(&core_id));
+        if let (Some(socket), false) = (socket, self.is_resctrl()) {
             let idx = core_id as usize;
-            metrics.insert(
-                "LocalMemoryBandwidth".to_string(),
-                self.local_memory_bandwidth[idx] as f64,
-            );
-            metrics.insert(
-                "RemoteMemoryBandwidth".to_string(),
-                self.remote_memory_bandwidth[idx] as f64,
-            );
-            metrics.insert(
-                "TotalMemoryBandwidth".to_string(),
-                (self.local_memory_bandwidth[idx] + self.remote_memory_bandwidth[idx]) as f64,
-            );
+            let local = self.local_memory_bytes[idx];
+            let remote = self.remote_memory_bytes[idx];
+            let bandwidths = [
+                ("LocalMemoryBandwidth", local),
+                ("RemoteMemoryBandwidth", remote),
+                ("TotalMemoryBandwidth", local + remote),
+            ];
+            for (name, bytes) in bandwidths {
+                if let Some(bandwidth) = socket.bandwidth(bytes) {
+                    metrics.insert(name.to_string(), bandwidth);
+                }
+            }
             metrics.insert(
                 "CMTLLCOccupancy".to_string(),
                 self.llc_occupancy[idx] as f64,
@@ -272,23 +391,38 @@ impl RdtMonitor {
         metrics
     }
 
+    /// Bandwidth and LLC occupancy of a resctrl monitoring group
+    pub fn get_group_metrics(&self, group: &str) -> HashMap<String, f64> {
+        let mut metrics = HashMap::new();
+
+        if let Some(group) = self.resctrl_groups.iter().find(|g| g.name() == group) {
+            metrics.insert("TotalMemoryBandwidth".to_string(), group.bandwidth());
+            metrics.insert("CMTLLCOccupancy".to_string(), group.occupancy() as f64);
+        }
+
+        metrics
+    }
+
     /// Get aggregated socket-level metrics
     pub fn get_socket_metrics(&self, socket_id: i32) -> HashMap<String, f64> {
         let mut metrics = HashMap::new();
 
         if let Some(socket_info) = self.sockets.iter().find(|s| s.socket_id == socket_id) {
-            metrics.insert(
-                "LocalMemoryBandwidth".to_string(),
-                socket_info.last_local_bw as f64,
-            );
-            metrics.insert(
-                "RemoteMemoryBandwidth".to_string(),
-                socket_info.last_remote_bw as f64,
-            );
-            metrics.insert(
-                "TotalMemoryBandwidth".to_string(),
-                (socket_info.last_local_bw + socket_info.last_remote_bw) as f64,
-            );
+            let total = socket_info.last_local_bytes + socket_info.last_remote_bytes;
+            let bandwidths = [
+                ("LocalMemoryBandwidth", socket_info.last_local_bytes),
+                ("RemoteMemoryBandwidth", socket_info.last_remote_bytes),
+                ("TotalMemoryBandwidth", total),
+            ];
+            for (name, bytes) in bandwidths {
+                if let Some(bandwidth) = socket_info.bandwidth(bytes) {
+                    metrics.insert(name.to_string(), bandwidth);
+                }
+            }
+            // Bytes moved over the window, for the byte counter
+            if socket_info.window.is_some() {
+                metrics.insert("TotalMemoryBytes".to_string(), total as f64);
+            }
 
             // Aggregate LLC occupancy for all cores in this socket
             let mut total_llc_occupancy = 0u64;
@@ -304,6 +438,9 @@ impl RdtMonitor {
 
 impl Drop for RdtMonitor {
     fn drop(&mut self) {
+        if self.is_resctrl() {
+            return;
+        }
         let cores = self.config.cores.clone();
         for core in cores {
             let rmid = self.core_to_rmid[core as usize];
@@ -313,3 +450,76 @@ impl Drop for RdtMonitor {
         }
     }
 }
+
+/// Bytes counted by an MBM counter between `prev` and `current`, 0 unless both are valid
+///
+/// The counter wraps at `width_bits`; a reading below the previous one is a wrap,
+/// not a restart from zero.
+fn mbm_delta_bytes(prev: Option<u64>, current: Option<u64>, width_bits: u64, scale: u64) -> u64 {
+    match (prev, current) {
+        (Some(prev), Some(current)) => wrapping_delta(prev, current, width_bits) * scale,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mbm_delta_across_wrap() {
+        // 24-bit counter 0x10 units before its wrap, then 0x20 units past it
+        assert_eq!(
+            mbm_delta_bytes(Some(0xFF_FFF0), Some(0x20), 24, 64),
+            0x30 * 64
+        );
+        assert_eq!(mbm_delta_bytes(Some(0x20), Some(0x28), 24, 64), 8 * 64);
+
+        // A wider counter does not wrap at 24 bits
+        assert_eq!(
+            mbm_delta_bytes(Some(0xFF_FFF0), Some(0x100_0010), 44, 1),
+            0x20
+        );
+
+        // No baseline yet, or an invalid reading: nothing counted
+        assert_eq!(mbm_delta_bytes(None, Some(0x20), 24, 64), 0);
+        assert_eq!(mbm_delta_bytes(Some(0x20), None, 24, 64), 0);
+    }
+
+    #[test]
+    fn test_first_read_only_sets_baseline() {
+        let mut socket = SocketInfo {
+            socket_id: 0,
+            cores: vec![2],
+            last_local_bytes: 0,
+            last_remote_bytes: 0,
+            baseline: Baseline::default(),
+            window: None,
+        };
+        let reading = |local, remote| {
+            HashMap::from([(
+                2,
+                MbmReading {
+                    local: Some(local),
+                    remote: Some(remote),
+                },
+            )])
+        };
+
+        // Counters far from zero at startup are not counted as one window
+        let start = Instant::now();
+        let deltas = socket.record(reading(50_000, 7_000), start, 24, 64);
+        assert_eq!(deltas[&2], (0, 0));
+        assert_eq!(socket.bandwidth(socket.last_local_bytes), None);
+
+        let deltas = socket.record(
+            reading(50_100, 7_010),
+            start + Duration::from_secs(2),
+            24,
+            64,
+        );
+        assert_eq!(deltas[&2], (100 * 64, 10 * 64));
+        assert_eq!(socket.bandwidth(socket.last_local_bytes), Some(3_200.0));
+        assert_eq!(socket.bandwidth(socket.last_remote_bytes), Some(320.0));
+    }
+}
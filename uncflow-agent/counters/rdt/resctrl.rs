@@ -0,0 +1,285 @@
+// resctrl-backed RDT monitoring
+//
+// Instead of assigning an RMID to every monitored core, the kernel's resctrl
+// filesystem is asked for the counters of existing monitoring groups (one per
+// cgroup/container, created by the container runtime or by hand). Each group has
+// a mon_data/mon_L3_<domain>/ directory per L3 cache domain holding:
+// - mbm_total_bytes: bytes moved to/from memory since the group was created
+// - llc_occupancy: bytes of L3 currently occupied by the group
+// Both read "Unavailable" while the kernel has no valid value for the RMID.
+
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::time::Instant;
+
+use crate::error::{Result, UncflowError};
+
+const MBM_TOTAL_BYTES: &str = "mbm_total_bytes";
+const LLC_OCCUPANCY: &str = "llc_occupancy";
+
+/// Parse one resctrl counter file, `None` when the kernel reports "Unavailable"
+pub fn parse_counter(contents: &str) -> Result<Option<u64>> {
+    let value = contents.trim();
+    if value == "Unavailable" {
+        return Ok(None);
+    }
+    value
+        .parse::<u64>()
+        .map(Some)
+        .map_err(|e| UncflowError::ParseError(format!("Invalid resctrl counter '{value}': {e}")))
+}
+
+/// L3 domain id of a `mon_data` entry such as `mon_L3_01`
+pub fn parse_l3_domain(name: &str) -> Option<u32> {
+    name.strip_prefix("mon_L3_")?.parse().ok()
+}
+
+/// Counters of one group in one L3 domain
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DomainCounters {
+    pub total_bytes: Option<u64>,
+    pub occupancy: Option<u64>,
+}
+
+/// Read the counters of every L3 domain below `group_dir/mon_data`
+pub fn read_domains(group_dir: &Path) -> Result<HashMap<u32, DomainCounters>> {
+    let mon_data = group_dir.join("mon_data");
+    let entries = std::fs::read_dir(&mon_data)
+        .map_err(|e| UncflowError::RdtError(format!("Cannot list {}: {e}", mon_data.display())))?;
+
+    let mut domains = HashMap::new();
+    for entry in entries {
+        let entry = entry?;
+        let Some(domain) = entry.file_name().to_str().and_then(parse_l3_domain) else {
+            continue;
+        };
+        let dir = entry.path();
+        domains.insert(
+            domain,
+            DomainCounters {
+                total_bytes: read_counter(&dir.join(MBM_TOTAL_BYTES))?,
+                occupancy: read_counter(&dir.join(LLC_OCCUPANCY))?,
+            },
+        );
+    }
+    Ok(domains)
+}
+
+// Missing files mean the feature (MBM or CMT) is not supported, same as "Unavailable"
+fn read_counter(path: &Path) -> Result<Option<u64>> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => parse_counter(&contents),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Check that resctrl is mounted with L3 monitoring support below `root`
+pub fn detect(root: &Path) -> Result<()> {
+    if root.join("info/L3_MON").is_dir() {
+        Ok(())
+    } else {
+        Err(UncflowError::RdtError(format!(
+            "resctrl with L3 monitoring is not available at {} (mount -t resctrl resctrl {})",
+            root.display(),
+            root.display()
+        )))
+    }
+}
+
+/// Directory of monitoring group `name`, a path relative to the resctrl root
+/// such as `mon_groups/web` or `ctrl0/mon_groups/db` (`.` is the default group)
+pub fn group_dir(root: &Path, name: &str) -> Result<PathBuf> {
+    let relative = Path::new(name);
+    let escapes = relative
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+    if name.is_empty() || escapes {
+        return Err(UncflowError::InvalidConfiguration(format!(
+            "Invalid resctrl group '{name}', expected a path below the resctrl mount"
+        )));
+    }
+
+    let dir = root.join(relative);
+    if !dir.join("mon_data").is_dir() {
+        return Err(UncflowError::RdtError(format!(
+            "resctrl group '{name}' has no mon_data at {}",
+            dir.display()
+        )));
+    }
+    Ok(dir)
+}
+
+/// Bandwidth and occupancy of one monitoring group, summed over its L3 domains
+pub struct ResctrlGroup {
+    name: String,
+    dir: PathBuf,
+    last_total_bytes: HashMap<u32, u64>,
+    last_update: Option<Instant>,
+    bandwidth: f64,
+    occupancy: u64,
+}
+
+impl ResctrlGroup {
+    pub fn new(root: &Path, name: &str) -> Result<Self> {
+        Ok(Self {
+            name: name.to_string(),
+            dir: group_dir(root, name)?,
+            last_total_bytes: HashMap::new(),
+            last_update: None,
+            bandwidth: 0.0,
+            occupancy: 0,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Memory bandwidth in bytes/s over the last update
+    pub fn bandwidth(&self) -> f64 {
+        self.bandwidth
+    }
+
+    /// LLC occupancy in bytes at the last update
+    pub fn occupancy(&self) -> u64 {
+        self.occupancy
+    }
+
+    /// Read the group's counters and derive bandwidth from the previous read
+    pub fn update(&mut self) -> Result<()> {
+        let domains = read_domains(&self.dir)?;
+        let now = Instant::now();
+        let elapsed = self.last_update.map(|last| now.duration_since(last));
+        self.apply(&domains, elapsed.map(|d| d.as_secs_f64()));
+        self.last_update = Some(now);
+        Ok(())
+    }
+
+    /// Drop the bandwidth baseline so the next update only sets it
+    pub fn reset(&mut self) {
+        self.last_total_bytes.clear();
+        self.last_update = None;
+        self.bandwidth = 0.0;
+    }
+
+    fn apply(&mut self, domains: &HashMap<u32, DomainCounters>, elapsed_secs: Option<f64>) {
+        let mut delta = 0u64;
+        let mut occupancy = 0u64;
+        for (&domain, counters) in domains {
+            occupancy += counters.occupancy.unwrap_or(0);
+
+            let Some(total) = counters.total_bytes else {
+                continue;
+            };
+            // A smaller value means the group was recreated, so restart from it
+            if let Some(last) = self.last_total_bytes.insert(domain, total) {
+                delta += total.saturating_sub(last);
+            }
+        }
+
+        self.occupancy = occupancy;
+        self.bandwidth = match elapsed_secs {
+            Some(secs) if secs > 0.0 => delta as f64 / secs,
+            _ => 0.0,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn domain(total_bytes: Option<u64>, occupancy: Option<u64>) -> DomainCounters {
+        DomainCounters {
+            total_bytes,
+            occupancy,
+        }
+    }
+
+    #[test]
+    fn test_parse_counter() {
+        assert_eq!(parse_counter("123456\n").unwrap(), Some(123456));
+        assert_eq!(parse_counter("Unavailable\n").unwrap(), None);
+        assert!(parse_counter("").is_err());
+        assert!(parse_counter("-1").is_err());
+
+        assert_eq!(parse_l3_domain("mon_L3_00"), Some(0));
+        assert_eq!(parse_l3_domain("mon_L3_01"), Some(1));
+        assert_eq!(parse_l3_domain("mon_MB_00"), None);
+    }
+
+    #[test]
+    fn test_group_dir_rejects_escapes() {
+        let root = Path::new("/sys/fs/resctrl");
+        for name in ["", "/tmp", "../etc", "mon_groups/../../x"] {
+            assert!(matches!(
+                group_dir(root, name),
+                Err(UncflowError::InvalidConfiguration(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_read_domains_from_sysfs_layout() {
+        let root = std::env::temp_dir().join(format!("uncflow-resctrl-{}", std::process::id()));
+        let group = root.join("mon_groups/web");
+        for (domain, total, occupancy) in [
+            ("mon_L3_00", "4096\n", "Unavailable\n"),
+            ("mon_L3_01", "8192\n", "65536\n"),
+        ] {
+            let dir = group.join("mon_data").join(domain);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join(MBM_TOTAL_BYTES), total).unwrap();
+            std::fs::write(dir.join(LLC_OCCUPANCY), occupancy).unwrap();
+        }
+        std::fs::create_dir_all(root.join("info/L3_MON")).unwrap();
+
+        assert!(detect(&root).is_ok());
+        assert!(detect(&group).is_err());
+        assert!(group_dir(&root, "mon_groups/db").is_err());
+        assert_eq!(group_dir(&root, "mon_groups/web").unwrap(), group);
+
+        let domains = read_domains(&group).unwrap();
+        assert_eq!(domains.len(), 2);
+        assert_eq!(domains[&0], domain(Some(4096), None));
+        assert_eq!(domains[&1], domain(Some(8192), Some(65536)));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_group_bandwidth_and_occupancy() {
+        let mut group = ResctrlGroup {
+            name: "web".to_string(),
+            dir: PathBuf::new(),
+            last_total_bytes: HashMap::new(),
+            last_update: None,
+            bandwidth: 0.0,
+            occupancy: 0,
+        };
+
+        // First read only sets the baseline
+        let first = HashMap::from([
+            (0, domain(Some(1_000), Some(2_048))),
+            (1, domain(Some(5_000), None)),
+        ]);
+        group.apply(&first, None);
+        assert_eq!(group.bandwidth(), 0.0);
+        assert_eq!(group.occupancy(), 2_048);
+
+        // Domain 1 was recreated and restarts from a lower value
+        let second = HashMap::from([
+            (0, domain(Some(3_000), Some(1_024))),
+            (1, domain(Some(100), Some(1_024))),
+        ]);
+        group.apply(&second, Some(2.0));
+        assert_eq!(group.bandwidth(), 1_000.0);
+        assert_eq!(group.occupancy(), 2_048);
+
+        let third = HashMap::from([(0, domain(None, None)), (1, domain(Some(600), Some(512)))]);
+        group.apply(&third, Some(1.0));
+        assert_eq!(group.bandwidth(), 500.0);
+        assert_eq!(group.occupancy(), 512);
+    }
+}
@@ -0,0 +1,139 @@
+// SST (Speed Select Technology) status reader
+//
+// Read-only: reports the active SST-PP level of each socket and the SST-CP priority
+// class (class of service) of each monitored core. Both change only when an
+// administrator reconfigures the part, but they decide how the frequency metrics
+// are to be read, so they are re-read every collection.
+
+use std::collections::BTreeMap;
+
+use crate::common::msr::{self, MsrAccess};
+use crate::config::ExportConfig;
+use crate::error::{Result, UncflowError};
+
+use uncflow_raw::current_arch::rdt::PqrAssoc;
+use uncflow_raw::current_arch::sst::{self, ConfigTdpControl, PlatformInfo};
+use uncflow_raw::RegisterLayout;
+
+/// One reading of the SST configuration
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SstStatus {
+    /// Active SST-PP level per socket, 0 is the nominal profile
+    pub config_levels: BTreeMap<i32, u8>,
+    /// SST-CP priority class per core, 0 is the highest priority
+    pub core_priorities: BTreeMap<i32, u32>,
+}
+
+pub struct SstMonitor {
+    msr: &'static dyn MsrAccess,
+    // CPU each socket's package-scoped MSRs are read from
+    socket_cpus: BTreeMap<i32, u32>,
+    cores: Vec<i32>,
+}
+
+impl SstMonitor {
+    pub fn new(config: &ExportConfig) -> Result<Self> {
+        Self::with_msr(config, msr::Msr::instance())
+    }
+
+    /// Monitor reading the SST MSRs through `msr`, failing if no socket supports SST-PP
+    pub fn with_msr(config: &ExportConfig, msr: &'static dyn MsrAccess) -> Result<Self> {
+        let mut socket_cpus = BTreeMap::new();
+        for &socket in &config.sockets {
+            let Some(cpu) = Self::socket_cpu(config, socket) else {
+                tracing::warn!("No monitored core on socket {socket}, skipping its SST level");
+                continue;
+            };
+
+            let platform_info =
+                PlatformInfo::from_msr_value(msr.read(cpu, sst::msr::MSR_PLATFORM_INFO)?);
+            if platform_info.has_sst_pp() {
+                tracing::info!(
+                    "Socket {} has {} configurable TDP levels",
+                    socket,
+                    platform_info.config_tdp_levels
+                );
+                socket_cpus.insert(socket, cpu);
+            } else {
+                tracing::warn!("Socket {socket} does not support SST-PP");
+            }
+        }
+
+        if socket_cpus.is_empty() {
+            return Err(UncflowError::HardwareError(
+                "SST-PP is not supported on any monitored socket".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            msr,
+            socket_cpus,
+            cores: config.cores.clone(),
+        })
+    }
+
+    /// The uncore core override of `socket`, else its first monitored core
+    fn socket_cpu(config: &ExportConfig, socket: i32) -> Option<u32> {
+        config.uncore_cores.get(&socket).copied().or_else(|| {
+            config
+                .cores
+                .iter()
+                .map(|&core| core as u32)
+                .find(|&core| ExportConfig::core_socket(core) == Some(socket))
+        })
+    }
+
+    /// Read the active level of every supported socket and the class of every core
+    pub fn collect(&self) -> Result<SstStatus> {
+        let mut status = SstStatus::default();
+        for (&socket, &cpu) in &self.socket_cpus {
+            let control = self.msr.read(cpu, sst::msr::MSR_CONFIG_TDP_CONTROL)?;
+            status
+                .config_levels
+                .insert(socket, ConfigTdpControl::from_msr_value(control).level);
+        }
+        for &core in &self.cores {
+            let assoc = self.msr.read(core as u32, sst::msr::IA32_PQR_ASSOC)?;
+            status
+                .core_priorities
+                .insert(core, PqrAssoc::from_msr_value(assoc).cos);
+        }
+        Ok(status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::msr::mock::MockMsr;
+    use std::collections::HashMap;
+
+    fn config() -> ExportConfig {
+        ExportConfig::new(vec![0], vec![2, 3]).with_uncore_cores(HashMap::from([(0, 2)]))
+    }
+
+    #[test]
+    fn test_collect_decodes_level_and_priority() {
+        let msr = MockMsr::new().leak();
+        msr.set(2, sst::msr::MSR_PLATFORM_INFO, 0x0000_0004_0000_1700);
+        msr.set(2, sst::msr::MSR_CONFIG_TDP_CONTROL, 0x8000_0001);
+        msr.set(3, sst::msr::IA32_PQR_ASSOC, 0x0000_0002_0000_0005);
+
+        let status = SstMonitor::with_msr(&config(), msr)
+            .unwrap()
+            .collect()
+            .unwrap();
+        assert_eq!(status.config_levels, BTreeMap::from([(0, 1)]));
+        assert_eq!(status.core_priorities, BTreeMap::from([(2, 0), (3, 2)]));
+    }
+
+    #[test]
+    fn test_unsupported_part_is_detected() {
+        // No configurable TDP levels
+        let msr = MockMsr::new().leak();
+        msr.set(2, sst::msr::MSR_PLATFORM_INFO, 0x1700);
+        assert!(SstMonitor::with_msr(&config(), msr).is_err());
+        // Detection only reads
+        assert_eq!(msr.write_count(), 0);
+    }
+}
@@ -0,0 +1,182 @@
+// Thermal status reader
+//
+// Read-only: the temperature target of every monitored core and socket is read
+// once at startup, then each collection reads IA32_THERM_STATUS of every monitored
+// core and IA32_PACKAGE_THERM_STATUS of every socket. Temperatures are TjMax minus
+// the sensor readout; throttling is the current TCC or PROCHOT# status, since the
+// sticky log bits would need a write to clear and are left to the kernel.
+
+use std::collections::BTreeMap;
+
+use crate::common::msr::{self, MsrAccess};
+use crate::config::ExportConfig;
+use crate::error::{Result, UncflowError};
+
+use uncflow_raw::current_arch::thermal::{self, TemperatureTarget, ThermStatus};
+use uncflow_raw::register::RegisterLayout;
+
+/// Temperature and throttling of one core or package
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThermalReading {
+    /// Degrees Celsius, None while the sensor reports no valid reading
+    pub temperature: Option<f64>,
+    /// Throttled by TCC or PROCHOT# at the time of the read
+    pub throttling: bool,
+}
+
+impl ThermalReading {
+    /// Reading of `status` against `target`; the package register has no valid bit
+    pub fn decode(status: ThermStatus, target: &TemperatureTarget, package: bool) -> Self {
+        Self {
+            temperature: (package || status.valid).then(|| status.temperature_celsius(target)),
+            throttling: status.throttling(),
+        }
+    }
+}
+
+/// Readings of one collection
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ThermalStatus {
+    pub core: BTreeMap<i32, ThermalReading>,
+    pub package: BTreeMap<i32, ThermalReading>,
+}
+
+pub struct ThermalMonitor {
+    msr: &'static dyn MsrAccess,
+    // Temperature target of each monitored core
+    cores: BTreeMap<i32, TemperatureTarget>,
+    // CPU each socket's package register is read from, with its target
+    sockets: BTreeMap<i32, (u32, TemperatureTarget)>,
+}
+
+impl ThermalMonitor {
+    pub fn new(config: &ExportConfig) -> Result<Self> {
+        Self::with_msr(config, msr::Msr::instance())
+    }
+
+    /// Monitor reading the thermal MSRs through `msr`, failing if the temperature
+    /// target is not readable
+    pub fn with_msr(config: &ExportConfig, msr: &'static dyn MsrAccess) -> Result<Self> {
+        let target = |cpu: u32| -> Result<TemperatureTarget> {
+            let value = msr
+                .read(cpu, thermal::msr::MSR_TEMPERATURE_TARGET)
+                .map_err(|e| {
+                    UncflowError::HardwareError(format!(
+                        "Temperature target of CPU {cpu} is not readable: {e}"
+                    ))
+                })?;
+            Ok(TemperatureTarget::from_msr_value(value))
+        };
+
+        let mut cores = BTreeMap::new();
+        for &core in &config.cores {
+            cores.insert(core, target(core as u32)?);
+        }
+
+        let mut sockets = BTreeMap::new();
+        for &socket in &config.sockets {
+            let cpu = config.uncore_cores.get(&socket).copied().or_else(|| {
+                config
+                    .cores
+                    .iter()
+                    .map(|&core| core as u32)
+                    .find(|&core| ExportConfig::core_socket(core) == Some(socket))
+            });
+            match cpu {
+                Some(cpu) => {
+                    sockets.insert(socket, (cpu, target(cpu)?));
+                }
+                None => tracing::warn!(
+                    "No monitored core on socket {socket}, skipping its package temperature"
+                ),
+            }
+        }
+
+        if let Some((_, target)) = sockets.values().next() {
+            tracing::info!(
+                "TjMax {} C, TCC throttling from {} C",
+                target.tj_max,
+                target.throttle_celsius()
+            );
+        }
+
+        Ok(Self {
+            msr,
+            cores,
+            sockets,
+        })
+    }
+
+    fn read(&self, cpu: u32, address: u64) -> Result<ThermStatus> {
+        Ok(ThermStatus::from_msr_value(self.msr.read(cpu, address)?))
+    }
+
+    /// Read the thermal status of every core and socket
+    pub fn collect(&self) -> Result<ThermalStatus> {
+        let mut status = ThermalStatus::default();
+        for (&core, target) in &self.cores {
+            let therm = self.read(core as u32, thermal::msr::IA32_THERM_STATUS)?;
+            status
+                .core
+                .insert(core, ThermalReading::decode(therm, target, false));
+        }
+        for (&socket, (cpu, target)) in &self.sockets {
+            let therm = self.read(*cpu, thermal::msr::IA32_PACKAGE_THERM_STATUS)?;
+            status
+                .package
+                .insert(socket, ThermalReading::decode(therm, target, true));
+        }
+        Ok(status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::msr::mock::MockMsr;
+    use std::collections::HashMap;
+    use thermal::msr::*;
+
+    #[test]
+    fn test_collect_decodes_temperature_and_throttling() {
+        let msr = MockMsr::new().leak();
+        for cpu in [2, 3] {
+            msr.set(cpu, MSR_TEMPERATURE_TARGET, 0x0064_0000);
+        }
+        // Core 2 at 55 C, core 3 PROCHOT-throttled with no valid reading
+        msr.set(2, IA32_THERM_STATUS, 0x802D_0000);
+        msr.set(3, IA32_THERM_STATUS, 0x0000_0004);
+        msr.set(2, IA32_PACKAGE_THERM_STATUS, 0x0019_0001);
+
+        let config =
+            ExportConfig::new(vec![0], vec![2, 3]).with_uncore_cores(HashMap::from([(0, 2)]));
+        let monitor = ThermalMonitor::with_msr(&config, msr).unwrap();
+        let status = monitor.collect().unwrap();
+
+        assert_eq!(
+            status.core[&2],
+            ThermalReading {
+                temperature: Some(55.0),
+                throttling: false
+            }
+        );
+        assert_eq!(
+            status.core[&3],
+            ThermalReading {
+                temperature: None,
+                throttling: true
+            }
+        );
+        assert_eq!(
+            status.package[&0],
+            ThermalReading {
+                temperature: Some(75.0),
+                throttling: true
+            }
+        );
+        assert_eq!(msr.write_count(), 0);
+
+        msr.fail_reads(3, MSR_TEMPERATURE_TARGET);
+        assert!(ThermalMonitor::with_msr(&config, msr).is_err());
+    }
+}
@@ -0,0 +1,239 @@
+// UPI (Ultra Path Interconnect) Monitor
+//
+// Counts data flits on each inter-socket link and converts them to bandwidth
+
+use crate::common::counter::{rate_window_secs, wrapping_delta};
+use crate::common::{pci, pmon, read_stats, register, ReadCounter};
+use crate::error::{Result, UncflowError};
+use crate::metrics::upi::UpiMetric;
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+// Import hardware definitions from uncflow-raw
+use uncflow_raw::current_arch::upi::{self, UpiCounterControl};
+use uncflow_raw::RegisterLayout;
+
+// Counter assignment: (event, umask) per counter
+const UPI_EVENTS: [(u8, u8); 3] = [
+    (upi::events::CLOCKTICKS, 0x00),
+    (upi::events::TXL_FLITS, upi::umasks::ALL_DATA),
+    (upi::events::RXL_FLITS, upi::umasks::ALL_DATA),
+];
+
+// Counter controls for UPI_EVENTS
+fn counter_controls() -> [UpiCounterControl; 3] {
+    UPI_EVENTS.map(|(event, umask)| UpiCounterControl {
+        event_select: event,
+        unit_mask: umask,
+        enable: true,
+        ..Default::default()
+    })
+}
+
+// PCI-based UPI link layer counter unit
+#[derive(Debug)]
+struct UpiLinkUnit {
+    link: usize,
+    pci_addr: pci::PciConfigAddress,
+}
+
+impl UpiLinkUnit {
+    fn new(socket: u32, link: usize) -> Result<Self> {
+        let pci_addr = pci::PciConfigAddress {
+            socket,
+            device: upi::pci::UPI_LINK_DEVICE[link],
+            function: upi::pci::UPI_LINK_FUNCTION,
+            device_id: upi::UPI_DEVICE_ID,
+        };
+
+        pci::Pci::instance().probe(&pci_addr, &format!("UPI link {link}"))?;
+
+        Ok(Self { link, pci_addr })
+    }
+
+    fn program(&self) -> Result<()> {
+        let pci = pci::Pci::instance();
+        let write_box_ctl =
+            |value| pci.write32(&self.pci_addr, upi::pci::UPI_UNIT_CTL_ADDR, value as u32);
+        pmon::program_frozen(write_box_ctl, || {
+            for (i, ctrl) in counter_controls().iter().enumerate() {
+                // 64-bit control: the extended umask is in the upper dword
+                pci.write64(
+                    &self.pci_addr,
+                    upi::pci::UPI_CTL_ADDR[i],
+                    ctrl.to_msr_value(),
+                )?;
+            }
+            Ok(())
+        })
+    }
+
+    fn read_counters(&self, reads: &ReadCounter) -> Result<[u64; 3]> {
+        let pci = pci::Pci::instance();
+        let mut values = [0u64; 3];
+        for (i, value) in values.iter_mut().enumerate() {
+            *value = reads.observe(pci.read64(&self.pci_addr, upi::pci::UPI_CTR_ADDR[i]))?
+                & upi::COUNTER_MASK;
+        }
+        Ok(values)
+    }
+}
+
+/// Names of the link counters, in delta order, as exported raw
+pub const LINK_COUNTER_NAMES: [&str; 3] = ["clockticks", "tx_data_flits", "rx_data_flits"];
+
+/// Compute link metrics from counter deltas [clockticks, tx data flits, rx data flits]
+pub fn calculate_link_metrics(deltas: &[u64; 3], elapsed: Duration) -> HashMap<UpiMetric, f64> {
+    let mut metrics = HashMap::new();
+    let Some(elapsed_s) = rate_window_secs(elapsed) else {
+        return metrics;
+    };
+
+    metrics.insert(UpiMetric::UPIFrequency, deltas[0] as f64 / elapsed_s / 1e9);
+    metrics.insert(
+        UpiMetric::UPITxBandwidth,
+        deltas[1] as f64 * upi::BYTES_PER_DATA_FLIT / elapsed_s / 1e9,
+    );
+    metrics.insert(
+        UpiMetric::UPIRxBandwidth,
+        deltas[2] as f64 * upi::BYTES_PER_DATA_FLIT / elapsed_s / 1e9,
+    );
+
+    metrics
+}
+
+#[derive(Debug)]
+pub struct UpiMonitor {
+    socket: i32,
+    links: Vec<UpiLinkUnit>,
+    prev_counters: HashMap<usize, [u64; 3]>,
+    last_time: Option<Instant>,
+    // Deltas the last metrics were calculated from, per link
+    last_deltas: BTreeMap<usize, [u64; 3]>,
+    // Counter reads and failed reads of this socket
+    reads: ReadCounter,
+}
+
+impl UpiMonitor {
+    pub fn new(socket: i32) -> Result<Self> {
+        Self::validate_program()?;
+
+        let mut links = Vec::new();
+        for link in 0..upi::UPI_LINK_COUNT {
+            match UpiLinkUnit::new(socket as u32, link) {
+                Ok(unit) => {
+                    unit.program()?;
+                    links.push(unit);
+                }
+                Err(e) => {
+                    tracing::debug!("UPI link {} unavailable on socket {}: {}", link, socket, e);
+                }
+            }
+        }
+
+        if links.is_empty() {
+            return Err(UncflowError::HardwareError(format!(
+                "No UPI links found on socket {socket}"
+            )));
+        }
+
+        tracing::info!(
+            "Initialized UPI monitor for socket {} with {} links",
+            socket,
+            links.len()
+        );
+
+        Ok(Self {
+            socket,
+            links,
+            prev_counters: HashMap::new(),
+            last_time: None,
+            last_deltas: BTreeMap::new(),
+            reads: read_stats().counter("upi", socket),
+        })
+    }
+
+    /// Build and validate the counter controls without touching hardware
+    pub fn validate_program() -> Result<()> {
+        register::validate_all("UPI counters", &counter_controls())
+    }
+
+    /// Collect per-link metrics, keyed by link index (empty on the first call)
+    pub fn collect_metrics(&mut self) -> Result<HashMap<(usize, UpiMetric), f64>> {
+        let mut metrics = HashMap::new();
+        let now = Instant::now();
+        if self
+            .last_time
+            .is_some_and(|t| rate_window_secs(now.duration_since(t)).is_none())
+        {
+            // Too soon after the last read: keep the baselines so the next window covers both
+            return Ok(HashMap::new());
+        }
+        let elapsed = self.last_time.map(|t| now.duration_since(t));
+
+        for unit in &self.links {
+            let current = unit.read_counters(&self.reads)?;
+
+            if let (Some(prev), Some(elapsed)) = (self.prev_counters.get(&unit.link), elapsed) {
+                let mut deltas = [0u64; 3];
+                for i in 0..3 {
+                    deltas[i] = wrapping_delta(prev[i], current[i], upi::COUNTER_WIDTH_BITS);
+                }
+
+                for (metric, value) in calculate_link_metrics(&deltas, elapsed) {
+                    metrics.insert((unit.link, metric), value);
+                }
+                self.last_deltas.insert(unit.link, deltas);
+            }
+
+            self.prev_counters.insert(unit.link, current);
+        }
+
+        self.last_time = Some(now);
+        Ok(metrics)
+    }
+
+    /// Drop the baselines, the next collect_metrics() is a first sample
+    pub fn reset(&mut self) {
+        self.prev_counters.clear();
+        self.last_time = None;
+        self.last_deltas.clear();
+    }
+
+    /// Counter deltas [clockticks, tx data flits, rx data flits] the last metrics were
+    /// calculated from, keyed by link index
+    pub fn link_deltas(&self) -> &BTreeMap<usize, [u64; 3]> {
+        &self.last_deltas
+    }
+
+    /// Link indices present on this socket
+    pub fn links(&self) -> Vec<usize> {
+        self.links.iter().map(|l| l.link).collect()
+    }
+
+    pub fn socket(&self) -> i32 {
+        self.socket
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bandwidth_calculation() {
+        // 9 data flits carry one 64-byte cache line
+        let deltas = [2_000_000_000, 9_000_000, 18_000_000];
+        let metrics = calculate_link_metrics(&deltas, Duration::from_secs(2));
+
+        assert!((metrics[&UpiMetric::UPIFrequency] - 1.0).abs() < 1e-9);
+        assert!((metrics[&UpiMetric::UPITxBandwidth] - 0.032).abs() < 1e-9);
+        assert!((metrics[&UpiMetric::UPIRxBandwidth] - 0.064).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_short_window_yields_no_rates() {
+        let deltas = [2_000_000, 9_000, 18_000];
+        assert!(calculate_link_metrics(&deltas, Duration::from_micros(50)).is_empty());
+    }
+}
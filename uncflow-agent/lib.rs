@@ -8,14 +8,20 @@ pub mod counters;
 pub mod error;
 pub mod metrics;
 pub mod orchestrator;
+pub mod output;
 pub mod prom;
 
 pub use config::ExportConfig;
 pub use error::{Result, UncflowError};
-pub use orchestrator::{CollectorConfig, MetricCollector};
+pub use orchestrator::{
+    CollectorConfig, MetricCollector, MetricFilter, MetricStream, Readiness, ScrapeCollector,
+    SelfMetrics, Sessions, SpikeDetector, SpikeRule, Subsystem, UncflowCollector,
+};
 
 // Re-export for backward compatibility
 pub use prom::{
-    ChaMetricExporter, CoreMetricExporter, IioMetricExporter, ImcMetricExporter, IrpMetricExporter,
-    RaplMetricExporter, RdtMetricExporter,
+    ChaCustomMetricExporter, ChaMetricExporter, CoreMetricExporter, CstateMetricExporter,
+    IioMetricExporter, ImcMetricExporter, IrpMetricExporter, M2mMetricExporter, PcuMetricExporter,
+    RaplMetricExporter, RdtMetricExporter, SstMetricExporter, ThermalMetricExporter,
+    UpiMetricExporter,
 };
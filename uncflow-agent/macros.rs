@@ -1,6 +1,6 @@
 //! Declarative macros to reduce boilerplate across the uncflow codebase
 
-/// Define a metric enum with automatic `name()` and `all()` implementations
+/// Define a metric enum with automatic `name()`, `all()` and parsing implementations
 ///
 /// # Example
 /// ```
@@ -18,12 +18,14 @@
 /// let metric = RaplMetric::PackageEnergy;
 /// assert_eq!(metric.name(), "PackageEnergy");
 /// assert_eq!(RaplMetric::all().len(), 3);
+/// assert_eq!("DRAMEnergy".parse::<RaplMetric>().unwrap(), RaplMetric::DramEnergy);
 /// ```
 ///
 /// Expands to:
 /// - An enum with Debug, Clone, Copy, PartialEq, Eq, Hash derives
 /// - A `name(&self) -> &'static str` method
 /// - An `all() -> Vec<Self>` method
+/// - `FromStr` and `TryFrom<&str>`, the inverse of `name()`
 #[macro_export]
 macro_rules! metric_enum {
     (
@@ -49,84 +51,26 @@ macro_rules! metric_enum {
                 vec![$($name::$variant,)*]
             }
         }
-    };
-}
 
-/// Initialize an exporter with standard error handling
-///
-/// # Example
-/// ```ignore
-/// // In orchestrator::collector::Collector::new()
-/// init_exporter!(
-///     collector,
-///     collector_config,
-///     config,
-///     rapl_exporter,
-///     rapl,
-///     RaplMetricExporter,
-///     "RAPL"
-/// );
-/// ```
-#[macro_export]
-macro_rules! init_exporter {
-    (
-        $collector:expr,
-        $collector_config:expr,
-        $config:expr,
-        $field:ident,
-        $flag:ident,
-        $Exporter:ty,
-        $name:literal
-    ) => {
-        if $collector_config.$flag {
-            match <$Exporter>::new($config.clone()) {
-                Ok(exporter) => {
-                    $collector.$field = Some(std::sync::Arc::new(exporter));
-                    tracing::info!(concat!($name, " exporter initialized"));
-                }
-                Err(e) => {
-                    tracing::error!(concat!("Failed to initialize ", $name, " exporter: {}"), e);
+        impl std::str::FromStr for $name {
+            type Err = $crate::error::UncflowError;
+
+            fn from_str(s: &str) -> $crate::error::Result<Self> {
+                match s {
+                    $($str => Ok($name::$variant),)*
+                    _ => Err($crate::error::UncflowError::ParseError(format!(
+                        concat!("Unknown ", stringify!($name), " '{}'"),
+                        s
+                    ))),
                 }
             }
         }
-    };
-}
 
-/// Spawn a collector task for an exporter if it exists
-///
-/// # Example
-/// ```ignore
-/// // In orchestrator::collector::Collector::collection_loop()
-/// let mut tasks = Vec::new();
-/// spawn_collector!(tasks, &self.rapl_exporter);
-/// ```
-#[macro_export]
-macro_rules! spawn_collector {
-    ($tasks:expr, $exporter:expr) => {
-        if let Some(exporter) = $exporter {
-            let exp = std::sync::Arc::clone(exporter);
-            $tasks.push(tokio::spawn(async move {
-                exp.collect().await;
-            }));
-        }
-    };
-}
+        impl TryFrom<&str> for $name {
+            type Error = $crate::error::UncflowError;
 
-/// Gather metrics from an exporter's registry
-///
-/// # Example
-/// ```ignore
-/// // In main.rs metrics handler
-/// let mut buffer = Vec::new();
-/// gather_metrics!(buffer, encoder, state.rapl_exporter, "RAPL");
-/// ```
-#[macro_export]
-macro_rules! gather_metrics {
-    ($buffer:expr, $encoder:expr, $exporter:expr, $name:literal) => {
-        if let Some(ref exporter) = $exporter {
-            let metric_families = exporter.registry().gather();
-            if let Err(e) = $encoder.encode(&metric_families, &mut $buffer) {
-                tracing::error!(concat!("Failed to encode ", $name, " metrics: {}"), e);
+            fn try_from(s: &str) -> $crate::error::Result<Self> {
+                s.parse()
             }
         }
     };
@@ -142,7 +86,7 @@ macro_rules! gather_metrics {
 ///     pub enum LLCState: u32 {
 ///         M => ("M", 0x40),
 ///         E => ("E", 0x20),
-///         S => ("S", 0x02),
+///         S => ("S", 0x10),
 ///         I => ("I", 0x01),
 ///     }
 ///     impl value -> u32
@@ -1,95 +1,920 @@
-use axum::{response::IntoResponse, routing::get, Router};
-use clap::Parser;
+use axum::{
+    http::{header, HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
+    routing::{get, post},
+    Json, Router,
+};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser};
+use prometheus::proto::MetricFamily;
 use prometheus::{Encoder, TextEncoder};
 use std::net::SocketAddr;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::signal;
+use tokio::sync::broadcast::error::RecvError;
 use tokio_util::sync::CancellationToken;
 
+use uncflow::common::pci::{PciAddress, PciHandle};
+use uncflow::common::{
+    lock, msr, read_stats, set_msr_pinning, CoreList, CounterBackend, Msr, MsrAccess, SysRoots,
+    UncoreLock, NO_MSR_PINNING_ENV,
+};
+use uncflow::counters::imc::max_sample_count;
+use uncflow::orchestrator::{ReadTimeout, COLLECTION_PERIOD, DEFAULT_READ_TIMEOUT};
+use uncflow::output::{influx, openmetrics};
+use uncflow::prom::cha::ChaUnavailable;
+use uncflow::prom::state::CounterState;
 use uncflow::{
-    ChaMetricExporter, CollectorConfig, CoreMetricExporter, ExportConfig, IioMetricExporter,
-    ImcMetricExporter, IrpMetricExporter, MetricCollector, RaplMetricExporter, RdtMetricExporter,
-    Result,
+    CollectorConfig, ExportConfig, MetricCollector, MetricFilter, MetricStream, Readiness, Result,
+    ScrapeCollector, SelfMetrics, Sessions, SpikeDetector, SpikeRule, Subsystem, UncflowError,
 };
 
+/// Named bundle of subsystem flags for `--profile`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Profile {
+    /// IMC, CHA and M2M traffic plus RAPL (DRAM) power, with socket bandwidth totals
+    Memory,
+    /// IIO, IRP and UPI traffic, with socket PCIe bandwidth totals
+    Io,
+    /// RAPL power and core frequency/IPC
+    Power,
+    /// Every subsystem
+    Full,
+}
+
+impl std::str::FromStr for Profile {
+    type Err = UncflowError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "memory" => Ok(Self::Memory),
+            "io" => Ok(Self::Io),
+            "power" => Ok(Self::Power),
+            "full" => Ok(Self::Full),
+            _ => Err(UncflowError::ParseError(format!(
+                "Unknown profile '{s}', expected memory, io, power or full"
+            ))),
+        }
+    }
+}
+
+impl Profile {
+    /// Families the profile narrows its subsystems to; the rest export everything
+    fn metric_filter(self) -> MetricFilter {
+        match self {
+            Profile::Memory => MetricFilter::default()
+                .allow("cha", &["bandwidth"])
+                .allow("rapl", &["dram"]),
+            Profile::Io | Profile::Power | Profile::Full => MetricFilter::default(),
+        }
+    }
+
+    /// Turn on the profile's flags and filter; flags given on the command line win,
+    /// so `--cha=false` turns off a subsystem of the profile and `--rapl` exports all
+    /// of RAPL instead of the profile's families
+    fn apply(self, args: &mut Args, matches: &ArgMatches) {
+        let explicit = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+        let enable = |id: &str, flag: &mut bool| {
+            if !explicit(id) {
+                *flag = true;
+            }
+        };
+        match self {
+            Profile::Memory => {
+                enable("imc", &mut args.imc);
+                enable("cha", &mut args.cha);
+                enable("m2m", &mut args.m2m);
+                enable("rapl", &mut args.rapl);
+                enable("rollups", &mut args.rollups);
+            }
+            Profile::Io => {
+                enable("iio", &mut args.iio);
+                enable("irp", &mut args.irp);
+                enable("upi", &mut args.upi);
+                enable("rollups", &mut args.rollups);
+            }
+            Profile::Power => {
+                enable("rapl", &mut args.rapl);
+                enable("core_metrics", &mut args.core_metrics);
+                enable("cstate", &mut args.cstate);
+                enable("thermal", &mut args.thermal);
+                enable("pcu", &mut args.pcu);
+            }
+            Profile::Full => {
+                enable("uncore", &mut args.uncore);
+                enable("rapl", &mut args.rapl);
+                enable("rdt", &mut args.rdt);
+                enable("sst", &mut args.sst);
+                enable("cstate", &mut args.cstate);
+                enable("thermal", &mut args.thermal);
+                enable("pcu", &mut args.pcu);
+                enable("core_metrics", &mut args.core_metrics);
+            }
+        }
+
+        args.metric_filter = self.metric_filter();
+        for subsystem in ["rapl", "imc", "cha", "irp", "iio", "upi", "m2m"] {
+            if explicit(subsystem) {
+                args.metric_filter.unrestrict(subsystem);
+            }
+        }
+    }
+}
+
+/// Log line format for `--log-format`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per event, for log aggregation pipelines
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = UncflowError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(UncflowError::ParseError(format!(
+                "Unknown log format '{s}', expected 'text' or 'json'"
+            ))),
+        }
+    }
+}
+
 #[derive(Parser, Debug)]
 #[command(name = "uncflow")]
 #[command(about = "Hardware performance monitoring for Intel CPUs")]
 struct Args {
-    #[arg(long, help = "Enable core metrics")]
+    #[arg(
+        long,
+        value_name = "memory|io|power|full",
+        help = "Enable a preset group of subsystems: memory (IMC, CHA and RAPL DRAM bandwidth/power, M2M, --rollups), io (IIO, IRP, UPI, --rollups), power (RAPL, core, C-state, thermal, PCU), full (everything); flags given explicitly win, e.g. --cha=false drops CHA and --rapl exports all of RAPL"
+    )]
+    profile: Option<Profile>,
+
+    // Families exported per subsystem, set by --profile
+    #[arg(skip)]
+    metric_filter: MetricFilter,
+
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_value_t = false,
+        require_equals = true,
+        default_missing_value = "true",
+        action = clap::ArgAction::Set,
+        help = "Enable core metrics"
+    )]
     core_metrics: bool,
 
-    #[arg(long, help = "Enable all uncore metrics (IMC, CHA, IRP, IIO)")]
+    #[arg(
+        long,
+        help = "Also count cycles per AVX frequency license (multiplexed with the core events, MSR backend only) and export core_avx_license_cycles_ratio"
+    )]
+    core_avx_license: bool,
+
+    #[arg(
+        long,
+        help = "Also count LLC misses served by local vs remote DRAM with offcore response events (multiplexed with the core events, MSR backend only)"
+    )]
+    core_offcore: bool,
+
+    #[arg(
+        long,
+        help = "Also export core metrics per socket as core_socket_<metric>{socket}: counts summed over the monitored cores, ratios such as IPC recomputed from the sums"
+    )]
+    core_socket_rollup: bool,
+
+    #[arg(
+        long = "core-event",
+        value_name = "EVENT",
+        help = "Also count a core PMU event given perf-style, e.g. cpu/event=0x24,umask=0x38/u or r3824:k, exported as core_event_count (multiplexed with the core events, MSR backend only; can be specified multiple times)",
+        action = clap::ArgAction::Append
+    )]
+    core_events: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Read --core-event descriptors from a file, one per line ('#' starts a comment)"
+    )]
+    core_events_file: Option<std::path::PathBuf>,
+
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_value_t = false,
+        require_equals = true,
+        default_missing_value = "true",
+        action = clap::ArgAction::Set,
+        help = "Enable all uncore metrics (IMC, CHA, IRP, IIO, UPI, M2M)"
+    )]
     uncore: bool,
 
-    #[arg(long, help = "Enable IMC (Integrated Memory Controller) metrics")]
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_value_t = false,
+        require_equals = true,
+        default_missing_value = "true",
+        action = clap::ArgAction::Set,
+        help = "Enable IMC (Integrated Memory Controller) metrics"
+    )]
     imc: bool,
 
     #[arg(
         long,
+        help = "Also export IMC bandwidth and queue occupancy per channel with a channel label"
+    )]
+    imc_per_channel: bool,
+
+    #[arg(
+        long,
+        value_name = "GBPS",
+        help = "Peak memory bandwidth per socket in GB/s for imc_bandwidth_saturation_ratio (default: DIMM speed from DMI x IMC channels)"
+    )]
+    memory_peak_bandwidth_gbps: Option<f64>,
+
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_value_t = false,
+        require_equals = true,
+        default_missing_value = "true",
+        action = clap::ArgAction::Set,
         help = "Enable CHA (Cache Agent/Home Agent) comprehensive metrics (142 metrics)"
     )]
     cha: bool,
 
-    #[arg(long, help = "Enable IRP (IO Request Processing) metrics")]
+    #[arg(
+        long,
+        help = "Also export CHA counters per box with a cha_box label (one series per box)"
+    )]
+    cha_per_box: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Read only N evenly spaced CHA boxes per socket and scale the aggregate CHA metrics to all boxes (cheaper, assumes uniform traffic across boxes)"
+    )]
+    cha_sample_boxes: Option<u64>,
+
+    #[arg(
+        long,
+        help = "Also export CHAMetricAgeSeconds, how long ago each rotated CHA metric was measured (one series per CHA metric)"
+    )]
+    cha_metric_age: bool,
+
+    #[arg(
+        long,
+        default_value = "omit",
+        value_name = "omit|nan",
+        help = "CHA metrics of sockets whose CHA counters are locked: omit them, or export them as NaN; CHAUp reports which sockets are counted either way"
+    )]
+    cha_unavailable: ChaUnavailable,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Program the CHA events of a JSON event file (event, umask, filters, counter, derived-metric formulas) and export each under its name in the file; replaces the built-in CHA metrics of --cha"
+    )]
+    events_from_json: Option<std::path::PathBuf>,
+
+    #[arg(
+        long,
+        help = "Count DRAM activates and page-miss precharges to export imc_page_{hit,miss,conflict}_ratio; replaces IMC queue occupancy, so read/write latency is not measured"
+    )]
+    imc_page_events: bool,
+
+    #[arg(
+        long,
+        help = "Count IMC thermal throttle cycles and read DIMM temperatures to export imc_thermal_throttle_cycles and imc_dimm_temperature_celsius; replaces WPQ occupancy, so write latency is not measured"
+    )]
+    imc_thermal: bool,
+
+    #[arg(
+        long,
+        help = "Count IIO IOMMU (VT-d) lookups, misses and page walks to export IOMMUCacheHitRatio and IOMMUPageWalkLatency; adds one event group window to every IIO collection"
+    )]
+    iio_iommu: bool,
+
+    #[arg(
+        long,
+        help = "Label the per-port IIO PCIe series with the device behind each root port (pci_vendor, pci_device, pci_device_name), re-read every minute to follow hotplug"
+    )]
+    iio_device_labels: bool,
+
+    #[arg(
+        long,
+        help = "Also export raw per-interval counter deltas (CHA, IMC, UPI, M2M) for debugging derived metrics"
+    )]
+    export_raw: bool,
+
+    #[arg(
+        long,
+        help = "Export per-window event counts (CHA LLC lookups, victims, SF evictions and no-credit stalls, IIO TLB/cache hits and misses) as per-second rates under the same names"
+    )]
+    count_rates: bool,
+
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_value_t = false,
+        require_equals = true,
+        default_missing_value = "true",
+        action = clap::ArgAction::Set,
+        help = "Also export socket totals of per-channel/per-port bandwidth (uncflow_socket_total_memory_bandwidth, uncflow_socket_total_pcie_bandwidth)"
+    )]
+    rollups: bool,
+
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_value_t = false,
+        require_equals = true,
+        default_missing_value = "true",
+        action = clap::ArgAction::Set,
+        help = "Enable IRP (IO Request Processing) metrics"
+    )]
     irp: bool,
 
-    #[arg(long, help = "Enable IIO (Integrated IO) metrics")]
+    #[arg(
+        long,
+        help = "Also export IRP metrics per IRP unit with an irp_unit label"
+    )]
+    irp_per_unit: bool,
+
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_value_t = false,
+        require_equals = true,
+        default_missing_value = "true",
+        action = clap::ArgAction::Set,
+        help = "Enable IIO (Integrated IO) metrics"
+    )]
     iio: bool,
 
-    #[arg(long, help = "Enable Intel RDT metrics (MBM)")]
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_value_t = false,
+        require_equals = true,
+        default_missing_value = "true",
+        action = clap::ArgAction::Set,
+        help = "Enable UPI (Ultra Path Interconnect) link metrics"
+    )]
+    upi: bool,
+
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_value_t = false,
+        require_equals = true,
+        default_missing_value = "true",
+        action = clap::ArgAction::Set,
+        help = "Enable M2M (Mesh-to-Memory) directory metrics"
+    )]
+    m2m: bool,
+
+    #[arg(
+        long = "imc-socket",
+        value_name = "LIST",
+        help = "Enable IMC on these sockets only (supports ranges, can be specified multiple times; default: every monitored socket)",
+        action = clap::ArgAction::Append
+    )]
+    imc_sockets: Vec<String>,
+
+    #[arg(
+        long = "cha-socket",
+        value_name = "LIST",
+        help = "Enable CHA on these sockets only (supports ranges, can be specified multiple times; default: every monitored socket)",
+        action = clap::ArgAction::Append
+    )]
+    cha_sockets: Vec<String>,
+
+    #[arg(
+        long = "irp-socket",
+        value_name = "LIST",
+        help = "Enable IRP on these sockets only (supports ranges, can be specified multiple times; default: every monitored socket)",
+        action = clap::ArgAction::Append
+    )]
+    irp_sockets: Vec<String>,
+
+    #[arg(
+        long = "iio-socket",
+        value_name = "LIST",
+        help = "Enable IIO on these sockets only (supports ranges, can be specified multiple times; default: every monitored socket)",
+        action = clap::ArgAction::Append
+    )]
+    iio_sockets: Vec<String>,
+
+    #[arg(
+        long = "upi-socket",
+        value_name = "LIST",
+        help = "Enable UPI on these sockets only (supports ranges, can be specified multiple times; default: every monitored socket)",
+        action = clap::ArgAction::Append
+    )]
+    upi_sockets: Vec<String>,
+
+    #[arg(
+        long = "m2m-socket",
+        value_name = "LIST",
+        help = "Enable M2M on these sockets only (supports ranges, can be specified multiple times; default: every monitored socket)",
+        action = clap::ArgAction::Append
+    )]
+    m2m_sockets: Vec<String>,
+
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_value_t = false,
+        require_equals = true,
+        default_missing_value = "true",
+        action = clap::ArgAction::Set,
+        help = "Enable Intel RDT metrics (MBM)"
+    )]
     rdt: bool,
 
-    #[arg(long, help = "Enable RAPL power/energy metrics")]
-    rapl: bool,
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_value_t = false,
+        require_equals = true,
+        default_missing_value = "true",
+        action = clap::ArgAction::Set,
+        help = "Export the active Speed Select (SST-PP) level per socket and the SST-CP priority class per core, read-only"
+    )]
+    sst: bool,
+
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_value_t = false,
+        require_equals = true,
+        default_missing_value = "true",
+        action = clap::ArgAction::Set,
+        help = "Export package and per-core C-state residency as a ratio of each interval, read-only"
+    )]
+    cstate: bool,
+
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_value_t = false,
+        require_equals = true,
+        default_missing_value = "true",
+        action = clap::ArgAction::Set,
+        help = "Export core and package temperature and whether each is thermally throttled (TCC or PROCHOT#), read-only"
+    )]
+    thermal: bool,
+
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_value_t = false,
+        require_equals = true,
+        default_missing_value = "true",
+        action = clap::ArgAction::Set,
+        help = "Enable PCU (Power Control Unit) metrics: frequency transitions, thermal/power/PROCHOT#/VR_HOT limited cycles and cores per C-state"
+    )]
+    pcu: bool,
+
+    #[arg(
+        long = "resctrl-group",
+        value_name = "GROUP",
+        help = "Read RDT bandwidth/occupancy of a resctrl monitoring group (path below /sys/fs/resctrl, e.g. mon_groups/web) instead of programming RMIDs per core; exported with a resctrl_group label (can be specified multiple times)",
+        action = clap::ArgAction::Append
+    )]
+    resctrl_groups: Vec<String>,
+
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_value_t = false,
+        require_equals = true,
+        default_missing_value = "true",
+        action = clap::ArgAction::Set,
+        help = "Enable RAPL power/energy metrics"
+    )]
+    rapl: bool,
+
+    #[arg(
+        long = "socket",
+        help = "Sockets to monitor (can be specified multiple times, supports ranges: --socket 0 --socket 1 or --socket 0-1)",
+        action = clap::ArgAction::Append
+    )]
+    sockets: Vec<String>,
+
+    #[arg(
+        long = "core",
+        help = "Cores to monitor (supports ranges and comma-separated lists: --core 0-3,5-8 or --core 0-3 --core 5-8)",
+        action = clap::ArgAction::Append
+    )]
+    cores: Vec<String>,
+
+    #[arg(
+        long = "numa-node",
+        value_name = "LIST",
+        help = "Also export IMC and CHA metrics of these NUMA nodes with a numa_node label (IMCNode*, CHANode*); with sub-NUMA clustering a socket's IMCs and CHAs are split between its nodes (supports ranges, can be specified multiple times)",
+        action = clap::ArgAction::Append
+    )]
+    numa_nodes: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "LIST",
+        value_delimiter = ',',
+        help = "PCI segment of each socket, in socket order (e.g. 0,0,1,1), for firmware whose segment numbering does not follow socket order (default: sockets take uncore PCI devices in segment and bus order)"
+    )]
+    pci_segments: Vec<u32>,
+
+    #[arg(
+        long,
+        help = "Exit on any unparseable entry in a --core/--socket list instead of warning and skipping it"
+    )]
+    strict_ranges: bool,
+
+    #[arg(
+        long,
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u32).range(1..=100),
+        help = "Sub-samples per export interval; bandwidth is reported as min/avg/max over them"
+    )]
+    sample_count: u32,
+
+    #[arg(
+        long = "spike-threshold",
+        value_name = "METRIC=VALUE",
+        help = "Record a spike at /spikes when METRIC rises above VALUE (can be specified multiple times)",
+        action = clap::ArgAction::Append
+    )]
+    spike_thresholds: Vec<String>,
+
+    #[arg(
+        long,
+        default_value_t = uncflow::orchestrator::DEFAULT_SPIKE_CAPACITY,
+        help = "Number of recent spikes kept for /spikes"
+    )]
+    spike_buffer_size: usize,
+
+    #[arg(
+        long,
+        value_name = "HOST:PORT",
+        help = "Push every collection to this statsd server as gauges, labels as DogStatsD tags"
+    )]
+    statsd: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "File holding the current correlation (trace) id, read when a spike is recorded"
+    )]
+    correlation_id_file: Option<std::path::PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Directory holding the host's sys/, proc/ and dev/ (default: $UNCFLOW_SYSROOT, else /)"
+    )]
+    sysroot: Option<std::path::PathBuf>,
+
+    #[arg(
+        long,
+        default_value = "msr",
+        value_name = "msr|perf",
+        help = "Counter backend for Core and RAPL metrics; perf uses perf_event_open instead of /dev/cpu/*/msr"
+    )]
+    backend: CounterBackend,
+
+    #[arg(
+        long = "uncore-core",
+        value_name = "SOCKET:CORE",
+        help = "Access a socket's CHA/IIO/IRP MSRs from this core instead of the default one, e.g. to keep off a latency-sensitive core (can be specified multiple times)",
+        action = clap::ArgAction::Append
+    )]
+    uncore_cores: Vec<String>,
+
+    #[arg(
+        long,
+        default_value_t = uncflow::orchestrator::DEFAULT_MAX_SERIES,
+        help = "Refuse to start when the enabled exporters would register more series than this"
+    )]
+    max_series: usize,
+
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = DEFAULT_READ_TIMEOUT.as_millis() as u64,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Abandon a subsystem's collection for the interval when its counter reads block longer than this (e.g. a stuck virtualized MSR)"
+    )]
+    read_timeout_ms: u64,
+
+    #[arg(
+        long,
+        help = "Don't take the uncore lock (/var/run/uncflow.lock) that keeps two instances from reprogramming the same IMC/CHA/IRP/IIO/UPI/M2M/PCU counters"
+    )]
+    no_lock: bool,
+
+    #[arg(
+        long,
+        help = "Never program counters: skip Core, IMC, CHA, IRP, UPI, M2M, PCU (and RDT without --resctrl-group) and collect only free-running/read-only metrics such as RAPL, IIO PCIe bandwidth, C-states and thermal"
+    )]
+    read_only_counters: bool,
+
+    #[arg(
+        long,
+        help = "Collect only when /metrics is scraped instead of every second; deltas then span the time between scrapes"
+    )]
+    collect_on_scrape: bool,
+
+    #[arg(
+        long,
+        value_name = "ADDR",
+        default_value = "0.0.0.0:8080",
+        help = "Serve HTTP on a TCP address (host:port) or a Unix domain socket (unix:/run/uncflow.sock); can be specified multiple times"
+    )]
+    listen: Vec<ListenAddr>,
+
+    #[arg(
+        long,
+        help = "Answer scrapers that accept OpenMetrics with # UNIT metadata; families with a unit are then named with it as a suffix (PackageEnergy_joules)"
+    )]
+    openmetrics: bool,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Save the cumulative counters (rapl_package_energy_joules_total, rdt_memory_bytes_total) to this file on graceful shutdown and continue them from it on startup; a missing or corrupt file starts them from zero"
+    )]
+    state_file: Option<std::path::PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "File holding a token that POST /reset and /session/{start,stop} must present as 'Authorization: Bearer <token>'"
+    )]
+    reset_token_file: Option<std::path::PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "LIST",
+        help = "Pin the agent's threads to these cores (e.g. 0-1,16) to keep collection off measured cores; MSR reads still hop onto their target CPU briefly"
+    )]
+    housekeeping_cores: Option<CoreList>,
+
+    #[arg(
+        long,
+        help = "Don't pin the reading thread to the target CPU around MSR accesses, for containers whose seccomp profile blocks sched_setaffinity (also UNCFLOW_NO_MSR_PINNING=1)"
+    )]
+    no_msr_pinning: bool,
+
+    #[arg(
+        long,
+        requires = "debug_token_file",
+        help = "Serve GET /debug/msr and /debug/pci for reading single registers; read-only, needs --debug-token-file"
+    )]
+    enable_debug_endpoints: bool,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "File holding a token the debug endpoints require as 'Authorization: Bearer <token>'"
+    )]
+    debug_token_file: Option<std::path::PathBuf>,
+
+    #[arg(
+        short,
+        long,
+        help = "Enable verbose logging (shows all MSR/PCI read/write operations)"
+    )]
+    verbose: bool,
+
+    #[arg(
+        short,
+        long,
+        conflicts_with = "verbose",
+        help = "Only log warnings and errors"
+    )]
+    quiet: bool,
+
+    #[arg(
+        long,
+        default_value = "text",
+        value_name = "text|json",
+        help = "Log line format; json writes one object per event for log aggregation pipelines"
+    )]
+    log_format: LogFormat,
+}
+
+/// Subscriber logging at `level` in `format`
+///
+/// MSR and PCI accesses are traced at DEBUG, so only --verbose shows them.
+fn log_subscriber(
+    level: tracing::Level,
+    format: LogFormat,
+) -> Box<dyn tracing::Subscriber + Send + Sync> {
+    let builder = tracing_subscriber::fmt().with_max_level(level);
+    match format {
+        LogFormat::Text => Box::new(builder.finish()),
+        LogFormat::Json => Box::new(builder.json().finish()),
+    }
+}
+
+struct AppState {
+    subsystems: Vec<Arc<dyn Subsystem>>,
+    // Measurement sessions over the subsystems, served at /session/*
+    sessions: Sessions,
+    self_metrics: Option<Arc<SelfMetrics>>,
+    spike_detector: Option<Arc<SpikeDetector>>,
+    readiness: Arc<Readiness>,
+    // Fed by every collection, served at /stream
+    metric_stream: Arc<MetricStream>,
+    // Set in collect-on-scrape mode, where /metrics drives collection
+    scrape_collector: Option<Arc<ScrapeCollector>>,
+    // Required bearer token for /reset and /session/*, open when None
+    reset_token: Option<String>,
+    // Deadline of counter reads made by the handlers, shared with the collections
+    read_timeout: Arc<ReadTimeout>,
+    // Families exported per subsystem, narrowed by --profile
+    metric_filter: MetricFilter,
+    // Set with --enable-debug-endpoints
+    debug_endpoints: Option<DebugEndpoints>,
+    // Serve OpenMetrics to scrapers that accept it
+    openmetrics: bool,
+    collection_handle: Option<tokio::task::JoinHandle<()>>,
+}
+
+/// Where the HTTP server listens
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ListenAddr {
+    Tcp(SocketAddr),
+    /// Unix domain socket, for sidecars scraping without an open TCP port
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddr {
+    type Err = UncflowError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err(UncflowError::ParseError(
+                    "unix: listen address needs a socket path".to_string(),
+                ));
+            }
+            return Ok(ListenAddr::Unix(PathBuf::from(path)));
+        }
+        s.parse().map(ListenAddr::Tcp).map_err(|e| {
+            UncflowError::ParseError(format!(
+                "Invalid listen address '{s}' (expected host:port or unix:/path): {e}"
+            ))
+        })
+    }
+}
+
+// Removes a Unix socket's file when its server stops, however it stops
+struct SocketFile(PathBuf);
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Serve `app` on `addr` until `shutdown` is cancelled
+async fn serve(addr: ListenAddr, app: Router, shutdown: CancellationToken) -> Result<()> {
+    match addr {
+        ListenAddr::Tcp(addr) => {
+            let listener = tokio::net::TcpListener::bind(addr).await?;
+            tracing::warn!("Starting HTTP server on {}", addr);
+            axum::serve(listener, app)
+                .with_graceful_shutdown(shutdown.cancelled_owned())
+                .await?;
+        }
+        ListenAddr::Unix(path) => serve_unix(&path, app, shutdown).await?,
+    }
+    Ok(())
+}
+
+async fn serve_unix(path: &Path, app: Router, shutdown: CancellationToken) -> Result<()> {
+    // A socket left behind by an unclean exit would make bind fail; anything else
+    // at the path is not ours to delete
+    if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+    let _socket_file = SocketFile(path.to_path_buf());
+    tracing::warn!("Starting HTTP server on unix:{}", path.display());
+
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown.cancelled_owned())
+        .await?;
+    Ok(())
+}
+
+/// Raw register reads for field debugging, always behind a token
+struct DebugEndpoints {
+    token: String,
+    msr: &'static dyn MsrAccess,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct DebugMsrQuery {
+    cpu: u32,
+    addr: String,
+}
 
-    #[arg(
-        long = "socket",
-        help = "Sockets to monitor (can be specified multiple times, supports ranges: --socket 0 --socket 1 or --socket 0-1)",
-        action = clap::ArgAction::Append
-    )]
-    sockets: Vec<String>,
+#[derive(Debug, serde::Deserialize)]
+struct DebugPciQuery {
+    #[serde(default)]
+    group: String,
+    bus: String,
+    device: String,
+    function: String,
+    offset: String,
+}
 
-    #[arg(
-        long = "core",
-        help = "Cores to monitor (supports ranges and comma-separated lists: --core 0-3,5-8 or --core 0-3 --core 5-8)",
-        action = clap::ArgAction::Append
-    )]
-    cores: Vec<String>,
+/// Body of POST /session/start, empty lists select everything monitored
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default)]
+struct SessionStartRequest {
+    cores: Vec<i32>,
+    sockets: Vec<i32>,
+    subsystems: Vec<String>,
+}
 
-    #[arg(
-        short,
-        long,
-        help = "Enable verbose logging (shows all MSR/PCI read/write operations)"
-    )]
-    verbose: bool,
+/// Body of POST /session/stop
+#[derive(Debug, serde::Deserialize)]
+struct SessionStopRequest {
+    id: uncflow::orchestrator::SessionId,
 }
 
-struct AppState {
-    rapl_exporter: Option<Arc<RaplMetricExporter>>,
-    rdt_exporter: Option<Arc<RdtMetricExporter>>,
-    core_exporter: Option<Arc<CoreMetricExporter>>,
-    imc_exporter: Option<Arc<ImcMetricExporter>>,
-    cha_exporter: Option<Arc<ChaMetricExporter>>,
-    irp_exporter: Option<Arc<IrpMetricExporter>>,
-    iio_exporter: Option<Arc<IioMetricExporter>>,
-    collection_handle: Option<tokio::task::JoinHandle<()>>,
+impl AppState {
+    /// Metric families of every enabled exporter, keyed by subsystem
+    fn gather_by_subsystem(&self) -> Vec<(&'static str, Vec<MetricFamily>)> {
+        let mut families: Vec<_> = self
+            .subsystems
+            .iter()
+            .map(|subsystem| {
+                let mut families = subsystem.registry().gather();
+                self.metric_filter.retain(subsystem.name(), &mut families);
+                (subsystem.name(), families)
+            })
+            .collect();
+        if let Some(self_metrics) = &self.self_metrics {
+            families.push(("uncflow", self_metrics.registry().gather()));
+        }
+        families.push(("uncflow", read_stats().registry().gather()));
+        families
+    }
 }
 
 async fn metrics_handler(
     axum::extract::State(state): axum::extract::State<Arc<AppState>>,
+    headers: HeaderMap,
 ) -> impl IntoResponse {
+    if let Some(scrape_collector) = &state.scrape_collector {
+        scrape_collector.collect().await;
+    }
+
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    if state.openmetrics && openmetrics::accepted(accept) {
+        let families: Vec<MetricFamily> = state
+            .gather_by_subsystem()
+            .into_iter()
+            .flat_map(|(_, families)| families)
+            .collect();
+        return (
+            [("Content-Type", openmetrics::CONTENT_TYPE.to_string())],
+            openmetrics::encode(&families),
+        );
+    }
+
     let encoder = TextEncoder::new();
     let mut buffer = Vec::new();
 
-    // Gather metrics from all exporters using macro
-    uncflow::gather_metrics!(buffer, encoder, state.rapl_exporter, "RAPL");
-    uncflow::gather_metrics!(buffer, encoder, state.rdt_exporter, "RDT");
-    uncflow::gather_metrics!(buffer, encoder, state.core_exporter, "Core");
-    uncflow::gather_metrics!(buffer, encoder, state.imc_exporter, "IMC");
-    uncflow::gather_metrics!(buffer, encoder, state.cha_exporter, "CHA");
-    uncflow::gather_metrics!(buffer, encoder, state.irp_exporter, "IRP");
-    uncflow::gather_metrics!(buffer, encoder, state.iio_exporter, "IIO");
+    for (subsystem, families) in state.gather_by_subsystem() {
+        if let Err(e) = encoder.encode(&families, &mut buffer) {
+            tracing::error!("Failed to encode {} metrics: {}", subsystem, e);
+        }
+    }
 
     let content_type = encoder.format_type().to_string();
     (
@@ -98,16 +923,301 @@ async fn metrics_handler(
     )
 }
 
+/// The /metrics families in Influx line protocol, one measurement per subsystem
+async fn influx_handler(
+    axum::extract::State(state): axum::extract::State<Arc<AppState>>,
+) -> impl IntoResponse {
+    if let Some(scrape_collector) = &state.scrape_collector {
+        scrape_collector.collect().await;
+    }
+
+    let timestamp_ns = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let mut body = String::new();
+    for (measurement, families) in state.gather_by_subsystem() {
+        influx::write_families(&mut body, measurement, &families, timestamp_ns);
+    }
+
+    ([("Content-Type", "text/plain; charset=utf-8")], body)
+}
+
+async fn spikes_handler(
+    axum::extract::State(state): axum::extract::State<Arc<AppState>>,
+) -> impl IntoResponse {
+    match &state.spike_detector {
+        Some(detector) => Json(detector.events()).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            "Spike annotation is disabled (see --spike-threshold)",
+        )
+            .into_response(),
+    }
+}
+
+/// Server-Sent Events with the metrics of every collection as JSON
+///
+/// A client that falls behind by more than the broadcast capacity is disconnected
+/// instead of holding back the others; it can simply reconnect.
+async fn stream_handler(
+    axum::extract::State(state): axum::extract::State<Arc<AppState>>,
+) -> impl IntoResponse {
+    let updates =
+        futures_util::stream::unfold(state.metric_stream.subscribe(), |mut receiver| async move {
+            match receiver.recv().await {
+                Ok(snapshot) => Some((
+                    Event::default().event("metrics").json_data(&*snapshot),
+                    receiver,
+                )),
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!(
+                        "Dropping /stream client that fell {} updates behind",
+                        skipped
+                    );
+                    None
+                }
+                Err(RecvError::Closed) => None,
+            }
+        });
+
+    Sse::new(updates).keep_alive(KeepAlive::default())
+}
+
+/// Readiness probe: 503 until at least one subsystem has completed a collection
+async fn ready_handler(
+    axum::extract::State(state): axum::extract::State<Arc<AppState>>,
+) -> impl IntoResponse {
+    if state.readiness.is_ready() {
+        (StatusCode::OK, "ready")
+    } else {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "waiting for the first collection",
+        )
+    }
+}
+
+/// Re-baseline every monitor so the next collection starts a fresh measurement window
+async fn reset_handler(
+    axum::extract::State(state): axum::extract::State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    if let Err(rejection) = authorize_reset(&state, &headers) {
+        return rejection;
+    }
+
+    tracing::warn!("Resetting all counter baselines");
+    // Resets re-read the counters under the monitor locks, which a stuck collect
+    // may hold, so they run under the read timeout rather than on a runtime worker
+    let subsystems = state.subsystems.clone();
+    let reset = state.read_timeout.call("reset", move || {
+        for subsystem in &subsystems {
+            subsystem.reset();
+        }
+    });
+    match reset.await {
+        Ok(()) => (StatusCode::OK, "reset".to_string()),
+        Err(e) => (StatusCode::SERVICE_UNAVAILABLE, e.to_string()),
+    }
+}
+
+/// Snapshot the requested subsystems, answering with the session id
+async fn session_start_handler(
+    axum::extract::State(state): axum::extract::State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(request): Json<SessionStartRequest>,
+) -> impl IntoResponse {
+    if let Err(rejection) = authorize_reset(&state, &headers) {
+        return rejection.into_response();
+    }
+
+    // Snapshots read the counters like /reset, so they share its deadline
+    let read_timeout = Arc::clone(&state.read_timeout);
+    let start = read_timeout.call("session start", move || {
+        state
+            .sessions
+            .start_session(&request.cores, &request.sockets, &request.subsystems)
+    });
+    match start.await {
+        Ok(Ok(id)) => Json(serde_json::json!({ "id": id })).into_response(),
+        Ok(Err(e)) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        Err(e) => (StatusCode::SERVICE_UNAVAILABLE, e.to_string()).into_response(),
+    }
+}
+
+/// Close a session, answering with the deltas accumulated since it started
+async fn session_stop_handler(
+    axum::extract::State(state): axum::extract::State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(request): Json<SessionStopRequest>,
+) -> impl IntoResponse {
+    if let Err(rejection) = authorize_reset(&state, &headers) {
+        return rejection.into_response();
+    }
+
+    let read_timeout = Arc::clone(&state.read_timeout);
+    let stop = read_timeout.call("session stop", move || {
+        state.sessions.stop_session(request.id)
+    });
+    match stop.await {
+        Ok(Ok(report)) => Json(report).into_response(),
+        Ok(Err(e)) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+        Err(e) => (StatusCode::SERVICE_UNAVAILABLE, e.to_string()).into_response(),
+    }
+}
+
+/// Reject a /reset or /session/* request without the --reset-token-file token, if set
+fn authorize_reset(
+    state: &AppState,
+    headers: &HeaderMap,
+) -> std::result::Result<(), (StatusCode, String)> {
+    match &state.reset_token {
+        Some(token) if !bearer_matches(headers, token) => Err((
+            StatusCode::UNAUTHORIZED,
+            "missing or invalid reset token".to_string(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Whether the request carries `Authorization: Bearer <token>`
+fn bearer_matches(headers: &HeaderMap, token: &str) -> bool {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|presented| constant_time_eq(presented.as_bytes(), token.as_bytes()))
+}
+
+/// Byte comparison whose time depends only on the lengths, so response timing
+/// doesn't tell a client how much of a guessed token was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .fold(0, |diff, (x, y)| std::hint::black_box(diff | (x ^ y)))
+            == 0
+}
+
+/// Parse a register address or offset, hex with a 0x prefix or decimal
+fn parse_register_number(value: &str) -> Option<u64> {
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// The debug endpoints if enabled and the request is authorized
+fn authorize_debug<'a>(
+    state: &'a AppState,
+    headers: &HeaderMap,
+) -> std::result::Result<&'a DebugEndpoints, (StatusCode, String)> {
+    let debug = state.debug_endpoints.as_ref().ok_or((
+        StatusCode::NOT_FOUND,
+        "debug endpoints are disabled".to_string(),
+    ))?;
+    if !bearer_matches(headers, &debug.token) {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            "missing or invalid debug token".to_string(),
+        ));
+    }
+    Ok(debug)
+}
+
+/// Read one MSR, e.g. GET /debug/msr?cpu=0&addr=0x611
+async fn debug_msr_handler(
+    axum::extract::State(state): axum::extract::State<Arc<AppState>>,
+    headers: HeaderMap,
+    axum::extract::Query(query): axum::extract::Query<DebugMsrQuery>,
+) -> impl IntoResponse {
+    let debug = match authorize_debug(&state, &headers) {
+        Ok(debug) => debug,
+        Err(rejection) => return rejection,
+    };
+    let Some(addr) = parse_register_number(&query.addr) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("invalid MSR address {:?}", query.addr),
+        );
+    };
+
+    tracing::warn!("Debug read of MSR 0x{:X} on CPU {}", addr, query.cpu);
+    match debug.msr.read(query.cpu, addr) {
+        Ok(value) => (StatusCode::OK, format!("0x{value:016x}")),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+/// Read one dword of PCI config space, e.g. GET /debug/pci?bus=0x3a&device=8&function=2&offset=0xd0
+async fn debug_pci_handler(
+    axum::extract::State(state): axum::extract::State<Arc<AppState>>,
+    headers: HeaderMap,
+    axum::extract::Query(query): axum::extract::Query<DebugPciQuery>,
+) -> impl IntoResponse {
+    if let Err(rejection) = authorize_debug(&state, &headers) {
+        return rejection;
+    }
+    let number = |value: &str| parse_register_number(value).and_then(|n| u32::try_from(n).ok());
+    let group = if query.group.is_empty() {
+        Some(0)
+    } else {
+        number(&query.group)
+    };
+    let (Some(group_number), Some(bus), Some(device), Some(function), Some(offset)) = (
+        group,
+        number(&query.bus),
+        number(&query.device),
+        number(&query.function),
+        number(&query.offset),
+    ) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("invalid PCI address in {query:?}"),
+        );
+    };
+
+    let address = PciAddress {
+        group_number,
+        bus,
+        device,
+        function,
+    };
+    tracing::warn!("Debug read of PCI {:?} offset 0x{:X}", address, offset);
+    // sysfs config space reads block, keep them off the runtime workers
+    let read = tokio::task::spawn_blocking(move || {
+        PciHandle::new(address).and_then(|handle| handle.read32(offset))
+    });
+    match read.await {
+        Ok(Ok(value)) => (StatusCode::OK, format!("0x{value:08x}")),
+        Ok(Err(e)) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("PCI read did not finish: {e}"),
+        ),
+    }
+}
+
+/// Liveness probe: the HTTP server is up and answering
+async fn healthz_handler() -> impl IntoResponse {
+    (StatusCode::OK, "ok")
+}
+
 fn check_permissions() {
     // Check if we can access MSR
-    let msr_path = "/dev/cpu/0/msr";
-    if std::fs::metadata(msr_path).is_err() {
+    let msr_file = uncflow::common::sys_roots().msr_path(0);
+    let msr_path = msr_file.display();
+    if std::fs::metadata(&msr_file).is_err() {
         eprintln!("\n⚠️  ERROR: Cannot access {msr_path}\n\nThe MSR kernel module may not be loaded.\nRun: sudo modprobe msr\n");
         std::process::exit(1);
     }
 
     // Try to open MSR to check actual permissions
-    if let Err(e) = std::fs::File::open(msr_path) {
+    if let Err(e) = std::fs::File::open(&msr_file) {
         if e.kind() == std::io::ErrorKind::PermissionDenied {
             eprintln!("\n⚠️  ERROR: Permission denied accessing {msr_path}\n\nRun with: cargo run --release -- --rapl\n(sudo is configured in .cargo/config.toml)\n");
             std::process::exit(1);
@@ -121,8 +1231,12 @@ fn check_permissions() {
 /// - Ranges: "0-3" (inclusive)
 /// - Comma-separated: "0,2,4"
 /// - Mixed: "0-3,5,8-11"
-fn parse_range_list(inputs: &[String]) -> Vec<i32> {
+///
+/// Unparseable parts are skipped with a warning, or rejected with `strict`. An
+/// argument in which nothing parses is always an error, never a default CPU.
+fn parse_range_list(inputs: &[String], strict: bool) -> Result<Vec<i32>> {
     let mut result = Vec::new();
+    let mut invalid = Vec::new();
 
     for input in inputs {
         // Split by commas first
@@ -133,68 +1247,107 @@ fn parse_range_list(inputs: &[String]) -> Vec<i32> {
             }
 
             // Check if it's a range (contains '-')
-            if let Some((start_str, end_str)) = part.split_once('-') {
-                // Parse range
-                if let (Ok(start), Ok(end)) = (
+            let parsed = if let Some((start_str, end_str)) = part.split_once('-') {
+                match (
                     start_str.trim().parse::<i32>(),
                     end_str.trim().parse::<i32>(),
                 ) {
-                    result.extend(start..=end);
-                } else {
-                    tracing::warn!("Failed to parse range: {}", part);
+                    (Ok(start), Ok(end)) if start <= end => Some(start..=end),
+                    _ => None,
                 }
             } else {
-                // Parse single value
-                if let Ok(val) = part.parse::<i32>() {
-                    result.push(val);
-                } else {
-                    tracing::warn!("Failed to parse value: {}", part);
-                }
+                part.parse::<i32>().ok().map(|val| val..=val)
+            };
+
+            match parsed {
+                Some(range) => result.extend(range),
+                None => invalid.push(part.to_string()),
             }
         }
     }
 
+    if !invalid.is_empty() {
+        let message = format!(
+            "Invalid CPU/socket list entries {:?} in {:?} (expected e.g. 0-3,5)",
+            invalid, inputs
+        );
+        if strict || result.is_empty() {
+            return Err(UncflowError::ParseError(message));
+        }
+        tracing::warn!("{}, ignoring them", message);
+    }
+
     // Remove duplicates and sort
     result.sort_unstable();
     result.dedup();
 
     if result.is_empty() {
-        tracing::warn!("No valid CPU/socket IDs parsed, using default: 0");
-        result.push(0);
+        return Err(UncflowError::ParseError(format!(
+            "No CPU/socket IDs given in {inputs:?}"
+        )));
     }
 
-    result
+    Ok(result)
 }
 
 /// Initialize orchestrator mode (unified collection loop)
 fn init_orchestrator_mode(
     config: ExportConfig,
     collector_config: CollectorConfig,
+    correlation_id_file: Option<std::path::PathBuf>,
+    collect_on_scrape: bool,
+    reset_token: Option<String>,
+    debug_endpoints: Option<DebugEndpoints>,
     cancel_token: CancellationToken,
 ) -> Result<AppState> {
     let collector = MetricCollector::new(config, collector_config)?;
 
+    if let Some(path) = correlation_id_file {
+        collector.set_correlation_source(Arc::new(move || {
+            std::fs::read_to_string(&path)
+                .ok()
+                .map(|id| id.trim().to_string())
+                .filter(|id| !id.is_empty())
+        }));
+    }
+
     // Extract exporters for metrics handler BEFORE starting (which consumes self)
-    let rapl_exporter = collector.rapl_exporter();
-    let rdt_exporter = collector.rdt_exporter();
-    let core_exporter = collector.core_exporter();
-    let imc_exporter = collector.imc_exporter();
-    let cha_exporter = collector.cha_exporter();
-    let irp_exporter = collector.irp_exporter();
-    let iio_exporter = collector.iio_exporter();
+    let subsystems = collector.subsystems();
+    let self_metrics = collector.self_metrics();
+    let spike_detector = collector.spike_detector();
+    let readiness = collector.readiness();
+    let metric_stream = collector.metric_stream();
+    let read_timeout = collector.read_timeout();
+    let metric_filter = collector.metric_filter();
 
-    // Start the unified collection loop with cancellation support (consumes collector)
-    let collection_handle = collector.start(cancel_token);
+    // Either /metrics drives collection, or the unified loop runs with cancellation
+    // support (both consume the collector)
+    let (scrape_collector, collection_handle) = if collect_on_scrape {
+        tracing::info!(
+            "Collecting on scrape, at most once every {:?}",
+            uncflow::orchestrator::MIN_SCRAPE_INTERVAL
+        );
+        let scrape_collector =
+            ScrapeCollector::new(collector, uncflow::orchestrator::MIN_SCRAPE_INTERVAL);
+        (Some(Arc::new(scrape_collector)), None)
+    } else {
+        (None, Some(collector.start(cancel_token)))
+    };
 
     let state = AppState {
-        rapl_exporter,
-        rdt_exporter,
-        core_exporter,
-        imc_exporter,
-        cha_exporter,
-        irp_exporter,
-        iio_exporter,
-        collection_handle: Some(collection_handle),
+        sessions: Sessions::new(subsystems.clone()),
+        subsystems,
+        self_metrics: Some(self_metrics),
+        spike_detector,
+        readiness,
+        metric_stream,
+        scrape_collector,
+        reset_token,
+        read_timeout,
+        metric_filter,
+        debug_endpoints,
+        openmetrics: false,
+        collection_handle,
     };
 
     Ok(state)
@@ -238,21 +1391,69 @@ async fn shutdown_signal(cancel_token: CancellationToken) {
     tracing::warn!("Cancellation token activated");
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let args = Args::parse();
+/// Multi-threaded runtime whose workers (and blocking threads) stay on `housekeeping`
+fn build_runtime(housekeeping: Option<CoreList>) -> Result<tokio::runtime::Runtime> {
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder.enable_all();
+
+    if let Some(cores) = housekeeping {
+        // Pin the main thread first: it drives block_on, threads spawned outside the
+        // runtime inherit its mask, and an unusable list fails here instead of per worker
+        cores.pin_current_thread()?;
+        tracing::info!(
+            "Pinning agent threads to housekeeping cores {:?}",
+            cores.cores()
+        );
+        // More workers than housekeeping cores would only contend with each other
+        builder.worker_threads(cores.cores().len());
+        builder.on_thread_start(move || {
+            if let Err(e) = cores.pin_current_thread() {
+                tracing::warn!("Failed to pin runtime thread: {}", e);
+            }
+        });
+    }
+
+    Ok(builder.build()?)
+}
+
+fn main() -> Result<()> {
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
 
-    // Setup logging based on verbose flag
+    // Setup logging based on the verbose and quiet flags
     let log_level = if args.verbose {
         tracing::Level::DEBUG
+    } else if args.quiet {
+        tracing::Level::WARN
     } else {
         tracing::Level::INFO
     };
 
-    tracing_subscriber::fmt().with_max_level(log_level).init();
+    tracing_subscriber::util::SubscriberInitExt::init(log_subscriber(log_level, args.log_format));
+
+    if let Some(profile) = args.profile {
+        tracing::info!("Using profile {:?}", profile);
+        profile.apply(&mut args, &matches);
+    }
+
+    build_runtime(args.housekeeping_cores.clone())?.block_on(run(args))
+}
+
+async fn run(args: Args) -> Result<()> {
+    if args.no_msr_pinning || std::env::var_os(NO_MSR_PINNING_ENV).is_some() {
+        tracing::info!("MSR accesses run without pinning to the target CPU");
+        set_msr_pinning(false);
+    }
 
-    // Check for root/capabilities early
-    check_permissions();
+    // Resolve host filesystem roots before any hardware access
+    if let Some(root) = &args.sysroot {
+        uncflow::common::sysroot::init(SysRoots::under(root))?;
+    }
+    tracing::info!("Using system roots: {:?}", uncflow::common::sys_roots());
+    if !args.pci_segments.is_empty() {
+        tracing::info!("PCI segment of each socket: {:?}", args.pci_segments);
+        uncflow::common::pci::set_socket_segments(args.pci_segments.clone())?;
+    }
 
     // Log detected architecture
     tracing::info!(
@@ -267,7 +1468,7 @@ async fn main() -> Result<()> {
     } else {
         // Parse cores first (if specified)
         let cores = if !args.cores.is_empty() {
-            parse_range_list(&args.cores)
+            parse_range_list(&args.cores, args.strict_ranges)?
         } else {
             // Default: detect all online cores if not specified
             ExportConfig::detect_online_cpus()
@@ -275,7 +1476,7 @@ async fn main() -> Result<()> {
 
         // Parse sockets
         let sockets = if !args.sockets.is_empty() {
-            parse_range_list(&args.sockets)
+            parse_range_list(&args.sockets, args.strict_ranges)?
         } else {
             // If cores specified but no sockets, auto-detect sockets from cores
             ExportConfig::detect_sockets(&cores)
@@ -284,61 +1485,332 @@ async fn main() -> Result<()> {
         tracing::info!("Using sockets: {:?}", sockets);
         tracing::info!("Using cores: {:?}", cores);
 
-        ExportConfig::new(sockets, cores)
+        ExportConfig::validated(sockets, cores)?
+    };
+    let mut uncore_cores = std::collections::HashMap::new();
+    for spec in &args.uncore_cores {
+        let (socket, core) = ExportConfig::parse_uncore_core(spec)?;
+        ExportConfig::check_uncore_core(socket, core, ExportConfig::core_socket(core))?;
+        tracing::info!(
+            "Accessing uncore MSRs of socket {} from core {}",
+            socket,
+            core
+        );
+        uncore_cores.insert(socket, core);
+    }
+
+    let mut core_events = match &args.core_events_file {
+        Some(path) => {
+            uncflow::counters::core::events::parse_event_file(&std::fs::read_to_string(path)?)?
+        }
+        None => Vec::new(),
+    };
+    for spec in &args.core_events {
+        core_events.push(spec.parse::<uncflow::counters::core::CustomEvent>()?);
+    }
+
+    let cha_custom_events = match &args.events_from_json {
+        Some(path) => {
+            if args.cha || args.uncore {
+                tracing::warn!(
+                    "--events-from-json programs the CHA counters, the built-in CHA metrics are disabled"
+                );
+            }
+            Some(uncflow::counters::cha::ChaCustomEvents::load(path)?)
+        }
+        None => None,
+    };
+
+    let config = if args.numa_nodes.is_empty() {
+        config
+    } else {
+        let topology = uncflow::common::NumaTopology::discover()?;
+        let nodes = parse_range_list(&args.numa_nodes, args.strict_ranges)?;
+        for &node in &nodes {
+            let Some(info) = topology.node(node) else {
+                return Err(UncflowError::InvalidConfiguration(format!(
+                    "NUMA node {node} does not exist or has no CPUs"
+                )));
+            };
+            if !config.sockets.contains(&info.socket) {
+                tracing::warn!(
+                    "NUMA node {} is on unmonitored socket {}, it will have no metrics",
+                    node,
+                    info.socket
+                );
+            }
+        }
+        if topology.is_snc() {
+            tracing::info!("Sub-NUMA clustering detected, attributing IMCs and CHAs per node");
+        }
+        config.with_numa_nodes(topology, nodes)
     };
 
+    let config = config
+        .with_sample_count(args.sample_count)
+        .with_cha_per_box(args.cha_per_box)
+        .with_cha_sample_boxes(args.cha_sample_boxes.map(|n| n as usize))
+        .with_cha_metric_age(args.cha_metric_age)
+        .with_cha_unavailable(args.cha_unavailable)
+        .with_cha_custom_events(cha_custom_events)
+        .with_imc_page_events(args.imc_page_events)
+        .with_imc_thermal(args.imc_thermal)
+        .with_iio_iommu(args.iio_iommu)
+        .with_iio_device_labels(args.iio_device_labels)
+        .with_imc_per_channel(args.imc_per_channel)
+        .with_irp_per_unit(args.irp_per_unit)
+        .with_export_raw(args.export_raw)
+        .with_count_rates(args.count_rates)
+        .with_rollups(args.rollups)
+        .with_core_avx_license(args.core_avx_license)
+        .with_core_offcore(args.core_offcore)
+        .with_core_socket_rollup(args.core_socket_rollup)
+        .with_core_events(core_events)
+        .with_resctrl_groups(args.resctrl_groups.clone())
+        .with_memory_peak_bandwidth_gbps(args.memory_peak_bandwidth_gbps)
+        .with_max_series(args.max_series)
+        .with_read_timeout(std::time::Duration::from_millis(args.read_timeout_ms))
+        .with_uncore_cores(uncore_cores)
+        .with_state_file(args.state_file.clone())
+        .with_backend(args.backend)
+        .with_read_only_counters(args.read_only_counters);
+
     tracing::info!(
         "Monitoring {} sockets, {} cores",
         config.sockets.len(),
         config.cores.len()
     );
+    // Closer sub-samples than MIN_RATE_WINDOW would be dropped as too soon
+    let max_samples = max_sample_count(COLLECTION_PERIOD, config.read_timeout);
+    let config = if config.sample_count > max_samples {
+        tracing::warn!(
+            "--sample-count {} leaves sub-samples under 10ms within half the {}ms read timeout, using {}",
+            config.sample_count,
+            config.read_timeout.as_millis(),
+            max_samples
+        );
+        config.with_sample_count(max_samples)
+    } else {
+        config
+    };
+    if config.sample_count > 1 {
+        tracing::info!("Taking {} sub-samples per interval", config.sample_count);
+    }
 
     // Determine which metrics to collect
     // Default: iio, imc, irp if no flags specified
+    // Selecting sockets for a unit enables it
+    let uncore_socket_selected = [
+        &args.imc_sockets,
+        &args.cha_sockets,
+        &args.irp_sockets,
+        &args.iio_sockets,
+        &args.upi_sockets,
+        &args.m2m_sockets,
+    ]
+    .iter()
+    .any(|sockets| !sockets.is_empty());
+    // An empty selection means every socket, so one that matches nothing is an error
+    let unit_sockets = |selected: &[String], unit: &str| -> Result<Vec<i32>> {
+        if selected.is_empty() {
+            return Ok(Vec::new());
+        }
+        let kind = format!("{unit} socket");
+        let sockets = ExportConfig::retain_available(
+            &parse_range_list(selected, args.strict_ranges)?,
+            &config.sockets,
+            &kind,
+        );
+        if sockets.is_empty() {
+            return Err(UncflowError::InvalidConfiguration(format!(
+                "None of the requested {unit} sockets are monitored (monitored: {:?})",
+                config.sockets
+            )));
+        }
+        Ok(sockets)
+    };
+
     let no_flags_specified = !args.rapl
         && !args.rdt
+        && args.resctrl_groups.is_empty()
         && !args.core_metrics
         && !args.uncore
         && !args.imc
         && !args.cha
+        && args.events_from_json.is_none()
         && !args.irp
-        && !args.iio;
+        && !args.iio
+        && !args.upi
+        && !args.m2m
+        && !args.sst
+        && !args.cstate
+        && !args.thermal
+        && !args.pcu
+        && !uncore_socket_selected;
 
     let collector_config = CollectorConfig {
         rapl: args.rapl,
-        rdt: args.rdt,
+        rdt: args.rdt || !args.resctrl_groups.is_empty(),
         core_metrics: args.core_metrics,
-        imc: args.uncore || args.imc || no_flags_specified,
-        cha: args.uncore || args.cha,
-        irp: args.uncore || args.irp || no_flags_specified,
-        iio: args.uncore || args.iio || no_flags_specified,
+        imc: args.uncore || args.imc || !args.imc_sockets.is_empty() || no_flags_specified,
+        cha: args.events_from_json.is_none()
+            && (args.uncore || args.cha || !args.cha_sockets.is_empty()),
+        cha_custom: args.events_from_json.is_some(),
+        irp: args.uncore || args.irp || !args.irp_sockets.is_empty() || no_flags_specified,
+        iio: args.uncore || args.iio || !args.iio_sockets.is_empty() || no_flags_specified,
+        upi: args.uncore || args.upi || !args.upi_sockets.is_empty(),
+        m2m: args.uncore || args.m2m || !args.m2m_sockets.is_empty(),
+        sst: args.sst,
+        cstate: args.cstate,
+        thermal: args.thermal,
+        pcu: args.pcu,
+        imc_sockets: unit_sockets(&args.imc_sockets, "IMC")?,
+        cha_sockets: unit_sockets(&args.cha_sockets, "CHA")?,
+        irp_sockets: unit_sockets(&args.irp_sockets, "IRP")?,
+        iio_sockets: unit_sockets(&args.iio_sockets, "IIO")?,
+        upi_sockets: unit_sockets(&args.upi_sockets, "UPI")?,
+        m2m_sockets: unit_sockets(&args.m2m_sockets, "M2M")?,
+        spike_rules: args
+            .spike_thresholds
+            .iter()
+            .map(|rule| rule.parse::<SpikeRule>())
+            .collect::<Result<Vec<_>>>()?,
+        spike_capacity: args.spike_buffer_size,
+        statsd: args.statsd.clone(),
+        metric_filter: args.metric_filter.clone(),
     };
 
     if no_flags_specified {
         tracing::info!("No metrics specified, using defaults: IIO, IMC, IRP");
     }
 
+    // Check for root/capabilities before any exporter touches hardware; the perf
+    // backend only needs the MSR device when an MSR-programmed subsystem is enabled
+    let msr_subsystems = (collector_config.rdt && config.resctrl_groups.is_empty())
+        || collector_config.cha
+        || collector_config.cha_custom
+        || collector_config.irp
+        || collector_config.iio
+        || collector_config.sst
+        || collector_config.cstate
+        || collector_config.thermal
+        || collector_config.pcu;
+    if config.backend == CounterBackend::Msr || msr_subsystems {
+        check_permissions();
+    }
+    if config.read_only_counters {
+        tracing::info!("Read-only counter mode: no counter will be programmed");
+    } else if let Some(reason) = msr::writes_blocked(uncflow::common::sys_roots()) {
+        tracing::warn!(
+            "MSR writes are blocked ({}); programmable counters will fail, consider --read-only-counters",
+            reason
+        );
+    }
+    if config.backend == CounterBackend::Perf {
+        tracing::info!("Reading Core and RAPL counters through perf_event_open");
+        if msr_subsystems || collector_config.imc || collector_config.upi || collector_config.m2m {
+            tracing::warn!(
+                "The perf backend only covers Core and RAPL, other subsystems still use MSR/PCI access"
+            );
+        }
+    }
+
+    // Uncore units are shared by the whole socket; Core and RDT are per CPU and can coexist.
+    // Nothing is reprogrammed with --read-only-counters.
+    let uncore_subsystems = !config.read_only_counters
+        && (collector_config.imc
+            || collector_config.cha
+            || collector_config.cha_custom
+            || collector_config.irp
+            || collector_config.iio
+            || collector_config.upi
+            || collector_config.m2m
+            || collector_config.pcu);
+    let _uncore_lock = if uncore_subsystems && !args.no_lock {
+        let lock = UncoreLock::acquire(lock::DEFAULT_LOCK_PATH)?;
+        tracing::info!("Holding uncore lock {}", lock.path().display());
+        Some(lock)
+    } else {
+        None
+    };
+
+    let read_token = |path: &Option<std::path::PathBuf>| -> Result<Option<String>> {
+        Ok(path
+            .as_ref()
+            .map(std::fs::read_to_string)
+            .transpose()?
+            .map(|token| token.trim().to_string())
+            .filter(|token| !token.is_empty()))
+    };
+    let reset_token = read_token(&args.reset_token_file)?;
+    if reset_token.is_none() {
+        tracing::info!("POST /reset and /session/* are not protected (see --reset-token-file)");
+    }
+
+    let debug_endpoints = if args.enable_debug_endpoints {
+        let token = read_token(&args.debug_token_file)?.ok_or_else(|| {
+            UncflowError::ConfigError(
+                "--enable-debug-endpoints needs a non-empty --debug-token-file".to_string(),
+            )
+        })?;
+        tracing::warn!("Debug endpoints /debug/msr and /debug/pci are enabled");
+        Some(DebugEndpoints {
+            token,
+            msr: Msr::instance(),
+        })
+    } else {
+        None
+    };
+    let debug_enabled = debug_endpoints.is_some();
+
     let cancel_token = CancellationToken::new();
 
     tracing::info!("Using orchestrator mode (unified collection loop)");
-    let mut state = init_orchestrator_mode(config, collector_config, cancel_token.clone())?;
+    let mut state = init_orchestrator_mode(
+        config,
+        collector_config,
+        args.correlation_id_file,
+        args.collect_on_scrape,
+        reset_token,
+        debug_endpoints,
+        cancel_token.clone(),
+    )?;
+    state.openmetrics = args.openmetrics;
 
     let collection_handle = state.collection_handle.take();
 
     let app_state = Arc::new(state);
 
-    let app = Router::new()
+    let mut app = Router::new()
         .route("/metrics", get(metrics_handler))
-        .with_state(app_state);
-
-    let addr = SocketAddr::from(([0, 0, 0, 0], 8080));
-    tracing::warn!("Starting HTTP server on {}", addr);
-
-    let listener = tokio::net::TcpListener::bind(addr).await?;
+        .route("/metrics.influx", get(influx_handler))
+        .route("/spikes", get(spikes_handler))
+        .route("/stream", get(stream_handler))
+        .route("/ready", get(ready_handler))
+        .route("/healthz", get(healthz_handler))
+        .route("/reset", post(reset_handler))
+        .route("/session/start", post(session_start_handler))
+        .route("/session/stop", post(session_stop_handler));
+    if debug_enabled {
+        app = app
+            .route("/debug/msr", get(debug_msr_handler))
+            .route("/debug/pci", get(debug_pci_handler));
+    }
+    let app = app.with_state(Arc::clone(&app_state));
 
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal(cancel_token))
-        .await?;
+    let signals = tokio::spawn(shutdown_signal(cancel_token.clone()));
+    let served = futures_util::future::try_join_all(
+        args.listen
+            .iter()
+            .map(|addr| serve(addr.clone(), app.clone(), cancel_token.clone())),
+    )
+    .await;
+    signals.abort();
+    if served.is_err() {
+        // Stop the collection loop along with the servers still running
+        cancel_token.cancel();
+    }
+    served?;
 
     tracing::info!("Server shutdown complete, waiting for collection loop to finish...");
 
@@ -346,7 +1818,461 @@ async fn main() -> Result<()> {
         let _ = handle.await;
     }
 
+    if let Some(path) = &args.state_file {
+        let families: Vec<MetricFamily> = app_state
+            .gather_by_subsystem()
+            .into_iter()
+            .flat_map(|(_, families)| families)
+            .collect();
+        match CounterState::from_families(&families).save(path) {
+            Ok(()) => tracing::info!("Saved cumulative counters to {}", path.display()),
+            Err(e) => tracing::error!("Failed to save counter state {}: {}", path.display(), e),
+        }
+    }
+
     tracing::info!("All tasks completed, exiting");
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn strings(inputs: &[&str]) -> Vec<String> {
+        inputs.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_ready_after_first_collection() {
+        let readiness = Arc::new(Readiness::new());
+        let state = Arc::new(AppState {
+            subsystems: Vec::new(),
+            sessions: Sessions::new(Vec::new()),
+            self_metrics: None,
+            spike_detector: None,
+            readiness: Arc::clone(&readiness),
+            metric_stream: Arc::new(MetricStream::default()),
+            scrape_collector: None,
+            reset_token: None,
+            read_timeout: Arc::new(ReadTimeout::new(DEFAULT_READ_TIMEOUT)),
+            metric_filter: MetricFilter::default(),
+            debug_endpoints: None,
+            openmetrics: false,
+            collection_handle: None,
+        });
+        let status = |state: Arc<AppState>| async move {
+            ready_handler(axum::extract::State(state))
+                .await
+                .into_response()
+                .status()
+        };
+
+        assert_eq!(
+            status(Arc::clone(&state)).await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        readiness.mark_collected();
+        assert_eq!(status(state).await, StatusCode::OK);
+        assert_eq!(
+            healthz_handler().await.into_response().status(),
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scrape_collects_before_gathering() {
+        let collector = MetricCollector::new(
+            ExportConfig::new(vec![0], vec![0]),
+            CollectorConfig::default(),
+        )
+        .unwrap();
+        let self_metrics = collector.self_metrics();
+        let scrape_collector = Arc::new(ScrapeCollector::new(collector, Duration::from_secs(60)));
+        let state = Arc::new(AppState {
+            subsystems: Vec::new(),
+            sessions: Sessions::new(Vec::new()),
+            self_metrics: Some(self_metrics),
+            spike_detector: None,
+            readiness: Arc::new(Readiness::new()),
+            metric_stream: Arc::new(MetricStream::default()),
+            scrape_collector: Some(Arc::clone(&scrape_collector)),
+            reset_token: None,
+            read_timeout: Arc::new(ReadTimeout::new(DEFAULT_READ_TIMEOUT)),
+            metric_filter: MetricFilter::default(),
+            debug_endpoints: None,
+            openmetrics: false,
+            collection_handle: None,
+        });
+
+        let response = metrics_handler(axum::extract::State(state), HeaderMap::new())
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        // The handler already ran this window's collection
+        assert!(!scrape_collector.collect().await);
+    }
+
+    #[tokio::test]
+    async fn test_collection_broadcasts_snapshot() {
+        let collector = MetricCollector::new(
+            ExportConfig::new(vec![0], vec![0]),
+            CollectorConfig::default(),
+        )
+        .unwrap();
+        let mut updates = collector.metric_stream().subscribe();
+
+        collector.collect_once().await;
+        let snapshot = updates.try_recv().unwrap();
+        assert!(snapshot.timestamp_ms > 0);
+        assert!(updates.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reset_requires_token() {
+        let state = Arc::new(AppState {
+            subsystems: Vec::new(),
+            sessions: Sessions::new(Vec::new()),
+            self_metrics: None,
+            spike_detector: None,
+            readiness: Arc::new(Readiness::new()),
+            metric_stream: Arc::new(MetricStream::default()),
+            scrape_collector: None,
+            reset_token: Some("s3cret".to_string()),
+            read_timeout: Arc::new(ReadTimeout::new(DEFAULT_READ_TIMEOUT)),
+            metric_filter: MetricFilter::default(),
+            debug_endpoints: None,
+            openmetrics: false,
+            collection_handle: None,
+        });
+        let status = |authorization: Option<&'static str>| {
+            let state = Arc::clone(&state);
+            async move {
+                let mut headers = HeaderMap::new();
+                if let Some(value) = authorization {
+                    headers.insert(header::AUTHORIZATION, value.parse().unwrap());
+                }
+                reset_handler(axum::extract::State(state), headers)
+                    .await
+                    .into_response()
+                    .status()
+            }
+        };
+
+        assert_eq!(status(None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(Some("Bearer wrong")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(Some("Bearer s3cre")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            status(Some("Bearer s3crex")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(Some("Bearer s3cret2")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(status(Some("Bearer s3cret")).await, StatusCode::OK);
+
+        // Sessions take the same token
+        let start = |authorization: Option<&'static str>| {
+            let state = Arc::clone(&state);
+            async move {
+                let mut headers = HeaderMap::new();
+                if let Some(value) = authorization {
+                    headers.insert(header::AUTHORIZATION, value.parse().unwrap());
+                }
+                let request = SessionStartRequest {
+                    cores: Vec::new(),
+                    sockets: Vec::new(),
+                    subsystems: Vec::new(),
+                };
+                session_start_handler(axum::extract::State(state), headers, Json(request))
+                    .await
+                    .into_response()
+                    .status()
+            }
+        };
+        assert_eq!(start(None).await, StatusCode::UNAUTHORIZED);
+        // No subsystem supports sessions here, so the authorized start is refused
+        assert_eq!(start(Some("Bearer s3cret")).await, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_debug_msr_requires_token() {
+        struct FixedMsr;
+        impl MsrAccess for FixedMsr {
+            fn read(&self, _cpu: u32, addr: u64) -> Result<u64> {
+                Ok(addr << 4)
+            }
+
+            fn write(&self, _cpu: u32, _addr: u64, _value: u64) -> Result<()> {
+                unreachable!("debug endpoints never write")
+            }
+        }
+
+        let state = |debug_endpoints| {
+            Arc::new(AppState {
+                subsystems: Vec::new(),
+                sessions: Sessions::new(Vec::new()),
+                self_metrics: None,
+                spike_detector: None,
+                readiness: Arc::new(Readiness::new()),
+                metric_stream: Arc::new(MetricStream::default()),
+                scrape_collector: None,
+                reset_token: None,
+                read_timeout: Arc::new(ReadTimeout::new(DEFAULT_READ_TIMEOUT)),
+                metric_filter: MetricFilter::default(),
+                debug_endpoints,
+                openmetrics: false,
+                collection_handle: None,
+            })
+        };
+        let read = |state: Arc<AppState>, authorization: Option<&'static str>, addr: &str| {
+            let mut headers = HeaderMap::new();
+            if let Some(value) = authorization {
+                headers.insert(header::AUTHORIZATION, value.parse().unwrap());
+            }
+            let query = DebugMsrQuery {
+                cpu: 0,
+                addr: addr.to_string(),
+            };
+            async move {
+                let response = debug_msr_handler(
+                    axum::extract::State(state),
+                    headers,
+                    axum::extract::Query(query),
+                )
+                .await
+                .into_response();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (status, String::from_utf8(body.to_vec()).unwrap())
+            }
+        };
+
+        let enabled = state(Some(DebugEndpoints {
+            token: "d3bug".to_string(),
+            msr: &FixedMsr,
+        }));
+        assert_eq!(
+            read(Arc::clone(&enabled), None, "0x611").await.0,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            read(Arc::clone(&enabled), Some("Bearer wrong"), "0x611")
+                .await
+                .0,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            read(Arc::clone(&enabled), Some("Bearer d3bug"), "0x611").await,
+            (StatusCode::OK, "0x0000000000006110".to_string())
+        );
+        assert_eq!(
+            read(Arc::clone(&enabled), Some("Bearer d3bug"), "1553").await,
+            (StatusCode::OK, "0x0000000000006110".to_string())
+        );
+        assert_eq!(
+            read(enabled, Some("Bearer d3bug"), "0xZZ").await.0,
+            StatusCode::BAD_REQUEST
+        );
+
+        // Off unless enabled, whatever the request carries
+        assert_eq!(
+            read(state(None), Some("Bearer d3bug"), "0x611").await.0,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn test_parse_listen_addr() {
+        assert_eq!(
+            "127.0.0.1:9100".parse::<ListenAddr>().unwrap(),
+            ListenAddr::Tcp(SocketAddr::from(([127, 0, 0, 1], 9100)))
+        );
+        assert_eq!(
+            "unix:/run/uncflow.sock".parse::<ListenAddr>().unwrap(),
+            ListenAddr::Unix(PathBuf::from("/run/uncflow.sock"))
+        );
+        assert!("unix:".parse::<ListenAddr>().is_err());
+        assert!("8080".parse::<ListenAddr>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_metrics_over_unix_socket() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let state = Arc::new(AppState {
+            subsystems: Vec::new(),
+            sessions: Sessions::new(Vec::new()),
+            self_metrics: Some(Arc::new(SelfMetrics::new().unwrap())),
+            spike_detector: None,
+            readiness: Arc::new(Readiness::new()),
+            metric_stream: Arc::new(MetricStream::default()),
+            scrape_collector: None,
+            reset_token: None,
+            read_timeout: Arc::new(ReadTimeout::new(DEFAULT_READ_TIMEOUT)),
+            metric_filter: MetricFilter::default(),
+            debug_endpoints: None,
+            openmetrics: false,
+            collection_handle: None,
+        });
+        let app = Router::new()
+            .route("/metrics", get(metrics_handler))
+            .with_state(state);
+        let path = std::env::temp_dir().join(format!("uncflow-{}.sock", std::process::id()));
+        let shutdown = CancellationToken::new();
+        let server = tokio::spawn(serve(ListenAddr::Unix(path.clone()), app, shutdown.clone()));
+
+        let mut stream = loop {
+            match tokio::net::UnixStream::connect(&path).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert!(response.contains("uncflow_build_info"));
+
+        shutdown.cancel();
+        server.await.unwrap().unwrap();
+        // The socket file goes with the server
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_json_logging_init() {
+        let args = Args::try_parse_from(["uncflow", "--quiet", "--log-format", "json"]).unwrap();
+        assert_eq!(args.log_format, LogFormat::Json);
+        assert!(args.quiet);
+        assert!(Args::try_parse_from(["uncflow", "--quiet", "--verbose"]).is_err());
+
+        tracing::subscriber::with_default(
+            log_subscriber(tracing::Level::WARN, args.log_format),
+            || tracing::warn!(socket = 0, "JSON logging smoke test"),
+        );
+    }
+
+    /// Parse a command line and apply its profile, as main() does
+    fn profile_args(argv: &[&str]) -> Args {
+        let matches = Args::command()
+            .try_get_matches_from(std::iter::once("uncflow").chain(argv.iter().copied()))
+            .unwrap();
+        let mut args = Args::from_arg_matches(&matches).unwrap();
+        if let Some(profile) = args.profile {
+            profile.apply(&mut args, &matches);
+        }
+        args
+    }
+
+    fn enabled_subsystems(args: &Args) -> Vec<&'static str> {
+        [
+            ("rapl", args.rapl),
+            ("rdt", args.rdt),
+            ("core", args.core_metrics),
+            ("imc", args.imc),
+            ("cha", args.cha),
+            ("irp", args.irp),
+            ("iio", args.iio),
+            ("upi", args.upi),
+            ("m2m", args.m2m),
+            ("uncore", args.uncore),
+        ]
+        .into_iter()
+        .filter_map(|(subsystem, on)| on.then_some(subsystem))
+        .collect()
+    }
+
+    #[test]
+    fn test_memory_profile() {
+        let args = profile_args(&["--profile", "memory"]);
+        assert_eq!(enabled_subsystems(&args), ["rapl", "imc", "cha", "m2m"]);
+        assert!(args.rollups);
+
+        // CHA narrowed to bandwidth and RAPL to DRAM, the other subsystems untouched
+        let filter = &args.metric_filter;
+        let kept = [
+            ("cha", "LLCReadBandwidth"),
+            ("cha", "LLCReadHitLatency"),
+            ("cha", "CHAUp"),
+            ("rapl", "DRAMPower"),
+            ("rapl", "rapl_dram_power_watts"),
+            ("rapl", "PackagePower"),
+            ("imc", "imc_read_latency_ns"),
+        ]
+        .into_iter()
+        .filter(|(subsystem, name)| filter.keeps(subsystem, name))
+        .collect::<Vec<_>>();
+        assert_eq!(
+            kept,
+            [
+                ("cha", "LLCReadBandwidth"),
+                ("rapl", "DRAMPower"),
+                ("rapl", "rapl_dram_power_watts"),
+                ("imc", "imc_read_latency_ns"),
+            ]
+        );
+
+        // Explicit flags win: --cha=false drops CHA, --rapl exports all of RAPL
+        let args = profile_args(&["--profile", "memory", "--cha=false", "--rapl"]);
+        assert_eq!(enabled_subsystems(&args), ["rapl", "imc", "m2m"]);
+        assert!(!args.metric_filter.restricts("rapl"));
+        assert!(args.metric_filter.keeps("rapl", "PackagePower"));
+
+        // Explicit flags add to the profile
+        let args = profile_args(&["--profile", "power", "--iio"]);
+        assert!(args.rapl && args.core_metrics && args.iio);
+        assert_eq!(args.metric_filter, MetricFilter::default());
+        assert!(Args::try_parse_from(["uncflow", "--profile", "disk"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_range_list() {
+        assert_eq!(
+            parse_range_list(&strings(&["0-3,5", "2", "8-9"]), false).unwrap(),
+            vec![0, 1, 2, 3, 5, 8, 9]
+        );
+        assert_eq!(
+            parse_range_list(&strings(&["0-3,5", "8-9"]), true).unwrap(),
+            vec![0, 1, 2, 3, 5, 8, 9]
+        );
+    }
+
+    #[test]
+    fn test_parse_range_list_partially_valid() {
+        // Bad entries are skipped unless strict
+        assert_eq!(
+            parse_range_list(&strings(&["0-1,x", "3-2", "4"]), false).unwrap(),
+            vec![0, 1, 4]
+        );
+        let err = parse_range_list(&strings(&["0-1,x"]), true).unwrap_err();
+        assert!(err.to_string().contains("\"x\""));
+    }
+
+    #[test]
+    fn test_parse_range_list_invalid() {
+        for input in [&["x"][..], &["0..3"], &["3-0"], &[" , "]] {
+            assert!(parse_range_list(&strings(input), false).is_err());
+        }
+    }
+
+    #[test]
+    fn test_parse_range_list_with_online_mask() {
+        let online = [0, 1, 2, 3, 4, 5, 6, 7];
+        let requested = parse_range_list(&strings(&["0-999"]), false).unwrap();
+
+        let cores = ExportConfig::retain_available(&requested, &online, "core");
+        assert_eq!(cores, online.to_vec());
+
+        let requested = parse_range_list(&strings(&["6-9,12"]), false).unwrap();
+        let cores = ExportConfig::retain_available(&requested, &online, "core");
+        assert_eq!(cores, vec![6, 7]);
+    }
+}
@@ -1,13 +1,58 @@
 // CHA Metric Calculator - Derives metrics from basic events
 
 use std::collections::HashMap;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use crate::common::counter::rate_window_secs;
 use crate::counters::cha::{LLCLookupType, LLCState, TransactionType};
-use crate::metrics::cha::TransactionMetricType;
+use crate::metrics::cha::{
+    ChaMetricSource, CreditType, QueueType, SFEvictionType, TransactionMetricType, VictimType,
+};
+use uncflow_raw::current_arch::cha::SF_ENTRIES_PER_CHA;
 
 const CACHELINE_SIZE: u64 = 64;
 
+/// Hit and miss last measured further apart than this come from different collects
+/// (the two passes of a transaction slot run back to back within one)
+const SAME_WINDOW_TOLERANCE: Duration = Duration::from_secs(1);
+
+/// Which of the occupancy, insert and clockticks counters were programmed successfully
+///
+/// Values of dead counters are zero and must not feed derived metrics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LiveCounters {
+    pub occupancy: bool,
+    pub insert: bool,
+    pub clockticks: bool,
+}
+
+impl LiveCounters {
+    pub const ALL: Self = Self {
+        occupancy: true,
+        insert: true,
+        clockticks: true,
+    };
+
+    pub fn all(&self) -> bool {
+        self.occupancy && self.insert && self.clockticks
+    }
+
+    /// Counters live in both, e.g. across accumulated measurement periods
+    pub fn and(self, other: Self) -> Self {
+        Self {
+            occupancy: self.occupancy && other.occupancy,
+            insert: self.insert && other.insert,
+            clockticks: self.clockticks && other.clockticks,
+        }
+    }
+}
+
+impl Default for LiveCounters {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
 /// Raw event data from hardware counters
 #[derive(Debug, Clone, Default)]
 pub struct RawEventData {
@@ -15,6 +60,9 @@ pub struct RawEventData {
     pub insert: u64,
     pub clockticks: u64,
     pub duration: Duration,
+    pub live: LiveCounters,
+    /// When the group was last counted; rotated groups keep older data between turns
+    pub measured_at: Option<Instant>,
 }
 
 /// Calculator for derived CHA metrics
@@ -35,23 +83,53 @@ impl MetricCalculator {
         self.events.insert(name, data);
     }
 
-    /// Calculate bandwidth in GB/s from insert count
-    fn calculate_bandwidth(insert: u64, duration: Duration) -> f64 {
-        let seconds = duration.as_secs_f64();
-        if seconds == 0.0 {
-            return 0.0;
-        }
-        (insert as f64 * CACHELINE_SIZE as f64) / seconds / 1e9
+    /// Calculate bandwidth in GB/s from insert count, None if the window is too short
+    fn calculate_bandwidth(insert: u64, duration: Duration) -> Option<f64> {
+        let seconds = rate_window_secs(duration)?;
+        Some((insert as f64 * CACHELINE_SIZE as f64) / seconds / 1e9)
     }
 
-    /// Calculate latency in nanoseconds
-    fn calculate_latency(occupancy: u64, insert: u64, clockticks: u64, duration: Duration) -> f64 {
+    /// Calculate latency in nanoseconds, None if the window is too short
+    fn calculate_latency(
+        occupancy: u64,
+        insert: u64,
+        clockticks: u64,
+        duration: Duration,
+    ) -> Option<f64> {
+        let elapsed_ns = rate_window_secs(duration)? * 1e9;
         if insert == 0 || clockticks == 0 {
+            return Some(0.0);
+        }
+        Some((occupancy as f64 / insert as f64) * (clockticks as f64 / elapsed_ns))
+    }
+
+    /// Combined latency of hit and miss transactions, weighted by their inserts:
+    ///
+    /// Latency = (HitLatency * HitInsert + MissLatency * MissInsert) / (HitInsert + MissInsert)
+    ///
+    /// Each insert is one transaction, so this is the mean latency over all of them.
+    fn calculate_combined_latency(
+        hit_latency: f64,
+        hit_insert: u64,
+        miss_latency: f64,
+        miss_insert: u64,
+    ) -> f64 {
+        let total = hit_insert + miss_insert;
+        if total == 0 {
             return 0.0;
         }
+        (hit_latency * hit_insert as f64 + miss_latency * miss_insert as f64) / total as f64
+    }
 
-        let elapsed_ns = duration.as_nanos() as f64;
-        (occupancy as f64 / insert as f64) * (clockticks as f64 / elapsed_ns)
+    /// Whether hit and miss were counted in the same collect, so their inserts weigh
+    /// the same traffic
+    fn same_window(hit: &RawEventData, miss: &RawEventData) -> bool {
+        match (hit.measured_at, miss.measured_at) {
+            (Some(hit_at), Some(miss_at)) => {
+                hit_at.max(miss_at) - hit_at.min(miss_at) <= SAME_WINDOW_TOLERANCE
+            }
+            _ => false,
+        }
     }
 
     /// Calculate hit rate as ratio
@@ -84,40 +162,72 @@ impl MetricCalculator {
         let hit_data = self.events.get(&hit_name);
         let miss_data = self.events.get(&miss_name);
 
+        // Metrics are only derived from counters that were programmed successfully
         if let (Some(hit), Some(miss)) = (hit_data, miss_data) {
-            // Bandwidth metrics
-            let hit_bw = Self::calculate_bandwidth(hit.insert, hit.duration);
-            let miss_bw = Self::calculate_bandwidth(miss.insert, miss.duration);
-            let total_bw = hit_bw + miss_bw;
-
-            metrics.insert(TransactionMetricType::Bandwidth, total_bw);
-            metrics.insert(TransactionMetricType::HitBandwidth, hit_bw);
-            metrics.insert(TransactionMetricType::MissBandwidth, miss_bw);
+            // Bandwidth metrics; rates over a too-short window are left out so
+            // the exported gauges keep their previous value
+            let hit_bw =
+                Self::calculate_bandwidth(hit.insert, hit.duration).filter(|_| hit.live.insert);
+            let miss_bw =
+                Self::calculate_bandwidth(miss.insert, miss.duration).filter(|_| miss.live.insert);
+
+            if let (Some(hit_bw), Some(miss_bw)) = (hit_bw, miss_bw) {
+                metrics.insert(TransactionMetricType::Bandwidth, hit_bw + miss_bw);
+            }
+            if let Some(hit_bw) = hit_bw {
+                metrics.insert(TransactionMetricType::HitBandwidth, hit_bw);
+            }
+            if let Some(miss_bw) = miss_bw {
+                metrics.insert(TransactionMetricType::MissBandwidth, miss_bw);
+            }
 
             // Latency metrics
             let hit_lat =
-                Self::calculate_latency(hit.occupancy, hit.insert, hit.clockticks, hit.duration);
+                Self::calculate_latency(hit.occupancy, hit.insert, hit.clockticks, hit.duration)
+                    .filter(|_| hit.live.all());
             let miss_lat = Self::calculate_latency(
                 miss.occupancy,
                 miss.insert,
                 miss.clockticks,
                 miss.duration,
-            );
-
-            metrics.insert(TransactionMetricType::HitLatency, hit_lat);
-            metrics.insert(TransactionMetricType::MissLatency, miss_lat);
-            metrics.insert(TransactionMetricType::Latency, 0.0); // Placeholder
+            )
+            .filter(|_| miss.live.all());
+            if let Some(hit_lat) = hit_lat {
+                metrics.insert(TransactionMetricType::HitLatency, hit_lat);
+            }
+            if let Some(miss_lat) = miss_lat {
+                metrics.insert(TransactionMetricType::MissLatency, miss_lat);
+            }
+            // Inserts from different windows would weigh unrelated traffic
+            if let (Some(hit_lat), Some(miss_lat)) = (hit_lat, miss_lat) {
+                if Self::same_window(hit, miss) {
+                    metrics.insert(
+                        TransactionMetricType::Latency,
+                        Self::calculate_combined_latency(
+                            hit_lat,
+                            hit.insert,
+                            miss_lat,
+                            miss.insert,
+                        ),
+                    );
+                }
+            }
 
             // Hit rate
-            let hit_rate = Self::calculate_hit_rate(hit.insert, miss.insert);
-            metrics.insert(TransactionMetricType::HitRate, hit_rate);
+            if hit.live.insert && miss.live.insert {
+                let hit_rate = Self::calculate_hit_rate(hit.insert, miss.insert);
+                metrics.insert(TransactionMetricType::HitRate, hit_rate);
+            }
 
             // Occupancy ratios
-            let hit_occ = Self::calculate_occupancy(hit.occupancy, hit.clockticks);
-            let miss_occ = Self::calculate_occupancy(miss.occupancy, miss.clockticks);
-
-            metrics.insert(TransactionMetricType::HitOccupancy, hit_occ);
-            metrics.insert(TransactionMetricType::MissOccupancy, miss_occ);
+            if hit.live.occupancy && hit.live.clockticks {
+                let hit_occ = Self::calculate_occupancy(hit.occupancy, hit.clockticks);
+                metrics.insert(TransactionMetricType::HitOccupancy, hit_occ);
+            }
+            if miss.live.occupancy && miss.live.clockticks {
+                let miss_occ = Self::calculate_occupancy(miss.occupancy, miss.clockticks);
+                metrics.insert(TransactionMetricType::MissOccupancy, miss_occ);
+            }
         }
 
         metrics
@@ -130,32 +240,36 @@ impl MetricCalculator {
     }
 
     /// Get LLC victim count
-    pub fn get_llc_victim(&self, victim_type: &str) -> u64 {
-        let name = format!("LLC Victim {victim_type}");
-        self.events.get(&name).map(|data| data.insert).unwrap_or(0)
+    pub fn get_llc_victim(&self, victim_type: VictimType) -> u64 {
+        self.events
+            .get(&victim_type.event_name())
+            .map(|data| data.insert)
+            .unwrap_or(0)
     }
 
     /// Get SF eviction count
-    pub fn get_sf_eviction(&self, eviction_type: &str) -> u64 {
-        let name = format!("SF Eviction {eviction_type}");
-        self.events.get(&name).map(|data| data.insert).unwrap_or(0)
+    pub fn get_sf_eviction(&self, eviction_type: SFEvictionType) -> u64 {
+        self.events
+            .get(&eviction_type.event_name())
+            .map(|data| data.insert)
+            .unwrap_or(0)
     }
 
-    /// Calculate eviction bandwidth
-    pub fn calculate_eviction_bandwidth(&self) -> f64 {
+    /// Calculate eviction bandwidth, None if the window is too short
+    pub fn calculate_eviction_bandwidth(&self) -> Option<f64> {
         if let Some(data) = self.events.get("Eviction") {
             Self::calculate_bandwidth(data.insert, data.duration)
         } else {
-            0.0
+            Some(0.0)
         }
     }
 
-    /// Calculate eviction latency
-    pub fn calculate_eviction_latency(&self) -> f64 {
+    /// Calculate eviction latency, None if the window is too short
+    pub fn calculate_eviction_latency(&self) -> Option<f64> {
         if let Some(data) = self.events.get("Eviction") {
             Self::calculate_latency(data.occupancy, data.insert, data.clockticks, data.duration)
         } else {
-            0.0
+            Some(0.0)
         }
     }
 
@@ -168,33 +282,120 @@ impl MetricCalculator {
         }
     }
 
-    /// Calculate uncore frequency in GHz
-    pub fn calculate_uncore_frequency(&self) -> f64 {
+    /// Calculate uncore frequency in GHz, None if the window is too short
+    pub fn calculate_uncore_frequency(&self) -> Option<f64> {
         // Use clockticks from any event to calculate frequency
         for data in self.events.values() {
-            if data.clockticks > 0 && data.duration.as_secs_f64() > 0.0 {
-                return data.clockticks as f64 / data.duration.as_secs_f64() / 1e9;
+            if data.live.clockticks && data.clockticks > 0 {
+                let seconds = rate_window_secs(data.duration)?;
+                return Some(data.clockticks as f64 / seconds / 1e9);
             }
         }
-        0.0
+        Some(0.0)
     }
 
     /// Get queue occupancy metric
-    pub fn get_queue_occupancy(&self, queue_name: &str) -> f64 {
-        if let Some(data) = self.events.get(queue_name) {
+    pub fn get_queue_occupancy(&self, queue: QueueType) -> f64 {
+        if let Some(data) = self.events.get(queue.event_name()) {
             Self::calculate_occupancy(data.occupancy, data.clockticks)
         } else {
             0.0
         }
     }
 
+    /// Average valid snoop filter entries per CHA
+    ///
+    /// Occupancy and clockticks are both summed over the CHA boxes, so their ratio
+    /// is the per-box average.
+    pub fn get_sf_occupancy(&self) -> Option<f64> {
+        let data = self.events.get("SF Occupancy")?;
+        (data.live.occupancy && data.live.clockticks)
+            .then(|| Self::calculate_occupancy(data.occupancy, data.clockticks))
+    }
+
+    /// Fraction of the snoop filter in use, see [`SF_ENTRIES_PER_CHA`]
+    pub fn calculate_sf_occupancy_ratio(&self) -> Option<f64> {
+        Some(self.get_sf_occupancy()? / SF_ENTRIES_PER_CHA as f64)
+    }
+
+    /// Snoop filter evictions, each back-invalidating the line in the cores
+    pub fn get_sf_back_invalidations(&self) -> u64 {
+        self.events
+            .get("SF Occupancy")
+            .filter(|data| data.live.insert)
+            .map(|data| data.insert)
+            .unwrap_or(0)
+    }
+
     /// Get credit metric
-    pub fn get_credit_metric(&self, metric_name: &str) -> u64 {
+    pub fn get_credit_metric(&self, credit: CreditType) -> u64 {
         self.events
-            .get(metric_name)
+            .get(credit.event_name())
             .map(|data| data.insert)
             .unwrap_or(0)
     }
+
+    /// When the data `source` is derived from was measured, None if it never was
+    ///
+    /// A metric is only as fresh as the oldest group it needs; one that any group
+    /// can provide (uncore frequency) is as fresh as the newest.
+    pub fn measured_at(&self, source: ChaMetricSource) -> Option<Instant> {
+        let groups = source.event_groups();
+        if groups.is_empty() {
+            return self
+                .events
+                .values()
+                .filter_map(|data| data.measured_at)
+                .max();
+        }
+        groups
+            .iter()
+            .map(|group| self.events.get(group)?.measured_at)
+            .collect::<Option<Vec<_>>>()?
+            .into_iter()
+            .min()
+    }
+
+    /// How long the counts `source` is derived from were counted, None if never
+    ///
+    /// Only meaningful for sources counted by a single event group.
+    pub fn window(&self, source: ChaMetricSource) -> Option<Duration> {
+        let groups = source.event_groups();
+        let [group] = groups.as_slice() else {
+            return None;
+        };
+        self.events.get(group).map(|data| data.duration)
+    }
+
+    /// Value of a metric source, None if it cannot be derived from this measurement
+    pub fn evaluate(&self, source: ChaMetricSource) -> Option<f64> {
+        match source {
+            ChaMetricSource::Transaction(trans_type, metric_type) => self
+                .calculate_transaction_metrics(trans_type)
+                .get(&metric_type)
+                .copied(),
+            ChaMetricSource::LLCLookup(state, lookup_type) => {
+                Some(self.get_llc_lookup(state, lookup_type) as f64)
+            }
+            ChaMetricSource::LLCVictim(victim_type) => {
+                Some(self.get_llc_victim(victim_type) as f64)
+            }
+            ChaMetricSource::SFEviction(eviction_type) => {
+                Some(self.get_sf_eviction(eviction_type) as f64)
+            }
+            ChaMetricSource::EvictionBandwidth => self.calculate_eviction_bandwidth(),
+            ChaMetricSource::EvictionLatency => self.calculate_eviction_latency(),
+            ChaMetricSource::EvictionQueueOccupancy => {
+                Some(self.calculate_eviction_queue_occupancy())
+            }
+            ChaMetricSource::QueueOccupancy(queue) => Some(self.get_queue_occupancy(queue)),
+            ChaMetricSource::UncoreFrequency => self.calculate_uncore_frequency(),
+            ChaMetricSource::Credit(credit) => Some(self.get_credit_metric(credit) as f64),
+            ChaMetricSource::SFOccupancy => self.get_sf_occupancy(),
+            ChaMetricSource::SFOccupancyRatio => self.calculate_sf_occupancy_ratio(),
+            ChaMetricSource::SFBackInvalidations => Some(self.get_sf_back_invalidations() as f64),
+        }
+    }
 }
 
 impl Default for MetricCalculator {
@@ -209,7 +410,7 @@ mod tests {
 
     #[test]
     fn test_bandwidth_calculation() {
-        let bw = MetricCalculator::calculate_bandwidth(1000, Duration::from_secs(1));
+        let bw = MetricCalculator::calculate_bandwidth(1000, Duration::from_secs(1)).unwrap();
         // 1000 * 64 bytes / 1 second = 64000 bytes/s = 0.000064 GB/s
         assert!((bw - 0.000_064).abs() < 1e-9);
     }
@@ -225,4 +426,190 @@ mod tests {
         let occ = MetricCalculator::calculate_occupancy(1000, 10000);
         assert!((occ - 0.1).abs() < 1e-9);
     }
+
+    #[test]
+    fn test_dead_counters_are_not_exported() {
+        let mut calculator = MetricCalculator::new();
+        let hit = RawEventData {
+            occupancy: 1000,
+            insert: 100,
+            clockticks: 10_000,
+            duration: Duration::from_secs(1),
+            measured_at: None,
+            live: LiveCounters {
+                insert: false,
+                ..LiveCounters::ALL
+            },
+        };
+        let miss = RawEventData {
+            live: LiveCounters::ALL,
+            ..hit.clone()
+        };
+        calculator.store_event("PCIeRead Hit".to_string(), hit);
+        calculator.store_event("PCIeRead Miss".to_string(), miss);
+
+        let metrics = calculator.calculate_transaction_metrics(TransactionType::PCIeRead);
+        assert!(metrics.contains_key(&TransactionMetricType::HitOccupancy));
+        assert!(metrics.contains_key(&TransactionMetricType::MissBandwidth));
+        assert!(metrics.contains_key(&TransactionMetricType::MissLatency));
+        for dead in [
+            TransactionMetricType::HitBandwidth,
+            TransactionMetricType::HitLatency,
+            TransactionMetricType::HitRate,
+            TransactionMetricType::Bandwidth,
+        ] {
+            assert!(!metrics.contains_key(&dead), "{dead:?} was exported");
+        }
+    }
+
+    #[test]
+    fn test_combined_latency_is_insert_weighted() {
+        // 300 hits at 50ns and 100 misses at 150ns: 75ns on average
+        assert_eq!(
+            MetricCalculator::calculate_combined_latency(50.0, 300, 150.0, 100),
+            75.0
+        );
+        assert_eq!(
+            MetricCalculator::calculate_combined_latency(50.0, 0, 150.0, 0),
+            0.0
+        );
+
+        let now = Instant::now();
+        // 2 cycles per ns over 1s: 100 and 300 cycles per transaction, 200ns and 600ns
+        let hit = RawEventData {
+            occupancy: 30_000,
+            insert: 300,
+            clockticks: 2_000_000_000,
+            duration: Duration::from_secs(1),
+            measured_at: Some(now),
+            live: LiveCounters::ALL,
+        };
+        let miss = RawEventData {
+            occupancy: 30_000,
+            insert: 100,
+            measured_at: Some(now + Duration::from_millis(100)),
+            ..hit.clone()
+        };
+        let mut calculator = MetricCalculator::new();
+        calculator.store_event("PCIeRead Hit".to_string(), hit.clone());
+        calculator.store_event("PCIeRead Miss".to_string(), miss.clone());
+        let metrics = calculator.calculate_transaction_metrics(TransactionType::PCIeRead);
+        assert_eq!(metrics[&TransactionMetricType::HitLatency], 200.0);
+        assert_eq!(metrics[&TransactionMetricType::MissLatency], 600.0);
+        assert_eq!(metrics[&TransactionMetricType::Latency], 300.0);
+
+        // Hit and miss from different rotations are not combined
+        let stale = RawEventData {
+            measured_at: Some(now + Duration::from_secs(26)),
+            ..miss
+        };
+        calculator.store_event("PCIeRead Miss".to_string(), stale);
+        let metrics = calculator.calculate_transaction_metrics(TransactionType::PCIeRead);
+        assert!(!metrics.contains_key(&TransactionMetricType::Latency));
+    }
+
+    #[test]
+    fn test_short_window_keeps_previous_rates() {
+        let mut calculator = MetricCalculator::new();
+        // A few hundred inserts over 1us would read as tens of GB/s
+        let data = RawEventData {
+            occupancy: 1000,
+            insert: 500,
+            clockticks: 2000,
+            duration: Duration::from_micros(1),
+            measured_at: None,
+            live: LiveCounters::ALL,
+        };
+        calculator.store_event("PCIeRead Hit".to_string(), data.clone());
+        calculator.store_event("PCIeRead Miss".to_string(), data.clone());
+        calculator.store_event("Eviction".to_string(), data);
+
+        let metrics = calculator.calculate_transaction_metrics(TransactionType::PCIeRead);
+        for rate in [
+            TransactionMetricType::Bandwidth,
+            TransactionMetricType::HitBandwidth,
+            TransactionMetricType::MissLatency,
+        ] {
+            assert!(!metrics.contains_key(&rate), "{rate:?} was exported");
+        }
+        // Ratios do not depend on the window length
+        assert!(metrics.contains_key(&TransactionMetricType::HitRate));
+        assert_eq!(calculator.calculate_eviction_bandwidth(), None);
+        assert_eq!(calculator.calculate_uncore_frequency(), None);
+    }
+
+    #[test]
+    fn test_every_metric_evaluates() {
+        use crate::metrics::cha::ChaMetric;
+
+        let data = RawEventData {
+            occupancy: 1000,
+            insert: 100,
+            clockticks: 10_000,
+            duration: Duration::from_secs(1),
+            measured_at: Some(Instant::now()),
+            live: LiveCounters::ALL,
+        };
+        let mut calculator = MetricCalculator::new();
+        calculator.store_event("Eviction".to_string(), data.clone());
+        calculator.store_event(CreditType::Read.event_name().to_string(), data.clone());
+        calculator.store_event("SF Occupancy".to_string(), data.clone());
+        for trans_type in TransactionType::all() {
+            for outcome in ["Hit", "Miss"] {
+                let name = format!("{} {outcome}", trans_type.name());
+                calculator.store_event(name, data.clone());
+            }
+        }
+
+        for metric in ChaMetric::all() {
+            assert!(
+                calculator.evaluate(metric.source()).is_some(),
+                "{} has no value",
+                metric.name()
+            );
+        }
+        assert_eq!(
+            calculator.evaluate(ChaMetricSource::Credit(CreditType::Read)),
+            Some(100.0)
+        );
+        assert_eq!(
+            calculator.evaluate(ChaMetricSource::Credit(CreditType::Write)),
+            Some(0.0)
+        );
+    }
+
+    #[test]
+    fn test_sf_occupancy_ratio() {
+        let mut calculator = MetricCalculator::new();
+        assert_eq!(calculator.calculate_sf_occupancy_ratio(), None);
+
+        // Two boxes over 1000 cycles each, holding 12288 and 6144 entries on average
+        let data = RawEventData {
+            occupancy: (12_288 + 6_144) * 1000,
+            insert: 42,
+            clockticks: 2 * 1000,
+            duration: Duration::from_secs(1),
+            measured_at: None,
+            live: LiveCounters::ALL,
+        };
+        calculator.store_event("SF Occupancy".to_string(), data.clone());
+        assert_eq!(calculator.get_sf_occupancy(), Some(9_216.0));
+        // 9216 of 24576 entries
+        assert_eq!(calculator.calculate_sf_occupancy_ratio(), Some(0.375));
+        assert_eq!(calculator.get_sf_back_invalidations(), 42);
+        // Back-invalidations are not LLC victims
+        assert_eq!(calculator.get_llc_victim(VictimType::M), 0);
+
+        // A dead occupancy counter has no ratio rather than an empty snoop filter
+        let dead = RawEventData {
+            live: LiveCounters {
+                occupancy: false,
+                ..LiveCounters::ALL
+            },
+            ..data
+        };
+        calculator.store_event("SF Occupancy".to_string(), dead);
+        assert_eq!(calculator.calculate_sf_occupancy_ratio(), None);
+        assert_eq!(calculator.get_sf_back_invalidations(), 42);
+    }
 }
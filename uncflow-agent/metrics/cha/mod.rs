@@ -1,5 +1,8 @@
 pub mod calculator;
 pub mod types;
 
-pub use calculator::{MetricCalculator, RawEventData};
-pub use types::{ChaMetric, SFEvictionType, TransactionMetricType, VictimType};
+pub use calculator::{LiveCounters, MetricCalculator, RawEventData};
+pub use types::{
+    ChaMetric, ChaMetricSource, CreditType, QueueType, SFEvictionType, TransactionMetricType,
+    VictimType,
+};
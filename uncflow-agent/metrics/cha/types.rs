@@ -1,6 +1,12 @@
 // CHA (Cache Home Agent) metrics - comprehensive coverage
 
+use std::str::FromStr;
+
 use crate::counters::cha::{LLCLookupType, LLCState, TransactionType};
+use crate::error::{Result, UncflowError};
+use crate::metrics::kind::MetricKind;
+use crate::metrics::unit::MetricUnit;
+use uncflow_raw::current_arch::cha::umasks::irq_reject;
 
 /// Transaction-specific derived metric types
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -11,7 +17,7 @@ pub enum TransactionMetricType {
     HitLatency,    // HitOccupancy / HitInsert * (HitClocks / duration)
     MissLatency,   // MissOccupancy / MissInsert * (MissClocks / duration)
     HitRate,       // HitInsert / (HitInsert + MissInsert)
-    Latency,       // Combined latency (placeholder)
+    Latency,       // Insert-weighted mean of HitLatency and MissLatency
     HitOccupancy,  // HitOccupancy / HitClockTicks
     MissOccupancy, // MissOccupancy / MissClockTicks
 }
@@ -47,6 +53,9 @@ impl TransactionMetricType {
 }
 
 /// LLC Victim types
+///
+/// An LLC victim is a line evicted from the LLC slice to make room for another;
+/// cores keep their copies. Compare [`SFEvictionType`].
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum VictimType {
     M, // Modified
@@ -74,12 +83,22 @@ impl VictimType {
         }
     }
 
+    /// Name of the event group counting this victim type
+    pub fn event_name(&self) -> String {
+        format!("LLC Victim {}", self.name())
+    }
+
     pub fn all() -> Vec<VictimType> {
         vec![VictimType::M, VictimType::E, VictimType::S, VictimType::F]
     }
 }
 
 /// SF Eviction types
+///
+/// A snoop filter eviction frees an entry of the filter tracking lines held by
+/// cores, and back-invalidates those lines in every core that holds them. Unlike
+/// LLC victims these hit lines still in use, so a full snoop filter shows up as
+/// core cache misses rather than LLC misses.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum SFEvictionType {
     M, // Modified
@@ -104,11 +123,123 @@ impl SFEvictionType {
         }
     }
 
+    /// Name of the event group counting this eviction type
+    pub fn event_name(&self) -> String {
+        format!("SF Eviction {}", self.name())
+    }
+
     pub fn all() -> Vec<SFEvictionType> {
         vec![SFEvictionType::M, SFEvictionType::E, SFEvictionType::S]
     }
 }
 
+/// Request queues whose occupancy is exported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QueueType {
+    IRQ, // Ingress request queue
+    PRQ, // Prefetch request queue
+}
+
+impl QueueType {
+    /// Name of the event group measuring this queue
+    pub fn event_name(&self) -> &'static str {
+        match self {
+            QueueType::IRQ => "IRQ",
+            QueueType::PRQ => "PRQ",
+        }
+    }
+}
+
+/// Requests stalled for lack of credits
+///
+/// Counted as IRQ rejects (RxC_IRQ0_REJECT, event 0x18 on Skylake-SP): a request
+/// leaving the CHA is rejected when the mesh has no credit for its message class.
+/// Reads need an AD request credit (umask 0x01, AD_REQ_VN0), writebacks a BL
+/// writeback credit (umask 0x08, BL_WB_VN0).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CreditType {
+    Read,
+    Write,
+}
+
+impl CreditType {
+    pub fn umask(&self) -> u8 {
+        match self {
+            CreditType::Read => irq_reject::AD_REQ_VN0,
+            CreditType::Write => irq_reject::BL_WB_VN0,
+        }
+    }
+
+    pub fn all() -> Vec<CreditType> {
+        vec![CreditType::Read, CreditType::Write]
+    }
+
+    /// Name of the event group counting these stalls
+    pub fn event_name(&self) -> &'static str {
+        match self {
+            CreditType::Read => "ReadNoCredit",
+            CreditType::Write => "WriteNoCredit",
+        }
+    }
+}
+
+/// How the exporter derives a [`ChaMetric`] from the measured event groups
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChaMetricSource {
+    /// One entry of the transaction's derived metrics
+    Transaction(TransactionType, TransactionMetricType),
+    /// Insert count of an LLC lookup group
+    LLCLookup(LLCState, LLCLookupType),
+    /// Insert count of an LLC victim group
+    LLCVictim(VictimType),
+    /// Insert count of a snoop filter eviction group
+    SFEviction(SFEvictionType),
+    EvictionBandwidth,
+    EvictionLatency,
+    EvictionQueueOccupancy,
+    /// Occupancy over clockticks of a request queue
+    QueueOccupancy(QueueType),
+    UncoreFrequency,
+    /// Insert count of a no-credit group
+    Credit(CreditType),
+    /// Valid snoop filter entries per CHA
+    SFOccupancy,
+    /// Valid snoop filter entries over snoop filter capacity
+    SFOccupancyRatio,
+    /// Snoop filter evictions in any state
+    SFBackInvalidations,
+}
+
+impl ChaMetricSource {
+    /// Names of the event groups this source is derived from, empty if any group will do
+    pub fn event_groups(&self) -> Vec<String> {
+        match self {
+            ChaMetricSource::Transaction(trans_type, _) => vec![
+                format!("{} Hit", trans_type.name()),
+                format!("{} Miss", trans_type.name()),
+            ],
+            ChaMetricSource::LLCLookup(state, lookup_type) => {
+                vec![format!(
+                    "LLC Lookup {} {}",
+                    state.name(),
+                    lookup_type.name()
+                )]
+            }
+            ChaMetricSource::LLCVictim(victim_type) => vec![victim_type.event_name()],
+            ChaMetricSource::SFEviction(eviction_type) => vec![eviction_type.event_name()],
+            ChaMetricSource::EvictionBandwidth
+            | ChaMetricSource::EvictionLatency
+            | ChaMetricSource::EvictionQueueOccupancy => vec!["Eviction".to_string()],
+            ChaMetricSource::QueueOccupancy(queue) => vec![queue.event_name().to_string()],
+            ChaMetricSource::UncoreFrequency => Vec::new(),
+            ChaMetricSource::Credit(credit) => vec![credit.event_name().to_string()],
+            ChaMetricSource::SFOccupancy
+            | ChaMetricSource::SFOccupancyRatio
+            | ChaMetricSource::SFBackInvalidations => vec!["SF Occupancy".to_string()],
+        }
+    }
+}
+
 /// Comprehensive CHA metrics enum
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ChaMetric {
@@ -139,6 +270,11 @@ pub enum ChaMetric {
     // Credit metrics: 2 metrics
     ReadNoCredit,
     WriteNoCredit,
+
+    // Snoop filter metrics: 3 metrics
+    SFOccupancy,
+    SFOccupancyRatio,
+    SFBackInvalidations,
 }
 
 impl ChaMetric {
@@ -164,6 +300,87 @@ impl ChaMetric {
             ChaMetric::UncoreFrequency => "UncoreFrequency".to_string(),
             ChaMetric::ReadNoCredit => "ReadNoCredit".to_string(),
             ChaMetric::WriteNoCredit => "WriteNoCredit".to_string(),
+            ChaMetric::SFOccupancy => "SFOccupancy".to_string(),
+            ChaMetric::SFOccupancyRatio => "SFOccupancyRatio".to_string(),
+            ChaMetric::SFBackInvalidations => "SFBackInvalidations".to_string(),
+        }
+    }
+
+    /// How the metric's value relates to time, see [`MetricKind`]
+    pub fn kind(&self) -> MetricKind {
+        match self {
+            ChaMetric::Transaction(_, TransactionMetricType::Bandwidth)
+            | ChaMetric::Transaction(_, TransactionMetricType::HitBandwidth)
+            | ChaMetric::Transaction(_, TransactionMetricType::MissBandwidth)
+            | ChaMetric::EvictionBandwidth
+            | ChaMetric::UncoreFrequency => MetricKind::Rate,
+            ChaMetric::LLCLookup(..)
+            | ChaMetric::LLCVictim(_)
+            | ChaMetric::SFEviction(_)
+            | ChaMetric::ReadNoCredit
+            | ChaMetric::WriteNoCredit
+            | ChaMetric::SFBackInvalidations => MetricKind::Count,
+            ChaMetric::Transaction(..)
+            | ChaMetric::EvictionLatency
+            | ChaMetric::EvictionQueueOccupancy
+            | ChaMetric::IRQOccupancy
+            | ChaMetric::PRQOccupancy
+            | ChaMetric::SFOccupancy
+            | ChaMetric::SFOccupancyRatio => MetricKind::Instantaneous,
+        }
+    }
+
+    /// Unit of the metric's values
+    pub fn unit(&self) -> Option<MetricUnit> {
+        match self {
+            ChaMetric::Transaction(_, TransactionMetricType::Bandwidth)
+            | ChaMetric::Transaction(_, TransactionMetricType::HitBandwidth)
+            | ChaMetric::Transaction(_, TransactionMetricType::MissBandwidth)
+            | ChaMetric::EvictionBandwidth => Some(MetricUnit::GigabytesPerSecond),
+            ChaMetric::Transaction(_, TransactionMetricType::HitLatency)
+            | ChaMetric::Transaction(_, TransactionMetricType::MissLatency)
+            | ChaMetric::Transaction(_, TransactionMetricType::Latency)
+            | ChaMetric::EvictionLatency => Some(MetricUnit::Nanoseconds),
+            ChaMetric::Transaction(_, TransactionMetricType::HitRate)
+            | ChaMetric::SFOccupancyRatio => Some(MetricUnit::Ratio),
+            ChaMetric::UncoreFrequency => Some(MetricUnit::Gigahertz),
+            ChaMetric::Transaction(_, TransactionMetricType::HitOccupancy)
+            | ChaMetric::Transaction(_, TransactionMetricType::MissOccupancy)
+            | ChaMetric::LLCLookup(..)
+            | ChaMetric::LLCVictim(_)
+            | ChaMetric::SFEviction(_)
+            | ChaMetric::EvictionQueueOccupancy
+            | ChaMetric::IRQOccupancy
+            | ChaMetric::PRQOccupancy
+            | ChaMetric::ReadNoCredit
+            | ChaMetric::WriteNoCredit
+            | ChaMetric::SFOccupancy
+            | ChaMetric::SFBackInvalidations => None,
+        }
+    }
+
+    /// Where the metric's value comes from
+    pub fn source(&self) -> ChaMetricSource {
+        match *self {
+            ChaMetric::Transaction(trans_type, metric_type) => {
+                ChaMetricSource::Transaction(trans_type, metric_type)
+            }
+            ChaMetric::LLCLookup(state, lookup_type) => {
+                ChaMetricSource::LLCLookup(state, lookup_type)
+            }
+            ChaMetric::LLCVictim(victim_type) => ChaMetricSource::LLCVictim(victim_type),
+            ChaMetric::SFEviction(eviction_type) => ChaMetricSource::SFEviction(eviction_type),
+            ChaMetric::EvictionBandwidth => ChaMetricSource::EvictionBandwidth,
+            ChaMetric::EvictionLatency => ChaMetricSource::EvictionLatency,
+            ChaMetric::EvictionQueueOccupancy => ChaMetricSource::EvictionQueueOccupancy,
+            ChaMetric::IRQOccupancy => ChaMetricSource::QueueOccupancy(QueueType::IRQ),
+            ChaMetric::PRQOccupancy => ChaMetricSource::QueueOccupancy(QueueType::PRQ),
+            ChaMetric::UncoreFrequency => ChaMetricSource::UncoreFrequency,
+            ChaMetric::ReadNoCredit => ChaMetricSource::Credit(CreditType::Read),
+            ChaMetric::WriteNoCredit => ChaMetricSource::Credit(CreditType::Write),
+            ChaMetric::SFOccupancy => ChaMetricSource::SFOccupancy,
+            ChaMetric::SFOccupancyRatio => ChaMetricSource::SFOccupancyRatio,
+            ChaMetric::SFBackInvalidations => ChaMetricSource::SFBackInvalidations,
         }
     }
 
@@ -205,6 +422,11 @@ impl ChaMetric {
         metrics.push(ChaMetric::ReadNoCredit);
         metrics.push(ChaMetric::WriteNoCredit);
 
+        // Snoop filter metrics (3)
+        metrics.push(ChaMetric::SFOccupancy);
+        metrics.push(ChaMetric::SFOccupancyRatio);
+        metrics.push(ChaMetric::SFBackInvalidations);
+
         metrics
     }
 
@@ -233,6 +455,26 @@ impl ChaMetric {
     }
 }
 
+impl FromStr for ChaMetric {
+    type Err = UncflowError;
+
+    /// Inverse of `name()`; names are composed from their parts, so all metrics are searched
+    fn from_str(s: &str) -> Result<Self> {
+        Self::all()
+            .into_iter()
+            .find(|metric| metric.name() == s)
+            .ok_or_else(|| UncflowError::ParseError(format!("Unknown ChaMetric '{s}'")))
+    }
+}
+
+impl TryFrom<&str> for ChaMetric {
+    type Error = UncflowError;
+
+    fn try_from(s: &str) -> Result<Self> {
+        s.parse()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -241,7 +483,7 @@ mod tests {
     fn test_metric_count() {
         let all_metrics = ChaMetric::all();
 
-        // 99 transaction + 28 LLC lookup + 4 victim + 3 eviction + 8 other = 142
+        // 99 transaction + 28 LLC lookup + 4 victim + 3 eviction + 8 other + 3 SF = 145
         // (Note: This is slightly more than the 137 mentioned due to including all states)
         assert!(all_metrics.len() >= 137);
         println!("Total CHA metrics: {}", all_metrics.len());
@@ -264,4 +506,16 @@ mod tests {
 
         assert_eq!(metric.name(), "LLCLookupMRead");
     }
+
+    #[test]
+    fn test_every_metric_has_its_own_source() {
+        let all_metrics = ChaMetric::all();
+        let sources: std::collections::HashSet<_> =
+            all_metrics.iter().map(ChaMetric::source).collect();
+        assert_eq!(sources.len(), all_metrics.len());
+        assert_eq!(
+            ChaMetric::ReadNoCredit.source(),
+            ChaMetricSource::Credit(CreditType::Read)
+        );
+    }
 }
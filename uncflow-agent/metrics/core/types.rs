@@ -1,5 +1,6 @@
 // Core PMU metrics for Skylake architecture
-use crate::metric_enum;
+
+use crate::metrics::unit::MetricUnit;
 
 metric_enum! {
     pub enum CoreMetric {
@@ -21,5 +22,22 @@ metric_enum! {
         L3MPI => "L3MPI",
         L2MPI => "L2MPI",
         ElapsedTime => "elapsedTime",
+        AvxLicenseCyclesRatio => "core_avx_license_cycles_ratio",
+        EffectiveFrequency => "core_effective_frequency_hz",
+        OffcoreLocalDram => "core_offcore_local_dram",
+        OffcoreRemoteDram => "core_offcore_remote_dram",
+    }
+}
+
+impl CoreMetric {
+    /// Unit of the metric's values
+    pub fn unit(&self) -> Option<MetricUnit> {
+        match self {
+            CoreMetric::L3CacheHitRatio
+            | CoreMetric::L2CacheHitRatio
+            | CoreMetric::AvxLicenseCyclesRatio => Some(MetricUnit::Ratio),
+            CoreMetric::EffectiveFrequency => Some(MetricUnit::Hertz),
+            _ => None,
+        }
     }
 }
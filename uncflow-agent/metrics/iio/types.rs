@@ -1,5 +1,11 @@
 // IIO (Integrated IO) metrics
 
+use std::str::FromStr;
+
+use crate::error::{Result, UncflowError};
+use crate::metrics::kind::MetricKind;
+use crate::metrics::unit::MetricUnit;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum IioMetric {
     IIOTLBMiss,
@@ -12,9 +18,14 @@ pub enum IioMetric {
     IIOTLB1Miss,
     IIOOccupancy,
     IIOFrequency,
+    // IOMMU (VT-d) translations, with --iio-iommu
+    IOMMUCacheHitRatio,
+    IOMMUPageWalkLatency,
     // PCIe bandwidth metrics (per channel and port)
     PCIeInBandwidth(usize, usize),  // (channel, port)
     PCIeOutBandwidth(usize, usize), // (channel, port)
+    // Fraction of IO clock cycles the port was active (per channel and port)
+    PCIeUtilization(usize, usize), // (channel, port)
 }
 
 impl IioMetric {
@@ -30,12 +41,61 @@ impl IioMetric {
             IioMetric::IIOTLB1Miss => "IIOTLB1Miss".to_string(),
             IioMetric::IIOOccupancy => "IIOOccupancy".to_string(),
             IioMetric::IIOFrequency => "IIOFrequency".to_string(),
+            IioMetric::IOMMUCacheHitRatio => "IOMMUCacheHitRatio".to_string(),
+            IioMetric::IOMMUPageWalkLatency => "IOMMUPageWalkLatency".to_string(),
             IioMetric::PCIeInBandwidth(ch, port) => {
                 format!("PCIe{ch}{port}InBandwidth")
             }
             IioMetric::PCIeOutBandwidth(ch, port) => {
                 format!("PCIe{ch}{port}OutBandwidth")
             }
+            IioMetric::PCIeUtilization(ch, port) => {
+                format!("PCIe{ch}{port}Utilization")
+            }
+        }
+    }
+
+    /// How the metric's value relates to time, see [`MetricKind`]
+    pub fn kind(&self) -> MetricKind {
+        match self {
+            IioMetric::IIOTLBMiss
+            | IioMetric::IIOTLBFull
+            | IioMetric::IIOL1Miss
+            | IioMetric::IIOL2Miss
+            | IioMetric::IIOL3Miss
+            | IioMetric::IIOContextMiss
+            | IioMetric::IIOTLBHit
+            | IioMetric::IIOTLB1Miss => MetricKind::Count,
+            IioMetric::IIOFrequency
+            | IioMetric::PCIeInBandwidth(..)
+            | IioMetric::PCIeOutBandwidth(..) => MetricKind::Rate,
+            IioMetric::IIOOccupancy
+            | IioMetric::IOMMUCacheHitRatio
+            | IioMetric::IOMMUPageWalkLatency
+            | IioMetric::PCIeUtilization(..) => MetricKind::Instantaneous,
+        }
+    }
+
+    /// Unit of the metric's values
+    pub fn unit(&self) -> Option<MetricUnit> {
+        match self {
+            IioMetric::PCIeInBandwidth(..) | IioMetric::PCIeOutBandwidth(..) => {
+                Some(MetricUnit::GigabytesPerSecond)
+            }
+            IioMetric::IIOFrequency => Some(MetricUnit::Gigahertz),
+            IioMetric::IOMMUPageWalkLatency => Some(MetricUnit::Nanoseconds),
+            IioMetric::IOMMUCacheHitRatio | IioMetric::PCIeUtilization(..) => {
+                Some(MetricUnit::Ratio)
+            }
+            IioMetric::IIOTLBMiss
+            | IioMetric::IIOTLBFull
+            | IioMetric::IIOL1Miss
+            | IioMetric::IIOL2Miss
+            | IioMetric::IIOL3Miss
+            | IioMetric::IIOContextMiss
+            | IioMetric::IIOTLBHit
+            | IioMetric::IIOTLB1Miss
+            | IioMetric::IIOOccupancy => None,
         }
     }
 
@@ -51,16 +111,39 @@ impl IioMetric {
             IioMetric::IIOTLB1Miss,
             IioMetric::IIOOccupancy,
             IioMetric::IIOFrequency,
+            IioMetric::IOMMUCacheHitRatio,
+            IioMetric::IOMMUPageWalkLatency,
         ];
 
-        // Add PCIe bandwidth metrics for 3 channels and 4 ports each
+        // Add PCIe bandwidth and utilization metrics for 3 channels and 4 ports each
         for ch in 0..3 {
             for port in 0..4 {
                 metrics.push(IioMetric::PCIeInBandwidth(ch, port));
                 metrics.push(IioMetric::PCIeOutBandwidth(ch, port));
+                metrics.push(IioMetric::PCIeUtilization(ch, port));
             }
         }
 
         metrics
     }
 }
+
+impl FromStr for IioMetric {
+    type Err = UncflowError;
+
+    /// Inverse of `name()`
+    fn from_str(s: &str) -> Result<Self> {
+        Self::all()
+            .into_iter()
+            .find(|metric| metric.name() == s)
+            .ok_or_else(|| UncflowError::ParseError(format!("Unknown IioMetric '{s}'")))
+    }
+}
+
+impl TryFrom<&str> for IioMetric {
+    type Error = UncflowError;
+
+    fn try_from(s: &str) -> Result<Self> {
+        s.parse()
+    }
+}
@@ -1,5 +1,10 @@
 // IMC (Integrated Memory Controller) metrics
 
+use std::str::FromStr;
+
+use crate::error::{Result, UncflowError};
+use crate::metrics::unit::MetricUnit;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ImcMetric {
     // Bandwidth metrics
@@ -10,6 +15,12 @@ pub enum ImcMetric {
     MemoryRemoteReadBandwidth,
     MemoryRemoteWriteBandwidth,
 
+    // Bandwidth extremes across sub-samples (see --sample-count)
+    MemoryReadBandwidthMin,
+    MemoryReadBandwidthMax,
+    MemoryWriteBandwidthMin,
+    MemoryWriteBandwidthMax,
+
     // Latency metrics
     MemoryReadLatency,
     MemoryWriteLatency,
@@ -26,10 +37,30 @@ pub enum ImcMetric {
 
     // Frequency metric
     IMCFrequency,
+    // DDR transfers per second derived from the DCLK frequency
+    DataRate,
 
     // NUMA locality ratios (new)
     MemoryLocalReadRatio,
     MemoryLocalWriteRatio,
+
+    // Total bandwidth over the socket's peak
+    BandwidthSaturationRatio,
+
+    // Optane persistent memory media traffic (PMEM systems only)
+    PmemReadBandwidth,
+    PmemWriteBandwidth,
+
+    // Write CAS over read CAS
+    WriteReadRatio,
+
+    // DRAM page locality (--imc-page-events only)
+    PageHitRatio,
+    PageMissRatio,
+    PageConflictRatio,
+
+    // Thermally throttled cycles (--imc-thermal only)
+    ThermalThrottleCycles,
 }
 
 impl ImcMetric {
@@ -41,6 +72,10 @@ impl ImcMetric {
             ImcMetric::MemoryLocalWriteBandwidth => "MemoryLocalWriteBandwidth",
             ImcMetric::MemoryRemoteReadBandwidth => "MemoryRemoteReadBandwidth",
             ImcMetric::MemoryRemoteWriteBandwidth => "MemoryRemoteWriteBandwidth",
+            ImcMetric::MemoryReadBandwidthMin => "MemoryReadBandwidthMin",
+            ImcMetric::MemoryReadBandwidthMax => "MemoryReadBandwidthMax",
+            ImcMetric::MemoryWriteBandwidthMin => "MemoryWriteBandwidthMin",
+            ImcMetric::MemoryWriteBandwidthMax => "MemoryWriteBandwidthMax",
             ImcMetric::MemoryReadLatency => "IMCReadLatency",
             ImcMetric::MemoryWriteLatency => "IMCWriteLatency",
             ImcMetric::MemoryRPQOccupancy => "MemoryRPQOccupancy",
@@ -50,8 +85,54 @@ impl ImcMetric {
             ImcMetric::IMCWPQNonEmpty => "IMCWPQNonEmpty",
             ImcMetric::IMCWPQFull => "IMCWPQFull",
             ImcMetric::IMCFrequency => "IMCFrequency",
+            ImcMetric::DataRate => "imc_data_rate_mts",
             ImcMetric::MemoryLocalReadRatio => "MemoryLocalReadRatio",
             ImcMetric::MemoryLocalWriteRatio => "MemoryLocalWriteRatio",
+            ImcMetric::BandwidthSaturationRatio => "imc_bandwidth_saturation_ratio",
+            ImcMetric::PmemReadBandwidth => "imc_pmem_read_bandwidth",
+            ImcMetric::PmemWriteBandwidth => "imc_pmem_write_bandwidth",
+            ImcMetric::WriteReadRatio => "imc_write_read_ratio",
+            ImcMetric::PageHitRatio => "imc_page_hit_ratio",
+            ImcMetric::PageMissRatio => "imc_page_miss_ratio",
+            ImcMetric::PageConflictRatio => "imc_page_conflict_ratio",
+            ImcMetric::ThermalThrottleCycles => "imc_thermal_throttle_cycles",
+        }
+    }
+
+    /// Unit of the metric's values
+    pub fn unit(&self) -> Option<MetricUnit> {
+        match self {
+            ImcMetric::MemoryReadBandwidth
+            | ImcMetric::MemoryWriteBandwidth
+            | ImcMetric::MemoryLocalReadBandwidth
+            | ImcMetric::MemoryLocalWriteBandwidth
+            | ImcMetric::MemoryRemoteReadBandwidth
+            | ImcMetric::MemoryRemoteWriteBandwidth
+            | ImcMetric::MemoryReadBandwidthMin
+            | ImcMetric::MemoryReadBandwidthMax
+            | ImcMetric::MemoryWriteBandwidthMin
+            | ImcMetric::MemoryWriteBandwidthMax
+            | ImcMetric::PmemReadBandwidth
+            | ImcMetric::PmemWriteBandwidth => Some(MetricUnit::BytesPerSecond),
+            ImcMetric::MemoryReadLatency | ImcMetric::MemoryWriteLatency => {
+                Some(MetricUnit::Nanoseconds)
+            }
+            ImcMetric::IMCFrequency => Some(MetricUnit::Gigahertz),
+            ImcMetric::IMCRPQNonEmpty
+            | ImcMetric::IMCRPQFull
+            | ImcMetric::IMCWPQNonEmpty
+            | ImcMetric::IMCWPQFull
+            | ImcMetric::MemoryLocalReadRatio
+            | ImcMetric::MemoryLocalWriteRatio
+            | ImcMetric::BandwidthSaturationRatio
+            | ImcMetric::WriteReadRatio
+            | ImcMetric::PageHitRatio
+            | ImcMetric::PageMissRatio
+            | ImcMetric::PageConflictRatio => Some(MetricUnit::Ratio),
+            ImcMetric::MemoryRPQOccupancy
+            | ImcMetric::MemoryWPQOccupancy
+            | ImcMetric::DataRate
+            | ImcMetric::ThermalThrottleCycles => None,
         }
     }
 
@@ -64,6 +145,11 @@ impl ImcMetric {
             ImcMetric::MemoryLocalWriteBandwidth,
             ImcMetric::MemoryRemoteReadBandwidth,
             ImcMetric::MemoryRemoteWriteBandwidth,
+            // Bandwidth extremes
+            ImcMetric::MemoryReadBandwidthMin,
+            ImcMetric::MemoryReadBandwidthMax,
+            ImcMetric::MemoryWriteBandwidthMin,
+            ImcMetric::MemoryWriteBandwidthMax,
             // Latency
             ImcMetric::MemoryReadLatency,
             ImcMetric::MemoryWriteLatency,
@@ -77,9 +163,43 @@ impl ImcMetric {
             ImcMetric::IMCWPQFull,
             // Frequency
             ImcMetric::IMCFrequency,
+            ImcMetric::DataRate,
             // NUMA ratios
             ImcMetric::MemoryLocalReadRatio,
             ImcMetric::MemoryLocalWriteRatio,
+            // Saturation
+            ImcMetric::BandwidthSaturationRatio,
+            // PMEM
+            ImcMetric::PmemReadBandwidth,
+            ImcMetric::PmemWriteBandwidth,
+            // Write/read mix
+            ImcMetric::WriteReadRatio,
+            // Page locality
+            ImcMetric::PageHitRatio,
+            ImcMetric::PageMissRatio,
+            ImcMetric::PageConflictRatio,
+            // Thermal throttling
+            ImcMetric::ThermalThrottleCycles,
         ]
     }
 }
+
+impl FromStr for ImcMetric {
+    type Err = UncflowError;
+
+    /// Inverse of `name()`
+    fn from_str(s: &str) -> Result<Self> {
+        Self::all()
+            .into_iter()
+            .find(|metric| metric.name() == s)
+            .ok_or_else(|| UncflowError::ParseError(format!("Unknown ImcMetric '{s}'")))
+    }
+}
+
+impl TryFrom<&str> for ImcMetric {
+    type Error = UncflowError;
+
+    fn try_from(s: &str) -> Result<Self> {
+        s.parse()
+    }
+}
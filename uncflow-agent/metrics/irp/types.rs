@@ -1,5 +1,6 @@
 // IRP (IO Request Processing) metrics
-use crate::metric_enum;
+
+use crate::metrics::unit::MetricUnit;
 
 metric_enum! {
     pub enum IrpMetric {
@@ -14,3 +15,19 @@ metric_enum! {
         IRPFrequency => "IRPFrequency",
     }
 }
+
+impl IrpMetric {
+    /// Unit of the metric's values
+    pub fn unit(&self) -> Option<MetricUnit> {
+        match self {
+            IrpMetric::IRPPCIeReadBandwidth
+            | IrpMetric::IRPRFOBandwidth
+            | IrpMetric::IRPAllBandwidth
+            | IrpMetric::IRPPCIItoMBandwidth
+            | IrpMetric::IRPWbMtoIBandwidth
+            | IrpMetric::IRPCLFlushBandwidth => Some(MetricUnit::GigabytesPerSecond),
+            IrpMetric::IRPFrequency => Some(MetricUnit::Gigahertz),
+            IrpMetric::IRPLatency | IrpMetric::IRPAnyOccupancy => None,
+        }
+    }
+}
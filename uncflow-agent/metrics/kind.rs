@@ -0,0 +1,52 @@
+// How exported values relate to time
+//
+// Rates (per second): memory, PCIe, IRP, UPI, RDT and CHA bandwidths, M2M directory
+// hits/misses and tag hits, frequencies, RAPL power.
+// Counts (events in the last measurement window): CHA LLC lookups, LLC victims, SF
+// evictions and back-invalidations and no-credit stalls, IIO TLB and cache hits/misses, RAPL energy.
+// Cumulative (totals since start or the last /reset): core instructions, cycles and
+// cache events, --core-event counts.
+// Instantaneous: ratios, latencies, occupancies, temperatures, LLC occupancy.
+//
+// A count depends on how long its window was, and CHA groups take turns on the
+// counters so their windows vary; rate() over such a gauge divides by time a second
+// time. With --count-rates counts are divided by their window where they are
+// exported and published per second under the same names. RAPL energy stays a
+// count, its rate is exported as *Power.
+
+use std::time::Duration;
+
+use crate::common::counter::rate_window_secs;
+
+/// How an exported metric's value relates to time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricKind {
+    /// Per-second rate
+    Rate,
+    /// Events in the last measurement window, a rate with --count-rates
+    Count,
+    /// Total since start or the last reset
+    Cumulative,
+    /// Value at (or averaged over) the last measurement
+    Instantaneous,
+}
+
+/// `count` events over `window` as a per-second rate, None if the window is too short
+pub fn per_second(count: f64, window: Duration) -> Option<f64> {
+    rate_window_secs(window).map(|seconds| count / seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_per_second() {
+        assert_eq!(
+            per_second(5_000.0, Duration::from_millis(250)),
+            Some(20_000.0)
+        );
+        assert_eq!(per_second(0.0, Duration::from_secs(2)), Some(0.0));
+        assert_eq!(per_second(5_000.0, Duration::ZERO), None);
+    }
+}
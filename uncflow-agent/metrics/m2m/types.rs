@@ -0,0 +1,24 @@
+// M2M (Mesh-to-Memory) metrics
+
+use crate::metrics::unit::MetricUnit;
+
+metric_enum! {
+    pub enum M2mMetric {
+        M2MDirectoryHitRatio => "m2m_directory_hit_ratio",
+        M2MDirectoryHits => "m2m_directory_hits",
+        M2MDirectoryMisses => "m2m_directory_misses",
+        M2MTagHits => "m2m_tag_hits",
+    }
+}
+
+impl M2mMetric {
+    /// Unit of the metric's values
+    pub fn unit(&self) -> Option<MetricUnit> {
+        match self {
+            M2mMetric::M2MDirectoryHitRatio => Some(MetricUnit::Ratio),
+            M2mMetric::M2MDirectoryHits | M2mMetric::M2MDirectoryMisses | M2mMetric::M2MTagHits => {
+                None
+            }
+        }
+    }
+}
@@ -3,5 +3,55 @@ pub mod core;
 pub mod iio;
 pub mod imc;
 pub mod irp;
+pub mod kind;
+pub mod m2m;
+pub mod pcu;
 pub mod rapl;
 pub mod rdt;
+pub mod unit;
+pub mod upi;
+
+#[cfg(test)]
+mod tests {
+    use super::cha::ChaMetric;
+    use super::core::CoreMetric;
+    use super::iio::IioMetric;
+    use super::imc::ImcMetric;
+    use super::rapl::RaplMetric;
+    use super::rdt::RdtMetric;
+
+    #[test]
+    fn test_name_round_trip() {
+        for metric in RaplMetric::all() {
+            assert_eq!(metric.name().parse::<RaplMetric>().unwrap(), metric);
+        }
+        for metric in ImcMetric::all() {
+            assert_eq!(metric.name().parse::<ImcMetric>().unwrap(), metric);
+        }
+        for metric in RdtMetric::all() {
+            assert_eq!(metric.name().parse::<RdtMetric>().unwrap(), metric);
+        }
+        for metric in CoreMetric::all() {
+            assert_eq!(metric.name().parse::<CoreMetric>().unwrap(), metric);
+        }
+        for metric in IioMetric::all() {
+            assert_eq!(IioMetric::try_from(metric.name().as_str()).unwrap(), metric);
+        }
+        for metric in ChaMetric::all() {
+            assert_eq!(ChaMetric::try_from(metric.name().as_str()).unwrap(), metric);
+        }
+    }
+
+    #[test]
+    fn test_unknown_name() {
+        assert_eq!(
+            RaplMetric::try_from("PackageEnergy").unwrap(),
+            RaplMetric::PackageEnergy
+        );
+        // Names are case-sensitive, like the exported series
+        assert!("packageenergy".parse::<RaplMetric>().is_err());
+        assert!("IMCReadLatency".parse::<ImcMetric>().is_ok());
+        assert!("MemoryReadLatency".parse::<ImcMetric>().is_err());
+        assert!("PCIe99InBandwidth".parse::<IioMetric>().is_err());
+    }
+}
@@ -0,0 +1,31 @@
+// PCU (Power Control Unit) metrics
+//
+// The cycle metrics are shares of the PCU clock cycles of the window, so a
+// frequency limit that is active half of the time reads 0.5. The core counts are
+// the average number of cores in each C-state over the window.
+
+use crate::metrics::unit::MetricUnit;
+
+metric_enum! {
+    pub enum PcuMetric {
+        FreqTransitionRatio => "pcu_frequency_transition_ratio",
+        ThermalLimitRatio => "pcu_thermal_limit_ratio",
+        PowerLimitRatio => "pcu_power_limit_ratio",
+        ProchotInternalRatio => "pcu_prochot_internal_ratio",
+        ProchotExternalRatio => "pcu_prochot_external_ratio",
+        VrHotRatio => "pcu_vr_hot_ratio",
+        CoresC0 => "pcu_cores_c0",
+        CoresC3 => "pcu_cores_c3",
+        CoresC6 => "pcu_cores_c6",
+    }
+}
+
+impl PcuMetric {
+    /// Unit of the metric's values
+    pub fn unit(&self) -> Option<MetricUnit> {
+        match self {
+            PcuMetric::CoresC0 | PcuMetric::CoresC3 | PcuMetric::CoresC6 => None,
+            _ => Some(MetricUnit::Ratio),
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use crate::metric_enum;
+use crate::metrics::unit::MetricUnit;
 
 metric_enum! {
     pub enum RaplMetric {
@@ -10,3 +10,17 @@ metric_enum! {
         DramPower => "DRAMPower",
     }
 }
+
+impl RaplMetric {
+    /// Unit of the metric's values
+    pub fn unit(&self) -> Option<MetricUnit> {
+        match self {
+            RaplMetric::PackageEnergy | RaplMetric::CoreEnergy | RaplMetric::DramEnergy => {
+                Some(MetricUnit::Joules)
+            }
+            RaplMetric::PackagePower | RaplMetric::CorePower | RaplMetric::DramPower => {
+                Some(MetricUnit::Watts)
+            }
+        }
+    }
+}
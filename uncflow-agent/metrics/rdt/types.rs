@@ -1,4 +1,4 @@
-use crate::metric_enum;
+use crate::metrics::unit::MetricUnit;
 
 metric_enum! {
     pub enum RdtMetric {
@@ -8,3 +8,15 @@ metric_enum! {
         LlcOccupancy => "CMTLLCOccupancy",
     }
 }
+
+impl RdtMetric {
+    /// Unit of the metric's values
+    pub fn unit(&self) -> Option<MetricUnit> {
+        match self {
+            RdtMetric::LocalMemoryBandwidth
+            | RdtMetric::RemoteMemoryBandwidth
+            | RdtMetric::TotalMemoryBandwidth => Some(MetricUnit::BytesPerSecond),
+            RdtMetric::LlcOccupancy => Some(MetricUnit::Bytes),
+        }
+    }
+}
@@ -0,0 +1,159 @@
+// Units of exported metrics, published as OpenMetrics `# UNIT` metadata
+//
+// Each metric enum declares the unit of its values next to its name(); values are
+// exported as computed, so uncore bandwidths stay in GB/s and frequencies in GHz
+// and their units say so. Metrics without a clear physical unit (counts, queue
+// occupancies) have none. Families outside the metric enums (self metrics, rollups)
+// get the unit their name already ends in.
+
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+
+use crate::metrics::cha::ChaMetric;
+use crate::metrics::core::CoreMetric;
+use crate::metrics::iio::IioMetric;
+use crate::metrics::imc::ImcMetric;
+use crate::metrics::irp::IrpMetric;
+use crate::metrics::m2m::M2mMetric;
+use crate::metrics::pcu::PcuMetric;
+use crate::metrics::rapl::RaplMetric;
+use crate::metrics::rdt::RdtMetric;
+use crate::metrics::upi::UpiMetric;
+
+/// Unit of an exported metric's values
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MetricUnit {
+    Joules,
+    Watts,
+    Bytes,
+    BytesPerSecond,
+    GigabytesPerSecond,
+    Hertz,
+    Gigahertz,
+    Seconds,
+    Nanoseconds,
+    Celsius,
+    Ratio,
+}
+
+impl MetricUnit {
+    /// Unit as written in OpenMetrics metadata and metric name suffixes
+    pub fn name(&self) -> &'static str {
+        match self {
+            MetricUnit::Joules => "joules",
+            MetricUnit::Watts => "watts",
+            MetricUnit::Bytes => "bytes",
+            MetricUnit::BytesPerSecond => "bytes_per_second",
+            MetricUnit::GigabytesPerSecond => "gigabytes_per_second",
+            MetricUnit::Hertz => "hertz",
+            MetricUnit::Gigahertz => "gigahertz",
+            MetricUnit::Seconds => "seconds",
+            MetricUnit::Nanoseconds => "nanoseconds",
+            MetricUnit::Celsius => "celsius",
+            MetricUnit::Ratio => "ratio",
+        }
+    }
+
+    pub fn all() -> Vec<MetricUnit> {
+        vec![
+            MetricUnit::Joules,
+            MetricUnit::Watts,
+            MetricUnit::Bytes,
+            MetricUnit::BytesPerSecond,
+            MetricUnit::GigabytesPerSecond,
+            MetricUnit::Hertz,
+            MetricUnit::Gigahertz,
+            MetricUnit::Seconds,
+            MetricUnit::Nanoseconds,
+            MetricUnit::Celsius,
+            MetricUnit::Ratio,
+        ]
+    }
+}
+
+// Units of every metric enum's families, keyed by exported name
+static UNITS: Lazy<HashMap<String, MetricUnit>> = Lazy::new(|| {
+    let rapl = RaplMetric::all()
+        .into_iter()
+        .map(|m| (m.name().to_string(), m.unit()));
+    let rdt = RdtMetric::all()
+        .into_iter()
+        .map(|m| (m.name().to_string(), m.unit()));
+    let core = CoreMetric::all()
+        .into_iter()
+        .map(|m| (m.name().to_string(), m.unit()));
+    let imc = ImcMetric::all()
+        .into_iter()
+        .map(|m| (m.name().to_string(), m.unit()));
+    let cha = ChaMetric::all().into_iter().map(|m| (m.name(), m.unit()));
+    let irp = IrpMetric::all()
+        .into_iter()
+        .map(|m| (m.name().to_string(), m.unit()));
+    let iio = IioMetric::all().into_iter().map(|m| (m.name(), m.unit()));
+    let upi = UpiMetric::all()
+        .into_iter()
+        .map(|m| (m.name().to_string(), m.unit()));
+    let m2m = M2mMetric::all()
+        .into_iter()
+        .map(|m| (m.name().to_string(), m.unit()));
+    let pcu = PcuMetric::all()
+        .into_iter()
+        .map(|m| (m.name().to_string(), m.unit()));
+    rapl.chain(rdt)
+        .chain(core)
+        .chain(imc)
+        .chain(cha)
+        .chain(irp)
+        .chain(iio)
+        .chain(upi)
+        .chain(m2m)
+        .chain(pcu)
+        .filter_map(|(name, unit)| Some((name, unit?)))
+        .collect()
+});
+
+/// Unit of the metric family `name`, None if it has none or is unknown
+///
+/// A counter's `_total` suffix is ignored, so `rapl_package_energy_joules_total`
+/// is in joules.
+pub fn unit_of(name: &str) -> Option<MetricUnit> {
+    let base = name.strip_suffix("_total").unwrap_or(name);
+    UNITS.get(base).copied().or_else(|| {
+        MetricUnit::all()
+            .into_iter()
+            .find(|unit| base.ends_with(&format!("_{}", unit.name())))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unit_of() {
+        assert_eq!(unit_of("PackageEnergy"), Some(MetricUnit::Joules));
+        assert_eq!(unit_of("DRAMPower"), Some(MetricUnit::Watts));
+        assert_eq!(
+            unit_of("MemoryReadBandwidth"),
+            Some(MetricUnit::BytesPerSecond)
+        );
+        assert_eq!(
+            unit_of("PCIe01InBandwidth"),
+            Some(MetricUnit::GigabytesPerSecond)
+        );
+        assert_eq!(unit_of("PCIeReadHitLatency"), Some(MetricUnit::Nanoseconds));
+        assert_eq!(unit_of("UncoreFrequency"), Some(MetricUnit::Gigahertz));
+        // Inferred from the name
+        assert_eq!(
+            unit_of("rapl_package_energy_joules_total"),
+            Some(MetricUnit::Joules)
+        );
+        assert_eq!(
+            unit_of("uncflow_collection_duration_seconds"),
+            Some(MetricUnit::Seconds)
+        );
+        // Counts have no unit
+        assert_eq!(unit_of("LLCVictimM"), None);
+        assert_eq!(unit_of("uncflow_reads_total"), None);
+    }
+}
@@ -0,0 +1,23 @@
+// UPI (Ultra Path Interconnect) link metrics
+
+use crate::metrics::unit::MetricUnit;
+
+metric_enum! {
+    pub enum UpiMetric {
+        UPITxBandwidth => "UPITxBandwidth",
+        UPIRxBandwidth => "UPIRxBandwidth",
+        UPIFrequency => "UPIFrequency",
+    }
+}
+
+impl UpiMetric {
+    /// Unit of the metric's values
+    pub fn unit(&self) -> Option<MetricUnit> {
+        match self {
+            UpiMetric::UPITxBandwidth | UpiMetric::UPIRxBandwidth => {
+                Some(MetricUnit::GigabytesPerSecond)
+            }
+            UpiMetric::UPIFrequency => Some(MetricUnit::Gigahertz),
+        }
+    }
+}
@@ -0,0 +1,284 @@
+// Series budget checked before any exporter registers a gauge (--max-series)
+//
+// Per-box CHA, per-channel IMC, raw deltas and per-core Core/RDT series multiply
+// with the number of sockets and cores, so a wide --core range or every optional
+// export at once can register enough gauges to exhaust memory at startup. The
+// counts below mirror what each exporter registers, taking every unit the
+// architecture can have, so they are an upper bound.
+
+use uncflow_raw::current_arch::imc::{self, IMC_CHANNEL_COUNT};
+use uncflow_raw::current_arch::{cha::CHA_COUNT, irp::IRP_UNIT_COUNT, upi::UPI_LINK_COUNT};
+
+use crate::config::ExportConfig;
+use crate::counters::cha::TransactionType;
+use crate::counters::imc::monitor::ImcCounters;
+use crate::counters::m2m::monitor::M2mCounts;
+use crate::counters::upi::monitor::LINK_COUNTER_NAMES;
+use crate::error::{Result, UncflowError};
+use crate::metrics::cha::{ChaMetric, CreditType};
+use crate::metrics::core::CoreMetric;
+use crate::metrics::iio::IioMetric;
+use crate::metrics::imc::ImcMetric;
+use crate::metrics::irp::IrpMetric;
+use crate::metrics::m2m::M2mMetric;
+use crate::metrics::pcu::PcuMetric;
+use crate::metrics::rapl::RaplMetric;
+use crate::metrics::rdt::RdtMetric;
+use crate::metrics::upi::UpiMetric;
+
+use super::CollectorConfig;
+
+/// Series limit used when --max-series is not given
+pub const DEFAULT_MAX_SERIES: usize = 50_000;
+
+// Offenders named in the error
+const WORST_OFFENDERS: usize = 3;
+
+// Per-box CHA gauge families (occupancy, inserts, clockticks)
+const CHA_BOX_COUNTERS: usize = 3;
+
+// Per-channel IMC gauge families (read/write bandwidth, RPQ/WPQ occupancy)
+const IMC_CHANNEL_SERIES: usize = 4;
+
+/// Series one group of gauges would register
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SeriesCount {
+    pub source: &'static str,
+    pub series: usize,
+}
+
+/// Series each enabled exporter would register, one entry per gauge group
+pub fn estimate_series(config: &ExportConfig, collector: &CollectorConfig) -> Vec<SeriesCount> {
+    let sockets = config.sockets.len();
+    let cores = config.cores.len();
+    let sockets_of = |selected: &[i32]| config.restricted_to(selected).sockets.len();
+    // CHA transactions are measured as a hit and a miss group each, plus the
+    // snoop filter group and the no-credit stall groups
+    let cha_groups = TransactionType::all().len() * 2 + 1 + CreditType::all().len();
+
+    let mut counts = Vec::new();
+    let mut add = |enabled: bool, source: &'static str, series: usize| {
+        if enabled {
+            counts.push(SeriesCount { source, series });
+        }
+    };
+
+    add(
+        collector.rapl,
+        "RAPL",
+        (RaplMetric::all().len() + 1) * sockets,
+    );
+
+    let resctrl = !config.resctrl_groups.is_empty();
+    add(
+        collector.rdt && !resctrl,
+        "RDT",
+        // Plus rdt_memory_bytes_total per socket
+        RdtMetric::all().len() * (sockets + cores) + sockets,
+    );
+    add(
+        collector.rdt && resctrl,
+        "RDT resctrl groups",
+        2 * config.resctrl_groups.len(),
+    );
+    // Level per socket, priority per core
+    add(collector.sst, "SST", sockets + cores);
+    // Three package states per socket, three core states per core
+    add(collector.cstate, "C-state", 3 * sockets + 3 * cores);
+    // Temperature and throttle per core and per socket
+    add(collector.thermal, "Thermal", 2 * sockets + 2 * cores);
+    add(collector.pcu, "PCU", PcuMetric::all().len() * sockets);
+
+    let core_metrics = CoreMetric::all()
+        .into_iter()
+        .filter(|metric| match metric {
+            CoreMetric::AvxLicenseCyclesRatio => config.core_avx_license,
+            CoreMetric::OffcoreLocalDram | CoreMetric::OffcoreRemoteDram => config.core_offcore,
+            _ => true,
+        })
+        .count();
+    add(collector.core_metrics, "Core", core_metrics * cores);
+    add(
+        collector.core_metrics && config.core_socket_rollup,
+        "Core socket rollup",
+        core_metrics * sockets,
+    );
+    add(
+        collector.core_metrics && !config.core_events.is_empty(),
+        "Core events",
+        config.core_events.len() * cores,
+    );
+
+    let imc_sockets = sockets_of(&collector.imc_sockets);
+    let imc_channels = IMC_CHANNEL_COUNT * imc_sockets;
+    add(collector.imc, "IMC", ImcMetric::all().len() * imc_sockets);
+    add(
+        collector.imc && config.imc_per_channel,
+        "IMC per-channel",
+        IMC_CHANNEL_SERIES * imc_channels,
+    );
+    add(
+        collector.imc && config.export_raw,
+        "IMC raw",
+        ImcCounters::default().named().len() * imc_channels,
+    );
+    add(
+        collector.imc && config.imc_thermal,
+        "IMC DIMM temperature",
+        imc::pci::DIMMTEMPSTAT.len() * imc_channels,
+    );
+    add(collector.imc && config.rollups, "IMC rollup", imc_sockets);
+    // Per-node families sum the per-channel and per-box ones
+    let numa_nodes = config.numa_nodes.len();
+    add(
+        collector.imc && numa_nodes > 0,
+        "IMC per-node",
+        IMC_CHANNEL_SERIES * numa_nodes,
+    );
+
+    let cha_sockets = sockets_of(&collector.cha_sockets);
+    let cha_boxes = CHA_COUNT * cha_sockets;
+    // Plus CHAUp
+    add(
+        collector.cha,
+        "CHA",
+        (ChaMetric::all().len() + 1) * cha_sockets,
+    );
+    add(
+        collector.cha && config.cha_per_box,
+        "CHA per-box",
+        CHA_BOX_COUNTERS * cha_groups * cha_boxes,
+    );
+    add(
+        collector.cha && config.export_raw,
+        "CHA raw",
+        CHA_BOX_COUNTERS * cha_groups * cha_boxes,
+    );
+    add(
+        collector.cha && numa_nodes > 0,
+        "CHA per-node",
+        CHA_BOX_COUNTERS * cha_groups * numa_nodes,
+    );
+    add(
+        collector.cha && config.cha_metric_age,
+        "CHA metric age",
+        ChaMetric::all().len() * cha_sockets,
+    );
+    // One gauge per user event and metric of --events-from-json
+    add(
+        collector.cha_custom,
+        "Custom CHA",
+        config
+            .cha_custom_events
+            .as_ref()
+            .map_or(0, |events| events.names().count())
+            * cha_sockets,
+    );
+
+    let irp_sockets = sockets_of(&collector.irp_sockets);
+    add(collector.irp, "IRP", IrpMetric::all().len() * irp_sockets);
+    add(
+        collector.irp && config.irp_per_unit,
+        "IRP per-unit",
+        IrpMetric::all().len() * IRP_UNIT_COUNT * irp_sockets,
+    );
+
+    let iio_sockets = sockets_of(&collector.iio_sockets);
+    let iio_metrics = IioMetric::all()
+        .into_iter()
+        .filter(|metric| match metric {
+            IioMetric::IOMMUCacheHitRatio | IioMetric::IOMMUPageWalkLatency => config.iio_iommu,
+            _ => true,
+        })
+        .count();
+    add(collector.iio, "IIO", iio_metrics * iio_sockets);
+    add(collector.iio && config.rollups, "IIO rollup", iio_sockets);
+
+    let upi_links = UPI_LINK_COUNT * sockets_of(&collector.upi_sockets);
+    add(collector.upi, "UPI", UpiMetric::all().len() * upi_links);
+    add(
+        collector.upi && config.export_raw,
+        "UPI raw",
+        LINK_COUNTER_NAMES.len() * upi_links,
+    );
+
+    let m2m_sockets = sockets_of(&collector.m2m_sockets);
+    add(collector.m2m, "M2M", M2mMetric::all().len() * m2m_sockets);
+    add(
+        collector.m2m && config.export_raw,
+        "M2M raw",
+        M2mCounts::default().named().len() * m2m_sockets,
+    );
+
+    counts
+}
+
+/// Total of `counts`, or an error naming the largest groups when it exceeds `max_series`
+pub fn check_series_budget(counts: &[SeriesCount], max_series: usize) -> Result<usize> {
+    let total: usize = counts.iter().map(|c| c.series).sum();
+    if total <= max_series {
+        return Ok(total);
+    }
+
+    let mut worst = counts.to_vec();
+    worst.sort_by_key(|c| std::cmp::Reverse(c.series));
+    let offenders = worst
+        .iter()
+        .take(WORST_OFFENDERS)
+        .map(|c| format!("{} ({})", c.source, c.series))
+        .collect::<Vec<_>>()
+        .join(", ");
+    Err(UncflowError::InvalidConfiguration(format!(
+        "Exporting {total} series exceeds --max-series {max_series}; largest: {offenders}. \
+         Narrow --core/--socket, drop per-box/per-channel/raw exports or raise --max-series"
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collector() -> CollectorConfig {
+        CollectorConfig {
+            core_metrics: true,
+            cha: true,
+            imc: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_exceeding_budget_names_worst_offenders() {
+        let config = ExportConfig::new(vec![0, 1], (0..224).collect())
+            .with_cha_per_box(true)
+            .with_export_raw(true);
+        let counts = estimate_series(&config, &collector());
+        let total: usize = counts.iter().map(|c| c.series).sum();
+
+        let err = check_series_budget(&counts, total - 1)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains(&format!("Exporting {total} series")), "{err}");
+        assert!(err.contains("--max-series"), "{err}");
+
+        // Per-box CHA dominates: 3 counters x 25 groups x 28 boxes x 2 sockets
+        let per_box = 3 * 25 * 28 * 2;
+        assert!(err.contains(&format!("CHA per-box ({per_box})")), "{err}");
+        assert!(err.contains(&format!("CHA raw ({per_box})")), "{err}");
+        // Only the worst offenders are listed
+        assert!(!err.contains("IMC raw"), "{err}");
+    }
+
+    #[test]
+    fn test_within_budget() {
+        let config = ExportConfig::new(vec![0], vec![0, 1, 2, 3]);
+        let counts = estimate_series(&config, &collector());
+        let total = check_series_budget(&counts, DEFAULT_MAX_SERIES).unwrap();
+        assert_eq!(total, counts.iter().map(|c| c.series).sum::<usize>());
+        assert_eq!(check_series_budget(&counts, total).unwrap(), total);
+
+        // Disabled subsystems and optional exports count nothing
+        assert!(!counts.iter().any(|c| c.source.starts_with("RDT")));
+        assert!(!counts.iter().any(|c| c.source == "CHA per-box"));
+    }
+}
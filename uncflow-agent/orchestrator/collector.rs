@@ -1,17 +1,71 @@
 // Centralized metric collection orchestrator
 // Manages all counter collection loops in a single unified async loop
 
+use prometheus::proto::MetricFamily;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::task::JoinHandle;
 use tokio_util::sync::CancellationToken;
 
 use crate::config::ExportConfig;
+use crate::output::statsd::StatsdSink;
 use crate::prom::{
-    ChaMetricExporter, CoreMetricExporter, IioMetricExporter, ImcMetricExporter, IrpMetricExporter,
-    RaplMetricExporter, RdtMetricExporter,
+    ChaCustomMetricExporter, ChaMetricExporter, CoreMetricExporter, CstateMetricExporter,
+    IioMetricExporter, ImcMetricExporter, IrpMetricExporter, M2mMetricExporter, PcuMetricExporter,
+    RaplMetricExporter, RdtMetricExporter, SstMetricExporter, ThermalMetricExporter,
+    UpiMetricExporter,
 };
 
+use super::cardinality::{check_series_budget, estimate_series};
+use super::subsystem::{spawn_collect, Subsystem};
+use super::{
+    CorrelationSource, MetricFilter, MetricSnapshot, MetricStream, ReadTimeout, Readiness,
+    SelfMetrics, SpikeDetector, SpikeRule, DEFAULT_SPIKE_CAPACITY,
+};
+
+/// Interval between collection ticks (and thus between exported samples)
+pub const COLLECTION_PERIOD: Duration = Duration::from_secs(1);
+
+/// Start times of successive collection ticks
+///
+/// `tokio::time::interval` fires late under load and catches up with bursts of
+/// short ticks afterwards, so the real sampling period can differ from
+/// `COLLECTION_PERIOD`; the interval between tick starts shows by how much.
+#[derive(Debug, Default)]
+struct TickClock {
+    last: Option<Instant>,
+}
+
+impl TickClock {
+    /// Record a tick starting at `now`, returning the time since the previous one
+    fn tick(&mut self, now: Instant) -> Option<Duration> {
+        let interval = self.last.map(|last| now.saturating_duration_since(last));
+        self.last = Some(now);
+        interval
+    }
+}
+
+/// Build `S` if `enabled`, logging instead of failing when it cannot be initialized
+fn init_subsystem<S: Subsystem + 'static>(
+    enabled: bool,
+    label: &str,
+    config: ExportConfig,
+) -> Option<Arc<dyn Subsystem>> {
+    if !enabled {
+        return None;
+    }
+    match S::init(config) {
+        Ok(subsystem) => {
+            tracing::info!("{} exporter initialized", label);
+            Some(Arc::new(subsystem))
+        }
+        Err(e) => {
+            tracing::error!("Failed to initialize {} exporter: {}", label, e);
+            None
+        }
+    }
+}
+
 /// Configuration for which metrics to collect
 #[derive(Debug, Clone, Default)]
 pub struct CollectorConfig {
@@ -20,25 +74,63 @@ pub struct CollectorConfig {
     pub core_metrics: bool,
     pub imc: bool,
     pub cha: bool,
+    /// User-defined CHA events from --events-from-json, instead of `cha`
+    pub cha_custom: bool,
     pub irp: bool,
     pub iio: bool,
+    pub upi: bool,
+    pub m2m: bool,
+    /// Active SST-PP level and per-core SST-CP priority
+    pub sst: bool,
+    /// Package and per-core C-state residency ratios
+    pub cstate: bool,
+    /// Core and package temperature and thermal throttling
+    pub thermal: bool,
+    /// PCU frequency-limit, throttling and C-state occupancy counters
+    pub pcu: bool,
+    /// Sockets each uncore unit is limited to; all configured sockets when empty
+    pub imc_sockets: Vec<i32>,
+    pub cha_sockets: Vec<i32>,
+    pub irp_sockets: Vec<i32>,
+    pub iio_sockets: Vec<i32>,
+    pub upi_sockets: Vec<i32>,
+    pub m2m_sockets: Vec<i32>,
+    /// Gauges to watch for threshold crossings; spike annotation is off when empty
+    pub spike_rules: Vec<SpikeRule>,
+    /// Ring buffer size for recorded spikes (0 uses the default)
+    pub spike_capacity: usize,
+    /// statsd server (host:port) every collection is pushed to
+    pub statsd: Option<String>,
+    /// Families exported per subsystem, e.g. narrowed by --profile
+    pub metric_filter: MetricFilter,
 }
 
 /// Centralized collector that orchestrates all metric collection
 pub struct MetricCollector {
     #[allow(dead_code)]
     config: ExportConfig,
-    #[allow(dead_code)]
     collector_config: CollectorConfig,
 
-    // Exporters (without their own loops)
-    rapl_exporter: Option<Arc<RaplMetricExporter>>,
-    rdt_exporter: Option<Arc<RdtMetricExporter>>,
-    core_exporter: Option<Arc<CoreMetricExporter>>,
-    imc_exporter: Option<Arc<ImcMetricExporter>>,
-    cha_exporter: Option<Arc<ChaMetricExporter>>,
-    irp_exporter: Option<Arc<IrpMetricExporter>>,
-    iio_exporter: Option<Arc<IioMetricExporter>>,
+    // Enabled subsystems, in initialization order
+    subsystems: Vec<Arc<dyn Subsystem>>,
+
+    // Agent self-monitoring (collection latency, interval overruns)
+    self_metrics: Arc<SelfMetrics>,
+
+    // Optional threshold-crossing annotation served at /spikes
+    spike_detector: Option<Arc<SpikeDetector>>,
+
+    // Set after the first successful collection, served at /ready
+    readiness: Arc<Readiness>,
+
+    // Per-collection snapshots pushed to /stream clients
+    metric_stream: Arc<MetricStream>,
+
+    // Optional push of every collection to statsd
+    statsd: Option<StatsdSink>,
+
+    // Deadline on each subsystem's collect, so a stuck read can't freeze the loop
+    read_timeout: Arc<ReadTimeout>,
 }
 
 impl MetricCollector {
@@ -46,82 +138,134 @@ impl MetricCollector {
         config: ExportConfig,
         collector_config: CollectorConfig,
     ) -> crate::error::Result<Self> {
+        // Checked before any monitor is programmed or gauge allocated
+        let series = check_series_budget(
+            &estimate_series(&config, &collector_config),
+            config.max_series,
+        )?;
+        tracing::info!(
+            "Exporting up to {} series (limit {})",
+            series,
+            config.max_series
+        );
+
         let mut collector = Self {
             config: config.clone(),
             collector_config: collector_config.clone(),
-            rapl_exporter: None,
-            rdt_exporter: None,
-            core_exporter: None,
-            imc_exporter: None,
-            cha_exporter: None,
-            irp_exporter: None,
-            iio_exporter: None,
+            subsystems: Vec::new(),
+            self_metrics: Arc::new(SelfMetrics::new()?),
+            spike_detector: None,
+            readiness: Arc::new(Readiness::new()),
+            metric_stream: Arc::new(MetricStream::default()),
+            statsd: None,
+            read_timeout: Arc::new(ReadTimeout::new(config.read_timeout)),
         };
 
-        // Initialize exporters based on config using macro
-        crate::init_exporter!(
-            collector,
-            collector_config,
-            config,
-            rapl_exporter,
-            rapl,
-            RaplMetricExporter,
-            "RAPL"
-        );
-        crate::init_exporter!(
-            collector,
-            collector_config,
-            config,
-            rdt_exporter,
-            rdt,
-            RdtMetricExporter,
-            "RDT"
-        );
-        crate::init_exporter!(
-            collector,
-            collector_config,
-            config,
-            core_exporter,
-            core_metrics,
-            CoreMetricExporter,
-            "Core PMU"
-        );
-        crate::init_exporter!(
-            collector,
-            collector_config,
-            config,
-            imc_exporter,
-            imc,
-            ImcMetricExporter,
-            "IMC"
-        );
-        crate::init_exporter!(
-            collector,
-            collector_config,
-            config,
-            cha_exporter,
-            cha,
-            ChaMetricExporter,
-            "CHA"
-        );
-        crate::init_exporter!(
-            collector,
-            collector_config,
-            config,
-            irp_exporter,
-            irp,
-            IrpMetricExporter,
-            "IRP"
-        );
-        crate::init_exporter!(
-            collector,
-            collector_config,
-            config,
-            iio_exporter,
-            iio,
-            IioMetricExporter,
-            "IIO"
-        );
+        if !collector_config.spike_rules.is_empty() {
+            let capacity = match collector_config.spike_capacity {
+                0 => DEFAULT_SPIKE_CAPACITY,
+                n => n,
+            };
+            tracing::info!(
+                "Spike annotation enabled for {} metric(s), keeping {} events",
+                collector_config.spike_rules.len(),
+                capacity
+            );
+            collector.spike_detector = Some(Arc::new(SpikeDetector::new(
+                collector_config.spike_rules.clone(),
+                capacity,
+            )));
+        }
+
+        if let Some(addr) = &collector_config.statsd {
+            collector.statsd = Some(StatsdSink::connect(addr)?);
+            tracing::info!("Pushing metrics to statsd at {}", addr);
+        }
+
+        // Subsystems that have to program counters are left out with --read-only-counters;
+        // RDT only writes MSRs when it assigns RMIDs itself instead of reading resctrl
+        let cc = &collector_config;
+        let read_only = config.read_only_counters;
+        let rdt_programs = config.resctrl_groups.is_empty();
+        if read_only {
+            let skipped = [
+                (cc.rdt && rdt_programs, "RDT"),
+                (cc.core_metrics, "Core PMU"),
+                (cc.imc, "IMC"),
+                (cc.cha, "CHA"),
+                (cc.cha_custom, "Custom CHA"),
+                (cc.irp, "IRP"),
+                (cc.upi, "UPI"),
+                (cc.m2m, "M2M"),
+                (cc.pcu, "PCU"),
+            ]
+            .into_iter()
+            .filter_map(|(enabled, label)| enabled.then_some(label))
+            .collect::<Vec<_>>();
+            if !skipped.is_empty() {
+                tracing::warn!(
+                    "Read-only counter mode: not collecting {}, they need programmable counters",
+                    skipped.join(", ")
+                );
+            }
+        }
+        let programmable = |enabled: bool| enabled && !read_only;
+
+        collector.subsystems = [
+            init_subsystem::<RaplMetricExporter>(cc.rapl, "RAPL", config.clone()),
+            init_subsystem::<RdtMetricExporter>(
+                cc.rdt && (!read_only || !rdt_programs),
+                "RDT",
+                config.clone(),
+            ),
+            init_subsystem::<CoreMetricExporter>(
+                programmable(cc.core_metrics),
+                "Core PMU",
+                config.clone(),
+            ),
+            init_subsystem::<ImcMetricExporter>(
+                programmable(cc.imc),
+                "IMC",
+                config.restricted_to(&cc.imc_sockets),
+            ),
+            init_subsystem::<ChaMetricExporter>(
+                programmable(cc.cha),
+                "CHA",
+                config.restricted_to(&cc.cha_sockets),
+            ),
+            init_subsystem::<ChaCustomMetricExporter>(
+                programmable(cc.cha_custom),
+                "Custom CHA",
+                config.restricted_to(&cc.cha_sockets),
+            ),
+            init_subsystem::<IrpMetricExporter>(
+                programmable(cc.irp),
+                "IRP",
+                config.restricted_to(&cc.irp_sockets),
+            ),
+            init_subsystem::<IioMetricExporter>(
+                cc.iio,
+                "IIO",
+                config.restricted_to(&cc.iio_sockets),
+            ),
+            init_subsystem::<UpiMetricExporter>(
+                programmable(cc.upi),
+                "UPI",
+                config.restricted_to(&cc.upi_sockets),
+            ),
+            init_subsystem::<M2mMetricExporter>(
+                programmable(cc.m2m),
+                "M2M",
+                config.restricted_to(&cc.m2m_sockets),
+            ),
+            init_subsystem::<SstMetricExporter>(cc.sst, "SST", config.clone()),
+            init_subsystem::<CstateMetricExporter>(cc.cstate, "C-state", config.clone()),
+            init_subsystem::<ThermalMetricExporter>(cc.thermal, "Thermal", config.clone()),
+            init_subsystem::<PcuMetricExporter>(programmable(cc.pcu), "PCU", config.clone()),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
 
         Ok(collector)
     }
@@ -137,7 +281,8 @@ impl MetricCollector {
 
     /// Main unified collection loop
     async fn collection_loop(self, cancel_token: CancellationToken) {
-        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        let mut interval = tokio::time::interval(COLLECTION_PERIOD);
+        let mut clock = TickClock::default();
 
         loop {
             tokio::select! {
@@ -148,52 +293,248 @@ impl MetricCollector {
                 _ = interval.tick() => {}
             }
 
-            // Collect all metrics in parallel using macro
-            let mut tasks = Vec::new();
-
-            crate::spawn_collector!(tasks, &self.rapl_exporter);
-            crate::spawn_collector!(tasks, &self.rdt_exporter);
-            crate::spawn_collector!(tasks, &self.core_exporter);
-            crate::spawn_collector!(tasks, &self.imc_exporter);
-            crate::spawn_collector!(tasks, &self.cha_exporter);
-            crate::spawn_collector!(tasks, &self.irp_exporter);
-            crate::spawn_collector!(tasks, &self.iio_exporter);
-
-            // Wait for all collections to complete
-            for task in tasks {
-                if let Err(e) = task.await {
-                    tracing::error!("Collection task failed: {}", e);
-                }
+            if let Some(elapsed) = clock.tick(Instant::now()) {
+                self.self_metrics.observe_tick_interval(elapsed);
             }
+
+            self.collect_once().await;
         }
     }
 
-    /// Get references to exporters for metrics handler
-    pub fn rapl_exporter(&self) -> Option<Arc<RaplMetricExporter>> {
-        self.rapl_exporter.clone()
+    /// Run one collection across all enabled exporters, as a loop tick or a scrape would
+    pub async fn collect_once(&self) {
+        let tick_start = Instant::now();
+
+        // Collect all subsystems in parallel
+        let tasks: Vec<_> = self
+            .subsystems
+            .iter()
+            .map(|subsystem| {
+                spawn_collect(
+                    Arc::clone(subsystem),
+                    Arc::clone(&self.self_metrics),
+                    Arc::clone(&self.read_timeout),
+                )
+            })
+            .collect();
+
+        // Wait for all collections to complete
+        let mut any_collected = false;
+        for task in tasks {
+            match task.await {
+                Ok(collected) => any_collected |= collected,
+                Err(e) => tracing::error!("Collection task failed: {}", e),
+            }
+        }
+        if any_collected {
+            self.readiness.mark_collected();
+        }
+
+        self.self_metrics
+            .observe_tick(tick_start.elapsed(), COLLECTION_PERIOD);
+
+        let streaming = self.metric_stream.has_subscribers();
+        if self.spike_detector.is_some() || self.statsd.is_some() || streaming {
+            let families = self.gather_all();
+            if let Some(detector) = &self.spike_detector {
+                detector.observe_families(&families);
+            }
+            if let Some(statsd) = &self.statsd {
+                statsd.send(&families);
+            }
+            if streaming {
+                self.metric_stream
+                    .publish(MetricSnapshot::from_families(&families));
+            }
+        }
+    }
+
+    /// Current metric families of all enabled exporters
+    pub(crate) fn gather_all(&self) -> Vec<MetricFamily> {
+        self.subsystems
+            .iter()
+            .flat_map(|subsystem| {
+                let mut families = subsystem.registry().gather();
+                self.collector_config
+                    .metric_filter
+                    .retain(subsystem.name(), &mut families);
+                families
+            })
+            .collect()
+    }
+
+    /// Attach a correlation (trace) id provider to recorded spikes
+    pub fn set_correlation_source(&self, source: CorrelationSource) {
+        if let Some(detector) = &self.spike_detector {
+            detector.set_correlation_source(source);
+        }
+    }
+
+    /// Add a subsystem built outside the collector, collected after the configured ones
+    pub fn add_subsystem(&mut self, subsystem: Arc<dyn Subsystem>) {
+        self.subsystems.push(subsystem);
+    }
+
+    /// Enabled subsystems, for the metrics and reset handlers
+    pub fn subsystems(&self) -> Vec<Arc<dyn Subsystem>> {
+        self.subsystems.clone()
+    }
+
+    /// Families exported per subsystem, for the metrics handlers
+    pub fn metric_filter(&self) -> MetricFilter {
+        self.collector_config.metric_filter.clone()
+    }
+
+    pub fn self_metrics(&self) -> Arc<SelfMetrics> {
+        Arc::clone(&self.self_metrics)
+    }
+
+    pub fn spike_detector(&self) -> Option<Arc<SpikeDetector>> {
+        self.spike_detector.clone()
+    }
+
+    pub fn readiness(&self) -> Arc<Readiness> {
+        Arc::clone(&self.readiness)
+    }
+
+    pub fn metric_stream(&self) -> Arc<MetricStream> {
+        Arc::clone(&self.metric_stream)
     }
 
-    pub fn rdt_exporter(&self) -> Option<Arc<RdtMetricExporter>> {
-        self.rdt_exporter.clone()
+    /// Deadline of the collections, shared with reads outside the loop
+    pub fn read_timeout(&self) -> Arc<ReadTimeout> {
+        Arc::clone(&self.read_timeout)
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::msr::mock::MockMsr;
+    use crate::counters::cha::ChaMonitor;
+    use uncflow_raw::current_arch::cha;
+
+    #[test]
+    fn test_tick_intervals() {
+        let start = Instant::now();
+        let ticks = [0, 1000, 2050, 2950, 4400].map(|ms| start + Duration::from_millis(ms));
+        let mut clock = TickClock::default();
+        let intervals: Vec<_> = ticks.iter().filter_map(|&t| clock.tick(t)).collect();
+        assert_eq!(
+            intervals,
+            [1000, 1050, 900, 1450].map(Duration::from_millis)
+        );
 
-    pub fn core_exporter(&self) -> Option<Arc<CoreMetricExporter>> {
-        self.core_exporter.clone()
+        let self_metrics = SelfMetrics::new().unwrap();
+        for &elapsed in &intervals {
+            self_metrics.observe_tick_interval(elapsed);
+        }
+        let families = self_metrics.registry().gather();
+        let histogram = families
+            .iter()
+            .find(|f| f.name() == "uncflow_tick_interval_seconds")
+            .map(|f| f.get_metric()[0].get_histogram().clone())
+            .unwrap();
+        assert_eq!(histogram.get_sample_count(), 4);
+        assert!((histogram.get_sample_sum() - 4.4).abs() < 1e-9);
+        // Within 1% of the period: only the 1000ms tick
+        let at_most = |bound: f64| {
+            histogram
+                .get_bucket()
+                .iter()
+                .find(|b| (b.upper_bound() - bound).abs() < 1e-9)
+                .map(|b| b.cumulative_count())
+                .unwrap()
+        };
+        assert_eq!(at_most(1.01) - at_most(0.99), 1);
+        // 900ms and 1000ms are at most 1.01s, the late ticks above 1.05s but not 1.5s
+        assert_eq!(at_most(1.01), 2);
+        assert_eq!(at_most(1.5) - at_most(1.05), 1);
     }
 
-    pub fn imc_exporter(&self) -> Option<Arc<ImcMetricExporter>> {
-        self.imc_exporter.clone()
+    #[tokio::test]
+    async fn test_monitor_error_is_tracked_as_failure() {
+        let msr = MockMsr::new().leak();
+        let config = ExportConfig::new(vec![0], vec![0]);
+        let exporter: Arc<dyn Subsystem> =
+            Arc::new(ChaMetricExporter::with_msr(config, msr).unwrap());
+        let self_metrics = Arc::new(SelfMetrics::new().unwrap());
+        let read_timeout = Arc::new(ReadTimeout::new(Duration::from_secs(5)));
+
+        // A counter the kernel stops letting us read
+        msr.fail_reads(ChaMonitor::default_core(0), cha::msr::counter_value(0, 0));
+
+        let collected = spawn_collect(exporter, Arc::clone(&self_metrics), read_timeout)
+            .await
+            .unwrap();
+
+        assert!(!collected);
+        assert_eq!(self_metrics.collection_failures("cha"), 1);
+        assert_eq!(self_metrics.collection_failures("imc"), 0);
     }
 
-    pub fn cha_exporter(&self) -> Option<Arc<ChaMetricExporter>> {
-        self.cha_exporter.clone()
+    /// Subsystem counting its collections into one gauge
+    struct DummySubsystem {
+        registry: prometheus::Registry,
+        collections: prometheus::IntGauge,
     }
 
-    pub fn irp_exporter(&self) -> Option<Arc<IrpMetricExporter>> {
-        self.irp_exporter.clone()
+    impl Subsystem for DummySubsystem {
+        fn name(&self) -> &'static str {
+            "dummy"
+        }
+
+        fn init(_config: ExportConfig) -> crate::error::Result<Self> {
+            let registry = prometheus::Registry::new();
+            let collections = prometheus::IntGauge::new("dummy_collections", "Collections so far")?;
+            registry.register(Box::new(collections.clone()))?;
+            Ok(Self {
+                registry,
+                collections,
+            })
+        }
+
+        fn collect(&self) -> futures_util::future::BoxFuture<'_, crate::error::Result<()>> {
+            Box::pin(async move {
+                self.collections.inc();
+                Ok(())
+            })
+        }
+
+        fn registry(&self) -> &prometheus::Registry {
+            &self.registry
+        }
+
+        fn reset(&self) {
+            self.collections.set(0);
+        }
     }
 
-    pub fn iio_exporter(&self) -> Option<Arc<IioMetricExporter>> {
-        self.iio_exporter.clone()
+    #[tokio::test]
+    async fn test_dummy_subsystem_plugs_into_collector() {
+        let config = ExportConfig::new(vec![0], vec![0]);
+        let mut collector =
+            MetricCollector::new(config.clone(), CollectorConfig::default()).unwrap();
+        assert!(collector.subsystems().is_empty());
+        collector.add_subsystem(Arc::new(DummySubsystem::init(config).unwrap()));
+
+        collector.collect_once().await;
+        collector.collect_once().await;
+
+        assert!(collector.readiness().is_ready());
+        assert_eq!(collector.self_metrics().collection_failures("dummy"), 0);
+        let value = |collector: &MetricCollector| {
+            collector
+                .gather_all()
+                .iter()
+                .find(|f| f.name() == "dummy_collections")
+                .map(|f| f.get_metric()[0].get_gauge().value())
+        };
+        assert_eq!(value(&collector), Some(2.0));
+
+        for subsystem in collector.subsystems() {
+            subsystem.reset();
+        }
+        assert_eq!(value(&collector), Some(0.0));
     }
 }
@@ -0,0 +1,68 @@
+// Per-subsystem allow lists of metric families (--profile)
+//
+// A profile narrows some subsystems to the families it is about, e.g. the memory
+// profile keeps only the CHA bandwidth and the RAPL DRAM families. Families are
+// matched by a case-insensitive substring of their name, which covers both the
+// legacy CamelCase names (DRAMPower) and the snake_case ones (rapl_dram_power_watts).
+// Subsystems without an allow list export every family.
+
+use prometheus::proto::MetricFamily;
+use std::collections::HashMap;
+
+/// Families kept per subsystem; subsystems without an entry keep everything
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetricFilter {
+    allowed: HashMap<&'static str, Vec<&'static str>>,
+}
+
+impl MetricFilter {
+    /// Keep only the families of `subsystem` whose name contains one of `patterns`
+    pub fn allow(mut self, subsystem: &'static str, patterns: &[&'static str]) -> Self {
+        self.allowed.insert(subsystem, patterns.to_vec());
+        self
+    }
+
+    /// Export every family of `subsystem` again
+    pub fn unrestrict(&mut self, subsystem: &str) {
+        self.allowed.remove(subsystem);
+    }
+
+    /// Whether `subsystem` has an allow list
+    pub fn restricts(&self, subsystem: &str) -> bool {
+        self.allowed.contains_key(subsystem)
+    }
+
+    /// Whether the family `name` of `subsystem` is exported
+    pub fn keeps(&self, subsystem: &str, name: &str) -> bool {
+        self.allowed.get(subsystem).is_none_or(|patterns| {
+            let name = name.to_ascii_lowercase();
+            patterns
+                .iter()
+                .any(|pattern| name.contains(&pattern.to_ascii_lowercase()))
+        })
+    }
+
+    /// Drop the families of `subsystem` that are not exported
+    pub fn retain(&self, subsystem: &str, families: &mut Vec<MetricFamily>) {
+        families.retain(|family| self.keeps(subsystem, family.name()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_matches_substrings() {
+        let mut filter = MetricFilter::default().allow("rapl", &["dram"]);
+
+        assert!(filter.keeps("rapl", "DRAMPower"));
+        assert!(filter.keeps("rapl", "rapl_dram_power_watts"));
+        assert!(!filter.keeps("rapl", "PackagePower"));
+        assert!(filter.keeps("imc", "imc_read_bandwidth"));
+
+        filter.unrestrict("rapl");
+        assert!(!filter.restricts("rapl"));
+        assert!(filter.keeps("rapl", "PackagePower"));
+    }
+}
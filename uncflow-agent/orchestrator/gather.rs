@@ -0,0 +1,115 @@
+// prometheus::Collector over every enabled exporter, collecting at gather time
+//
+// For embedding uncflow into an application's own Registry:
+//
+//     registry.register(Box::new(UncflowCollector::new(config, collector_config)?))?;
+//
+// Each gather() runs one collection through a ScrapeCollector, so gathers closer
+// than MIN_SCRAPE_INTERVAL return the previous values, then yields the exporters'
+// and the agent's own families. The collection is async; it runs on a runtime
+// owned by the collector, on a separate thread so gather() may also be called
+// from inside another tokio runtime.
+
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use std::collections::HashMap;
+
+use crate::config::ExportConfig;
+use crate::error::Result;
+
+use super::{CollectorConfig, MetricCollector, ScrapeCollector, MIN_SCRAPE_INTERVAL};
+
+/// Collects uncflow metrics whenever the Registry it is registered in is gathered
+pub struct UncflowCollector {
+    scrape: ScrapeCollector,
+    runtime: tokio::runtime::Runtime,
+    descs: Vec<Desc>,
+}
+
+impl UncflowCollector {
+    pub fn new(config: ExportConfig, collector_config: CollectorConfig) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let scrape = ScrapeCollector::new(
+            MetricCollector::new(config, collector_config)?,
+            MIN_SCRAPE_INTERVAL,
+        );
+
+        // One descriptor per family registered so far, for the Registry's
+        // duplicate checks
+        let mut descs = Vec::new();
+        for family in scrape.gather() {
+            let labels = family
+                .get_metric()
+                .first()
+                .map(|metric| {
+                    metric
+                        .get_label()
+                        .iter()
+                        .map(|label| label.name().to_string())
+                        .collect()
+                })
+                .unwrap_or_default();
+            descs.push(Desc::new(
+                family.name().to_string(),
+                family.help().to_string(),
+                labels,
+                HashMap::new(),
+            )?);
+        }
+
+        Ok(Self {
+            scrape,
+            runtime,
+            descs,
+        })
+    }
+}
+
+impl Collector for UncflowCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.descs.iter().collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                self.runtime.block_on(self.scrape.collect());
+            });
+        });
+        self.scrape.gather()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::Registry;
+
+    #[test]
+    fn test_collect_yields_families() {
+        let collector = UncflowCollector::new(
+            ExportConfig::new(vec![0], vec![0]),
+            CollectorConfig::default(),
+        )
+        .unwrap();
+        assert!(!collector.desc().is_empty());
+
+        let names = |families: Vec<MetricFamily>| -> Vec<String> {
+            families.iter().map(|f| f.name().to_string()).collect()
+        };
+        let collected = names(Collector::collect(&collector));
+        for name in ["uncflow_build_info", "uncflow_interval_overrun_total"] {
+            assert!(collected.iter().any(|n| n == name), "{collected:?}");
+        }
+
+        // Also from inside another runtime, through a Registry
+        let registry = Registry::new();
+        registry.register(Box::new(collector)).unwrap();
+        let gathered = tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(async { registry.gather() });
+        assert!(names(gathered).iter().any(|n| n == "uncflow_build_info"));
+    }
+}
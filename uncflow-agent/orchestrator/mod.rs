@@ -1,3 +1,25 @@
+pub mod cardinality;
 pub mod collector;
+pub mod filter;
+pub mod gather;
+pub mod readiness;
+pub mod scrape;
+pub mod self_metrics;
+pub mod session;
+pub mod spikes;
+pub mod stream;
+pub mod subsystem;
+pub mod timeout;
 
-pub use collector::{CollectorConfig, MetricCollector};
+pub use cardinality::{SeriesCount, DEFAULT_MAX_SERIES};
+pub use collector::{CollectorConfig, MetricCollector, COLLECTION_PERIOD};
+pub use filter::MetricFilter;
+pub use gather::UncflowCollector;
+pub use readiness::Readiness;
+pub use scrape::{ScrapeCollector, MIN_SCRAPE_INTERVAL};
+pub use self_metrics::SelfMetrics;
+pub use session::{SessionId, SessionReport, Sessions, SubsystemSnapshot, MAX_OPEN_SESSIONS};
+pub use spikes::{CorrelationSource, SpikeDetector, SpikeEvent, SpikeRule, DEFAULT_SPIKE_CAPACITY};
+pub use stream::{MetricSample, MetricSnapshot, MetricStream, DEFAULT_STREAM_CAPACITY};
+pub use subsystem::Subsystem;
+pub use timeout::{CollectOutcome, ReadTimeout, DEFAULT_READ_TIMEOUT};
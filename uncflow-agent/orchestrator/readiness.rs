@@ -0,0 +1,46 @@
+// Readiness tracking for the /ready probe
+// The agent is ready once any subsystem has completed a collection, so scrapes
+// are not routed to a freshly started pod that would only export zeros
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Flag flipped by the collection loop after the first successful collection
+#[derive(Debug, Default)]
+pub struct Readiness {
+    collected: AtomicBool,
+}
+
+impl Readiness {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that at least one subsystem finished collecting
+    pub fn mark_collected(&self) {
+        if !self.collected.swap(true, Ordering::Release) {
+            tracing::info!("First collection completed, agent is ready");
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.collected.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ready_after_first_collection() {
+        let readiness = Readiness::new();
+        assert!(!readiness.is_ready());
+
+        readiness.mark_collected();
+        assert!(readiness.is_ready());
+
+        // Stays ready
+        readiness.mark_collected();
+        assert!(readiness.is_ready());
+    }
+}
@@ -0,0 +1,96 @@
+// Collect-on-scrape mode
+// Instead of the background interval loop, each /metrics request triggers one collection
+// before gathering. Counter deltas (bandwidth, latency, rates) then span the time since the
+// previous scrape rather than COLLECTION_PERIOD, so they average over the scrape interval.
+
+use prometheus::proto::MetricFamily;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+use super::{MetricCollector, COLLECTION_PERIOD};
+
+/// Minimum time between scrape-triggered collections; closer scrapes reuse the last values
+pub const MIN_SCRAPE_INTERVAL: Duration = COLLECTION_PERIOD;
+
+/// Runs a collection per scrape, coalescing concurrent and closely spaced scrapes
+pub struct ScrapeCollector {
+    collector: MetricCollector,
+    min_interval: Duration,
+    // Held for the whole collection, so concurrent scrapes wait for the one in flight
+    last_collection: Mutex<Option<Instant>>,
+}
+
+impl ScrapeCollector {
+    pub fn new(collector: MetricCollector, min_interval: Duration) -> Self {
+        Self {
+            collector,
+            min_interval,
+            last_collection: Mutex::new(None),
+        }
+    }
+
+    /// Collect unless another collection finished less than `min_interval` ago
+    ///
+    /// Returns true if this call ran the collection. Very short windows would make the
+    /// deltas noisy, so scrapes inside the guard see the previous values instead.
+    pub async fn collect(&self) -> bool {
+        let mut last = self.last_collection.lock().await;
+        if last.is_some_and(|at| at.elapsed() < self.min_interval) {
+            return false;
+        }
+
+        self.collector.collect_once().await;
+        *last = Some(Instant::now());
+        true
+    }
+
+    /// Current metric families of the exporters and the agent's self metrics
+    pub fn gather(&self) -> Vec<MetricFamily> {
+        let mut families = self.collector.gather_all();
+        families.extend(self.collector.self_metrics().registry().gather());
+        families
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ExportConfig;
+    use crate::orchestrator::CollectorConfig;
+    use std::sync::Arc;
+
+    fn scrape_collector(min_interval: Duration) -> Arc<ScrapeCollector> {
+        let collector = MetricCollector::new(
+            ExportConfig::new(vec![0], vec![0]),
+            CollectorConfig::default(),
+        )
+        .unwrap();
+        Arc::new(ScrapeCollector::new(collector, min_interval))
+    }
+
+    #[tokio::test]
+    async fn test_scrape_triggers_collection() {
+        let scrape = scrape_collector(Duration::ZERO);
+
+        assert!(scrape.collect().await);
+        assert!(scrape.collect().await);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_scrapes_coalesce() {
+        let scrape = scrape_collector(Duration::from_secs(60));
+
+        let scrapes = (0..8).map(|_| {
+            let scrape = Arc::clone(&scrape);
+            tokio::spawn(async move { scrape.collect().await })
+        });
+        let mut collected = 0;
+        for scrape in scrapes {
+            collected += scrape.await.unwrap() as usize;
+        }
+        assert_eq!(collected, 1);
+
+        // Still inside the guard
+        assert!(!scrape.collect().await);
+    }
+}
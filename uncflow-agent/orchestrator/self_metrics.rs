@@ -0,0 +1,262 @@
+// Self-monitoring metrics for the collection orchestrator
+// Tracks how long each subsystem takes to collect, how often a tick overruns its interval,
+// how far apart ticks actually are, how often a collect fails or is abandoned on the
+// read timeout, plus info metrics
+// (constant 1, facts in the labels) identifying the hardware and agent build
+
+use prometheus::{GaugeVec, Histogram, HistogramOpts, IntCounter, IntCounterVec, Opts, Registry};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::COLLECTION_PERIOD;
+use crate::common::{CpuInfo, CPU_INFO};
+use crate::error::Result;
+
+/// Bucket bounds of the tick interval histogram, as fractions of the collection period
+const TICK_INTERVAL_BUCKETS: [f64; 9] = [0.5, 0.9, 0.95, 0.99, 1.01, 1.05, 1.1, 1.5, 2.0];
+
+/// Agent health metrics, exported from a small internal registry
+pub struct SelfMetrics {
+    registry: Arc<Registry>,
+    collection_duration: GaugeVec,
+    interval_overrun: IntCounter,
+    tick_interval: Histogram,
+    read_timeouts: IntCounterVec,
+    collection_failures: IntCounterVec,
+}
+
+impl SelfMetrics {
+    pub fn new() -> Result<Self> {
+        Self::with_cpu_info(&CPU_INFO)
+    }
+
+    /// Agent health metrics, with uncflow_cpu_info describing `cpu`
+    pub fn with_cpu_info(cpu: &CpuInfo) -> Result<Self> {
+        let registry = Arc::new(Registry::new());
+
+        let collection_duration = GaugeVec::new(
+            Opts::new(
+                "uncflow_collection_duration_seconds",
+                "Wall-clock time of the last collect() call per subsystem",
+            ),
+            &["subsystem"],
+        )?;
+        registry.register(Box::new(collection_duration.clone()))?;
+
+        let interval_overrun = IntCounter::new(
+            "uncflow_interval_overrun_total",
+            "Number of collection ticks whose work exceeded the collection interval",
+        )?;
+        registry.register(Box::new(interval_overrun.clone()))?;
+
+        let period = COLLECTION_PERIOD.as_secs_f64();
+        let tick_interval = Histogram::with_opts(
+            HistogramOpts::new(
+                "uncflow_tick_interval_seconds",
+                "Time between the starts of successive collection ticks",
+            )
+            .buckets(
+                TICK_INTERVAL_BUCKETS
+                    .iter()
+                    .map(|fraction| fraction * period)
+                    .collect(),
+            ),
+        )?;
+        registry.register(Box::new(tick_interval.clone()))?;
+
+        let read_timeouts = IntCounterVec::new(
+            Opts::new(
+                "uncflow_read_timeouts_total",
+                "Collections abandoned or skipped because a counter read exceeded --read-timeout-ms",
+            ),
+            &["subsystem"],
+        )?;
+        registry.register(Box::new(read_timeouts.clone()))?;
+
+        let collection_failures = IntCounterVec::new(
+            Opts::new(
+                "uncflow_collection_failures_total",
+                "Collections whose collect() returned an error, per subsystem",
+            ),
+            &["subsystem"],
+        )?;
+        registry.register(Box::new(collection_failures.clone()))?;
+
+        let cpu_info = GaugeVec::new(
+            Opts::new(
+                "uncflow_cpu_info",
+                "CPUID-derived facts of the monitored CPU, always 1",
+            ),
+            &[
+                "family",
+                "model",
+                "stepping",
+                "arch",
+                "cha_count",
+                "pmu_version",
+            ],
+        )?;
+        registry.register(Box::new(cpu_info.clone()))?;
+        let cha_count = cpu
+            .arch
+            .cha_count()
+            .map_or_else(|| "unknown".to_string(), |count| count.to_string());
+        cpu_info
+            .with_label_values(&[
+                format!("0x{:X}", cpu.family).as_str(),
+                format!("0x{:X}", cpu.model).as_str(),
+                cpu.stepping.to_string().as_str(),
+                cpu.arch.name(),
+                cha_count.as_str(),
+                cpu.pmu_version.to_string().as_str(),
+            ])
+            .set(1.0);
+
+        let build_info = GaugeVec::new(
+            Opts::new(
+                "uncflow_build_info",
+                "Version of the running agent, always 1",
+            ),
+            &["version"],
+        )?;
+        registry.register(Box::new(build_info.clone()))?;
+        build_info
+            .with_label_values(&[env!("CARGO_PKG_VERSION")])
+            .set(1.0);
+
+        Ok(Self {
+            registry,
+            collection_duration,
+            interval_overrun,
+            tick_interval,
+            read_timeouts,
+            collection_failures,
+        })
+    }
+
+    /// Record how long a subsystem's collect() took
+    pub fn observe_collection(&self, subsystem: &str, elapsed: Duration) {
+        self.collection_duration
+            .with_label_values(&[subsystem])
+            .set(elapsed.as_secs_f64());
+    }
+
+    /// Record a collection of `subsystem` lost to a stuck read
+    pub fn observe_read_timeout(&self, subsystem: &str) {
+        self.read_timeouts.with_label_values(&[subsystem]).inc();
+    }
+
+    /// Record a collection of `subsystem` that returned an error
+    pub fn observe_collection_failure(&self, subsystem: &str) {
+        self.collection_failures
+            .with_label_values(&[subsystem])
+            .inc();
+    }
+
+    /// Number of failed collections of `subsystem` so far
+    pub fn collection_failures(&self, subsystem: &str) -> u64 {
+        self.collection_failures
+            .with_label_values(&[subsystem])
+            .get()
+    }
+
+    /// Record the time since the previous tick started, the real sampling period
+    pub fn observe_tick_interval(&self, interval: Duration) {
+        self.tick_interval.observe(interval.as_secs_f64());
+    }
+
+    /// Record the total work time of one tick, returns true if it overran the interval
+    pub fn observe_tick(&self, elapsed: Duration, interval: Duration) -> bool {
+        let overrun = elapsed > interval;
+        if overrun {
+            self.interval_overrun.inc();
+            tracing::warn!(
+                "Collection tick took {:?}, exceeding the {:?} interval",
+                elapsed,
+                interval
+            );
+        }
+        overrun
+    }
+
+    pub fn registry(&self) -> Arc<Registry> {
+        Arc::clone(&self.registry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prom::raw::tests::gauge_value;
+
+    #[test]
+    fn test_overrun_counter() {
+        let metrics = SelfMetrics::new().unwrap();
+        let interval = Duration::from_millis(20);
+
+        // Slow collect
+        assert!(metrics.observe_tick(Duration::from_millis(30), interval));
+
+        // Fast tick does not count
+        assert!(!metrics.observe_tick(Duration::from_millis(5), interval));
+
+        assert_eq!(metrics.interval_overrun.get(), 1);
+    }
+
+    #[test]
+    fn test_collection_duration() {
+        let metrics = SelfMetrics::new().unwrap();
+        metrics.observe_collection("imc", Duration::from_millis(250));
+
+        let value = metrics
+            .collection_duration
+            .with_label_values(&["imc"])
+            .get();
+        assert!((value - 0.25).abs() < 1e-9);
+        // Collection duration, overruns, tick intervals and the two info metrics
+        assert_eq!(metrics.registry().gather().len(), 5);
+    }
+
+    #[test]
+    fn test_cpu_info_labels() {
+        let cascade_lake = CpuInfo {
+            family: 6,
+            model: 0x55,
+            stepping: 7,
+            arch: crate::common::CpuArchitecture::CascadeLake,
+            pmu_version: 4,
+        };
+        let families = SelfMetrics::with_cpu_info(&cascade_lake)
+            .unwrap()
+            .registry()
+            .gather();
+        let cpu_info = gauge_value(
+            &families,
+            "uncflow_cpu_info",
+            &[
+                ("family", "0x6"),
+                ("model", "0x55"),
+                ("stepping", "7"),
+                ("arch", "Cascade Lake"),
+                ("cha_count", "26"),
+                ("pmu_version", "4"),
+            ],
+        );
+        assert_eq!(cpu_info, Some(1.0));
+        let version = [("version", env!("CARGO_PKG_VERSION"))];
+        assert_eq!(
+            gauge_value(&families, "uncflow_build_info", &version),
+            Some(1.0)
+        );
+
+        // The default carries the detected architecture
+        let families = SelfMetrics::new().unwrap().registry().gather();
+        let arch = families
+            .iter()
+            .find(|f| f.name() == "uncflow_cpu_info")
+            .and_then(|f| f.get_metric().first())
+            .and_then(|m| m.get_label().iter().find(|l| l.name() == "arch"))
+            .map(|l| l.value().to_string());
+        assert_eq!(arch.as_deref(), Some(crate::common::CPU_ARCH.name()));
+    }
+}
@@ -0,0 +1,290 @@
+// Measurement sessions bracketing a benchmark phase
+//
+// A session snapshots the counters of the requested subsystems when it starts and
+// again when it stops, and reports the diff restricted to the requested cores and
+// sockets. Snapshots come from the exporters' own monitors, so sessions reprogram
+// nothing and do not disturb the regular collection. Only subsystems implementing
+// Subsystem::snapshot (core and imc) can be measured.
+
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::counters::imc::peak;
+use crate::counters::{core, imc};
+use crate::error::{Result, UncflowError};
+use crate::metrics::imc::ImcMetric;
+
+use super::Subsystem;
+
+/// Sessions that may be open at once, so abandoned ones cannot pile up
+pub const MAX_OPEN_SESSIONS: usize = 64;
+
+/// Handle returned by [`Sessions::start_session`]
+pub type SessionId = u64;
+
+/// Point-in-time counter reading of one subsystem
+#[derive(Debug, Clone)]
+pub enum SubsystemSnapshot {
+    /// Core PMU counters of every monitored core
+    Core(core::CounterSnapshot),
+    /// IMC channel counters by socket
+    Imc(BTreeMap<i32, imc::CounterSnapshot>),
+}
+
+impl SubsystemSnapshot {
+    /// Metrics over the window up to `later`, by core (core) or socket (imc)
+    pub fn diff(&self, later: &SubsystemSnapshot) -> BTreeMap<i32, BTreeMap<String, f64>> {
+        match (self, later) {
+            (Self::Core(before), Self::Core(after)) => core::diff(before, after)
+                .into_iter()
+                .map(|(core, metrics)| (core, metrics.into_iter().collect()))
+                .collect(),
+            (Self::Imc(before), Self::Imc(after)) => after
+                .iter()
+                .filter_map(|(&socket, after)| {
+                    let metrics = imc::diff(before.get(&socket)?, after);
+                    Some((socket, imc_metrics_by_name(&metrics)))
+                })
+                .collect(),
+            _ => BTreeMap::new(),
+        }
+    }
+}
+
+/// Socket-wide IMC metrics under their /metrics names
+fn imc_metrics_by_name(metrics: &imc::ImcMetrics) -> BTreeMap<String, f64> {
+    [
+        (
+            ImcMetric::MemoryReadBandwidth,
+            metrics.read_bandwidth as f64,
+        ),
+        (
+            ImcMetric::MemoryWriteBandwidth,
+            metrics.write_bandwidth as f64,
+        ),
+        (ImcMetric::MemoryReadLatency, metrics.read_latency),
+        (ImcMetric::MemoryWriteLatency, metrics.write_latency),
+        (ImcMetric::MemoryRPQOccupancy, metrics.rpq_occupancy as f64),
+        (ImcMetric::MemoryWPQOccupancy, metrics.wpq_occupancy as f64),
+        (ImcMetric::IMCRPQNonEmpty, metrics.rpq_non_empty),
+        (ImcMetric::IMCRPQFull, metrics.rpq_full),
+        (ImcMetric::IMCWPQNonEmpty, metrics.wpq_non_empty),
+        (ImcMetric::IMCWPQFull, metrics.wpq_full),
+        (ImcMetric::IMCFrequency, metrics.frequency),
+        (ImcMetric::DataRate, peak::data_rate_mts(metrics.frequency)),
+        (ImcMetric::WriteReadRatio, metrics.write_read_ratio),
+    ]
+    .into_iter()
+    .map(|(metric, value)| (metric.name().to_string(), value))
+    .collect()
+}
+
+/// Deltas accumulated over one session, as served by POST /session/stop
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SessionReport {
+    pub id: SessionId,
+    /// Wall-clock length of the session
+    pub seconds: f64,
+    /// Metrics by subsystem, then by core (core) or socket (imc)
+    pub subsystems: BTreeMap<String, BTreeMap<i32, BTreeMap<String, f64>>>,
+}
+
+struct Session {
+    started: Instant,
+    // Requested cores and sockets, None for all monitored ones
+    cores: Option<BTreeSet<i32>>,
+    sockets: Option<BTreeSet<i32>>,
+    start: Vec<(Arc<dyn Subsystem>, SubsystemSnapshot)>,
+}
+
+/// Open measurement sessions over the enabled subsystems
+pub struct Sessions {
+    subsystems: Vec<Arc<dyn Subsystem>>,
+    next_id: AtomicU64,
+    open: Mutex<HashMap<SessionId, Session>>,
+}
+
+impl Sessions {
+    pub fn new(subsystems: Vec<Arc<dyn Subsystem>>) -> Self {
+        Self {
+            subsystems,
+            next_id: AtomicU64::new(1),
+            open: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Snapshot `subsystems` (every one supporting sessions when empty) and open a
+    /// session reporting on `cores` and `sockets` (all monitored ones when empty)
+    ///
+    /// With multiplexed core event groups, programmable counts are only meaningful
+    /// when no group switch falls inside the session.
+    pub fn start_session(
+        &self,
+        cores: &[i32],
+        sockets: &[i32],
+        subsystems: &[String],
+    ) -> Result<SessionId> {
+        if let Some(unknown) = subsystems
+            .iter()
+            .find(|&name| !self.subsystems.iter().any(|s| s.name() == name))
+        {
+            return Err(UncflowError::ConfigError(format!(
+                "Subsystem {unknown:?} is not enabled"
+            )));
+        }
+        if self.open.lock().len() >= MAX_OPEN_SESSIONS {
+            return Err(UncflowError::ConfigError(format!(
+                "{MAX_OPEN_SESSIONS} sessions are already open"
+            )));
+        }
+
+        let mut start = Vec::new();
+        for subsystem in &self.subsystems {
+            let requested = subsystems.iter().any(|name| name == subsystem.name());
+            if !subsystems.is_empty() && !requested {
+                continue;
+            }
+            match subsystem.snapshot() {
+                Some(snapshot) => start.push((Arc::clone(subsystem), snapshot?)),
+                None if requested => {
+                    return Err(UncflowError::ConfigError(format!(
+                        "Subsystem {:?} does not support sessions",
+                        subsystem.name()
+                    )))
+                }
+                None => {}
+            }
+        }
+        if start.is_empty() {
+            return Err(UncflowError::ConfigError(
+                "No enabled subsystem supports sessions (core, imc)".to_string(),
+            ));
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let filter = |ids: &[i32]| (!ids.is_empty()).then(|| ids.iter().copied().collect());
+        self.open.lock().insert(
+            id,
+            Session {
+                started: Instant::now(),
+                cores: filter(cores),
+                sockets: filter(sockets),
+                start,
+            },
+        );
+        tracing::info!("Started measurement session {}", id);
+        Ok(id)
+    }
+
+    /// Close session `id` and report the deltas since it started
+    pub fn stop_session(&self, id: SessionId) -> Result<SessionReport> {
+        let session =
+            self.open.lock().remove(&id).ok_or_else(|| {
+                UncflowError::ConfigError(format!("No open session with id {id}"))
+            })?;
+        let seconds = session.started.elapsed().as_secs_f64();
+
+        let mut report = SessionReport {
+            id,
+            seconds,
+            subsystems: BTreeMap::new(),
+        };
+        for (subsystem, before) in &session.start {
+            let Some(after) = subsystem.snapshot() else {
+                continue;
+            };
+            let wanted = match before {
+                SubsystemSnapshot::Core(_) => &session.cores,
+                SubsystemSnapshot::Imc(_) => &session.sockets,
+            };
+            let mut deltas = before.diff(&after?);
+            if let Some(wanted) = wanted {
+                deltas.retain(|id, _| wanted.contains(id));
+            }
+            report
+                .subsystems
+                .insert(subsystem.name().to_string(), deltas);
+        }
+        tracing::info!("Stopped measurement session {} after {:.3}s", id, seconds);
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::msr::mock::MockMsr;
+    use crate::config::ExportConfig;
+    use crate::counters::core::events::IA32_FIXED_CTR0;
+    use crate::counters::core::CoreMonitor;
+    use futures_util::future::BoxFuture;
+    use prometheus::Registry;
+
+    struct CoreSubsystem {
+        monitor: CoreMonitor,
+        registry: Registry,
+    }
+
+    impl CoreSubsystem {
+        fn with_msr(config: ExportConfig, msr: &'static MockMsr) -> Result<Self> {
+            let mut monitor = CoreMonitor::with_msr(config, msr)?;
+            monitor.initialize()?;
+            Ok(Self {
+                monitor,
+                registry: Registry::new(),
+            })
+        }
+    }
+
+    impl Subsystem for CoreSubsystem {
+        fn name(&self) -> &'static str {
+            "core"
+        }
+
+        fn init(config: ExportConfig) -> Result<Self> {
+            Self::with_msr(config, MockMsr::new().leak())
+        }
+
+        fn collect(&self) -> BoxFuture<'_, Result<()>> {
+            Box::pin(async { Ok(()) })
+        }
+
+        fn registry(&self) -> &Registry {
+            &self.registry
+        }
+
+        fn snapshot(&self) -> Option<Result<SubsystemSnapshot>> {
+            Some(self.monitor.snapshot().map(SubsystemSnapshot::Core))
+        }
+    }
+
+    #[test]
+    fn test_report_is_diff_of_start_and_stop_snapshots() {
+        let msr = MockMsr::new().leak();
+        let subsystem =
+            Arc::new(CoreSubsystem::with_msr(ExportConfig::new(vec![0], vec![0, 1]), msr).unwrap());
+        let sessions = Sessions::new(vec![subsystem.clone()]);
+
+        let before = subsystem.snapshot().unwrap().unwrap();
+        let id = sessions.start_session(&[1], &[], &[]).unwrap();
+        msr.set(0, IA32_FIXED_CTR0, 1_000);
+        msr.set(1, IA32_FIXED_CTR0, 4_000);
+        let after = subsystem.snapshot().unwrap().unwrap();
+        let report = sessions.stop_session(id).unwrap();
+
+        let mut expected = before.diff(&after);
+        expected.remove(&0);
+        assert_eq!(report.subsystems["core"], expected);
+        assert_eq!(report.subsystems["core"][&1]["instructions"], 4_000.0);
+
+        // Closed sessions and unknown subsystems are rejected
+        assert!(sessions.stop_session(id).is_err());
+        assert!(sessions
+            .start_session(&[], &[], &["cha".to_string()])
+            .is_err());
+    }
+}
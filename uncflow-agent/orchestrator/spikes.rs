@@ -0,0 +1,235 @@
+// Spike annotation for correlating metric anomalies with distributed traces
+// After each tick, configured gauges are checked against a threshold and upward
+// crossings are recorded with a timestamp and an externally-supplied correlation id
+
+use parking_lot::Mutex;
+use prometheus::proto::{MetricFamily, MetricType};
+use serde::Serialize;
+use std::collections::{HashSet, VecDeque};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::UncflowError;
+
+/// Default number of spike events retained for `/spikes`
+pub const DEFAULT_SPIKE_CAPACITY: usize = 256;
+
+/// Supplies the correlation (trace) id to attach to a newly recorded spike
+pub type CorrelationSource = Arc<dyn Fn() -> Option<String> + Send + Sync>;
+
+/// A metric name and the value above which it counts as a spike
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpikeRule {
+    pub metric: String,
+    pub threshold: f64,
+}
+
+impl FromStr for SpikeRule {
+    type Err = UncflowError;
+
+    /// Parse `METRIC=THRESHOLD`, e.g. `MemoryReadBandwidth=2e10`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (metric, threshold) = s.split_once('=').ok_or_else(|| {
+            UncflowError::ParseError(format!("Expected METRIC=THRESHOLD, got '{s}'"))
+        })?;
+        let metric = metric.trim();
+        if metric.is_empty() {
+            return Err(UncflowError::ParseError(format!(
+                "Missing metric name in '{s}'"
+            )));
+        }
+        let threshold = threshold
+            .trim()
+            .parse::<f64>()
+            .map_err(|e| UncflowError::ParseError(format!("Invalid threshold in '{s}': {e}")))?;
+
+        Ok(Self {
+            metric: metric.to_string(),
+            threshold,
+        })
+    }
+}
+
+/// One threshold crossing, as served by `/spikes`
+#[derive(Debug, Clone, Serialize)]
+pub struct SpikeEvent {
+    pub timestamp_ms: u64,
+    pub metric: String,
+    pub labels: String,
+    pub value: f64,
+    pub threshold: f64,
+    pub correlation_id: Option<String>,
+}
+
+/// Detects upward threshold crossings and keeps the latest ones in a bounded ring buffer
+pub struct SpikeDetector {
+    rules: Vec<SpikeRule>,
+    capacity: usize,
+    correlation_source: Mutex<Option<CorrelationSource>>,
+    // Series (metric + labels) currently above their threshold
+    above: Mutex<HashSet<String>>,
+    events: Mutex<VecDeque<SpikeEvent>>,
+}
+
+impl SpikeDetector {
+    pub fn new(rules: Vec<SpikeRule>, capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            rules,
+            capacity,
+            correlation_source: Mutex::new(None),
+            above: Mutex::new(HashSet::new()),
+            events: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    pub fn set_correlation_source(&self, source: CorrelationSource) {
+        *self.correlation_source.lock() = Some(source);
+    }
+
+    /// Check one series value, returns true if it just crossed its threshold
+    ///
+    /// Only the transition from below to above is recorded, so a sustained
+    /// spike yields a single event.
+    pub fn observe(&self, metric: &str, labels: &str, value: f64) -> bool {
+        let Some(rule) = self.rules.iter().find(|r| r.metric == metric) else {
+            return false;
+        };
+
+        let series = format!("{metric}{{{labels}}}");
+        let crossed = {
+            let mut above = self.above.lock();
+            if value > rule.threshold {
+                above.insert(series)
+            } else {
+                above.remove(&series);
+                false
+            }
+        };
+
+        if crossed {
+            self.record(SpikeEvent {
+                timestamp_ms: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_millis() as u64)
+                    .unwrap_or(0),
+                metric: metric.to_string(),
+                labels: labels.to_string(),
+                value,
+                threshold: rule.threshold,
+                correlation_id: self.correlation_id(),
+            });
+        }
+        crossed
+    }
+
+    /// Check every gauge of the configured metrics in freshly gathered families
+    pub fn observe_families(&self, families: &[MetricFamily]) {
+        for family in families {
+            if family.get_field_type() != MetricType::GAUGE {
+                continue;
+            }
+            for metric in family.get_metric() {
+                let labels = metric
+                    .get_label()
+                    .iter()
+                    .map(|l| format!("{}=\"{}\"", l.name(), l.value()))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                self.observe(family.name(), &labels, metric.get_gauge().value());
+            }
+        }
+    }
+
+    fn correlation_id(&self) -> Option<String> {
+        let source = self.correlation_source.lock().clone();
+        source.and_then(|source| source())
+    }
+
+    fn record(&self, event: SpikeEvent) {
+        tracing::info!(
+            "Spike: {}{{{}}} = {} > {} (correlation id: {:?})",
+            event.metric,
+            event.labels,
+            event.value,
+            event.threshold,
+            event.correlation_id
+        );
+
+        let mut events = self.events.lock();
+        if events.len() == self.capacity {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    /// Recorded spikes, oldest first
+    pub fn events(&self) -> Vec<SpikeEvent> {
+        self.events.lock().iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(metric: &str, threshold: f64) -> SpikeRule {
+        SpikeRule {
+            metric: metric.to_string(),
+            threshold,
+        }
+    }
+
+    #[test]
+    fn test_parse_rule() {
+        assert_eq!(
+            "MemoryReadBandwidth=2e10".parse::<SpikeRule>().unwrap(),
+            rule("MemoryReadBandwidth", 2e10)
+        );
+        assert!("MemoryReadBandwidth".parse::<SpikeRule>().is_err());
+        assert!("=5".parse::<SpikeRule>().is_err());
+        assert!("IMCReadLatency=fast".parse::<SpikeRule>().is_err());
+    }
+
+    #[test]
+    fn test_threshold_crossing() {
+        let detector = SpikeDetector::new(vec![rule("IMCReadLatency", 100.0)], 8);
+        detector.set_correlation_source(Arc::new(|| Some("trace-1".to_string())));
+
+        let socket0 = "socket=\"0\"";
+        assert!(!detector.observe("IMCReadLatency", socket0, 50.0));
+        assert!(detector.observe("IMCReadLatency", socket0, 150.0));
+        // Still above: no new event
+        assert!(!detector.observe("IMCReadLatency", socket0, 180.0));
+        // Other series cross independently
+        assert!(detector.observe("IMCReadLatency", "socket=\"1\"", 120.0));
+        // Drop below and cross again
+        assert!(!detector.observe("IMCReadLatency", socket0, 90.0));
+        assert!(detector.observe("IMCReadLatency", socket0, 110.0));
+        // Unconfigured metrics are ignored
+        assert!(!detector.observe("IMCWriteLatency", socket0, 1e9));
+
+        let events = detector.events();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].value, 150.0);
+        assert_eq!(events[0].correlation_id.as_deref(), Some("trace-1"));
+        assert_eq!(events[1].labels, "socket=\"1\"");
+    }
+
+    #[test]
+    fn test_ring_buffer_bounds() {
+        let detector = SpikeDetector::new(vec![rule("IRPLatency", 1.0)], 3);
+
+        for i in 0..5 {
+            assert!(detector.observe("IRPLatency", "", 10.0 + i as f64));
+            detector.observe("IRPLatency", "", 0.0);
+        }
+
+        let events = detector.events();
+        assert_eq!(events.len(), 3);
+        // Oldest two were evicted
+        assert_eq!(events[0].value, 12.0);
+        assert_eq!(events[2].value, 14.0);
+    }
+}
@@ -0,0 +1,136 @@
+// Live metric updates for the /stream endpoint
+// After each tick the computed metric families are flattened into one snapshot and
+// broadcast to subscribers; a subscriber that falls more than the channel capacity
+// behind is dropped rather than slowing the collection loop
+
+use prometheus::proto::{MetricFamily, MetricType};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+
+/// Snapshots a subscriber may fall behind before it is dropped
+pub const DEFAULT_STREAM_CAPACITY: usize = 16;
+
+/// One series of a snapshot
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MetricSample {
+    pub name: String,
+    pub labels: BTreeMap<String, String>,
+    pub value: f64,
+}
+
+/// Gauge and counter values computed by one collection
+#[derive(Debug, Clone, Serialize)]
+pub struct MetricSnapshot {
+    pub timestamp_ms: u64,
+    pub samples: Vec<MetricSample>,
+}
+
+impl MetricSnapshot {
+    pub fn from_families(families: &[MetricFamily]) -> Self {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+
+        let samples = families
+            .iter()
+            .flat_map(|family| {
+                family.get_metric().iter().filter_map(|metric| {
+                    let value = match family.get_field_type() {
+                        MetricType::GAUGE => metric.get_gauge().value(),
+                        MetricType::COUNTER => metric.get_counter().value(),
+                        _ => return None,
+                    };
+                    Some(MetricSample {
+                        name: family.name().to_string(),
+                        labels: metric
+                            .get_label()
+                            .iter()
+                            .map(|l| (l.name().to_string(), l.value().to_string()))
+                            .collect(),
+                        value,
+                    })
+                })
+            })
+            .collect();
+
+        Self {
+            timestamp_ms,
+            samples,
+        }
+    }
+}
+
+/// Broadcasts a snapshot per collection to the connected /stream clients
+pub struct MetricStream {
+    sender: broadcast::Sender<Arc<MetricSnapshot>>,
+}
+
+impl MetricStream {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self { sender }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<MetricSnapshot>> {
+        self.sender.subscribe()
+    }
+
+    /// Whether anyone is listening, so idle agents can skip building snapshots
+    pub fn has_subscribers(&self) -> bool {
+        self.sender.receiver_count() > 0
+    }
+
+    pub fn publish(&self, snapshot: MetricSnapshot) {
+        // Only fails when nobody is subscribed
+        let _ = self.sender.send(Arc::new(snapshot));
+    }
+}
+
+impl Default for MetricStream {
+    fn default() -> Self {
+        Self::new(DEFAULT_STREAM_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{GaugeVec, Opts, Registry};
+
+    #[test]
+    fn test_snapshot_from_families() {
+        let registry = Registry::new();
+        let gauge = GaugeVec::new(Opts::new("imc_read_bw", "test"), &["socket"]).unwrap();
+        registry.register(Box::new(gauge.clone())).unwrap();
+        gauge.with_label_values(&["0"]).set(2.5);
+
+        let snapshot = MetricSnapshot::from_families(&registry.gather());
+        assert_eq!(
+            snapshot.samples,
+            vec![MetricSample {
+                name: "imc_read_bw".to_string(),
+                labels: BTreeMap::from([("socket".to_string(), "0".to_string())]),
+                value: 2.5,
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lagging_subscriber_is_told() {
+        let stream = MetricStream::new(2);
+        let mut receiver = stream.subscribe();
+        assert!(stream.has_subscribers());
+
+        for _ in 0..3 {
+            stream.publish(MetricSnapshot::from_families(&[]));
+        }
+        assert!(matches!(
+            receiver.recv().await,
+            Err(broadcast::error::RecvError::Lagged(1))
+        ));
+    }
+}
@@ -0,0 +1,67 @@
+// Lifecycle shared by every exporter the collector drives
+//
+// A subsystem is built from the export configuration, collected once per tick
+// (or scrape), re-baselined by POST /reset, and gathered from its own Registry.
+// MetricCollector only sees `dyn Subsystem`, so adding one is an impl plus an
+// entry in MetricCollector::new.
+
+use futures_util::future::BoxFuture;
+use prometheus::Registry;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::config::ExportConfig;
+use crate::error::Result;
+
+use super::{CollectOutcome, ReadTimeout, SelfMetrics, SubsystemSnapshot};
+
+/// An exporter the collector initializes, collects, resets and gathers
+pub trait Subsystem: Send + Sync {
+    /// Short name used in self-metrics labels and logs, e.g. "cha"
+    fn name(&self) -> &'static str;
+
+    /// Build the subsystem and program its counters
+    fn init(config: ExportConfig) -> Result<Self>
+    where
+        Self: Sized;
+
+    /// Read the counters once and update the gauges
+    fn collect(&self) -> BoxFuture<'_, Result<()>>;
+
+    /// Registry holding the subsystem's gauges
+    fn registry(&self) -> &Registry;
+
+    /// Drop counter baselines so the next collection starts a fresh window
+    fn reset(&self) {}
+
+    /// Read the counters for a measurement session, None if sessions are unsupported
+    fn snapshot(&self) -> Option<Result<SubsystemSnapshot>> {
+        None
+    }
+}
+
+/// Collect `subsystem` on its own task under the read timeout, yielding whether it completed
+///
+/// A collect() error is counted as a failure of the subsystem.
+pub fn spawn_collect(
+    subsystem: Arc<dyn Subsystem>,
+    self_metrics: Arc<SelfMetrics>,
+    read_timeout: Arc<ReadTimeout>,
+) -> tokio::task::JoinHandle<bool> {
+    tokio::spawn(async move {
+        let name = subsystem.name();
+        let start = Instant::now();
+        let outcome = read_timeout
+            .run(name, async move { subsystem.collect().await })
+            .await;
+        match outcome {
+            CollectOutcome::Completed => self_metrics.observe_collection(name, start.elapsed()),
+            CollectOutcome::Failed => self_metrics.observe_collection_failure(name),
+            CollectOutcome::TimedOut | CollectOutcome::Skipped => {
+                self_metrics.observe_read_timeout(name)
+            }
+            CollectOutcome::Panicked => {}
+        }
+        outcome == CollectOutcome::Completed
+    })
+}
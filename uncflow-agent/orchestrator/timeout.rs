@@ -0,0 +1,218 @@
+// Per-subsystem deadline on counter reads (--read-timeout-ms)
+//
+// MSR and PCI config reads are blocking syscalls. A misbehaving BMC or a
+// virtualized MSR can make one take far longer than the collection interval, and
+// since every subsystem is awaited each tick, one stuck read would freeze the
+// whole loop. Each collect() therefore runs on the blocking pool and is abandoned
+// when it misses the deadline; its metrics keep their previous values.
+// A collect that returns an error in time is reported as failed rather than
+// completed, so persistent failures show up in the self-monitoring metrics. A thread
+// stuck in the kernel cannot be cancelled, so the subsystem is skipped until the
+// abandoned collect returns instead of piling up more blocked threads behind it.
+// HTTP handlers that read counters, e.g. /reset, go through the same deadline.
+
+use parking_lot::Mutex;
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+use crate::error::{Result, UncflowError};
+
+/// Read timeout used when --read-timeout-ms is not given
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How a deadline-bounded collect ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollectOutcome {
+    Completed,
+    /// Returned an error before the deadline
+    Failed,
+    /// Missed the deadline and was abandoned
+    TimedOut,
+    /// An earlier collect of the subsystem is still stuck
+    Skipped,
+    Panicked,
+}
+
+/// Runs collect() calls on the blocking pool, bounded by a deadline
+#[derive(Debug)]
+pub struct ReadTimeout {
+    timeout: Duration,
+    // Subsystems whose abandoned collect is still running
+    stuck: Arc<Mutex<HashSet<&'static str>>>,
+}
+
+impl ReadTimeout {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            stuck: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    /// Run `collect` for `subsystem`, giving up on it after the timeout
+    pub async fn run<F>(&self, subsystem: &'static str, collect: F) -> CollectOutcome
+    where
+        F: Future<Output = Result<()>> + Send + 'static,
+    {
+        let handle = tokio::runtime::Handle::current();
+        let Some(task) = self.spawn(subsystem, move || handle.block_on(collect)) else {
+            tracing::warn!(
+                "Skipping {} collection, an earlier one is still blocked on a read",
+                subsystem
+            );
+            return CollectOutcome::Skipped;
+        };
+
+        match tokio::time::timeout(self.timeout, task).await {
+            Ok(Ok(Ok(()))) => CollectOutcome::Completed,
+            Ok(Ok(Err(e))) => {
+                tracing::warn!("{} collection failed: {}", subsystem, e);
+                CollectOutcome::Failed
+            }
+            Ok(Err(e)) => {
+                self.stuck.lock().remove(subsystem);
+                tracing::error!("{} collection failed: {}", subsystem, e);
+                CollectOutcome::Panicked
+            }
+            Err(_) => {
+                tracing::warn!(
+                    "{} collection exceeded the {:?} read timeout, skipping it this interval",
+                    subsystem,
+                    self.timeout
+                );
+                CollectOutcome::TimedOut
+            }
+        }
+    }
+
+    /// Run blocking `task` on behalf of `name` outside the collection loop, e.g. an
+    /// HTTP handler, failing when it misses the deadline or an earlier one of
+    /// `name` is still blocked
+    pub async fn call<T, F>(&self, name: &'static str, task: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let Some(task) = self.spawn(name, task) else {
+            return Err(UncflowError::HardwareError(format!(
+                "{name} is still blocked on an earlier read"
+            )));
+        };
+
+        match tokio::time::timeout(self.timeout, task).await {
+            Ok(Ok(value)) => Ok(value),
+            Ok(Err(e)) => {
+                self.stuck.lock().remove(name);
+                Err(UncflowError::HardwareError(format!("{name} failed: {e}")))
+            }
+            Err(_) => Err(UncflowError::HardwareError(format!(
+                "{name} exceeded the {:?} read timeout",
+                self.timeout
+            ))),
+        }
+    }
+
+    // Start `task` on the blocking pool, None while an earlier task of `name` runs;
+    // `name` stays marked until the task returns, whether or not it made the deadline
+    fn spawn<T, F>(&self, name: &'static str, task: F) -> Option<JoinHandle<T>>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        if !self.stuck.lock().insert(name) {
+            return None;
+        }
+        let stuck = Arc::clone(&self.stuck);
+        Some(tokio::task::spawn_blocking(move || {
+            let result = task();
+            stuck.lock().remove(name);
+            result
+        }))
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::msr::MsrAccess;
+    use std::time::Instant;
+
+    /// MSR backend whose reads block like a wedged BMC or virtualized MSR
+    struct SlowMsr {
+        delay: Duration,
+    }
+
+    impl MsrAccess for SlowMsr {
+        fn read(&self, _cpu: u32, _addr: u64) -> Result<u64> {
+            std::thread::sleep(self.delay);
+            Ok(42)
+        }
+
+        fn write(&self, _cpu: u32, _addr: u64, _value: u64) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stuck_read_is_abandoned() {
+        let msr: &'static SlowMsr = Box::leak(Box::new(SlowMsr {
+            delay: Duration::from_millis(300),
+        }));
+        let timeout = ReadTimeout::new(Duration::from_millis(20));
+        let read = move || async move { msr.read(0, 0x10).map(|_| ()) };
+
+        let start = Instant::now();
+        assert_eq!(timeout.run("imc", read()).await, CollectOutcome::TimedOut);
+        assert!(start.elapsed() < Duration::from_millis(200));
+
+        // Not retried while the first read is still blocked, other subsystems unaffected
+        assert_eq!(timeout.run("imc", read()).await, CollectOutcome::Skipped);
+        assert_eq!(
+            timeout.run("cha", async { Ok(()) }).await,
+            CollectOutcome::Completed
+        );
+
+        // Collected again once the stuck read returns
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert_eq!(
+            timeout.run("imc", async { Ok(()) }).await,
+            CollectOutcome::Completed
+        );
+    }
+
+    #[tokio::test]
+    async fn test_collect_error_is_a_failure() {
+        let timeout = ReadTimeout::new(Duration::from_millis(100));
+        let failing = async { Err(UncflowError::MsrError("read refused".to_string())) };
+        assert_eq!(timeout.run("upi", failing).await, CollectOutcome::Failed);
+
+        // A failed collect is not stuck, the next one runs
+        assert_eq!(
+            timeout.run("upi", async { Ok(()) }).await,
+            CollectOutcome::Completed
+        );
+    }
+
+    #[tokio::test]
+    async fn test_call_returns_value_or_times_out() {
+        let timeout = ReadTimeout::new(Duration::from_millis(20));
+        assert_eq!(timeout.call("reset", || 7).await.unwrap(), 7);
+
+        let slow = || std::thread::sleep(Duration::from_millis(300));
+        assert!(timeout.call("reset", slow).await.is_err());
+        // Refused at once while the first call is still blocked
+        let start = Instant::now();
+        assert!(timeout.call("reset", || ()).await.is_err());
+        assert!(start.elapsed() < Duration::from_millis(20));
+
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert!(timeout.call("reset", || ()).await.is_ok());
+    }
+}
@@ -0,0 +1,126 @@
+// Influx line protocol, served at /metrics.influx for InfluxDB/Telegraf
+//
+// Each subsystem is a measurement, each metric family a field and each label a
+// tag, one line per series:
+//   imc,instance=server,socket=0 IMCReadBandwidth=1234.5 1700000000000000000
+// Only gauges and counters are written; Influx has no use for histogram buckets
+// and rejects non-finite values.
+
+use prometheus::proto::{MetricFamily, MetricType};
+use std::fmt::Write;
+
+/// Append one line per gauge/counter series of `families` to `out`
+pub fn write_families(
+    out: &mut String,
+    measurement: &str,
+    families: &[MetricFamily],
+    timestamp_ns: u128,
+) {
+    for family in families {
+        for metric in family.get_metric() {
+            let value = match family.get_field_type() {
+                MetricType::GAUGE => metric.get_gauge().value(),
+                MetricType::COUNTER => metric.get_counter().value(),
+                _ => continue,
+            };
+            if !value.is_finite() {
+                continue;
+            }
+
+            out.push_str(&escape(measurement, &[',', ' ']));
+            // Influx wants tags sorted by key
+            let mut labels: Vec<_> = metric.get_label().iter().collect();
+            labels.sort_by(|a, b| a.name().cmp(b.name()));
+            for label in labels {
+                if label.value().is_empty() {
+                    continue;
+                }
+                let _ = write!(
+                    out,
+                    ",{}={}",
+                    escape(label.name(), &[',', '=', ' ']),
+                    escape(label.value(), &[',', '=', ' '])
+                );
+            }
+            let _ = writeln!(
+                out,
+                " {}={} {}",
+                escape(family.name(), &[',', '=', ' ']),
+                value,
+                timestamp_ns
+            );
+        }
+    }
+}
+
+// Backslash-escape the characters that delimit this part of the line
+fn escape(s: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{Gauge, GaugeVec, Histogram, HistogramOpts, Opts, Registry};
+
+    #[test]
+    fn test_labeled_gauge_line() {
+        let registry = Registry::new();
+        let gauge = GaugeVec::new(
+            Opts::new("IMCChannelReadBandwidth", "test").const_label("instance", "server"),
+            &["socket", "channel"],
+        )
+        .unwrap();
+        registry.register(Box::new(gauge.clone())).unwrap();
+        gauge.with_label_values(&["0", "3"]).set(1234.5);
+
+        let mut out = String::new();
+        write_families(
+            &mut out,
+            "imc",
+            &registry.gather(),
+            1_700_000_000_000_000_000,
+        );
+        assert_eq!(
+            out,
+            "imc,channel=3,instance=server,socket=0 IMCChannelReadBandwidth=1234.5 1700000000000000000\n"
+        );
+    }
+
+    #[test]
+    fn test_escaping_and_skipped_values() {
+        let registry = Registry::new();
+        let core = Gauge::with_opts(
+            Opts::new("core_ipc", "test")
+                .const_label("core_label", "web server,1")
+                .const_label("core", "0"),
+        )
+        .unwrap();
+        core.set(1.5);
+        let nan = Gauge::with_opts(Opts::new("undefined_ratio", "test")).unwrap();
+        nan.set(f64::NAN);
+        let histogram = Histogram::with_opts(HistogramOpts::new("latency", "test")).unwrap();
+        histogram.observe(1.0);
+        for collector in [
+            Box::new(core) as Box<dyn prometheus::core::Collector>,
+            Box::new(nan),
+            Box::new(histogram),
+        ] {
+            registry.register(collector).unwrap();
+        }
+
+        let mut out = String::new();
+        write_families(&mut out, "core metrics", &registry.gather(), 1);
+        assert_eq!(
+            out,
+            "core\\ metrics,core=0,core_label=web\\ server\\,1 core_ipc=1.5 1\n"
+        );
+    }
+}
@@ -0,0 +1,5 @@
+// Serializations of the gathered metrics other than the Prometheus text format
+
+pub mod influx;
+pub mod openmetrics;
+pub mod statsd;
@@ -0,0 +1,247 @@
+// OpenMetrics text format, served at /metrics to scrapers that ask for it
+//
+// Differs from the Prometheus text format in what consumers can rely on:
+// - `# UNIT` metadata for every family with a unit (see metrics::unit). OpenMetrics
+//   requires the unit as a name suffix, so a family whose name lacks it is exposed
+//   with `_<unit>` appended, as the official client libraries do:
+//   PackageEnergy becomes PackageEnergy_joules.
+// - Counter families are named without `_total`, their samples always with it.
+// - Untyped families are `unknown`, and the exposition ends with `# EOF`.
+
+use prometheus::proto::{MetricFamily, MetricType};
+use std::fmt::Write;
+
+use crate::metrics::unit::unit_of;
+
+/// Content type of the OpenMetrics text exposition
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Whether an Accept header prefers OpenMetrics over the Prometheus text format
+pub fn accepted(accept: &str) -> bool {
+    accept.split(',').any(|media_range| {
+        media_range
+            .trim()
+            .starts_with("application/openmetrics-text")
+    })
+}
+
+/// Encode `families` as one OpenMetrics exposition, including the final `# EOF`
+pub fn encode(families: &[MetricFamily]) -> String {
+    let mut out = String::new();
+    for family in families {
+        write_family(&mut out, family);
+    }
+    out.push_str("# EOF\n");
+    out
+}
+
+fn write_family(out: &mut String, family: &MetricFamily) {
+    let field_type = family.get_field_type();
+    let mut name = family.name().to_string();
+    if field_type == MetricType::COUNTER {
+        if let Some(base) = name.strip_suffix("_total") {
+            name = base.to_string();
+        }
+    }
+    let unit = unit_of(family.name());
+    if let Some(unit) = unit {
+        let suffix = format!("_{}", unit.name());
+        if !name.ends_with(&suffix) {
+            name.push_str(&suffix);
+        }
+    }
+
+    let type_name = match field_type {
+        MetricType::COUNTER => "counter",
+        MetricType::GAUGE => "gauge",
+        MetricType::SUMMARY => "summary",
+        MetricType::HISTOGRAM => "histogram",
+        MetricType::UNTYPED => "unknown",
+    };
+    let _ = writeln!(out, "# TYPE {name} {type_name}");
+    if let Some(unit) = unit {
+        let _ = writeln!(out, "# UNIT {name} {}", unit.name());
+    }
+    if !family.help().is_empty() {
+        let _ = writeln!(out, "# HELP {name} {}", escape(family.help()));
+    }
+
+    for metric in family.get_metric() {
+        let labels: Vec<(&str, String)> = metric
+            .get_label()
+            .iter()
+            .map(|label| (label.name(), label.value().to_string()))
+            .collect();
+        let timestamp = metric.timestamp_ms();
+        let mut sample = |suffix: &str, extra: Option<(&str, String)>, value: String| {
+            let mut sample_labels = labels.clone();
+            sample_labels.extend(extra);
+            write_sample(
+                out,
+                &format!("{name}{suffix}"),
+                &sample_labels,
+                &value,
+                timestamp,
+            );
+        };
+
+        match field_type {
+            MetricType::COUNTER => sample("_total", None, number(metric.get_counter().value())),
+            MetricType::GAUGE => sample("", None, number(metric.get_gauge().value())),
+            MetricType::UNTYPED => sample("", None, number(metric.untyped.value())),
+            MetricType::HISTOGRAM => {
+                let histogram = metric.get_histogram();
+                let mut saw_inf = false;
+                for bucket in histogram.get_bucket() {
+                    saw_inf |= bucket.upper_bound() == f64::INFINITY;
+                    sample(
+                        "_bucket",
+                        Some(("le", number(bucket.upper_bound()))),
+                        bucket.cumulative_count().to_string(),
+                    );
+                }
+                // OpenMetrics requires the +Inf bucket, Prometheus leaves it implicit
+                if !saw_inf {
+                    sample(
+                        "_bucket",
+                        Some(("le", number(f64::INFINITY))),
+                        histogram.get_sample_count().to_string(),
+                    );
+                }
+                sample("_sum", None, number(histogram.get_sample_sum()));
+                sample("_count", None, histogram.get_sample_count().to_string());
+            }
+            MetricType::SUMMARY => {
+                let summary = metric.get_summary();
+                for quantile in summary.get_quantile() {
+                    sample(
+                        "",
+                        Some(("quantile", number(quantile.quantile()))),
+                        number(quantile.value()),
+                    );
+                }
+                sample("_sum", None, number(summary.sample_sum()));
+                sample("_count", None, summary.sample_count().to_string());
+            }
+        }
+    }
+}
+
+fn write_sample(
+    out: &mut String,
+    name: &str,
+    labels: &[(&str, String)],
+    value: &str,
+    timestamp_ms: i64,
+) {
+    out.push_str(name);
+    if !labels.is_empty() {
+        out.push('{');
+        for (i, (label, label_value)) in labels.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let _ = write!(out, "{label}=\"{}\"", escape(label_value));
+        }
+        out.push('}');
+    }
+    let _ = write!(out, " {value}");
+    // OpenMetrics timestamps are in seconds
+    if timestamp_ms != 0 {
+        let _ = write!(out, " {}", timestamp_ms as f64 / 1000.0);
+    }
+    out.push('\n');
+}
+
+fn number(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value == f64::INFINITY {
+        "+Inf".to_string()
+    } else if value == f64::NEG_INFINITY {
+        "-Inf".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+// Escape backslashes, double quotes and newlines in label values and help text
+fn escape(s: &str) -> String {
+    s.replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{Counter, GaugeVec, Histogram, HistogramOpts, Opts, Registry};
+
+    #[test]
+    fn test_unit_lines_for_energy_and_bandwidth() {
+        let registry = Registry::new();
+        let energy = GaugeVec::new(Opts::new("PackageEnergy", "energy"), &["socket"]).unwrap();
+        energy.with_label_values(&["0"]).set(12.5);
+        registry.register(Box::new(energy)).unwrap();
+        let bandwidth =
+            GaugeVec::new(Opts::new("MemoryReadBandwidth", "read"), &["socket"]).unwrap();
+        bandwidth.with_label_values(&["1"]).set(2e9);
+        registry.register(Box::new(bandwidth)).unwrap();
+        let total = Counter::new("rapl_package_energy_joules_total", "total").unwrap();
+        total.inc_by(3.0);
+        registry.register(Box::new(total)).unwrap();
+
+        let out = encode(&registry.gather());
+        for line in [
+            "# TYPE MemoryReadBandwidth_bytes_per_second gauge",
+            "# UNIT MemoryReadBandwidth_bytes_per_second bytes_per_second",
+            "MemoryReadBandwidth_bytes_per_second{socket=\"1\"} 2000000000",
+            "# TYPE PackageEnergy_joules gauge",
+            "# UNIT PackageEnergy_joules joules",
+            "PackageEnergy_joules{socket=\"0\"} 12.5",
+            // Already carries its unit; counters drop _total from the family only
+            "# TYPE rapl_package_energy_joules counter",
+            "# UNIT rapl_package_energy_joules joules",
+            "rapl_package_energy_joules_total 3",
+        ] {
+            assert!(out.lines().any(|l| l == line), "missing {line:?} in\n{out}");
+        }
+        assert!(out.ends_with("# EOF\n"));
+    }
+
+    #[test]
+    fn test_histogram_and_unitless_counter() {
+        let registry = Registry::new();
+        let histogram = Histogram::with_opts(
+            HistogramOpts::new("uncflow_collection_duration_seconds", "duration")
+                .buckets(vec![0.1]),
+        )
+        .unwrap();
+        histogram.observe(0.05);
+        registry.register(Box::new(histogram)).unwrap();
+        let reads = Counter::new("uncflow_reads", "reads").unwrap();
+        registry.register(Box::new(reads)).unwrap();
+
+        let out = encode(&registry.gather());
+        for line in [
+            "# UNIT uncflow_collection_duration_seconds seconds",
+            "uncflow_collection_duration_seconds_bucket{le=\"0.1\"} 1",
+            "uncflow_collection_duration_seconds_bucket{le=\"+Inf\"} 1",
+            "uncflow_collection_duration_seconds_count 1",
+            "# TYPE uncflow_reads counter",
+            "uncflow_reads_total 0",
+        ] {
+            assert!(out.lines().any(|l| l == line), "missing {line:?} in\n{out}");
+        }
+        assert!(!out.contains("# UNIT uncflow_reads"));
+    }
+
+    #[test]
+    fn test_accept_negotiation() {
+        assert!(accepted(
+            "application/openmetrics-text;version=1.0.0,text/plain;version=0.0.4;q=0.5"
+        ));
+        assert!(!accepted("text/plain;version=0.0.4"));
+        assert!(!accepted("*/*"));
+    }
+}
@@ -0,0 +1,167 @@
+// statsd/DogStatsD push sink, enabled with --statsd
+//
+// After each collection every gauge and counter series is sent as a statsd gauge,
+// its labels as DogStatsD tags:
+//   IMCReadBandwidth:1234.5|g|#instance:server,socket:0
+// Lines are batched into datagrams small enough to avoid IP fragmentation. Send
+// errors are logged (rate-limited) and dropped; statsd is fire-and-forget.
+
+use prometheus::proto::{MetricFamily, MetricType};
+use std::fmt::Write;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+
+use crate::common::log_limiter;
+use crate::error::{Result, UncflowError};
+
+/// Largest datagram sent, below a typical 1500-byte MTU
+pub const MAX_DATAGRAM: usize = 1432;
+
+/// Append one statsd gauge line per gauge/counter series of `families` to `out`
+pub fn write_families(out: &mut Vec<String>, families: &[MetricFamily]) {
+    for family in families {
+        for metric in family.get_metric() {
+            let value = match family.get_field_type() {
+                MetricType::GAUGE => metric.get_gauge().value(),
+                MetricType::COUNTER => metric.get_counter().value(),
+                _ => continue,
+            };
+            if !value.is_finite() {
+                continue;
+            }
+
+            let mut line = format!("{}:{}|g", sanitize(family.name()), value);
+            let mut separator = "|#";
+            for label in metric.get_label() {
+                if label.value().is_empty() {
+                    continue;
+                }
+                let _ = write!(
+                    line,
+                    "{}{}:{}",
+                    separator,
+                    sanitize(label.name()),
+                    sanitize(label.value())
+                );
+                separator = ",";
+            }
+            out.push(line);
+        }
+    }
+}
+
+// Replace the characters that delimit a statsd line
+fn sanitize(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            ':' | '|' | '@' | '#' | ',' | '\n' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// UDP sink pushing the gathered metrics to a statsd server
+pub struct StatsdSink {
+    socket: UdpSocket,
+}
+
+impl StatsdSink {
+    /// Sink sending to `addr` (host:port), over IPv4 or IPv6 as it resolves
+    pub fn connect(addr: &str) -> Result<Self> {
+        let unreachable = |e: std::io::Error| {
+            UncflowError::ConfigError(format!("Cannot reach statsd server {addr}: {e}"))
+        };
+        let mut failure = None;
+        for target in addr.to_socket_addrs().map_err(unreachable)? {
+            // Bound to the unspecified address of the target's family
+            let local = match target {
+                SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+                SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+            };
+            match UdpSocket::bind(local).and_then(|socket| socket.connect(target).map(|()| socket))
+            {
+                Ok(socket) => {
+                    socket.set_nonblocking(true)?;
+                    return Ok(Self { socket });
+                }
+                Err(e) => failure = Some(e),
+            }
+        }
+        Err(unreachable(failure.unwrap_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::NotFound, "no address resolved")
+        })))
+    }
+
+    /// Send every series of `families`, logging instead of failing on send errors
+    pub fn send(&self, families: &[MetricFamily]) {
+        let mut lines = Vec::new();
+        write_families(&mut lines, families);
+
+        let mut datagram = String::with_capacity(MAX_DATAGRAM);
+        for line in lines {
+            if !datagram.is_empty() && datagram.len() + 1 + line.len() > MAX_DATAGRAM {
+                self.send_datagram(&datagram);
+                datagram.clear();
+            }
+            if !datagram.is_empty() {
+                datagram.push('\n');
+            }
+            datagram.push_str(&line);
+        }
+        if !datagram.is_empty() {
+            self.send_datagram(&datagram);
+        }
+    }
+
+    fn send_datagram(&self, datagram: &str) {
+        match self.socket.send(datagram.as_bytes()) {
+            Ok(_) => log_limiter().clear("statsd"),
+            Err(e) => {
+                if log_limiter().allow("statsd", &e.kind().to_string()) {
+                    tracing::warn!("Failed to send metrics to statsd: {}", e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{GaugeVec, Opts, Registry};
+
+    #[test]
+    fn test_labeled_gauge_line() {
+        let registry = Registry::new();
+        let gauge = GaugeVec::new(
+            Opts::new("IMCChannelReadBandwidth", "test").const_label("instance", "server"),
+            &["socket", "channel"],
+        )
+        .unwrap();
+        registry.register(Box::new(gauge.clone())).unwrap();
+        gauge.with_label_values(&["0", "3"]).set(1234.5);
+        gauge.with_label_values(&["1", "a,b"]).set(f64::NAN);
+
+        let mut lines = Vec::new();
+        write_families(&mut lines, &registry.gather());
+        assert_eq!(
+            lines,
+            ["IMCChannelReadBandwidth:1234.5|g|#channel:3,instance:server,socket:0"]
+        );
+    }
+
+    #[test]
+    fn test_connects_to_either_family() {
+        for server in ["127.0.0.1:0", "[::1]:0"] {
+            // Hosts without IPv6 loopback only check IPv4
+            let Ok(server) = UdpSocket::bind(server) else {
+                continue;
+            };
+            let sink = StatsdSink::connect(&server.local_addr().unwrap().to_string()).unwrap();
+            sink.send_datagram("up:1|g");
+
+            let mut buf = [0; 64];
+            let len = server.recv(&mut buf).unwrap();
+            assert_eq!(&buf[..len], b"up:1|g");
+        }
+    }
+}
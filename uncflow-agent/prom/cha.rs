@@ -1,39 +1,318 @@
 // CHA Comprehensive Metrics Exporter
 // Exports all 142 comprehensive CHA metrics
-
-use prometheus::{Gauge, Registry};
-use std::collections::HashMap;
+//
+// Event groups are rotated, so most metrics carry the value of the last time their
+// groups were counted, up to a full rotation cycle ago, while Prometheus stamps
+// every sample with the scrape time. With --cha-metric-age, CHAMetricAgeSeconds
+// reports how old each metric's data is. Per-sample exposition timestamps would
+// express the same thing, but Prometheus treats samples older than the staleness
+// window (5m) as missing and rejects out-of-order ones, and recording rules lose
+// them, so an explicit age gauge is exported instead. It doubles the CHA series.
+//
+// CHA control MSRs are locked by some BIOSes and most hypervisors. Programming is
+// verified by reading every write back when a socket's monitor initializes, and a
+// socket that fails has no monitor. Its metrics are omitted, or exported as NaN
+// with --cha-unavailable nan, rather than left at a flat 0 that reads as an idle
+// uncore. CHAUp is 1 for sockets whose CHA counters are programmed and 0 otherwise.
+
+use futures_util::future::BoxFuture;
+use prometheus::{Gauge, GaugeVec, Opts, Registry};
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::task::JoinHandle;
 
+use crate::common::msr::{self, MsrAccess};
 use crate::config::ExportConfig;
-use crate::counters::cha::{ChaMonitor, LLCLookupType, LLCState, TransactionType};
-use crate::error::Result;
-use crate::metrics::cha::{ChaMetric, MetricCalculator, SFEvictionType, VictimType};
+use crate::counters::cha::{ChaBoxDelta, ChaGroupDeltas, ChaMonitor};
+use crate::error::{Result, UncflowError};
+use crate::metrics::cha::{ChaMetric, MetricCalculator, RawEventData};
+use crate::metrics::kind::{self, MetricKind};
+use crate::orchestrator::Subsystem;
+use crate::prom::raw::RawCounterGauges;
+use crate::prom::sockets::collect_sockets;
+
+/// What the CHA metrics of a socket whose counters cannot be programmed export
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChaUnavailable {
+    /// Register no CHA metrics for the socket
+    #[default]
+    Omit,
+    /// Register them with a NaN value
+    Nan,
+}
+
+impl FromStr for ChaUnavailable {
+    type Err = UncflowError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "omit" => Ok(Self::Omit),
+            "nan" => Ok(Self::Nan),
+            _ => Err(UncflowError::ParseError(format!(
+                "Unknown CHA unavailable policy '{s}', expected 'omit' or 'nan'"
+            ))),
+        }
+    }
+}
+
+/// Per-box raw counter gauges, labeled by socket, CHA box and event group
+struct ChaBoxGauges {
+    occupancy: GaugeVec,
+    insert: GaugeVec,
+    clockticks: GaugeVec,
+}
+
+impl ChaBoxGauges {
+    const LABELS: [&'static str; 3] = ["socket", "cha_box", "event"];
+
+    fn new(registry: &Registry, instance_label: &str) -> Result<Self> {
+        let gauge_vec = |name: &str, help: &str| -> Result<GaugeVec> {
+            let gauge = GaugeVec::new(
+                Opts::new(name, help).const_label("instance", instance_label),
+                &Self::LABELS,
+            )?;
+            registry.register(Box::new(gauge.clone()))?;
+            Ok(gauge)
+        };
+
+        Ok(Self {
+            occupancy: gauge_vec(
+                "CHABoxOccupancy",
+                "Per-CHA-box TOR occupancy delta for the last measured event group",
+            )?,
+            insert: gauge_vec(
+                "CHABoxInserts",
+                "Per-CHA-box TOR inserts delta for the last measured event group",
+            )?,
+            clockticks: gauge_vec(
+                "CHABoxClockticks",
+                "Per-CHA-box clockticks delta for the last measured event group",
+            )?,
+        })
+    }
+
+    fn set(&self, socket_id: i32, deltas: &ChaGroupDeltas) {
+        let socket = socket_id.to_string();
+        let live = deltas.live;
+        for (cha_box, delta) in deltas.box_ids.iter().zip(&deltas.boxes) {
+            let cha_box = cha_box.to_string();
+            let labels = [socket.as_str(), cha_box.as_str(), deltas.group.as_str()];
+            for (gauge, alive, value) in [
+                (&self.occupancy, live.occupancy, delta.occupancy),
+                (&self.insert, live.insert, delta.insert),
+                (&self.clockticks, live.clockticks, delta.clockticks),
+            ] {
+                if alive {
+                    gauge.with_label_values(&labels).set(value as f64);
+                } else {
+                    // Drop series a dead counter left behind from an earlier rotation
+                    let _ = gauge.remove_label_values(&labels);
+                }
+            }
+        }
+    }
+}
+
+/// Per-NUMA-node sums of the per-box counters, labeled by socket, node and event group
+struct ChaNodeGauges {
+    occupancy: GaugeVec,
+    insert: GaugeVec,
+    clockticks: GaugeVec,
+}
+
+impl ChaNodeGauges {
+    const LABELS: [&'static str; 3] = ["socket", "numa_node", "event"];
+
+    fn new(registry: &Registry, instance_label: &str) -> Result<Self> {
+        let gauge_vec = |name: &str, help: &str| -> Result<GaugeVec> {
+            let gauge = GaugeVec::new(
+                Opts::new(name, help).const_label("instance", instance_label),
+                &Self::LABELS,
+            )?;
+            registry.register(Box::new(gauge.clone()))?;
+            Ok(gauge)
+        };
+
+        Ok(Self {
+            occupancy: gauge_vec(
+                "CHANodeOccupancy",
+                "TOR occupancy delta of the CHA boxes attributed to a NUMA node",
+            )?,
+            insert: gauge_vec(
+                "CHANodeInserts",
+                "TOR inserts delta of the CHA boxes attributed to a NUMA node",
+            )?,
+            clockticks: gauge_vec(
+                "CHANodeClockticks",
+                "Clockticks delta of the CHA boxes attributed to a NUMA node",
+            )?,
+        })
+    }
+
+    /// Sampled boxes are scaled up to all boxes of their node
+    fn set(
+        &self,
+        config: &ExportConfig,
+        socket_id: i32,
+        cha_count: usize,
+        deltas: &ChaGroupDeltas,
+    ) {
+        let node_of = |cha_box: usize| config.numa_node_of_unit(socket_id, cha_box, cha_count);
+        let mut nodes: BTreeMap<i32, (ChaBoxDelta, usize)> = BTreeMap::new();
+        for (&cha_box, delta) in deltas.box_ids.iter().zip(&deltas.boxes) {
+            let Some(node) = node_of(cha_box) else {
+                continue;
+            };
+            let (sum, sampled) = nodes.entry(node).or_default();
+            sum.occupancy += delta.occupancy;
+            sum.insert += delta.insert;
+            sum.clockticks += delta.clockticks;
+            *sampled += 1;
+        }
+
+        let socket = socket_id.to_string();
+        let live = deltas.live;
+        for (node, (sum, sampled)) in nodes {
+            let node_boxes = (0..cha_count).filter(|&b| node_of(b) == Some(node)).count();
+            let scale = |value: u64| value as f64 * node_boxes as f64 / sampled as f64;
+            let node = node.to_string();
+            let labels = [socket.as_str(), node.as_str(), deltas.group.as_str()];
+            for (gauge, alive, value) in [
+                (&self.occupancy, live.occupancy, sum.occupancy),
+                (&self.insert, live.insert, sum.insert),
+                (&self.clockticks, live.clockticks, sum.clockticks),
+            ] {
+                if alive {
+                    gauge.with_label_values(&labels).set(scale(value));
+                } else {
+                    let _ = gauge.remove_label_values(&labels);
+                }
+            }
+        }
+    }
+}
+
+/// Export the raw per-box deltas aggregated into one socket's event data
+fn set_raw_deltas(raw: &RawCounterGauges, socket_id: i32, groups: &[ChaGroupDeltas]) {
+    let socket = socket_id.to_string();
+    for deltas in groups {
+        let live = deltas.live;
+        for (cha_box, delta) in deltas.box_ids.iter().zip(&deltas.boxes) {
+            let cha_box = cha_box.to_string();
+            let labels = [socket.as_str(), cha_box.as_str(), deltas.group.as_str()];
+            for (counter, alive, value) in [
+                ("occupancy", live.occupancy, delta.occupancy),
+                ("insert", live.insert, delta.insert),
+                ("clockticks", live.clockticks, delta.clockticks),
+            ] {
+                if alive {
+                    raw.set(&labels, counter, value);
+                } else {
+                    raw.remove(&labels, counter);
+                }
+            }
+        }
+    }
+}
+
+/// Set the data age of every metric of one socket that has been measured
+fn set_ages(age: &GaugeVec, socket_id: i32, calculator: &MetricCalculator, now: Instant) {
+    let socket = socket_id.to_string();
+    for metric in ChaMetric::all() {
+        if let Some(measured_at) = calculator.measured_at(metric.source()) {
+            age.with_label_values(&[socket.as_str(), metric.name().as_str()])
+                .set(now.saturating_duration_since(measured_at).as_secs_f64());
+        }
+    }
+}
+
+/// Exported value of `metric`, counts per second of their window if `count_rates`
+fn exported_value(
+    metric: ChaMetric,
+    calculator: &MetricCalculator,
+    count_rates: bool,
+) -> Option<f64> {
+    let source = metric.source();
+    let value = calculator.evaluate(source)?;
+    if count_rates && metric.kind() == MetricKind::Count {
+        // Never-counted groups evaluate to 0 and have no window, keep that 0
+        return match calculator.window(source) {
+            Some(window) => kind::per_second(value, window),
+            None => Some(value),
+        };
+    }
+    Some(value)
+}
+
+/// Set every gauge of one socket from a measurement
+///
+/// Metrics the calculator cannot derive this time keep their previous value.
+fn export_socket(
+    socket_gauges: &HashMap<ChaMetric, HashMap<i32, Gauge>>,
+    socket_id: i32,
+    calculator: &MetricCalculator,
+    count_rates: bool,
+) {
+    for (metric, gauges) in socket_gauges {
+        if let Some(gauge) = gauges.get(&socket_id) {
+            if let Some(value) = exported_value(*metric, calculator, count_rates) {
+                gauge.set(value);
+            }
+        }
+    }
+}
+
+// What one socket's monitor measured in a collect, taken out of the monitor lock
+struct ChaSample {
+    event_data: HashMap<String, RawEventData>,
+    box_deltas: Vec<ChaGroupDeltas>,
+    cha_count: usize,
+}
 
 pub struct ChaMetricExporter {
     config: ExportConfig,
     registry: Arc<Registry>,
     monitor: Arc<parking_lot::Mutex<HashMap<i32, ChaMonitor>>>,
     socket_gauges: HashMap<ChaMetric, HashMap<i32, Gauge>>,
+    box_gauges: Option<ChaBoxGauges>,
+    node_gauges: Option<ChaNodeGauges>,
+    raw_gauges: Option<RawCounterGauges>,
+    age_gauges: Option<GaugeVec>,
 }
 
 impl ChaMetricExporter {
     pub fn new(config: ExportConfig) -> Result<Self> {
+        Self::with_msr(config, msr::Msr::instance())
+    }
+
+    /// Create an exporter whose monitors program CHA boxes through `msr`
+    pub fn with_msr(config: ExportConfig, msr: &'static dyn MsrAccess) -> Result<Self> {
         let registry = Arc::new(Registry::new());
 
         let mut monitors = HashMap::new();
         for &socket in &config.sockets {
-            match ChaMonitor::new(socket) {
-                Ok(mut monitor) => {
-                    monitor.initialize()?;
-                    monitors.insert(socket, monitor);
-                    tracing::info!(
-                        "Initialized comprehensive CHA monitor for socket {}",
-                        socket
-                    );
-                }
+            let core = config.uncore_core(socket, ChaMonitor::default_core(socket));
+            let monitor = ChaMonitor::with_core(socket, core, msr)
+                .map(|monitor| monitor.with_sample_boxes(config.cha_sample_boxes));
+            match monitor {
+                Ok(mut monitor) => match monitor.initialize() {
+                    Ok(()) => {
+                        monitors.insert(socket, monitor);
+                        tracing::info!(
+                            "Initialized comprehensive CHA monitor for socket {}",
+                            socket
+                        );
+                    }
+                    Err(e) => {
+                        tracing::warn!(
+                            "CHA counters not programmable on socket {}, skipping: {}",
+                            socket,
+                            e
+                        );
+                    }
+                },
                 Err(e) => {
                     tracing::warn!("Failed to initialize CHA for socket {}: {}", socket, e);
                 }
@@ -47,6 +326,10 @@ impl ChaMetricExporter {
             registry: Arc::clone(&registry),
             monitor,
             socket_gauges: HashMap::new(),
+            box_gauges: None,
+            node_gauges: None,
+            raw_gauges: None,
+            age_gauges: None,
         };
 
         exporter.register_metrics()?;
@@ -58,6 +341,26 @@ impl ChaMetricExporter {
         let instance_label =
             std::env::var("INSTANCE_LABEL").unwrap_or_else(|_| "server".to_string());
 
+        let up = GaugeVec::new(
+            Opts::new(
+                "CHAUp",
+                "Whether the CHA counters of a socket are programmed (1) or locked (0)",
+            )
+            .const_label("instance", &instance_label),
+            &["socket"],
+        )?;
+        self.registry.register(Box::new(up.clone()))?;
+        let programmed = self.sockets();
+        let mut sockets = Vec::new();
+        for &socket_id in &self.config.sockets {
+            let is_up = programmed.contains(&socket_id);
+            up.with_label_values(&[socket_id.to_string().as_str()])
+                .set(if is_up { 1.0 } else { 0.0 });
+            if is_up || self.config.cha_unavailable == ChaUnavailable::Nan {
+                sockets.push(socket_id);
+            }
+        }
+
         // Register all 142 CHA metrics
         for metric in ChaMetric::all() {
             let metric_name = metric.name();
@@ -67,12 +370,15 @@ impl ChaMetricExporter {
             );
 
             let mut socket_map = HashMap::new();
-            for &socket_id in &self.config.sockets {
+            for &socket_id in &sockets {
                 let gauge = Gauge::with_opts(
                     opts.clone()
                         .const_label("socket", socket_id.to_string())
                         .const_label("instance", &instance_label),
                 )?;
+                if !programmed.contains(&socket_id) {
+                    gauge.set(f64::NAN);
+                }
                 self.registry.register(Box::new(gauge.clone()))?;
                 socket_map.insert(socket_id, gauge);
             }
@@ -84,6 +390,40 @@ impl ChaMetricExporter {
             ChaMetric::all().len()
         );
 
+        if self.config.cha_per_box {
+            self.box_gauges = Some(ChaBoxGauges::new(&self.registry, &instance_label)?);
+            tracing::info!("Exporting per-box CHA counters");
+        }
+
+        if !self.config.numa_nodes.is_empty() {
+            self.node_gauges = Some(ChaNodeGauges::new(&self.registry, &instance_label)?);
+        }
+
+        if self.config.export_raw {
+            self.raw_gauges = Some(RawCounterGauges::new(
+                &self.registry,
+                Opts::new(
+                    "CHARawCounter",
+                    "Per-box CHA counter delta of an event group before derivation",
+                )
+                .const_label("instance", &instance_label),
+                &ChaBoxGauges::LABELS,
+            )?);
+        }
+
+        if self.config.cha_metric_age {
+            let age = GaugeVec::new(
+                Opts::new(
+                    "CHAMetricAgeSeconds",
+                    "Seconds since the event groups a CHA metric is derived from were last counted",
+                )
+                .const_label("instance", &instance_label),
+                &["socket", "metric"],
+            )?;
+            self.registry.register(Box::new(age.clone()))?;
+            self.age_gauges = Some(age);
+        }
+
         Ok(())
     }
 
@@ -113,110 +453,7 @@ impl ChaMetricExporter {
                             calculator.store_event(name, data);
                         }
 
-                        // Calculate and export all transaction metrics
-                        for trans_type in TransactionType::all() {
-                            let metrics = calculator.calculate_transaction_metrics(trans_type);
-
-                            for (metric_type, value) in metrics {
-                                let metric = ChaMetric::Transaction(trans_type, metric_type);
-                                if let Some(gauge) =
-                                    socket_gauges.get(&metric).and_then(|m| m.get(&socket_id))
-                                {
-                                    gauge.set(value);
-                                }
-                            }
-                        }
-
-                        // Export LLC lookup metrics
-                        for state in LLCState::all() {
-                            for lookup_type in LLCLookupType::all() {
-                                let value = calculator.get_llc_lookup(state, lookup_type);
-                                let metric = ChaMetric::LLCLookup(state, lookup_type);
-                                if let Some(gauge) =
-                                    socket_gauges.get(&metric).and_then(|m| m.get(&socket_id))
-                                {
-                                    gauge.set(value as f64);
-                                }
-                            }
-                        }
-
-                        // Export LLC victim metrics
-                        for victim_type in VictimType::all() {
-                            let value = calculator.get_llc_victim(victim_type.name());
-                            let metric = ChaMetric::LLCVictim(victim_type);
-                            if let Some(gauge) =
-                                socket_gauges.get(&metric).and_then(|m| m.get(&socket_id))
-                            {
-                                gauge.set(value as f64);
-                            }
-                        }
-
-                        // Export SF eviction metrics
-                        for eviction_type in SFEvictionType::all() {
-                            let value = calculator.get_sf_eviction(eviction_type.name());
-                            let metric = ChaMetric::SFEviction(eviction_type);
-                            if let Some(gauge) =
-                                socket_gauges.get(&metric).and_then(|m| m.get(&socket_id))
-                            {
-                                gauge.set(value as f64);
-                            }
-                        }
-
-                        // Export eviction metrics
-                        if let Some(gauge) = socket_gauges
-                            .get(&ChaMetric::EvictionBandwidth)
-                            .and_then(|m| m.get(&socket_id))
-                        {
-                            gauge.set(calculator.calculate_eviction_bandwidth());
-                        }
-                        if let Some(gauge) = socket_gauges
-                            .get(&ChaMetric::EvictionLatency)
-                            .and_then(|m| m.get(&socket_id))
-                        {
-                            gauge.set(calculator.calculate_eviction_latency());
-                        }
-                        if let Some(gauge) = socket_gauges
-                            .get(&ChaMetric::EvictionQueueOccupancy)
-                            .and_then(|m| m.get(&socket_id))
-                        {
-                            gauge.set(calculator.calculate_eviction_queue_occupancy());
-                        }
-
-                        // Export queue occupancy
-                        if let Some(gauge) = socket_gauges
-                            .get(&ChaMetric::IRQOccupancy)
-                            .and_then(|m| m.get(&socket_id))
-                        {
-                            gauge.set(calculator.get_queue_occupancy("IRQ"));
-                        }
-                        if let Some(gauge) = socket_gauges
-                            .get(&ChaMetric::PRQOccupancy)
-                            .and_then(|m| m.get(&socket_id))
-                        {
-                            gauge.set(calculator.get_queue_occupancy("PRQ"));
-                        }
-
-                        // Export frequency
-                        if let Some(gauge) = socket_gauges
-                            .get(&ChaMetric::UncoreFrequency)
-                            .and_then(|m| m.get(&socket_id))
-                        {
-                            gauge.set(calculator.calculate_uncore_frequency());
-                        }
-
-                        // Export credit metrics
-                        if let Some(gauge) = socket_gauges
-                            .get(&ChaMetric::ReadNoCredit)
-                            .and_then(|m| m.get(&socket_id))
-                        {
-                            gauge.set(calculator.get_credit_metric("ReadNoCredit") as f64);
-                        }
-                        if let Some(gauge) = socket_gauges
-                            .get(&ChaMetric::WriteNoCredit)
-                            .and_then(|m| m.get(&socket_id))
-                        {
-                            gauge.set(calculator.get_credit_metric("WriteNoCredit") as f64);
-                        }
+                        export_socket(&socket_gauges, socket_id, &calculator, config.count_rates);
                     } else {
                         drop(monitors);
                     }
@@ -234,145 +471,295 @@ impl ChaMetricExporter {
     }
 
     /// Collect metrics once (called by orchestrator)
-    pub async fn collect(&self) {
-        for &socket_id in &self.config.sockets {
-            let mut monitors = self.monitor.lock();
+    pub async fn collect(&self) -> Result<()> {
+        // Two-pass slots sleep between their reads, so the monitors are read on the
+        // blocking pool rather than on a runtime worker
+        let monitor = Arc::clone(&self.monitor);
+        let sockets = self.config.sockets.clone();
+        let samples = tokio::task::spawn_blocking(move || {
+            let mut monitors = monitor.lock();
+            sockets
+                .into_iter()
+                .filter_map(|socket| {
+                    let mon = monitors.get_mut(&socket)?;
+                    let sample = mon.collect().map(|event_data| ChaSample {
+                        event_data,
+                        box_deltas: mon.box_deltas().to_vec(),
+                        cha_count: mon.cha_count(),
+                    });
+                    Some((socket, sample))
+                })
+                .collect::<Vec<_>>()
+        })
+        .await
+        .map_err(|e| UncflowError::HardwareError(format!("CHA collection did not finish: {e}")))?;
+
+        collect_sockets("CHA", samples, |socket_id, sample| {
+            let sample = sample?;
+            if let Some(box_gauges) = &self.box_gauges {
+                for deltas in &sample.box_deltas {
+                    box_gauges.set(socket_id, deltas);
+                }
+            }
+            if let Some(node_gauges) = &self.node_gauges {
+                for deltas in &sample.box_deltas {
+                    node_gauges.set(&self.config, socket_id, sample.cha_count, deltas);
+                }
+            }
+            if let Some(raw_gauges) = &self.raw_gauges {
+                set_raw_deltas(raw_gauges, socket_id, &sample.box_deltas);
+            }
 
-            if let Some(mon) = monitors.get_mut(&socket_id) {
-                if let Ok(event_data) = mon.collect() {
-                    drop(monitors);
+            let mut calculator = MetricCalculator::new();
+            for (name, data) in sample.event_data {
+                calculator.store_event(name, data);
+            }
 
-                    let mut calculator = MetricCalculator::new();
-                    for (name, data) in event_data {
-                        calculator.store_event(name, data);
-                    }
+            export_socket(
+                &self.socket_gauges,
+                socket_id,
+                &calculator,
+                self.config.count_rates,
+            );
+            if let Some(age) = &self.age_gauges {
+                set_ages(age, socket_id, &calculator, Instant::now());
+            }
+            Ok(())
+        })
+    }
 
-                    // Calculate and export all transaction metrics
-                    for trans_type in TransactionType::all() {
-                        let metrics = calculator.calculate_transaction_metrics(trans_type);
-
-                        for (metric_type, value) in metrics {
-                            let metric = ChaMetric::Transaction(trans_type, metric_type);
-                            if let Some(gauge) = self
-                                .socket_gauges
-                                .get(&metric)
-                                .and_then(|m| m.get(&socket_id))
-                            {
-                                gauge.set(value);
-                            }
-                        }
-                    }
+    /// Drop every socket's baselines and accumulated event data
+    pub fn reset(&self) {
+        for mon in self.monitor.lock().values_mut() {
+            mon.reset();
+        }
+    }
 
-                    // Export LLC lookup metrics
-                    for state in LLCState::all() {
-                        for lookup_type in LLCLookupType::all() {
-                            let value = calculator.get_llc_lookup(state, lookup_type);
-                            let metric = ChaMetric::LLCLookup(state, lookup_type);
-                            if let Some(gauge) = self
-                                .socket_gauges
-                                .get(&metric)
-                                .and_then(|m| m.get(&socket_id))
-                            {
-                                gauge.set(value as f64);
-                            }
-                        }
-                    }
+    /// Sockets with an initialized CHA monitor
+    pub fn sockets(&self) -> Vec<i32> {
+        let mut sockets: Vec<i32> = self.monitor.lock().keys().copied().collect();
+        sockets.sort_unstable();
+        sockets
+    }
 
-                    // Export LLC victim metrics
-                    for victim_type in VictimType::all() {
-                        let value = calculator.get_llc_victim(victim_type.name());
-                        let metric = ChaMetric::LLCVictim(victim_type);
-                        if let Some(gauge) = self
-                            .socket_gauges
-                            .get(&metric)
-                            .and_then(|m| m.get(&socket_id))
-                        {
-                            gauge.set(value as f64);
-                        }
-                    }
+    pub fn registry(&self) -> Arc<Registry> {
+        Arc::clone(&self.registry)
+    }
+}
 
-                    // Export SF eviction metrics
-                    for eviction_type in SFEvictionType::all() {
-                        let value = calculator.get_sf_eviction(eviction_type.name());
-                        let metric = ChaMetric::SFEviction(eviction_type);
-                        if let Some(gauge) = self
-                            .socket_gauges
-                            .get(&metric)
-                            .and_then(|m| m.get(&socket_id))
-                        {
-                            gauge.set(value as f64);
-                        }
-                    }
+impl Subsystem for ChaMetricExporter {
+    fn name(&self) -> &'static str {
+        "cha"
+    }
 
-                    // Export eviction metrics
-                    if let Some(gauge) = self
-                        .socket_gauges
-                        .get(&ChaMetric::EvictionBandwidth)
-                        .and_then(|m| m.get(&socket_id))
-                    {
-                        gauge.set(calculator.calculate_eviction_bandwidth());
-                    }
-                    if let Some(gauge) = self
-                        .socket_gauges
-                        .get(&ChaMetric::EvictionLatency)
-                        .and_then(|m| m.get(&socket_id))
-                    {
-                        gauge.set(calculator.calculate_eviction_latency());
-                    }
-                    if let Some(gauge) = self
-                        .socket_gauges
-                        .get(&ChaMetric::EvictionQueueOccupancy)
-                        .and_then(|m| m.get(&socket_id))
-                    {
-                        gauge.set(calculator.calculate_eviction_queue_occupancy());
-                    }
+    fn init(config: ExportConfig) -> Result<Self> {
+        Self::new(config)
+    }
 
-                    // Export queue occupancy
-                    if let Some(gauge) = self
-                        .socket_gauges
-                        .get(&ChaMetric::IRQOccupancy)
-                        .and_then(|m| m.get(&socket_id))
-                    {
-                        gauge.set(calculator.get_queue_occupancy("IRQ"));
-                    }
-                    if let Some(gauge) = self
-                        .socket_gauges
-                        .get(&ChaMetric::PRQOccupancy)
-                        .and_then(|m| m.get(&socket_id))
-                    {
-                        gauge.set(calculator.get_queue_occupancy("PRQ"));
-                    }
+    fn collect(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(ChaMetricExporter::collect(self))
+    }
 
-                    // Export frequency
-                    if let Some(gauge) = self
-                        .socket_gauges
-                        .get(&ChaMetric::UncoreFrequency)
-                        .and_then(|m| m.get(&socket_id))
-                    {
-                        gauge.set(calculator.calculate_uncore_frequency());
-                    }
+    fn registry(&self) -> &Registry {
+        &self.registry
+    }
 
-                    // Export credit metrics
-                    if let Some(gauge) = self
-                        .socket_gauges
-                        .get(&ChaMetric::ReadNoCredit)
-                        .and_then(|m| m.get(&socket_id))
-                    {
-                        gauge.set(calculator.get_credit_metric("ReadNoCredit") as f64);
-                    }
-                    if let Some(gauge) = self
-                        .socket_gauges
-                        .get(&ChaMetric::WriteNoCredit)
-                        .and_then(|m| m.get(&socket_id))
-                    {
-                        gauge.set(calculator.get_credit_metric("WriteNoCredit") as f64);
+    fn reset(&self) {
+        ChaMetricExporter::reset(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::arch::CPU_ARCH;
+    use crate::common::msr::mock::MockMsr;
+    use crate::prom::raw::tests::gauge_value;
+    use uncflow_raw::current_arch::cha;
+
+    #[test]
+    fn test_raw_deltas_match_derivation_input() {
+        let msr = MockMsr::new().leak();
+        let mut monitor = ChaMonitor::with_msr(0, msr).unwrap();
+        monitor.initialize().unwrap();
+        let cha_count = CPU_ARCH.cha_count().unwrap_or(28) as usize;
+        let registry = Registry::new();
+        let raw = RawCounterGauges::new(
+            &registry,
+            Opts::new("CHARawCounter", "CHA counter deltas"),
+            &ChaBoxGauges::LABELS,
+        )
+        .unwrap();
+
+        // Keep the counters moving while collect() measures its passes
+        let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let ticker = {
+            let done = Arc::clone(&done);
+            std::thread::spawn(move || {
+                while !done.load(std::sync::atomic::Ordering::Relaxed) {
+                    for cha_id in 0..cha_count {
+                        for counter in 0..3 {
+                            let addr = cha::msr::counter_value(cha_id, counter);
+                            let step = (counter as u64 + 1) * 10;
+                            msr.set(0, addr, msr.get(0, addr).unwrap_or(0) + step);
+                        }
                     }
+                    std::thread::sleep(Duration::from_millis(1));
                 }
+            })
+        };
+        let event_data = monitor.collect().unwrap();
+        done.store(true, std::sync::atomic::Ordering::Relaxed);
+        ticker.join().unwrap();
+
+        set_raw_deltas(&raw, 0, monitor.box_deltas());
+        let families = registry.gather();
+
+        // Every exported box delta adds up to the aggregate the calculator is fed
+        for deltas in monitor.box_deltas() {
+            let data = &event_data[&deltas.group];
+            for (counter, aggregate) in [
+                ("occupancy", data.occupancy),
+                ("insert", data.insert),
+                ("clockticks", data.clockticks),
+            ] {
+                let sum: f64 = deltas
+                    .box_ids
+                    .iter()
+                    .map(|cha_box| {
+                        let cha_box = cha_box.to_string();
+                        let labels = [
+                            ("socket", "0"),
+                            ("cha_box", cha_box.as_str()),
+                            ("event", deltas.group.as_str()),
+                            ("counter", counter),
+                        ];
+                        gauge_value(&families, "CHARawCounter", &labels).unwrap()
+                    })
+                    .sum();
+                assert_eq!(sum, aggregate as f64, "{} {counter}", deltas.group);
             }
         }
+        assert_eq!(monitor.box_deltas().len(), 2);
+        assert!(event_data.values().all(|data| data.insert > 0));
     }
 
-    pub fn registry(&self) -> Arc<Registry> {
-        Arc::clone(&self.registry)
+    #[test]
+    fn test_count_rates() {
+        use crate::metrics::cha::{RawEventData, VictimType};
+
+        let mut calculator = MetricCalculator::new();
+        // 3000 M-state victims over a 250 ms rotation turn
+        calculator.store_event(
+            VictimType::M.event_name(),
+            RawEventData {
+                insert: 3_000,
+                duration: Duration::from_millis(250),
+                ..Default::default()
+            },
+        );
+        let victims = ChaMetric::LLCVictim(VictimType::M);
+        assert_eq!(exported_value(victims, &calculator, false), Some(3_000.0));
+        assert_eq!(exported_value(victims, &calculator, true), Some(12_000.0));
+
+        // Rates and ratios are exported as they are
+        let frequency = ChaMetric::UncoreFrequency;
+        assert_eq!(
+            exported_value(frequency, &calculator, true),
+            exported_value(frequency, &calculator, false)
+        );
+        // A group that was never counted stays 0
+        let victims_e = ChaMetric::LLCVictim(VictimType::E);
+        assert_eq!(exported_value(victims_e, &calculator, true), Some(0.0));
+    }
+
+    #[test]
+    fn test_metric_missed_by_rotation_reports_age() {
+        use crate::metrics::cha::RawEventData;
+
+        let now = Instant::now();
+        let measured = |ago| RawEventData {
+            insert: 10,
+            duration: Duration::from_secs(1),
+            measured_at: Some(now - Duration::from_secs(ago)),
+            ..Default::default()
+        };
+        let mut calculator = MetricCalculator::new();
+        // PCIeRead was counted a rotation ago, RFO in this collect
+        calculator.store_event("PCIeRead Hit".to_string(), measured(6));
+        calculator.store_event("PCIeRead Miss".to_string(), measured(4));
+        calculator.store_event("RFO Hit".to_string(), measured(0));
+        calculator.store_event("RFO Miss".to_string(), measured(0));
+
+        let registry = Registry::new();
+        let age = GaugeVec::new(
+            Opts::new("CHAMetricAgeSeconds", "age"),
+            &["socket", "metric"],
+        )
+        .unwrap();
+        registry.register(Box::new(age.clone())).unwrap();
+        set_ages(&age, 0, &calculator, now);
+
+        let families = registry.gather();
+        let age_of = |metric: &str| {
+            gauge_value(
+                &families,
+                "CHAMetricAgeSeconds",
+                &[("socket", "0"), ("metric", metric)],
+            )
+        };
+        // As old as the oldest group the metric needs
+        assert_eq!(age_of("PCIeReadHitRate"), Some(6.0));
+        assert_eq!(age_of("RFOHitRate"), Some(0.0));
+        // Uncore frequency comes from whichever group was counted last
+        assert_eq!(age_of("UncoreFrequency"), Some(0.0));
+        // Never measured, no age
+        assert_eq!(age_of("ItoMHitRate"), None);
+    }
+
+    #[test]
+    fn test_unselected_socket_has_no_monitor() {
+        let msr = MockMsr::new().leak();
+        let config = ExportConfig::new(vec![0, 1], vec![0]).restricted_to(&[0]);
+        let exporter = ChaMetricExporter::with_msr(config, msr).unwrap();
+
+        assert_eq!(exporter.sockets(), vec![0]);
+        let families = exporter.registry().gather();
+        let family = ChaMetric::all()[0].name();
+        let labels = |socket| [("socket", socket), ("instance", "server")];
+        assert!(gauge_value(&families, &family, &labels("0")).is_some());
+        assert!(gauge_value(&families, &family, &labels("1")).is_none());
+    }
+
+    #[test]
+    fn test_locked_cha_exports_no_zeros() {
+        let family = ChaMetric::all()[0].name();
+        let labels = [("socket", "0"), ("instance", "server")];
+
+        // Writes to a locked control register do not stick
+        for (policy, expected) in [
+            (ChaUnavailable::Omit, None),
+            (ChaUnavailable::Nan, Some(true)),
+        ] {
+            let msr = MockMsr::read_only().leak();
+            let config = ExportConfig::new(vec![0], vec![0]).with_cha_unavailable(policy);
+            let exporter = ChaMetricExporter::with_msr(config, msr).unwrap();
+            assert!(exporter.sockets().is_empty());
+
+            let families = exporter.registry().gather();
+            assert_eq!(
+                gauge_value(&families, &family, &labels).map(f64::is_nan),
+                expected,
+                "{policy:?}"
+            );
+            assert_eq!(gauge_value(&families, "CHAUp", &labels), Some(0.0));
+        }
+
+        let msr = MockMsr::new().leak();
+        let exporter =
+            ChaMetricExporter::with_msr(ExportConfig::new(vec![0], vec![0]), msr).unwrap();
+        let families = exporter.registry().gather();
+        assert_eq!(gauge_value(&families, &family, &labels), Some(0.0));
+        assert_eq!(gauge_value(&families, "CHAUp", &labels), Some(1.0));
     }
 }
@@ -0,0 +1,171 @@
+// User-defined CHA Event Exporter (--events-from-json)
+//
+// Every event and derived metric of the event file is exported as its own gauge
+// under the name the file gives it, labeled by socket. Events are the count over
+// the last collection window summed over the socket's CHA boxes; metrics keep
+// their previous value when their formula cannot be evaluated. Sockets whose CHA
+// counters are locked are skipped, like with --cha.
+
+use futures_util::future::BoxFuture;
+use parking_lot::Mutex;
+use prometheus::{GaugeVec, Opts, Registry};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use crate::common::msr::{self, MsrAccess};
+use crate::config::ExportConfig;
+use crate::counters::cha::{ChaCustomMonitor, ChaMonitor};
+use crate::error::{Result, UncflowError};
+use crate::orchestrator::Subsystem;
+use crate::prom::sockets::collect_sockets;
+
+pub struct ChaCustomMetricExporter {
+    registry: Arc<Registry>,
+    monitors: Mutex<BTreeMap<i32, ChaCustomMonitor>>,
+    // One gauge per event and metric name
+    gauges: HashMap<String, GaugeVec>,
+}
+
+impl ChaCustomMetricExporter {
+    pub fn new(config: ExportConfig) -> Result<Self> {
+        Self::with_msr(config, msr::Msr::instance())
+    }
+
+    /// Create an exporter whose monitors program CHA boxes through `msr`
+    pub fn with_msr(config: ExportConfig, msr: &'static dyn MsrAccess) -> Result<Self> {
+        let events = config.cha_custom_events.clone().ok_or_else(|| {
+            UncflowError::ConfigError("No CHA event file given (--events-from-json)".to_string())
+        })?;
+
+        let mut monitors = BTreeMap::new();
+        for &socket in &config.sockets {
+            let core = config.uncore_core(socket, ChaMonitor::default_core(socket));
+            let monitor = ChaCustomMonitor::with_core(socket, core, events.clone(), msr)
+                .and_then(|mut monitor| monitor.initialize().map(|()| monitor));
+            match monitor {
+                Ok(monitor) => {
+                    monitors.insert(socket, monitor);
+                }
+                Err(e) => tracing::warn!(
+                    "Custom CHA events not programmable on socket {}, skipping: {}",
+                    socket,
+                    e
+                ),
+            }
+        }
+
+        let instance_label =
+            std::env::var("INSTANCE_LABEL").unwrap_or_else(|_| "server".to_string());
+        let registry = Arc::new(Registry::new());
+        let mut gauges = HashMap::new();
+        for name in events.names() {
+            let gauge = GaugeVec::new(
+                Opts::new(name, format!("CHA {name} from the --events-from-json file"))
+                    .const_label("instance", &instance_label),
+                &["socket"],
+            )?;
+            registry.register(Box::new(gauge.clone()))?;
+            gauges.insert(name.to_string(), gauge);
+        }
+
+        Ok(Self {
+            registry,
+            monitors: Mutex::new(monitors),
+            gauges,
+        })
+    }
+
+    /// Collect metrics once (called by orchestrator)
+    pub async fn collect(&self) -> Result<()> {
+        let mut monitors = self.monitors.lock();
+        let monitors = monitors.iter_mut().map(|(&socket, m)| (socket, m));
+        collect_sockets("Custom CHA", monitors, |socket, monitor| {
+            let Some(sample) = monitor.collect()? else {
+                return Ok(());
+            };
+
+            let socket = socket.to_string();
+            let counts = sample
+                .events
+                .iter()
+                .map(|(name, &count)| (name, count as f64));
+            for (name, value) in counts.chain(sample.metrics.iter().map(|(n, &v)| (n, v))) {
+                if let Some(gauge) = self.gauges.get(name) {
+                    gauge.with_label_values(&[socket.as_str()]).set(value);
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Re-baseline every socket, the next collection starts a fresh window
+    pub fn reset(&self) {
+        for monitor in self.monitors.lock().values_mut() {
+            monitor.reset();
+        }
+    }
+
+    pub fn registry(&self) -> Arc<Registry> {
+        Arc::clone(&self.registry)
+    }
+}
+
+impl Subsystem for ChaCustomMetricExporter {
+    fn name(&self) -> &'static str {
+        "cha_custom"
+    }
+
+    fn init(config: ExportConfig) -> Result<Self> {
+        Self::new(config)
+    }
+
+    fn collect(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(ChaCustomMetricExporter::collect(self))
+    }
+
+    fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    fn reset(&self) {
+        ChaCustomMetricExporter::reset(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::msr::mock::MockMsr;
+    use crate::prom::raw::tests::gauge_value;
+    use std::time::Duration;
+    use uncflow_raw::current_arch::cha;
+
+    #[tokio::test]
+    async fn test_exports_under_user_names() {
+        let events = r#"{
+            "unit": "cha",
+            "events": [
+                { "name": "llc_lookups_any", "counter": 0, "event": "0x34", "umask": "0x11" }
+            ],
+            "metrics": [{ "name": "llc_lookups_per_second", "formula": "llc_lookups_any / seconds" }]
+        }"#;
+        let config = ExportConfig::new(vec![0], vec![0])
+            .with_cha_custom_events(Some(events.parse().unwrap()));
+        let msr = MockMsr::new().leak();
+        let exporter = ChaCustomMetricExporter::with_msr(config, msr).unwrap();
+        exporter.collect().await.unwrap();
+
+        let cha_count = exporter.monitors.lock()[&0].cha_count();
+        msr.set(0, cha::msr::counter_value(cha_count - 1, 0), 500);
+        std::thread::sleep(Duration::from_millis(20));
+        exporter.collect().await.unwrap();
+
+        let families = exporter.registry().gather();
+        let labels = [("instance", "server"), ("socket", "0")];
+        assert_eq!(
+            gauge_value(&families, "llc_lookups_any", &labels),
+            Some(500.0)
+        );
+        assert!(gauge_value(&families, "llc_lookups_per_second", &labels).unwrap() > 0.0);
+    }
+}
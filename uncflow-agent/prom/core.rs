@@ -1,19 +1,112 @@
-use prometheus::{Gauge, Registry};
-use std::collections::HashMap;
+use futures_util::future::BoxFuture;
+use prometheus::{Gauge, GaugeVec, Registry};
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::task::JoinHandle;
 
+use crate::common::{log_limiter, CounterBackend};
 use crate::config::ExportConfig;
 use crate::counters::core::CoreMonitor;
 use crate::error::Result;
 use crate::metrics::core::CoreMetric;
+use crate::orchestrator::{Subsystem, SubsystemSnapshot};
+
+/// `numerator / denominator`, 0 when nothing was counted (as for the per-core ratios)
+fn ratio(numerator: f64, denominator: f64) -> f64 {
+    if denominator > 0.0 {
+        numerator / denominator
+    } else {
+        0.0
+    }
+}
+
+/// Socket-level value of each core metric from the per-core metrics of its cores
+///
+/// Counts are summed and ratios recomputed from the sums, so IPC is the socket's
+/// instructions per cycle rather than the mean of the per-core IPCs.
+fn socket_metrics(per_core: &[&HashMap<String, f64>]) -> HashMap<CoreMetric, f64> {
+    let values = |metric: CoreMetric| per_core.iter().filter_map(move |m| m.get(metric.name()));
+    let sum = |metric: CoreMetric| values(metric).sum::<f64>();
+    let hit_ratio = |miss, reference| {
+        if sum(reference) > 0.0 {
+            1.0 - ratio(sum(miss), sum(reference))
+        } else {
+            0.0
+        }
+    };
+
+    let mut result = HashMap::new();
+    for metric in CoreMetric::all() {
+        // Metrics of optional event groups are missing on every core
+        if values(metric).next().is_none() {
+            continue;
+        }
+        let value = match metric {
+            CoreMetric::IPC => ratio(sum(CoreMetric::Instructions), sum(CoreMetric::Cycles)),
+            CoreMetric::L3CacheHitRatio => {
+                hit_ratio(CoreMetric::L3CacheMiss, CoreMetric::L3CacheRef)
+            }
+            CoreMetric::L2CacheHitRatio => {
+                hit_ratio(CoreMetric::L2CacheMiss, CoreMetric::L2CacheRef)
+            }
+            CoreMetric::L3MPI => ratio(sum(CoreMetric::L3CacheMiss), sum(CoreMetric::Instructions)),
+            CoreMetric::L2MPI => ratio(sum(CoreMetric::L2CacheMiss), sum(CoreMetric::Instructions)),
+            // Weighted by how long each core ran
+            CoreMetric::AvxLicenseCyclesRatio => ratio(
+                per_core
+                    .iter()
+                    .filter_map(|m| Some(m.get(metric.name())? * m.get(CoreMetric::Cycles.name())?))
+                    .sum(),
+                sum(CoreMetric::Cycles),
+            ),
+            // Mean clock of the socket's cores
+            CoreMetric::EffectiveFrequency => ratio(sum(metric), values(metric).count() as f64),
+            // Every core is read in the same pass
+            CoreMetric::ElapsedTime => values(metric).fold(0.0, |a: f64, &b| a.max(b)),
+            _ => sum(metric),
+        };
+        result.insert(metric, value);
+    }
+    result
+}
+
+// core_socket_<metric>{socket}, with --core-socket-rollup
+#[derive(Clone)]
+struct SocketCoreRollup {
+    // Socket of every monitored core whose package is known
+    core_sockets: HashMap<i32, i32>,
+    gauges: HashMap<CoreMetric, GaugeVec>,
+}
+
+impl SocketCoreRollup {
+    fn publish(&self, per_core: &HashMap<i32, HashMap<String, f64>>) {
+        let mut by_socket: BTreeMap<i32, Vec<&HashMap<String, f64>>> = BTreeMap::new();
+        for (core, metrics) in per_core {
+            if let Some(&socket) = self.core_sockets.get(core) {
+                by_socket.entry(socket).or_default().push(metrics);
+            }
+        }
+
+        for (socket, metrics) in by_socket {
+            let socket = socket.to_string();
+            for (metric, value) in socket_metrics(&metrics) {
+                if let Some(gauge) = self.gauges.get(&metric) {
+                    gauge.with_label_values(&[socket.as_str()]).set(value);
+                }
+            }
+        }
+    }
+}
 
 pub struct CoreMetricExporter {
     config: ExportConfig,
     registry: Arc<Registry>,
     monitor: Arc<parking_lot::Mutex<CoreMonitor>>,
     core_gauges: HashMap<CoreMetric, HashMap<i32, Gauge>>,
+    // core_event_count{core,core_label,event}, when --core-event events are counted
+    event_gauges: Option<GaugeVec>,
+    socket_rollup: Option<SocketCoreRollup>,
 }
 
 impl CoreMetricExporter {
@@ -30,6 +123,8 @@ impl CoreMetricExporter {
             registry: Arc::clone(&registry),
             monitor,
             core_gauges: HashMap::new(),
+            event_gauges: None,
+            socket_rollup: None,
         };
 
         exporter.register_metrics()?;
@@ -38,7 +133,23 @@ impl CoreMetricExporter {
     }
 
     fn register_metrics(&mut self) -> Result<()> {
+        let (counts_avx_license, counts_offcore) = {
+            let monitor = self.monitor.lock();
+            (monitor.counts_avx_license(), monitor.counts_offcore())
+        };
         for metric in CoreMetric::all() {
+            // Metrics of optional event groups only exist when the group is counted
+            let counted = match metric {
+                CoreMetric::AvxLicenseCyclesRatio => counts_avx_license,
+                CoreMetric::OffcoreLocalDram | CoreMetric::OffcoreRemoteDram => counts_offcore,
+                // APERF/MPERF are read as MSRs
+                CoreMetric::EffectiveFrequency => self.config.backend == CounterBackend::Msr,
+                _ => true,
+            };
+            if !counted {
+                continue;
+            }
+
             let opts =
                 prometheus::Opts::new(metric.name(), format!("Core {} measurement", metric.name()));
 
@@ -62,13 +173,82 @@ impl CoreMetricExporter {
             self.core_gauges.insert(metric, core_map);
         }
 
+        if !self.config.core_events.is_empty() {
+            let gauges = GaugeVec::new(
+                prometheus::Opts::new(
+                    "core_event_count",
+                    "Count of a --core-event event, extrapolated over multiplexing",
+                ),
+                &["core", "core_label", "event"],
+            )?;
+            self.registry.register(Box::new(gauges.clone()))?;
+            self.event_gauges = Some(gauges);
+        }
+
+        if self.config.core_socket_rollup {
+            let mut core_sockets = HashMap::new();
+            for &core_id in &self.config.cores {
+                match ExportConfig::core_socket(core_id as u32) {
+                    Some(socket) => {
+                        core_sockets.insert(core_id, socket);
+                    }
+                    None => tracing::warn!(
+                        "Socket of core {} unknown, leaving it out of the socket rollup",
+                        core_id
+                    ),
+                }
+            }
+
+            let mut gauges = HashMap::new();
+            for &metric in self.core_gauges.keys() {
+                let gauge = GaugeVec::new(
+                    prometheus::Opts::new(
+                        format!("core_socket_{}", metric.name()),
+                        format!("Core {} of all monitored cores of a socket", metric.name()),
+                    ),
+                    &["socket"],
+                )?;
+                self.registry.register(Box::new(gauge.clone()))?;
+                gauges.insert(metric, gauge);
+            }
+            self.socket_rollup = Some(SocketCoreRollup {
+                core_sockets,
+                gauges,
+            });
+        }
+
         Ok(())
     }
 
+    // Publish the --core-event counts of `core_id`
+    fn set_event_counts(
+        config: &ExportConfig,
+        monitor: &CoreMonitor,
+        event_gauges: Option<&GaugeVec>,
+        core_id: i32,
+    ) {
+        let Some(gauges) = event_gauges else {
+            return;
+        };
+        let core = core_id.to_string();
+        let label = config
+            .core_labels
+            .get(&core_id)
+            .map(|s| s.as_str())
+            .unwrap_or("unknown");
+        for (event, count) in monitor.custom_event_counts(core_id) {
+            gauges
+                .with_label_values(&[core.as_str(), label, &event])
+                .set(count as f64);
+        }
+    }
+
     async fn collect_loop(
         config: ExportConfig,
         monitor: Arc<parking_lot::Mutex<CoreMonitor>>,
         core_gauges: HashMap<CoreMetric, HashMap<i32, Gauge>>,
+        event_gauges: Option<GaugeVec>,
+        socket_rollup: Option<SocketCoreRollup>,
     ) {
         tracing::warn!("Starting Core PMU export thread");
 
@@ -85,42 +265,26 @@ impl CoreMetricExporter {
                 }
             }
 
+            let mut per_core = HashMap::new();
             for &core_id in &config.cores {
                 let mon = monitor.lock();
                 let metrics = mon.get_metrics(core_id);
+                Self::set_event_counts(&config, &mon, event_gauges.as_ref(), core_id);
                 drop(mon);
 
                 // Update gauges based on metric name
-                for (metric_name, value) in metrics {
-                    let metric_enum = match metric_name.as_str() {
-                        "IPC" => Some(CoreMetric::IPC),
-                        "instructions" => Some(CoreMetric::Instructions),
-                        "cycles" => Some(CoreMetric::Cycles),
-                        "L3CacheMissNum" => Some(CoreMetric::L3CacheMiss),
-                        "L3CacheRef" => Some(CoreMetric::L3CacheRef),
-                        "L2CacheMissNum" => Some(CoreMetric::L2CacheMiss),
-                        "L2CacheRef" => Some(CoreMetric::L2CacheRef),
-                        "L3CacheHitRatio" => Some(CoreMetric::L3CacheHitRatio),
-                        "L2CacheHitRatio" => Some(CoreMetric::L2CacheHitRatio),
-                        "L3MPI" => Some(CoreMetric::L3MPI),
-                        "L2MPI" => Some(CoreMetric::L2MPI),
-                        "elapsedTime" => Some(CoreMetric::ElapsedTime),
-                        "L2PrefetchMiss" => Some(CoreMetric::L2PrefetchMiss),
-                        "L2PrefetchHit" => Some(CoreMetric::L2PrefetchHit),
-                        "L2OutSilent" => Some(CoreMetric::L2OutSilent),
-                        "L2OutNonSilent" => Some(CoreMetric::L2OutNonSilent),
-                        "L2In" => Some(CoreMetric::L2In),
-                        "L2Writeback" => Some(CoreMetric::L2Writeback),
-                        _ => None,
-                    };
-
-                    if let Some(metric) = metric_enum {
+                for (metric_name, value) in &metrics {
+                    if let Ok(metric) = metric_name.parse::<CoreMetric>() {
                         if let Some(gauge) = core_gauges.get(&metric).and_then(|m| m.get(&core_id))
                         {
-                            gauge.set(value);
+                            gauge.set(*value);
                         }
                     }
                 }
+                per_core.insert(core_id, metrics);
+            }
+            if let Some(rollup) = &socket_rollup {
+                rollup.publish(&per_core);
             }
         }
     }
@@ -129,56 +293,58 @@ impl CoreMetricExporter {
         let config = self.config.clone();
         let monitor = Arc::clone(&self.monitor);
         let core_gauges = self.core_gauges.clone();
+        let event_gauges = self.event_gauges.clone();
+        let socket_rollup = self.socket_rollup.clone();
 
-        tokio::spawn(Self::collect_loop(config, monitor, core_gauges))
+        tokio::spawn(Self::collect_loop(
+            config,
+            monitor,
+            core_gauges,
+            event_gauges,
+            socket_rollup,
+        ))
     }
 
     /// Collect metrics once (called by orchestrator)
-    pub async fn collect(&self) {
+    pub async fn collect(&self) -> Result<()> {
         {
             let mut mon = self.monitor.lock();
             if let Err(e) = mon.collect() {
-                tracing::error!("Failed to collect core metrics: {}", e);
-                return;
+                if log_limiter().allow("Core", &e.to_string()) {
+                    tracing::error!("Failed to collect core metrics: {}", e);
+                }
+                return Err(e);
             }
         }
 
+        let mut per_core = HashMap::new();
         for &core_id in &self.config.cores {
             let mon = self.monitor.lock();
             let metrics = mon.get_metrics(core_id);
+            Self::set_event_counts(&self.config, &mon, self.event_gauges.as_ref(), core_id);
             drop(mon);
 
             // Update gauges based on metric name
-            for (metric_name, value) in metrics {
-                let metric_enum = match metric_name.as_str() {
-                    "IPC" => Some(CoreMetric::IPC),
-                    "instructions" => Some(CoreMetric::Instructions),
-                    "cycles" => Some(CoreMetric::Cycles),
-                    "L3CacheMissNum" => Some(CoreMetric::L3CacheMiss),
-                    "L3CacheRef" => Some(CoreMetric::L3CacheRef),
-                    "L2CacheMissNum" => Some(CoreMetric::L2CacheMiss),
-                    "L2CacheRef" => Some(CoreMetric::L2CacheRef),
-                    "L3CacheHitRatio" => Some(CoreMetric::L3CacheHitRatio),
-                    "L2CacheHitRatio" => Some(CoreMetric::L2CacheHitRatio),
-                    "L3MPI" => Some(CoreMetric::L3MPI),
-                    "L2MPI" => Some(CoreMetric::L2MPI),
-                    "elapsedTime" => Some(CoreMetric::ElapsedTime),
-                    "L2PrefetchMiss" => Some(CoreMetric::L2PrefetchMiss),
-                    "L2PrefetchHit" => Some(CoreMetric::L2PrefetchHit),
-                    "L2OutSilent" => Some(CoreMetric::L2OutSilent),
-                    "L2OutNonSilent" => Some(CoreMetric::L2OutNonSilent),
-                    "L2In" => Some(CoreMetric::L2In),
-                    "L2Writeback" => Some(CoreMetric::L2Writeback),
-                    _ => None,
-                };
-
-                if let Some(metric) = metric_enum {
+            for (metric_name, value) in &metrics {
+                if let Ok(metric) = metric_name.parse::<CoreMetric>() {
                     if let Some(gauge) = self.core_gauges.get(&metric).and_then(|m| m.get(&core_id))
                     {
-                        gauge.set(value);
+                        gauge.set(*value);
                     }
                 }
             }
+            per_core.insert(core_id, metrics);
+        }
+        if let Some(rollup) = &self.socket_rollup {
+            rollup.publish(&per_core);
+        }
+        Ok(())
+    }
+
+    /// Re-read the baseline of every core
+    pub fn reset(&self) {
+        if let Err(e) = self.monitor.lock().reset() {
+            tracing::warn!("Failed to reset core PMU baselines: {}", e);
         }
     }
 
@@ -186,3 +352,93 @@ impl CoreMetricExporter {
         Arc::clone(&self.registry)
     }
 }
+
+impl Subsystem for CoreMetricExporter {
+    fn name(&self) -> &'static str {
+        "core"
+    }
+
+    fn init(config: ExportConfig) -> Result<Self> {
+        Self::new(config)
+    }
+
+    fn collect(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(CoreMetricExporter::collect(self))
+    }
+
+    fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    fn reset(&self) {
+        CoreMetricExporter::reset(self)
+    }
+
+    fn snapshot(&self) -> Option<Result<SubsystemSnapshot>> {
+        Some(self.monitor.lock().snapshot().map(SubsystemSnapshot::Core))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn core(instructions: f64, cycles: f64, llc_miss: f64) -> HashMap<String, f64> {
+        HashMap::from([
+            ("instructions".to_string(), instructions),
+            ("cycles".to_string(), cycles),
+            ("IPC".to_string(), ratio(instructions, cycles)),
+            ("L3CacheMissNum".to_string(), llc_miss),
+            ("elapsedTime".to_string(), 1.0),
+        ])
+    }
+
+    #[test]
+    fn test_socket_ipc_is_weighted() {
+        // A busy core at IPC 2 and a nearly idle one at IPC 0.5
+        let busy = core(2_000_000.0, 1_000_000.0, 300.0);
+        let idle = core(500.0, 1_000.0, 10.0);
+        let socket = socket_metrics(&[&busy, &idle]);
+
+        let ipc = 2_000_500.0 / 1_001_000.0;
+        assert!((socket[&CoreMetric::IPC] - ipc).abs() < 1e-12);
+        assert!((socket[&CoreMetric::IPC] - 1.25).abs() > 0.5);
+        assert_eq!(socket[&CoreMetric::L3CacheMiss], 310.0);
+        assert_eq!(socket[&CoreMetric::ElapsedTime], 1.0);
+        // Not counted on any core, so not exported
+        assert!(!socket.contains_key(&CoreMetric::AvxLicenseCyclesRatio));
+    }
+
+    #[test]
+    fn test_rollup_groups_cores_by_socket() {
+        let registry = Registry::new();
+        let gauge = GaugeVec::new(
+            prometheus::Opts::new("core_socket_IPC", "test"),
+            &["socket"],
+        )
+        .unwrap();
+        registry.register(Box::new(gauge.clone())).unwrap();
+        let rollup = SocketCoreRollup {
+            core_sockets: HashMap::from([(0, 0), (1, 0), (2, 1)]),
+            gauges: HashMap::from([(CoreMetric::IPC, gauge)]),
+        };
+
+        rollup.publish(&HashMap::from([
+            (0, core(300.0, 100.0, 0.0)),
+            (1, core(100.0, 100.0, 0.0)),
+            (2, core(50.0, 100.0, 0.0)),
+            // Core of unknown socket
+            (3, core(9_000.0, 1.0, 0.0)),
+        ]));
+        let families = registry.gather();
+        let ipc = |socket| {
+            crate::prom::raw::tests::gauge_value(
+                &families,
+                "core_socket_IPC",
+                &[("socket", socket)],
+            )
+        };
+        assert_eq!(ipc("0"), Some(2.0));
+        assert_eq!(ipc("1"), Some(0.5));
+    }
+}
@@ -0,0 +1,158 @@
+// C-state Metrics Exporter
+//
+// Share of each collection interval every socket spent in each package C-state
+// and every monitored core in each core C-state, from the residency counters.
+
+use futures_util::future::BoxFuture;
+use parking_lot::Mutex;
+use prometheus::{GaugeVec, Opts, Registry};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::common::log_limiter;
+use crate::config::ExportConfig;
+use crate::counters::cstate::CstateMonitor;
+use crate::error::Result;
+use crate::orchestrator::Subsystem;
+use uncflow_raw::current_arch::cstate;
+
+pub struct CstateMetricExporter {
+    registry: Arc<Registry>,
+    monitor: Mutex<CstateMonitor>,
+    package: HashMap<&'static str, GaugeVec>,
+    core: HashMap<&'static str, GaugeVec>,
+}
+
+impl CstateMetricExporter {
+    pub fn new(config: ExportConfig) -> Result<Self> {
+        Self::with_monitor(CstateMonitor::new(&config)?)
+    }
+
+    /// Create an exporter publishing what `monitor` reads
+    pub fn with_monitor(monitor: CstateMonitor) -> Result<Self> {
+        let registry = Arc::new(Registry::new());
+
+        let mut package = HashMap::new();
+        for (state, _) in cstate::PACKAGE_CSTATES {
+            let gauge = GaugeVec::new(
+                Opts::new(
+                    format!("cstate_package_{state}_ratio"),
+                    format!(
+                        "Share of the last interval the package spent in {}",
+                        state.to_uppercase()
+                    ),
+                ),
+                &["socket"],
+            )?;
+            registry.register(Box::new(gauge.clone()))?;
+            package.insert(state, gauge);
+        }
+
+        let mut core = HashMap::new();
+        for (state, _) in cstate::CORE_CSTATES {
+            let gauge = GaugeVec::new(
+                Opts::new(
+                    format!("cstate_core_{state}_ratio"),
+                    format!(
+                        "Share of the last interval the core spent in {}",
+                        state.to_uppercase()
+                    ),
+                ),
+                &["core"],
+            )?;
+            registry.register(Box::new(gauge.clone()))?;
+            core.insert(state, gauge);
+        }
+
+        Ok(Self {
+            registry,
+            monitor: Mutex::new(monitor),
+            package,
+            core,
+        })
+    }
+
+    /// Collect metrics once (called by orchestrator)
+    pub async fn collect(&self) -> Result<()> {
+        let residency = self.monitor.lock().collect().inspect_err(|e| {
+            if log_limiter().allow("C-state", &e.to_string()) {
+                tracing::error!("Failed to read C-state residency: {}", e);
+            }
+        })?;
+        for ((socket, state), ratio) in residency.package {
+            self.package[state]
+                .with_label_values(&[socket.to_string().as_str()])
+                .set(ratio);
+        }
+        for ((core, state), ratio) in residency.core {
+            self.core[state]
+                .with_label_values(&[core.to_string().as_str()])
+                .set(ratio);
+        }
+        Ok(())
+    }
+
+    /// Drop the residency baselines
+    pub fn reset(&self) {
+        self.monitor.lock().reset();
+    }
+
+    pub fn registry(&self) -> Arc<Registry> {
+        Arc::clone(&self.registry)
+    }
+}
+
+impl Subsystem for CstateMetricExporter {
+    fn name(&self) -> &'static str {
+        "cstate"
+    }
+
+    fn init(config: ExportConfig) -> Result<Self> {
+        Self::new(config)
+    }
+
+    fn collect(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(CstateMetricExporter::collect(self))
+    }
+
+    fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    fn reset(&self) {
+        CstateMetricExporter::reset(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::msr::mock::MockMsr;
+    use crate::prom::raw::tests::gauge_value;
+    use cstate::msr::*;
+
+    #[tokio::test]
+    async fn test_exports_package_c6_ratio() {
+        let msr = MockMsr::new().leak();
+        let config = ExportConfig::new(vec![1], vec![4]).with_uncore_cores(HashMap::from([(1, 4)]));
+        let exporter =
+            CstateMetricExporter::with_monitor(CstateMonitor::with_msr(&config, msr).unwrap())
+                .unwrap();
+
+        exporter.collect().await.unwrap();
+        msr.set(4, IA32_TIME_STAMP_COUNTER, 4_000);
+        msr.set(4, MSR_PKG_C6_RESIDENCY, 3_000);
+        msr.set(4, MSR_CORE_C7_RESIDENCY, 1_000);
+        exporter.collect().await.unwrap();
+
+        let families = exporter.registry().gather();
+        assert_eq!(
+            gauge_value(&families, "cstate_package_c6_ratio", &[("socket", "1")]),
+            Some(0.75)
+        );
+        assert_eq!(
+            gauge_value(&families, "cstate_core_c7_ratio", &[("core", "4")]),
+            Some(0.25)
+        );
+    }
+}
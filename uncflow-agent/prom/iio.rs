@@ -1,68 +1,223 @@
 // IIO Metrics Exporter
 
+use crate::common::Msr;
+use crate::counters::iio::devices::{PortDevices, DEVICE_LABELS};
+use crate::counters::iio::monitor::EVENT_GROUP_WINDOW;
 use crate::counters::iio::IioMonitor;
 use crate::error::Result;
 use crate::metrics::iio::IioMetric;
+use crate::metrics::kind::{self, MetricKind};
+use crate::orchestrator::Subsystem;
+use crate::prom::rollup::{self, SocketRollup};
+use crate::prom::sockets::collect_sockets;
 use crate::ExportConfig;
+use futures_util::future::BoxFuture;
 use parking_lot::Mutex;
-use prometheus::{Gauge, Registry};
+use prometheus::{Gauge, GaugeVec, Opts, Registry};
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use std::thread;
 use std::time::Duration;
 
+/// Exported value of `metric`, counts per second of the event group window if `count_rates`
+fn exported_value(metric: IioMetric, value: f64, count_rates: bool) -> Option<f64> {
+    if count_rates && metric.kind() == MetricKind::Count {
+        return kind::per_second(value, EVENT_GROUP_WINDOW);
+    }
+    Some(value)
+}
+
+/// Whether `metric` is exported, IOMMU metrics only with --iio-iommu and only the
+/// free-running PCIe metrics with --read-only-counters
+fn is_exported(metric: IioMetric, config: &ExportConfig) -> bool {
+    let iommu = config.iio_iommu && !config.read_only_counters;
+    if config.read_only_counters && port_of(metric).is_none() {
+        return false;
+    }
+    match metric {
+        IioMetric::IOMMUCacheHitRatio | IioMetric::IOMMUPageWalkLatency => iommu,
+        _ => true,
+    }
+}
+
+/// (channel, port) of a per-port PCIe metric
+fn port_of(metric: IioMetric) -> Option<(usize, usize)> {
+    match metric {
+        IioMetric::PCIeInBandwidth(ch, port)
+        | IioMetric::PCIeOutBandwidth(ch, port)
+        | IioMetric::PCIeUtilization(ch, port) => Some((ch, port)),
+        _ => None,
+    }
+}
+
+/// Every gauge of the exporter, keyed by socket and metric name
+#[derive(Clone)]
+struct IioGauges {
+    plain: HashMap<(i32, String), Gauge>,
+    // Per-port series labeled with their device, with --iio-device-labels
+    labeled: HashMap<(i32, String), GaugeVec>,
+    devices: Option<Arc<Mutex<PortDevices>>>,
+}
+
+impl IioGauges {
+    fn set(&self, socket: i32, metric: IioMetric, value: f64) {
+        let key = (socket, metric.name());
+        if let Some(gauge) = self.plain.get(&key) {
+            gauge.set(value);
+            return;
+        }
+        if let (Some(gauge), Some((ch, port)), Some(devices)) =
+            (self.labeled.get(&key), port_of(metric), &self.devices)
+        {
+            // A port without a device keeps empty labels
+            let labels = devices
+                .lock()
+                .get(socket, ch, port)
+                .map(|device| device.labels())
+                .unwrap_or_default();
+            gauge
+                .with_label_values(&labels.each_ref().map(String::as_str))
+                .set(value);
+        }
+    }
+
+    /// Re-read the port devices when due, dropping series of ports whose device changed
+    fn refresh_devices(&self) {
+        let Some(devices) = &self.devices else {
+            return;
+        };
+        let changed = devices.lock().refresh_if_due();
+        for (socket, ch, port) in changed {
+            for metric in [
+                IioMetric::PCIeInBandwidth(ch, port),
+                IioMetric::PCIeOutBandwidth(ch, port),
+                IioMetric::PCIeUtilization(ch, port),
+            ] {
+                if let Some(gauge) = self.labeled.get(&(socket, metric.name())) {
+                    gauge.reset();
+                }
+            }
+        }
+    }
+}
+
 pub struct IioMetricExporter {
     monitors: Mutex<Vec<IioMonitor>>, // Use Mutex for interior mutability
+    iommu: bool,
+    read_only: bool,
+    count_rates: bool,
     registry: Registry,
-    gauges: HashMap<(i32, String), Gauge>,
+    gauges: IioGauges,
+    rollup: Option<SocketRollup>,
 }
 
 impl IioMetricExporter {
     pub fn new(config: ExportConfig) -> Result<Self> {
         let registry = Registry::new();
         let mut monitors = Vec::new();
-        let mut gauges = HashMap::new();
+        let mut plain = HashMap::new();
+        let mut labeled = HashMap::new();
 
         // Create monitors for each socket
         for &socket in &config.sockets {
-            let monitor = IioMonitor::new(socket)?;
+            let core = config.uncore_core(socket, IioMonitor::default_core(socket));
+            let monitor = if config.read_only_counters {
+                IioMonitor::read_only(socket, core, Msr::instance())?
+            } else {
+                IioMonitor::with_core(socket, core, Msr::instance())?.with_iommu(config.iio_iommu)
+            };
             monitors.push(monitor);
 
             // Register gauges for each metric on this socket
-            for metric in IioMetric::all() {
+            for metric in IioMetric::all()
+                .into_iter()
+                .filter(|&metric| is_exported(metric, &config))
+            {
                 let metric_name = metric.name();
-                let gauge = Gauge::new(
+                let opts = Opts::new(
                     format!("iio_{socket}_{metric_name}"),
                     format!("IIO {metric_name} for socket {socket}"),
-                )?;
-                registry.register(Box::new(gauge.clone()))?;
-                gauges.insert((socket, metric_name), gauge);
+                );
+                if config.iio_device_labels && port_of(metric).is_some() {
+                    let gauge = GaugeVec::new(opts, &DEVICE_LABELS)?;
+                    registry.register(Box::new(gauge.clone()))?;
+                    labeled.insert((socket, metric_name), gauge);
+                } else {
+                    let gauge = Gauge::with_opts(opts)?;
+                    registry.register(Box::new(gauge.clone()))?;
+                    plain.insert((socket, metric_name), gauge);
+                }
             }
         }
 
+        let gauges = IioGauges {
+            plain,
+            labeled,
+            devices: config
+                .iio_device_labels
+                .then(|| Arc::new(Mutex::new(PortDevices::new(config.sockets.clone())))),
+        };
+        gauges.refresh_devices();
+
+        let rollup = if config.rollups {
+            Some(SocketRollup::new(
+                &registry,
+                Opts::new(
+                    "uncflow_socket_total_pcie_bandwidth",
+                    "Inbound plus outbound bandwidth of all PCIe ports in GB/s",
+                ),
+            )?)
+        } else {
+            None
+        };
+
         Ok(Self {
             monitors: Mutex::new(monitors),
+            iommu: config.iio_iommu,
+            read_only: config.read_only_counters,
+            count_rates: config.count_rates,
             registry,
             gauges,
+            rollup,
         })
     }
 
     pub fn start(&self) {
         let monitors = {
             let lock = self.monitors.lock();
-            lock.iter().map(|m| m.socket()).collect::<Vec<_>>()
+            lock.iter()
+                .map(|m| (m.socket(), m.core()))
+                .collect::<Vec<_>>()
         };
         let gauges = self.gauges.clone();
+        let count_rates = self.count_rates;
+        let iommu = self.iommu;
+        let read_only = self.read_only;
 
-        thread::spawn(move || loop {
-            for &socket in &monitors {
-                if let Ok(mut monitor) = IioMonitor::new(socket) {
+        thread::spawn(move || {
+            // Kept across iterations so the event groups rotate
+            let mut monitors: Vec<_> = monitors
+                .into_iter()
+                .filter_map(|(socket, core)| {
+                    if read_only {
+                        IioMonitor::read_only(socket, core, Msr::instance()).ok()
+                    } else {
+                        IioMonitor::with_core(socket, core, Msr::instance())
+                            .ok()
+                            .map(|monitor| monitor.with_iommu(iommu))
+                    }
+                })
+                .collect();
+            loop {
+                gauges.refresh_devices();
+                for monitor in &mut monitors {
+                    let socket = monitor.socket();
                     match monitor.collect_metrics() {
                         Ok(metrics) => {
                             for (metric, value) in metrics {
-                                let metric_name = metric.name();
-                                if let Some(gauge) = gauges.get(&(socket, metric_name)) {
-                                    gauge.set(value);
+                                if let Some(value) = exported_value(metric, value, count_rates) {
+                                    gauges.set(socket, metric, value);
                                 }
                             }
                         }
@@ -75,29 +230,34 @@ impl IioMetricExporter {
                         }
                     }
                 }
+                thread::sleep(Duration::from_secs(1));
             }
-            thread::sleep(Duration::from_secs(1));
         });
     }
 
     /// Collect metrics once (called by orchestrator)
-    pub async fn collect(&self) {
+    pub async fn collect(&self) -> Result<()> {
+        self.gauges.refresh_devices();
         let mut monitors = self.monitors.lock();
-        for monitor in monitors.iter_mut() {
-            let socket = monitor.socket();
-            match monitor.collect_metrics() {
-                Ok(metrics) => {
-                    for (metric, value) in metrics {
-                        let metric_name = metric.name();
-                        if let Some(gauge) = self.gauges.get(&(socket, metric_name)) {
-                            gauge.set(value);
-                        }
-                    }
-                }
-                Err(e) => {
-                    tracing::error!("Failed to collect IIO metrics for socket {}: {}", socket, e);
+        let monitors = monitors.iter_mut().map(|m| (m.socket(), m));
+        collect_sockets("IIO", monitors, |socket, monitor| {
+            let metrics = monitor.collect_metrics()?;
+            if let (Some(rollup), Some(total)) = (&self.rollup, rollup::pcie_bandwidth(&metrics)) {
+                rollup.set(socket, total);
+            }
+            for (metric, value) in metrics {
+                if let Some(value) = exported_value(metric, value, self.count_rates) {
+                    self.gauges.set(socket, metric, value);
                 }
             }
+            Ok(())
+        })
+    }
+
+    /// Drop every socket's baselines
+    pub fn reset(&self) {
+        for monitor in self.monitors.lock().iter_mut() {
+            monitor.reset();
         }
     }
 
@@ -105,3 +265,46 @@ impl IioMetricExporter {
         &self.registry
     }
 }
+
+impl Subsystem for IioMetricExporter {
+    fn name(&self) -> &'static str {
+        "iio"
+    }
+
+    fn init(config: ExportConfig) -> Result<Self> {
+        Self::new(config)
+    }
+
+    fn collect(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(IioMetricExporter::collect(self))
+    }
+
+    fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    fn reset(&self) {
+        IioMetricExporter::reset(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count_rates() {
+        let misses = 4_000.0 * EVENT_GROUP_WINDOW.as_secs_f64();
+        assert_eq!(
+            exported_value(IioMetric::IIOTLBMiss, misses, true),
+            Some(4_000.0)
+        );
+        assert_eq!(
+            exported_value(IioMetric::IIOTLBMiss, misses, false),
+            Some(misses)
+        );
+        // Bandwidth is already per second
+        let bandwidth = IioMetric::PCIeInBandwidth(0, 0);
+        assert_eq!(exported_value(bandwidth, 2.5, true), Some(2.5));
+    }
+}
@@ -1,19 +1,183 @@
-use prometheus::{Gauge, Registry};
-use std::collections::HashMap;
+use futures_util::future::BoxFuture;
+use prometheus::{Gauge, GaugeVec, Opts, Registry};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::task::JoinHandle;
 
+use crate::common::log_limiter;
 use crate::config::ExportConfig;
-use crate::counters::imc::ImcMonitor;
+use crate::counters::imc::{
+    aggregate, peak, sub_sample_period, ImcChannelMetrics, ImcMetrics, ImcMonitor,
+};
 use crate::error::Result;
 use crate::metrics::imc::ImcMetric;
+use crate::orchestrator::{Subsystem, SubsystemSnapshot, COLLECTION_PERIOD};
+use crate::prom::raw::RawCounterGauges;
+use crate::prom::rollup::{self, SocketRollup};
+use uncflow_raw::current_arch::imc::IMC_CHANNEL_COUNT;
+
+/// Per-channel gauges, labeled by socket and IMC channel
+struct ImcChannelGauges {
+    read_bandwidth: GaugeVec,
+    write_bandwidth: GaugeVec,
+    rpq_occupancy: GaugeVec,
+    wpq_occupancy: GaugeVec,
+}
+
+impl ImcChannelGauges {
+    const LABELS: [&'static str; 2] = ["socket", "channel"];
+
+    fn new(registry: &Registry, instance_label: &str) -> Result<Self> {
+        let gauge_vec = |name: &str, help: &str| -> Result<GaugeVec> {
+            let gauge = GaugeVec::new(
+                Opts::new(name, help).const_label("instance", instance_label),
+                &Self::LABELS,
+            )?;
+            registry.register(Box::new(gauge.clone()))?;
+            Ok(gauge)
+        };
+
+        Ok(Self {
+            read_bandwidth: gauge_vec(
+                "IMCChannelReadBandwidth",
+                "Per-channel memory read bandwidth in bytes/sec",
+            )?,
+            write_bandwidth: gauge_vec(
+                "IMCChannelWriteBandwidth",
+                "Per-channel memory write bandwidth in bytes/sec",
+            )?,
+            rpq_occupancy: gauge_vec(
+                "IMCChannelRPQOccupancy",
+                "Per-channel read pending queue occupancy",
+            )?,
+            wpq_occupancy: gauge_vec(
+                "IMCChannelWPQOccupancy",
+                "Per-channel write pending queue occupancy",
+            )?,
+        })
+    }
+
+    fn set(&self, socket_id: i32, metrics: &ImcMetrics) {
+        let socket = socket_id.to_string();
+        for (channel, c) in &metrics.channels {
+            let channel = channel.to_string();
+            let labels = [socket.as_str(), channel.as_str()];
+            self.read_bandwidth
+                .with_label_values(&labels)
+                .set(c.read_bandwidth as f64);
+            self.write_bandwidth
+                .with_label_values(&labels)
+                .set(c.write_bandwidth as f64);
+            self.rpq_occupancy
+                .with_label_values(&labels)
+                .set(c.rpq_occupancy as f64);
+            self.wpq_occupancy
+                .with_label_values(&labels)
+                .set(c.wpq_occupancy as f64);
+        }
+    }
+}
+
+/// Per-NUMA-node sums of the channel gauges, labeled by socket and node
+struct ImcNodeGauges {
+    read_bandwidth: GaugeVec,
+    write_bandwidth: GaugeVec,
+    rpq_occupancy: GaugeVec,
+    wpq_occupancy: GaugeVec,
+}
+
+impl ImcNodeGauges {
+    const LABELS: [&'static str; 2] = ["socket", "numa_node"];
+
+    fn new(registry: &Registry, instance_label: &str) -> Result<Self> {
+        let gauge_vec = |name: &str, help: &str| -> Result<GaugeVec> {
+            let gauge = GaugeVec::new(
+                Opts::new(name, help).const_label("instance", instance_label),
+                &Self::LABELS,
+            )?;
+            registry.register(Box::new(gauge.clone()))?;
+            Ok(gauge)
+        };
+
+        Ok(Self {
+            read_bandwidth: gauge_vec(
+                "IMCNodeReadBandwidth",
+                "Memory read bandwidth of the IMC channels of a NUMA node in bytes/sec",
+            )?,
+            write_bandwidth: gauge_vec(
+                "IMCNodeWriteBandwidth",
+                "Memory write bandwidth of the IMC channels of a NUMA node in bytes/sec",
+            )?,
+            rpq_occupancy: gauge_vec(
+                "IMCNodeRPQOccupancy",
+                "Read pending queue occupancy of the IMC channels of a NUMA node",
+            )?,
+            wpq_occupancy: gauge_vec(
+                "IMCNodeWPQOccupancy",
+                "Write pending queue occupancy of the IMC channels of a NUMA node",
+            )?,
+        })
+    }
+
+    fn set(&self, config: &ExportConfig, socket_id: i32, metrics: &ImcMetrics) {
+        let mut nodes: BTreeMap<i32, ImcChannelMetrics> = BTreeMap::new();
+        for (&channel, c) in &metrics.channels {
+            let Some(node) =
+                config.numa_node_of_unit(socket_id, channel as usize, IMC_CHANNEL_COUNT)
+            else {
+                continue;
+            };
+            let sum = nodes.entry(node).or_default();
+            sum.read_bandwidth += c.read_bandwidth;
+            sum.write_bandwidth += c.write_bandwidth;
+            sum.rpq_occupancy += c.rpq_occupancy;
+            sum.wpq_occupancy += c.wpq_occupancy;
+        }
+
+        let socket = socket_id.to_string();
+        for (node, sum) in nodes {
+            let node = node.to_string();
+            let labels = [socket.as_str(), node.as_str()];
+            self.read_bandwidth
+                .with_label_values(&labels)
+                .set(sum.read_bandwidth as f64);
+            self.write_bandwidth
+                .with_label_values(&labels)
+                .set(sum.write_bandwidth as f64);
+            self.rpq_occupancy
+                .with_label_values(&labels)
+                .set(sum.rpq_occupancy as f64);
+            self.wpq_occupancy
+                .with_label_values(&labels)
+                .set(sum.wpq_occupancy as f64);
+        }
+    }
+}
 
 pub struct ImcMetricExporter {
     config: ExportConfig,
     registry: Arc<Registry>,
     monitor: Arc<parking_lot::Mutex<HashMap<i32, ImcMonitor>>>,
     socket_gauges: HashMap<ImcMetric, HashMap<i32, Gauge>>,
+    channel_gauges: Option<ImcChannelGauges>,
+    node_gauges: Option<ImcNodeGauges>,
+    raw_gauges: Option<RawCounterGauges>,
+    rollup: Option<SocketRollup>,
+    // Peak bandwidth in bytes/s of the sockets whose peak is known
+    peak_bandwidth: HashMap<i32, f64>,
+    // Slowest DIMM speed in MT/s from DMI, to cross-check the measured data rate
+    memory_speed: Option<u32>,
+    // Sockets whose measured data rate was already checked against memory_speed
+    data_rate_checked: parking_lot::Mutex<HashSet<i32>>,
+    // Sockets counting PMM traffic
+    pmem_sockets: Vec<i32>,
+    // Sockets counting DRAM page events
+    page_sockets: Vec<i32>,
+    // Sockets counting thermal throttle cycles
+    thermal_sockets: Vec<i32>,
+    // imc_dimm_temperature_celsius{socket,channel,dimm}, with --imc-thermal
+    dimm_temperature: Option<GaugeVec>,
 }
 
 impl ImcMetricExporter {
@@ -22,8 +186,9 @@ impl ImcMetricExporter {
 
         let mut monitors = HashMap::new();
         for &socket in &config.sockets {
-            match ImcMonitor::new(socket) {
-                Ok(mut monitor) => {
+            match ImcMonitor::with_page_events(socket, config.imc_page_events) {
+                Ok(monitor) => {
+                    let mut monitor = monitor.with_thermal(config.imc_thermal);
                     monitor.initialize()?;
                     monitors.insert(socket, monitor);
                     tracing::info!("Initialized IMC monitor for socket {}", socket);
@@ -34,6 +199,23 @@ impl ImcMetricExporter {
             }
         }
 
+        let memory_speed = peak::detect_memory_speed();
+        let peak_bandwidth = Self::peak_bandwidth(&config, &monitors, memory_speed);
+        let pmem_sockets = monitors
+            .iter()
+            .filter(|(_, mon)| mon.has_pmem())
+            .map(|(&socket, _)| socket)
+            .collect();
+        let page_sockets = monitors
+            .iter()
+            .filter(|(_, mon)| mon.has_page_events())
+            .map(|(&socket, _)| socket)
+            .collect();
+        let thermal_sockets = monitors
+            .iter()
+            .filter(|(_, mon)| mon.has_thermal())
+            .map(|(&socket, _)| socket)
+            .collect();
         let monitor = Arc::new(parking_lot::Mutex::new(monitors));
 
         let mut exporter = Self {
@@ -41,6 +223,17 @@ impl ImcMetricExporter {
             registry: Arc::clone(&registry),
             monitor,
             socket_gauges: HashMap::new(),
+            channel_gauges: None,
+            node_gauges: None,
+            raw_gauges: None,
+            rollup: None,
+            peak_bandwidth,
+            memory_speed,
+            data_rate_checked: parking_lot::Mutex::new(HashSet::new()),
+            pmem_sockets,
+            page_sockets,
+            thermal_sockets,
+            dimm_temperature: None,
         };
 
         exporter.register_metrics()?;
@@ -48,6 +241,62 @@ impl ImcMetricExporter {
         Ok(exporter)
     }
 
+    // Warn once per socket when the measured data rate disagrees with DMI
+    fn check_data_rate(&self, socket: i32, data_rate: f64) {
+        let Some(speed) = self.memory_speed else {
+            return;
+        };
+        // DCLK not counting, or no window yet
+        if data_rate <= 0.0 || !self.data_rate_checked.lock().insert(socket) {
+            return;
+        }
+        if peak::data_rate_mismatch(data_rate, speed) {
+            tracing::warn!(
+                "Socket {} memory runs at {:.0} MT/s by DCLK, DMI reports {} MT/s",
+                socket,
+                data_rate,
+                speed
+            );
+        } else {
+            tracing::info!("Socket {} memory runs at {:.0} MT/s", socket, data_rate);
+        }
+    }
+
+    // Configured peak, else DIMM speed from DMI times the detected channels
+    fn peak_bandwidth(
+        config: &ExportConfig,
+        monitors: &HashMap<i32, ImcMonitor>,
+        memory_speed: Option<u32>,
+    ) -> HashMap<i32, f64> {
+        if let Some(gbps) = config.memory_peak_bandwidth_gbps {
+            return monitors
+                .keys()
+                .map(|&socket| (socket, gbps * 1e9))
+                .collect();
+        }
+
+        let Some(speed) = memory_speed else {
+            tracing::warn!(
+                "Memory speed not found in DMI, set --memory-peak-bandwidth-gbps to export imc_bandwidth_saturation_ratio"
+            );
+            return HashMap::new();
+        };
+        monitors
+            .iter()
+            .map(|(&socket, mon)| {
+                let peak = peak::peak_bandwidth(speed, mon.channel_count());
+                tracing::info!(
+                    "Socket {} peak memory bandwidth: {:.1} GB/s ({} MT/s x {} channels)",
+                    socket,
+                    peak / 1e9,
+                    speed,
+                    mon.channel_count()
+                );
+                (socket, peak)
+            })
+            .collect()
+    }
+
     fn register_metrics(&mut self) -> Result<()> {
         let instance_label = std::env::var("INSTANCE_LABEL").unwrap_or_else(|_| "none".to_string());
 
@@ -57,6 +306,33 @@ impl ImcMetricExporter {
 
             let mut socket_map = HashMap::new();
             for &socket_id in &self.config.sockets {
+                // No peak, no meaningful ratio
+                if metric == ImcMetric::BandwidthSaturationRatio
+                    && !self.peak_bandwidth.contains_key(&socket_id)
+                {
+                    continue;
+                }
+                if matches!(
+                    metric,
+                    ImcMetric::PmemReadBandwidth | ImcMetric::PmemWriteBandwidth
+                ) && !self.pmem_sockets.contains(&socket_id)
+                {
+                    continue;
+                }
+                if matches!(
+                    metric,
+                    ImcMetric::PageHitRatio
+                        | ImcMetric::PageMissRatio
+                        | ImcMetric::PageConflictRatio
+                ) && !self.page_sockets.contains(&socket_id)
+                {
+                    continue;
+                }
+                if metric == ImcMetric::ThermalThrottleCycles
+                    && !self.thermal_sockets.contains(&socket_id)
+                {
+                    continue;
+                }
                 let gauge = Gauge::with_opts(
                     opts.clone()
                         .const_label("socket", socket_id.to_string())
@@ -68,6 +344,51 @@ impl ImcMetricExporter {
             self.socket_gauges.insert(metric, socket_map);
         }
 
+        if self.config.imc_per_channel {
+            self.channel_gauges = Some(ImcChannelGauges::new(&self.registry, &instance_label)?);
+            tracing::info!("Exporting per-channel IMC metrics");
+        }
+
+        if !self.config.numa_nodes.is_empty() {
+            self.node_gauges = Some(ImcNodeGauges::new(&self.registry, &instance_label)?);
+        }
+
+        if self.config.imc_thermal {
+            let gauge = GaugeVec::new(
+                Opts::new(
+                    "imc_dimm_temperature_celsius",
+                    "Last DIMM temperature read by the IMC in degrees C",
+                )
+                .const_label("instance", &instance_label),
+                &["socket", "channel", "dimm"],
+            )?;
+            self.registry.register(Box::new(gauge.clone()))?;
+            self.dimm_temperature = Some(gauge);
+        }
+
+        if self.config.export_raw {
+            self.raw_gauges = Some(RawCounterGauges::new(
+                &self.registry,
+                Opts::new(
+                    "IMCRawCounter",
+                    "Per-channel IMC counter delta over the export interval before derivation",
+                )
+                .const_label("instance", &instance_label),
+                &ImcChannelGauges::LABELS,
+            )?);
+        }
+
+        if self.config.rollups {
+            self.rollup = Some(SocketRollup::new(
+                &self.registry,
+                Opts::new(
+                    "uncflow_socket_total_memory_bandwidth",
+                    "Read plus write memory bandwidth of all IMC channels in bytes/sec",
+                )
+                .const_label("instance", &instance_label),
+            )?);
+        }
+
         Ok(())
     }
 
@@ -87,7 +408,7 @@ impl ImcMetricExporter {
                 let mut monitors = monitor.lock();
 
                 if let Some(mon) = monitors.get_mut(&socket_id) {
-                    if let Ok(metrics) = mon.collect() {
+                    if let Ok(Some(metrics)) = mon.collect() {
                         drop(monitors);
 
                         // Update bandwidth gauges
@@ -221,146 +542,320 @@ impl ImcMetricExporter {
     }
 
     /// Collect metrics once (called by orchestrator)
-    pub async fn collect(&self) {
-        for &socket_id in &self.config.sockets {
-            let mut monitors = self.monitor.lock();
+    ///
+    /// Failed samples are left out of their socket's window; the first error is
+    /// returned after the remaining sockets have been collected.
+    pub async fn collect(&self) -> Result<()> {
+        let mut failure = None;
+        let sample_count = self.config.sample_count.max(1);
+        let sub_period =
+            sub_sample_period(COLLECTION_PERIOD, sample_count, self.config.read_timeout);
+
+        // Sub-sample all sockets together so the windows line up across sockets
+        let mut samples: HashMap<i32, Vec<ImcMetrics>> = HashMap::new();
+        for i in 0..sample_count {
+            if i > 0 {
+                tokio::time::sleep(sub_period).await;
+            }
 
-            if let Some(mon) = monitors.get_mut(&socket_id) {
-                if let Ok(metrics) = mon.collect() {
-                    drop(monitors);
-
-                    // Update bandwidth gauges
-                    if let Some(gauge) = self
-                        .socket_gauges
-                        .get(&ImcMetric::MemoryReadBandwidth)
-                        .and_then(|m| m.get(&socket_id))
-                    {
-                        gauge.set(metrics.read_bandwidth as f64);
-                    }
-                    if let Some(gauge) = self
-                        .socket_gauges
-                        .get(&ImcMetric::MemoryWriteBandwidth)
-                        .and_then(|m| m.get(&socket_id))
-                    {
-                        gauge.set(metrics.write_bandwidth as f64);
+            let mut monitors = self.monitor.lock();
+            for &socket_id in &self.config.sockets {
+                if let Some(mon) = monitors.get_mut(&socket_id) {
+                    let source = format!("IMC socket {socket_id}");
+                    match mon.collect() {
+                        Ok(metrics) => {
+                            log_limiter().clear(&source);
+                            // Nothing yet on the first collect, which reads the baseline
+                            if let Some(metrics) = metrics {
+                                samples.entry(socket_id).or_default().push(metrics);
+                            }
+                        }
+                        Err(e) => {
+                            if log_limiter().allow(&source, &e.to_string()) {
+                                tracing::error!(
+                                    "Failed to collect IMC metrics for socket {}: {}",
+                                    socket_id,
+                                    e
+                                );
+                            }
+                            failure.get_or_insert(e);
+                        }
                     }
+                }
+            }
+        }
 
-                    // Update latency gauges
-                    if let Some(gauge) = self
-                        .socket_gauges
-                        .get(&ImcMetric::MemoryReadLatency)
-                        .and_then(|m| m.get(&socket_id))
-                    {
-                        gauge.set(metrics.read_latency);
-                    }
-                    if let Some(gauge) = self
-                        .socket_gauges
-                        .get(&ImcMetric::MemoryWriteLatency)
-                        .and_then(|m| m.get(&socket_id))
-                    {
-                        gauge.set(metrics.write_latency);
-                    }
+        // Temperatures change slowly, one read per interval is enough
+        if let Some(gauge) = &self.dimm_temperature {
+            let monitors = self.monitor.lock();
+            for (socket_id, mon) in monitors.iter() {
+                let socket = socket_id.to_string();
+                for ((channel, dimm), celsius) in mon.dimm_temperatures() {
+                    gauge
+                        .with_label_values(&[
+                            socket.as_str(),
+                            channel.to_string().as_str(),
+                            dimm.to_string().as_str(),
+                        ])
+                        .set(celsius);
+                }
+            }
+        }
 
-                    // Update queue occupancy gauges
-                    if let Some(gauge) = self
-                        .socket_gauges
-                        .get(&ImcMetric::MemoryRPQOccupancy)
-                        .and_then(|m| m.get(&socket_id))
-                    {
-                        gauge.set(metrics.rpq_occupancy as f64);
-                    }
-                    if let Some(gauge) = self
-                        .socket_gauges
-                        .get(&ImcMetric::MemoryWPQOccupancy)
-                        .and_then(|m| m.get(&socket_id))
-                    {
-                        gauge.set(metrics.wpq_occupancy as f64);
-                    }
+        for (socket_id, socket_samples) in samples {
+            let window = aggregate(&socket_samples);
+            let metrics = &window.mean;
 
-                    // Update queue status gauges
-                    if let Some(gauge) = self
-                        .socket_gauges
-                        .get(&ImcMetric::IMCRPQNonEmpty)
-                        .and_then(|m| m.get(&socket_id))
-                    {
-                        gauge.set(metrics.rpq_non_empty);
-                    }
-                    if let Some(gauge) = self
-                        .socket_gauges
-                        .get(&ImcMetric::IMCRPQFull)
-                        .and_then(|m| m.get(&socket_id))
-                    {
-                        gauge.set(metrics.rpq_full);
-                    }
-                    if let Some(gauge) = self
-                        .socket_gauges
-                        .get(&ImcMetric::IMCWPQNonEmpty)
-                        .and_then(|m| m.get(&socket_id))
-                    {
-                        gauge.set(metrics.wpq_non_empty);
-                    }
-                    if let Some(gauge) = self
-                        .socket_gauges
-                        .get(&ImcMetric::IMCWPQFull)
-                        .and_then(|m| m.get(&socket_id))
-                    {
-                        gauge.set(metrics.wpq_full);
+            if let Some(channel_gauges) = &self.channel_gauges {
+                channel_gauges.set(socket_id, metrics);
+            }
+            if let Some(node_gauges) = &self.node_gauges {
+                node_gauges.set(&self.config, socket_id, metrics);
+            }
+            if let Some(rollup) = &self.rollup {
+                rollup.set(socket_id, rollup::memory_bandwidth(metrics));
+            }
+            if let Some(raw_gauges) = &self.raw_gauges {
+                let socket = socket_id.to_string();
+                for (channel, deltas) in &metrics.deltas {
+                    let channel = channel.to_string();
+                    for (counter, delta) in deltas.named() {
+                        raw_gauges.set(&[socket.as_str(), channel.as_str()], counter, delta);
                     }
+                }
+            }
 
-                    // Update frequency gauge
-                    if let Some(gauge) = self
-                        .socket_gauges
-                        .get(&ImcMetric::IMCFrequency)
-                        .and_then(|m| m.get(&socket_id))
-                    {
-                        gauge.set(metrics.frequency);
-                    }
+            // Update bandwidth gauges
+            if let Some(gauge) = self
+                .socket_gauges
+                .get(&ImcMetric::MemoryReadBandwidth)
+                .and_then(|m| m.get(&socket_id))
+            {
+                gauge.set(metrics.read_bandwidth as f64);
+            }
+            if let Some(gauge) = self
+                .socket_gauges
+                .get(&ImcMetric::MemoryWriteBandwidth)
+                .and_then(|m| m.get(&socket_id))
+            {
+                gauge.set(metrics.write_bandwidth as f64);
+            }
+            if let (Some(gauge), Some(&peak)) = (
+                self.socket_gauges
+                    .get(&ImcMetric::BandwidthSaturationRatio)
+                    .and_then(|m| m.get(&socket_id)),
+                self.peak_bandwidth.get(&socket_id),
+            ) {
+                gauge.set(peak::saturation_ratio(
+                    metrics.read_bandwidth + metrics.write_bandwidth,
+                    peak,
+                ));
+            }
 
-                    // NUMA metrics
-                    if let Some(gauge) = self
-                        .socket_gauges
-                        .get(&ImcMetric::MemoryLocalReadBandwidth)
-                        .and_then(|m| m.get(&socket_id))
-                    {
-                        gauge.set(metrics.read_bandwidth as f64);
-                    }
-                    if let Some(gauge) = self
-                        .socket_gauges
-                        .get(&ImcMetric::MemoryLocalWriteBandwidth)
-                        .and_then(|m| m.get(&socket_id))
-                    {
-                        gauge.set(metrics.write_bandwidth as f64);
-                    }
-                    if let Some(gauge) = self
-                        .socket_gauges
-                        .get(&ImcMetric::MemoryRemoteReadBandwidth)
-                        .and_then(|m| m.get(&socket_id))
-                    {
-                        gauge.set(0.0);
-                    }
-                    if let Some(gauge) = self
-                        .socket_gauges
-                        .get(&ImcMetric::MemoryRemoteWriteBandwidth)
-                        .and_then(|m| m.get(&socket_id))
-                    {
-                        gauge.set(0.0);
-                    }
-                    if let Some(gauge) = self
-                        .socket_gauges
-                        .get(&ImcMetric::MemoryLocalReadRatio)
-                        .and_then(|m| m.get(&socket_id))
-                    {
-                        gauge.set(1.0);
-                    }
-                    if let Some(gauge) = self
-                        .socket_gauges
-                        .get(&ImcMetric::MemoryLocalWriteRatio)
-                        .and_then(|m| m.get(&socket_id))
-                    {
-                        gauge.set(1.0);
-                    }
+            // PMEM media traffic, only registered on sockets with PMEM
+            if let Some(gauge) = self
+                .socket_gauges
+                .get(&ImcMetric::PmemReadBandwidth)
+                .and_then(|m| m.get(&socket_id))
+            {
+                gauge.set(metrics.pmem_read_bandwidth as f64);
+            }
+            if let Some(gauge) = self
+                .socket_gauges
+                .get(&ImcMetric::PmemWriteBandwidth)
+                .and_then(|m| m.get(&socket_id))
+            {
+                gauge.set(metrics.pmem_write_bandwidth as f64);
+            }
+
+            // Write/read mix; page ratios and throttling are only registered on sockets
+            // counting page events or thermal throttling
+            for (metric, value) in [
+                (ImcMetric::WriteReadRatio, metrics.write_read_ratio),
+                (ImcMetric::PageHitRatio, metrics.page_hit_ratio),
+                (ImcMetric::PageMissRatio, metrics.page_miss_ratio),
+                (ImcMetric::PageConflictRatio, metrics.page_conflict_ratio),
+                (
+                    ImcMetric::ThermalThrottleCycles,
+                    metrics.thermal_throttle_cycles as f64,
+                ),
+            ] {
+                if let Some(gauge) = self
+                    .socket_gauges
+                    .get(&metric)
+                    .and_then(|m| m.get(&socket_id))
+                {
+                    gauge.set(value);
                 }
             }
+
+            // Bandwidth extremes across sub-samples
+            if let Some(gauge) = self
+                .socket_gauges
+                .get(&ImcMetric::MemoryReadBandwidthMin)
+                .and_then(|m| m.get(&socket_id))
+            {
+                gauge.set(window.read_bandwidth.min);
+            }
+            if let Some(gauge) = self
+                .socket_gauges
+                .get(&ImcMetric::MemoryReadBandwidthMax)
+                .and_then(|m| m.get(&socket_id))
+            {
+                gauge.set(window.read_bandwidth.max);
+            }
+            if let Some(gauge) = self
+                .socket_gauges
+                .get(&ImcMetric::MemoryWriteBandwidthMin)
+                .and_then(|m| m.get(&socket_id))
+            {
+                gauge.set(window.write_bandwidth.min);
+            }
+            if let Some(gauge) = self
+                .socket_gauges
+                .get(&ImcMetric::MemoryWriteBandwidthMax)
+                .and_then(|m| m.get(&socket_id))
+            {
+                gauge.set(window.write_bandwidth.max);
+            }
+
+            // Update latency gauges
+            if let Some(gauge) = self
+                .socket_gauges
+                .get(&ImcMetric::MemoryReadLatency)
+                .and_then(|m| m.get(&socket_id))
+            {
+                gauge.set(metrics.read_latency);
+            }
+            if let Some(gauge) = self
+                .socket_gauges
+                .get(&ImcMetric::MemoryWriteLatency)
+                .and_then(|m| m.get(&socket_id))
+            {
+                gauge.set(metrics.write_latency);
+            }
+
+            // Update queue occupancy gauges
+            if let Some(gauge) = self
+                .socket_gauges
+                .get(&ImcMetric::MemoryRPQOccupancy)
+                .and_then(|m| m.get(&socket_id))
+            {
+                gauge.set(metrics.rpq_occupancy as f64);
+            }
+            if let Some(gauge) = self
+                .socket_gauges
+                .get(&ImcMetric::MemoryWPQOccupancy)
+                .and_then(|m| m.get(&socket_id))
+            {
+                gauge.set(metrics.wpq_occupancy as f64);
+            }
+
+            // Update queue status gauges
+            if let Some(gauge) = self
+                .socket_gauges
+                .get(&ImcMetric::IMCRPQNonEmpty)
+                .and_then(|m| m.get(&socket_id))
+            {
+                gauge.set(metrics.rpq_non_empty);
+            }
+            if let Some(gauge) = self
+                .socket_gauges
+                .get(&ImcMetric::IMCRPQFull)
+                .and_then(|m| m.get(&socket_id))
+            {
+                gauge.set(metrics.rpq_full);
+            }
+            if let Some(gauge) = self
+                .socket_gauges
+                .get(&ImcMetric::IMCWPQNonEmpty)
+                .and_then(|m| m.get(&socket_id))
+            {
+                gauge.set(metrics.wpq_non_empty);
+            }
+            if let Some(gauge) = self
+                .socket_gauges
+                .get(&ImcMetric::IMCWPQFull)
+                .and_then(|m| m.get(&socket_id))
+            {
+                gauge.set(metrics.wpq_full);
+            }
+
+            // Update frequency gauge
+            if let Some(gauge) = self
+                .socket_gauges
+                .get(&ImcMetric::IMCFrequency)
+                .and_then(|m| m.get(&socket_id))
+            {
+                gauge.set(metrics.frequency);
+            }
+            let data_rate = peak::data_rate_mts(metrics.frequency);
+            if let Some(gauge) = self
+                .socket_gauges
+                .get(&ImcMetric::DataRate)
+                .and_then(|m| m.get(&socket_id))
+            {
+                gauge.set(data_rate);
+            }
+            self.check_data_rate(socket_id, data_rate);
+
+            // NUMA metrics
+            if let Some(gauge) = self
+                .socket_gauges
+                .get(&ImcMetric::MemoryLocalReadBandwidth)
+                .and_then(|m| m.get(&socket_id))
+            {
+                gauge.set(metrics.read_bandwidth as f64);
+            }
+            if let Some(gauge) = self
+                .socket_gauges
+                .get(&ImcMetric::MemoryLocalWriteBandwidth)
+                .and_then(|m| m.get(&socket_id))
+            {
+                gauge.set(metrics.write_bandwidth as f64);
+            }
+            if let Some(gauge) = self
+                .socket_gauges
+                .get(&ImcMetric::MemoryRemoteReadBandwidth)
+                .and_then(|m| m.get(&socket_id))
+            {
+                gauge.set(0.0);
+            }
+            if let Some(gauge) = self
+                .socket_gauges
+                .get(&ImcMetric::MemoryRemoteWriteBandwidth)
+                .and_then(|m| m.get(&socket_id))
+            {
+                gauge.set(0.0);
+            }
+            if let Some(gauge) = self
+                .socket_gauges
+                .get(&ImcMetric::MemoryLocalReadRatio)
+                .and_then(|m| m.get(&socket_id))
+            {
+                gauge.set(1.0);
+            }
+            if let Some(gauge) = self
+                .socket_gauges
+                .get(&ImcMetric::MemoryLocalWriteRatio)
+                .and_then(|m| m.get(&socket_id))
+            {
+                gauge.set(1.0);
+            }
+        }
+        failure.map_or(Ok(()), Err)
+    }
+
+    /// Take a fresh baseline on every socket
+    pub fn reset(&self) {
+        for (socket_id, mon) in self.monitor.lock().iter_mut() {
+            if let Err(e) = mon.reset() {
+                tracing::warn!(
+                    "Failed to reset IMC baseline for socket {}: {}",
+                    socket_id,
+                    e
+                );
+            }
         }
     }
 
@@ -368,3 +863,35 @@ impl ImcMetricExporter {
         Arc::clone(&self.registry)
     }
 }
+
+impl Subsystem for ImcMetricExporter {
+    fn name(&self) -> &'static str {
+        "imc"
+    }
+
+    fn init(config: ExportConfig) -> Result<Self> {
+        Self::new(config)
+    }
+
+    fn collect(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(ImcMetricExporter::collect(self))
+    }
+
+    fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    fn reset(&self) {
+        ImcMetricExporter::reset(self)
+    }
+
+    fn snapshot(&self) -> Option<Result<SubsystemSnapshot>> {
+        let monitors = self.monitor.lock();
+        let snapshots = monitors
+            .iter()
+            .map(|(&socket, monitor)| Ok((socket, monitor.snapshot()?)))
+            .collect::<Result<_>>()
+            .map(SubsystemSnapshot::Imc);
+        Some(snapshots)
+    }
+}
@@ -1,19 +1,62 @@
 // IRP Metrics Exporter
 
-use crate::counters::irp::IrpMonitor;
+use crate::counters::irp::{IrpMetrics, IrpMonitor};
 use crate::error::Result;
 use crate::metrics::irp::IrpMetric;
+use crate::orchestrator::Subsystem;
+use crate::prom::sockets::collect_sockets;
 use crate::ExportConfig;
-use prometheus::{Gauge, Registry};
+use futures_util::future::BoxFuture;
+use parking_lot::Mutex;
+use prometheus::{Gauge, GaugeVec, Opts, Registry};
 use std::collections::HashMap;
 
 use std::thread;
 use std::time::Duration;
 
+/// Per-unit gauges, labeled by socket and IRP unit
+struct IrpUnitGauges {
+    gauges: HashMap<IrpMetric, GaugeVec>,
+}
+
+impl IrpUnitGauges {
+    const LABELS: [&'static str; 2] = ["socket", "irp_unit"];
+
+    fn new(registry: &Registry) -> Result<Self> {
+        let mut gauges = HashMap::new();
+        for metric in IrpMetric::all() {
+            // IRPAllBandwidth becomes IRPUnitAllBandwidth
+            let name = format!("IRPUnit{}", metric.name().trim_start_matches("IRP"));
+            let gauge = GaugeVec::new(
+                Opts::new(&name, format!("Per-unit IRP {} metric", metric.name())),
+                &Self::LABELS,
+            )?;
+            registry.register(Box::new(gauge.clone()))?;
+            gauges.insert(metric, gauge);
+        }
+        Ok(Self { gauges })
+    }
+
+    fn set(&self, socket: i32, metrics: &IrpMetrics) {
+        let socket = socket.to_string();
+        for (unit, unit_metrics) in metrics.units.iter().enumerate() {
+            let unit = unit.to_string();
+            for (metric, &value) in unit_metrics {
+                if let Some(gauge) = self.gauges.get(metric) {
+                    gauge
+                        .with_label_values(&[socket.as_str(), unit.as_str()])
+                        .set(value);
+                }
+            }
+        }
+    }
+}
+
 pub struct IrpMetricExporter {
-    monitors: Vec<IrpMonitor>,
+    monitors: Mutex<Vec<IrpMonitor>>,
     registry: Registry,
     gauges: HashMap<(i32, IrpMetric), Gauge>,
+    unit_gauges: Option<IrpUnitGauges>,
 }
 
 impl IrpMetricExporter {
@@ -24,7 +67,8 @@ impl IrpMetricExporter {
 
         // Create monitors for each socket
         for &socket in &config.sockets {
-            let monitor = IrpMonitor::new(socket)?;
+            let core = config.uncore_core(socket, IrpMonitor::default_core(socket));
+            let monitor = IrpMonitor::with_core(socket, core)?;
             monitors.push(monitor);
         }
 
@@ -42,23 +86,41 @@ impl IrpMetricExporter {
             }
         }
 
+        let unit_gauges = if config.irp_per_unit {
+            Some(IrpUnitGauges::new(&registry)?)
+        } else {
+            None
+        };
+
         Ok(Self {
-            monitors,
+            monitors: Mutex::new(monitors),
             registry,
             gauges,
+            unit_gauges,
         })
     }
 
     pub fn start(&self) {
-        let monitors = self.monitors.iter().map(|m| m.socket()).collect::<Vec<_>>();
+        let monitors = self
+            .monitors
+            .lock()
+            .iter()
+            .map(|m| (m.socket(), m.core()))
+            .collect::<Vec<_>>();
         let gauges = self.gauges.clone();
 
-        thread::spawn(move || loop {
-            for &socket in &monitors {
-                if let Ok(mut monitor) = IrpMonitor::new(socket) {
+        thread::spawn(move || {
+            // Kept across iterations so the event passes rotate
+            let mut monitors: Vec<_> = monitors
+                .into_iter()
+                .filter_map(|(socket, core)| IrpMonitor::with_core(socket, core).ok())
+                .collect();
+            loop {
+                for monitor in &mut monitors {
+                    let socket = monitor.socket();
                     match monitor.collect_metrics() {
                         Ok(metrics) => {
-                            for (metric, value) in metrics {
+                            for (metric, value) in metrics.total {
                                 if let Some(gauge) = gauges.get(&(socket, metric)) {
                                     gauge.set(value);
                                 }
@@ -73,34 +135,33 @@ impl IrpMetricExporter {
                         }
                     }
                 }
+                thread::sleep(Duration::from_secs(1));
             }
-            thread::sleep(Duration::from_secs(1));
         });
     }
 
     /// Collect metrics once (called by orchestrator)
-    pub async fn collect(&self) {
-        let sockets: Vec<_> = self.monitors.iter().map(|m| m.socket()).collect();
-
-        for &socket in &sockets {
-            if let Ok(mut monitor) = IrpMonitor::new(socket) {
-                match monitor.collect_metrics() {
-                    Ok(metrics) => {
-                        for (metric, value) in metrics {
-                            if let Some(gauge) = self.gauges.get(&(socket, metric)) {
-                                gauge.set(value);
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        tracing::error!(
-                            "Failed to collect IRP metrics for socket {}: {}",
-                            socket,
-                            e
-                        );
-                    }
+    pub async fn collect(&self) -> Result<()> {
+        let mut monitors = self.monitors.lock();
+        let monitors = monitors.iter_mut().map(|m| (m.socket(), m));
+        collect_sockets("IRP", monitors, |socket, monitor| {
+            let metrics = monitor.collect_metrics()?;
+            for (&metric, &value) in &metrics.total {
+                if let Some(gauge) = self.gauges.get(&(socket, metric)) {
+                    gauge.set(value);
                 }
             }
+            if let Some(unit_gauges) = &self.unit_gauges {
+                unit_gauges.set(socket, &metrics);
+            }
+            Ok(())
+        })
+    }
+
+    /// Drop every socket's cached counts
+    pub fn reset(&self) {
+        for monitor in self.monitors.lock().iter_mut() {
+            monitor.reset();
         }
     }
 
@@ -108,3 +169,25 @@ impl IrpMetricExporter {
         &self.registry
     }
 }
+
+impl Subsystem for IrpMetricExporter {
+    fn name(&self) -> &'static str {
+        "irp"
+    }
+
+    fn init(config: ExportConfig) -> Result<Self> {
+        Self::new(config)
+    }
+
+    fn collect(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(IrpMetricExporter::collect(self))
+    }
+
+    fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    fn reset(&self) {
+        IrpMetricExporter::reset(self)
+    }
+}
@@ -0,0 +1,123 @@
+// M2M Metrics Exporter
+
+use crate::counters::m2m::M2mMonitor;
+use crate::error::Result;
+use crate::metrics::m2m::M2mMetric;
+use crate::orchestrator::Subsystem;
+use crate::prom::raw::RawCounterGauges;
+use crate::prom::sockets::collect_sockets;
+use crate::ExportConfig;
+use futures_util::future::BoxFuture;
+use parking_lot::Mutex;
+use prometheus::{Gauge, Opts, Registry};
+use std::collections::HashMap;
+
+pub struct M2mMetricExporter {
+    monitors: Mutex<Vec<M2mMonitor>>,
+    registry: Registry,
+    gauges: HashMap<(i32, M2mMetric), Gauge>,
+    raw_gauges: Option<RawCounterGauges>,
+}
+
+impl M2mMetricExporter {
+    pub fn new(config: ExportConfig) -> Result<Self> {
+        let registry = Registry::new();
+        let mut monitors = Vec::new();
+        let mut gauges = HashMap::new();
+
+        // Create monitors for each socket
+        for &socket in &config.sockets {
+            match M2mMonitor::new(socket) {
+                Ok(monitor) => monitors.push(monitor),
+                Err(e) => {
+                    tracing::warn!("Failed to initialize M2M for socket {}: {}", socket, e);
+                }
+            }
+        }
+
+        for monitor in &monitors {
+            let socket = monitor.socket();
+            for metric in M2mMetric::all() {
+                let gauge = Gauge::with_opts(
+                    prometheus::Opts::new(metric.name(), format!("M2M {} metric", metric.name()))
+                        .const_label("socket", socket.to_string()),
+                )?;
+                registry.register(Box::new(gauge.clone()))?;
+                gauges.insert((socket, metric), gauge);
+            }
+        }
+
+        let raw_gauges = if config.export_raw {
+            Some(RawCounterGauges::new(
+                &registry,
+                Opts::new(
+                    "M2MRawCounter",
+                    "M2M counter delta summed across units before derivation",
+                ),
+                &["socket"],
+            )?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            monitors: Mutex::new(monitors),
+            registry,
+            gauges,
+            raw_gauges,
+        })
+    }
+
+    /// Collect metrics once (called by orchestrator)
+    pub async fn collect(&self) -> Result<()> {
+        let mut monitors = self.monitors.lock();
+        let monitors = monitors.iter_mut().map(|m| (m.socket(), m));
+        collect_sockets("M2M", monitors, |socket, monitor| {
+            for (metric, value) in monitor.collect_metrics()? {
+                if let Some(gauge) = self.gauges.get(&(socket, metric)) {
+                    gauge.set(value);
+                }
+            }
+            if let (Some(raw_gauges), Some(counts)) = (&self.raw_gauges, monitor.last_counts()) {
+                let socket = socket.to_string();
+                for (counter, delta) in counts.named() {
+                    raw_gauges.set(&[socket.as_str()], counter, delta);
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Drop every socket's baselines
+    pub fn reset(&self) {
+        for monitor in self.monitors.lock().iter_mut() {
+            monitor.reset();
+        }
+    }
+
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+}
+
+impl Subsystem for M2mMetricExporter {
+    fn name(&self) -> &'static str {
+        "m2m"
+    }
+
+    fn init(config: ExportConfig) -> Result<Self> {
+        Self::new(config)
+    }
+
+    fn collect(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(M2mMetricExporter::collect(self))
+    }
+
+    fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    fn reset(&self) {
+        M2mMetricExporter::reset(self)
+    }
+}
@@ -1,15 +1,33 @@
 pub mod cha;
+pub mod cha_custom;
 pub mod core;
+pub mod cstate;
 pub mod iio;
 pub mod imc;
 pub mod irp;
+pub mod m2m;
+pub mod pcu;
 pub mod rapl;
+pub mod raw;
 pub mod rdt;
+pub mod rollup;
+pub mod sockets;
+pub mod sst;
+pub mod state;
+pub mod thermal;
+pub mod upi;
 
 pub use cha::ChaMetricExporter;
+pub use cha_custom::ChaCustomMetricExporter;
 pub use core::CoreMetricExporter;
+pub use cstate::CstateMetricExporter;
 pub use iio::IioMetricExporter;
 pub use imc::ImcMetricExporter;
 pub use irp::IrpMetricExporter;
+pub use m2m::M2mMetricExporter;
+pub use pcu::PcuMetricExporter;
 pub use rapl::RaplMetricExporter;
 pub use rdt::RdtMetricExporter;
+pub use sst::SstMetricExporter;
+pub use thermal::ThermalMetricExporter;
+pub use upi::UpiMetricExporter;
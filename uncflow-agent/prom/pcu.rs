@@ -0,0 +1,107 @@
+// PCU Metrics Exporter
+//
+// Frequency-limit, throttling and C-state occupancy metrics of each socket's PCU,
+// labeled by socket. Sockets whose PCU cannot be programmed are skipped.
+
+use futures_util::future::BoxFuture;
+use parking_lot::Mutex;
+use prometheus::{GaugeVec, Opts, Registry};
+use std::collections::HashMap;
+
+use crate::common::msr::{self, MsrAccess};
+use crate::config::ExportConfig;
+use crate::counters::pcu::PcuMonitor;
+use crate::error::Result;
+use crate::metrics::pcu::PcuMetric;
+use crate::orchestrator::Subsystem;
+use crate::prom::sockets::collect_sockets;
+
+pub struct PcuMetricExporter {
+    monitors: Mutex<Vec<PcuMonitor>>,
+    registry: Registry,
+    gauges: HashMap<PcuMetric, GaugeVec>,
+}
+
+impl PcuMetricExporter {
+    pub fn new(config: ExportConfig) -> Result<Self> {
+        Self::with_msr(config, msr::Msr::instance())
+    }
+
+    /// Create an exporter whose monitors program the PCU through `msr`
+    pub fn with_msr(config: ExportConfig, msr: &'static dyn MsrAccess) -> Result<Self> {
+        let mut monitors = Vec::new();
+        for &socket in &config.sockets {
+            let core = config.uncore_core(socket, PcuMonitor::default_core(socket));
+            match PcuMonitor::with_core(socket, core, msr) {
+                Ok(monitor) => monitors.push(monitor),
+                Err(e) => tracing::warn!("Failed to initialize PCU for socket {}: {}", socket, e),
+            }
+        }
+
+        let registry = Registry::new();
+        let mut gauges = HashMap::new();
+        for metric in PcuMetric::all() {
+            let gauge = GaugeVec::new(
+                Opts::new(metric.name(), format!("PCU {} metric", metric.name())),
+                &["socket"],
+            )?;
+            registry.register(Box::new(gauge.clone()))?;
+            gauges.insert(metric, gauge);
+        }
+
+        Ok(Self {
+            monitors: Mutex::new(monitors),
+            registry,
+            gauges,
+        })
+    }
+
+    /// Collect metrics once (called by orchestrator)
+    pub async fn collect(&self) -> Result<()> {
+        let mut monitors = self.monitors.lock();
+        let monitors = monitors.iter_mut().map(|m| (m.socket(), m));
+        collect_sockets("PCU", monitors, |socket, monitor| {
+            let metrics = monitor.collect_metrics()?;
+            let socket = socket.to_string();
+            for (metric, value) in metrics {
+                if let Some(gauge) = self.gauges.get(&metric) {
+                    gauge.with_label_values(&[socket.as_str()]).set(value);
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Restart every socket's current group, the next collection starts a fresh window
+    pub fn reset(&self) {
+        for monitor in self.monitors.lock().iter_mut() {
+            monitor.reset();
+        }
+    }
+
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+}
+
+impl Subsystem for PcuMetricExporter {
+    fn name(&self) -> &'static str {
+        "pcu"
+    }
+
+    fn init(config: ExportConfig) -> Result<Self> {
+        Self::new(config)
+    }
+
+    fn collect(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(PcuMetricExporter::collect(self))
+    }
+
+    fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    fn reset(&self) {
+        PcuMetricExporter::reset(self)
+    }
+}
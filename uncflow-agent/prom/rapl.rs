@@ -1,19 +1,43 @@
-use prometheus::{Gauge, Registry};
+use futures_util::future::BoxFuture;
+use prometheus::{Counter, Gauge, Opts, Registry};
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
 use std::time::Duration;
 use tokio::task::JoinHandle;
 
+use crate::common::{log_limiter, CounterBackend};
 use crate::config::ExportConfig;
-use crate::counters::rapl::RaplMonitor;
+use crate::counters::rapl::{RaplMonitor, ENERGY_POLL_INTERVAL};
 use crate::error::Result;
 use crate::metrics::rapl::RaplMetric;
+use crate::orchestrator::Subsystem;
+use crate::prom::sockets::collect_sockets;
+use crate::prom::state::CounterState;
 
 pub struct RaplMetricExporter {
     config: ExportConfig,
     registry: Arc<Registry>,
     monitor: Arc<parking_lot::Mutex<RaplMonitor>>,
     socket_gauges: HashMap<RaplMetric, HashMap<i32, Gauge>>,
+    // rapl_package_energy_joules_total per socket
+    energy_counters: HashMap<i32, EnergyCounter>,
+    // rapl_{package,dram}_power_watts per socket
+    power_gauges: HashMap<i32, PowerGauges>,
+}
+
+/// Cumulative package energy of one socket
+#[derive(Clone)]
+struct EnergyCounter {
+    counter: Counter,
+    // Joules restored from the state file, on top of the monitor's accumulator
+    restored: f64,
+}
+
+/// Average power of one socket over the last interval, in watts
+#[derive(Clone)]
+struct PowerGauges {
+    package: Gauge,
+    dram: Gauge,
 }
 
 impl RaplMetricExporter {
@@ -26,10 +50,17 @@ impl RaplMetricExporter {
             registry: Arc::clone(&registry),
             monitor,
             socket_gauges: HashMap::new(),
+            energy_counters: HashMap::new(),
+            power_gauges: HashMap::new(),
         };
 
         exporter.register_metrics()?;
 
+        // Scrapes may be too far apart to see every wrap of the 32-bit MSR counters
+        if config.backend == CounterBackend::Msr {
+            Self::spawn_energy_poller(Arc::downgrade(&exporter.monitor))?;
+        }
+
         Ok(exporter)
     }
 
@@ -48,82 +79,128 @@ impl RaplMetricExporter {
             self.socket_gauges.insert(metric, socket_map);
         }
 
+        let opts = Opts::new(
+            "rapl_package_energy_joules_total",
+            "Cumulative package energy in joules consumed since the agent started, or since \
+             the first start with --state-file",
+        );
+        let saved = self
+            .config
+            .state_file
+            .as_deref()
+            .map(CounterState::load)
+            .unwrap_or_default();
+        for &socket_id in &self.config.sockets {
+            let socket = socket_id.to_string();
+            let counter = Counter::with_opts(opts.clone().const_label("socket", &socket))?;
+            let restored = saved.get(opts.name.as_str(), &[("socket", &socket)]);
+            counter.inc_by(restored);
+            self.registry.register(Box::new(counter.clone()))?;
+            self.energy_counters
+                .insert(socket_id, EnergyCounter { counter, restored });
+        }
+
+        // Joules stay with the cumulative counter; these are rates of it
+        let package_opts = Opts::new(
+            "rapl_package_power_watts",
+            "Average package power in watts over the last interval: energy delta / interval length",
+        );
+        let dram_opts = Opts::new(
+            "rapl_dram_power_watts",
+            "Average DRAM power in watts over the last interval: energy delta / interval length",
+        );
+        for &socket_id in &self.config.sockets {
+            let socket = socket_id.to_string();
+            let gauges = PowerGauges {
+                package: Gauge::with_opts(package_opts.clone().const_label("socket", &socket))?,
+                dram: Gauge::with_opts(dram_opts.clone().const_label("socket", &socket))?,
+            };
+            self.registry.register(Box::new(gauges.package.clone()))?;
+            self.registry.register(Box::new(gauges.dram.clone()))?;
+            self.power_gauges.insert(socket_id, gauges);
+        }
+
         Ok(())
     }
 
     /// Collect metrics once (called by orchestrator)
-    pub async fn collect(&self) {
-        for &socket_id in &self.config.sockets {
-            let mut monitor = self.monitor.lock();
-
-            match monitor.get_current_energy(socket_id) {
-                Ok(energy_data) => {
-                    if let Some(gauge) = self
-                        .socket_gauges
-                        .get(&RaplMetric::PackageEnergy)
-                        .and_then(|m| m.get(&socket_id))
-                    {
-                        gauge.set(energy_data.package_energy);
-                    }
-                    if let Some(gauge) = self
-                        .socket_gauges
-                        .get(&RaplMetric::CoreEnergy)
-                        .and_then(|m| m.get(&socket_id))
-                    {
-                        gauge.set(energy_data.core_energy);
-                    }
-                    if let Some(gauge) = self
-                        .socket_gauges
-                        .get(&RaplMetric::DramEnergy)
-                        .and_then(|m| m.get(&socket_id))
-                    {
-                        gauge.set(energy_data.dram_energy);
+    pub async fn collect(&self) -> Result<()> {
+        let mut monitor = self.monitor.lock();
+        let sockets = self.config.sockets.iter().map(|&socket| (socket, ()));
+        collect_sockets("RAPL", sockets, |socket_id, ()| {
+            Self::export_socket(
+                &mut monitor,
+                socket_id,
+                &self.socket_gauges,
+                &self.energy_counters,
+                &self.power_gauges,
+            )
+        })
+    }
+
+    /// Set one socket's gauges, returning the first failed read
+    fn export_socket(
+        monitor: &mut RaplMonitor,
+        socket_id: i32,
+        socket_gauges: &HashMap<RaplMetric, HashMap<i32, Gauge>>,
+        energy_counters: &HashMap<i32, EnergyCounter>,
+        power_gauges: &HashMap<i32, PowerGauges>,
+    ) -> Result<()> {
+        let mut failure = None;
+        let set = |metric: RaplMetric, value: f64| {
+            if let Some(gauge) = socket_gauges.get(&metric).and_then(|m| m.get(&socket_id)) {
+                gauge.set(value);
+            }
+        };
+
+        match monitor.total_energy(socket_id) {
+            Ok(energy_data) => {
+                set(RaplMetric::PackageEnergy, energy_data.package_energy);
+                set(RaplMetric::CoreEnergy, energy_data.core_energy);
+                set(RaplMetric::DramEnergy, energy_data.dram_energy);
+
+                // The accumulator only grows, so this catches the counter up to it
+                if let Some(energy) = energy_counters.get(&socket_id) {
+                    let increase =
+                        energy.restored + energy_data.package_energy - energy.counter.get();
+                    if increase > 0.0 {
+                        energy.counter.inc_by(increase);
                     }
                 }
-                Err(e) => {
-                    tracing::error!("Failed to get energy data for socket {}: {}", socket_id, e);
-                }
             }
+            Err(e) => {
+                failure.get_or_insert(e);
+            }
+        }
 
-            match monitor.get_power_consumption(socket_id) {
-                Ok(power_data) => {
-                    if let Some(gauge) = self
-                        .socket_gauges
-                        .get(&RaplMetric::PackagePower)
-                        .and_then(|m| m.get(&socket_id))
-                    {
-                        gauge.set(power_data.package_energy);
-                    }
-                    if let Some(gauge) = self
-                        .socket_gauges
-                        .get(&RaplMetric::CorePower)
-                        .and_then(|m| m.get(&socket_id))
-                    {
-                        gauge.set(power_data.core_energy);
-                    }
-                    if let Some(gauge) = self
-                        .socket_gauges
-                        .get(&RaplMetric::DramPower)
-                        .and_then(|m| m.get(&socket_id))
-                    {
-                        gauge.set(power_data.dram_energy);
+        match monitor.energy_interval(socket_id) {
+            // Nothing yet on the first read, which only sets the baseline
+            Ok(None) => {}
+            Ok(Some(interval)) => {
+                // Back-to-back reads have no interval to average over
+                if let Some(watts) = interval.average_power() {
+                    set(RaplMetric::PackagePower, watts.package_energy);
+                    set(RaplMetric::CorePower, watts.core_energy);
+                    set(RaplMetric::DramPower, watts.dram_energy);
+                    if let Some(gauges) = power_gauges.get(&socket_id) {
+                        gauges.package.set(watts.package_energy);
+                        gauges.dram.set(watts.dram_energy);
                     }
                 }
-                Err(e) => {
-                    tracing::error!(
-                        "Failed to get power consumption for socket {}: {}",
-                        socket_id,
-                        e
-                    );
-                }
+            }
+            Err(e) => {
+                failure.get_or_insert(e);
             }
         }
+        failure.map_or(Ok(()), Err)
     }
 
     async fn collect_loop(
         config: ExportConfig,
         monitor: Arc<parking_lot::Mutex<RaplMonitor>>,
         socket_gauges: HashMap<RaplMetric, HashMap<i32, Gauge>>,
+        energy_counters: HashMap<i32, EnergyCounter>,
+        power_gauges: HashMap<i32, PowerGauges>,
     ) {
         tracing::warn!("Starting RAPL export thread");
 
@@ -132,81 +209,86 @@ impl RaplMetricExporter {
         loop {
             interval.tick().await;
 
-            for &socket_id in &config.sockets {
-                let mut monitor = monitor.lock();
-
-                match monitor.get_current_energy(socket_id) {
-                    Ok(energy_data) => {
-                        if let Some(gauge) = socket_gauges
-                            .get(&RaplMetric::PackageEnergy)
-                            .and_then(|m| m.get(&socket_id))
-                        {
-                            gauge.set(energy_data.package_energy);
-                        }
-                        if let Some(gauge) = socket_gauges
-                            .get(&RaplMetric::CoreEnergy)
-                            .and_then(|m| m.get(&socket_id))
-                        {
-                            gauge.set(energy_data.core_energy);
-                        }
-                        if let Some(gauge) = socket_gauges
-                            .get(&RaplMetric::DramEnergy)
-                            .and_then(|m| m.get(&socket_id))
-                        {
-                            gauge.set(energy_data.dram_energy);
-                        }
-                    }
-                    Err(e) => {
-                        tracing::error!(
-                            "Failed to get energy data for socket {}: {}",
-                            socket_id,
-                            e
-                        );
-                    }
-                }
+            let mut monitor = monitor.lock();
+            let sockets = config.sockets.iter().map(|&socket| (socket, ()));
+            // Failures are logged by collect_sockets
+            let _ = collect_sockets("RAPL", sockets, |socket_id, ()| {
+                Self::export_socket(
+                    &mut monitor,
+                    socket_id,
+                    &socket_gauges,
+                    &energy_counters,
+                    &power_gauges,
+                )
+            });
+        }
+    }
 
-                match monitor.get_power_consumption(socket_id) {
-                    Ok(power_data) => {
-                        if let Some(gauge) = socket_gauges
-                            .get(&RaplMetric::PackagePower)
-                            .and_then(|m| m.get(&socket_id))
-                        {
-                            gauge.set(power_data.package_energy);
-                        }
-                        if let Some(gauge) = socket_gauges
-                            .get(&RaplMetric::CorePower)
-                            .and_then(|m| m.get(&socket_id))
-                        {
-                            gauge.set(power_data.core_energy);
-                        }
-                        if let Some(gauge) = socket_gauges
-                            .get(&RaplMetric::DramPower)
-                            .and_then(|m| m.get(&socket_id))
-                        {
-                            gauge.set(power_data.dram_energy);
-                        }
-                    }
-                    Err(e) => {
-                        tracing::error!(
-                            "Failed to get power consumption for socket {}: {}",
-                            socket_id,
-                            e
-                        );
+    /// Poll the energy counters every `ENERGY_POLL_INTERVAL` until the exporter is dropped
+    fn spawn_energy_poller(monitor: Weak<parking_lot::Mutex<RaplMonitor>>) -> Result<()> {
+        std::thread::Builder::new()
+            .name("rapl-poller".to_string())
+            .spawn(move || loop {
+                std::thread::sleep(ENERGY_POLL_INTERVAL);
+                let Some(monitor) = monitor.upgrade() else {
+                    break;
+                };
+                let result = monitor.lock().poll();
+                if let Err(e) = result {
+                    if log_limiter().allow("RAPL poller", &e.to_string()) {
+                        tracing::warn!("Failed to poll RAPL energy counters: {}", e);
                     }
                 }
-            }
-        }
+            })?;
+        Ok(())
     }
 
     pub fn start(&self) -> JoinHandle<()> {
         let config = self.config.clone();
         let monitor = Arc::clone(&self.monitor);
         let socket_gauges = self.socket_gauges.clone();
+        let energy_counters = self.energy_counters.clone();
+        let power_gauges = self.power_gauges.clone();
+
+        tokio::spawn(Self::collect_loop(
+            config,
+            monitor,
+            socket_gauges,
+            energy_counters,
+            power_gauges,
+        ))
+    }
 
-        tokio::spawn(Self::collect_loop(config, monitor, socket_gauges))
+    /// Re-read the energy baseline of every socket
+    pub fn reset(&self) {
+        if let Err(e) = self.monitor.lock().reset() {
+            tracing::warn!("Failed to reset RAPL baselines: {}", e);
+        }
     }
 
     pub fn registry(&self) -> Arc<Registry> {
         Arc::clone(&self.registry)
     }
 }
+
+impl Subsystem for RaplMetricExporter {
+    fn name(&self) -> &'static str {
+        "rapl"
+    }
+
+    fn init(config: ExportConfig) -> Result<Self> {
+        Self::new(config)
+    }
+
+    fn collect(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(RaplMetricExporter::collect(self))
+    }
+
+    fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    fn reset(&self) {
+        RaplMetricExporter::reset(self)
+    }
+}
@@ -0,0 +1,91 @@
+// Raw counter deltas for checking derived metrics against pcm/perf
+// Only registered with --export-raw: one series per unit and counter, so high cardinality
+
+use prometheus::{GaugeVec, Opts, Registry};
+
+use crate::error::Result;
+
+/// Gauge family holding the per-interval delta of each counter before derivation
+pub struct RawCounterGauges {
+    gauge: GaugeVec,
+}
+
+impl RawCounterGauges {
+    /// Register a family labeled by `labels` plus a trailing `counter` label
+    pub fn new(registry: &Registry, opts: Opts, labels: &[&str]) -> Result<Self> {
+        let labels: Vec<&str> = labels.iter().copied().chain(["counter"]).collect();
+        let gauge = GaugeVec::new(opts, &labels)?;
+        registry.register(Box::new(gauge.clone()))?;
+        Ok(Self { gauge })
+    }
+
+    /// Set the delta of `counter` on the unit identified by `labels`
+    pub fn set(&self, labels: &[&str], counter: &str, delta: u64) {
+        self.gauge
+            .with_label_values(&Self::values(labels, counter))
+            .set(delta as f64);
+    }
+
+    /// Drop a counter's series, e.g. once it could not be programmed
+    pub fn remove(&self, labels: &[&str], counter: &str) {
+        let _ = self
+            .gauge
+            .remove_label_values(&Self::values(labels, counter));
+    }
+
+    fn values<'a>(labels: &[&'a str], counter: &'a str) -> Vec<&'a str> {
+        labels.iter().copied().chain([counter]).collect()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use prometheus::proto::MetricFamily;
+
+    /// Value of the series of `family` whose labels are exactly `labels`
+    pub(crate) fn gauge_value(
+        families: &[MetricFamily],
+        family: &str,
+        labels: &[(&str, &str)],
+    ) -> Option<f64> {
+        families
+            .iter()
+            .find(|f| f.name() == family)?
+            .get_metric()
+            .iter()
+            .find(|m| {
+                m.get_label().len() == labels.len()
+                    && labels.iter().all(|(name, value)| {
+                        m.get_label()
+                            .iter()
+                            .any(|l| l.name() == *name && l.value() == *value)
+                    })
+            })
+            .map(|m| m.get_gauge().value())
+    }
+
+    #[test]
+    fn test_set_and_remove() {
+        let registry = Registry::new();
+        let raw = RawCounterGauges::new(
+            &registry,
+            Opts::new("UPIRawCounter", "UPI counter deltas"),
+            &["socket", "link"],
+        )
+        .unwrap();
+
+        raw.set(&["0", "1"], "tx_data_flits", 900);
+        let labels = [("socket", "0"), ("link", "1"), ("counter", "tx_data_flits")];
+        assert_eq!(
+            gauge_value(&registry.gather(), "UPIRawCounter", &labels),
+            Some(900.0)
+        );
+
+        raw.remove(&["0", "1"], "tx_data_flits");
+        assert_eq!(
+            gauge_value(&registry.gather(), "UPIRawCounter", &labels),
+            None
+        );
+    }
+}
@@ -1,13 +1,17 @@
-use prometheus::{Gauge, Registry};
+use futures_util::future::BoxFuture;
+use prometheus::{Counter, Gauge, Opts, Registry};
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::task::JoinHandle;
 
+use crate::common::log_limiter;
 use crate::config::ExportConfig;
 use crate::counters::rdt::RdtMonitor;
 use crate::error::Result;
 use crate::metrics::rdt::RdtMetric;
+use crate::orchestrator::Subsystem;
+use crate::prom::state::CounterState;
 
 pub struct RdtMetricExporter {
     config: ExportConfig,
@@ -15,6 +19,9 @@ pub struct RdtMetricExporter {
     monitor: Arc<parking_lot::Mutex<RdtMonitor>>,
     socket_gauges: HashMap<RdtMetric, HashMap<i32, Gauge>>,
     core_gauges: HashMap<RdtMetric, HashMap<i32, Gauge>>,
+    group_gauges: HashMap<RdtMetric, HashMap<String, Gauge>>,
+    // rdt_memory_bytes_total per socket
+    bytes_counters: HashMap<i32, Counter>,
     rmid_refresh_counter: Arc<parking_lot::Mutex<u32>>,
 }
 
@@ -24,6 +31,10 @@ impl RdtMetricExporter {
 
         let mut monitor = RdtMonitor::new(config.clone())?;
         monitor.initialize()?;
+        // Read the MBM baselines so the first delta is not counted from zero
+        if let Err(e) = monitor.reset() {
+            tracing::warn!("Failed to read RDT baselines: {}", e);
+        }
 
         let monitor = Arc::new(parking_lot::Mutex::new(monitor));
 
@@ -33,6 +44,8 @@ impl RdtMetricExporter {
             monitor,
             socket_gauges: HashMap::new(),
             core_gauges: HashMap::new(),
+            group_gauges: HashMap::new(),
+            bytes_counters: HashMap::new(),
             rmid_refresh_counter: Arc::new(parking_lot::Mutex::new(0)),
         };
 
@@ -42,6 +55,10 @@ impl RdtMetricExporter {
     }
 
     fn register_metrics(&mut self) -> Result<()> {
+        if !self.config.resctrl_groups.is_empty() {
+            return self.register_group_metrics();
+        }
+
         for metric in RdtMetric::all() {
             let opts =
                 prometheus::Opts::new(metric.name(), format!("RDT {} measurement", metric.name()));
@@ -75,14 +92,81 @@ impl RdtMetricExporter {
             self.core_gauges.insert(metric, core_map);
         }
 
+        let opts = Opts::new(
+            "rdt_memory_bytes_total",
+            "Cumulative local and remote memory traffic in bytes counted by MBM since the agent \
+             started, or since the first start with --state-file",
+        );
+        let saved = self
+            .config
+            .state_file
+            .as_deref()
+            .map(CounterState::load)
+            .unwrap_or_default();
+        for &socket_id in &self.config.sockets {
+            let socket = socket_id.to_string();
+            let counter = Counter::with_opts(opts.clone().const_label("socket", &socket))?;
+            counter.inc_by(saved.get(opts.name.as_str(), &[("socket", &socket)]));
+            self.registry.register(Box::new(counter.clone()))?;
+            self.bytes_counters.insert(socket_id, counter);
+        }
+
+        Ok(())
+    }
+
+    // resctrl mode only measures total bandwidth and occupancy, per group
+    fn register_group_metrics(&mut self) -> Result<()> {
+        for metric in [RdtMetric::TotalMemoryBandwidth, RdtMetric::LlcOccupancy] {
+            let opts =
+                prometheus::Opts::new(metric.name(), format!("RDT {} measurement", metric.name()));
+
+            let mut group_map = HashMap::new();
+            for group in &self.config.resctrl_groups {
+                let gauge = Gauge::with_opts(opts.clone().const_label("resctrl_group", group))?;
+                self.registry.register(Box::new(gauge.clone()))?;
+                group_map.insert(group.clone(), gauge);
+            }
+            self.group_gauges.insert(metric, group_map);
+        }
+
         Ok(())
     }
 
+    fn update_group_gauges(
+        monitor: &parking_lot::Mutex<RdtMonitor>,
+        group_gauges: &HashMap<RdtMetric, HashMap<String, Gauge>>,
+    ) {
+        for (metric, gauges) in group_gauges {
+            for (group, gauge) in gauges {
+                let metrics = monitor.lock().get_group_metrics(group);
+                if let Some(&value) = metrics.get(metric.name()) {
+                    gauge.set(value);
+                }
+            }
+        }
+    }
+
+    // The socket's MBM bandwidth is the bytes moved since the previous update
+    fn count_bytes(
+        bytes_counters: &HashMap<i32, Counter>,
+        socket_id: i32,
+        socket_metrics: &HashMap<String, f64>,
+    ) {
+        if let (Some(counter), Some(&bytes)) = (
+            bytes_counters.get(&socket_id),
+            socket_metrics.get("TotalMemoryBytes"),
+        ) {
+            counter.inc_by(bytes);
+        }
+    }
+
     async fn collect_loop(
         config: ExportConfig,
         monitor: Arc<parking_lot::Mutex<RdtMonitor>>,
         socket_gauges: HashMap<RdtMetric, HashMap<i32, Gauge>>,
         core_gauges: HashMap<RdtMetric, HashMap<i32, Gauge>>,
+        group_gauges: HashMap<RdtMetric, HashMap<String, Gauge>>,
+        bytes_counters: HashMap<i32, Counter>,
     ) {
         tracing::warn!("Starting RDT export thread");
 
@@ -100,12 +184,16 @@ impl RdtMetricExporter {
                 }
             }
 
+            Self::update_group_gauges(&monitor, &group_gauges);
+
             // Update socket-level gauges
             for &socket_id in &config.sockets {
                 let mon = monitor.lock();
                 let socket_metrics = mon.get_socket_metrics(socket_id);
                 drop(mon);
 
+                Self::count_bytes(&bytes_counters, socket_id, &socket_metrics);
+
                 if let Some(gauge) = socket_gauges
                     .get(&RdtMetric::LocalMemoryBandwidth)
                     .and_then(|m| m.get(&socket_id))
@@ -202,31 +290,41 @@ impl RdtMetricExporter {
         let monitor = Arc::clone(&self.monitor);
         let socket_gauges = self.socket_gauges.clone();
         let core_gauges = self.core_gauges.clone();
+        let group_gauges = self.group_gauges.clone();
+        let bytes_counters = self.bytes_counters.clone();
 
         tokio::spawn(Self::collect_loop(
             config,
             monitor,
             socket_gauges,
             core_gauges,
+            group_gauges,
+            bytes_counters,
         ))
     }
 
     /// Collect metrics once (called by orchestrator)
-    pub async fn collect(&self) {
+    pub async fn collect(&self) -> Result<()> {
         {
             let mut mon = self.monitor.lock();
             if let Err(e) = mon.update() {
-                tracing::error!("Failed to update RDT metrics: {}", e);
-                return;
+                if log_limiter().allow("RDT", &e.to_string()) {
+                    tracing::error!("Failed to update RDT metrics: {}", e);
+                }
+                return Err(e);
             }
         }
 
+        Self::update_group_gauges(&self.monitor, &self.group_gauges);
+
         // Update socket-level gauges
         for &socket_id in &self.config.sockets {
             let mon = self.monitor.lock();
             let socket_metrics = mon.get_socket_metrics(socket_id);
             drop(mon);
 
+            Self::count_bytes(&self.bytes_counters, socket_id, &socket_metrics);
+
             if let Some(gauge) = self
                 .socket_gauges
                 .get(&RdtMetric::LocalMemoryBandwidth)
@@ -325,9 +423,39 @@ impl RdtMetricExporter {
             }
             *counter = 0;
         }
+        Ok(())
+    }
+
+    /// Re-read the MBM baselines of every monitored core or resctrl group
+    pub fn reset(&self) {
+        if let Err(e) = self.monitor.lock().reset() {
+            tracing::warn!("Failed to reset RDT baselines: {}", e);
+        }
     }
 
     pub fn registry(&self) -> Arc<Registry> {
         Arc::clone(&self.registry)
     }
 }
+
+impl Subsystem for RdtMetricExporter {
+    fn name(&self) -> &'static str {
+        "rdt"
+    }
+
+    fn init(config: ExportConfig) -> Result<Self> {
+        Self::new(config)
+    }
+
+    fn collect(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(RdtMetricExporter::collect(self))
+    }
+
+    fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    fn reset(&self) {
+        RdtMetricExporter::reset(self)
+    }
+}
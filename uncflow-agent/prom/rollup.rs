@@ -0,0 +1,121 @@
+// Socket totals of per-channel/per-port series, computed in the agent (--rollups)
+// Saves dashboards a sum() per panel and stays correct when the component series
+// are not exported at all (e.g. IMC without --imc-per-channel)
+
+use prometheus::{GaugeVec, Opts, Registry};
+use std::collections::HashMap;
+
+use crate::counters::imc::ImcMetrics;
+use crate::error::Result;
+use crate::metrics::iio::IioMetric;
+
+/// Gauge family with one series per socket
+pub struct SocketRollup {
+    gauge: GaugeVec,
+}
+
+impl SocketRollup {
+    pub fn new(registry: &Registry, opts: Opts) -> Result<Self> {
+        let gauge = GaugeVec::new(opts, &["socket"])?;
+        registry.register(Box::new(gauge.clone()))?;
+        Ok(Self { gauge })
+    }
+
+    pub fn set(&self, socket: i32, value: f64) {
+        self.gauge
+            .with_label_values(&[socket.to_string().as_str()])
+            .set(value);
+    }
+}
+
+/// Read plus write bandwidth of every IMC channel, in bytes/sec
+pub fn memory_bandwidth(metrics: &ImcMetrics) -> f64 {
+    (metrics.read_bandwidth + metrics.write_bandwidth) as f64
+}
+
+/// Inbound plus outbound bandwidth of every PCIe port, in GB/s
+///
+/// `None` until the ports have a rate, i.e. on the first sample.
+pub fn pcie_bandwidth(metrics: &HashMap<IioMetric, f64>) -> Option<f64> {
+    let mut ports = metrics.iter().filter(|(metric, _)| {
+        matches!(
+            metric,
+            IioMetric::PCIeInBandwidth(..) | IioMetric::PCIeOutBandwidth(..)
+        )
+    });
+    let (_, &first) = ports.next()?;
+    Some(first + ports.map(|(_, &value)| value).sum::<f64>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::counters::imc::ImcChannelMetrics;
+    use crate::prom::raw::tests::gauge_value;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_memory_rollup_is_channel_sum() {
+        let channel = |read_bandwidth, write_bandwidth| ImcChannelMetrics {
+            read_bandwidth,
+            write_bandwidth,
+            ..Default::default()
+        };
+        let channels = BTreeMap::from([
+            (0, channel(1_000, 200)),
+            (1, channel(3_000, 0)),
+            (4, channel(500, 700)),
+        ]);
+        let metrics = ImcMetrics {
+            read_bandwidth: channels.values().map(|c| c.read_bandwidth).sum(),
+            write_bandwidth: channels.values().map(|c| c.write_bandwidth).sum(),
+            channels: channels.clone(),
+            ..Default::default()
+        };
+
+        let components: u64 = channels
+            .values()
+            .map(|c| c.read_bandwidth + c.write_bandwidth)
+            .sum();
+        assert_eq!(memory_bandwidth(&metrics), components as f64);
+    }
+
+    #[test]
+    fn test_pcie_rollup_is_port_sum() {
+        let metrics = HashMap::from([
+            (IioMetric::PCIeInBandwidth(0, 0), 1.5),
+            (IioMetric::PCIeOutBandwidth(0, 0), 0.25),
+            (IioMetric::PCIeInBandwidth(2, 3), 4.0),
+            (IioMetric::PCIeOutBandwidth(1, 2), 0.125),
+            // Not a port, not part of the total
+            (IioMetric::IIOFrequency, 1.2),
+        ]);
+        assert_eq!(pcie_bandwidth(&metrics), Some(5.875));
+
+        let first_sample = HashMap::from([(IioMetric::IIOTLBMiss, 10.0)]);
+        assert_eq!(pcie_bandwidth(&first_sample), None);
+    }
+
+    #[test]
+    fn test_rollup_gauge_per_socket() {
+        let registry = Registry::new();
+        let rollup = SocketRollup::new(
+            &registry,
+            Opts::new("uncflow_socket_total_memory_bandwidth", "test"),
+        )
+        .unwrap();
+
+        rollup.set(0, 10.0);
+        rollup.set(1, 20.0);
+        let families = registry.gather();
+        let family = "uncflow_socket_total_memory_bandwidth";
+        assert_eq!(
+            gauge_value(&families, family, &[("socket", "0")]),
+            Some(10.0)
+        );
+        assert_eq!(
+            gauge_value(&families, family, &[("socket", "1")]),
+            Some(20.0)
+        );
+    }
+}
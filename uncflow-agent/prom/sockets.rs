@@ -0,0 +1,61 @@
+// Per-socket collection shared by the uncore exporters
+
+use crate::common::log_limiter;
+use crate::error::Result;
+
+/// Collect every socket with `collect`, logging failures under `unit`
+///
+/// Sockets that fail keep their previous values; the first error is returned
+/// after the remaining sockets have been collected. Repeated failures of a socket are
+/// rate limited by `log_limiter`, which is cleared once the socket collects again.
+pub fn collect_sockets<M>(
+    unit: &str,
+    monitors: impl IntoIterator<Item = (i32, M)>,
+    mut collect: impl FnMut(i32, M) -> Result<()>,
+) -> Result<()> {
+    let mut failure = None;
+    for (socket, monitor) in monitors {
+        let source = format!("{unit} socket {socket}");
+        match collect(socket, monitor) {
+            Ok(()) => log_limiter().clear(&source),
+            Err(e) => {
+                if log_limiter().allow(&source, &e.to_string()) {
+                    tracing::error!(
+                        "Failed to collect {} metrics for socket {}: {}",
+                        unit,
+                        socket,
+                        e
+                    );
+                }
+                failure.get_or_insert(e);
+            }
+        }
+    }
+    failure.map_or(Ok(()), Err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::UncflowError;
+
+    #[test]
+    fn test_collect_sockets_returns_first_error() {
+        let mut collected = Vec::new();
+        let result = collect_sockets(
+            "Test",
+            [(0, false), (1, true), (2, true), (3, false)],
+            |socket, fail| {
+                collected.push(socket);
+                if fail {
+                    Err(UncflowError::HardwareError(format!("socket {socket}")))
+                } else {
+                    Ok(())
+                }
+            },
+        );
+
+        assert_eq!(collected, [0, 1, 2, 3]);
+        assert!(matches!(result, Err(UncflowError::HardwareError(msg)) if msg == "socket 1"));
+    }
+}
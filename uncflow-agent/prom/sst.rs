@@ -0,0 +1,130 @@
+// SST Metrics Exporter
+//
+// Active SST-PP level per socket and SST-CP priority class per core, so frequency
+// metrics can be read against the profile and priority the part was running.
+
+use futures_util::future::BoxFuture;
+use prometheus::{GaugeVec, Opts, Registry};
+use std::sync::Arc;
+
+use crate::common::log_limiter;
+use crate::config::ExportConfig;
+use crate::counters::sst::SstMonitor;
+use crate::error::Result;
+use crate::orchestrator::Subsystem;
+
+pub struct SstMetricExporter {
+    registry: Arc<Registry>,
+    monitor: SstMonitor,
+    config_level: GaugeVec,
+    core_priority: GaugeVec,
+}
+
+impl SstMetricExporter {
+    pub fn new(config: ExportConfig) -> Result<Self> {
+        Self::with_monitor(SstMonitor::new(&config)?)
+    }
+
+    /// Create an exporter publishing what `monitor` reads
+    pub fn with_monitor(monitor: SstMonitor) -> Result<Self> {
+        let registry = Arc::new(Registry::new());
+
+        let config_level = GaugeVec::new(
+            Opts::new(
+                "sst_config_level",
+                "Active SST-PP configurable TDP level, 0 is the nominal profile",
+            ),
+            &["socket"],
+        )?;
+        registry.register(Box::new(config_level.clone()))?;
+
+        let core_priority = GaugeVec::new(
+            Opts::new(
+                "sst_core_priority",
+                "SST-CP priority class (class of service) of the core, 0 is the highest",
+            ),
+            &["core"],
+        )?;
+        registry.register(Box::new(core_priority.clone()))?;
+
+        Ok(Self {
+            registry,
+            monitor,
+            config_level,
+            core_priority,
+        })
+    }
+
+    /// Collect metrics once (called by orchestrator)
+    pub async fn collect(&self) -> Result<()> {
+        let status = self.monitor.collect().inspect_err(|e| {
+            if log_limiter().allow("SST", &e.to_string()) {
+                tracing::error!("Failed to read SST status: {}", e);
+            }
+        })?;
+        for (socket, level) in status.config_levels {
+            self.config_level
+                .with_label_values(&[socket.to_string().as_str()])
+                .set(f64::from(level));
+        }
+        for (core, priority) in status.core_priorities {
+            self.core_priority
+                .with_label_values(&[core.to_string().as_str()])
+                .set(f64::from(priority));
+        }
+        Ok(())
+    }
+
+    pub fn registry(&self) -> Arc<Registry> {
+        Arc::clone(&self.registry)
+    }
+}
+
+impl Subsystem for SstMetricExporter {
+    fn name(&self) -> &'static str {
+        "sst"
+    }
+
+    fn init(config: ExportConfig) -> Result<Self> {
+        Self::new(config)
+    }
+
+    fn collect(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(SstMetricExporter::collect(self))
+    }
+
+    fn registry(&self) -> &Registry {
+        &self.registry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::msr::mock::MockMsr;
+    use crate::prom::raw::tests::gauge_value;
+    use std::collections::HashMap;
+    use uncflow_raw::current_arch::sst;
+
+    #[tokio::test]
+    async fn test_exports_level_and_priority() {
+        let msr = MockMsr::new().leak();
+        msr.set(4, sst::msr::MSR_PLATFORM_INFO, 0x0000_0006_0000_1700);
+        msr.set(4, sst::msr::MSR_CONFIG_TDP_CONTROL, 0x3);
+        msr.set(4, sst::msr::IA32_PQR_ASSOC, 0x0000_0003_0000_0000);
+        let config = ExportConfig::new(vec![1], vec![4]).with_uncore_cores(HashMap::from([(1, 4)]));
+        let exporter =
+            SstMetricExporter::with_monitor(SstMonitor::with_msr(&config, msr).unwrap()).unwrap();
+
+        exporter.collect().await.unwrap();
+        let families = exporter.registry().gather();
+        assert_eq!(
+            gauge_value(&families, "sst_config_level", &[("socket", "1")]),
+            Some(3.0)
+        );
+        assert_eq!(
+            gauge_value(&families, "sst_core_priority", &[("core", "4")]),
+            Some(3.0)
+        );
+    }
+}
@@ -0,0 +1,164 @@
+// Cumulative counters persisted across agent restarts (--state-file)
+//
+// The `_total` counters accumulate in the agent, so a restart would drop them
+// back to zero and break rate() over the restart. On graceful shutdown their
+// values are written to a small state file, one `name{labels} value` line per
+// series, and the exporters start their counters from it on the next start.
+//
+// A missing file starts every counter from zero; so does a corrupt one, which is
+// logged and otherwise ignored rather than partially restored.
+
+use prometheus::proto::{MetricFamily, MetricType};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::error::{Result, UncflowError};
+
+/// Counter families whose values are persisted
+pub const PERSISTED_COUNTERS: [&str; 2] =
+    ["rapl_package_energy_joules_total", "rdt_memory_bytes_total"];
+
+/// Saved values of the persisted counters, keyed by series
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CounterState {
+    values: BTreeMap<String, f64>,
+}
+
+/// Key of the series of `name` with `labels`, as written to the state file
+pub fn series_key(name: &str, labels: &[(&str, &str)]) -> String {
+    let mut labels = labels.to_vec();
+    labels.sort_unstable();
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(label, value)| format!("{label}=\"{value}\""))
+        .collect();
+    format!("{name}{{{}}}", labels.join(","))
+}
+
+impl CounterState {
+    /// Values of the persisted counters among `families`
+    pub fn from_families(families: &[MetricFamily]) -> Self {
+        let mut values = BTreeMap::new();
+        for family in families {
+            if family.get_field_type() != MetricType::COUNTER
+                || !PERSISTED_COUNTERS.contains(&family.name())
+            {
+                continue;
+            }
+            for metric in family.get_metric() {
+                let labels: Vec<(&str, &str)> = metric
+                    .get_label()
+                    .iter()
+                    .map(|label| (label.name(), label.value()))
+                    .collect();
+                values.insert(
+                    series_key(family.name(), &labels),
+                    metric.get_counter().value(),
+                );
+            }
+        }
+        Self { values }
+    }
+
+    /// Saved value of the series of `name` with `labels`, 0 if none was saved
+    pub fn get(&self, name: &str, labels: &[(&str, &str)]) -> f64 {
+        self.values
+            .get(&series_key(name, labels))
+            .copied()
+            .unwrap_or(0.0)
+    }
+
+    /// Read the state saved at `path`, empty if it is missing or corrupt
+    pub fn load(path: &Path) -> Self {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                tracing::warn!("Cannot read counter state {}: {}", path.display(), e);
+                return Self::default();
+            }
+        };
+        match Self::parse(&text) {
+            Ok(state) => state,
+            Err(e) => {
+                tracing::warn!(
+                    "Ignoring corrupt counter state {}, counters start from zero: {}",
+                    path.display(),
+                    e
+                );
+                Self::default()
+            }
+        }
+    }
+
+    fn parse(text: &str) -> Result<Self> {
+        let mut values = BTreeMap::new();
+        for (number, line) in text.lines().enumerate() {
+            let corrupt = || UncflowError::ParseError(format!("line {}: {:?}", number + 1, line));
+            let (key, value) = line.rsplit_once(' ').ok_or_else(corrupt)?;
+            let value: f64 = value.parse().map_err(|_| corrupt())?;
+            // Counters only go up from zero
+            if !key.ends_with('}') || !value.is_finite() || value < 0.0 {
+                return Err(corrupt());
+            }
+            values.insert(key.to_string(), value);
+        }
+        Ok(Self { values })
+    }
+
+    /// Write the state to `path`, replacing it atomically
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let text: String = self
+            .values
+            .iter()
+            .map(|(key, value)| format!("{key} {value}\n"))
+            .collect();
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, text)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{Counter, Opts, Registry};
+
+    #[test]
+    fn test_save_restore_round_trip() {
+        let registry = Registry::new();
+        let energy = Counter::with_opts(
+            Opts::new("rapl_package_energy_joules_total", "energy").const_label("socket", "1"),
+        )
+        .unwrap();
+        energy.inc_by(1234.5);
+        registry.register(Box::new(energy)).unwrap();
+        // Not persisted
+        let reads = Counter::new("uncflow_reads_total", "reads").unwrap();
+        reads.inc_by(7.0);
+        registry.register(Box::new(reads)).unwrap();
+
+        let state = CounterState::from_families(&registry.gather());
+        let path = std::env::temp_dir().join(format!("uncflow-state-{}", std::process::id()));
+        state.save(&path).unwrap();
+
+        let restored = CounterState::load(&path);
+        assert_eq!(restored, state);
+        assert_eq!(
+            restored.get("rapl_package_energy_joules_total", &[("socket", "1")]),
+            1234.5
+        );
+        assert_eq!(restored.get("uncflow_reads_total", &[]), 0.0);
+
+        // Corrupt and missing files start fresh
+        std::fs::write(
+            &path,
+            "rapl_package_energy_joules_total{socket=\"1\"} lots\n",
+        )
+        .unwrap();
+        assert_eq!(CounterState::load(&path), CounterState::default());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(CounterState::load(&path), CounterState::default());
+    }
+}
@@ -0,0 +1,155 @@
+// Thermal Metrics Exporter
+//
+// Temperature and throttling of every monitored core and socket, for correlating
+// frequency and IPC drops with thermal or PROCHOT# throttling.
+
+use futures_util::future::BoxFuture;
+use parking_lot::Mutex;
+use prometheus::{GaugeVec, Opts, Registry};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::common::log_limiter;
+use crate::config::ExportConfig;
+use crate::counters::thermal::{ThermalMonitor, ThermalReading};
+use crate::error::Result;
+use crate::orchestrator::Subsystem;
+
+/// Temperature and throttle gauges of one scope (core or package)
+struct ThermalGauges {
+    temperature: GaugeVec,
+    throttle: GaugeVec,
+}
+
+impl ThermalGauges {
+    fn new(registry: &Registry, scope: &str, label: &str) -> Result<Self> {
+        let gauge_vec = |name: String, help: String| -> Result<GaugeVec> {
+            let gauge = GaugeVec::new(Opts::new(name, help), &[label])?;
+            registry.register(Box::new(gauge.clone()))?;
+            Ok(gauge)
+        };
+        Ok(Self {
+            temperature: gauge_vec(
+                format!("{scope}_temperature_celsius"),
+                format!("Current {scope} temperature"),
+            )?,
+            throttle: gauge_vec(
+                format!("{scope}_thermal_throttle"),
+                format!("1 while the {scope} is throttled by TCC or PROCHOT#, else 0"),
+            )?,
+        })
+    }
+
+    fn set(&self, readings: &BTreeMap<i32, ThermalReading>) {
+        for (id, reading) in readings {
+            let id = id.to_string();
+            let labels = [id.as_str()];
+            match reading.temperature {
+                Some(celsius) => self.temperature.with_label_values(&labels).set(celsius),
+                // No stale temperature while the sensor is not valid
+                None => {
+                    let _ = self.temperature.remove_label_values(&labels);
+                }
+            }
+            self.throttle
+                .with_label_values(&labels)
+                .set(if reading.throttling { 1.0 } else { 0.0 });
+        }
+    }
+}
+
+pub struct ThermalMetricExporter {
+    registry: Arc<Registry>,
+    monitor: Mutex<ThermalMonitor>,
+    core: ThermalGauges,
+    package: ThermalGauges,
+}
+
+impl ThermalMetricExporter {
+    pub fn new(config: ExportConfig) -> Result<Self> {
+        Self::with_monitor(ThermalMonitor::new(&config)?)
+    }
+
+    /// Create an exporter publishing what `monitor` reads
+    pub fn with_monitor(monitor: ThermalMonitor) -> Result<Self> {
+        let registry = Arc::new(Registry::new());
+        let core = ThermalGauges::new(&registry, "core", "core")?;
+        let package = ThermalGauges::new(&registry, "package", "socket")?;
+        Ok(Self {
+            registry,
+            monitor: Mutex::new(monitor),
+            core,
+            package,
+        })
+    }
+
+    /// Collect metrics once (called by orchestrator)
+    pub async fn collect(&self) -> Result<()> {
+        let status = self.monitor.lock().collect().inspect_err(|e| {
+            if log_limiter().allow("Thermal", &e.to_string()) {
+                tracing::error!("Failed to read thermal status: {}", e);
+            }
+        })?;
+        self.core.set(&status.core);
+        self.package.set(&status.package);
+        Ok(())
+    }
+
+    pub fn registry(&self) -> Arc<Registry> {
+        Arc::clone(&self.registry)
+    }
+}
+
+impl Subsystem for ThermalMetricExporter {
+    fn name(&self) -> &'static str {
+        "thermal"
+    }
+
+    fn init(config: ExportConfig) -> Result<Self> {
+        Self::new(config)
+    }
+
+    fn collect(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(ThermalMetricExporter::collect(self))
+    }
+
+    fn registry(&self) -> &Registry {
+        &self.registry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::msr::mock::MockMsr;
+    use crate::prom::raw::tests::gauge_value;
+    use std::collections::HashMap;
+    use uncflow_raw::current_arch::thermal::msr::*;
+
+    #[tokio::test]
+    async fn test_exports_core_temperature_and_throttle() {
+        let msr = MockMsr::new().leak();
+        msr.set(4, MSR_TEMPERATURE_TARGET, 0x0A5A_0000);
+        msr.set(4, IA32_THERM_STATUS, 0x8014_0001);
+        let config = ExportConfig::new(vec![1], vec![4]).with_uncore_cores(HashMap::from([(1, 4)]));
+        let exporter =
+            ThermalMetricExporter::with_monitor(ThermalMonitor::with_msr(&config, msr).unwrap())
+                .unwrap();
+        exporter.collect().await.unwrap();
+
+        let families = exporter.registry().gather();
+        let core = [("core", "4")];
+        assert_eq!(
+            gauge_value(&families, "core_temperature_celsius", &core),
+            Some(70.0)
+        );
+        assert_eq!(
+            gauge_value(&families, "core_thermal_throttle", &core),
+            Some(1.0)
+        );
+        assert_eq!(
+            gauge_value(&families, "package_thermal_throttle", &[("socket", "1")]),
+            Some(0.0)
+        );
+    }
+}
@@ -0,0 +1,133 @@
+// UPI Metrics Exporter
+
+use crate::counters::upi::{monitor::LINK_COUNTER_NAMES, UpiMonitor};
+use crate::error::Result;
+use crate::metrics::upi::UpiMetric;
+use crate::orchestrator::Subsystem;
+use crate::prom::raw::RawCounterGauges;
+use crate::prom::sockets::collect_sockets;
+use crate::ExportConfig;
+use futures_util::future::BoxFuture;
+use parking_lot::Mutex;
+use prometheus::{Gauge, Opts, Registry};
+use std::collections::HashMap;
+
+pub struct UpiMetricExporter {
+    monitors: Mutex<Vec<UpiMonitor>>,
+    registry: Registry,
+    gauges: HashMap<(i32, usize, UpiMetric), Gauge>,
+    raw_gauges: Option<RawCounterGauges>,
+}
+
+impl UpiMetricExporter {
+    pub fn new(config: ExportConfig) -> Result<Self> {
+        let registry = Registry::new();
+        let mut monitors = Vec::new();
+        let mut gauges = HashMap::new();
+
+        // Create monitors for each socket
+        for &socket in &config.sockets {
+            match UpiMonitor::new(socket) {
+                Ok(monitor) => monitors.push(monitor),
+                Err(e) => {
+                    tracing::warn!("Failed to initialize UPI for socket {}: {}", socket, e);
+                }
+            }
+        }
+
+        // Register gauges for each metric, socket and link combination
+        for monitor in &monitors {
+            let socket = monitor.socket();
+            for link in monitor.links() {
+                for metric in UpiMetric::all() {
+                    let gauge = Gauge::with_opts(
+                        prometheus::Opts::new(
+                            metric.name(),
+                            format!("UPI {} metric", metric.name()),
+                        )
+                        .const_label("socket", socket.to_string())
+                        .const_label("link", link.to_string()),
+                    )?;
+                    registry.register(Box::new(gauge.clone()))?;
+                    gauges.insert((socket, link, metric), gauge);
+                }
+            }
+        }
+
+        let raw_gauges = if config.export_raw {
+            Some(RawCounterGauges::new(
+                &registry,
+                Opts::new(
+                    "UPIRawCounter",
+                    "Per-link UPI counter delta before derivation",
+                ),
+                &["socket", "link"],
+            )?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            monitors: Mutex::new(monitors),
+            registry,
+            gauges,
+            raw_gauges,
+        })
+    }
+
+    /// Collect metrics once (called by orchestrator)
+    pub async fn collect(&self) -> Result<()> {
+        let mut monitors = self.monitors.lock();
+        let monitors = monitors.iter_mut().map(|m| (m.socket(), m));
+        collect_sockets("UPI", monitors, |socket, monitor| {
+            for ((link, metric), value) in monitor.collect_metrics()? {
+                if let Some(gauge) = self.gauges.get(&(socket, link, metric)) {
+                    gauge.set(value);
+                }
+            }
+            if let Some(raw_gauges) = &self.raw_gauges {
+                let socket = socket.to_string();
+                for (link, deltas) in monitor.link_deltas() {
+                    let link = link.to_string();
+                    for (counter, delta) in LINK_COUNTER_NAMES.iter().zip(deltas) {
+                        raw_gauges.set(&[socket.as_str(), link.as_str()], counter, *delta);
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Drop every socket's baselines
+    pub fn reset(&self) {
+        for monitor in self.monitors.lock().iter_mut() {
+            monitor.reset();
+        }
+    }
+
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+}
+
+impl Subsystem for UpiMetricExporter {
+    fn name(&self) -> &'static str {
+        "upi"
+    }
+
+    fn init(config: ExportConfig) -> Result<Self> {
+        Self::new(config)
+    }
+
+    fn collect(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(UpiMetricExporter::collect(self))
+    }
+
+    fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    fn reset(&self) {
+        UpiMetricExporter::reset(self)
+    }
+}
@@ -0,0 +1,26 @@
+//! IRP (I/O Request Processing) register definitions for Broadwell-EP
+//!
+//! Same device, function and register offsets as Haswell-EP.
+//!
+//! ## References
+//!
+//! - Intel® Xeon® Processor E5 and E7 v4 Family Uncore Performance Monitoring Reference Manual
+//! - Section: IRP Performance Monitoring
+
+pub use crate::arch::haswell::irp::{
+    pci, COUNTERS_PER_IRP, COUNTER_MASK, COUNTER_WIDTH_BITS, IRP_DEVICE, IRP_FUNCTION,
+};
+
+/// IRP PCI Device ID for Broadwell-EP
+pub const IRP_DEVICE_ID: u32 = 0x6F39;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_irp_pci_addresses() {
+        assert_eq!((IRP_DEVICE, IRP_FUNCTION, IRP_DEVICE_ID), (5, 6, 0x6F39));
+        assert_eq!(pci::IRP_UNIT_STATUS_ADDR, 0xF8);
+    }
+}
@@ -0,0 +1,13 @@
+//! Intel Broadwell-EP (Xeon E5/E7 v4) register definitions
+//!
+//! Broadwell-EP keeps the Haswell-EP uncore layout; only device IDs differ.
+//!
+//! ## Uncore Units
+//!
+//! - **IRP** (I/O Request Processing) - I/O arbitration
+//!
+//! ## References
+//!
+//! - Intel® Xeon® Processor E5 and E7 v4 Family Uncore Performance Monitoring Reference Manual
+
+pub mod irp;
@@ -0,0 +1,63 @@
+//! IRP (I/O Request Processing) register definitions for Haswell-EP
+//!
+//! Unlike Skylake-SP, the IRP unit is a single PCI device per socket whose
+//! counters and controls live in its config space.
+//!
+//! ## References
+//!
+//! - Intel® Xeon® Processor E5 and E7 v3 Family Uncore Performance Monitoring Reference Manual
+//! - Section: IRP Performance Monitoring
+
+use crate::register::counter_mask;
+
+/// Number of programmable counters in the IRP unit
+pub const COUNTERS_PER_IRP: usize = 4;
+
+/// Bit width of IRP counters as read through 32-bit config space accesses
+pub const COUNTER_WIDTH_BITS: u64 = 32;
+
+/// Mask applied to raw counter reads
+pub const COUNTER_MASK: u64 = counter_mask(COUNTER_WIDTH_BITS);
+
+/// IRP PCI device number
+pub const IRP_DEVICE: u32 = 5;
+
+/// IRP PCI function number
+pub const IRP_FUNCTION: u32 = 6;
+
+/// IRP PCI Device ID for Haswell-EP
+pub const IRP_DEVICE_ID: u32 = 0x2F39;
+
+/// PCI configuration addresses for the IRP unit
+pub mod pci {
+    /// IRP Unit Status register offset
+    pub const IRP_UNIT_STATUS_ADDR: u32 = 0xF8;
+
+    /// IRP Unit Control register offset
+    pub const IRP_UNIT_CTL_ADDR: u32 = 0xF4;
+
+    /// IRP Counter register offsets (4 counters)
+    pub const IRP_CTR_ADDR: [u32; 4] = [0xA0, 0xB0, 0xB8, 0xC0];
+
+    /// IRP Control register offsets (4 control registers)
+    pub const IRP_CTL_ADDR: [u32; 4] = [0xD8, 0xDC, 0xE0, 0xE4];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_irp_pci_addresses() {
+        assert_eq!((IRP_DEVICE, IRP_FUNCTION, IRP_DEVICE_ID), (5, 6, 0x2F39));
+        assert_eq!(pci::IRP_UNIT_CTL_ADDR, 0xF4);
+        assert_eq!(pci::IRP_CTR_ADDR.len(), COUNTERS_PER_IRP);
+        assert_eq!(pci::IRP_CTL_ADDR.len(), COUNTERS_PER_IRP);
+    }
+
+    #[test]
+    fn test_irp_counter_mask() {
+        assert_eq!(COUNTER_MASK, (1u64 << COUNTER_WIDTH_BITS) - 1);
+        assert_eq!(COUNTER_MASK, u32::MAX as u64);
+    }
+}
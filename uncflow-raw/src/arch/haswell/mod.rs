@@ -0,0 +1,14 @@
+//! Intel Haswell-EP (Xeon E5/E7 v3) register definitions
+//!
+//! Only the units the agent still drives through PCI config space on this
+//! generation are covered here.
+//!
+//! ## Uncore Units
+//!
+//! - **IRP** (I/O Request Processing) - I/O arbitration
+//!
+//! ## References
+//!
+//! - Intel® Xeon® Processor E5 and E7 v3 Family Uncore Performance Monitoring Reference Manual
+
+pub mod irp;
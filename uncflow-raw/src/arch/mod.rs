@@ -6,10 +6,18 @@
 //!
 //! ## Supported Architectures
 //!
+//! - Haswell-EP (`haswell` feature) - PCI-based IRP unit only
+//! - Broadwell-EP (`broadwell` feature) - PCI-based IRP unit only
 //! - **Skylake-SP** (`skylake` feature) - Intel Xeon Scalable (Skylake Server)
 //! - Cascade Lake-SP (`cascadelake` feature) - Coming soon
 //! - Ice Lake-SP (`icelake` feature) - Coming soon
 
+#[cfg(feature = "haswell")]
+pub mod haswell;
+
+#[cfg(feature = "broadwell")]
+pub mod broadwell;
+
 #[cfg(feature = "skylake")]
 pub mod skylake;
 
@@ -8,16 +8,27 @@
 //! - Intel® Xeon® Processor Scalable Family Uncore Performance Monitoring Reference Manual
 //! - Based on peacock C++ implementation and uncflow-agent
 
-use crate::register::RegisterLayout;
+use crate::register::{counter_mask, RegisterLayout};
 
 /// Number of CHA units in Skylake-SP (up to 28, varies by SKU)
 pub const CHA_COUNT: usize = 28;
 
+/// Snoop filter entries per CHA unit (2048 sets of 12 ways)
+///
+/// The snoop filter tracks lines held in core caches but not in the LLC slice.
+/// An LLC victim is a line evicted from the LLC slice itself; a snoop filter
+/// eviction happens when the filter runs out of ways and has to back-invalidate
+/// a line in the cores that hold it, even if those cores still use it.
+pub const SF_ENTRIES_PER_CHA: u64 = 2048 * 12;
+
 /// Number of programmable counters per CHA unit
 pub const COUNTERS_PER_CHA: usize = 4;
 
 /// Bit width of CHA counters
-pub const COUNTER_WIDTH_BITS: u64 = 48;
+pub const COUNTER_WIDTH_BITS: u64 = super::UNCORE_COUNTER_WIDTH_BITS;
+
+/// Mask applied to raw counter reads
+pub const COUNTER_MASK: u64 = counter_mask(COUNTER_WIDTH_BITS);
 
 /// Stride between CHA box MSR addresses
 pub const CHA_BOX_STRIDE: u64 = 0x10;
@@ -70,46 +81,68 @@ pub mod msr {
 /// CHA Unit Box Control Register layout
 ///
 /// Controls freeze/reset for all counters in a CHA unit.
-#[derive(Debug, Clone, Copy, Default)]
+///
+/// ## Register Format
+///
+/// | Bits   | Field               | Description                          |
+/// |--------|---------------------|--------------------------------------|
+/// | 0      | reset_control       | Reset all counter control registers  |
+/// | 1      | reset_counters      | Reset all counters to 0              |
+/// | 2-7    | reserved            |                                      |
+/// | 8      | freeze              | Freeze all counters in the box       |
+/// | 9-15   | reserved            |                                      |
+/// | 16     | freeze_enable       | Allow the box to be frozen           |
+///
+/// The box is unfrozen by clearing `freeze` while leaving `freeze_enable`
+/// set; there is no separate unfreeze bit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct ChaBoxControl {
-    /// Freeze all counters in this CHA unit
+    /// Reset all control registers (bit 0)
+    pub reset_control: bool,
+    /// Reset all counters to 0 (bit 1)
+    pub reset_counters: bool,
+    /// Freeze all counters in this CHA unit (bit 8)
     pub freeze: bool,
-    /// Enable freeze control
+    /// Enable freeze control (bit 16)
     pub freeze_enable: bool,
-    /// Reset all counters to 0
-    pub reset_counters: bool,
-    /// Reset all control registers
-    pub reset_control: bool,
 }
 
-impl RegisterLayout for ChaBoxControl {
-    fn to_msr_value(&self) -> u64 {
-        let mut value = 0u64;
-        if self.freeze {
-            value |= 1 << 0;
-        }
-        if self.freeze_enable {
-            value |= 1 << 8;
-        }
-        if self.reset_counters {
-            value |= 1 << 1;
-        }
-        if self.reset_control {
-            value |= 1 << 2;
+impl ChaBoxControl {
+    /// Stop all counters in the box while it is reprogrammed
+    pub const fn frozen() -> Self {
+        Self {
+            reset_control: false,
+            reset_counters: false,
+            freeze: true,
+            freeze_enable: true,
         }
-        // Unfreeze bit is at position 16
-        if !self.freeze && self.freeze_enable {
-            value |= 1 << 16;
+    }
+
+    /// Let the counters run again after [`ChaBoxControl::frozen`]
+    pub const fn unfrozen() -> Self {
+        Self {
+            reset_control: false,
+            reset_counters: false,
+            freeze: false,
+            freeze_enable: true,
         }
-        value
+    }
+}
+
+impl RegisterLayout for ChaBoxControl {
+    fn to_msr_value(&self) -> u64 {
+        (if self.reset_control { 1 << 0 } else { 0 })
+            | (if self.reset_counters { 1 << 1 } else { 0 })
+            | (if self.freeze { 1 << 8 } else { 0 })
+            | (if self.freeze_enable { 1 << 16 } else { 0 })
     }
 
     fn from_msr_value(value: u64) -> Self {
         Self {
-            freeze: (value & (1 << 0)) != 0,
-            freeze_enable: (value & (1 << 8)) != 0,
+            reset_control: (value & (1 << 0)) != 0,
             reset_counters: (value & (1 << 1)) != 0,
-            reset_control: (value & (1 << 2)) != 0,
+            freeze: (value & (1 << 8)) != 0,
+            freeze_enable: (value & (1 << 16)) != 0,
         }
     }
 }
@@ -162,6 +195,11 @@ pub struct ChaCounterControl {
     pub occupancy_edge_detect: bool,
 }
 
+impl ChaCounterControl {
+    /// Bits that read back as written (all defined fields, no reserved bits)
+    pub const VERIFY_MASK: u64 = 0xFFC7_FFFF;
+}
+
 impl RegisterLayout for ChaCounterControl {
     fn to_msr_value(&self) -> u64 {
         (self.event_select as u64)
@@ -241,27 +279,27 @@ impl RegisterLayout for ChaFilter0 {
 /// | Bits   | Field  | Description                     |
 /// |--------|--------|---------------------------------|
 /// | 0-16   | tid    | Thread ID filter                |
-/// | 17-23  | state  | Cache line state filter         |
-/// | 24-63  | reserved |                              |
+/// | 17-26  | state  | Cache line state filter         |
+/// | 27-63  | reserved |                              |
 #[derive(Debug, Clone, Copy, Default)]
 pub struct ChaFilter1 {
     /// Thread ID filter (bits 0-16)
     pub tid: u32,
 
-    /// Cache line state filter (bits 17-23)
-    /// Bit flags for M, E, S, I states
-    pub state: u8,
+    /// Cache line state filter (bits 17-26)
+    /// One bit per state, see [`states`]; several may be set
+    pub state: u16,
 }
 
 impl RegisterLayout for ChaFilter1 {
     fn to_msr_value(&self) -> u64 {
-        (self.tid as u64 & 0x1FFFF) | ((self.state as u64) << 17)
+        (self.tid as u64 & 0x1FFFF) | ((self.state as u64 & 0x3FF) << 17)
     }
 
     fn from_msr_value(value: u64) -> Self {
         Self {
             tid: (value & 0x1FFFF) as u32,
-            state: ((value >> 17) & 0x7F) as u8,
+            state: ((value >> 17) & 0x3FF) as u16,
         }
     }
 
@@ -269,6 +307,9 @@ impl RegisterLayout for ChaFilter1 {
         if self.tid > 0x1FFFF {
             return Err("TID must be <= 0x1FFFF (17 bits)");
         }
+        if self.state & !states::ALL != 0 {
+            return Err("State must only select defined states (I, SF S/E/H, LLC S/E/M/F)");
+        }
         Ok(())
     }
 }
@@ -287,8 +328,19 @@ pub mod events {
     /// LLC Victims (evictions)
     pub const LLC_VICTIMS: u8 = 0x37;
 
+    /// Snoop filter evictions; the umask selects the state of the evicted entry
+    pub const SF_EVICTION: u8 = 0x3D;
+
+    /// Snoop filter occupancy, adds the number of valid entries every cycle;
+    /// the umask selects the states counted
+    pub const SF_OCCUPANCY: u8 = 0x3E;
+
     /// Clockticks
     pub const CLOCKTICKS: u8 = 0x00;
+
+    /// Ingress request queue (IRQ) rejects, RxC_IRQ0_REJECT; the umask selects
+    /// the mesh credit that was missing
+    pub const RXC_IRQ0_REJECT: u8 = 0x18;
 }
 
 /// CHA unit masks (event sub-selectors)
@@ -305,6 +357,30 @@ pub mod umasks {
         pub const ALL: u8 = 0xFF;
     }
 
+    /// IRQ reject umasks, one per mesh credit class
+    pub mod irq_reject {
+        /// No AD VN0 credit to send a request (reads towards memory or a remote socket)
+        pub const AD_REQ_VN0: u8 = 0x01;
+
+        /// No BL VN0 credit to send a writeback
+        pub const BL_WB_VN0: u8 = 0x08;
+    }
+
+    /// Snoop filter eviction and occupancy umasks, one per entry state
+    pub mod sf {
+        /// Modified (H state)
+        pub const M: u8 = 0x01;
+
+        /// Exclusive
+        pub const E: u8 = 0x02;
+
+        /// Shared
+        pub const S: u8 = 0x04;
+
+        /// Entries in any state
+        pub const ALL: u8 = M | E | S;
+    }
+
     /// LLC lookup umasks
     pub mod llc_lookup {
         /// Read lookup
@@ -322,27 +398,36 @@ pub mod umasks {
 }
 
 /// Cache line states for filter1 register
+///
+/// Each state has its own bit of the state field (register bit = 17 + log2(value)):
+/// bit 17 is I, bits 18-20 the snoop filter states and bits 21-24 the LLC states.
 pub mod states {
-    /// Modified
-    pub const M: u8 = 0x40;
+    /// Invalid (bit 17)
+    pub const I: u16 = 0x01;
+
+    /// Snoop Filter Shared (bit 18)
+    pub const SFS: u16 = 0x02;
+
+    /// Snoop Filter Exclusive (bit 19)
+    pub const SFE: u16 = 0x04;
 
-    /// Exclusive
-    pub const E: u8 = 0x20;
+    /// Snoop Filter H state, a line a core may hold modified (bit 20)
+    pub const SFM: u16 = 0x08;
 
-    /// Shared
-    pub const S: u8 = 0x02;
+    /// Shared (bit 21)
+    pub const S: u16 = 0x10;
 
-    /// Invalid
-    pub const I: u8 = 0x01;
+    /// Exclusive (bit 22)
+    pub const E: u16 = 0x20;
 
-    /// Snoop Filter Modified
-    pub const SFM: u8 = 0x08;
+    /// Modified (bit 23)
+    pub const M: u16 = 0x40;
 
-    /// Snoop Filter Exclusive
-    pub const SFE: u8 = 0x04;
+    /// Forward (bit 24)
+    pub const F: u16 = 0x80;
 
-    /// Snoop Filter Shared
-    pub const SFS: u8 = 0x02;
+    /// Every defined state
+    pub const ALL: u16 = I | SFS | SFE | SFM | S | E | M | F;
 }
 
 #[cfg(test)]
@@ -381,6 +466,88 @@ mod tests {
         assert!(ctrl.validate().is_err());
     }
 
+    #[test]
+    fn test_cha_verify_mask() {
+        let all = ChaCounterControl {
+            event_select: 0xFF,
+            unit_mask: 0xFF,
+            queue_occupancy_select: 0x03,
+            edge_detect: true,
+            enable: true,
+            invert: true,
+            threshold: 0x3F,
+            occupancy_invert: true,
+            occupancy_edge_detect: true,
+        };
+        assert_eq!(all.to_msr_value(), ChaCounterControl::VERIFY_MASK);
+    }
+
+    #[test]
+    fn test_cha_box_control_encoding() {
+        assert_eq!(ChaBoxControl::frozen().to_msr_value(), 0x10100);
+        assert_eq!(ChaBoxControl::unfrozen().to_msr_value(), 0x10000);
+
+        let reset = ChaBoxControl {
+            reset_control: true,
+            reset_counters: true,
+            ..ChaBoxControl::frozen()
+        };
+        assert_eq!(reset.to_msr_value(), 0x10103);
+        assert_eq!(ChaBoxControl::from_msr_value(0x10103), reset);
+        assert_eq!(
+            ChaBoxControl::from_msr_value(0x10000),
+            ChaBoxControl::unfrozen()
+        );
+    }
+
+    #[test]
+    fn test_filter1_states_do_not_alias() {
+        let all = [
+            states::I,
+            states::SFS,
+            states::SFE,
+            states::SFM,
+            states::S,
+            states::E,
+            states::M,
+            states::F,
+        ];
+        for (i, a) in all.iter().enumerate() {
+            assert_eq!(a.count_ones(), 1);
+            for b in &all[i + 1..] {
+                assert_eq!(a & b, 0, "0x{a:X} and 0x{b:X} share a bit");
+            }
+        }
+
+        // LLC M, E and S only: bits 21-23, no snoop filter state
+        let filter = ChaFilter1 {
+            tid: 0,
+            state: states::M | states::E | states::S,
+        };
+        assert_eq!(filter.to_msr_value(), 0x00E0_0000);
+        assert_eq!(ChaFilter1::from_msr_value(0x00E0_0000).state, 0x70);
+
+        // Snoop filter shared alone: bit 18 only
+        let filter = ChaFilter1 {
+            tid: 0,
+            state: states::SFS,
+        };
+        assert_eq!(filter.to_msr_value(), 1 << 18);
+
+        assert!(ChaFilter1 {
+            tid: 0,
+            state: states::ALL
+        }
+        .validate()
+        .is_ok());
+        assert!(ChaFilter1 {
+            tid: 0,
+            state: 0x100
+        }
+        .validate()
+        .is_err());
+    }
+
     #[test]
     fn test_cha_msr_addresses() {
         assert_eq!(msr::box_ctl(0), 0xE00);
@@ -7,7 +7,7 @@
 //! - Intel® 64 and IA-32 Architectures Software Developer's Manual, Volume 3B
 //! - Chapter 18: Performance Monitoring
 
-use crate::register::RegisterLayout;
+use crate::register::{counter_mask, RegisterLayout};
 
 /// Number of general-purpose performance counters per core
 pub const CORE_PMU_COUNTERS: usize = 4;
@@ -15,6 +15,12 @@ pub const CORE_PMU_COUNTERS: usize = 4;
 /// Number of fixed-function performance counters
 pub const CORE_FIXED_COUNTERS: usize = 3;
 
+/// Bit width of the fixed and general-purpose counters
+pub const COUNTER_WIDTH_BITS: u64 = super::CORE_COUNTER_WIDTH_BITS;
+
+/// Mask applied to raw counter reads
+pub const COUNTER_MASK: u64 = counter_mask(COUNTER_WIDTH_BITS);
+
 /// MSR addresses for Core PMU
 pub mod msr {
     /// Performance Event Select registers (IA32_PERFEVTSELx)
@@ -45,6 +51,20 @@ pub mod msr {
 
     /// Performance Counter Global Status Reset
     pub const IA32_PERF_GLOBAL_STATUS_RESET: u64 = 0x390;
+
+    /// Offcore response matrices, used by OFFCORE_RESPONSE_0 and OFFCORE_RESPONSE_1
+    pub const MSR_OFFCORE_RSP_0: u64 = 0x1A6;
+    pub const MSR_OFFCORE_RSP_1: u64 = 0x1A7;
+}
+
+/// Offcore response event codes
+///
+/// Each event counts the requests matching the response matrix in its own MSR.
+pub mod offcore_events {
+    /// OFFCORE_RESPONSE_0, filtered by MSR_OFFCORE_RSP_0
+    pub const OFFCORE_RESPONSE_0: (u8, u8) = (0xB7, 0x01);
+    /// OFFCORE_RESPONSE_1, filtered by MSR_OFFCORE_RSP_1
+    pub const OFFCORE_RESPONSE_1: (u8, u8) = (0xBB, 0x01);
 }
 
 /// Core Performance Event Select Register layout
@@ -227,6 +247,112 @@ impl RegisterLayout for FixedCtrCtrl {
     }
 }
 
+/// Offcore Response Register layout (MSR_OFFCORE_RSP_0/1)
+///
+/// A request is counted if it matches a request type bit, and its response
+/// matches a supplier bit and a snoop bit (or ANY_RESPONSE).
+///
+/// ## Register Format
+///
+/// | Bits   | Field    | Description                              |
+/// |--------|----------|------------------------------------------|
+/// | 0-15   | request  | Request types (demand/prefetch, RFO)     |
+/// | 16-29  | supplier | Where the data came from                 |
+/// | 30-37  | snoop    | Snoop response                           |
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OffcoreResponse {
+    /// Request type bits (bits 0-15), see [`offcore_request`]
+    pub request: u16,
+
+    /// Supplier bits (bits 16-29), see [`offcore_supplier`]
+    pub supplier: u16,
+
+    /// Snoop response bits (bits 30-37), see [`offcore_snoop`]
+    pub snoop: u8,
+}
+
+impl RegisterLayout for OffcoreResponse {
+    fn to_msr_value(&self) -> u64 {
+        (self.request as u64)
+            | (((self.supplier & 0x3FFF) as u64) << 16)
+            | ((self.snoop as u64) << 30)
+    }
+
+    fn from_msr_value(value: u64) -> Self {
+        Self {
+            request: (value & 0xFFFF) as u16,
+            supplier: ((value >> 16) & 0x3FFF) as u16,
+            snoop: ((value >> 30) & 0xFF) as u8,
+        }
+    }
+
+    fn validate(&self) -> Result<(), &'static str> {
+        if self.request == 0 {
+            return Err("Offcore response must select at least one request type");
+        }
+        if self.supplier > 0x3FFF {
+            return Err("Supplier must be <= 0x3FFF (14 bits)");
+        }
+        if self.supplier == 0 && self.snoop == 0 {
+            return Err("Offcore response must select at least one supplier or snoop response");
+        }
+        Ok(())
+    }
+}
+
+/// Offcore response request type bits (relative to bit 0)
+pub mod offcore_request {
+    pub const DEMAND_DATA_RD: u16 = 1 << 0;
+    pub const DEMAND_RFO: u16 = 1 << 1;
+    pub const DEMAND_CODE_RD: u16 = 1 << 2;
+    pub const PF_L2_DATA_RD: u16 = 1 << 4;
+    pub const PF_L2_RFO: u16 = 1 << 5;
+    pub const PF_L3_DATA_RD: u16 = 1 << 7;
+    pub const PF_L3_RFO: u16 = 1 << 8;
+    pub const PF_L1D_AND_SW: u16 = 1 << 10;
+    pub const OTHER: u16 = 1 << 15;
+
+    /// Demand and prefetch data reads
+    pub const ALL_DATA_RD: u16 = DEMAND_DATA_RD | PF_L2_DATA_RD | PF_L3_DATA_RD | PF_L1D_AND_SW;
+    /// Demand and prefetch reads for ownership
+    pub const ALL_RFO: u16 = DEMAND_RFO | PF_L2_RFO | PF_L3_RFO;
+}
+
+/// Offcore response supplier bits (relative to bit 16)
+pub mod offcore_supplier {
+    pub const ANY_RESPONSE: u16 = 1 << 0;
+    pub const SUPPLIER_NONE: u16 = 1 << 1;
+    pub const L3_HIT_M: u16 = 1 << 2;
+    pub const L3_HIT_E: u16 = 1 << 3;
+    pub const L3_HIT_S: u16 = 1 << 4;
+    pub const L3_HIT_F: u16 = 1 << 5;
+    pub const L3_MISS_LOCAL_DRAM: u16 = 1 << 10;
+    pub const L3_MISS_REMOTE_HOP0_DRAM: u16 = 1 << 11;
+    pub const L3_MISS_REMOTE_HOP1_DRAM: u16 = 1 << 12;
+    pub const L3_MISS_REMOTE_HOP2P_DRAM: u16 = 1 << 13;
+
+    /// Any LLC hit
+    pub const L3_HIT: u16 = L3_HIT_M | L3_HIT_E | L3_HIT_S | L3_HIT_F;
+    /// DRAM on another socket, any number of hops away
+    pub const L3_MISS_REMOTE_DRAM: u16 =
+        L3_MISS_REMOTE_HOP0_DRAM | L3_MISS_REMOTE_HOP1_DRAM | L3_MISS_REMOTE_HOP2P_DRAM;
+}
+
+/// Offcore response snoop bits (relative to bit 30)
+pub mod offcore_snoop {
+    pub const SPL_HIT: u8 = 1 << 0;
+    pub const SNOOP_NONE: u8 = 1 << 1;
+    pub const SNOOP_NOT_NEEDED: u8 = 1 << 2;
+    pub const SNOOP_MISS: u8 = 1 << 3;
+    pub const SNOOP_HIT_NO_FWD: u8 = 1 << 4;
+    pub const SNOOP_HIT_WITH_FWD: u8 = 1 << 5;
+    pub const SNOOP_HITM: u8 = 1 << 6;
+    pub const SNOOP_NON_DRAM: u8 = 1 << 7;
+
+    /// Every snoop outcome except SPL_HIT
+    pub const ANY_SNOOP: u8 = 0xFE;
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -273,4 +399,52 @@ mod tests {
         assert_eq!(decoded.ctr1_os, ctrl.ctr1_os);
         assert_eq!(decoded.ctr2_usr, ctrl.ctr2_usr);
     }
+
+    #[test]
+    fn test_offcore_response_encoding() {
+        // OFFCORE_RESPONSE.ALL_DATA_RD.L3_MISS_LOCAL_DRAM.ANY_SNOOP from the Skylake-SP event list
+        let local_dram = OffcoreResponse {
+            request: offcore_request::ALL_DATA_RD,
+            supplier: offcore_supplier::L3_MISS_LOCAL_DRAM,
+            snoop: offcore_snoop::ANY_SNOOP,
+        };
+        assert_eq!(local_dram.to_msr_value(), 0x3F_8400_0491);
+        assert_eq!(OffcoreResponse::from_msr_value(0x3F_8400_0491), local_dram);
+
+        // OFFCORE_RESPONSE.ALL_RFO.L3_MISS.SNOOP_MISS_OR_NO_FWD
+        let rfo_miss = OffcoreResponse {
+            request: offcore_request::ALL_RFO,
+            supplier: offcore_supplier::L3_MISS_LOCAL_DRAM | offcore_supplier::L3_MISS_REMOTE_DRAM,
+            snoop: offcore_snoop::SNOOP_MISS | offcore_snoop::SNOOP_HIT_NO_FWD,
+        };
+        assert_eq!(rfo_miss.to_msr_value(), 0x06_3C00_0122);
+    }
+
+    #[test]
+    fn test_offcore_response_validation() {
+        let valid = OffcoreResponse {
+            request: offcore_request::DEMAND_DATA_RD,
+            supplier: offcore_supplier::ANY_RESPONSE,
+            snoop: 0,
+        };
+        assert!(valid.validate().is_ok());
+
+        let no_request = OffcoreResponse {
+            request: 0,
+            ..valid
+        };
+        assert!(no_request.validate().is_err());
+
+        let no_response = OffcoreResponse {
+            supplier: 0,
+            ..valid
+        };
+        assert!(no_response.validate().is_err());
+
+        let wide_supplier = OffcoreResponse {
+            supplier: 0x4000,
+            ..valid
+        };
+        assert!(wide_supplier.validate().is_err());
+    }
 }
@@ -0,0 +1,50 @@
+//! C-state residency counter definitions for Skylake-SP
+//!
+//! Each residency MSR counts, at the TSC frequency, the cycles its package or
+//! core spent in the C-state, so the delta of a residency counter over the delta
+//! of the TSC read on the same CPU is the fraction of time spent in the state.
+//! All of them are read-only 64-bit counters that never wrap in practice.
+//!
+//! Package C3 and C7 are not entered by Skylake-SP parts and read zero there.
+//!
+//! ## References
+//!
+//! - Intel® 64 and IA-32 Architectures Software Developer's Manual, Volume 4
+
+/// MSR addresses of the residency counters and the TSC they are compared against
+pub mod msr {
+    /// Time stamp counter
+    pub const IA32_TIME_STAMP_COUNTER: u64 = 0x10;
+
+    /// Package C2 residency
+    pub const MSR_PKG_C2_RESIDENCY: u64 = 0x60D;
+
+    /// Package C3 residency
+    pub const MSR_PKG_C3_RESIDENCY: u64 = 0x3F8;
+
+    /// Package C6 residency
+    pub const MSR_PKG_C6_RESIDENCY: u64 = 0x3F9;
+
+    /// Core C3 residency
+    pub const MSR_CORE_C3_RESIDENCY: u64 = 0x3FC;
+
+    /// Core C6 residency
+    pub const MSR_CORE_C6_RESIDENCY: u64 = 0x3FD;
+
+    /// Core C7 residency
+    pub const MSR_CORE_C7_RESIDENCY: u64 = 0x3FE;
+}
+
+/// Package C-states with a residency counter: (name, MSR)
+pub const PACKAGE_CSTATES: [(&str, u64); 3] = [
+    ("c2", msr::MSR_PKG_C2_RESIDENCY),
+    ("c3", msr::MSR_PKG_C3_RESIDENCY),
+    ("c6", msr::MSR_PKG_C6_RESIDENCY),
+];
+
+/// Core C-states with a residency counter: (name, MSR)
+pub const CORE_CSTATES: [(&str, u64); 3] = [
+    ("c3", msr::MSR_CORE_C3_RESIDENCY),
+    ("c6", msr::MSR_CORE_C6_RESIDENCY),
+    ("c7", msr::MSR_CORE_C7_RESIDENCY),
+];
@@ -6,8 +6,25 @@
 //!
 //! - Based on peacock C++ implementation
 //! - Intel® Xeon® Processor Scalable Family Uncore Performance Monitoring Reference Manual
+//!
+//! ## IOMMU (VT-d) events
+//!
+//! The IOMMU events share event codes with the occupancy and TLB events; the
+//! umask picks the IOMMU sub-event.
+//!
+//! | Event | Umask | Constant                              | Counts                                   |
+//! |-------|-------|---------------------------------------|------------------------------------------|
+//! | 0x40  | 0x01  | `IOMMU_FIRST_LOOKUPS`                 | First lookups of a DMA translation       |
+//! | 0x40  | 0x20  | `IOMMU_MISSES`                        | Lookups missing every IOTLB/paging cache |
+//! | 0x40  | 0x40  | `IOMMU_CTXT_CACHE_LOOKUPS`            | Context cache lookups                    |
+//! | 0x40  | 0x80  | `IOMMU_CTXT_CACHE_HITS`               | Context cache hits                       |
+//! | 0x41  | 0xC0  | `IOMMU_NUM_MEM_ACCESSES`              | Page-walker reads of the remapping tables|
+//! | 0x43  | 0x01  | `IOMMU_PWT_OCCUPANCY`                 | Page walks in flight, added every cycle  |
+//!
+//! Each miss starts a page walk, so the occupancy divided by the misses is the
+//! average walk latency in IO clock cycles.
 
-use crate::register::RegisterLayout;
+use crate::register::{counter_mask, RegisterLayout};
 
 /// Number of IIO channels per socket (Skylake-SP has 3 IIO stacks)
 pub const IIO_CHANNEL_COUNT: usize = 3;
@@ -15,14 +32,26 @@ pub const IIO_CHANNEL_COUNT: usize = 3;
 /// Number of PCIe ports per IIO channel
 pub const IIO_PCIE_PORT_COUNT: usize = 4;
 
+/// Stack (CPUBUSNO byte index) of each IIO channel
+///
+/// Stack 0 is the legacy CBDMA/DMI stack, the PCIe channels are stacks 1-3.
+/// Their root ports A-D are devices 0-3, function 0, on the stack's bus.
+pub const IIO_CHANNEL_STACKS: [usize; IIO_CHANNEL_COUNT] = [1, 2, 3];
+
 /// Number of programmable counters per IIO unit
 pub const IIO_COUNTERS_PER_UNIT: usize = 4;
 
-/// Bit width of IIO free-running counters (PCIe bandwidth counters)
+/// Bit width of IIO free-running counters (IO clock, PCIe bandwidth and utilization)
 pub const IIO_COUNTER_WIDTH_BITS: u64 = 36;
 
+/// Mask applied to free-running counter reads
+pub const IIO_COUNTER_MASK: u64 = counter_mask(IIO_COUNTER_WIDTH_BITS);
+
 /// Bit width of IIO programmable counters
-pub const UNCORE_COUNTER_WIDTH_BITS: u64 = 48;
+pub const COUNTER_WIDTH_BITS: u64 = super::UNCORE_COUNTER_WIDTH_BITS;
+
+/// Mask applied to programmable counter reads
+pub const COUNTER_MASK: u64 = counter_mask(COUNTER_WIDTH_BITS);
 
 /// MSR addresses for IIO units
 pub mod msr {
@@ -56,7 +85,10 @@ pub mod msr {
     /// IIO Unit Counter 3 Value
     pub const IIO_UNIT_CTR3: [u64; 3] = [0x0A64, 0x0A84, 0x0AA4];
 
-    /// IIO Unit Clock Counter (uncore clock)
+    /// IIO Unit Clock Counter (free-running IO clock)
+    ///
+    /// Also the denominator of the PCIe utilization counters, which count
+    /// port active cycles of the same clock.
     pub const IIO_UNIT_CLK: [u64; 3] = [0x0A65, 0x0A85, 0x0AA5];
 
     /// PCIe free-running bandwidth counters - Inbound
@@ -74,6 +106,22 @@ pub mod msr {
         [0x0B24, 0x0B25, 0x0B26, 0x0B27],
         [0x0B34, 0x0B35, 0x0B36, 0x0B37],
     ];
+
+    /// PCIe free-running utilization counters - Inbound active cycles
+    /// [channel][port]
+    pub const IIO_PCIE_UTIL_IN: [[u64; 4]; 3] = [
+        [0x0B18, 0x0B19, 0x0B1A, 0x0B1B],
+        [0x0B28, 0x0B29, 0x0B2A, 0x0B2B],
+        [0x0B38, 0x0B39, 0x0B3A, 0x0B3B],
+    ];
+
+    /// PCIe free-running utilization counters - Outbound active cycles
+    /// [channel][port]
+    pub const IIO_PCIE_UTIL_OUT: [[u64; 4]; 3] = [
+        [0x0B1C, 0x0B1D, 0x0B1E, 0x0B1F],
+        [0x0B2C, 0x0B2D, 0x0B2E, 0x0B2F],
+        [0x0B3C, 0x0B3D, 0x0B3E, 0x0B3F],
+    ];
 }
 
 /// IIO Unit Counter Control Register layout
@@ -157,6 +205,12 @@ pub struct IioCounterControl {
     pub fc_mask: u8,
 }
 
+impl IioCounterControl {
+    /// Bits that read back as written: excludes reserved bits and the
+    /// self-clearing reset_counter bit (17)
+    pub const VERIFY_MASK: u64 = 0x7FFF_FFDC_FFFF;
+}
+
 impl RegisterLayout for IioCounterControl {
     fn to_msr_value(&self) -> u64 {
         (self.event_select as u64)
@@ -217,6 +271,15 @@ pub mod events {
 
     /// Uncore clockticks
     pub const CLOCKTICKS: u8 = 0x01;
+
+    /// IOMMU lookups and IOTLB/context cache hits and misses
+    pub const IOMMU0: u8 = 0x40;
+
+    /// IOMMU page-walker memory accesses
+    pub const IOMMU1: u8 = 0x41;
+
+    /// IOMMU page-walk tracker occupancy
+    pub const IOMMU3: u8 = 0x43;
 }
 
 /// IIO unit masks (event sub-selectors)
@@ -248,6 +311,24 @@ pub mod umasks {
     /// Completion inserts umask
     pub const COMP_INSERTS: u8 = 0x04;
 
+    /// IOMMU0: first lookup of each DMA translation
+    pub const IOMMU_FIRST_LOOKUPS: u8 = 0x01;
+
+    /// IOMMU0: lookup missed the IOTLB and paging structure caches
+    pub const IOMMU_MISSES: u8 = 0x20;
+
+    /// IOMMU0: context cache lookups
+    pub const IOMMU_CTXT_CACHE_LOOKUPS: u8 = 0x40;
+
+    /// IOMMU0: context cache hits
+    pub const IOMMU_CTXT_CACHE_HITS: u8 = 0x80;
+
+    /// IOMMU1: memory reads by the page walker
+    pub const IOMMU_NUM_MEM_ACCESSES: u8 = 0xC0;
+
+    /// IOMMU3: page walks in flight per cycle
+    pub const IOMMU_PWT_OCCUPANCY: u8 = 0x01;
+
     /// All channels mask (use with channel_mask field)
     pub const CH_MASK_ALL: u8 = 0xFF;
 
@@ -298,4 +379,70 @@ mod tests {
         ctrl.fc_mask = 0x08; // Too large (3 bits = max 0x07)
         assert!(ctrl.validate().is_err());
     }
+
+    #[test]
+    fn test_free_running_addresses() {
+        use msr::*;
+
+        for ch in 0..IIO_CHANNEL_COUNT {
+            // Each stack's free-running block is 16 MSRs: bandwidth in/out, then utilization in/out
+            let base = 0x0B10 + 0x10 * ch as u64;
+            for port in 0..IIO_PCIE_PORT_COUNT {
+                let port = port as u64;
+                assert_eq!(IIO_PCIE_BANDWIDTH_IN[ch][port as usize], base + port);
+                assert_eq!(IIO_PCIE_BANDWIDTH_OUT[ch][port as usize], base + 4 + port);
+                assert_eq!(IIO_PCIE_UTIL_IN[ch][port as usize], base + 8 + port);
+                assert_eq!(IIO_PCIE_UTIL_OUT[ch][port as usize], base + 12 + port);
+            }
+            // IO clock sits at offset 5 of the stack's unit MSRs
+            assert_eq!(IIO_UNIT_CLK[ch], IIO_UNIT_BOX_CTL[ch] + 5);
+        }
+    }
+
+    #[test]
+    fn test_iommu_event_config() {
+        let misses = IioCounterControl {
+            event_select: events::IOMMU0,
+            unit_mask: umasks::IOMMU_MISSES,
+            enable: true,
+            channel_mask: umasks::CH_MASK_ALL,
+            fc_mask: umasks::FC_MASK_ALL,
+            ..Default::default()
+        };
+        assert!(misses.validate().is_ok());
+        assert_eq!(misses.to_msr_value(), 0x7FF0_0040_2040);
+
+        let occupancy = IioCounterControl {
+            event_select: events::IOMMU3,
+            unit_mask: umasks::IOMMU_PWT_OCCUPANCY,
+            ..misses
+        };
+        let decoded = IioCounterControl::from_msr_value(occupancy.to_msr_value());
+        assert_eq!(decoded.event_select, 0x43);
+        assert_eq!(decoded.unit_mask, 0x01);
+    }
+
+    #[test]
+    fn test_iio_verify_mask() {
+        let ctrl = IioCounterControl {
+            reset_counter: true,
+            ..Default::default()
+        };
+        assert_eq!(ctrl.to_msr_value() & IioCounterControl::VERIFY_MASK, 0);
+
+        let all = IioCounterControl {
+            event_select: 0xFF,
+            unit_mask: 0xFF,
+            edge_detect: true,
+            thread_id_enable: true,
+            overflow_enable: true,
+            enable: true,
+            invert: true,
+            threshold: 0xFFF,
+            channel_mask: 0xFF,
+            fc_mask: 0x07,
+            ..Default::default()
+        };
+        assert_eq!(all.to_msr_value(), IioCounterControl::VERIFY_MASK);
+    }
 }
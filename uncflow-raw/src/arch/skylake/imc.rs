@@ -8,14 +8,25 @@
 //! - Intel® Xeon® Processor Scalable Family Uncore Performance Monitoring Reference Manual
 //! - Section: Memory Controller Performance Monitoring
 
+use crate::register::counter_mask;
+
 /// Number of IMC channels in Skylake-SP (varies by SKU, up to 6)
 pub const IMC_CHANNEL_COUNT: usize = 6;
 
 /// Number of programmable counters per IMC channel
 pub const COUNTERS_PER_CHANNEL: usize = 4;
 
-/// Bit width of IMC counters (48 bits)
-pub const COUNTER_WIDTH_BITS: u64 = 48;
+/// Bit width of IMC counters
+pub const COUNTER_WIDTH_BITS: u64 = super::UNCORE_COUNTER_WIDTH_BITS;
+
+/// Mask applied to raw counter reads
+pub const COUNTER_MASK: u64 = counter_mask(COUNTER_WIDTH_BITS);
+
+/// Bit width of IMC counters as read through 32-bit PCI config space accesses
+pub const PCI_COUNTER_WIDTH_BITS: u64 = 32;
+
+/// Mask applied to 32-bit PCI counter reads
+pub const PCI_COUNTER_MASK: u64 = counter_mask(PCI_COUNTER_WIDTH_BITS);
 
 /// Cache line size for bandwidth calculations (64 bytes)
 pub const CACHE_LINE_SIZE: u64 = 64;
@@ -58,11 +69,29 @@ pub mod pci {
     /// IMC Box Control register offset
     pub const IMC_BOX_CTL: u32 = 0x0F4;
 
-    /// IMC DCLK Control register offset
-    pub const IMC_DCLK_CTL: u32 = 0x0A4;
+    /// Offsets of the programmable counters, each 48 bits wide in two dwords
+    ///
+    /// 0x0A4 and the other `+ 4` offsets are the upper halves of these counters.
+    pub const IMC_CTR: [u32; 4] = [0x0A0, 0x0A8, 0x0B0, 0x0B8];
+
+    /// Offsets of the programmable counter controls, one dword each
+    ///
+    /// Control `i` (MC_CHy_PCI_PMON_CTLi) selects the event of counter `i`
+    /// (MC_CHy_PCI_PMON_CTRi) at `IMC_CTR[i]`: controls are 4 bytes apart from
+    /// 0x0D8, counters 8 bytes apart from 0x0A0.
+    pub const IMC_CTL: [u32; 4] = [0x0D8, 0x0DC, 0x0E0, 0x0E4];
+
+    /// IMC DCLK (fixed counter) Control register offset (MC_CHy_PCI_PMON_FIXED_CTL)
+    pub const IMC_DCLK_CTL: u32 = 0x0F0;
+
+    /// IMC DCLK (fixed counter) Counter offset (MC_CHy_PCI_PMON_FIXED_CTR), low dword
+    pub const IMC_DCLK_CTR: u32 = 0x0D0;
 
-    /// IMC DCLK Counter offset
-    pub const IMC_DCLK_CTR: u32 = 0x0A4;
+    /// DCLK control: enable counting
+    pub const DCLK_ENABLE_BIT: u32 = 1 << 22;
+
+    /// DCLK control: reset the counter, self-clearing
+    pub const DCLK_RESET_BIT: u32 = 1 << 19;
 
     /// IMC channel PCI configurations: (device, function, device_id)
     ///
@@ -75,6 +104,26 @@ pub mod pci {
         (0x0C, 6, 0x2046), // Channel 4: device 12, function 6
         (0x0D, 2, 0x204A), // Channel 5: device 13, function 2
     ];
+
+    /// IMC channel thermal function PCI configurations: (device, function, device_id)
+    ///
+    /// Holds the DIMM temperature CSRs, one function above each channel's PMON function.
+    pub const IMC_THERMAL_CHANNELS: [(u32, u32, u32); 6] = [
+        (0x0A, 3, 0x2043), // Channel 0: device 10, function 3
+        (0x0A, 7, 0x2047), // Channel 1: device 10, function 7
+        (0x0B, 3, 0x204B), // Channel 2: device 11, function 3
+        (0x0C, 3, 0x2043), // Channel 3: device 12, function 3
+        (0x0C, 7, 0x2047), // Channel 4: device 12, function 7
+        (0x0D, 3, 0x204B), // Channel 5: device 13, function 3
+    ];
+
+    /// DIMMTEMPSTAT_[0:2] offsets, one per DIMM slot of the channel
+    ///
+    /// Bits 7:0 hold the last temperature read from the DIMM's sensor in degrees C.
+    pub const DIMMTEMPSTAT: [u32; 3] = [0x150, 0x154, 0x158];
+
+    /// DIMMTEMPSTAT temperature field mask
+    pub const DIMM_TEMP_MASK: u32 = 0xFF;
 }
 
 /// IMC performance event codes
@@ -96,4 +145,72 @@ pub mod events {
 
     /// Write Pending Queue occupancy event
     pub const WPQ_OCCUPANCY: u8 = 0x81;
+
+    /// Optane persistent memory (DDR-T) read queue inserts, one per 64-byte
+    /// media read (Cascade Lake)
+    pub const PMM_RPQ_INSERTS: u8 = 0xE3;
+
+    /// Optane persistent memory (DDR-T) write queue inserts, one per 64-byte
+    /// media write (Cascade Lake)
+    pub const PMM_WPQ_INSERTS: u8 = 0xE7;
+
+    /// PMM insert events count every request and take no umask
+    pub const PMM_INSERTS_UMASK: u8 = 0x00;
+
+    /// DRAM activates (ACT.COUNT), one per row opened
+    pub const ACT_COUNT: u8 = 0x01;
+
+    /// Activates for reads, writes and bypass (ACT.COUNT.RD | WR | BYP)
+    pub const ACT_COUNT_ALL_UMASK: u8 = 0x0B;
+
+    /// DRAM precharges (PRE_COUNT)
+    pub const PRE_COUNT: u8 = 0x02;
+
+    /// Precharges closing a row to open a different one (PRE_COUNT.PAGE_MISS),
+    /// one per page conflict
+    pub const PRE_COUNT_PAGE_MISS_UMASK: u8 = 0x01;
+
+    /// DCLK cycles a DIMM rank was throttled for thermal or power reasons
+    /// (POWER_THROTTLE_CYCLES)
+    pub const POWER_THROTTLE_CYCLES: u8 = 0x41;
+
+    /// One umask bit per rank, all ranks of the channel
+    pub const POWER_THROTTLE_CYCLES_ALL_RANKS_UMASK: u8 = 0xFF;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dclk_offsets_are_distinct_from_counters() {
+        // The fixed DCLK counter and its control are their own registers, not
+        // the upper dword of counter 0 at 0x0A4
+        assert_eq!(pci::IMC_DCLK_CTL, 0x0F0);
+        assert_eq!(pci::IMC_DCLK_CTR, 0x0D0);
+        let counter_dwords: Vec<u32> = pci::IMC_CTR.iter().flat_map(|&c| [c, c + 4]).collect();
+        assert!(counter_dwords.contains(&0x0A4));
+        for offset in [pci::IMC_DCLK_CTL, pci::IMC_DCLK_CTR, pci::IMC_DCLK_CTR + 4] {
+            assert!(!counter_dwords.contains(&offset));
+            assert_ne!(offset, pci::IMC_BOX_CTL);
+        }
+        assert_eq!(pci::IMC_CTR[0] as u64, msr::IMC_CTR0);
+    }
+
+    #[test]
+    fn test_control_i_pairs_with_counter_i() {
+        let named = [
+            (msr::IMC_CTL0, msr::IMC_CTR0),
+            (msr::IMC_CTL1, msr::IMC_CTR1),
+            (msr::IMC_CTL2, msr::IMC_CTR2),
+            (msr::IMC_CTL3, msr::IMC_CTR3),
+        ];
+        assert_eq!(pci::IMC_CTL.len(), COUNTERS_PER_CHANNEL);
+        for (i, (control, counter)) in named.into_iter().enumerate() {
+            assert_eq!(pci::IMC_CTL[i], 0x0D8 + 4 * i as u32);
+            assert_eq!(pci::IMC_CTR[i], 0x0A0 + 8 * i as u32);
+            assert_eq!(pci::IMC_CTL[i] as u64, control);
+            assert_eq!(pci::IMC_CTR[i] as u64, counter);
+        }
+    }
 }
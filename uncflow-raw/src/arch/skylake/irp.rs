@@ -8,6 +8,8 @@
 //! - Intel® Xeon® Processor Scalable Family Uncore Performance Monitoring Reference Manual
 //! - Section: I/O Request Processing Performance Monitoring
 
+use crate::register::{counter_mask, RegisterLayout};
+
 /// Number of IRP units in Skylake-SP
 pub const IRP_UNIT_COUNT: usize = 3;
 
@@ -15,10 +17,10 @@ pub const IRP_UNIT_COUNT: usize = 3;
 pub const COUNTERS_PER_IRP: usize = 4;
 
 /// Bit width of IRP counters
-pub const COUNTER_WIDTH_BITS: u64 = 48;
+pub const COUNTER_WIDTH_BITS: u64 = super::UNCORE_COUNTER_WIDTH_BITS;
 
-/// IRP PCI Device ID for Skylake-SP
-pub const IRP_DEVICE_ID: u32 = 0x6F39;
+/// Mask applied to raw counter reads
+pub const COUNTER_MASK: u64 = counter_mask(COUNTER_WIDTH_BITS);
 
 /// MSR addresses for IRP performance counters
 pub mod msr {
@@ -41,17 +43,145 @@ pub mod msr {
     pub const IRP_CTRL1: [u64; 3] = [0x0A7C, 0x0A9C, 0x0ABC];
 }
 
-/// PCI configuration addresses for IRP units
-pub mod pci {
-    /// IRP Unit Status register offset
-    pub const IRP_UNIT_STATUS_ADDR: u32 = 0xF8;
+/// IRP Unit Counter Control Register layout
+///
+/// Also matches the PCI-based Haswell-EP/Broadwell-EP IRP control registers
+/// (see `arch::haswell::irp::pci`); the register is 32 bits wide in both cases.
+///
+/// ## Register Format
+///
+/// | Bits   | Field               | Description                          |
+/// |--------|---------------------|--------------------------------------|
+/// | 0-7    | event_select        | Event code to count                  |
+/// | 8-15   | unit_mask           | Event sub-select (umask)             |
+/// | 16     | reserved            |                                      |
+/// | 17     | reset_counter       | Reset counter on programming         |
+/// | 18     | edge_detect         | Count rising edges vs level          |
+/// | 19     | reserved            |                                      |
+/// | 20     | overflow_enable     | Enable overflow interrupts           |
+/// | 21     | reserved            |                                      |
+/// | 22     | enable              | Enable counter                       |
+/// | 23     | invert              | Invert threshold comparison          |
+/// | 24-31  | threshold           | Threshold for filtering (8 bits)     |
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IrpCounterControl {
+    /// Event select code (bits 0-7)
+    pub event_select: u8,
+
+    /// Unit mask / event sub-select (bits 8-15)
+    pub unit_mask: u8,
+
+    /// Reset counter on programming (bit 17)
+    pub reset_counter: bool,
+
+    /// Edge detection mode (bit 18)
+    pub edge_detect: bool,
+
+    /// Overflow interrupt enable (bit 20)
+    pub overflow_enable: bool,
+
+    /// Enable counter (bit 22)
+    pub enable: bool,
+
+    /// Invert threshold comparison (bit 23)
+    pub invert: bool,
+
+    /// Threshold value for filtering (bits 24-31)
+    pub threshold: u8,
+}
+
+impl IrpCounterControl {
+    /// Counting control for `event`/`umask` with every optional feature off
+    pub const fn counting(event_select: u8, unit_mask: u8) -> Self {
+        Self {
+            event_select,
+            unit_mask,
+            reset_counter: false,
+            edge_detect: false,
+            overflow_enable: false,
+            enable: true,
+            invert: false,
+            threshold: 0,
+        }
+    }
+
+    /// Value for a 32-bit PCI config space write
+    pub fn to_pci_value(&self) -> u32 {
+        self.to_msr_value() as u32
+    }
+}
+
+impl RegisterLayout for IrpCounterControl {
+    fn to_msr_value(&self) -> u64 {
+        (self.event_select as u64)
+            | ((self.unit_mask as u64) << 8)
+            | (if self.reset_counter { 1 << 17 } else { 0 })
+            | (if self.edge_detect { 1 << 18 } else { 0 })
+            | (if self.overflow_enable { 1 << 20 } else { 0 })
+            | (if self.enable { 1 << 22 } else { 0 })
+            | (if self.invert { 1 << 23 } else { 0 })
+            | ((self.threshold as u64) << 24)
+    }
+
+    fn from_msr_value(value: u64) -> Self {
+        Self {
+            event_select: (value & 0xFF) as u8,
+            unit_mask: ((value >> 8) & 0xFF) as u8,
+            reset_counter: (value & (1 << 17)) != 0,
+            edge_detect: (value & (1 << 18)) != 0,
+            overflow_enable: (value & (1 << 20)) != 0,
+            enable: (value & (1 << 22)) != 0,
+            invert: (value & (1 << 23)) != 0,
+            threshold: ((value >> 24) & 0xFF) as u8,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_irp_counter_control_round_trip() {
+        let ctrl = IrpCounterControl {
+            event_select: 0x10,
+            unit_mask: 0x08,
+            reset_counter: true,
+            edge_detect: true,
+            overflow_enable: true,
+            enable: true,
+            invert: true,
+            threshold: 0xA5,
+        };
+
+        let value = ctrl.to_msr_value();
+        assert_eq!(IrpCounterControl::from_msr_value(value), ctrl);
+        assert_eq!(ctrl.to_pci_value() as u64, value);
+    }
 
-    /// IRP Unit Control register offset
-    pub const IRP_UNIT_CTL_ADDR: u32 = 0xF4;
+    #[test]
+    fn test_irp_msr_addresses() {
+        use crate::current_arch::irp::msr;
 
-    /// IRP Counter register offsets (4 counters)
-    pub const IRP_CTR_ADDR: [u32; 4] = [0xA0, 0xB0, 0xB8, 0xC0];
+        assert_eq!(msr::IRP_UNIT_CTRL, [0x0A78, 0x0A98, 0x0AB8]);
+        assert_eq!(msr::IRP_UNIT_STATUS[0], 0x0A7F);
+        assert_eq!(msr::IRP_CTR0[1], 0x0A99);
+        assert_eq!(msr::IRP_CTR1[2], 0x0ABA);
+        assert_eq!(msr::IRP_CTRL0[0], 0x0A7B);
+        assert_eq!(msr::IRP_CTRL1[0], 0x0A7C);
+        assert_eq!(msr::IRP_UNIT_CTRL.len(), IRP_UNIT_COUNT);
+        assert_eq!(crate::current_arch::irp::COUNTER_WIDTH_BITS, 48);
+    }
 
-    /// IRP Control register offsets (4 control registers)
-    pub const IRP_CTL_ADDR: [u32; 4] = [0xD8, 0xDC, 0xE0, 0xE4];
+    #[test]
+    fn test_irp_counting_encoding() {
+        // Matches the (umask << 8) | event | enable layout the agent used to build
+        let ctrl = IrpCounterControl::counting(0x0F, 0x01);
+        assert_eq!(ctrl.to_msr_value(), 0x0040_010F);
+        assert_eq!(ctrl.to_pci_value(), 0x0040_010F);
+        assert_eq!(
+            IrpCounterControl::from_msr_value(0x0040_FF10),
+            IrpCounterControl::counting(0x10, 0xFF)
+        );
+    }
 }
@@ -0,0 +1,200 @@
+//! M2M (Mesh-to-Memory) register definitions for Skylake-SP
+//!
+//! The M2M units sit between the mesh and the memory controllers (one per IMC)
+//! and own the memory directory. Their counters expose directory and
+//! near-memory tag lookups that the IMC counters cannot see. Like the UPI
+//! link layer, the M2M PMON registers live in PCI configuration space.
+//!
+//! ## References
+//!
+//! - Intel® Xeon® Processor Scalable Family Uncore Performance Monitoring Reference Manual
+//! - Section: M2M Performance Monitoring
+
+use crate::register::{counter_mask, RegisterLayout};
+
+/// Number of M2M units per socket in Skylake-SP (one per memory controller)
+pub const M2M_UNIT_COUNT: usize = 2;
+
+/// Number of programmable counters per M2M unit
+pub const COUNTERS_PER_M2M: usize = 4;
+
+/// Bit width of M2M counters
+pub const COUNTER_WIDTH_BITS: u64 = super::UNCORE_COUNTER_WIDTH_BITS;
+
+/// Mask applied to raw counter reads
+pub const COUNTER_MASK: u64 = counter_mask(COUNTER_WIDTH_BITS);
+
+/// M2M PCI Device ID for Skylake-SP
+pub const M2M_DEVICE_ID: u32 = 0x2066;
+
+/// PCI configuration addresses for M2M units
+pub mod pci {
+    /// PCI device number for each M2M unit
+    pub const M2M_DEVICE: [u32; 2] = [8, 9];
+
+    /// PCI function number for M2M PMON
+    pub const M2M_FUNCTION: u32 = 0;
+
+    /// M2M Unit (box) Control register offset
+    pub const M2M_UNIT_CTL_ADDR: u32 = 0x258;
+
+    /// M2M Counter register offsets (4 counters, 64-bit)
+    pub const M2M_CTR_ADDR: [u32; 4] = [0x200, 0x208, 0x210, 0x218];
+
+    /// M2M Control register offsets (4 control registers)
+    pub const M2M_CTL_ADDR: [u32; 4] = [0x228, 0x230, 0x238, 0x240];
+}
+
+/// M2M Counter Control Register layout
+///
+/// ## Register Format
+///
+/// | Bits   | Field               | Description                          |
+/// |--------|---------------------|--------------------------------------|
+/// | 0-7    | event_select        | Event code to count                  |
+/// | 8-15   | unit_mask           | Event sub-select (umask)             |
+/// | 16     | reserved            | Must be 0                            |
+/// | 17     | reset_counter       | Reset counter on programming         |
+/// | 18     | edge_detect         | Count rising edges vs level          |
+/// | 19-21  | reserved            | Must be 0                            |
+/// | 22     | enable              | Enable counter                       |
+/// | 23     | invert              | Invert threshold comparison          |
+/// | 24-31  | threshold           | Threshold for filtering (8 bits)     |
+#[derive(Debug, Clone, Copy, Default)]
+pub struct M2mCounterControl {
+    /// Event select code (bits 0-7)
+    pub event_select: u8,
+
+    /// Unit mask / event sub-select (bits 8-15)
+    pub unit_mask: u8,
+
+    /// Reset counter on programming (bit 17)
+    pub reset_counter: bool,
+
+    /// Edge detection mode (bit 18)
+    pub edge_detect: bool,
+
+    /// Enable counter (bit 22)
+    pub enable: bool,
+
+    /// Invert threshold comparison (bit 23)
+    pub invert: bool,
+
+    /// Threshold value (bits 24-31)
+    pub threshold: u8,
+}
+
+impl RegisterLayout for M2mCounterControl {
+    fn to_msr_value(&self) -> u64 {
+        (self.event_select as u64)
+            | ((self.unit_mask as u64) << 8)
+            | (if self.reset_counter { 1 << 17 } else { 0 })
+            | (if self.edge_detect { 1 << 18 } else { 0 })
+            | (if self.enable { 1 << 22 } else { 0 })
+            | (if self.invert { 1 << 23 } else { 0 })
+            | ((self.threshold as u64) << 24)
+    }
+
+    fn from_msr_value(value: u64) -> Self {
+        Self {
+            event_select: (value & 0xFF) as u8,
+            unit_mask: ((value >> 8) & 0xFF) as u8,
+            reset_counter: (value & (1 << 17)) != 0,
+            edge_detect: (value & (1 << 18)) != 0,
+            enable: (value & (1 << 22)) != 0,
+            invert: (value & (1 << 23)) != 0,
+            threshold: ((value >> 24) & 0xFF) as u8,
+        }
+    }
+
+    fn validate(&self) -> Result<(), &'static str> {
+        // Every M2M event but the clockticks counts the sub-events its umask selects
+        if self.event_select != events::CLOCKTICKS && self.unit_mask == 0 {
+            return Err("Unit mask must select at least one sub-event");
+        }
+        Ok(())
+    }
+}
+
+/// M2M event codes
+pub mod events {
+    /// M2M clockticks
+    pub const CLOCKTICKS: u8 = 0x00;
+
+    /// Directory lookup found the line's directory state (DIRECTORY_HIT)
+    pub const DIRECTORY_HIT: u8 = 0x2A;
+
+    /// Directory lookup missed (DIRECTORY_MISS)
+    pub const DIRECTORY_MISS: u8 = 0x2B;
+
+    /// Near-memory cache tag hit (TAG_HIT)
+    pub const TAG_HIT: u8 = 0x2C;
+
+    /// Directory state seen on lookup (DIRECTORY_LOOKUP)
+    pub const DIRECTORY_LOOKUP: u8 = 0x2D;
+}
+
+/// M2M unit masks (event sub-selectors)
+pub mod umasks {
+    /// Any directory hit/miss (all clean/dirty I/S/P/A sub-events)
+    pub const DIRECTORY_ANY: u8 = 0xFF;
+
+    /// Any near-memory tag hit (read/underfill, clean/dirty)
+    pub const TAG_HIT_ANY: u8 = 0x0F;
+
+    /// Directory lookup found state A (line may be cached on a remote socket)
+    pub const LOOKUP_STATE_A: u8 = 0x08;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_m2m_counter_control_round_trip() {
+        let ctrl = M2mCounterControl {
+            event_select: events::DIRECTORY_HIT,
+            unit_mask: umasks::DIRECTORY_ANY,
+            reset_counter: true,
+            enable: true,
+            threshold: 7,
+            ..Default::default()
+        };
+
+        let value = ctrl.to_msr_value();
+        let decoded = M2mCounterControl::from_msr_value(value);
+
+        assert_eq!(decoded.event_select, ctrl.event_select);
+        assert_eq!(decoded.unit_mask, ctrl.unit_mask);
+        assert_eq!(decoded.reset_counter, ctrl.reset_counter);
+        assert_eq!(decoded.enable, ctrl.enable);
+        assert_eq!(decoded.threshold, ctrl.threshold);
+    }
+
+    #[test]
+    fn test_m2m_control_encoding() {
+        let ctrl = M2mCounterControl {
+            event_select: events::DIRECTORY_MISS,
+            unit_mask: umasks::DIRECTORY_ANY,
+            enable: true,
+            ..Default::default()
+        };
+        assert_eq!(ctrl.to_msr_value(), 0x40_FF2B);
+    }
+
+    #[test]
+    fn test_m2m_validate() {
+        let mut ctrl = M2mCounterControl {
+            event_select: events::TAG_HIT,
+            unit_mask: umasks::TAG_HIT_ANY,
+            ..Default::default()
+        };
+        assert!(ctrl.validate().is_ok());
+        ctrl.unit_mask = 0;
+        assert!(ctrl.validate().is_err());
+
+        // Clockticks take no umask
+        ctrl.event_select = events::CLOCKTICKS;
+        assert!(ctrl.validate().is_ok());
+    }
+}
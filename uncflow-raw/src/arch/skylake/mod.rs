@@ -6,11 +6,19 @@
 //! ## Uncore Units
 //!
 //! - **CHA** (Caching/Home Agent) - LLC cache and snoop filter
+//! - **C-state** - Package and core C-state residency
 //! - **IIO** (Integrated I/O) - PCIe root complex
 //! - **IMC** (Integrated Memory Controller) - DDR4 memory controller
 //! - **IRP** (I/O Request Processing) - I/O arbitration
+//! - **M2M** (Mesh-to-Memory) - Memory directory and mesh interface
+//! - **PCU** (Power Control Unit) - Frequency transitions, throttling and C-state occupancy
+//! - **PMON** - Box control shared by the uncore units
 //! - **RAPL** (Running Average Power Limit) - Power monitoring
 //! - **RDT** (Resource Director Technology) - Cache/memory monitoring
+//! - **SST** (Speed Select Technology) - Active TDP level and core priority
+//! - **Thermal** - Core and package temperature and throttling
+//! - **UPI** (Ultra Path Interconnect) - Inter-socket links
+//! - **UBox** - Global uncore PMON freeze control
 //! - **Core** - Core performance monitoring units
 //!
 //! ## References
@@ -20,8 +28,68 @@
 
 pub mod cha;
 pub mod core;
+pub mod cstate;
 pub mod iio;
 pub mod imc;
 pub mod irp;
+pub mod m2m;
+pub mod pcu;
+pub mod pmon;
 pub mod rapl;
 pub mod rdt;
+pub mod sst;
+pub mod thermal;
+pub mod ubox;
+pub mod upi;
+
+/// Bit width of the uncore PMON counters (CHA, IIO, IMC, IRP, M2M, PCU, UPI)
+pub const UNCORE_COUNTER_WIDTH_BITS: u64 = 48;
+
+/// Bit width of the core PMU counters
+pub const CORE_COUNTER_WIDTH_BITS: u64 = 48;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expected_mask(width: u64) -> u64 {
+        (1u64 << width) - 1
+    }
+
+    #[test]
+    fn test_counter_masks_match_widths() {
+        assert_eq!(cha::COUNTER_MASK, expected_mask(cha::COUNTER_WIDTH_BITS));
+        assert_eq!(core::COUNTER_MASK, expected_mask(core::COUNTER_WIDTH_BITS));
+        assert_eq!(iio::COUNTER_MASK, expected_mask(iio::COUNTER_WIDTH_BITS));
+        assert_eq!(
+            iio::IIO_COUNTER_MASK,
+            expected_mask(iio::IIO_COUNTER_WIDTH_BITS)
+        );
+        assert_eq!(imc::COUNTER_MASK, expected_mask(imc::COUNTER_WIDTH_BITS));
+        assert_eq!(
+            imc::PCI_COUNTER_MASK,
+            expected_mask(imc::PCI_COUNTER_WIDTH_BITS)
+        );
+        assert_eq!(irp::COUNTER_MASK, expected_mask(irp::COUNTER_WIDTH_BITS));
+        assert_eq!(m2m::COUNTER_MASK, expected_mask(m2m::COUNTER_WIDTH_BITS));
+        assert_eq!(pcu::COUNTER_MASK, expected_mask(pcu::COUNTER_WIDTH_BITS));
+        assert_eq!(upi::COUNTER_MASK, expected_mask(upi::COUNTER_WIDTH_BITS));
+    }
+
+    #[test]
+    fn test_uncore_units_share_width() {
+        for width in [
+            cha::COUNTER_WIDTH_BITS,
+            iio::COUNTER_WIDTH_BITS,
+            imc::COUNTER_WIDTH_BITS,
+            irp::COUNTER_WIDTH_BITS,
+            m2m::COUNTER_WIDTH_BITS,
+            pcu::COUNTER_WIDTH_BITS,
+            upi::COUNTER_WIDTH_BITS,
+        ] {
+            assert_eq!(width, UNCORE_COUNTER_WIDTH_BITS);
+        }
+        assert_eq!(iio::IIO_COUNTER_WIDTH_BITS, 36);
+        assert_eq!(imc::PCI_COUNTER_WIDTH_BITS, 32);
+    }
+}
@@ -0,0 +1,268 @@
+//! PCU (Power Control Unit) register definitions for Skylake-SP
+//!
+//! The PCU runs the package power management firmware. Its counters see frequency
+//! transitions, the cycles the cores are held below their requested frequency by
+//! thermal, power or PROCHOT# limits, and how many cores sit in each C-state. There
+//! is one PCU per socket, programmed through MSRs like the CHA and IIO boxes.
+//!
+//! ## References
+//!
+//! - Intel® Xeon® Processor Scalable Family Uncore Performance Monitoring Reference Manual
+//! - Section: Power Control (PCU) Performance Monitoring
+
+use crate::register::{counter_mask, RegisterLayout};
+
+/// Number of programmable counters of the PCU
+pub const COUNTERS_PER_PCU: usize = 4;
+
+/// Bit width of PCU counters
+pub const COUNTER_WIDTH_BITS: u64 = super::UNCORE_COUNTER_WIDTH_BITS;
+
+/// Mask applied to raw counter reads
+pub const COUNTER_MASK: u64 = counter_mask(COUNTER_WIDTH_BITS);
+
+/// MSR addresses of the PCU PMON registers
+pub mod msr {
+    /// PCU Unit Box Control
+    pub const PCU_UNIT_BOX_CTL: u64 = 0x0710;
+
+    /// PCU Counter Control registers 0-3
+    pub const PCU_UNIT_CTL: [u64; 4] = [0x0711, 0x0712, 0x0713, 0x0714];
+
+    /// PCU Box Filter, the frequency bands of the FREQ_BAND events
+    pub const PCU_UNIT_BOX_FILTER: u64 = 0x0715;
+
+    /// PCU Box Status (overflow bits)
+    pub const PCU_UNIT_BOX_STATUS: u64 = 0x0716;
+
+    /// PCU Counter registers 0-3
+    pub const PCU_UNIT_CTR: [u64; 4] = [0x0717, 0x0718, 0x0719, 0x071A];
+}
+
+/// PCU event codes
+pub mod events {
+    /// PCU clock cycles
+    pub const CLOCKTICKS: u8 = 0x00;
+
+    /// Cycles the thermal limit is the strongest upper limit on frequency
+    pub const FREQ_MAX_LIMIT_THERMAL_CYCLES: u8 = 0x04;
+
+    /// Cycles the power limit is the strongest upper limit on frequency
+    pub const FREQ_MAX_POWER_CYCLES: u8 = 0x05;
+
+    /// Cycles an internal PROCHOT# throttles the package
+    pub const PROCHOT_INTERNAL_CYCLES: u8 = 0x09;
+
+    /// Cycles an external PROCHOT# throttles the package
+    pub const PROCHOT_EXTERNAL_CYCLES: u8 = 0x0A;
+
+    /// Cycles a voltage regulator signals VR_HOT
+    pub const VR_HOT_CYCLES: u8 = 0x42;
+
+    /// Cycles spent changing frequency
+    pub const FREQ_TRANS_CYCLES: u8 = 0x74;
+
+    /// Number of cores in the C-state chosen by `occ_sel`, added up every cycle
+    pub const POWER_STATE_OCCUPANCY: u8 = 0x80;
+}
+
+/// `occ_sel` values of POWER_STATE_OCCUPANCY
+pub mod occupancy {
+    /// Cores in C0
+    pub const CORES_C0: u8 = 1;
+
+    /// Cores in C3
+    pub const CORES_C3: u8 = 2;
+
+    /// Cores in C6 or C7
+    pub const CORES_C6: u8 = 3;
+}
+
+/// PCU Counter Control Register layout
+///
+/// The PCU has no unit mask; bits 14-15 select the C-state counted by the
+/// occupancy events instead.
+///
+/// ## Register Format
+///
+/// | Bits   | Field               | Description                          |
+/// |--------|---------------------|--------------------------------------|
+/// | 0-7    | event_select        | Event code to count                  |
+/// | 8-13   | reserved            | Must be 0                            |
+/// | 14-15  | occ_sel             | C-state of the occupancy events      |
+/// | 16     | reserved            | Must be 0                            |
+/// | 17     | reset_counter       | Reset counter on programming         |
+/// | 18     | edge_detect         | Count rising edges vs level          |
+/// | 19     | reserved            | Must be 0                            |
+/// | 20     | overflow_enable     | Enable overflow interrupts           |
+/// | 21     | reserved            | Must be 0                            |
+/// | 22     | enable              | Enable counter                       |
+/// | 23     | invert              | Invert threshold comparison          |
+/// | 24-28  | threshold           | Threshold for filtering (5 bits)     |
+/// | 29     | reserved            | Must be 0                            |
+/// | 30     | occ_invert          | Invert the occupancy threshold       |
+/// | 31     | occ_edge_detect     | Count occupancy rising edges         |
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PcuCounterControl {
+    /// Event select code (bits 0-7)
+    pub event_select: u8,
+
+    /// Occupancy C-state select (bits 14-15)
+    pub occ_sel: u8,
+
+    /// Reset counter on programming (bit 17)
+    pub reset_counter: bool,
+
+    /// Edge detection mode (bit 18)
+    pub edge_detect: bool,
+
+    /// Overflow interrupt enable (bit 20)
+    pub overflow_enable: bool,
+
+    /// Enable counter (bit 22)
+    pub enable: bool,
+
+    /// Invert threshold comparison (bit 23)
+    pub invert: bool,
+
+    /// Threshold value for filtering (bits 24-28)
+    pub threshold: u8,
+
+    /// Invert the occupancy threshold (bit 30)
+    pub occ_invert: bool,
+
+    /// Occupancy edge detection (bit 31)
+    pub occ_edge_detect: bool,
+}
+
+impl PcuCounterControl {
+    /// Bits that read back as written; the reset bit self-clears
+    pub const VERIFY_MASK: u64 = 0xDFD4_C0FF;
+
+    /// Counting control for `event` with the occupancy select `occ_sel`
+    pub const fn counting(event_select: u8, occ_sel: u8) -> Self {
+        Self {
+            event_select,
+            occ_sel,
+            reset_counter: true,
+            edge_detect: false,
+            overflow_enable: false,
+            enable: true,
+            invert: false,
+            threshold: 0,
+            occ_invert: false,
+            occ_edge_detect: false,
+        }
+    }
+}
+
+impl RegisterLayout for PcuCounterControl {
+    fn to_msr_value(&self) -> u64 {
+        (self.event_select as u64)
+            | (((self.occ_sel & 0x3) as u64) << 14)
+            | (if self.reset_counter { 1 << 17 } else { 0 })
+            | (if self.edge_detect { 1 << 18 } else { 0 })
+            | (if self.overflow_enable { 1 << 20 } else { 0 })
+            | (if self.enable { 1 << 22 } else { 0 })
+            | (if self.invert { 1 << 23 } else { 0 })
+            | (((self.threshold & 0x1F) as u64) << 24)
+            | (if self.occ_invert { 1 << 30 } else { 0 })
+            | (if self.occ_edge_detect { 1 << 31 } else { 0 })
+    }
+
+    fn from_msr_value(value: u64) -> Self {
+        Self {
+            event_select: (value & 0xFF) as u8,
+            occ_sel: ((value >> 14) & 0x3) as u8,
+            reset_counter: (value & (1 << 17)) != 0,
+            edge_detect: (value & (1 << 18)) != 0,
+            overflow_enable: (value & (1 << 20)) != 0,
+            enable: (value & (1 << 22)) != 0,
+            invert: (value & (1 << 23)) != 0,
+            threshold: ((value >> 24) & 0x1F) as u8,
+            occ_invert: (value & (1 << 30)) != 0,
+            occ_edge_detect: (value & (1 << 31)) != 0,
+        }
+    }
+
+    fn validate(&self) -> Result<(), &'static str> {
+        if self.occ_sel > 0x3 {
+            return Err("Occupancy select must be <= 3 (2 bits)");
+        }
+        if self.threshold > 0x1F {
+            return Err("Threshold must be <= 31 (5 bits)");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pcu_counter_control_round_trip() {
+        let ctrl = PcuCounterControl {
+            event_select: events::POWER_STATE_OCCUPANCY,
+            occ_sel: occupancy::CORES_C6,
+            reset_counter: true,
+            edge_detect: true,
+            overflow_enable: true,
+            enable: true,
+            invert: true,
+            threshold: 0x15,
+            occ_invert: true,
+            occ_edge_detect: true,
+        };
+
+        let value = ctrl.to_msr_value();
+        assert_eq!(PcuCounterControl::from_msr_value(value), ctrl);
+        assert!(ctrl.validate().is_ok());
+
+        // Every field but the self-clearing reset bit reads back
+        let readback = PcuCounterControl {
+            event_select: 0xFF,
+            reset_counter: false,
+            threshold: 0x1F,
+            ..ctrl
+        };
+        assert_eq!(readback.to_msr_value(), PcuCounterControl::VERIFY_MASK);
+    }
+
+    #[test]
+    fn test_pcu_counting_encoding() {
+        // C0 occupancy is event 0x80 with occ_sel 1, i.e. "umask" 0x40
+        let ctrl = PcuCounterControl::counting(events::POWER_STATE_OCCUPANCY, occupancy::CORES_C0);
+        assert_eq!(ctrl.to_msr_value(), 0x0042_4080);
+        assert_eq!(
+            PcuCounterControl::counting(events::FREQ_TRANS_CYCLES, 0).to_msr_value(),
+            0x0042_0074
+        );
+    }
+
+    #[test]
+    fn test_pcu_validate() {
+        let ctrl = PcuCounterControl {
+            threshold: 0x20,
+            ..Default::default()
+        };
+        assert!(ctrl.validate().is_err());
+        let ctrl = PcuCounterControl {
+            occ_sel: 4,
+            ..Default::default()
+        };
+        assert!(ctrl.validate().is_err());
+    }
+
+    #[test]
+    fn test_pcu_msr_addresses() {
+        use crate::current_arch::pcu::msr;
+
+        assert_eq!(msr::PCU_UNIT_BOX_CTL, 0x0710);
+        assert_eq!(msr::PCU_UNIT_CTL[0], 0x0711);
+        assert_eq!(msr::PCU_UNIT_BOX_FILTER, 0x0715);
+        assert_eq!(msr::PCU_UNIT_BOX_STATUS, 0x0716);
+        assert_eq!(msr::PCU_UNIT_CTR, [0x0717, 0x0718, 0x0719, 0x071A]);
+        assert_eq!(msr::PCU_UNIT_CTL.len(), COUNTERS_PER_PCU);
+    }
+}
@@ -0,0 +1,97 @@
+//! Uncore PMON box control for Skylake-SP
+//!
+//! Every uncore box has a box control register that freezes its counters and
+//! resets its controls and counters. The IIO, IRP, M2M, PCU and UPI boxes share
+//! the layout below, whether the register is an MSR or lives in PCI config space;
+//! the CHA adds a freeze enable bit (see `cha::ChaBoxControl`).
+//!
+//! ## References
+//!
+//! - Intel® Xeon® Processor Scalable Family Uncore Performance Monitoring Reference Manual
+//! - Section: Uncore Performance Monitoring - Unit Level Control
+
+use crate::register::RegisterLayout;
+
+/// Uncore Box Control Register layout
+///
+/// ## Register Format
+///
+/// | Bits   | Field               | Description                          |
+/// |--------|---------------------|--------------------------------------|
+/// | 0      | reset_control       | Reset all counter control registers  |
+/// | 1      | reset_counters      | Reset all counters to 0              |
+/// | 2-7    | reserved            |                                      |
+/// | 8      | freeze              | Freeze all counters in the box       |
+/// | 9-31   | reserved            |                                      |
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BoxControl {
+    /// Reset all control registers (bit 0)
+    pub reset_control: bool,
+    /// Reset all counters to 0 (bit 1)
+    pub reset_counters: bool,
+    /// Freeze all counters in the box (bit 8)
+    pub freeze: bool,
+}
+
+impl BoxControl {
+    /// Stop all counters in the box while it is reprogrammed
+    pub const fn frozen() -> Self {
+        Self {
+            reset_control: false,
+            reset_counters: false,
+            freeze: true,
+        }
+    }
+
+    /// Keep the box frozen and clear its counters
+    pub const fn frozen_reset() -> Self {
+        Self {
+            reset_counters: true,
+            ..Self::frozen()
+        }
+    }
+
+    /// Let the counters run again after [`BoxControl::frozen`]
+    pub const fn unfrozen() -> Self {
+        Self {
+            reset_control: false,
+            reset_counters: false,
+            freeze: false,
+        }
+    }
+}
+
+impl RegisterLayout for BoxControl {
+    fn to_msr_value(&self) -> u64 {
+        (if self.reset_control { 1 << 0 } else { 0 })
+            | (if self.reset_counters { 1 << 1 } else { 0 })
+            | (if self.freeze { 1 << 8 } else { 0 })
+    }
+
+    fn from_msr_value(value: u64) -> Self {
+        Self {
+            reset_control: (value & (1 << 0)) != 0,
+            reset_counters: (value & (1 << 1)) != 0,
+            freeze: (value & (1 << 8)) != 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_box_control_encoding() {
+        assert_eq!(BoxControl::frozen().to_msr_value(), 0x100);
+        assert_eq!(BoxControl::frozen_reset().to_msr_value(), 0x102);
+        assert_eq!(BoxControl::unfrozen().to_msr_value(), 0);
+        assert_eq!(
+            BoxControl::from_msr_value(0x103),
+            BoxControl {
+                reset_control: true,
+                ..BoxControl::frozen_reset()
+            }
+        );
+    }
+}
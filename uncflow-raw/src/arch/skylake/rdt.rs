@@ -104,6 +104,61 @@ impl RegisterLayout for PqrAssoc {
     }
 }
 
+/// QM Counter Register layout
+///
+/// Holds the value of the event and RMID selected in IA32_QM_EVTSEL. LLC occupancy
+/// is a current value; the memory bandwidth events are free-running byte counts
+/// that wrap at the MBM counter width (CPUID.(EAX=0FH,ECX=1):EAX[7:0] + 24 bits).
+///
+/// ## Register Format
+///
+/// | Bits   | Field       | Description                                   |
+/// |--------|-------------|-----------------------------------------------|
+/// | 0-61   | data        | Value in units of the MBM scaling factor      |
+/// | 62     | unavailable | No data for this RMID and event yet           |
+/// | 63     | error       | Unsupported RMID or event selected            |
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QmCounter {
+    /// Counter value (bits 0-61)
+    pub data: u64,
+
+    /// Unavailable (bit 62)
+    pub unavailable: bool,
+
+    /// Error (bit 63)
+    pub error: bool,
+}
+
+impl QmCounter {
+    /// Whether `data` holds a value
+    pub fn is_valid(&self) -> bool {
+        !self.unavailable && !self.error
+    }
+}
+
+impl RegisterLayout for QmCounter {
+    fn to_msr_value(&self) -> u64 {
+        (self.data & ((1 << 62) - 1))
+            | (if self.unavailable { 1 << 62 } else { 0 })
+            | (if self.error { 1 << 63 } else { 0 })
+    }
+
+    fn from_msr_value(value: u64) -> Self {
+        Self {
+            data: value & ((1 << 62) - 1),
+            unavailable: (value & (1 << 62)) != 0,
+            error: (value & (1 << 63)) != 0,
+        }
+    }
+
+    fn validate(&self) -> Result<(), &'static str> {
+        if self.data >= 1 << 62 {
+            return Err("data must fit in 62 bits");
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -132,4 +187,17 @@ mod tests {
         assert_eq!(decoded.rmid, pqr.rmid);
         assert_eq!(decoded.cos, pqr.cos);
     }
+
+    #[test]
+    fn test_qm_counter_status_bits() {
+        let counter = QmCounter::from_msr_value(0x1234);
+        assert!(counter.is_valid());
+        assert_eq!(counter.data, 0x1234);
+
+        let unavailable = QmCounter::from_msr_value((1 << 62) | 0x1234);
+        assert!(!unavailable.is_valid());
+        assert_eq!(unavailable.data, 0x1234);
+        assert!(QmCounter::from_msr_value(1 << 63).error);
+        assert_eq!(unavailable.to_msr_value(), (1 << 62) | 0x1234);
+    }
 }
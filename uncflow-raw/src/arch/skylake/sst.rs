@@ -0,0 +1,140 @@
+//! SST (Intel Speed Select Technology) register definitions for Skylake-SP
+//!
+//! SST-PP (performance profile) selects one of several configurable TDP levels,
+//! each with its own base frequency and core count. SST-CP (core power) assigns
+//! every core a class of service whose priority decides which cores keep their
+//! frequency when the package runs out of power budget.
+//!
+//! The active level and a core's class are readable through MSRs; configuring
+//! them goes through the OS mailbox and is out of scope here.
+//!
+//! ## References
+//!
+//! - Intel® 64 and IA-32 Architectures Software Developer's Manual, Volume 4
+//! - Intel® Speed Select Technology (linux/tools/power/x86/intel-speed-select)
+
+use crate::register::RegisterLayout;
+
+/// MSR addresses read for SST
+pub mod msr {
+    /// Platform information, including the number of configurable TDP levels
+    pub const MSR_PLATFORM_INFO: u64 = 0xCE;
+
+    /// Configurable TDP control, the active SST-PP level
+    pub const MSR_CONFIG_TDP_CONTROL: u64 = 0x64B;
+
+    /// PQR association, the core's class of service (SST-CP priority class)
+    pub const IA32_PQR_ASSOC: u64 = super::super::rdt::msr::IA32_PQR_ASSOC;
+}
+
+/// Platform Information Register layout (SST-relevant fields)
+///
+/// ## Register Format
+///
+/// | Bits   | Field               | Description                               |
+/// |--------|---------------------|-------------------------------------------|
+/// | 8-15   | max_non_turbo_ratio | Base frequency in 100 MHz units           |
+/// | 33-34  | config_tdp_levels   | Configurable TDP levels besides nominal   |
+///
+/// Other bits are not decoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PlatformInfo {
+    /// Maximum non-turbo ratio (bits 8-15)
+    pub max_non_turbo_ratio: u8,
+    /// Number of configurable TDP levels (bits 33-34), 0 without SST-PP
+    pub config_tdp_levels: u8,
+}
+
+impl PlatformInfo {
+    /// Whether the part has SST-PP levels to select from
+    pub fn has_sst_pp(&self) -> bool {
+        self.config_tdp_levels > 0
+    }
+}
+
+impl RegisterLayout for PlatformInfo {
+    fn to_msr_value(&self) -> u64 {
+        ((self.max_non_turbo_ratio as u64) << 8) | (((self.config_tdp_levels & 0x3) as u64) << 33)
+    }
+
+    fn from_msr_value(value: u64) -> Self {
+        Self {
+            max_non_turbo_ratio: ((value >> 8) & 0xFF) as u8,
+            config_tdp_levels: ((value >> 33) & 0x3) as u8,
+        }
+    }
+
+    fn validate(&self) -> Result<(), &'static str> {
+        if self.config_tdp_levels > 3 {
+            return Err("config_tdp_levels must be 0-3 (2 bits)");
+        }
+        Ok(())
+    }
+}
+
+/// Configurable TDP Control Register layout
+///
+/// ## Register Format
+///
+/// | Bits   | Field    | Description                                  |
+/// |--------|----------|----------------------------------------------|
+/// | 0-1    | level    | Active TDP level, 0 is the nominal profile   |
+/// | 2-30   | reserved |                                              |
+/// | 31     | lock     | Level locked until the next reset            |
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConfigTdpControl {
+    /// Active SST-PP level (bits 0-1)
+    pub level: u8,
+    /// Lock (bit 31)
+    pub lock: bool,
+}
+
+impl RegisterLayout for ConfigTdpControl {
+    fn to_msr_value(&self) -> u64 {
+        ((self.level & 0x3) as u64) | (if self.lock { 1 << 31 } else { 0 })
+    }
+
+    fn from_msr_value(value: u64) -> Self {
+        Self {
+            level: (value & 0x3) as u8,
+            lock: (value & (1 << 31)) != 0,
+        }
+    }
+
+    fn validate(&self) -> Result<(), &'static str> {
+        if self.level > 3 {
+            return Err("level must be 0-3 (2 bits)");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::skylake::rdt::PqrAssoc;
+
+    #[test]
+    fn test_sst_status_decode() {
+        // Base ratio 23 (2.3 GHz) with two configurable levels, level 2 locked
+        let platform = PlatformInfo::from_msr_value(0x0000_0004_2000_1700);
+        assert_eq!(platform.max_non_turbo_ratio, 0x17);
+        assert_eq!(platform.config_tdp_levels, 2);
+        assert!(platform.has_sst_pp());
+        assert!(!PlatformInfo::from_msr_value(0x1700).has_sst_pp());
+
+        let control = ConfigTdpControl::from_msr_value(0x8000_0002);
+        assert_eq!(
+            control,
+            ConfigTdpControl {
+                level: 2,
+                lock: true
+            }
+        );
+        assert_eq!(control.to_msr_value(), 0x8000_0002);
+
+        // Priority class 1 in the upper half, RMID 7 in the lower
+        let assoc = PqrAssoc::from_msr_value(0x0000_0001_0000_0007);
+        assert_eq!((assoc.cos, assoc.rmid), (1, 7));
+    }
+}
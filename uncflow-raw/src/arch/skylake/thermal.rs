@@ -0,0 +1,193 @@
+//! Thermal status register definitions for Skylake-SP
+//!
+//! The digital thermal sensor reports temperature as degrees below TjMax, the
+//! temperature target in MSR_TEMPERATURE_TARGET, so the current temperature is
+//! TjMax minus the readout. Thermal control (TCC) starts throttling at TjMax minus
+//! the TCC activation offset.
+//!
+//! IA32_THERM_STATUS is per core and IA32_PACKAGE_THERM_STATUS per package; both
+//! share the layout below. The status bits show the current condition, the log bits
+//! stick until software clears them.
+//!
+//! ## References
+//!
+//! - Intel® 64 and IA-32 Architectures Software Developer's Manual, Volume 3B, 14.7
+//! - Intel® 64 and IA-32 Architectures Software Developer's Manual, Volume 4
+
+use crate::register::RegisterLayout;
+
+/// MSR addresses of the thermal sensors and the temperature target
+pub mod msr {
+    /// Core thermal status
+    pub const IA32_THERM_STATUS: u64 = 0x19C;
+
+    /// Package thermal status
+    pub const IA32_PACKAGE_THERM_STATUS: u64 = 0x1B1;
+
+    /// Temperature target (TjMax) and TCC activation offset
+    pub const MSR_TEMPERATURE_TARGET: u64 = 0x1A2;
+}
+
+/// Thermal Status Register layout (core and package)
+///
+/// ## Register Format
+///
+/// | Bits   | Field          | Description                                     |
+/// |--------|----------------|-------------------------------------------------|
+/// | 0      | thermal_status | TCC is throttling right now                     |
+/// | 1      | thermal_log    | TCC throttled since the log was last cleared    |
+/// | 2      | prochot_status | PROCHOT# or FORCEPR# is asserted right now      |
+/// | 3      | prochot_log    | PROCHOT# or FORCEPR# asserted since last clear  |
+/// | 4-15   | (not decoded)  | Critical temperature, thresholds, power limit   |
+/// | 16-22  | readout        | Degrees Celsius below TjMax                     |
+/// | 23-30  | (not decoded)  | Resolution in degrees Celsius                   |
+/// | 31     | valid          | Readout is valid (core register only)           |
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ThermStatus {
+    /// Thermal status (bit 0)
+    pub thermal_status: bool,
+    /// Thermal status log (bit 1)
+    pub thermal_log: bool,
+    /// PROCHOT# or FORCEPR# event (bit 2)
+    pub prochot_status: bool,
+    /// PROCHOT# or FORCEPR# log (bit 3)
+    pub prochot_log: bool,
+    /// Digital readout (bits 16-22)
+    pub readout: u8,
+    /// Reading valid (bit 31)
+    pub valid: bool,
+}
+
+impl ThermStatus {
+    /// Whether the part is throttling right now, by TCC or by PROCHOT#
+    pub fn throttling(&self) -> bool {
+        self.thermal_status || self.prochot_status
+    }
+
+    /// Current temperature in degrees Celsius given the temperature target
+    pub fn temperature_celsius(&self, target: &TemperatureTarget) -> f64 {
+        target.tj_max as f64 - self.readout as f64
+    }
+}
+
+impl RegisterLayout for ThermStatus {
+    fn to_msr_value(&self) -> u64 {
+        (self.thermal_status as u64)
+            | ((self.thermal_log as u64) << 1)
+            | ((self.prochot_status as u64) << 2)
+            | ((self.prochot_log as u64) << 3)
+            | (((self.readout & 0x7F) as u64) << 16)
+            | ((self.valid as u64) << 31)
+    }
+
+    fn from_msr_value(value: u64) -> Self {
+        Self {
+            thermal_status: (value & 1) != 0,
+            thermal_log: (value & (1 << 1)) != 0,
+            prochot_status: (value & (1 << 2)) != 0,
+            prochot_log: (value & (1 << 3)) != 0,
+            readout: ((value >> 16) & 0x7F) as u8,
+            valid: (value & (1 << 31)) != 0,
+        }
+    }
+
+    fn validate(&self) -> Result<(), &'static str> {
+        if self.readout > 0x7F {
+            return Err("readout must be 0-127 (7 bits)");
+        }
+        Ok(())
+    }
+}
+
+/// Temperature Target Register layout
+///
+/// ## Register Format
+///
+/// | Bits   | Field      | Description                                     |
+/// |--------|------------|-------------------------------------------------|
+/// | 16-23  | tj_max     | Temperature target in degrees Celsius           |
+/// | 24-29  | tcc_offset | TCC activation offset below tj_max, in degrees  |
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TemperatureTarget {
+    /// Temperature target (bits 16-23)
+    pub tj_max: u8,
+    /// TCC activation offset (bits 24-29)
+    pub tcc_offset: u8,
+}
+
+impl TemperatureTarget {
+    /// Temperature in degrees Celsius at which TCC starts throttling
+    pub fn throttle_celsius(&self) -> f64 {
+        self.tj_max as f64 - self.tcc_offset as f64
+    }
+}
+
+impl RegisterLayout for TemperatureTarget {
+    fn to_msr_value(&self) -> u64 {
+        ((self.tj_max as u64) << 16) | (((self.tcc_offset & 0x3F) as u64) << 24)
+    }
+
+    fn from_msr_value(value: u64) -> Self {
+        Self {
+            tj_max: ((value >> 16) & 0xFF) as u8,
+            tcc_offset: ((value >> 24) & 0x3F) as u8,
+        }
+    }
+
+    fn validate(&self) -> Result<(), &'static str> {
+        if self.tcc_offset > 0x3F {
+            return Err("tcc_offset must be 0-63 (6 bits)");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thermal_status_round_trip() {
+        // 38 degrees below TjMax, PROCHOT asserted, TCC throttled earlier
+        let status = ThermStatus::from_msr_value(0x8826_000E);
+        assert_eq!(
+            status,
+            ThermStatus {
+                thermal_status: false,
+                thermal_log: true,
+                prochot_status: true,
+                prochot_log: true,
+                readout: 38,
+                valid: true,
+            }
+        );
+        assert!(status.throttling());
+        // Resolution bits are not decoded
+        assert_eq!(status.to_msr_value(), 0x8026_000E);
+        assert_eq!(ThermStatus::from_msr_value(status.to_msr_value()), status);
+
+        // TjMax 100, throttling from 90
+        let target = TemperatureTarget::from_msr_value(0x0A64_0000);
+        assert_eq!(
+            target,
+            TemperatureTarget {
+                tj_max: 100,
+                tcc_offset: 10
+            }
+        );
+        assert_eq!(target.to_msr_value(), 0x0A64_0000);
+        assert_eq!(target.throttle_celsius(), 90.0);
+        assert_eq!(status.temperature_celsius(&target), 62.0);
+
+        let idle = ThermStatus::from_msr_value(0x8040_0000);
+        assert!(!idle.throttling());
+        assert_eq!(idle.temperature_celsius(&target), 36.0);
+        assert!(idle.validate().is_ok());
+        assert!(ThermStatus {
+            readout: 0x80,
+            ..idle
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -0,0 +1,126 @@
+//! UBox register definitions for Skylake-SP
+//!
+//! The UBox holds the global uncore PMON control, which freezes and unfreezes the
+//! counters of every uncore box (CHA, IIO, IRP, IMC, M2M, UPI) of a socket at once.
+//!
+//! ## References
+//!
+//! - Intel® Xeon® Processor Scalable Family Uncore Performance Monitoring Reference Manual
+
+use crate::register::RegisterLayout;
+
+/// MSR addresses for the UBox
+pub mod msr {
+    /// Global uncore PMON control
+    pub const U_MSR_PMON_GLOBAL_CTL: u64 = 0x0700;
+}
+
+/// PCI configuration of the UBox function holding the stack bus numbers
+pub mod pci {
+    /// UBox PCI device
+    pub const UBOX_DEVICE: u32 = 0x08;
+
+    /// UBox PCI function
+    pub const UBOX_FUNCTION: u32 = 2;
+
+    /// UBox device ID
+    pub const UBOX_DEVICE_ID: u32 = 0x2014;
+
+    /// CPUBUSNO: bus numbers of stacks 0-3, one per byte
+    pub const CPUBUSNO: u32 = 0xCC;
+
+    /// CPUBUSNO1: bus numbers of stacks 4-5 in bytes 0-1
+    pub const CPUBUSNO1: u32 = 0xD0;
+
+    /// CPUBUSNO_VALID: bit 31 is set once the bus numbers are programmed
+    pub const CPUBUSNO_VALID: u32 = 0xD4;
+}
+
+/// Global Uncore PMON Control Register layout
+///
+/// ## Register Format
+///
+/// | Bits   | Field        | Description                                  |
+/// |--------|--------------|----------------------------------------------|
+/// | 0-60   | reserved     |                                              |
+/// | 61     | unfrz_all    | Unfreeze the counters of every uncore box    |
+/// | 62     | wk_on_pmi    | Wake cores on a PMI                          |
+/// | 63     | frz_all      | Freeze the counters of every uncore box      |
+///
+/// Per-box programming succeeds while `frz_all` is set, but no counter advances.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UncoreGlobalControl {
+    /// Unfreeze all uncore counters (bit 61)
+    pub unfrz_all: bool,
+    /// Wake on PMI (bit 62)
+    pub wk_on_pmi: bool,
+    /// Freeze all uncore counters (bit 63)
+    pub frz_all: bool,
+}
+
+impl UncoreGlobalControl {
+    /// Value that lifts a global freeze
+    pub const UNFREEZE: Self = Self {
+        unfrz_all: true,
+        wk_on_pmi: false,
+        frz_all: false,
+    };
+
+    /// Whether every uncore counter of the socket is held frozen
+    pub fn is_frozen(&self) -> bool {
+        self.frz_all && !self.unfrz_all
+    }
+}
+
+impl RegisterLayout for UncoreGlobalControl {
+    fn to_msr_value(&self) -> u64 {
+        (if self.unfrz_all { 1 << 61 } else { 0 })
+            | (if self.wk_on_pmi { 1 << 62 } else { 0 })
+            | (if self.frz_all { 1 << 63 } else { 0 })
+    }
+
+    fn from_msr_value(value: u64) -> Self {
+        Self {
+            unfrz_all: (value & (1 << 61)) != 0,
+            wk_on_pmi: (value & (1 << 62)) != 0,
+            frz_all: (value & (1 << 63)) != 0,
+        }
+    }
+
+    fn validate(&self) -> Result<(), &'static str> {
+        if self.frz_all && self.unfrz_all {
+            return Err("frz_all and unfrz_all are mutually exclusive");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_global_control_decode() {
+        let frozen = UncoreGlobalControl::from_msr_value(0x8000_0000_0000_0000);
+        assert!(frozen.frz_all && !frozen.unfrz_all && !frozen.wk_on_pmi);
+        assert!(frozen.is_frozen());
+
+        // Reserved bits are ignored
+        let running = UncoreGlobalControl::from_msr_value(0x0000_0000_0000_FFFF);
+        assert_eq!(running, UncoreGlobalControl::default());
+        assert!(!running.is_frozen());
+
+        assert_eq!(
+            UncoreGlobalControl::UNFREEZE.to_msr_value(),
+            0x2000_0000_0000_0000
+        );
+        assert!(UncoreGlobalControl::UNFREEZE.validate().is_ok());
+
+        let both = UncoreGlobalControl {
+            frz_all: true,
+            unfrz_all: true,
+            ..Default::default()
+        };
+        assert!(both.validate().is_err());
+    }
+}
@@ -0,0 +1,190 @@
+//! UPI (Ultra Path Interconnect) register definitions for Skylake-SP
+//!
+//! The UPI link layer (LL) connects sockets and provides performance counters
+//! for monitoring inter-socket flit traffic. On Skylake-SP the UPI LL PMON
+//! registers live in PCI configuration space, one device per link, rather
+//! than in MSRs.
+//!
+//! ## References
+//!
+//! - Intel® Xeon® Processor Scalable Family Uncore Performance Monitoring Reference Manual
+//! - Section: UPI Link Layer Performance Monitoring
+
+use crate::register::{counter_mask, RegisterLayout};
+
+/// Number of UPI links per socket in Skylake-SP
+pub const UPI_LINK_COUNT: usize = 3;
+
+/// Number of programmable counters per UPI link
+pub const COUNTERS_PER_LINK: usize = 4;
+
+/// Bit width of UPI counters
+pub const COUNTER_WIDTH_BITS: u64 = super::UNCORE_COUNTER_WIDTH_BITS;
+
+/// Mask applied to raw counter reads
+pub const COUNTER_MASK: u64 = counter_mask(COUNTER_WIDTH_BITS);
+
+/// UPI link layer PCI Device ID for Skylake-SP
+pub const UPI_DEVICE_ID: u32 = 0x2058;
+
+/// Bytes of payload carried per data flit (64-byte cache line over 9 flits)
+pub const BYTES_PER_DATA_FLIT: f64 = 64.0 / 9.0;
+
+/// PCI configuration addresses for UPI link layer units
+pub mod pci {
+    /// PCI device number for each UPI link
+    pub const UPI_LINK_DEVICE: [u32; 3] = [14, 15, 16];
+
+    /// PCI function number for UPI link layer PMON
+    pub const UPI_LINK_FUNCTION: u32 = 0;
+
+    /// UPI Unit (box) Control register offset
+    pub const UPI_UNIT_CTL_ADDR: u32 = 0x378;
+
+    /// UPI Counter register offsets (4 counters, 64-bit)
+    pub const UPI_CTR_ADDR: [u32; 4] = [0x318, 0x320, 0x328, 0x330];
+
+    /// UPI Control register offsets (4 control registers, 64-bit)
+    pub const UPI_CTL_ADDR: [u32; 4] = [0x350, 0x358, 0x360, 0x368];
+}
+
+/// UPI Counter Control Register layout
+///
+/// ## Register Format
+///
+/// | Bits   | Field               | Description                          |
+/// |--------|---------------------|--------------------------------------|
+/// | 0-7    | event_select        | Event code to count                  |
+/// | 8-15   | unit_mask           | Event sub-select (umask)             |
+/// | 16     | reserved            | Must be 0                            |
+/// | 17     | reset_counter       | Reset counter on programming         |
+/// | 18     | edge_detect         | Count rising edges vs level          |
+/// | 19-21  | reserved            | Must be 0                            |
+/// | 22     | enable              | Enable counter                       |
+/// | 23     | invert              | Invert threshold comparison          |
+/// | 24-31  | threshold           | Threshold for filtering (8 bits)     |
+/// | 32-55  | unit_mask_ext       | Extended umask for flit matching     |
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UpiCounterControl {
+    /// Event select code (bits 0-7)
+    pub event_select: u8,
+
+    /// Unit mask / event sub-select (bits 8-15)
+    pub unit_mask: u8,
+
+    /// Reset counter on programming (bit 17)
+    pub reset_counter: bool,
+
+    /// Edge detection mode (bit 18)
+    pub edge_detect: bool,
+
+    /// Enable counter (bit 22)
+    pub enable: bool,
+
+    /// Invert threshold comparison (bit 23)
+    pub invert: bool,
+
+    /// Threshold value (bits 24-31)
+    pub threshold: u8,
+
+    /// Extended unit mask (bits 32-55, 24 bits)
+    pub unit_mask_ext: u32,
+}
+
+impl RegisterLayout for UpiCounterControl {
+    fn to_msr_value(&self) -> u64 {
+        (self.event_select as u64)
+            | ((self.unit_mask as u64) << 8)
+            | (if self.reset_counter { 1 << 17 } else { 0 })
+            | (if self.edge_detect { 1 << 18 } else { 0 })
+            | (if self.enable { 1 << 22 } else { 0 })
+            | (if self.invert { 1 << 23 } else { 0 })
+            | ((self.threshold as u64) << 24)
+            | ((self.unit_mask_ext as u64 & 0xFF_FFFF) << 32)
+    }
+
+    fn from_msr_value(value: u64) -> Self {
+        Self {
+            event_select: (value & 0xFF) as u8,
+            unit_mask: ((value >> 8) & 0xFF) as u8,
+            reset_counter: (value & (1 << 17)) != 0,
+            edge_detect: (value & (1 << 18)) != 0,
+            enable: (value & (1 << 22)) != 0,
+            invert: (value & (1 << 23)) != 0,
+            threshold: ((value >> 24) & 0xFF) as u8,
+            unit_mask_ext: ((value >> 32) & 0xFF_FFFF) as u32,
+        }
+    }
+
+    fn validate(&self) -> Result<(), &'static str> {
+        if self.unit_mask_ext > 0xFF_FFFF {
+            return Err("Extended umask must fit in 24 bits");
+        }
+        Ok(())
+    }
+}
+
+/// UPI event codes
+pub mod events {
+    /// UPI link clockticks
+    pub const CLOCKTICKS: u8 = 0x01;
+
+    /// Flits transmitted (TxL_FLITS)
+    pub const TXL_FLITS: u8 = 0x02;
+
+    /// Flits received (RxL_FLITS)
+    pub const RXL_FLITS: u8 = 0x03;
+}
+
+/// UPI unit masks (event sub-selectors)
+pub mod umasks {
+    /// All data flits (slots 0-2 data + LLCRD/LLCTRL)
+    pub const ALL_DATA: u8 = 0x0F;
+
+    /// All non-data flits
+    pub const NON_DATA: u8 = 0x97;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upi_counter_control_round_trip() {
+        let ctrl = UpiCounterControl {
+            event_select: events::TXL_FLITS,
+            unit_mask: umasks::ALL_DATA,
+            reset_counter: true,
+            enable: true,
+            threshold: 3,
+            unit_mask_ext: 0x12_3456,
+            ..Default::default()
+        };
+
+        let value = ctrl.to_msr_value();
+        let decoded = UpiCounterControl::from_msr_value(value);
+
+        assert_eq!(decoded.event_select, ctrl.event_select);
+        assert_eq!(decoded.unit_mask, ctrl.unit_mask);
+        assert_eq!(decoded.reset_counter, ctrl.reset_counter);
+        assert_eq!(decoded.enable, ctrl.enable);
+        assert_eq!(decoded.threshold, ctrl.threshold);
+        assert_eq!(decoded.unit_mask_ext, ctrl.unit_mask_ext);
+    }
+
+    #[test]
+    fn test_upi_control_bits() {
+        let ctrl = UpiCounterControl {
+            event_select: events::RXL_FLITS,
+            unit_mask: umasks::ALL_DATA,
+            enable: true,
+            ..Default::default()
+        };
+        assert_eq!(ctrl.to_msr_value(), 0x40_0F03);
+
+        let mut ctrl = UpiCounterControl::default();
+        assert!(ctrl.validate().is_ok());
+        ctrl.unit_mask_ext = 0x100_0000;
+        assert!(ctrl.validate().is_err());
+    }
+}
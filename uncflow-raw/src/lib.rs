@@ -10,10 +10,20 @@
 //! ## Features
 //!
 //! Select the target architecture via feature flags:
+//! - `haswell` / `broadwell` - Haswell-EP / Broadwell-EP PCI uncore definitions
 //! - `skylake` (default) - Skylake-SP register definitions
 //! - `cascadelake` - Cascade Lake-SP register definitions
 //! - `icelake` - Ice Lake-SP register definitions
 //!
+//! The `std` feature (default) adds [`read_msr`]/[`write_msr`] over
+//! `/dev/cpu/*/msr`. Everything else is pure encode/decode, so with
+//! `default-features = false` the crate is `no_std` and keeps the register
+//! layouts, addresses and event codes:
+//!
+//! ```toml
+//! uncflow-raw = { version = "*", default-features = false, features = ["skylake"] }
+//! ```
+//!
 //! ## Usage
 //!
 //! ```ignore
@@ -33,13 +43,17 @@
 //! write_msr(0, msr_addr, ctrl.to_msr_value())?;
 //! ```
 
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
 pub mod arch;
+#[cfg(feature = "std")]
 pub mod msr;
 pub mod register;
 
 // Re-export for convenience
+#[cfg(feature = "std")]
 pub use msr::{read_msr, write_msr, MsrError, Result};
-pub use register::{Register, RegisterLayout};
+pub use register::{counter_mask, Register, RegisterLayout};
 
 // Export current architecture based on feature flag
 #[cfg(feature = "skylake")]
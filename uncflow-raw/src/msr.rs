@@ -37,6 +37,19 @@ pub enum MsrError {
     },
 }
 
+/// Decode the 8 bytes the msr driver returns for a read
+///
+/// The driver copies the register into the buffer as a native `u64`, so the
+/// byte order is the host's, not a fixed little-endian layout.
+pub fn decode_msr_value(bytes: [u8; 8]) -> u64 {
+    u64::from_ne_bytes(bytes)
+}
+
+/// Encode a value as the 8 bytes the msr driver expects for a write
+pub fn encode_msr_value(value: u64) -> [u8; 8] {
+    value.to_ne_bytes()
+}
+
 /// Read a 64-bit value from an MSR
 ///
 /// # Arguments
@@ -78,7 +91,7 @@ pub fn read_msr(cpu: u32, msr: u64) -> Result<u64> {
             source: e,
         })?;
 
-    Ok(u64::from_le_bytes(buffer))
+    Ok(decode_msr_value(buffer))
 }
 
 /// Write a 64-bit value to an MSR
@@ -135,7 +148,7 @@ pub fn write_msr(cpu: u32, msr: u64, value: u64) -> Result<()> {
             source: e,
         })?;
 
-    file.write_all(&value.to_le_bytes())
+    file.write_all(&encode_msr_value(value))
         .map_err(|e| MsrError::WriteFailed {
             cpu,
             msr,
@@ -157,4 +170,11 @@ mod tests {
         };
         assert!(err.to_string().contains("Failed to open MSR device"));
     }
+
+    #[test]
+    fn test_msr_value_byte_order() {
+        let value = 0x0123_4567_89AB_CDEF;
+        assert_eq!(encode_msr_value(value), value.to_ne_bytes());
+        assert_eq!(decode_msr_value(encode_msr_value(value)), value);
+    }
 }
@@ -45,6 +45,15 @@ pub trait RegisterLayout: Sized {
     }
 }
 
+/// Mask selecting the low `width_bits` bits of a counter read, i.e. `(1 << width) - 1`
+pub const fn counter_mask(width_bits: u64) -> u64 {
+    if width_bits >= 64 {
+        u64::MAX
+    } else {
+        (1u64 << width_bits) - 1
+    }
+}
+
 /// A hardware register with address and typed layout
 ///
 /// This struct combines an MSR address with a typed register layout,